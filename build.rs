@@ -0,0 +1,42 @@
+use std::env;
+use std::process::Command;
+
+/// Run a command and return its trimmed stdout or unknown if it fails
+fn command_output(program: &str, args: &[&str]) -> String {
+    Command::new(program)
+        .args(args)
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok())
+        .map(|output| output.trim().to_string())
+        .filter(|output| !output.is_empty())
+        .unwrap_or_else(|| "unknown".to_string())
+}
+
+fn main() {
+    let commit = command_output("git", &["rev-parse", "--short", "HEAD"]);
+    let rustc = env::var("RUSTC").unwrap_or_else(|_| "rustc".to_string());
+    let rustc_version = command_output(&rustc, &["--version"]);
+    let build_date = command_output("date", &["-u", "+%Y-%m-%dT%H:%M:%SZ"]);
+    // cargo exposes each enabled feature as a CARGO_FEATURE_<NAME> env var
+    let mut features: Vec<String> = env::vars()
+        .filter_map(|(key, _)| key.strip_prefix("CARGO_FEATURE_").map(str::to_string))
+        .map(|feature| feature.to_lowercase().replace('_', "-"))
+        .collect();
+    features.sort();
+    let features = if features.is_empty() {
+        "none".to_string()
+    } else {
+        features.join(",")
+    };
+
+    println!("cargo:rustc-env=PROBES_GIT_COMMIT={commit}");
+    println!("cargo:rustc-env=PROBES_RUSTC_VERSION={rustc_version}");
+    println!("cargo:rustc-env=PROBES_BUILD_DATE={build_date}");
+    println!("cargo:rustc-env=PROBES_FEATURES={features}");
+    // tokio runtime metrics are gated on the tokio_unstable cfg set by the Makefile
+    println!("cargo:rustc-check-cfg=cfg(tokio_unstable)");
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+}
@@ -0,0 +1,517 @@
+use std::io;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, BytesMut};
+use ripemd::{Digest, Ripemd160};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::{info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const PROTO_VERSION: u8 = 2;
+const INFO_TYPE: u8 = 1;
+const MESSAGE_TYPE: u8 = 3;
+const PROTO_HEADER_LEN: usize = 8;
+const MESSAGE_HEADER_LEN: u8 = 22;
+
+const INFO1_READ: u8 = 1;
+const INFO2_WRITE: u8 = 1;
+
+const FIELD_NAMESPACE: u8 = 0;
+const FIELD_SET: u8 = 1;
+const FIELD_DIGEST: u8 = 4;
+
+const OP_READ: u8 = 1;
+const OP_WRITE: u8 = 2;
+const PARTICLE_STRING: u8 = 3;
+
+// Record written and read back by the canary
+const CANARY_SET: &str = "probes";
+const CANARY_KEY: &str = "probes_canary";
+const CANARY_BIN: &str = "probe";
+
+// Guard against allocating a corrupted response size
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// Namespace of the canary read and write, only set once from main
+static AEROSPIKE_CANARY_NAMESPACE: OnceLock<String> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum AerospikeClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("Namespace {0} not found.")]
+    NamespaceNotFound(String),
+    #[error("Result code {0}.")]
+    ResultCode(String),
+    #[error("Unexpected canary value {0:?}.")]
+    UnexpectedValue(Vec<u8>),
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Set the namespace of the canary read and write
+///
+/// # Arguments
+///
+/// * `namespace` - namespace written to and read from, empty to only issue info requests
+///
+pub fn set_canary_namespace(namespace: &str) -> Result<(), String> {
+    if namespace.is_empty() {
+        return Ok(());
+    }
+    info!("Canary read and write on aerospike namespace {}", namespace);
+    AEROSPIKE_CANARY_NAMESPACE
+        .set(namespace.to_string())
+        .map_err(|_| "Aerospike canary namespace is already initialized".to_string())
+}
+
+/// Return the name of a result code
+fn result_code_name(result_code: u8) -> String {
+    match result_code {
+        0 => "OK".to_string(),
+        1 => "SERVER_ERROR".to_string(),
+        2 => "KEY_NOT_FOUND".to_string(),
+        3 => "GENERATION_ERROR".to_string(),
+        4 => "PARAMETER_ERROR".to_string(),
+        8 => "SERVER_MEM_ERROR".to_string(),
+        9 => "TIMEOUT".to_string(),
+        11 => "PARTITION_UNAVAILABLE".to_string(),
+        14 => "KEY_BUSY".to_string(),
+        20 => "NAMESPACE_NOT_FOUND".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Compute the digest identifying the canary record
+///
+/// The digest is the ripemd160 of the set, the particle type and the key
+fn canary_digest() -> Vec<u8> {
+    let mut hasher = Ripemd160::new();
+    hasher.update(CANARY_SET.as_bytes());
+    hasher.update([PARTICLE_STRING]);
+    hasher.update(CANARY_KEY.as_bytes());
+    hasher.finalize().to_vec()
+}
+
+/// Encode a proto frame
+///
+/// # Arguments
+///
+/// * `proto_type` - info or message
+/// * `body` - payload of the frame
+///
+fn encode_proto(proto_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut frame = BytesMut::with_capacity(PROTO_HEADER_LEN + body.len());
+    frame.put_u64(((PROTO_VERSION as u64) << 56) | ((proto_type as u64) << 48) | body.len() as u64);
+    frame.put_slice(body);
+    frame.to_vec()
+}
+
+/// Encode a message writing or reading the canary bin
+///
+/// # Arguments
+///
+/// * `namespace` - namespace of the canary record
+/// * `value` - value written, None to read the bin
+///
+fn encode_canary(namespace: &str, value: Option<&[u8]>) -> Vec<u8> {
+    let (info1, info2) = match value {
+        Some(_) => (0, INFO2_WRITE),
+        None => (INFO1_READ, 0),
+    };
+    let mut message = BytesMut::new();
+    message.put_u8(MESSAGE_HEADER_LEN);
+    message.put_u8(info1);
+    message.put_u8(info2);
+    // Info3, unused and result code
+    message.put_slice(&[0; 3]);
+    // Generation and record ttl
+    message.put_slice(&[0; 8]);
+    message.put_u32(TIMEOUT.as_millis() as u32);
+    // Namespace, set and digest fields
+    message.put_u16(3);
+    message.put_u16(1);
+
+    let digest = canary_digest();
+    for (field_type, data) in [
+        (FIELD_NAMESPACE, namespace.as_bytes()),
+        (FIELD_SET, CANARY_SET.as_bytes()),
+        (FIELD_DIGEST, digest.as_slice()),
+    ] {
+        message.put_u32(data.len() as u32 + 1);
+        message.put_u8(field_type);
+        message.put_slice(data);
+    }
+
+    let (op, particle_type, value) = match value {
+        Some(value) => (OP_WRITE, PARTICLE_STRING, value),
+        None => (OP_READ, 0, &[][..]),
+    };
+    message.put_u32((4 + CANARY_BIN.len() + value.len()) as u32);
+    message.put_u8(op);
+    message.put_u8(particle_type);
+    // Version
+    message.put_u8(0);
+    message.put_u8(CANARY_BIN.len() as u8);
+    message.put_slice(CANARY_BIN.as_bytes());
+    message.put_slice(value);
+    encode_proto(MESSAGE_TYPE, &message)
+}
+
+/// Parse the result code and the value of the first bin of a message
+///
+/// # Arguments
+///
+/// * `message` - body of a message frame
+///
+fn parse_message(mut message: &[u8]) -> Result<(u8, Option<Vec<u8>>), AerospikeClientError> {
+    if message.len() < MESSAGE_HEADER_LEN as usize {
+        return Err(invalid("truncated message header"));
+    }
+    let header_len = message[0] as usize;
+    let result_code = message[5];
+    let field_count = u16::from_be_bytes([message[18], message[19]]);
+    let op_count = u16::from_be_bytes([message[20], message[21]]);
+    if message.len() < header_len {
+        return Err(invalid("truncated message header"));
+    }
+    message.advance(header_len);
+
+    for _ in 0..field_count {
+        if message.len() < 4 {
+            return Err(invalid("truncated field"));
+        }
+        let len = message.get_u32() as usize;
+        if message.len() < len {
+            return Err(invalid("truncated field"));
+        }
+        message.advance(len);
+    }
+    if op_count == 0 {
+        return Ok((result_code, None));
+    }
+    if message.len() < 8 {
+        return Err(invalid("truncated op"));
+    }
+    let len = message.get_u32() as usize;
+    if len < 4 || message.len() < len {
+        return Err(invalid("truncated op"));
+    }
+    // Op, particle type and version
+    message.advance(3);
+    let name_len = message.get_u8() as usize;
+    if len < 4 + name_len {
+        return Err(invalid("truncated op"));
+    }
+    Ok((result_code, Some(message[name_len..len - 4].to_vec())))
+}
+
+fn invalid(reason: &str) -> AerospikeClientError {
+    AerospikeClientError::InvalidResponse(reason.to_string())
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, AerospikeClientError> {
+    let socket = TcpStream::connect(addr).await?;
+    Ok(Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stream: BufWriter::new(socket),
+        canary_namespace: AEROSPIKE_CANARY_NAMESPACE
+            .get()
+            .cloned()
+            .unwrap_or_default(),
+        canary_id: 0,
+    })
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    stream: BufWriter<TcpStream>,
+    // Empty to only issue info requests
+    canary_namespace: String,
+    canary_id: u64,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one info request checking the canary namespace is served
+    /// * write and read back the canary record if a namespace is set
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), AerospikeClientError> {
+        self.info().await?;
+        if self.canary_namespace.is_empty() {
+            return Ok(());
+        }
+        self.canary_id += 1;
+        let value = self.canary_id.to_string().into_bytes();
+        let write = encode_canary(&self.canary_namespace, Some(&value));
+        self.handler_with_timeout("write", write).await?;
+        let read = encode_canary(&self.canary_namespace, None);
+        match self.handler_with_timeout("read", read).await? {
+            Some(read_value) if read_value == value => Ok(()),
+            read_value => Err(AerospikeClientError::UnexpectedValue(
+                read_value.unwrap_or_default(),
+            )),
+        }
+    }
+
+    /// Info call
+    pub async fn info(&mut self) -> Result<(), AerospikeClientError> {
+        let start = Instant::now();
+        let request = encode_proto(INFO_TYPE, b"node\nnamespaces\n");
+        let response_res = tokio::time::timeout(TIMEOUT, self.exchange(&request, INFO_TYPE)).await;
+        let result = match response_res {
+            Err(_timeout_elapsed) => {
+                self.request_metrics.observe_response_time("info", TIMEOUT);
+                return Err(AerospikeClientError::from(_timeout_elapsed));
+            }
+            Ok(Err(issue)) => return Err(issue),
+            Ok(Ok(response)) => self.check_namespaces(&response),
+        };
+        let status = match &result {
+            Ok(_) => "OK",
+            Err(_) => "NAMESPACE_NOT_FOUND",
+        };
+        self.request_metrics.count_request(status, "info");
+        self.request_metrics
+            .observe_response_time("info", start.elapsed());
+        result
+    }
+
+    /// Check the canary namespace is listed in an info response
+    fn check_namespaces(&self, response: &[u8]) -> Result<(), AerospikeClientError> {
+        if self.canary_namespace.is_empty() {
+            return Ok(());
+        }
+        let response = String::from_utf8_lossy(response);
+        let served = response
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .filter(|(name, _)| *name == "namespaces")
+            .flat_map(|(_, value)| value.split(';'))
+            .any(|namespace| namespace == self.canary_namespace);
+        if !served {
+            return Err(AerospikeClientError::NamespaceNotFound(
+                self.canary_namespace.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        request: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, AerospikeClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type, request)).await {
+            Ok(value_res) => value_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(AerospikeClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform aerospike message request
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    /// * `request` - the message frame to send
+    ///
+    /// # Return
+    ///
+    /// * Value of the bin returned by the node
+    ///
+    #[instrument(skip(self, request))]
+    pub async fn handle_request(
+        &mut self,
+        cmd_type: &str,
+        request: Vec<u8>,
+    ) -> Result<Option<Vec<u8>>, AerospikeClientError> {
+        let start = Instant::now();
+        let response = self.exchange(&request, MESSAGE_TYPE).await?;
+        let (result_code, value) = parse_message(&response)?;
+        let elapsed = start.elapsed();
+        let status = result_code_name(result_code);
+        self.request_metrics.count_request(&status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+
+        if result_code != 0 {
+            return Err(AerospikeClientError::ResultCode(status));
+        }
+        Ok(value)
+    }
+
+    /// Send a frame and read the body of the response frame
+    ///
+    /// # Arguments
+    ///
+    /// * `request` - the frame to send
+    /// * `proto_type` - type expected for the response frame
+    ///
+    async fn exchange(
+        &mut self,
+        request: &[u8],
+        proto_type: u8,
+    ) -> Result<Vec<u8>, AerospikeClientError> {
+        self.stream.write_all(request).await?;
+        self.stream.flush().await?;
+
+        let header = self.stream.read_u64().await?;
+        let version = (header >> 56) as u8;
+        let response_type = (header >> 48) as u8;
+        let size = (header & 0xffff_ffff_ffff) as usize;
+        if version != PROTO_VERSION || response_type != proto_type {
+            return Err(AerospikeClientError::InvalidResponse(format!(
+                "unexpected version {version} or type {response_type}"
+            )));
+        }
+        if size > MAX_RESPONSE_SIZE {
+            return Err(AerospikeClientError::InvalidResponse(format!(
+                "response of {size} bytes"
+            )));
+        }
+        let mut body = vec![0; size];
+        self.stream.read_exact(&mut body).await?;
+        Ok(body)
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::aerospike::{
+        canary_digest, connect, encode_proto, parse_message, AerospikeClientError, INFO_TYPE,
+        MESSAGE_TYPE,
+    };
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+
+    // Message with one op returning the value of the canary bin
+    fn message(result_code: u8, value: &[u8]) -> Vec<u8> {
+        let mut message = vec![22, 0, 0, 0, 0, result_code];
+        message.extend_from_slice(&[0; 12]);
+        message.extend_from_slice(&[0, 0, 0, 1]);
+        message.extend_from_slice(&(4 + 5 + value.len() as u32).to_be_bytes());
+        message.extend_from_slice(&[1, 3, 0, 5]);
+        message.extend_from_slice(b"probe");
+        message.extend_from_slice(value);
+        message
+    }
+
+    async fn read_frame(socket: &mut TcpStream) -> Vec<u8> {
+        let size = socket.read_u64().await.unwrap() & 0xffff_ffff_ffff;
+        let mut body = vec![0; size as usize];
+        socket.read_exact(&mut body).await.unwrap();
+        body
+    }
+
+    #[test]
+    fn digest() {
+        assert_eq!(
+            "7b9608a4c2aac25eee3380b4afbbaeec49b15173",
+            hex::encode(canary_digest())
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            (0, Some(b"42".to_vec())),
+            parse_message(&message(0, b"42")).unwrap()
+        );
+        assert_eq!(2, parse_message(&message(2, b"")).unwrap().0);
+        assert!(matches!(
+            parse_message(&message(0, b"42")[..24]),
+            Err(AerospikeClientError::InvalidResponse(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            assert_eq!(
+                b"node\nnamespaces\n".to_vec(),
+                read_frame(&mut socket).await
+            );
+            let info = encode_proto(INFO_TYPE, b"node\tBB9\nnamespaces\ttest;canary\n");
+            socket.write_all(&info).await.unwrap();
+            read_frame(&mut socket).await;
+            let mut write_header = vec![0; 22];
+            write_header[0] = 22;
+            let write = encode_proto(MESSAGE_TYPE, &write_header);
+            socket.write_all(&write).await.unwrap();
+            read_frame(&mut socket).await;
+            let read = encode_proto(MESSAGE_TYPE, &message(0, b"1"));
+            socket.write_all(&read).await.unwrap();
+        });
+
+        let mut client = connect("aerospike_probe", &addr).await.unwrap();
+        client.canary_namespace = "canary".to_string();
+        client.probe().await.unwrap();
+        for cmd_type in ["info", "write", "read"] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&["aerospike_probe", &addr, "OK", cmd_type])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_namespace_not_found() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            read_frame(&mut socket).await;
+            let info = encode_proto(INFO_TYPE, b"node\tBB9\nnamespaces\ttest\n");
+            socket.write_all(&info).await.unwrap();
+        });
+
+        let mut client = connect("aerospike_probe_namespace", &addr).await.unwrap();
+        client.canary_namespace = "canary".to_string();
+        assert!(matches!(
+            client.probe().await,
+            Err(AerospikeClientError::NamespaceNotFound(_))
+        ));
+    }
+}
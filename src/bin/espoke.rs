@@ -0,0 +1,10 @@
+use probes::cli;
+use probes::probes::protocol::Protocol;
+
+fn main() -> Result<(), i32> {
+    cli::run(
+        "espoke",
+        "ElasticSearch Probe (ESPoke)",
+        Protocol::Elasticsearch,
+    )
+}
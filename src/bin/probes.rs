@@ -0,0 +1,10 @@
+use probes::cli;
+use probes::probes::protocol::Protocol;
+
+fn main() -> Result<(), i32> {
+    cli::run(
+        "probes",
+        "Multi-protocol Probe, protocol selected with --protocol or probe-protocol tags",
+        Protocol::Memcached,
+    )
+}
@@ -0,0 +1,429 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, BytesMut};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::instrument;
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+// Native protocol v4, the response version has the direction bit set
+const REQUEST_VERSION: u8 = 0x04;
+const RESPONSE_VERSION: u8 = 0x84;
+const HEADER_LEN: usize = 9;
+
+const OPCODE_ERROR: u8 = 0x00;
+const OPCODE_STARTUP: u8 = 0x01;
+const OPCODE_READY: u8 = 0x02;
+const OPCODE_AUTHENTICATE: u8 = 0x03;
+const OPCODE_OPTIONS: u8 = 0x05;
+const OPCODE_SUPPORTED: u8 = 0x06;
+const OPCODE_QUERY: u8 = 0x07;
+const OPCODE_RESULT: u8 = 0x08;
+
+const CQL_VERSION: &str = "3.0.0";
+const HEALTH_QUERY: &str = "SELECT now() FROM system.local";
+const CONSISTENCY_ONE: u16 = 0x0001;
+
+// Max size of a response body, the health query returns one row
+const MAX_BODY_SIZE: usize = 1024 * 1024;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Error, Debug)]
+pub enum CassandraClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid frame: {0}")]
+    InvalidFrame(String),
+    #[error("Authentication is not supported, {0} requested.")]
+    AuthenticationRequired(String),
+    #[error("Error {code:#06x} on {cmd_type}: {message}")]
+    ErrorResponse {
+        cmd_type: String,
+        code: i32,
+        message: String,
+    },
+    #[error("Unexpected opcode {opcode:#04x} on {cmd_type}.")]
+    UnexpectedOpcode { cmd_type: String, opcode: u8 },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Encode a request frame
+///
+/// # Arguments
+///
+/// * `stream_id` - id of the stream returned in the response
+/// * `opcode` - operation of the request
+/// * `body` - encoded body of the request
+///
+fn encode_frame(stream_id: i16, opcode: u8, body: &[u8]) -> Vec<u8> {
+    let mut frame = BytesMut::with_capacity(HEADER_LEN + body.len());
+    frame.put_u8(REQUEST_VERSION);
+    // No compression nor tracing
+    frame.put_u8(0);
+    frame.put_i16(stream_id);
+    frame.put_u8(opcode);
+    frame.put_i32(body.len() as i32);
+    frame.put_slice(body);
+    frame.to_vec()
+}
+
+/// Encode a [string] (u16 length prefix)
+fn put_string(body: &mut BytesMut, value: &str) {
+    body.put_u16(value.len() as u16);
+    body.put_slice(value.as_bytes());
+}
+
+/// Encode the body of a STARTUP request
+fn startup_body() -> Vec<u8> {
+    let mut body = BytesMut::new();
+    body.put_u16(1);
+    put_string(&mut body, "CQL_VERSION");
+    put_string(&mut body, CQL_VERSION);
+    body.to_vec()
+}
+
+/// Encode the body of a QUERY request without values
+fn query_body(query: &str) -> Vec<u8> {
+    let mut body = BytesMut::with_capacity(query.len() + 7);
+    body.put_i32(query.len() as i32);
+    body.put_slice(query.as_bytes());
+    body.put_u16(CONSISTENCY_ONE);
+    // No flag
+    body.put_u8(0);
+    body.to_vec()
+}
+
+/// Parse the body of an ERROR response
+///
+/// # Return
+///
+/// * Error code and message
+///
+fn parse_error(mut body: &[u8]) -> Result<(i32, String), CassandraClientError> {
+    if body.len() < 6 {
+        return Err(CassandraClientError::InvalidFrame(
+            "truncated error".to_string(),
+        ));
+    }
+    let code = body.get_i32();
+    let len = body.get_u16() as usize;
+    let message = String::from_utf8_lossy(&body[..len.min(body.len())]).to_string();
+    Ok((code, message))
+}
+
+/// Return the status exported in the number of requests metric
+fn response_status(opcode: u8, body: &[u8]) -> String {
+    match opcode {
+        OPCODE_ERROR => parse_error(body)
+            .map(|(code, _)| format!("{code:#06x}"))
+            .unwrap_or_else(|_| "ERROR".to_string()),
+        _ => "OK".to_string(),
+    }
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, CassandraClientError> {
+    let stream = TcpStream::connect(addr).await?;
+    let mut client = Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stream,
+        stream_id: 0,
+    };
+    client.startup().await?;
+    Ok(client)
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    stream: TcpStream,
+    // Id of the last request, one request is in flight at a time
+    stream_id: i16,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one options
+    /// * issue one select of the local node time
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), CassandraClientError> {
+        self.options().await?;
+        self.query().await
+    }
+
+    /// Startup call, only done once per connection
+    async fn startup(&mut self) -> Result<(), CassandraClientError> {
+        match self
+            .handler_with_timeout("startup", OPCODE_STARTUP, startup_body())
+            .await?
+        {
+            (OPCODE_READY, _) => Ok(()),
+            (OPCODE_AUTHENTICATE, body) => {
+                let mut body = &body[..];
+                let authenticator = if body.len() >= 2 {
+                    let len = body.get_u16() as usize;
+                    String::from_utf8_lossy(&body[..len.min(body.len())]).to_string()
+                } else {
+                    "unknown".to_string()
+                };
+                Err(CassandraClientError::AuthenticationRequired(authenticator))
+            }
+            (opcode, _) => Err(CassandraClientError::UnexpectedOpcode {
+                cmd_type: "startup".to_string(),
+                opcode,
+            }),
+        }
+    }
+
+    /// Options call
+    pub async fn options(&mut self) -> Result<(), CassandraClientError> {
+        match self
+            .handler_with_timeout("options", OPCODE_OPTIONS, Vec::new())
+            .await?
+        {
+            (OPCODE_SUPPORTED, _) => Ok(()),
+            (opcode, _) => Err(CassandraClientError::UnexpectedOpcode {
+                cmd_type: "options".to_string(),
+                opcode,
+            }),
+        }
+    }
+
+    /// Query call of the local node time
+    pub async fn query(&mut self) -> Result<(), CassandraClientError> {
+        match self
+            .handler_with_timeout("query", OPCODE_QUERY, query_body(HEALTH_QUERY))
+            .await?
+        {
+            (OPCODE_RESULT, _) => Ok(()),
+            (opcode, _) => Err(CassandraClientError::UnexpectedOpcode {
+                cmd_type: "query".to_string(),
+                opcode,
+            }),
+        }
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        opcode: u8,
+        body: Vec<u8>,
+    ) -> Result<(u8, Vec<u8>), CassandraClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type, opcode, body)).await {
+            Ok(response_res) => response_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(CassandraClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform cassandra request
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    /// * `opcode` - operation of the request
+    /// * `body` - encoded body of the request
+    ///
+    /// # Return
+    ///
+    /// * Opcode and body of the response, errors are returned as Err
+    ///
+    #[instrument(skip(self, body))]
+    pub async fn handle_request(
+        &mut self,
+        cmd_type: &str,
+        opcode: u8,
+        body: Vec<u8>,
+    ) -> Result<(u8, Vec<u8>), CassandraClientError> {
+        let start = Instant::now();
+        self.stream_id = self.stream_id.wrapping_add(1) & i16::MAX;
+        self.stream
+            .write_all(&encode_frame(self.stream_id, opcode, &body))
+            .await?;
+
+        let (opcode, body) = self.read_frame().await?;
+        let elapsed = start.elapsed();
+        self.request_metrics
+            .count_request(&response_status(opcode, &body), cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+
+        if opcode == OPCODE_ERROR {
+            let (code, message) = parse_error(&body)?;
+            return Err(CassandraClientError::ErrorResponse {
+                cmd_type: cmd_type.to_string(),
+                code,
+                message,
+            });
+        }
+        Ok((opcode, body))
+    }
+
+    /// Read a response frame of the last request
+    async fn read_frame(&mut self) -> Result<(u8, Vec<u8>), CassandraClientError> {
+        let mut header = [0; HEADER_LEN];
+        self.stream.read_exact(&mut header).await?;
+        let mut header = &header[..];
+        let version = header.get_u8();
+        // Flags
+        header.advance(1);
+        let stream_id = header.get_i16();
+        let opcode = header.get_u8();
+        let len = header.get_i32();
+        if version != RESPONSE_VERSION {
+            return Err(CassandraClientError::InvalidFrame(format!(
+                "unsupported version {version:#04x}"
+            )));
+        }
+        if len < 0 || len as usize > MAX_BODY_SIZE {
+            return Err(CassandraClientError::InvalidFrame(format!(
+                "invalid size {len}"
+            )));
+        }
+        let mut body = vec![0; len as usize];
+        self.stream.read_exact(&mut body).await?;
+        if stream_id != self.stream_id {
+            return Err(CassandraClientError::InvalidFrame(format!(
+                "unexpected stream {stream_id}, expected {}",
+                self.stream_id
+            )));
+        }
+        Ok((opcode, body))
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, BytesMut};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::cassandra::{
+        connect, encode_frame, parse_error, query_body, response_status, CassandraClientError,
+    };
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+
+    fn error_body() -> Vec<u8> {
+        let mut body = BytesMut::new();
+        body.put_i32(0x1000);
+        body.put_u16(11);
+        body.put_slice(b"Unavailable");
+        body.to_vec()
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(vec![4, 0, 0, 1, 5, 0, 0, 0, 0], encode_frame(1, 0x05, &[]));
+        let body = query_body("SELECT 1");
+        assert_eq!(&[0, 0, 0, 8], &body[..4]);
+        assert_eq!(&[0, 1, 0], &body[12..]);
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            (0x1000, "Unavailable".to_string()),
+            parse_error(&error_body()).unwrap()
+        );
+        assert!(parse_error(&[0, 0]).is_err());
+        assert_eq!("0x1000", response_status(0x00, &error_body()));
+        assert_eq!("OK", response_status(0x08, &[]));
+    }
+
+    async fn run_server(listener: TcpListener, responses: Vec<(u8, Vec<u8>)>) {
+        let (mut socket, _) = listener.accept().await.unwrap();
+        for (opcode, body) in responses {
+            let mut header = [0; 9];
+            socket.read_exact(&mut header).await.unwrap();
+            let len = i32::from_be_bytes([header[5], header[6], header[7], header[8]]);
+            let mut request = vec![0; len as usize];
+            socket.read_exact(&mut request).await.unwrap();
+
+            let mut frame = BytesMut::new();
+            frame.put_u8(0x84);
+            frame.put_u8(0);
+            // Stream id of the request
+            frame.put_slice(&header[2..4]);
+            frame.put_u8(opcode);
+            frame.put_i32(body.len() as i32);
+            frame.put_slice(&body);
+            socket.write_all(&frame).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(run_server(
+            listener,
+            vec![
+                (0x02, Vec::new()),
+                (0x06, vec![0, 0]),
+                // Void result
+                (0x08, vec![0, 0, 0, 1]),
+            ],
+        ));
+
+        let mut client = connect("cassandra_probe", &addr).await.unwrap();
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["cassandra_probe", &addr, "OK", "query"])
+                .unwrap()
+                .get()
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(run_server(
+            listener,
+            vec![(0x02, Vec::new()), (0x06, vec![0, 0]), (0x00, error_body())],
+        ));
+
+        let mut client = connect("cassandra_probe_error", &addr).await.unwrap();
+        assert!(matches!(
+            client.probe().await,
+            Err(CassandraClientError::ErrorResponse { code: 0x1000, .. })
+        ));
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["cassandra_probe_error", &addr, "0x1000", "query"])
+                .unwrap()
+                .get()
+        );
+    }
+}
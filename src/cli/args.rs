@@ -0,0 +1,1526 @@
+use std::io::Write;
+use std::net::SocketAddr;
+
+use clap::{ArgAction, Command, CommandFactory, Parser, Subcommand};
+use clap_complete::Shell;
+
+use crate::probes::dedup::DedupPolicy;
+use crate::probes::discovery::{DiscoveryKind, DiscoveryKinds, MergePolicy};
+use crate::probes::prometheus::{parse_buckets, parse_static_labels};
+use crate::probes::protocol::Protocol;
+use crate::probes::shard::Shard;
+use crate::probes::statsd::StatsdFlavor;
+use crate::probes::telemetry::LogFormat;
+use crate::token_bucket::RateLimiterKind;
+
+/// Version details printed by --version for audits and bug reports
+const LONG_VERSION: &str = concat!(
+    env!("CARGO_PKG_VERSION"),
+    "\ncommit: ",
+    env!("PROBES_GIT_COMMIT"),
+    "\nbuild date: ",
+    env!("PROBES_BUILD_DATE"),
+    "\nrustc: ",
+    env!("PROBES_RUSTC_VERSION"),
+    "\nfeatures: ",
+    env!("PROBES_FEATURES"),
+);
+
+// Longest ttl read by memcached as a number of seconds, 30 days
+const MAX_RELATIVE_TTL_SECONDS: u32 = 2_592_000;
+
+/// Command line arguments shared by the probes binaries
+///
+/// Each `--flag` falls back to its `PROBES_*` env var, then to its default
+#[derive(Parser, Debug)]
+pub struct Args {
+    #[command(subcommand)]
+    pub subcommand: Option<CliCommand>,
+
+    /// Comma separated sources of the nodes to probe, in priority order: consul, srv, file, http,
+    /// ec2, gce, docker, nomad or mdns
+    #[arg(long, env = "PROBES_DISCOVERY", default_value = "consul")]
+    pub discovery: DiscoveryKinds,
+
+    /// Merge of the nodes of multiple discovery sources: union probes the nodes of all sources,
+    /// priority probes a service from the first source listing it only
+    #[arg(long, env = "PROBES_DISCOVERY_MERGE", default_value = "union")]
+    pub discovery_merge: MergePolicy,
+
+    /// Consul hostname
+    #[arg(
+        long,
+        env = "PROBES_CONSUL_FQDN",
+        default_value = "http://localhost:8500"
+    )]
+    pub consul_fqdn: String,
+
+    /// Probe the Connect sidecar proxies of the services instead of the services, for services only
+    /// reachable through the mesh
+    #[arg(long, env = "PROBES_CONSUL_CONNECT")]
+    pub consul_connect: bool,
+
+    /// Service identity of the Connect leaf certificate presented to the sidecar proxies, sidecars
+    /// are then probed with the tls protocol, no mTLS if empty (default: none)
+    #[arg(
+        long,
+        env = "PROBES_CONSUL_CONNECT_MTLS_SERVICE",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub consul_connect_mtls_service: String,
+
+    /// Acl token of the consul discovery (default: CONSUL_HTTP_TOKEN env)
+    #[arg(
+        long,
+        env = "PROBES_CONSUL_TOKEN",
+        hide_env_values = true,
+        hide_default_value = true
+    )]
+    pub consul_token: String,
+
+    /// File holding the acl token of the consul discovery, re-read on each query to follow
+    /// rotations, takes precedence over --consul-token (default: CONSUL_HTTP_TOKEN_FILE env)
+    #[arg(long, env = "PROBES_CONSUL_TOKEN_FILE", hide_default_value = true)]
+    pub consul_token_file: String,
+
+    /// Comma separated tags to select services to probe, a service having one of them is probed,
+    /// required by the consul and nomad discoveries, can be changed at runtime through
+    /// /admin/services-tag
+    #[arg(
+        long,
+        env = "PROBES_SERVICES_TAG",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub services_tag: String,
+
+    /// Comma separated list of SRV names resolved by the srv discovery, each name is the cluster
+    /// name of its targets (default: none)
+    #[arg(
+        long,
+        env = "PROBES_SRV_NAMES",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub srv_names: String,
+
+    /// Resolver ip[:port] of the srv discovery (default: first nameserver of /etc/resolv.conf)
+    #[arg(
+        long,
+        env = "PROBES_SRV_RESOLVER",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub srv_resolver: String,
+
+    /// Interval between each resolution of the srv discovery
+    #[arg(long, env = "PROBES_SRV_REFRESH_INTERVAL_MS", default_value_t = 30000)]
+    pub srv_refresh_interval_ms: u64,
+
+    /// Yaml or json file listing the targets of each service, or target groups in the Prometheus
+    /// file_sd format, for the file discovery (default: none)
+    #[arg(
+        long,
+        env = "PROBES_TARGETS_FILE",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub targets_file: String,
+
+    /// Interval between each check for changes of the targets file
+    #[arg(
+        long,
+        env = "PROBES_TARGETS_FILE_REFRESH_INTERVAL_MS",
+        default_value_t = 5000
+    )]
+    pub targets_file_refresh_interval_ms: u64,
+
+    /// Url returning target groups in the Prometheus http_sd format for the http discovery,
+    /// cluster_name (or job) and probe_protocol labels are used (default: none)
+    #[arg(
+        long,
+        env = "PROBES_HTTP_SD_URL",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub http_sd_url: String,
+
+    /// Interval between each fetch of the http_sd target groups
+    #[arg(
+        long,
+        env = "PROBES_HTTP_SD_REFRESH_INTERVAL_MS",
+        default_value_t = 60000
+    )]
+    pub http_sd_refresh_interval_ms: u64,
+
+    /// Key or key=value of the tag (ec2) or label (gce) selecting the running instances of the ec2
+    /// and gce discoveries, its value is the cluster name (default: none)
+    #[arg(
+        long,
+        env = "PROBES_CLOUD_TAG",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub cloud_tag: String,
+
+    /// Port probed on the private ip of the cloud instances
+    #[arg(long, env = "PROBES_CLOUD_PORT", default_value_t = 0)]
+    pub cloud_port: u16,
+
+    /// Region of the ec2 instances (default: AWS_REGION env)
+    #[arg(long, env = "PROBES_CLOUD_REGION")]
+    pub cloud_region: String,
+
+    /// Project of the gce instances (default: none)
+    #[arg(
+        long,
+        env = "PROBES_CLOUD_PROJECT",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub cloud_project: String,
+
+    /// Base url of the ec2 or gce api (default: the public endpoint of the provider)
+    #[arg(
+        long,
+        env = "PROBES_CLOUD_ENDPOINT",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub cloud_endpoint: String,
+
+    /// Interval between each listing of the cloud instances
+    #[arg(
+        long,
+        env = "PROBES_CLOUD_REFRESH_INTERVAL_MS",
+        default_value_t = 60000
+    )]
+    pub cloud_refresh_interval_ms: u64,
+
+    /// Unix socket of the docker engine listing the containers labeled probe.enable=true for the
+    /// docker discovery
+    #[arg(
+        long,
+        env = "PROBES_DOCKER_SOCKET",
+        default_value = "/var/run/docker.sock"
+    )]
+    pub docker_socket: String,
+
+    /// Max interval between each listing of the containers, also listed on container start or stop
+    #[arg(
+        long,
+        env = "PROBES_DOCKER_REFRESH_INTERVAL_MS",
+        default_value_t = 30000
+    )]
+    pub docker_refresh_interval_ms: u64,
+
+    /// Address of the nomad agent of the nomad discovery (default: NOMAD_ADDR env or
+    /// http://localhost:4646)
+    #[arg(long, env = "PROBES_NOMAD_ADDR")]
+    pub nomad_addr: String,
+
+    /// Acl token of the nomad discovery (default: NOMAD_TOKEN env)
+    #[arg(
+        long,
+        env = "PROBES_NOMAD_TOKEN",
+        hide_env_values = true,
+        hide_default_value = true
+    )]
+    pub nomad_token: String,
+
+    /// Namespace of the services of the nomad discovery, * for all
+    #[arg(long, env = "PROBES_NOMAD_NAMESPACE", default_value = "default")]
+    pub nomad_namespace: String,
+
+    /// Comma separated list of service types browsed on the local segment by the mdns discovery,
+    /// like _memcache._tcp (default: none)
+    #[arg(
+        long,
+        env = "PROBES_MDNS_SERVICES",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub mdns_services: String,
+
+    /// Time waiting for the mDNS responders after each query
+    #[arg(long, env = "PROBES_MDNS_BROWSE_MS", default_value_t = 1000)]
+    pub mdns_browse_ms: u64,
+
+    /// Interval between each browse of the mdns discovery
+    #[arg(long, env = "PROBES_MDNS_REFRESH_INTERVAL_MS", default_value_t = 30000)]
+    pub mdns_refresh_interval_ms: u64,
+
+    /// Protocol used to probe the discovered nodes without probe-protocol tag
+    #[arg(long, env = "PROBES_PROTOCOL")]
+    pub protocol: Protocol,
+
+    /// File the pid of the prober is written to, removed on graceful shutdown (default: none)
+    #[arg(
+        long,
+        env = "PROBES_PID_FILE",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub pid_file: String,
+
+    /// Detach from the terminal to run in background under classic init, stdout and stderr
+    /// are kept for the init script to redirect the logs
+    #[arg(long, env = "PROBES_DAEMONIZE")]
+    pub daemonize: bool,
+
+    /// Enable console subscriber for the tokio console
+    #[arg(long, env = "PROBES_TOKIO_CONSOLE", default_value_t = false, action = ArgAction::Set)]
+    pub tokio_console: bool,
+
+    /// Worker threads of the tokio runtime, 0 for one per core
+    #[arg(long, env = "PROBES_WORKER_THREADS", default_value_t = 0)]
+    pub worker_threads: usize,
+
+    /// Max threads of the tokio blocking pool, 0 for the tokio default
+    #[arg(long, env = "PROBES_MAX_BLOCKING_THREADS", default_value_t = 0)]
+    pub max_blocking_threads: usize,
+
+    /// Output format of the logs: text or json
+    #[arg(long, env = "PROBES_LOG_FORMAT", default_value = "text")]
+    pub log_format: LogFormat,
+
+    /// Level or directives like info,probes=debug filtering the logs (default: RUST_LOG env)
+    #[arg(
+        long,
+        env = "PROBES_LOG_LEVEL",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub log_level: String,
+
+    /// Http port for metrics endpoint
+    #[arg(long, env = "PROBES_HTTP_PORT", default_value_t = 8080)]
+    pub http_port: u16,
+
+    /// Listening address of the healthz and ready endpoints, e.g. 127.0.0.1:8081, always over
+    /// plaintext http and without auth (default: served on --http-port)
+    #[arg(
+        long,
+        env = "PROBES_HEALTH_HTTP_ADDR",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub health_http_addr: String,
+
+    /// PEM certificate chain to serve the metrics endpoint over https (default: none)
+    #[arg(
+        long,
+        env = "PROBES_TLS_CERT_PATH",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub tls_cert_path: String,
+
+    /// PEM private key to serve the metrics endpoint over https (default: none)
+    #[arg(
+        long,
+        env = "PROBES_TLS_KEY_PATH",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub tls_key_path: String,
+
+    /// Username for basic auth on the metrics endpoint (default: none)
+    #[arg(
+        long,
+        env = "PROBES_HTTP_AUTH_USERNAME",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub http_auth_username: String,
+
+    /// Password for basic auth on the metrics endpoint (default: none)
+    #[arg(
+        long,
+        env = "PROBES_HTTP_AUTH_PASSWORD",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub http_auth_password: String,
+
+    /// Bearer token required on the metrics endpoint (default: none)
+    #[arg(
+        long,
+        env = "PROBES_HTTP_AUTH_BEARER_TOKEN",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub http_auth_bearer_token: String,
+
+    /// Max time since the last successful consul discovery before healthz reports unhealthy
+    #[arg(
+        long,
+        env = "PROBES_HEALTHZ_MAX_DISCOVERY_AGE_SECS",
+        default_value_t = 900
+    )]
+    pub healthz_max_discovery_age_secs: u64,
+
+    /// Log method, path, status and duration of each http request at info level
+    #[arg(long, env = "PROBES_HTTP_ACCESS_LOG", default_value_t = false, action = ArgAction::Set)]
+    pub http_access_log: bool,
+
+    /// Share the http port with the next prober through SO_REUSEPORT so that scrapes are not
+    /// refused while upgrading, combined with --drain-seconds on the previous prober
+    #[arg(long, env = "PROBES_HTTP_REUSE_PORT", default_value_t = false, action = ArgAction::Set)]
+    pub http_reuse_port: bool,
+
+    /// Seconds the metrics endpoint keeps serving once probes stopped on shutdown, /ready
+    /// failing meanwhile so the prober is deregistered before the listener closes
+    #[arg(long, env = "PROBES_DRAIN_SECONDS", default_value_t = 0)]
+    pub drain_seconds: u64,
+
+    /// Interval between each check
+    #[arg(long, env = "PROBES_INTERVAL_CHECK_MS", default_value_t = 1000)]
+    pub interval_check_ms: u64,
+
+    /// Timeout of each memcached command, shorter than the interval between each check
+    #[arg(long, env = "PROBES_PROBE_TIMEOUT_MS", default_value_t = 100)]
+    pub probe_timeout_ms: u64,
+
+    /// Size of the value set on memcached nodes, generated at startup
+    #[arg(long, env = "PROBES_PROBE_VALUE_BYTES", default_value_t = 1024)]
+    pub probe_value_bytes: u32,
+
+    /// Seconds before the key set on memcached nodes expires, 0 to never expire
+    #[arg(long, env = "PROBES_PROBE_TTL_SECONDS", default_value_t = 300)]
+    pub probe_ttl_seconds: u32,
+
+    /// Policy for nodes registered under multiple matching services: disabled, first or all
+    #[arg(long, env = "PROBES_DEDUP_POLICY", default_value = "disabled")]
+    pub dedup_policy: DedupPolicy,
+
+    /// Max number of nodes probed at the same time to cap metrics cardinality, 0 for unlimited
+    #[arg(long, env = "PROBES_MAX_PROBED_NODES", default_value_t = 0)]
+    pub max_probed_nodes: usize,
+
+    /// Exit with code 2 once the discovery has been failing for that long, 0 to retry forever
+    #[arg(
+        long,
+        env = "PROBES_EXIT_ON_DISCOVERY_FAILURE_SECS",
+        default_value_t = 0
+    )]
+    pub exit_on_discovery_failure_secs: u64,
+
+    /// Exit with code 10 if the metrics endpoint fails, otherwise keep probing and retry to
+    /// bind its port
+    #[arg(long, env = "PROBES_EXIT_ON_HTTP_FAILURE", default_value_t = true, action = ArgAction::Set)]
+    pub exit_on_http_failure: bool,
+
+    /// Exit with code 11 once that many nodes of the first discovery failed their first probe,
+    /// 0 to never exit
+    #[arg(
+        long,
+        env = "PROBES_EXIT_ON_STARTUP_PROBE_FAILURES",
+        default_value_t = 0
+    )]
+    pub exit_on_startup_probe_failures: usize,
+
+    /// Index of the shard of the discovered nodes probed by this prober, from 0
+    #[arg(long, env = "PROBES_SHARD_INDEX", default_value_t = 0)]
+    pub shard_index: u32,
+
+    /// Number of probers splitting the discovered nodes, each one probing the shard of its index
+    #[arg(long, env = "PROBES_SHARD_COUNT", default_value_t = 1)]
+    pub shard_count: u32,
+
+    /// Remove metrics of nodes not probed for that time, 0 to disable
+    #[arg(long, env = "PROBES_IDLE_SERIES_EXPIRY_SECS", default_value_t = 0)]
+    pub idle_series_expiry_secs: u64,
+
+    /// Rate limiter of the node probes and of the consul and nomad discoveries: token-bucket
+    /// allows bursts, gcra spaces calls evenly
+    #[arg(long, env = "PROBES_RATE_LIMITER", default_value = "token-bucket")]
+    pub rate_limiter: RateLimiterKind,
+
+    /// Comma separated list of response time histogram buckets in seconds (default:
+    /// 0.00001,0.00025,0.0005,0.001,0.0025,0.005,0.01,0.025,0.05,0.1,0.25,0.5,1,2.5,5,10)
+    #[arg(
+        long,
+        env = "PROBES_RESPONSE_TIME_BUCKETS",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub response_time_buckets: String,
+
+    /// Sliding window of the p50/p99/p999 response time gauges exported per node and command, 0 to
+    /// disable
+    #[arg(
+        long,
+        env = "PROBES_RESPONSE_TIME_QUANTILES_WINDOW_SECS",
+        default_value_t = 0
+    )]
+    pub response_time_quantiles_window_secs: u64,
+
+    /// Label added to all exported metrics as key=value, can be repeated (default: none)
+    #[arg(long = "label", env = "PROBES_LABELS", value_delimiter = ',')]
+    pub static_labels: Vec<String>,
+
+    /// OpenTelemetry collector grpc endpoint to export traces, requires the otlp feature (default:
+    /// disabled)
+    #[arg(
+        long,
+        env = "PROBES_OTLP_ENDPOINT",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub otlp_endpoint: String,
+
+    /// Prometheus remote write endpoint to push metrics to (default: disabled)
+    #[arg(
+        long,
+        env = "PROBES_REMOTE_WRITE_URL",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub remote_write_url: String,
+
+    /// Interval between each remote write push
+    #[arg(long, env = "PROBES_REMOTE_WRITE_INTERVAL_MS", default_value_t = 15000)]
+    pub remote_write_interval_ms: u64,
+
+    /// Username for remote write basic auth (default: none)
+    #[arg(
+        long,
+        env = "PROBES_REMOTE_WRITE_USERNAME",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub remote_write_username: String,
+
+    /// Password for remote write basic auth (default: none)
+    #[arg(
+        long,
+        env = "PROBES_REMOTE_WRITE_PASSWORD",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub remote_write_password: String,
+
+    /// Bearer token for remote write (default: none)
+    #[arg(
+        long,
+        env = "PROBES_REMOTE_WRITE_BEARER_TOKEN",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub remote_write_bearer_token: String,
+
+    /// StatsD agent host:port to also emit probe metrics to (default: disabled)
+    #[arg(
+        long,
+        env = "PROBES_STATSD_ADDRESS",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub statsd_address: String,
+
+    /// Prefix of StatsD metric names
+    #[arg(long, env = "PROBES_STATSD_PREFIX")]
+    pub statsd_prefix: String,
+
+    /// StatsD line protocol: statsd or dogstatsd
+    #[arg(long, env = "PROBES_STATSD_FLAVOR", default_value = "statsd")]
+    pub statsd_flavor: StatsdFlavor,
+
+    /// ACL username sent with AUTH to redis nodes (default: none)
+    #[arg(
+        long,
+        env = "PROBES_REDIS_USERNAME",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub redis_username: String,
+
+    /// Password sent with AUTH to redis nodes (default: no AUTH)
+    #[arg(
+        long,
+        env = "PROBES_REDIS_PASSWORD",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub redis_password: String,
+
+    /// User authenticated on elasticsearch and opensearch nodes (default: none)
+    #[arg(
+        long,
+        env = "PROBES_ELASTICSEARCH_USERNAME",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub elasticsearch_username: String,
+
+    /// Password of the user authenticated on elasticsearch and opensearch nodes, empty to disable
+    /// authentication (default: none)
+    #[arg(
+        long,
+        env = "PROBES_ELASTICSEARCH_PASSWORD",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub elasticsearch_password: String,
+
+    /// Bucket selected on couchbase data nodes, empty for plain memcached nodes (default: none)
+    #[arg(
+        long,
+        env = "PROBES_MEMCACHED_BUCKET",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub memcached_bucket: String,
+
+    /// User authenticated with sasl on couchbase data nodes, empty to skip it (default: none)
+    #[arg(
+        long,
+        env = "PROBES_MEMCACHED_USERNAME",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub memcached_username: String,
+
+    /// Password of the user authenticated on couchbase data nodes (default: none)
+    #[arg(
+        long,
+        env = "PROBES_MEMCACHED_PASSWORD",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub memcached_password: String,
+
+    /// Comma separated list of hash tags routed to each shard behind memcached proxies, empty to
+    /// probe a single key (default: none)
+    #[arg(
+        long,
+        env = "PROBES_MEMCACHED_SHARD_TAGS",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub memcached_shard_tags: String,
+
+    /// Delimiters of the part of the key hashed by memcached proxies
+    #[arg(long, env = "PROBES_MEMCACHED_HASH_TAG", default_value = "{}")]
+    pub memcached_hash_tag: String,
+
+    /// User authenticated on mysql nodes
+    #[arg(long, env = "PROBES_MYSQL_USERNAME", default_value = "probes")]
+    pub mysql_username: String,
+
+    /// Password of the user authenticated on mysql nodes (default: none)
+    #[arg(
+        long,
+        env = "PROBES_MYSQL_PASSWORD",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub mysql_password: String,
+
+    /// Query run on mysql nodes after the ping, empty to only ping
+    #[arg(long, env = "PROBES_MYSQL_HEALTH_QUERY", default_value = "SELECT 1")]
+    pub mysql_health_query: String,
+
+    /// Name resolved on dns nodes
+    #[arg(long, env = "PROBES_DNS_QUERY_NAME", default_value = "localhost")]
+    pub dns_query_name: String,
+
+    /// Comma separated list of A records expected for the resolved name, empty to accept any
+    /// (default: none)
+    #[arg(
+        long,
+        env = "PROBES_DNS_EXPECTED_RECORDS",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub dns_expected_records: String,
+
+    /// Service checked on grpc nodes, empty for the overall server health (default: none)
+    #[arg(
+        long,
+        env = "PROBES_GRPC_HEALTH_SERVICE",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub grpc_health_service: String,
+
+    /// User authenticated on rabbitmq nodes
+    #[arg(long, env = "PROBES_RABBITMQ_USERNAME", default_value = "guest")]
+    pub rabbitmq_username: String,
+
+    /// Password of the user authenticated on rabbitmq nodes
+    #[arg(
+        long,
+        env = "PROBES_RABBITMQ_PASSWORD",
+        hide_env_values = true,
+        default_value = "guest"
+    )]
+    pub rabbitmq_password: String,
+
+    /// Virtual host opened on rabbitmq nodes
+    #[arg(long, env = "PROBES_RABBITMQ_VHOST", default_value = "/")]
+    pub rabbitmq_vhost: String,
+
+    /// Queue used to publish and get a canary message on rabbitmq nodes, empty to only open a
+    /// channel (default: none)
+    #[arg(
+        long,
+        env = "PROBES_RABBITMQ_CANARY_QUEUE",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub rabbitmq_canary_queue: String,
+
+    /// Subject of the canary round trip on nats nodes, empty to only ping (default: none)
+    #[arg(
+        long,
+        env = "PROBES_NATS_CANARY_SUBJECT",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub nats_canary_subject: String,
+
+    /// Namespace used to write and read a canary record on aerospike nodes, empty to only issue
+    /// info requests (default: none)
+    #[arg(
+        long,
+        env = "PROBES_AEROSPIKE_CANARY_NAMESPACE",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub aerospike_canary_namespace: String,
+
+    /// Query run on each core of solr nodes, empty to only ping the cores (default: none)
+    #[arg(
+        long,
+        env = "PROBES_SOLR_CANARY_QUERY",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub solr_canary_query: String,
+
+    /// User running the health query on clickhouse nodes
+    #[arg(long, env = "PROBES_CLICKHOUSE_USERNAME", default_value = "default")]
+    pub clickhouse_username: String,
+
+    /// Password of the user running the health query on clickhouse nodes (default: none)
+    #[arg(
+        long,
+        env = "PROBES_CLICKHOUSE_PASSWORD",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub clickhouse_password: String,
+
+    /// Port of the native protocol handshaked on clickhouse nodes, 0 to skip it
+    #[arg(long, env = "PROBES_CLICKHOUSE_NATIVE_PORT", default_value_t = 0)]
+    pub clickhouse_native_port: u16,
+
+    /// Path of the test request issued on varnish nodes
+    #[arg(long, env = "PROBES_VARNISH_TEST_PATH", default_value = "/")]
+    pub varnish_test_path: String,
+
+    /// Host header of the test request issued on varnish nodes, empty to use the node socket
+    /// (default: none)
+    #[arg(
+        long,
+        env = "PROBES_VARNISH_TEST_HOST",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub varnish_test_host: String,
+
+    /// Port of the cli pinged on varnish nodes, 0 to skip it
+    #[arg(long, env = "PROBES_VARNISH_ADMIN_PORT", default_value_t = 0)]
+    pub varnish_admin_port: u16,
+
+    /// Secret file used to authenticate on the cli of varnish nodes (default: none)
+    #[arg(
+        long,
+        env = "PROBES_VARNISH_SECRET_FILE",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub varnish_secret_file: String,
+
+    /// Path of the csv stats page of haproxy nodes, empty to query the stats socket
+    #[arg(long, env = "PROBES_HAPROXY_STATS_PATH", default_value = "/stats;csv")]
+    pub haproxy_stats_path: String,
+
+    /// Bucket holding the canary objects of s3 endpoints
+    #[arg(long, env = "PROBES_S3_BUCKET", default_value = "probes")]
+    pub s3_bucket: String,
+
+    /// Access key id signing the requests on s3 endpoints (default: none)
+    #[arg(
+        long,
+        env = "PROBES_S3_ACCESS_KEY",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub s3_access_key: String,
+
+    /// Secret access key signing the requests on s3 endpoints (default: none)
+    #[arg(
+        long,
+        env = "PROBES_S3_SECRET_KEY",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub s3_secret_key: String,
+
+    /// Region of the signature of the requests on s3 endpoints
+    #[arg(long, env = "PROBES_S3_REGION", default_value = "us-east-1")]
+    pub s3_region: String,
+
+    /// Address the bucket in the host instead of the path on s3 endpoints
+    #[arg(long, env = "PROBES_S3_VIRTUAL_HOST")]
+    pub s3_virtual_host: bool,
+
+    /// Domain sent on ehlo to smtp nodes
+    #[arg(long, env = "PROBES_SMTP_EHLO_DOMAIN", default_value = "localhost")]
+    pub smtp_ehlo_domain: String,
+
+    /// Upgrade the connection to smtp nodes with starttls, failing the probe if it is not
+    /// advertised
+    #[arg(long, env = "PROBES_SMTP_STARTTLS")]
+    pub smtp_starttls: bool,
+
+    /// Dn of the simple bind on ldap nodes, empty for an anonymous bind (default: none)
+    #[arg(
+        long,
+        env = "PROBES_LDAP_BIND_DN",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub ldap_bind_dn: String,
+
+    /// Password of the simple bind on ldap nodes (default: none)
+    #[arg(
+        long,
+        env = "PROBES_LDAP_BIND_PASSWORD",
+        hide_env_values = true,
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub ldap_bind_password: String,
+
+    /// Dn of the entry searched with a base scope on ldap nodes, empty for the root DSE (default:
+    /// none)
+    #[arg(
+        long,
+        env = "PROBES_LDAP_BASE_DN",
+        default_value = "",
+        hide_default_value = true
+    )]
+    pub ldap_base_dn: String,
+}
+
+// Mode of the binary, probing the discovered nodes if none
+#[derive(Subcommand, Debug, PartialEq, Eq, Clone)]
+pub enum CliCommand {
+    /// Check the config then exit without probing
+    Validate,
+    /// Run the discovery once and print the targets as json
+    Targets,
+    /// Run the probe once against a single node and print the status of each command
+    Check {
+        /// ip:port of the node
+        address: String,
+    },
+    /// Drive set and get against a single memcached node and print the response time
+    /// percentiles
+    Bench {
+        /// ip:port of the node
+        address: String,
+        /// Requests per second over all connections, 0 for unlimited
+        #[arg(long, default_value_t = 1000)]
+        rate: u64,
+        /// Number of connections issuing requests concurrently
+        #[arg(long, default_value_t = 4)]
+        concurrency: usize,
+        /// Duration of the bench
+        #[arg(long, default_value_t = 10)]
+        duration_secs: u64,
+    },
+    /// Perform a single command against a memcached node and print its answer
+    Memcached {
+        /// ip:port of the node
+        #[arg(long, default_value = "127.0.0.1:11211")]
+        address: String,
+        /// Timeout of the connection and of the command
+        #[arg(long, default_value_t = 1000)]
+        timeout_ms: u64,
+        #[command(subcommand)]
+        command: MemcachedCommand,
+    },
+    /// Print the completion script of a shell like bash, zsh or fish
+    Completions {
+        #[arg(value_enum)]
+        shell: Shell,
+    },
+}
+
+// Command performed by the memcached subcommand
+#[derive(Subcommand, Debug, PartialEq, Eq, Clone)]
+pub enum MemcachedCommand {
+    /// Print the value of a key
+    Get {
+        #[arg(long)]
+        key: String,
+    },
+    /// Set the value of a key
+    Set {
+        #[arg(long)]
+        key: String,
+        #[arg(long)]
+        value: String,
+        /// Seconds before the key expires, 0 to never expire
+        #[arg(long, default_value_t = 0)]
+        ttl: u32,
+    },
+    /// Delete a key
+    Delete {
+        #[arg(long)]
+        key: String,
+    },
+    /// Print the stats of the node
+    Stats {
+        /// Group of stats like items or slabs, empty for the general stats
+        #[arg(long, default_value = "")]
+        group: String,
+    },
+    /// Print the version of the node
+    Version,
+}
+
+/// Write the completion script of the binary for a shell
+///
+/// # Arguments
+///
+/// * `shell` - shell loading the script
+/// * `cmd` - command line of the binary
+/// * `out` - where the script is written
+///
+pub fn write_completions(shell: Shell, cmd: &mut Command, out: &mut dyn Write) {
+    let binary_name = cmd.get_name().to_string();
+    clap_complete::generate(shell, cmd, binary_name, out);
+}
+
+impl Args {
+    /// Check the values of the arguments and their consistency
+    ///
+    /// # Return
+    ///
+    /// * All the issues found in the config
+    ///
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut issues = Vec::new();
+
+        if !self.protocol.is_enabled() {
+            issues.push(self.protocol.disabled_issue());
+        }
+        if self.interval_check_ms == 0 {
+            issues.push("--interval-check-ms must be greater than 0".to_string());
+        }
+        // Only memcached commands are bound by the probe timeout
+        if self.protocol == Protocol::Memcached {
+            if self.probe_timeout_ms == 0 {
+                issues.push("--probe-timeout-ms must be greater than 0".to_string());
+            } else if self.probe_timeout_ms >= self.interval_check_ms {
+                issues.push(
+                    "--probe-timeout-ms must be shorter than --interval-check-ms".to_string(),
+                );
+            }
+        }
+        if self.probe_ttl_seconds > MAX_RELATIVE_TTL_SECONDS {
+            issues.push(format!(
+                "--probe-ttl-seconds must be at most {MAX_RELATIVE_TTL_SECONDS}"
+            ));
+        }
+        if self.idle_series_expiry_secs > 0
+            && self.idle_series_expiry_secs.saturating_mul(1000) <= self.interval_check_ms
+        {
+            issues.push(
+                "--idle-series-expiry-secs must be longer than --interval-check-ms \
+                to not expire the series of probed nodes"
+                    .to_string(),
+            );
+        }
+        if self.response_time_quantiles_window_secs > 0
+            && self.response_time_quantiles_window_secs * 1000 <= self.interval_check_ms
+        {
+            issues.push(
+                "--response-time-quantiles-window-secs must be longer than --interval-check-ms"
+                    .to_string(),
+            );
+        }
+        if !self.response_time_buckets.is_empty() {
+            if let Err(issue) = parse_buckets(&self.response_time_buckets) {
+                issues.push(format!("--response-time-buckets: {issue}"));
+            }
+        }
+        if let Err(issue) = parse_static_labels(&self.static_labels) {
+            issues.push(format!("--label: {issue}"));
+        }
+        if !self.health_http_addr.is_empty() {
+            match self.health_http_addr.parse::<SocketAddr>() {
+                Ok(addr) if addr.port() != 0 && addr.port() == self.http_port => {
+                    issues.push("--health-http-addr must not use --http-port".to_string())
+                }
+                Ok(_) => {}
+                Err(issue) => issues.push(format!("--health-http-addr: {issue}")),
+            }
+        }
+        if self.tls_cert_path.is_empty() != self.tls_key_path.is_empty() {
+            issues.push("--tls-cert-path and --tls-key-path must be set together".to_string());
+        }
+        if !self.remote_write_url.is_empty() && self.remote_write_interval_ms == 0 {
+            issues.push("--remote-write-interval-ms must be greater than 0".to_string());
+        }
+        if let Err(issue) = Shard::new(self.shard_index, self.shard_count) {
+            issues.push(format!("--shard-index and --shard-count: {issue}"));
+        }
+        if self.memcached_hash_tag.chars().count() != 2 {
+            issues.push(format!(
+                "--memcached-hash-tag {} must be two delimiters",
+                self.memcached_hash_tag
+            ));
+        }
+        if let Some(CliCommand::Memcached {
+            timeout_ms,
+            command,
+            ..
+        }) = &self.subcommand
+        {
+            if self.protocol != Protocol::Memcached {
+                issues.push("memcached requires --protocol memcached".to_string());
+            }
+            if *timeout_ms == 0 {
+                issues.push("--timeout-ms must be greater than 0".to_string());
+            }
+            if let MemcachedCommand::Set { ttl, .. } = command {
+                if *ttl > MAX_RELATIVE_TTL_SECONDS {
+                    issues.push(format!("--ttl must be at most {MAX_RELATIVE_TTL_SECONDS}"));
+                }
+            }
+        }
+
+        if let Some(CliCommand::Bench {
+            concurrency,
+            duration_secs,
+            ..
+        }) = self.subcommand
+        {
+            if self.protocol != Protocol::Memcached {
+                issues.push("bench requires --protocol memcached".to_string());
+            }
+            if concurrency == 0 {
+                issues.push("--concurrency must be greater than 0".to_string());
+            }
+            if duration_secs == 0 {
+                issues.push("--duration-secs must be greater than 0".to_string());
+            }
+        }
+
+        // A checked, benched or queried node is not discovered
+        let discovery_kinds: &[DiscoveryKind] = match self.subcommand {
+            Some(
+                CliCommand::Check { .. } | CliCommand::Bench { .. } | CliCommand::Memcached { .. },
+            ) => &[],
+            _ => self.discovery.kinds(),
+        };
+        for &discovery in discovery_kinds {
+            if !discovery.is_enabled() {
+                issues.push(discovery.disabled_issue());
+                continue;
+            }
+            let (required, refresh_interval) = match discovery {
+                DiscoveryKind::Consul | DiscoveryKind::Nomad => {
+                    (vec![("--services-tag", &self.services_tag)], None)
+                }
+                DiscoveryKind::Srv => (
+                    vec![("--srv-names", &self.srv_names)],
+                    Some(("--srv-refresh-interval-ms", self.srv_refresh_interval_ms)),
+                ),
+                DiscoveryKind::File => (
+                    vec![("--targets-file", &self.targets_file)],
+                    Some((
+                        "--targets-file-refresh-interval-ms",
+                        self.targets_file_refresh_interval_ms,
+                    )),
+                ),
+                DiscoveryKind::Http => (
+                    vec![("--http-sd-url", &self.http_sd_url)],
+                    Some((
+                        "--http-sd-refresh-interval-ms",
+                        self.http_sd_refresh_interval_ms,
+                    )),
+                ),
+                DiscoveryKind::Ec2 => (
+                    vec![
+                        ("--cloud-tag", &self.cloud_tag),
+                        ("--cloud-region", &self.cloud_region),
+                    ],
+                    Some((
+                        "--cloud-refresh-interval-ms",
+                        self.cloud_refresh_interval_ms,
+                    )),
+                ),
+                DiscoveryKind::Gce => (
+                    vec![
+                        ("--cloud-tag", &self.cloud_tag),
+                        ("--cloud-project", &self.cloud_project),
+                    ],
+                    Some((
+                        "--cloud-refresh-interval-ms",
+                        self.cloud_refresh_interval_ms,
+                    )),
+                ),
+                DiscoveryKind::Docker => (
+                    vec![("--docker-socket", &self.docker_socket)],
+                    Some((
+                        "--docker-refresh-interval-ms",
+                        self.docker_refresh_interval_ms,
+                    )),
+                ),
+                DiscoveryKind::Mdns => (
+                    vec![("--mdns-services", &self.mdns_services)],
+                    Some(("--mdns-refresh-interval-ms", self.mdns_refresh_interval_ms)),
+                ),
+            };
+
+            for (flag, value) in required {
+                if value.trim().is_empty() {
+                    issues.push(format!("{flag} is required by the {discovery} discovery"));
+                }
+            }
+            if matches!(discovery, DiscoveryKind::Ec2 | DiscoveryKind::Gce) && self.cloud_port == 0
+            {
+                issues.push(format!(
+                    "--cloud-port is required by the {discovery} discovery"
+                ));
+            }
+            if discovery == DiscoveryKind::Mdns && self.mdns_browse_ms == 0 {
+                issues.push("--mdns-browse-ms must be greater than 0".to_string());
+            }
+            if let Some((flag, refresh_interval_ms)) = refresh_interval {
+                if refresh_interval_ms == 0 {
+                    issues.push(format!("{flag} must be greater than 0"));
+                } else if refresh_interval_ms >= self.healthz_max_discovery_age_secs * 1000 {
+                    issues.push(format!(
+                        "--healthz-max-discovery-age-secs must be longer than {flag} \
+                        to not report the {discovery} discovery as stale"
+                    ));
+                }
+            }
+        }
+
+        if issues.is_empty() {
+            Ok(())
+        } else {
+            Err(issues)
+        }
+    }
+}
+
+/// Build the command parsing the arguments of a binary
+///
+/// # Arguments
+///
+/// * `binary_name` - name of the binary displayed in the help and default statsd prefix
+/// * `description` - description of the binary displayed in the help
+/// * `protocol` - default protocol used to probe the discovered nodes
+///
+/// # Return
+///
+/// * Command with the defaults depending on the binary and the environment
+///
+pub fn command(binary_name: &str, description: &str, protocol: Protocol) -> Command {
+    let cloud_region = std::env::var("AWS_REGION").unwrap_or_default();
+    let nomad_addr =
+        std::env::var("NOMAD_ADDR").unwrap_or_else(|_| "http://localhost:4646".to_string());
+    let consul_token = std::env::var("CONSUL_HTTP_TOKEN").unwrap_or_default();
+    let consul_token_file = std::env::var("CONSUL_HTTP_TOKEN_FILE").unwrap_or_default();
+    let nomad_token = std::env::var("NOMAD_TOKEN").unwrap_or_default();
+
+    // Arguments are also accepted after the subcommand
+    let mut command = Args::command();
+    let arg_ids: Vec<String> = command
+        .get_arguments()
+        .map(|arg| arg.get_id().to_string())
+        .collect();
+    for arg_id in arg_ids {
+        command = command.mut_arg(arg_id, |arg| arg.global(true));
+    }
+
+    command
+        .name(binary_name.to_string())
+        .about(description.to_string())
+        .version(env!("CARGO_PKG_VERSION"))
+        .long_version(LONG_VERSION)
+        .mut_arg("protocol", |arg| arg.default_value(protocol.to_string()))
+        .mut_arg("statsd_prefix", |arg| {
+            arg.default_value(binary_name.to_string())
+        })
+        .mut_arg("cloud_region", |arg| arg.default_value(cloud_region))
+        .mut_arg("consul_token", |arg| arg.default_value(consul_token))
+        .mut_arg("consul_token_file", |arg| {
+            arg.default_value(consul_token_file)
+        })
+        .mut_arg("nomad_addr", |arg| arg.default_value(nomad_addr))
+        .mut_arg("nomad_token", |arg| arg.default_value(nomad_token))
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::FromArgMatches;
+
+    use clap_complete::Shell;
+
+    use crate::cli::args::{command, write_completions, Args, CliCommand, MemcachedCommand};
+    use crate::probes::discovery::DiscoveryKinds;
+    use crate::probes::protocol::Protocol;
+    use crate::token_bucket::RateLimiterKind;
+
+    fn parse(args: &[&str]) -> Result<Args, clap::Error> {
+        let matches = command("mempoke", "test", Protocol::Memcached).try_get_matches_from(args)?;
+        Args::from_arg_matches(&matches)
+    }
+
+    #[test]
+    fn command_is_valid() {
+        command("mempoke", "test", Protocol::Memcached).debug_assert();
+    }
+
+    #[test]
+    fn version() {
+        let version = command("mempoke", "test", Protocol::Memcached).render_long_version();
+        assert!(version.starts_with(&format!("mempoke {}", env!("CARGO_PKG_VERSION"))));
+        assert!(version.contains(&format!("commit: {}", env!("PROBES_GIT_COMMIT"))));
+        assert!(version.contains("build date: "));
+        assert!(version.contains("features: "));
+
+        let issue = parse(&["mempoke", "--version"]).unwrap_err();
+        assert_eq!(clap::error::ErrorKind::DisplayVersion, issue.kind());
+    }
+
+    #[test]
+    fn defaults() {
+        let args = parse(&["mempoke"]).unwrap();
+        assert_eq!(Protocol::Memcached, args.protocol);
+        assert_eq!("mempoke", args.statsd_prefix);
+        assert_eq!(DiscoveryKinds::default(), args.discovery);
+        assert_eq!("http://localhost:8500", args.consul_fqdn);
+        assert_eq!(RateLimiterKind::TokenBucket, args.rate_limiter);
+        assert_eq!(8080, args.http_port);
+        assert!(args.static_labels.is_empty());
+        assert!(!args.consul_connect);
+        assert!(!args.tokio_console);
+        assert_eq!(0, args.worker_threads);
+    }
+
+    #[test]
+    fn flags() {
+        let args = parse(&[
+            "mempoke",
+            "--protocol",
+            "redis",
+            "--services-tag",
+            "cache",
+            "--interval-check-ms",
+            "250",
+            "--tokio-console",
+            "true",
+            "--worker-threads",
+            "2",
+            "--consul-connect",
+            "--consul-token-file",
+            "/run/secrets/consul-token",
+            "--label",
+            "env=prod",
+            "--label",
+            "dc=par",
+        ])
+        .unwrap();
+        assert_eq!(Protocol::Redis, args.protocol);
+        assert_eq!("cache", args.services_tag);
+        assert_eq!(250, args.interval_check_ms);
+        assert!(args.tokio_console);
+        assert_eq!(2, args.worker_threads);
+        assert!(args.consul_connect);
+        assert_eq!("/run/secrets/consul-token", args.consul_token_file);
+        assert_eq!(vec!["env=prod", "dc=par"], args.static_labels);
+
+        assert!(parse(&["mempoke", "--protocol", "other"]).is_err());
+        assert!(parse(&["mempoke", "--unknown"]).is_err());
+    }
+
+    #[test]
+    fn completions() {
+        let args = parse(&["mempoke", "completions", "zsh"]).unwrap();
+        assert_eq!(
+            Some(CliCommand::Completions { shell: Shell::Zsh }),
+            args.subcommand
+        );
+        assert!(parse(&["mempoke", "completions", "tcsh"]).is_err());
+
+        for shell in [Shell::Bash, Shell::Zsh, Shell::Fish] {
+            let mut script = Vec::new();
+            write_completions(
+                shell,
+                &mut command("mempoke", "test", Protocol::Memcached),
+                &mut script,
+            );
+            let script = String::from_utf8(script).unwrap();
+            assert!(script.contains("mempoke"));
+            assert!(script.contains("probe-timeout-ms"));
+        }
+    }
+
+    #[test]
+    fn check() {
+        let args = parse(&["mempoke", "check", "127.0.0.1:11211"]).unwrap();
+        assert_eq!(
+            Some(CliCommand::Check {
+                address: "127.0.0.1:11211".to_string()
+            }),
+            args.subcommand
+        );
+        // No --services-tag required for the consul discovery
+        assert_eq!(Ok(()), args.validate());
+        assert!(parse(&["mempoke", "check"]).is_err());
+    }
+
+    #[test]
+    fn bench() {
+        let args = parse(&[
+            "mempoke",
+            "bench",
+            "127.0.0.1:11211",
+            "--rate",
+            "500",
+            "--duration-secs",
+            "5",
+        ])
+        .unwrap();
+        assert_eq!(
+            Some(CliCommand::Bench {
+                address: "127.0.0.1:11211".to_string(),
+                rate: 500,
+                concurrency: 4,
+                duration_secs: 5,
+            }),
+            args.subcommand
+        );
+        assert_eq!(Ok(()), args.validate());
+
+        let args = parse(&[
+            "mempoke",
+            "bench",
+            "127.0.0.1:6379",
+            "--protocol",
+            "redis",
+            "--concurrency",
+            "0",
+        ])
+        .unwrap();
+        assert_eq!(
+            Err(vec![
+                "bench requires --protocol memcached".to_string(),
+                "--concurrency must be greater than 0".to_string(),
+            ]),
+            args.validate()
+        );
+    }
+
+    #[test]
+    fn memcached() {
+        let args = parse(&[
+            "mempoke",
+            "memcached",
+            "--address",
+            "10.0.0.1:11211",
+            "set",
+            "--key",
+            "foo",
+            "--value",
+            "bar",
+            "--ttl",
+            "60",
+        ])
+        .unwrap();
+        assert_eq!(
+            Some(CliCommand::Memcached {
+                address: "10.0.0.1:11211".to_string(),
+                timeout_ms: 1000,
+                command: MemcachedCommand::Set {
+                    key: "foo".to_string(),
+                    value: "bar".to_string(),
+                    ttl: 60,
+                },
+            }),
+            args.subcommand
+        );
+        assert_eq!(Ok(()), args.validate());
+        assert!(parse(&["mempoke", "memcached", "get"]).is_err());
+
+        let args = parse(&[
+            "mempoke",
+            "memcached",
+            "--timeout-ms",
+            "0",
+            "--protocol",
+            "redis",
+            "version",
+        ])
+        .unwrap();
+        assert_eq!(
+            Err(vec![
+                "memcached requires --protocol memcached".to_string(),
+                "--timeout-ms must be greater than 0".to_string(),
+            ]),
+            args.validate()
+        );
+    }
+
+    #[test]
+    fn validate() {
+        let args = parse(&["mempoke", "validate", "--services-tag", "cache"]).unwrap();
+        assert_eq!(Some(CliCommand::Validate), args.subcommand);
+        assert_eq!(Ok(()), args.validate());
+
+        let args = parse(&["mempoke", "targets", "--services-tag", "cache"]).unwrap();
+        assert_eq!(Some(CliCommand::Targets), args.subcommand);
+
+        let args = parse(&["mempoke"]).unwrap();
+        assert_eq!(None, args.subcommand);
+        assert_eq!(
+            Err(vec![
+                "--services-tag is required by the consul discovery".to_string()
+            ]),
+            args.validate()
+        );
+
+        let args = parse(&[
+            "mempoke",
+            "--discovery",
+            "srv,ec2",
+            "--srv-names",
+            "_memcached._tcp.example.com",
+            "--srv-refresh-interval-ms",
+            "0",
+            "--cloud-tag",
+            "cluster",
+            "--cloud-region",
+            "eu-west-1",
+            "--cloud-refresh-interval-ms",
+            "1800000",
+            "--interval-check-ms",
+            "5000",
+            "--probe-timeout-ms",
+            "5000",
+            "--probe-ttl-seconds",
+            "2592001",
+            "--idle-series-expiry-secs",
+            "5",
+            "--response-time-buckets",
+            "0.1,0.01",
+            "--label",
+            "env",
+            "--health-http-addr",
+            "127.0.0.1:8080",
+            "--tls-cert-path",
+            "/etc/probes/cert.pem",
+        ])
+        .unwrap();
+        let issues = args.validate().unwrap_err();
+        assert_eq!(
+            vec![
+                "--probe-timeout-ms must be shorter than --interval-check-ms",
+                "--probe-ttl-seconds must be at most 2592000",
+                "--idle-series-expiry-secs must be longer than --interval-check-ms \
+                to not expire the series of probed nodes",
+                "--response-time-buckets: Buckets must be in strictly increasing order: 0.1,0.01",
+                "--label: Invalid label env, expected key=value",
+                "--health-http-addr must not use --http-port",
+                "--tls-cert-path and --tls-key-path must be set together",
+                "--srv-refresh-interval-ms must be greater than 0",
+                "--cloud-port is required by the ec2 discovery",
+                "--healthz-max-discovery-age-secs must be longer than --cloud-refresh-interval-ms \
+                to not report the ec2 discovery as stale",
+            ],
+            issues
+        );
+    }
+
+    #[test]
+    fn validate_probe_timeout() {
+        // The probe timeout only bounds memcached commands
+        let args = parse(&[
+            "mempoke",
+            "--services-tag",
+            "cache",
+            "--protocol",
+            "redis",
+            "--interval-check-ms",
+            "50",
+        ])
+        .unwrap();
+        assert_eq!(Ok(()), args.validate());
+
+        let args = parse(&[
+            "mempoke",
+            "--services-tag",
+            "cache",
+            "--interval-check-ms",
+            "50",
+        ])
+        .unwrap();
+        assert_eq!(
+            Err(vec![
+                "--probe-timeout-ms must be shorter than --interval-check-ms".to_string()
+            ]),
+            args.validate()
+        );
+
+        // No overflow on huge expiries
+        let args = parse(&[
+            "mempoke",
+            "--services-tag",
+            "cache",
+            "--idle-series-expiry-secs",
+            &u64::MAX.to_string(),
+        ])
+        .unwrap();
+        assert_eq!(Ok(()), args.validate());
+    }
+
+    #[test]
+    fn env_fallback() {
+        std::env::set_var("PROBES_MDNS_BROWSE_MS", "500");
+        std::env::set_var("PROBES_S3_REGION", "eu-west-3");
+        std::env::set_var("PROBES_SMTP_STARTTLS", "true");
+
+        let args = parse(&["mempoke", "--s3-region", "eu-west-1"]).unwrap();
+        assert_eq!(500, args.mdns_browse_ms);
+        assert_eq!("eu-west-1", args.s3_region);
+        assert!(args.smtp_starttls);
+
+        std::env::remove_var("PROBES_MDNS_BROWSE_MS");
+        std::env::remove_var("PROBES_S3_REGION");
+        std::env::remove_var("PROBES_SMTP_STARTTLS");
+    }
+}
@@ -0,0 +1,141 @@
+use std::fs;
+use std::fs::File;
+use std::io;
+use std::os::unix::io::AsRawFd;
+use std::path::PathBuf;
+
+use tracing::warn;
+
+/// Fork twice and detach from the controlling terminal to run under classic init
+///
+/// Must be called before any thread is started, stdin is redirected to /dev/null
+/// while stdout and stderr are kept for the init script to redirect the logs
+///
+/// # Return
+///
+/// * Error if a fork or the detach failed, only the daemon process returns
+///
+pub fn daemonize() -> io::Result<()> {
+    fork_and_exit_parent()?;
+    // SAFETY: setsid has no memory safety requirement
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // Not a session leader anymore so that the daemon never reacquires a terminal
+    fork_and_exit_parent()?;
+
+    let dev_null = File::open("/dev/null")?;
+    // SAFETY: both file descriptors are valid for the duration of the call
+    if unsafe { libc::dup2(dev_null.as_raw_fd(), libc::STDIN_FILENO) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Fork the process and exit the parent
+fn fork_and_exit_parent() -> io::Result<()> {
+    // SAFETY: the process is still single threaded, the tokio runtime is started afterward
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(()),
+        // SAFETY: _exit skips the atexit handlers and buffers shared with the child
+        _ => unsafe { libc::_exit(0) },
+    }
+}
+
+/// Return true if a process with that pid is running
+fn is_running(pid: i32) -> bool {
+    // SAFETY: signal 0 only checks the existence of the process
+    unsafe { libc::kill(pid, 0) == 0 }
+    || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// Pid file of the running prober, removed on graceful shutdown
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+}
+
+impl PidFile {
+    /// Write the pid of the process to a pid file
+    ///
+    /// A pid file left by a stopped prober is overwritten
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - path of the pid file
+    ///
+    /// # Return
+    ///
+    /// * PidFile removing the file on drop or an error if another prober is running
+    ///
+    pub fn create(path: &str) -> io::Result<PidFile> {
+        let pid = std::process::id();
+        let previous_pid = fs::read_to_string(path)
+            .ok()
+            .and_then(|content| content.trim().parse::<i32>().ok())
+            .filter(|previous_pid| *previous_pid > 0 && *previous_pid as u32 != pid);
+        if let Some(previous_pid) = previous_pid {
+            if is_running(previous_pid) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("Pid file {path} belongs to running process {previous_pid}"),
+                ));
+            }
+        }
+
+        fs::write(path, format!("{pid}\n"))?;
+        Ok(PidFile {
+            path: PathBuf::from(path),
+        })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        if let Err(issue) = fs::remove_file(&self.path) {
+            warn!(
+                "Issue to remove pid file {} due to {}",
+                self.path.display(),
+                issue
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use crate::cli::daemon::PidFile;
+
+    #[test]
+    fn pid_file() {
+        let path = std::env::temp_dir().join(format!("probes-{}.pid", std::process::id()));
+        let path_str = path.to_str().unwrap();
+
+        let pid_file = PidFile::create(path_str).unwrap();
+        assert_eq!(
+            format!("{}\n", std::process::id()),
+            fs::read_to_string(&path).unwrap()
+        );
+        drop(pid_file);
+        assert!(!path.exists());
+
+        // Stale pid file of a stopped prober
+        fs::write(&path, format!("{}\n", i32::MAX)).unwrap();
+        let pid_file = PidFile::create(path_str).unwrap();
+        drop(pid_file);
+
+        // Pid file of a running process
+        // SAFETY: getppid has no memory safety requirement
+        let parent_pid = unsafe { libc::getppid() };
+        fs::write(&path, format!("{parent_pid}\n")).unwrap();
+        assert!(PidFile::create(path_str).is_err());
+        assert_eq!(
+            format!("{parent_pid}\n"),
+            fs::read_to_string(&path).unwrap()
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
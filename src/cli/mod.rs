@@ -0,0 +1,883 @@
+use std::time::Duration;
+
+use clap::FromArgMatches;
+use serde_json::json;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::oneshot;
+use tokio::time::sleep;
+use tracing::{error, info};
+
+#[cfg(feature = "aerospike")]
+use crate::aerospike;
+#[cfg(feature = "memcached")]
+use crate::cli::args::MemcachedCommand;
+use crate::cli::args::{Args, CliCommand};
+use crate::cli::daemon::PidFile;
+#[cfg(feature = "clickhouse")]
+use crate::clickhouse;
+#[cfg(feature = "cloud")]
+use crate::cloud::{CloudClient, CloudDiscovery, CloudProvider};
+#[cfg(feature = "consul")]
+use crate::consul::{ConsulClient, ConsulDiscovery};
+#[cfg(feature = "dns")]
+use crate::dns;
+#[cfg(feature = "docker")]
+use crate::docker::{DockerClient, DockerDiscovery};
+#[cfg(feature = "elasticsearch")]
+use crate::elasticsearch;
+#[cfg(feature = "file")]
+use crate::file::{FileClient, FileDiscovery};
+#[cfg(feature = "grpc")]
+use crate::grpc;
+#[cfg(feature = "haproxy")]
+use crate::haproxy;
+#[cfg(feature = "http-sd")]
+use crate::http_sd::{HttpSdClient, HttpSdDiscovery};
+#[cfg(feature = "ldap")]
+use crate::ldap;
+#[cfg(feature = "mdns")]
+use crate::mdns::{MdnsClient, MdnsDiscovery};
+#[cfg(feature = "memcached")]
+use crate::memcached;
+#[cfg(feature = "memcached")]
+use crate::memcached::bench::{bench, BenchConfig};
+#[cfg(feature = "memcached")]
+use crate::memcached::tool::{run_command, ToolCommand};
+#[cfg(feature = "mysql")]
+use crate::mysql;
+#[cfg(feature = "nats")]
+use crate::nats;
+#[cfg(feature = "nomad")]
+use crate::nomad::{NomadClient, NomadDiscovery};
+use crate::probes::auth::HttpAuth;
+use crate::probes::check::check_node;
+use crate::probes::discovery::{Discovery, DiscoveryKind, MergedDiscovery};
+use crate::probes::exit::{ExitError, ExitPolicy};
+use crate::probes::prometheus::{
+    init_build_info, init_prometheus_http_endpoint, parse_buckets, parse_static_labels, redact,
+    set_effective_config, set_health_http_addr, set_http_bind_retry, set_http_reuse_port,
+    set_response_time_buckets, set_static_labels,
+};
+use crate::probes::protocol::Protocol;
+use crate::probes::quantiles::init_response_time_quantiles;
+use crate::probes::readiness::READINESS;
+use crate::probes::remote_write::RemoteWriteClient;
+use crate::probes::runtime::register_runtime_metrics;
+use crate::probes::shard::Shard;
+use crate::probes::statsd::init_statsd;
+use crate::probes::systemd;
+use crate::probes::telemetry::{init_tracing, shutdown_tracing};
+use crate::probes::{discover_targets, init_probing, ProbeServices};
+#[cfg(feature = "rabbitmq")]
+use crate::rabbitmq;
+#[cfg(feature = "redis")]
+use crate::redis;
+#[cfg(feature = "s3")]
+use crate::s3;
+#[cfg(feature = "smtp")]
+use crate::smtp;
+#[cfg(feature = "solr")]
+use crate::solr;
+#[cfg(feature = "srv")]
+use crate::srv::{SrvClient, SrvDiscovery};
+#[cfg(feature = "varnish")]
+use crate::varnish;
+
+pub mod args;
+pub mod daemon;
+
+/// Wait for SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut sigterm = match signal(SignalKind::terminate()) {
+        Ok(sigterm) => sigterm,
+        Err(issue) => {
+            error!("Issue to listen for SIGTERM due to {}", issue);
+            let _ = tokio::signal::ctrl_c().await;
+            return;
+        }
+    };
+
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => info!("Received SIGINT"),
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+    }
+}
+
+/// Map the memcached subcommand to the command performed against the node
+#[cfg(feature = "memcached")]
+fn tool_command(command: &MemcachedCommand) -> ToolCommand {
+    match command {
+        MemcachedCommand::Get { key } => ToolCommand::Get { key: key.clone() },
+        MemcachedCommand::Set { key, value, ttl } => ToolCommand::Set {
+            key: key.clone(),
+            value: value.clone(),
+            ttl: *ttl,
+        },
+        MemcachedCommand::Delete { key } => ToolCommand::Delete { key: key.clone() },
+        MemcachedCommand::Stats { group } => ToolCommand::Stats {
+            group: group.clone(),
+        },
+        MemcachedCommand::Version => ToolCommand::Version,
+    }
+}
+
+/// Parse the command line arguments and probe discovered nodes until a shutdown signal
+///
+/// # Arguments
+///
+/// * `binary_name` - name of the binary used for tracing, thread names and statsd prefix
+/// * `description` - description of the binary displayed in the help
+/// * `protocol` - default protocol used to probe the discovered nodes, overridden by --protocol
+///
+/// # Return
+///
+/// * Exit code on failure
+///
+pub fn run(binary_name: &str, description: &str, protocol: Protocol) -> Result<(), i32> {
+    let matches = args::command(binary_name, description, protocol).get_matches();
+    let args = Args::from_arg_matches(&matches).unwrap_or_else(|issue| issue.exit());
+    // Completions do not depend on the rest of the config
+    if let Some(CliCommand::Completions { shell }) = args.subcommand {
+        args::write_completions(
+            shell,
+            &mut args::command(binary_name, description, protocol),
+            &mut std::io::stdout(),
+        );
+        return Ok(());
+    }
+    if let Err(issues) = args.validate() {
+        for issue in issues {
+            eprintln!("Invalid config: {issue}");
+        }
+        return Err(1);
+    }
+    if args.subcommand == Some(CliCommand::Validate) {
+        println!("Config is valid");
+        return Ok(());
+    }
+
+    let Args {
+        subcommand,
+        discovery,
+        discovery_merge,
+        consul_fqdn,
+        consul_connect,
+        consul_connect_mtls_service,
+        consul_token,
+        consul_token_file,
+        services_tag,
+        srv_names,
+        srv_resolver,
+        srv_refresh_interval_ms,
+        targets_file,
+        targets_file_refresh_interval_ms,
+        http_sd_url,
+        http_sd_refresh_interval_ms,
+        cloud_tag,
+        cloud_port,
+        cloud_region,
+        cloud_project,
+        cloud_endpoint,
+        cloud_refresh_interval_ms,
+        docker_socket,
+        docker_refresh_interval_ms,
+        nomad_addr,
+        nomad_token,
+        nomad_namespace,
+        mdns_services,
+        mdns_browse_ms,
+        mdns_refresh_interval_ms,
+        protocol,
+        pid_file,
+        daemonize,
+        tokio_console,
+        worker_threads,
+        max_blocking_threads,
+        log_format,
+        log_level,
+        http_port,
+        health_http_addr,
+        tls_cert_path,
+        tls_key_path,
+        http_auth_username,
+        http_auth_password,
+        http_auth_bearer_token,
+        healthz_max_discovery_age_secs,
+        http_access_log,
+        http_reuse_port,
+        drain_seconds,
+        interval_check_ms,
+        probe_timeout_ms,
+        probe_value_bytes,
+        probe_ttl_seconds,
+        dedup_policy,
+        max_probed_nodes,
+        shard_index,
+        shard_count,
+        exit_on_discovery_failure_secs,
+        exit_on_http_failure,
+        exit_on_startup_probe_failures,
+        idle_series_expiry_secs,
+        rate_limiter,
+        response_time_buckets,
+        response_time_quantiles_window_secs,
+        static_labels,
+        otlp_endpoint,
+        remote_write_url,
+        remote_write_interval_ms,
+        remote_write_username,
+        remote_write_password,
+        remote_write_bearer_token,
+        statsd_address,
+        statsd_prefix,
+        statsd_flavor,
+        redis_username,
+        redis_password,
+        elasticsearch_username,
+        elasticsearch_password,
+        memcached_bucket,
+        memcached_username,
+        memcached_password,
+        memcached_shard_tags,
+        memcached_hash_tag,
+        mysql_username,
+        mysql_password,
+        mysql_health_query,
+        dns_query_name,
+        dns_expected_records,
+        grpc_health_service,
+        rabbitmq_username,
+        rabbitmq_password,
+        rabbitmq_vhost,
+        rabbitmq_canary_queue,
+        nats_canary_subject,
+        aerospike_canary_namespace,
+        solr_canary_query,
+        clickhouse_username,
+        clickhouse_password,
+        clickhouse_native_port,
+        varnish_test_path,
+        varnish_test_host,
+        varnish_admin_port,
+        varnish_secret_file,
+        haproxy_stats_path,
+        s3_bucket,
+        s3_access_key,
+        s3_secret_key,
+        s3_region,
+        s3_virtual_host,
+        smtp_ehlo_domain,
+        smtp_starttls,
+        ldap_bind_dn,
+        ldap_bind_password,
+        ldap_base_dn,
+    } = args;
+
+    // Served on /config, secrets are redacted
+    let effective_config = json!({
+        "protocol": protocol.to_string(),
+        "discovery": discovery.to_string(),
+        "discovery_merge": discovery_merge.to_string(),
+        "consul_fqdn": consul_fqdn,
+        "consul_connect": consul_connect,
+        "consul_connect_mtls_service": consul_connect_mtls_service,
+        "consul_token": redact(&consul_token),
+        "consul_token_file": consul_token_file,
+        "services_tag": services_tag,
+        "srv_names": srv_names,
+        "srv_resolver": srv_resolver,
+        "srv_refresh_interval_ms": srv_refresh_interval_ms,
+        "targets_file": targets_file,
+        "targets_file_refresh_interval_ms": targets_file_refresh_interval_ms,
+        "http_sd_url": http_sd_url,
+        "http_sd_refresh_interval_ms": http_sd_refresh_interval_ms,
+        "cloud_tag": cloud_tag,
+        "cloud_port": cloud_port,
+        "cloud_region": cloud_region,
+        "cloud_project": cloud_project,
+        "cloud_endpoint": cloud_endpoint,
+        "cloud_refresh_interval_ms": cloud_refresh_interval_ms,
+        "docker_socket": docker_socket,
+        "docker_refresh_interval_ms": docker_refresh_interval_ms,
+        "nomad_addr": nomad_addr,
+        "nomad_token": redact(&nomad_token),
+        "nomad_namespace": nomad_namespace,
+        "mdns_services": mdns_services,
+        "mdns_browse_ms": mdns_browse_ms,
+        "mdns_refresh_interval_ms": mdns_refresh_interval_ms,
+        "pid_file": pid_file,
+        "daemonize": daemonize,
+        "tokio_console": tokio_console,
+        "worker_threads": worker_threads,
+        "max_blocking_threads": max_blocking_threads,
+        "log_format": log_format.to_string(),
+        "log_level": log_level,
+        "http_port": http_port,
+        "health_http_addr": health_http_addr,
+        "tls_cert_path": tls_cert_path,
+        "tls_key_path": tls_key_path,
+        "http_auth_username": http_auth_username,
+        "http_auth_password": redact(&http_auth_password),
+        "http_auth_bearer_token": redact(&http_auth_bearer_token),
+        "healthz_max_discovery_age_secs": healthz_max_discovery_age_secs,
+        "http_access_log": http_access_log,
+        "http_reuse_port": http_reuse_port,
+        "drain_seconds": drain_seconds,
+        "interval_check_ms": interval_check_ms,
+        "probe_timeout_ms": probe_timeout_ms,
+        "probe_value_bytes": probe_value_bytes,
+        "probe_ttl_seconds": probe_ttl_seconds,
+        "dedup_policy": dedup_policy.to_string(),
+        "max_probed_nodes": max_probed_nodes,
+        "shard_index": shard_index,
+        "shard_count": shard_count,
+        "exit_on_discovery_failure_secs": exit_on_discovery_failure_secs,
+        "exit_on_http_failure": exit_on_http_failure,
+        "exit_on_startup_probe_failures": exit_on_startup_probe_failures,
+        "idle_series_expiry_secs": idle_series_expiry_secs,
+        "rate_limiter": rate_limiter.to_string(),
+        "response_time_buckets": response_time_buckets,
+        "response_time_quantiles_window_secs": response_time_quantiles_window_secs,
+        "labels": static_labels,
+        "otlp_endpoint": otlp_endpoint,
+        "remote_write_url": remote_write_url,
+        "remote_write_interval_ms": remote_write_interval_ms,
+        "remote_write_username": remote_write_username,
+        "remote_write_password": redact(&remote_write_password),
+        "remote_write_bearer_token": redact(&remote_write_bearer_token),
+        "statsd_address": statsd_address,
+        "statsd_prefix": statsd_prefix,
+        "statsd_flavor": statsd_flavor.to_string(),
+        "redis_username": redis_username,
+        "redis_password": redact(&redis_password),
+        "elasticsearch_username": elasticsearch_username,
+        "elasticsearch_password": redact(&elasticsearch_password),
+        "memcached_bucket": memcached_bucket,
+        "memcached_username": memcached_username,
+        "memcached_password": redact(&memcached_password),
+        "memcached_shard_tags": memcached_shard_tags,
+        "memcached_hash_tag": memcached_hash_tag,
+        "mysql_username": mysql_username,
+        "mysql_password": redact(&mysql_password),
+        "mysql_health_query": mysql_health_query,
+        "dns_query_name": dns_query_name,
+        "dns_expected_records": dns_expected_records,
+        "grpc_health_service": grpc_health_service,
+        "rabbitmq_username": rabbitmq_username,
+        "rabbitmq_password": redact(&rabbitmq_password),
+        "rabbitmq_vhost": rabbitmq_vhost,
+        "rabbitmq_canary_queue": rabbitmq_canary_queue,
+        "nats_canary_subject": nats_canary_subject,
+        "aerospike_canary_namespace": aerospike_canary_namespace,
+        "solr_canary_query": solr_canary_query,
+        "clickhouse_username": clickhouse_username,
+        "clickhouse_password": redact(&clickhouse_password),
+        "clickhouse_native_port": clickhouse_native_port,
+        "varnish_test_path": varnish_test_path,
+        "varnish_test_host": varnish_test_host,
+        "varnish_admin_port": varnish_admin_port,
+        "varnish_secret_file": varnish_secret_file,
+        "haproxy_stats_path": haproxy_stats_path,
+        "s3_bucket": s3_bucket,
+        "s3_access_key": s3_access_key,
+        "s3_secret_key": redact(&s3_secret_key),
+        "s3_region": s3_region,
+        "s3_virtual_host": s3_virtual_host,
+        "smtp_ehlo_domain": smtp_ehlo_domain,
+        "smtp_starttls": smtp_starttls,
+        "ldap_bind_dn": ldap_bind_dn,
+        "ldap_bind_password": redact(&ldap_bind_password),
+        "ldap_base_dn": ldap_base_dn,
+    });
+
+    // Detach before any thread is started by the tokio scheduler
+    if daemonize && subcommand.is_none() {
+        if let Err(issue) = daemon::daemonize() {
+            eprintln!("Issue to daemonize due to {issue}");
+            return Err(8);
+        }
+    }
+
+    // Removed when returning from run on graceful shutdown
+    let _pid_file = if pid_file.is_empty() || subcommand.is_some() {
+        None
+    } else {
+        match PidFile::create(&pid_file) {
+            Ok(pid_file) => Some(pid_file),
+            Err(issue) => {
+                eprintln!("Issue to write pid file due to {issue}");
+                return Err(8);
+            }
+        }
+    };
+
+    // Init multi thread tokio scheduler
+    let mut runtime_builder = tokio::runtime::Builder::new_multi_thread();
+    runtime_builder.enable_all().thread_name(binary_name);
+    if worker_threads > 0 {
+        runtime_builder.worker_threads(worker_threads);
+    }
+    if max_blocking_threads > 0 {
+        runtime_builder.max_blocking_threads(max_blocking_threads);
+    }
+    let multi_thread_runtime_res = runtime_builder.build();
+
+    // install global collector configured based on RUST_LOG env var.
+    // OTLP export needs to be initialized from within the tokio runtime
+    let _runtime_guard = multi_thread_runtime_res
+        .as_ref()
+        .ok()
+        .map(|multi_thread_runtime| multi_thread_runtime.enter());
+    if let Err(issue) = init_tracing(
+        binary_name,
+        tokio_console,
+        log_format,
+        &log_level,
+        // Keep stdout for the output of the subcommand
+        subcommand.is_some(),
+        &otlp_endpoint,
+    ) {
+        eprintln!("Issue to init tracing due to {issue}");
+        return Err(4);
+    }
+
+    // Override response time histogram buckets before any metric is registered
+    if !response_time_buckets.is_empty() {
+        if let Err(issue) =
+            parse_buckets(&response_time_buckets).and_then(set_response_time_buckets)
+        {
+            error!("Invalid response time buckets: {}", issue);
+            return Err(3);
+        }
+    }
+
+    if response_time_quantiles_window_secs > 0 {
+        if let Err(issue) =
+            init_response_time_quantiles(Duration::from_secs(response_time_quantiles_window_secs))
+        {
+            error!("Issue to init response time quantiles due to {}", issue);
+            return Err(3);
+        }
+    }
+
+    if let Err(issue) = parse_static_labels(&static_labels).and_then(set_static_labels) {
+        error!("Invalid static labels: {}", issue);
+        return Err(7);
+    }
+
+    init_build_info();
+    set_effective_config(effective_config).unwrap_or(());
+    set_http_reuse_port(http_reuse_port).unwrap_or(());
+    // Validated by args
+    set_health_http_addr(health_http_addr.parse().ok()).unwrap_or(());
+    set_http_bind_retry(!exit_on_http_failure).unwrap_or(());
+    #[cfg(feature = "redis")]
+    redis::set_auth(&redis_username, &redis_password).unwrap_or(());
+    #[cfg(feature = "elasticsearch")]
+    elasticsearch::set_auth(&elasticsearch_username, &elasticsearch_password).unwrap_or(());
+    #[cfg(feature = "memcached")]
+    {
+        memcached::set_bucket_config(&memcached_bucket, &memcached_username, &memcached_password)
+            .unwrap_or(());
+        memcached::set_timeout(Duration::from_millis(probe_timeout_ms)).unwrap_or(());
+        memcached::set_payload(probe_value_bytes as usize, u64::from(probe_ttl_seconds))
+            .unwrap_or(());
+        if let Err(issue) = memcached::set_shard_keys(&memcached_shard_tags, &memcached_hash_tag) {
+            error!("Invalid memcached shard config: {}", issue);
+            return Err(1);
+        }
+    }
+    #[cfg(feature = "mysql")]
+    mysql::set_config(&mysql_username, &mysql_password, &mysql_health_query).unwrap_or(());
+    #[cfg(feature = "dns")]
+    if let Err(issue) = dns::set_config(&dns_query_name, &dns_expected_records) {
+        error!("Invalid dns config: {}", issue);
+        return Err(1);
+    }
+    #[cfg(feature = "grpc")]
+    grpc::set_health_service(&grpc_health_service).unwrap_or(());
+    #[cfg(feature = "rabbitmq")]
+    rabbitmq::set_config(
+        &rabbitmq_username,
+        &rabbitmq_password,
+        &rabbitmq_vhost,
+        &rabbitmq_canary_queue,
+    )
+    .unwrap_or(());
+    #[cfg(feature = "nats")]
+    nats::set_canary_subject(&nats_canary_subject).unwrap_or(());
+    #[cfg(feature = "aerospike")]
+    aerospike::set_canary_namespace(&aerospike_canary_namespace).unwrap_or(());
+    #[cfg(feature = "solr")]
+    solr::set_canary_query(&solr_canary_query).unwrap_or(());
+    #[cfg(feature = "clickhouse")]
+    clickhouse::set_config(
+        &clickhouse_username,
+        &clickhouse_password,
+        clickhouse_native_port,
+    )
+    .unwrap_or(());
+    #[cfg(feature = "varnish")]
+    if let Err(issue) = varnish::set_config(
+        &varnish_test_path,
+        &varnish_test_host,
+        varnish_admin_port,
+        &varnish_secret_file,
+    ) {
+        error!("Invalid varnish config: {}", issue);
+        return Err(1);
+    }
+    #[cfg(feature = "haproxy")]
+    haproxy::set_stats_path(&haproxy_stats_path).unwrap_or(());
+    #[cfg(feature = "s3")]
+    s3::set_config(
+        &s3_bucket,
+        &s3_access_key,
+        &s3_secret_key,
+        &s3_region,
+        s3_virtual_host,
+    )
+    .unwrap_or(());
+    #[cfg(feature = "smtp")]
+    smtp::set_config(&smtp_ehlo_domain, smtp_starttls).unwrap_or(());
+    #[cfg(feature = "ldap")]
+    ldap::set_config(&ldap_bind_dn, &ldap_bind_password, &ldap_base_dn).unwrap_or(());
+
+    let shard = match Shard::new(shard_index, shard_count) {
+        Ok(shard) => shard,
+        Err(issue) => {
+            error!("Invalid shard config: {}", issue);
+            return Err(1);
+        }
+    };
+
+    let exit_policy = ExitPolicy {
+        discovery_failure: (exit_on_discovery_failure_secs > 0)
+            .then(|| Duration::from_secs(exit_on_discovery_failure_secs)),
+        startup_probe_failures: exit_on_startup_probe_failures,
+        http_failure: exit_on_http_failure,
+    };
+
+    // Subcommands run against a single node without discovery
+    match &subcommand {
+        Some(CliCommand::Check { address })
+        | Some(CliCommand::Bench { address, .. })
+        | Some(CliCommand::Memcached { address, .. }) => {
+            let multi_thread_runtime = match &multi_thread_runtime_res {
+                Ok(multi_thread_runtime) => multi_thread_runtime,
+                Err(issue) => {
+                    error!(
+                        "Issue starting multi-threaded tokio scheduler due to: {}",
+                        issue
+                    );
+                    return Err(1);
+                }
+            };
+            let (report, is_ok) = match &subcommand {
+                #[cfg(feature = "memcached")]
+                Some(CliCommand::Bench {
+                    rate,
+                    concurrency,
+                    duration_secs,
+                    ..
+                }) => {
+                    let report = multi_thread_runtime.block_on(bench(
+                        address,
+                        BenchConfig {
+                            rate: *rate,
+                            concurrency: *concurrency,
+                            duration: Duration::from_secs(*duration_secs),
+                        },
+                    ));
+                    (report.to_string(), report.is_ok())
+                }
+                #[cfg(feature = "memcached")]
+                Some(CliCommand::Memcached {
+                    timeout_ms,
+                    command,
+                    ..
+                }) => {
+                    let report = multi_thread_runtime.block_on(run_command(
+                        address,
+                        &tool_command(command),
+                        Duration::from_millis(*timeout_ms),
+                    ));
+                    (report.to_string(), report.is_ok())
+                }
+                _ => {
+                    let report = multi_thread_runtime.block_on(check_node(
+                        protocol,
+                        binary_name,
+                        address,
+                        Duration::from_millis(interval_check_ms),
+                    ));
+                    (report.to_string(), report.is_ok())
+                }
+            };
+            shutdown_tracing();
+            print!("{report}");
+            return if is_ok { Ok(()) } else { Err(9) };
+        }
+        _ => {}
+    }
+
+    // Each source is probed alongside the others, merged per the merge policy
+    let mut discovery_sources = Vec::new();
+    for discovery in discovery.kinds() {
+        let discovery_source: Box<dyn Discovery> = match discovery {
+            #[cfg(feature = "consul")]
+            DiscoveryKind::Consul => {
+                if services_tag.is_empty() {
+                    error!("Services tag is required by the consul discovery");
+                    return Err(1);
+                }
+                let mut consul_client = ConsulClient::new(consul_fqdn.clone())
+                    .with_token(&consul_token, &consul_token_file);
+                if consul_connect {
+                    consul_client = consul_client.with_connect(&consul_connect_mtls_service);
+                }
+                Box::new(ConsulDiscovery::new(
+                    consul_client,
+                    &services_tag,
+                    rate_limiter,
+                ))
+            }
+            #[cfg(feature = "srv")]
+            DiscoveryKind::Srv => match SrvClient::new(&srv_names, &srv_resolver) {
+                Ok(srv_client) => Box::new(SrvDiscovery::new(
+                    srv_client,
+                    Duration::from_millis(srv_refresh_interval_ms),
+                )),
+                Err(issue) => {
+                    error!("Invalid srv discovery config: {}", issue);
+                    return Err(1);
+                }
+            },
+            #[cfg(feature = "file")]
+            DiscoveryKind::File => match FileClient::new(&targets_file) {
+                Ok(file_client) => Box::new(FileDiscovery::new(
+                    file_client,
+                    Duration::from_millis(targets_file_refresh_interval_ms),
+                )),
+                Err(issue) => {
+                    error!("Invalid file discovery config: {}", issue);
+                    return Err(1);
+                }
+            },
+            #[cfg(feature = "http-sd")]
+            DiscoveryKind::Http => match HttpSdClient::new(&http_sd_url) {
+                Ok(http_sd_client) => Box::new(HttpSdDiscovery::new(
+                    http_sd_client,
+                    Duration::from_millis(http_sd_refresh_interval_ms),
+                )),
+                Err(issue) => {
+                    error!("Invalid http discovery config: {}", issue);
+                    return Err(1);
+                }
+            },
+            #[cfg(feature = "cloud")]
+            DiscoveryKind::Ec2 | DiscoveryKind::Gce => {
+                let (provider, location) = match discovery {
+                    DiscoveryKind::Ec2 => (CloudProvider::Ec2, &cloud_region),
+                    _ => (CloudProvider::Gce, &cloud_project),
+                };
+                match CloudClient::new(provider, &cloud_tag, cloud_port, location, &cloud_endpoint)
+                {
+                    Ok(cloud_client) => Box::new(CloudDiscovery::new(
+                        cloud_client,
+                        Duration::from_millis(cloud_refresh_interval_ms),
+                    )),
+                    Err(issue) => {
+                        error!("Invalid {} discovery config: {}", discovery, issue);
+                        return Err(1);
+                    }
+                }
+            }
+            #[cfg(feature = "docker")]
+            DiscoveryKind::Docker => match DockerClient::new(&docker_socket) {
+                Ok(docker_client) => Box::new(DockerDiscovery::new(
+                    docker_client,
+                    Duration::from_millis(docker_refresh_interval_ms),
+                )),
+                Err(issue) => {
+                    error!("Invalid docker discovery config: {}", issue);
+                    return Err(1);
+                }
+            },
+            #[cfg(feature = "nomad")]
+            DiscoveryKind::Nomad => {
+                if services_tag.is_empty() {
+                    error!("Services tag is required by the nomad discovery");
+                    return Err(1);
+                }
+                Box::new(NomadDiscovery::new(
+                    NomadClient::new(&nomad_addr, &nomad_token, &nomad_namespace),
+                    &services_tag,
+                    rate_limiter,
+                ))
+            }
+            #[cfg(feature = "mdns")]
+            DiscoveryKind::Mdns => {
+                match MdnsClient::new(&mdns_services, Duration::from_millis(mdns_browse_ms)) {
+                    Ok(mdns_client) => Box::new(MdnsDiscovery::new(
+                        mdns_client,
+                        Duration::from_millis(mdns_refresh_interval_ms),
+                    )),
+                    Err(issue) => {
+                        error!("Invalid mdns discovery config: {}", issue);
+                        return Err(1);
+                    }
+                }
+            }
+            // Discoveries whose feature is disabled in this build, rejected by args
+            #[allow(unreachable_patterns)]
+            kind => {
+                error!("{}", kind.disabled_issue());
+                return Err(1);
+            }
+        };
+        discovery_sources.push((discovery.to_string(), discovery_source));
+    }
+    let mut discovery_source: Box<dyn Discovery> = match discovery_sources.len() {
+        1 => discovery_sources.remove(0).1,
+        _ => Box::new(MergedDiscovery::new(discovery_sources, discovery_merge)),
+    };
+
+    // Init statsd sink
+    if !statsd_address.is_empty() {
+        if let Err(issue) = init_statsd(&statsd_address, &statsd_prefix, statsd_flavor) {
+            error!("Issue to init statsd sink due to {}", issue);
+            return Err(6);
+        }
+    }
+
+    match &multi_thread_runtime_res {
+        Ok(multi_thread_runtime) if subcommand == Some(CliCommand::Targets) => {
+            let targets_res = multi_thread_runtime.block_on(discover_targets(
+                discovery_source.as_mut(),
+                dedup_policy,
+                protocol,
+                shard,
+            ));
+            shutdown_tracing();
+            match targets_res {
+                Ok(targets) => {
+                    println!("{targets:#}");
+                    return Ok(());
+                }
+                Err(issue) => {
+                    error!("Issue to discover targets: {}", issue);
+                    return Err(2);
+                }
+            }
+        }
+        Ok(multi_thread_runtime) => {
+            // Export tokio runtime metrics
+            if let Err(issue) = register_runtime_metrics(multi_thread_runtime.handle().clone()) {
+                error!("Issue to register tokio runtime metrics due to {}", issue);
+            }
+
+            // Init prometheus http endpoint
+            let http_auth = HttpAuth::new(
+                &http_auth_username,
+                &http_auth_password,
+                &http_auth_bearer_token,
+            );
+            let (shutdown_tx, shutdown_rx) = oneshot::channel();
+            let (http_failure_tx, http_failure_rx) = oneshot::channel();
+            let http_endpoint = multi_thread_runtime.spawn(async move {
+                if let Err(issue) = init_prometheus_http_endpoint(
+                    http_port,
+                    &tls_cert_path,
+                    &tls_key_path,
+                    http_auth,
+                    Duration::from_secs(healthz_max_discovery_age_secs),
+                    shutdown_rx,
+                    http_access_log,
+                )
+                .await
+                {
+                    error!("Issue to start prometheus http endpoint due to {}", issue);
+                    let _ = http_failure_tx.send(issue.to_string());
+                }
+            });
+
+            // Init remote write push
+            if !remote_write_url.is_empty() {
+                match RemoteWriteClient::new(
+                    &remote_write_url,
+                    &remote_write_username,
+                    &remote_write_password,
+                    &remote_write_bearer_token,
+                    remote_write_interval_ms,
+                ) {
+                    Ok(remote_write_client) => {
+                        multi_thread_runtime.spawn(remote_write_client.run());
+                    }
+                    Err(issue) => {
+                        error!("Issue to init remote write client due to {}", issue);
+                        return Err(5);
+                    }
+                }
+            }
+
+            // Init probing until a shutdown signal is received or the exit policy is met
+            let probe_services =
+                ProbeServices::new(interval_check_ms, dedup_policy, max_probed_nodes, protocol)
+                    .with_shard(shard)
+                    .with_exit_policy(exit_policy)
+                    .with_rate_limiter(rate_limiter);
+            let probing_res = multi_thread_runtime.block_on(async {
+                tokio::select! {
+                    probing_res = init_probing(
+                        discovery_source,
+                        probe_services,
+                        Duration::from_secs(idle_series_expiry_secs),
+                    ) => probing_res,
+                    Ok(issue) = http_failure_rx, if exit_policy.http_failure => {
+                        Err(ExitError::HttpFailure(issue))
+                    }
+                    _ = shutdown_signal() => Ok(()),
+                }
+            });
+
+            if let Err(issue) = systemd::notify("STOPPING=1") {
+                error!("Issue to notify systemd of shutdown due to {}", issue);
+            }
+
+            // Probes are stopped, keep serving their last state until deregistered
+            if drain_seconds > 0 && probing_res.is_ok() {
+                READINESS.drain();
+                info!("Drain metrics endpoint for {}s", drain_seconds);
+                multi_thread_runtime.block_on(async {
+                    tokio::select! {
+                        _ = sleep(Duration::from_secs(drain_seconds)) => {}
+                        _ = shutdown_signal() => info!("Skip the end of the drain"),
+                    }
+                });
+            }
+
+            // Let in-flight scrapes finish and release the http port before exiting
+            let _ = shutdown_tx.send(());
+            if let Err(issue) = multi_thread_runtime.block_on(http_endpoint) {
+                error!("Issue to stop prometheus http endpoint due to {}", issue);
+            }
+
+            if let Err(issue) = probing_res {
+                error!("Stop probing: {}", issue);
+                shutdown_tracing();
+                return Err(issue.exit_code());
+            }
+        }
+        Err(issue) => {
+            error!(
+                "Issue starting multi-threaded tokio scheduler due to: {}",
+                issue
+            );
+            return Err(1);
+        }
+    };
+
+    shutdown_tracing();
+    Ok(())
+}
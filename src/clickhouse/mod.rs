@@ -0,0 +1,460 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, BytesMut};
+use hyper::client::HttpConnector;
+use hyper::{Body, Client as HttpClient, Method, Request, StatusCode};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::{debug, info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const HEALTH_QUERY: &str = "SELECT 1";
+// Header holding the code of the exception raised by a query
+const EXCEPTION_CODE_HEADER: &str = "x-clickhouse-exception-code";
+
+const CLIENT_HELLO: u64 = 0;
+const SERVER_HELLO: u64 = 0;
+const SERVER_EXCEPTION: u64 = 2;
+// Revision before the timezone was added to the server hello
+const PROTOCOL_REVISION: u64 = 54_057;
+// Guard against allocating a corrupted string size
+const MAX_STRING_SIZE: u64 = 64 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// Credentials and native port of the clickhouse nodes, only set once from main
+static CLICKHOUSE_CONFIG: OnceLock<ClickhouseConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+struct ClickhouseConfig {
+    username: String,
+    password: String,
+    // 0 to skip the native protocol handshake
+    native_port: u16,
+}
+
+impl Default for ClickhouseConfig {
+    fn default() -> Self {
+        ClickhouseConfig {
+            username: "default".to_string(),
+            password: "".to_string(),
+            native_port: 0,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum ClickhouseClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Invalid socket {0}.")]
+    InvalidSocket(String),
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("Exception {code} on {cmd_type}: {message}")]
+    Exception {
+        cmd_type: String,
+        code: String,
+        message: String,
+    },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Set the credentials and the native port used on clickhouse nodes
+///
+/// # Arguments
+///
+/// * `username` - user running the health query
+/// * `password` - password of the user
+/// * `native_port` - port of the native protocol, 0 to skip the handshake
+///
+pub fn set_config(username: &str, password: &str, native_port: u16) -> Result<(), String> {
+    info!("Probe clickhouse nodes as {}", username);
+    CLICKHOUSE_CONFIG
+        .set(ClickhouseConfig {
+            username: username.to_string(),
+            password: password.to_string(),
+            native_port,
+        })
+        .map_err(|_| "Clickhouse config is already initialized".to_string())
+}
+
+fn put_varint(buffer: &mut BytesMut, mut value: u64) {
+    while value >= 0x80 {
+        buffer.put_u8(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buffer.put_u8(value as u8);
+}
+
+fn put_string(buffer: &mut BytesMut, value: &str) {
+    put_varint(buffer, value.len() as u64);
+    buffer.put_slice(value.as_bytes());
+}
+
+/// Encode the client hello packet
+///
+/// # Arguments
+///
+/// * `username` - user authenticated
+/// * `password` - password of the user
+///
+fn encode_hello(username: &str, password: &str) -> Vec<u8> {
+    let mut packet = BytesMut::new();
+    put_varint(&mut packet, CLIENT_HELLO);
+    put_string(&mut packet, "probes");
+    // Client major and minor versions
+    put_varint(&mut packet, 1);
+    put_varint(&mut packet, 0);
+    put_varint(&mut packet, PROTOCOL_REVISION);
+    put_string(&mut packet, "default");
+    put_string(&mut packet, username);
+    put_string(&mut packet, password);
+    packet.to_vec()
+}
+
+/// Native protocol reader over a buffer filled from the stream
+struct NativeReader<'a> {
+    stream: &'a mut TcpStream,
+    buffer: BytesMut,
+}
+
+impl NativeReader<'_> {
+    async fn fill(&mut self, len: usize) -> Result<(), ClickhouseClientError> {
+        while self.buffer.len() < len {
+            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                return Err(ClickhouseClientError::InvalidResponse(
+                    "truncated packet".to_string(),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    async fn varint(&mut self) -> Result<u64, ClickhouseClientError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            self.fill(1).await?;
+            let byte = self.buffer.get_u8();
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(ClickhouseClientError::InvalidResponse(
+            "varint overflow".to_string(),
+        ))
+    }
+
+    async fn string(&mut self) -> Result<String, ClickhouseClientError> {
+        let len = self.varint().await?;
+        if len > MAX_STRING_SIZE {
+            return Err(ClickhouseClientError::InvalidResponse(format!(
+                "string of {len} bytes"
+            )));
+        }
+        self.fill(len as usize).await?;
+        let value = self.buffer.split_to(len as usize);
+        Ok(String::from_utf8_lossy(&value).to_string())
+    }
+
+    async fn i32_le(&mut self) -> Result<i32, ClickhouseClientError> {
+        self.fill(4).await?;
+        Ok(self.buffer.get_i32_le())
+    }
+}
+
+/// Run a step of the probe within the timeout
+///
+/// # Arguments
+///
+/// * `step` - the step of the probe
+///
+/// # Return
+///
+/// * Result of the step and its response time
+///
+async fn timed(
+    step: impl std::future::Future<Output = Result<(), ClickhouseClientError>>,
+) -> (Result<(), ClickhouseClientError>, Duration) {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(TIMEOUT, step).await {
+        Ok(result) => result,
+        Err(_timeout_elapsed) => Err(ClickhouseClientError::from(_timeout_elapsed)),
+    };
+    (result, start.elapsed().min(TIMEOUT))
+}
+
+/// Create a client probing a clickhouse node
+///
+/// Http connections are kept alive by the http client, a new native
+/// connection is opened on each probe
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node
+/// * `addr` - ip:port of the http interface of the node
+///
+pub fn connect(cluster_name: &str, addr: &str) -> Result<Client, ClickhouseClientError> {
+    let config = CLICKHOUSE_CONFIG.get().cloned().unwrap_or_default();
+    let native_addr = match config.native_port {
+        0 => None,
+        native_port => {
+            let mut socket_addr: SocketAddr = addr
+                .parse()
+                .map_err(|_| ClickhouseClientError::InvalidSocket(addr.to_string()))?;
+            socket_addr.set_port(native_port);
+            Some(socket_addr)
+        }
+    };
+    Ok(Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        native_addr,
+        config,
+        http_client: HttpClient::new(),
+    })
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    native_addr: Option<SocketAddr>,
+    config: ClickhouseConfig,
+    http_client: HttpClient<HttpConnector>,
+}
+
+impl Client {
+    /// Probe action
+    /// * run the health query on the http interface
+    /// * do one native protocol handshake if a native port is set
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), ClickhouseClientError> {
+        let query = timed(self.query()).await;
+        self.record("query", query)?;
+        if let Some(native_addr) = self.native_addr {
+            let native_handshake = timed(self.native_handshake(native_addr)).await;
+            self.record("native_handshake", native_handshake)?;
+        }
+        Ok(())
+    }
+
+    /// Export the status and the response time of a step of the probe
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string representation of the step
+    /// * `step` - result of the step and its response time
+    ///
+    fn record(
+        &mut self,
+        cmd_type: &str,
+        (result, elapsed): (Result<(), ClickhouseClientError>, Duration),
+    ) -> Result<(), ClickhouseClientError> {
+        let status = match &result {
+            Ok(_) => "OK".to_string(),
+            Err(ClickhouseClientError::Exception { code, .. }) => code.clone(),
+            Err(ClickhouseClientError::Timeout { .. }) => "Timeout".to_string(),
+            Err(_) => "Error".to_string(),
+        };
+        self.request_metrics.count_request(&status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+        result
+    }
+
+    /// Run the health query on the http interface
+    async fn query(&self) -> Result<(), ClickhouseClientError> {
+        debug!("Query clickhouse: {} {}", self.addr, HEALTH_QUERY);
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{}/", self.addr))
+            .header("x-clickhouse-user", &self.config.username)
+            .header("x-clickhouse-key", &self.config.password)
+            .body(Body::from(HEALTH_QUERY))?;
+        let response = self.http_client.request(request).await?;
+        let status = response.status();
+        let code = response
+            .headers()
+            .get(EXCEPTION_CODE_HEADER)
+            .and_then(|code| code.to_str().ok())
+            .map(str::to_string);
+        let bytes = hyper::body::to_bytes(response.into_body()).await?;
+        if status != StatusCode::OK || code.is_some() {
+            return Err(ClickhouseClientError::Exception {
+                cmd_type: "query".to_string(),
+                code: code.unwrap_or_else(|| status.as_str().to_string()),
+                message: String::from_utf8_lossy(&bytes).trim().to_string(),
+            });
+        }
+        if bytes.as_ref() != b"1\n" {
+            return Err(ClickhouseClientError::InvalidResponse(
+                String::from_utf8_lossy(&bytes).to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Send the client hello and read the server hello on the native port
+    async fn native_handshake(&self, native_addr: SocketAddr) -> Result<(), ClickhouseClientError> {
+        let mut stream = TcpStream::connect(native_addr).await?;
+        stream
+            .write_all(&encode_hello(&self.config.username, &self.config.password))
+            .await?;
+        let mut reader = NativeReader {
+            stream: &mut stream,
+            buffer: BytesMut::with_capacity(256),
+        };
+        match reader.varint().await? {
+            SERVER_HELLO => {
+                let name = reader.string().await?;
+                debug!("Native handshake with {} on {}", name, native_addr);
+                Ok(())
+            }
+            SERVER_EXCEPTION => {
+                let code = reader.i32_le().await?;
+                let _name = reader.string().await?;
+                let message = reader.string().await?;
+                Err(ClickhouseClientError::Exception {
+                    cmd_type: "native_handshake".to_string(),
+                    code: code.to_string(),
+                    message,
+                })
+            }
+            packet => Err(ClickhouseClientError::InvalidResponse(format!(
+                "unexpected packet {packet}"
+            ))),
+        }
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use wiremock::matchers::{body_string, method};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::clickhouse::{connect, encode_hello, put_string, put_varint, ClickhouseClientError};
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+
+    #[test]
+    fn varint() {
+        let mut buffer = BytesMut::new();
+        put_varint(&mut buffer, 54_057);
+        assert_eq!(vec![0xa9, 0xa6, 0x03], buffer.to_vec());
+    }
+
+    #[test]
+    fn hello() {
+        let hello = encode_hello("default", "");
+        assert_eq!(
+            b"\x00\x06probes\x01\x00\xa9\xa6\x03\x07default\x07default\x00".to_vec(),
+            hello
+        );
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(body_string("SELECT 1"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("1\n"))
+            .mount(&mock_server)
+            .await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let native_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 256];
+            socket.read(&mut buffer).await.unwrap();
+            let mut hello = BytesMut::new();
+            put_varint(&mut hello, 0);
+            put_string(&mut hello, "ClickHouse");
+            socket.write_all(&hello).await.unwrap();
+        });
+
+        let socket = mock_server.address().to_string();
+        let mut client = connect("clickhouse_probe", &socket).unwrap();
+        client.native_addr = Some(format!("127.0.0.1:{native_port}").parse().unwrap());
+        client.probe().await.unwrap();
+        for cmd_type in ["query", "native_handshake"] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&["clickhouse_probe", &socket, "OK", cmd_type])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_exception() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(
+                ResponseTemplate::new(500)
+                    .insert_header("X-ClickHouse-Exception-Code", "516")
+                    .set_body_string("Code: 516. DB::Exception: Authentication failed"),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let socket = mock_server.address().to_string();
+        let mut client = connect("clickhouse_probe_exception", &socket).unwrap();
+        assert!(matches!(
+            client.probe().await,
+            Err(ClickhouseClientError::Exception { .. })
+        ));
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&[
+                    "clickhouse_probe_exception",
+                    &socket,
+                    "516",
+                    "query"
+                ])
+                .unwrap()
+                .get()
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+
+use tokio::time::{sleep, Duration, Instant};
+
+// Future returned by a clock sleep
+pub type Sleep = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+// Source of time, allows to simulate time in tests
+pub trait Clock: Send + Sync {
+    /// Return the current time
+    fn now(&self) -> Instant;
+
+    /// Wait until the given duration has elapsed
+    fn sleep(&self, duration: Duration) -> Sleep;
+}
+
+// Clock relying on tokio time
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioClock;
+
+impl Clock for TokioClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) -> Sleep {
+        Box::pin(sleep(duration))
+    }
+}
+
+// Clock only moving forward when advanced or slept on
+// Clones share the same time
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<Instant>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        ManualClock::new()
+    }
+}
+
+impl ManualClock {
+    /// Returns a manual clock starting at the current time
+    pub fn new() -> ManualClock {
+        ManualClock {
+            now: Arc::new(Mutex::new(Instant::now())),
+        }
+    }
+
+    /// Move the clock forward
+    ///
+    /// # Arguments
+    ///
+    /// * `duration` - duration to add to the current time
+    ///
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock().unwrap() += duration;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Instant {
+        *self.now.lock().unwrap()
+    }
+
+    /// Advance the clock by the duration and return immediately
+    fn sleep(&self, duration: Duration) -> Sleep {
+        self.advance(duration);
+        Box::pin(std::future::ready(()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::time::Duration;
+
+    use crate::clock::{Clock, ManualClock};
+
+    #[tokio::test]
+    async fn manual_clock() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+
+        clock.advance(Duration::from_millis(250));
+        clock.clone().sleep(Duration::from_secs(2)).await;
+
+        assert_eq!(Duration::from_millis(2250), clock.now() - start);
+    }
+}
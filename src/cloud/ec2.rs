@@ -0,0 +1,494 @@
+use std::collections::HashMap;
+use std::env;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use hyper::header::{CONTENT_TYPE, HOST};
+use hyper::{Body, Method, Request, Response, Uri};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use tracing::debug;
+
+use crate::cloud::{CloudClient, CloudError, Instance};
+use crate::probes::discovery::url_encode;
+use crate::s3::{amz_date, SigV4};
+
+pub(super) const METADATA_ENDPOINT: &str = "http://169.254.169.254";
+const API_VERSION: &str = "2016-11-15";
+// Max instances returned per page
+const MAX_RESULTS: u32 = 1000;
+// Lifetime requested for the IMDSv2 session token
+const METADATA_TOKEN_TTL_SECS: u32 = 300;
+
+/// Returns the regional endpoint of the ec2 api
+pub(super) fn endpoint(region: &str) -> String {
+    format!("https://ec2.{region}.amazonaws.com")
+}
+
+// Credentials signing the ec2 requests
+#[derive(Debug, PartialEq)]
+struct AwsCredentials {
+    access_key: String,
+    secret_key: String,
+    // Set for temporary credentials of an instance role
+    session_token: Option<String>,
+}
+
+// Element of an xml document, attributes are ignored
+#[derive(Debug, Default, PartialEq)]
+struct Element {
+    name: String,
+    text: String,
+    children: Vec<Element>,
+}
+
+impl Element {
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn child_text(&self, name: &str) -> Option<&str> {
+        self.child(name).map(|child| child.text.as_str())
+    }
+
+    /// Returns the items of a set child, empty if missing
+    fn items<'a>(&'a self, set: &str) -> impl Iterator<Item = &'a Element> {
+        self.child(set)
+            .into_iter()
+            .flat_map(|set| set.children.iter())
+            .filter(|child| child.name == "item")
+    }
+}
+
+fn unescape(text: &str) -> String {
+    text.replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Parse an xml document into its tree of elements
+///
+/// Only covers what the ec2 api returns: no cdata, no mixed content
+///
+/// # Return
+///
+/// * Element - unnamed root holding the document element
+///
+fn parse_xml(document: &str) -> Result<Element, CloudError> {
+    let invalid = |issue: &str| CloudError::InvalidResponse(format!("invalid xml, {issue}"));
+    let mut stack = vec![Element::default()];
+    let mut rest = document;
+    while let Some(start) = rest.find('<') {
+        if let Some(element) = stack.last_mut() {
+            element.text.push_str(&rest[..start]);
+        }
+        rest = &rest[start..];
+        let end_marker = if rest.starts_with("<?") {
+            "?>"
+        } else if rest.starts_with("<!--") {
+            "-->"
+        } else {
+            ">"
+        };
+        let end = rest
+            .find(end_marker)
+            .ok_or_else(|| invalid("unterminated tag"))?;
+        let tag = &rest[1..end];
+        rest = &rest[end + end_marker.len()..];
+        if end_marker != ">" || tag.starts_with('!') {
+            continue;
+        }
+
+        if let Some(name) = tag.strip_prefix('/') {
+            let mut element = stack.pop().ok_or_else(|| invalid("unexpected end tag"))?;
+            if element.name != name.trim() || stack.is_empty() {
+                return Err(invalid(&format!("unexpected end tag {name}")));
+            }
+            element.text = unescape(element.text.trim());
+            if let Some(parent) = stack.last_mut() {
+                parent.children.push(element);
+            }
+        } else {
+            let name = tag
+                .split(|c: char| c.is_whitespace() || c == '/')
+                .next()
+                .unwrap_or_default();
+            let element = Element {
+                name: name.to_string(),
+                ..Element::default()
+            };
+            match (tag.ends_with('/'), stack.last_mut()) {
+                (true, Some(parent)) => parent.children.push(element),
+                _ => stack.push(element),
+            }
+        }
+    }
+    match stack.pop() {
+        Some(root) if stack.is_empty() => Ok(root),
+        _ => Err(invalid("unclosed element")),
+    }
+}
+
+/// Parse a page of the DescribeInstances response
+///
+/// # Return
+///
+/// * Vec<Instance> - the instances of the page with a private ip
+/// * Option<String> - the token of the next page, none on the last page
+///
+fn parse_describe_instances(document: &str) -> Result<(Vec<Instance>, Option<String>), CloudError> {
+    let root = parse_xml(document)?;
+    let response = root
+        .child("DescribeInstancesResponse")
+        .ok_or_else(|| CloudError::InvalidResponse("expected DescribeInstancesResponse".into()))?;
+    let instances = response
+        .items("reservationSet")
+        .flat_map(|reservation| reservation.items("instancesSet"))
+        .filter_map(|instance| {
+            let private_ip = instance.child_text("privateIpAddress")?;
+            let tags = instance
+                .items("tagSet")
+                .filter_map(|tag| {
+                    Some((
+                        tag.child_text("key")?.to_string(),
+                        tag.child_text("value").unwrap_or_default().to_string(),
+                    ))
+                })
+                .collect::<HashMap<_, _>>();
+            Some(Instance {
+                private_ip: private_ip.to_string(),
+                tags,
+            })
+        })
+        .collect();
+    let next_token = response
+        .child_text("nextToken")
+        .filter(|next_token| !next_token.is_empty())
+        .map(str::to_string);
+    Ok((instances, next_token))
+}
+
+/// Returns the form of a DescribeInstances request of running instances with the tag
+///
+/// # Arguments
+///
+/// * `cloud_client` - client holding the tag selecting the instances
+/// * `next_token` - token of the page to fetch, the first one if none
+///
+fn describe_instances_form(cloud_client: &CloudClient, next_token: Option<&str>) -> String {
+    let tag_filter = match &cloud_client.tag_value {
+        Some(value) => (format!("tag:{}", cloud_client.tag_key), value.as_str()),
+        None => ("tag-key".to_string(), cloud_client.tag_key.as_str()),
+    };
+    let mut params = vec![
+        ("Action", "DescribeInstances".to_string()),
+        ("Version", API_VERSION.to_string()),
+        ("MaxResults", MAX_RESULTS.to_string()),
+        ("Filter.1.Name", "instance-state-name".to_string()),
+        ("Filter.1.Value.1", "running".to_string()),
+        ("Filter.2.Name", tag_filter.0),
+        ("Filter.2.Value.1", tag_filter.1.to_string()),
+    ];
+    if let Some(next_token) = next_token {
+        params.push(("NextToken", next_token.to_string()));
+    }
+    params
+        .iter()
+        .map(|(name, value)| format!("{name}={}", url_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&")
+}
+
+/// Get the credentials from the environment, or from the instance role
+/// through the instance metadata service (IMDSv2)
+async fn credentials(cloud_client: &CloudClient) -> Result<AwsCredentials, CloudError> {
+    if let (Ok(access_key), Ok(secret_key)) = (
+        env::var("AWS_ACCESS_KEY_ID"),
+        env::var("AWS_SECRET_ACCESS_KEY"),
+    ) {
+        return Ok(AwsCredentials {
+            access_key,
+            secret_key,
+            session_token: env::var("AWS_SESSION_TOKEN").ok(),
+        });
+    }
+
+    let metadata = &cloud_client.metadata_endpoint;
+    let token_uri = format!("{metadata}/latest/api/token");
+    let request = Request::builder()
+        .method(Method::PUT)
+        .uri(&token_uri)
+        .header(
+            "x-aws-ec2-metadata-token-ttl-seconds",
+            METADATA_TOKEN_TTL_SECS,
+        )
+        .body(Body::empty())?;
+    let token = read_body(&token_uri, cloud_client.client.request(request).await?).await?;
+    let get_metadata = |path: &str| {
+        let uri = format!("{metadata}/latest/meta-data/iam/security-credentials/{path}");
+        let token = &token;
+        async move {
+            let request = Request::builder()
+                .uri(&uri)
+                .header("x-aws-ec2-metadata-token", token)
+                .body(Body::empty())?;
+            read_body(&uri, cloud_client.client.request(request).await?).await
+        }
+    };
+    let roles = get_metadata("").await?;
+    let role = roles
+        .lines()
+        .next()
+        .filter(|role| !role.is_empty())
+        .ok_or_else(|| CloudError::Credentials("no instance role".to_string()))?;
+    let role_credentials: Value = serde_json::from_str(&get_metadata(role).await?)?;
+    let field = |name: &str| {
+        role_credentials
+            .get(name)
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| CloudError::Credentials(format!("missing {name} of role {role}")))
+    };
+    Ok(AwsCredentials {
+        access_key: field("AccessKeyId")?,
+        secret_key: field("SecretAccessKey")?,
+        session_token: field("Token").ok(),
+    })
+}
+
+/// Read the body of a successful response, the ec2 error message otherwise
+async fn read_body(uri: &str, response: Response<Body>) -> Result<String, CloudError> {
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await?;
+    let body = String::from_utf8_lossy(&bytes).into_owned();
+    if status.is_success() {
+        return Ok(body);
+    }
+    let message = parse_xml(&body)
+        .ok()
+        .and_then(|root| {
+            root.child("Response")?
+                .child("Errors")?
+                .child("Error")?
+                .child_text("Message")
+                .map(str::to_string)
+        })
+        .unwrap_or(body);
+    Err(CloudError::Status {
+        uri: uri.to_string(),
+        status: status.as_u16(),
+        message,
+    })
+}
+
+/// List all the pages of running instances with the tag
+pub(super) async fn list_instances(
+    cloud_client: &CloudClient,
+) -> Result<Vec<Instance>, CloudError> {
+    let credentials = credentials(cloud_client).await?;
+    let uri = format!("{}/", cloud_client.endpoint)
+        .parse::<Uri>()
+        .map_err(hyper::http::Error::from)?;
+    let host = uri.authority().map(ToString::to_string).unwrap_or_default();
+    let sigv4 = SigV4 {
+        access_key: &credentials.access_key,
+        secret_key: &credentials.secret_key,
+        region: &cloud_client.location,
+        service: "ec2",
+    };
+
+    let mut instances = Vec::new();
+    let mut next_token: Option<String> = None;
+    loop {
+        let form = describe_instances_form(cloud_client, next_token.as_deref());
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        let amz_date = amz_date(seconds);
+        let payload_hash = hex::encode(Sha256::digest(form.as_bytes()));
+        let mut headers = vec![("host", host.as_str()), ("x-amz-date", amz_date.as_str())];
+        if let Some(session_token) = &credentials.session_token {
+            headers.push(("x-amz-security-token", session_token.as_str()));
+        }
+        let authorization =
+            sigv4.authorization(&Method::POST, "/", &headers, &payload_hash, &amz_date);
+
+        debug!("Describe ec2 instances on {}", uri);
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(uri.clone())
+            .header(HOST, &host)
+            .header(
+                CONTENT_TYPE,
+                "application/x-www-form-urlencoded; charset=utf-8",
+            )
+            .header("x-amz-date", &amz_date)
+            .header("authorization", authorization);
+        if let Some(session_token) = &credentials.session_token {
+            request = request.header("x-amz-security-token", session_token);
+        }
+        let response = cloud_client
+            .client
+            .request(request.body(Body::from(form))?)
+            .await?;
+        let body = read_body(&cloud_client.endpoint, response).await?;
+        let (page, token) = parse_describe_instances(&body)?;
+        instances.extend(page);
+        match token {
+            Some(token) => next_token = Some(token),
+            None => return Ok(instances),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{body_string_contains, header, header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::cloud::ec2::{describe_instances_form, parse_describe_instances, parse_xml};
+    use crate::cloud::{CloudClient, CloudError, CloudProvider};
+
+    fn instance(private_ip: &str, service: &str) -> String {
+        format!(
+            "<item><instanceId>i-{service}</instanceId><privateIpAddress>{private_ip}</privateIpAddress>\
+            <networkInterfaceSet><item><privateIpAddress>192.168.0.1</privateIpAddress></item>\
+            </networkInterfaceSet><tagSet><item><key>service</key><value>{service}</value></item>\
+            </tagSet></item>"
+        )
+    }
+
+    fn page(instances: &[String], next_token: Option<&str>) -> String {
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+            <DescribeInstancesResponse xmlns=\"http://ec2.amazonaws.com/doc/2016-11-15/\">\
+            <requestId>r-1</requestId><reservationSet><item><reservationId>r-1</reservationId>\
+            <groupSet/><instancesSet>{}</instancesSet></item></reservationSet>{}\
+            </DescribeInstancesResponse>",
+            instances.join(""),
+            next_token
+                .map(|next_token| format!("<nextToken>{next_token}</nextToken>"))
+                .unwrap_or_default()
+        )
+    }
+
+    #[test]
+    fn xml() {
+        let root =
+            parse_xml("<?xml version=\"1.0\"?><a x=\"1\"><b>1 &amp; 2</b><!-- c --><c/></a>")
+                .unwrap();
+        let a = root.child("a").unwrap();
+        assert_eq!(Some("1 & 2"), a.child_text("b"));
+        assert_eq!(Some(""), a.child_text("c"));
+        assert!(parse_xml("<a><b></a>").is_err());
+        assert!(parse_xml("<a>").is_err());
+        assert!(parse_xml("</a>").is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let (instances, next_token) = parse_describe_instances(&page(
+            &[
+                instance("10.0.0.1", "sessions"),
+                instance("10.0.0.2", "cache"),
+            ],
+            Some("token"),
+        ))
+        .unwrap();
+        assert_eq!(2, instances.len());
+        assert_eq!("10.0.0.1", instances[0].private_ip);
+        assert_eq!(
+            Some(&"sessions".to_string()),
+            instances[0].tags.get("service")
+        );
+        assert_eq!(Some("token".to_string()), next_token);
+
+        let (instances, next_token) = parse_describe_instances(&page(&[], None)).unwrap();
+        assert!(instances.is_empty());
+        assert_eq!(None, next_token);
+        assert!(matches!(
+            parse_describe_instances("<Response/>"),
+            Err(CloudError::InvalidResponse(_))
+        ));
+    }
+
+    #[test]
+    fn form() {
+        let cloud_client =
+            CloudClient::new(CloudProvider::Ec2, "service", 6379, "eu-west-1", "").unwrap();
+        assert_eq!(
+            "Action=DescribeInstances&Version=2016-11-15&MaxResults=1000\
+            &Filter.1.Name=instance-state-name&Filter.1.Value.1=running\
+            &Filter.2.Name=tag-key&Filter.2.Value.1=service",
+            describe_instances_form(&cloud_client, None)
+        );
+        let cloud_client = CloudClient::new(
+            CloudProvider::Ec2,
+            "service=sessions",
+            6379,
+            "eu-west-1",
+            "",
+        )
+        .unwrap();
+        assert!(describe_instances_form(&cloud_client, Some("a/b"))
+            .ends_with("&Filter.2.Name=tag%3Aservice&Filter.2.Value.1=sessions&NextToken=a%2Fb"));
+    }
+
+    #[tokio::test]
+    async fn list_nodes() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("PUT"))
+            .and(path("/latest/api/token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("imds-token"))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/latest/meta-data/iam/security-credentials/"))
+            .and(header("x-aws-ec2-metadata-token", "imds-token"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("probes"))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/latest/meta-data/iam/security-credentials/probes"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "{\"AccessKeyId\": \"accesskey\", \"SecretAccessKey\": \"secretkey\", \
+                \"Token\": \"token\"}",
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/"))
+            .and(header_exists("authorization"))
+            .and(body_string_contains("NextToken=page2"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(page(&[instance("10.0.0.2", "sessions")], None)),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/"))
+            .and(header_exists("authorization"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(page(&[instance("10.0.0.1", "sessions")], Some("page2"))),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let mut cloud_client = CloudClient::new(
+            CloudProvider::Ec2,
+            "service",
+            6379,
+            "eu-west-1",
+            &mock_server.uri(),
+        )
+        .unwrap();
+        cloud_client.metadata_endpoint = mock_server.uri();
+        let service_nodes = cloud_client.list_nodes().await.unwrap();
+        assert_eq!(vec!["sessions".to_string()], service_nodes.services);
+        assert!(service_nodes.nodes.contains_key("sessions:10.0.0.1:6379"));
+        assert!(service_nodes.nodes.contains_key("sessions:10.0.0.2:6379"));
+    }
+}
@@ -0,0 +1,255 @@
+use std::env;
+
+use hyper::header::AUTHORIZATION;
+use hyper::{Body, Request, Response};
+use serde_json::Value;
+use tracing::debug;
+
+use crate::cloud::{CloudClient, CloudError, Instance};
+use crate::probes::discovery::url_encode;
+
+pub(super) const ENDPOINT: &str = "https://compute.googleapis.com/compute/v1";
+pub(super) const METADATA_ENDPOINT: &str = "http://metadata.google.internal";
+// Max instances returned per page
+const MAX_RESULTS: u32 = 500;
+
+/// Read the json body of a successful response, the gce error message otherwise
+async fn read_json(uri: &str, response: Response<Body>) -> Result<Value, CloudError> {
+    let status = response.status();
+    let bytes = hyper::body::to_bytes(response.into_body()).await?;
+    if status.is_success() {
+        return Ok(serde_json::from_slice(&bytes)?);
+    }
+    let message = serde_json::from_slice::<Value>(&bytes)
+        .ok()
+        .and_then(|error| Some(error.get("error")?.get("message")?.as_str()?.to_string()))
+        .unwrap_or_else(|| String::from_utf8_lossy(&bytes).into_owned());
+    Err(CloudError::Status {
+        uri: uri.to_string(),
+        status: status.as_u16(),
+        message,
+    })
+}
+
+/// Get an access token from the environment, or from the default service account
+/// through the instance metadata server
+async fn access_token(cloud_client: &CloudClient) -> Result<String, CloudError> {
+    if let Ok(access_token) = env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+        return Ok(access_token);
+    }
+
+    let uri = format!(
+        "{}/computeMetadata/v1/instance/service-accounts/default/token",
+        cloud_client.metadata_endpoint
+    );
+    let request = Request::builder()
+        .uri(&uri)
+        .header("metadata-flavor", "Google")
+        .body(Body::empty())?;
+    let token = read_json(&uri, cloud_client.client.request(request).await?).await?;
+    token
+        .get("access_token")
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .ok_or_else(|| CloudError::Credentials("missing access_token".to_string()))
+}
+
+/// Returns the filter of running instances with the label
+fn instances_filter(cloud_client: &CloudClient) -> String {
+    let label_filter = match &cloud_client.tag_value {
+        Some(value) => format!("labels.{} = \"{value}\"", cloud_client.tag_key),
+        None => format!("labels.{}:*", cloud_client.tag_key),
+    };
+    format!("(status = RUNNING) AND ({label_filter})")
+}
+
+/// Parse a page of the aggregated list of instances
+///
+/// # Return
+///
+/// * Vec<Instance> - the instances of the page with a private ip, from all zones
+/// * Option<String> - the token of the next page, none on the last page
+///
+fn parse_aggregated_list(page: &Value) -> (Vec<Instance>, Option<String>) {
+    let instances = page
+        .get("items")
+        .and_then(Value::as_object)
+        .into_iter()
+        .flat_map(|scopes| scopes.values())
+        .filter_map(|scope| scope.get("instances").and_then(Value::as_array))
+        .flatten()
+        .filter_map(|instance| {
+            let private_ip = instance
+                .get("networkInterfaces")?
+                .get(0)?
+                .get("networkIP")?
+                .as_str()?;
+            let tags = instance
+                .get("labels")
+                .and_then(Value::as_object)
+                .into_iter()
+                .flatten()
+                .filter_map(|(key, value)| Some((key.clone(), value.as_str()?.to_string())))
+                .collect();
+            Some(Instance {
+                private_ip: private_ip.to_string(),
+                tags,
+            })
+        })
+        .collect();
+    let next_token = page
+        .get("nextPageToken")
+        .and_then(Value::as_str)
+        .filter(|next_token| !next_token.is_empty())
+        .map(str::to_string);
+    (instances, next_token)
+}
+
+/// List all the pages of running instances with the label
+pub(super) async fn list_instances(
+    cloud_client: &CloudClient,
+) -> Result<Vec<Instance>, CloudError> {
+    let access_token = access_token(cloud_client).await?;
+    let base_uri = format!(
+        "{}/projects/{}/aggregated/instances?filter={}&maxResults={MAX_RESULTS}\
+        &returnPartialSuccess=true",
+        cloud_client.endpoint,
+        url_encode(&cloud_client.location),
+        url_encode(&instances_filter(cloud_client))
+    );
+
+    let mut instances = Vec::new();
+    let mut next_token: Option<String> = None;
+    loop {
+        let uri = match &next_token {
+            Some(next_token) => format!("{base_uri}&pageToken={}", url_encode(next_token)),
+            None => base_uri.clone(),
+        };
+        debug!("List gce instances on {}", uri);
+        let request = Request::builder()
+            .uri(&uri)
+            .header(AUTHORIZATION, format!("Bearer {access_token}"))
+            .body(Body::empty())?;
+        let page = read_json(&uri, cloud_client.client.request(request).await?).await?;
+        let (page_instances, token) = parse_aggregated_list(&page);
+        instances.extend(page_instances);
+        match token {
+            Some(token) => next_token = Some(token),
+            None => return Ok(instances),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::cloud::gce::{instances_filter, parse_aggregated_list};
+    use crate::cloud::{CloudClient, CloudError, CloudProvider};
+
+    fn page(private_ip: &str, next_token: Option<&str>) -> serde_json::Value {
+        json!({
+            "items": {
+                "zones/europe-west1-b": {
+                    "instances": [{
+                        "name": "sessions-1",
+                        "status": "RUNNING",
+                        "labels": {"service": "sessions", "probe-protocol": "redis"},
+                        "networkInterfaces": [{"networkIP": private_ip}]
+                    }]
+                },
+                "zones/europe-west1-c": {
+                    "warning": {"code": "NO_RESULTS_ON_PAGE"}
+                }
+            },
+            "nextPageToken": next_token
+        })
+    }
+
+    #[test]
+    fn filter() {
+        let cloud_client =
+            CloudClient::new(CloudProvider::Gce, "service", 6379, "project", "").unwrap();
+        assert_eq!(
+            "(status = RUNNING) AND (labels.service:*)",
+            instances_filter(&cloud_client)
+        );
+        let cloud_client =
+            CloudClient::new(CloudProvider::Gce, "service=sessions", 6379, "project", "").unwrap();
+        assert_eq!(
+            "(status = RUNNING) AND (labels.service = \"sessions\")",
+            instances_filter(&cloud_client)
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let (instances, next_token) = parse_aggregated_list(&page("10.0.0.1", Some("page2")));
+        assert_eq!(1, instances.len());
+        assert_eq!("10.0.0.1", instances[0].private_ip);
+        assert_eq!(
+            Some(&"redis".to_string()),
+            instances[0].tags.get("probe-protocol")
+        );
+        assert_eq!(Some("page2".to_string()), next_token);
+        assert_eq!(None, parse_aggregated_list(&page("10.0.0.1", None)).1);
+    }
+
+    #[tokio::test]
+    async fn list_nodes() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path(
+                "/computeMetadata/v1/instance/service-accounts/default/token",
+            ))
+            .and(header("metadata-flavor", "Google"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(json!({"access_token": "token", "expires_in": 3599})),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/projects/project/aggregated/instances"))
+            .and(query_param("pageToken", "page2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page("10.0.0.2", None)))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/projects/project/aggregated/instances"))
+            .and(header("authorization", "Bearer token"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(page("10.0.0.1", Some("page2"))))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/projects/denied/aggregated/instances"))
+            .respond_with(ResponseTemplate::new(403).set_body_json(
+                json!({"error": {"code": 403, "message": "Required 'compute.instances.list'"}}),
+            ))
+            .mount(&mock_server)
+            .await;
+
+        let mut cloud_client = CloudClient::new(
+            CloudProvider::Gce,
+            "service",
+            6379,
+            "project",
+            &mock_server.uri(),
+        )
+        .unwrap();
+        cloud_client.metadata_endpoint = mock_server.uri();
+        let service_nodes = cloud_client.list_nodes().await.unwrap();
+        assert_eq!(vec!["sessions".to_string()], service_nodes.services);
+        assert_eq!(2, service_nodes.nodes.len());
+        assert!(service_nodes.nodes.contains_key("sessions:10.0.0.2:6379"));
+
+        cloud_client.location = "denied".to_string();
+        assert!(matches!(
+            cloud_client.list_nodes().await,
+            Err(CloudError::Status { status: 403, message, .. })
+                if message == "Required 'compute.instances.list'"
+        ));
+    }
+}
@@ -0,0 +1,333 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::time::error::Elapsed;
+use tokio::time::timeout;
+use tracing::{info, warn};
+
+use crate::probes::discovery::{
+    Discovery, DiscoveryFuture, PollInterval, ServiceNode, ServiceNodes,
+};
+use crate::probes::http_client::{shared_client, HttpClient};
+use crate::probes::protocol::Protocol;
+
+mod ec2;
+mod gce;
+
+// Instance tag or label declaring the protocol used to probe the instance
+const PROBE_PROTOCOL_TAG: &str = "probe-protocol";
+// Max time to list all the pages of instances
+const LIST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Error, Debug)]
+pub enum CloudError {
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Issue query: {uri} - status code: {status} - {message}")]
+    Status {
+        uri: String,
+        status: u16,
+        message: String,
+    },
+    #[error("Invalid json: {source}")]
+    Json {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("No credentials: {0}")]
+    Credentials(String),
+    #[error("Timeout listing instances")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+// Cloud provider listing the instances
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CloudProvider {
+    // Aws EC2 DescribeInstances
+    Ec2,
+    // Google compute engine instances aggregated list
+    Gce,
+}
+
+impl fmt::Display for CloudProvider {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CloudProvider::Ec2 => write!(f, "ec2"),
+            CloudProvider::Gce => write!(f, "gce"),
+        }
+    }
+}
+
+// Running instance listed by the cloud api
+#[derive(Debug, PartialEq)]
+struct Instance {
+    private_ip: String,
+    // Tags on ec2, labels on gce
+    tags: HashMap<String, String>,
+}
+
+// List running instances with a tag to derive the nodes to probe
+#[derive(Debug)]
+pub struct CloudClient {
+    provider: CloudProvider,
+    // Tag key naming the cluster of the instance, only instances with that tag are probed
+    tag_key: String,
+    // Restrict the instances to the ones with that tag value, any value if none
+    tag_value: Option<String>,
+    // Port probed on the private ip of the instances
+    port: u16,
+    // Region on ec2, project on gce
+    location: String,
+    // Base url of the cloud api
+    endpoint: String,
+    // Base url of the instance metadata service providing credentials
+    metadata_endpoint: String,
+    client: Arc<HttpClient>,
+}
+
+impl CloudClient {
+    /// Returns a cloud client
+    ///
+    /// # Arguments
+    ///
+    /// * `provider` - cloud provider listing the instances
+    /// * `tag` - key or key=value of the tag selecting the instances, its value is the cluster name
+    /// * `port` - port probed on the instances
+    /// * `location` - region on ec2, project on gce
+    /// * `endpoint` - base url of the cloud api, the provider default one if empty
+    ///
+    pub fn new(
+        provider: CloudProvider,
+        tag: &str,
+        port: u16,
+        location: &str,
+        endpoint: &str,
+    ) -> Result<Self, String> {
+        let (tag_key, tag_value) = match tag.split_once('=') {
+            Some((key, value)) => (key, Some(value.to_string())),
+            None => (tag, None),
+        };
+        if tag_key.is_empty() {
+            return Err("No instance tag".to_string());
+        }
+        if port == 0 {
+            return Err("No port to probe on instances".to_string());
+        }
+        if location.is_empty() {
+            return Err(match provider {
+                CloudProvider::Ec2 => "No ec2 region".to_string(),
+                CloudProvider::Gce => "No gce project".to_string(),
+            });
+        }
+        let (default_endpoint, metadata_endpoint) = match provider {
+            CloudProvider::Ec2 => (ec2::endpoint(location), ec2::METADATA_ENDPOINT),
+            CloudProvider::Gce => (gce::ENDPOINT.to_string(), gce::METADATA_ENDPOINT),
+        };
+        let endpoint = match endpoint {
+            "" => default_endpoint,
+            endpoint => endpoint.trim_end_matches('/').to_string(),
+        };
+        info!(
+            "List {} instances with tag {} from {}",
+            provider, tag, endpoint
+        );
+
+        Ok(CloudClient {
+            provider,
+            tag_key: tag_key.to_string(),
+            tag_value,
+            port,
+            location: location.to_string(),
+            endpoint,
+            metadata_endpoint: metadata_endpoint.to_string(),
+            client: shared_client(),
+        })
+    }
+
+    /// List all the pages of running instances with the tag
+    ///
+    /// # Return
+    ///
+    /// * ServiceNodes - one node per instance, named after its tag value
+    ///
+    pub async fn list_nodes(&self) -> Result<ServiceNodes, CloudError> {
+        let instances = match self.provider {
+            CloudProvider::Ec2 => timeout(LIST_TIMEOUT, ec2::list_instances(self)).await??,
+            CloudProvider::Gce => timeout(LIST_TIMEOUT, gce::list_instances(self)).await??,
+        };
+        Ok(self.instances_to_nodes(instances))
+    }
+
+    /// Create the nodes of the instances matching the tag
+    ///
+    /// # Arguments
+    ///
+    /// * `instances` - running instances listed by the cloud api
+    ///
+    fn instances_to_nodes(&self, instances: Vec<Instance>) -> ServiceNodes {
+        let mut services = BTreeSet::new();
+        let mut nodes = HashMap::new();
+        for instance in instances {
+            let service_name = match instance.tags.get(&self.tag_key) {
+                Some(service_name)
+                    if self
+                        .tag_value
+                        .as_ref()
+                        .map_or(true, |value| value == service_name) =>
+                {
+                    service_name.clone()
+                }
+                _ => continue,
+            };
+            let protocol =
+                instance.tags.get(PROBE_PROTOCOL_TAG).and_then(|protocol| {
+                    match protocol.parse::<Protocol>() {
+                        Ok(protocol) => Some(protocol),
+                        Err(issue) => {
+                            warn!("Invalid probe protocol: {}", issue);
+                            None
+                        }
+                    }
+                });
+            let service_node = ServiceNode {
+                service_name: service_name.clone(),
+                ip: instance.private_ip,
+                port: self.port,
+                protocol,
+            };
+            nodes.insert(service_node.to_string(), service_node);
+            services.insert(service_name);
+        }
+        ServiceNodes {
+            index: 0,
+            services: services.into_iter().collect(),
+            nodes,
+        }
+    }
+}
+
+// Discovery listing the cloud instances again on each interval
+#[derive(Debug)]
+pub struct CloudDiscovery {
+    cloud_client: CloudClient,
+    poll_interval: PollInterval,
+}
+
+impl CloudDiscovery {
+    /// Returns a CloudDiscovery
+    ///
+    /// # Arguments
+    ///
+    /// * `cloud_client` - a cloud client
+    /// * `refresh_interval` - interval between each listing of the instances
+    ///
+    pub fn new(cloud_client: CloudClient, refresh_interval: Duration) -> Self {
+        CloudDiscovery {
+            cloud_client,
+            poll_interval: PollInterval::new(refresh_interval),
+        }
+    }
+}
+
+impl fmt::Display for CloudDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cloud instances")
+    }
+}
+
+impl Discovery for CloudDiscovery {
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+        Box::pin(async move {
+            self.poll_interval.tick().await;
+            self.cloud_client.list_nodes().await.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::cloud::{CloudClient, CloudProvider, Instance};
+    use crate::probes::protocol::Protocol;
+
+    fn instance(private_ip: &str, tags: &[(&str, &str)]) -> Instance {
+        Instance {
+            private_ip: private_ip.to_string(),
+            tags: tags
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect::<HashMap<_, _>>(),
+        }
+    }
+
+    #[test]
+    fn new() {
+        let cloud_client = CloudClient::new(
+            CloudProvider::Ec2,
+            "service=sessions",
+            6379,
+            "eu-west-1",
+            "",
+        )
+        .unwrap();
+        assert_eq!("service", cloud_client.tag_key);
+        assert_eq!(Some("sessions".to_string()), cloud_client.tag_value);
+        assert_eq!("https://ec2.eu-west-1.amazonaws.com", cloud_client.endpoint);
+
+        assert!(CloudClient::new(CloudProvider::Ec2, "", 6379, "eu-west-1", "").is_err());
+        assert!(CloudClient::new(CloudProvider::Ec2, "service", 0, "eu-west-1", "").is_err());
+        assert!(CloudClient::new(CloudProvider::Gce, "service", 6379, "", "").is_err());
+    }
+
+    #[test]
+    fn instances_to_nodes() {
+        let cloud_client =
+            CloudClient::new(CloudProvider::Gce, "service", 6379, "project", "").unwrap();
+        let service_nodes = cloud_client.instances_to_nodes(vec![
+            instance(
+                "10.0.0.1",
+                &[("service", "sessions"), ("probe-protocol", "redis")],
+            ),
+            instance(
+                "10.0.0.2",
+                &[("service", "cache"), ("probe-protocol", "http")],
+            ),
+            instance("10.0.0.3", &[("env", "prod")]),
+        ]);
+        assert_eq!(
+            vec!["cache".to_string(), "sessions".to_string()],
+            service_nodes.services
+        );
+        assert_eq!(2, service_nodes.nodes.len());
+        let service_node = service_nodes.nodes.get("sessions:10.0.0.1:6379").unwrap();
+        assert_eq!(Some(Protocol::Redis), service_node.protocol);
+        let service_node = service_nodes.nodes.get("cache:10.0.0.2:6379").unwrap();
+        assert_eq!(None, service_node.protocol);
+
+        let cloud_client =
+            CloudClient::new(CloudProvider::Gce, "service=cache", 6379, "project", "").unwrap();
+        let service_nodes = cloud_client.instances_to_nodes(vec![
+            instance("10.0.0.1", &[("service", "sessions")]),
+            instance("10.0.0.2", &[("service", "cache")]),
+        ]);
+        assert_eq!(vec!["cache".to_string()], service_nodes.services);
+        assert_eq!(1, service_nodes.nodes.len());
+    }
+}
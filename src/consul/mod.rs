@@ -1,45 +1,146 @@
 use std::collections::HashMap;
 use std::fmt;
+use std::sync::Arc;
+use std::time::Instant;
 
-use hyper::client::HttpConnector;
-use hyper::{Client, Uri};
-use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use futures::{stream, StreamExt};
+use hyper::{Body, Request, Uri};
 use serde_json::{Map, Value};
+use thiserror::Error;
 use tracing::log::warn;
-use tracing::{debug, error};
-
-// Represent a consul client
-#[derive(Debug, Clone)]
-pub struct ConsulClient {
-    // The fqdn of the consul agent to query
-    fqdn: String,
-    client: Client<HttpsConnector<HttpConnector>>,
+use tracing::{debug, error, info, instrument};
+
+use crate::probes::discovery::{
+    Discovery, DiscoveryError, DiscoveryFuture, ServiceNode, ServiceNodes,
+};
+use crate::probes::http_client::{shared_client, HttpClient};
+use crate::probes::prometheus::{
+    CONSUL_DISCOVERY_RATE, CONSUL_SERVICE_FAILURES, CONSUL_SERVICE_INDEX_RESETS,
+    CONSUL_WATCH_DURATION, CONSUL_WATCH_INDEX, CONSUL_WATCH_INDEX_RESETS,
+};
+use crate::probes::protocol::Protocol;
+use crate::probes::state::PROBER_STATE;
+use crate::tcp::{self, TcpClientError};
+use crate::token_bucket::adaptive::AdaptiveRate;
+use crate::token_bucket::{RateLimiter, RateLimiterKind};
+
+// Service meta listing additional ports to probe (comma separated)
+const PROBE_PORTS_META: &str = "probe-ports";
+// Service tag prefix declaring an additional port to probe
+const PROBE_PORT_TAG_PREFIX: &str = "probe-port=";
+// Service meta declaring the protocol used to probe the service
+const PROBE_PROTOCOL_META: &str = "probe-protocol";
+// Service tag prefix declaring the protocol used to probe the service
+const PROBE_PROTOCOL_TAG_PREFIX: &str = "probe-protocol=";
+// Max wait of the catalog watch for a change
+const CATALOG_WATCH_WAIT: &str = "5m";
+// Max wait of a service query for its index to advance, the cached nodes are kept otherwise
+const SERVICE_INDEX_WAIT: &str = "10ms";
+// Max number of services whose nodes are listed at the same time
+const SERVICE_FETCH_CONCURRENCY: usize = 32;
+
+#[derive(Error, Debug)]
+pub enum ConsulError {
+    #[error("Invalid uri: {source}")]
+    Uri {
+        #[from]
+        source: hyper::http::uri::InvalidUri,
+    },
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Issue query: {uri} - status code: {status}")]
+    Status { uri: String, status: u16 },
+    #[error("Invalid x-consul-index header: {source}")]
+    Index {
+        #[from]
+        source: std::num::ParseIntError,
+    },
+    #[error("Missing {0} in the leaf certificate")]
+    MissingLeafCert(&'static str),
+    #[error("Invalid leaf certificate: {source}")]
+    LeafCert {
+        #[from]
+        source: TcpClientError,
+    },
+    #[error("Failed to list the nodes of {} services: {}", .0.len(), services_issues(.0))]
+    Services(Vec<(String, ConsulError)>),
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct ServiceNode {
-    pub service_name: String,
-    pub ip: String,
-    pub port: u16,
+fn services_issues(failures: &[(String, ConsulError)]) -> String {
+    failures
+        .iter()
+        .map(|(service_name, issue)| format!("{service_name}: {issue}"))
+        .collect::<Vec<String>>()
+        .join(", ")
 }
 
-impl fmt::Display for ServiceNode {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}:{}:{}", self.service_name, self.ip, self.port)
+impl ConsulError {
+    /// Return true if consul asks to slow down (429)
+    pub fn is_throttled(&self) -> bool {
+        match self {
+            ConsulError::Status { status, .. } => *status == 429,
+            ConsulError::Services(failures) => {
+                failures.iter().any(|(_, issue)| issue.is_throttled())
+            }
+            _ => false,
+        }
+    }
+
+    /// Return true if consul failed to handle the query (5xx)
+    pub fn is_server_error(&self) -> bool {
+        match self {
+            ConsulError::Status { status, .. } => *status >= 500,
+            ConsulError::Services(failures) => {
+                failures.iter().any(|(_, issue)| issue.is_server_error())
+            }
+            _ => false,
+        }
     }
 }
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct ServiceNodes {
-    pub index: i64,
-    pub nodes: HashMap<String, ServiceNode>,
+// Represent a consul client
+#[derive(Debug, Clone)]
+pub struct ConsulClient {
+    // The fqdn of the consul agent to query
+    fqdn: String,
+    client: Arc<HttpClient>,
+    // Probe the Connect sidecar proxies of the services instead of the services
+    connect: bool,
+    // Service identity of the leaf certificate presented to the sidecar proxies, no mTLS if empty
+    connect_mtls_service: String,
+    // Leaf certificate currently presented by the tls probes
+    leaf_cert: String,
+    // Acl token sent on each query, none if empty
+    token: String,
+    // File holding the acl token, re-read on each discovery pass to follow rotations, none if empty
+    token_file: String,
+    // Nodes of each matching service on its last query
+    service_nodes: HashMap<String, ServiceCache>,
 }
 
 struct HttpCall {
-    index: i64,
+    // Index returned by consul, none if the header is missing
+    index: Option<i64>,
     body_json: Value,
 }
 
+// Nodes of a matching service kept between the discovery passes
+struct ServiceCache {
+    // Index of the service on its last query
+    index: i64,
+    // Index of the catalog when the service was last queried
+    catalog_index: i64,
+    nodes: Vec<ServiceNode>,
+}
+
 impl ConsulClient {
     /// Returns a consul client
     ///
@@ -56,18 +157,68 @@ impl ConsulClient {
     /// ```
     pub fn new(consul_fqdn: String) -> Self {
         debug!("Create consul client {}", consul_fqdn);
-        let https = HttpsConnectorBuilder::new()
-            .with_native_roots()
-            .https_or_http()
-            .enable_http1()
-            .build();
 
         ConsulClient {
             fqdn: consul_fqdn,
-            client: Client::builder().build::<_, hyper::Body>(https),
+            client: shared_client(),
+            connect: false,
+            connect_mtls_service: "".to_string(),
+            leaf_cert: "".to_string(),
+            token: "".to_string(),
+            token_file: "".to_string(),
+            service_nodes: HashMap::new(),
+        }
+    }
+
+    /// Authenticate the queries with an acl token
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - acl token, none if empty
+    /// * `token_file` - file holding the acl token, taking precedence over `token`, none if empty
+    ///
+    pub fn with_token(mut self, token: &str, token_file: &str) -> Self {
+        self.token = token.to_string();
+        self.token_file = token_file.to_string();
+        self
+    }
+
+    /// Re-read the acl token from the token file if any
+    ///
+    /// The last token read is kept if the file can't be read, e.g. while being rotated
+    fn refresh_token(&mut self) {
+        if !self.token_file.is_empty() {
+            match std::fs::read_to_string(&self.token_file) {
+                Ok(content) => {
+                    let token = content.trim();
+                    if token != self.token {
+                        info!("Read consul acl token from {}", self.token_file);
+                        self.token = token.to_string();
+                    }
+                }
+                Err(issue) => warn!(
+                    "Issue to read consul token file {}: {}",
+                    self.token_file, issue
+                ),
+            }
         }
     }
 
+    /// Probe the Connect sidecar proxies of the services, for services only reachable through the mesh
+    ///
+    /// With mTLS, sidecars only accept connections presenting a leaf certificate of the Connect CA:
+    /// nodes are probed with the tls protocol presenting the leaf certificate of `mtls_service`
+    ///
+    /// # Arguments
+    ///
+    /// * `mtls_service` - service identity of the leaf certificate, no mTLS if empty
+    ///
+    pub fn with_connect(mut self, mtls_service: &str) -> Self {
+        self.connect = true;
+        self.connect_mtls_service = mtls_service.to_string();
+        self
+    }
+
     /// Get string from json value
     ///
     /// # Arguments
@@ -90,19 +241,19 @@ impl ConsulClient {
     ///
     /// # Arguments
     ///
-    /// * `tag` - tag needed on service to enable probing
+    /// * `tag` - comma separated tags, one of them needed on service to enable probing
     /// * `tags_opt` - list of tags set on the service
     ///
     /// # Return
     ///
-    /// * bool - true if the list of tags_opt contains tag
+    /// * bool - true if the list of tags_opt contains one of the tags
     ///
     fn is_matching_service(tag: &str, tags_opt: Option<&Vec<Value>>) -> bool {
         if let Some(tags) = tags_opt {
             if tags
                 .iter()
                 .map(ConsulClient::get_string_value)
-                .any(|x| x == *tag)
+                .any(|x| tag.split(',').any(|tag| x == tag.trim()))
             {
                 return true;
             }
@@ -141,7 +292,110 @@ impl ConsulClient {
         matching_services
     }
 
-    /// Create ServiceNode from json representing a node in consul service
+    /// Get the list of ports to probe for a node in consul service
+    ///
+    /// The service port is always probed. Additional ports can be declared
+    /// with the `probe-ports` service meta (comma separated list of ports)
+    /// or with `probe-port=<port>` service tags
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - json representing a node in consul service
+    ///
+    /// # Return
+    ///
+    /// * List u16 - the deduplicated list of ports to probe
+    ///
+    fn get_probe_ports(node: &Map<String, Value>) -> Vec<u16> {
+        let mut ports: Vec<u16> = Vec::new();
+        if let Some(service_port) = node.get("ServicePort").and_then(Value::as_u64) {
+            ports.push(service_port as u16);
+        }
+
+        let meta_ports = node
+            .get("ServiceMeta")
+            .and_then(|meta| meta.get(PROBE_PORTS_META))
+            .map(ConsulClient::get_string_value)
+            .unwrap_or_default();
+        let tag_ports = node
+            .get("ServiceTags")
+            .and_then(Value::as_array)
+            .map(|tags| {
+                tags.iter()
+                    .map(ConsulClient::get_string_value)
+                    .filter_map(|tag| tag.strip_prefix(PROBE_PORT_TAG_PREFIX).map(str::to_string))
+                    .collect::<Vec<String>>()
+            })
+            .unwrap_or_default();
+
+        for port_str in meta_ports.split(',').map(str::to_string).chain(tag_ports) {
+            let port_str = port_str.trim();
+            if port_str.is_empty() {
+                continue;
+            }
+            match port_str.parse::<u16>() {
+                Ok(port) if !ports.contains(&port) => ports.push(port),
+                Ok(_) => {}
+                Err(issue) => warn!("Invalid probe port {}: {}", port_str, issue),
+            }
+        }
+
+        ports
+    }
+
+    /// Get the protocol declared with the `probe-protocol` service meta
+    /// or with a `probe-protocol=<protocol>` service tag, the meta taking precedence
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - json representing a node in consul service
+    ///
+    /// # Return
+    ///
+    /// * Option Protocol - the declared protocol, None if not declared or invalid
+    ///
+    fn get_probe_protocol(node: &Map<String, Value>) -> Option<Protocol> {
+        ConsulClient::get_declared_protocol(node.get("ServiceMeta"), node.get("ServiceTags"))
+    }
+
+    /// Get the protocol declared in the meta or tags of a service
+    ///
+    /// # Arguments
+    ///
+    /// * `meta` - json of the service meta
+    /// * `tags` - json of the service tags
+    ///
+    /// # Return
+    ///
+    /// * Option Protocol - the declared protocol, None if not declared or invalid
+    ///
+    fn get_declared_protocol(meta: Option<&Value>, tags: Option<&Value>) -> Option<Protocol> {
+        let meta_protocol = meta
+            .and_then(|meta| meta.get(PROBE_PROTOCOL_META))
+            .map(ConsulClient::get_string_value);
+        let protocol_str = match meta_protocol {
+            Some(protocol_str) => protocol_str,
+            None => tags
+                .and_then(Value::as_array)?
+                .iter()
+                .map(ConsulClient::get_string_value)
+                .find_map(|tag| {
+                    tag.strip_prefix(PROBE_PROTOCOL_TAG_PREFIX)
+                        .map(str::to_string)
+                })?,
+        };
+
+        match protocol_str.parse::<Protocol>() {
+            Ok(protocol) => Some(protocol),
+            Err(issue) => {
+                warn!("Invalid probe protocol: {}", issue);
+                None
+            }
+        }
+    }
+
+    /// Create ServiceNodes from json representing a node in consul service
+    /// One ServiceNode is created for each port to probe on the node
     ///
     /// # Arguments
     ///
@@ -150,9 +404,9 @@ impl ConsulClient {
     ///
     /// # Return
     ///
-    /// * ServiceNode - the definition of a node to probe with service_name, ip and port
+    /// * List ServiceNode - the definition of nodes to probe with service_name, ip and port
     ///
-    fn get_service_address_port(service_name: &str, node_value: &Value) -> ServiceNode {
+    fn get_service_nodes(service_name: &str, node_value: &Value) -> Vec<ServiceNode> {
         let node = node_value.as_object().unwrap();
         let service_address = node
             .get("ServiceAddress")
@@ -160,13 +414,17 @@ impl ConsulClient {
             .as_str()
             .unwrap()
             .to_string();
-        let service_port: u16 = node.get("ServicePort").unwrap().as_u64().unwrap() as u16;
-
-        ServiceNode {
-            service_name: service_name.to_owned(),
-            ip: service_address,
-            port: service_port,
-        }
+        let protocol = ConsulClient::get_probe_protocol(node);
+
+        ConsulClient::get_probe_ports(node)
+            .into_iter()
+            .map(|port| ServiceNode {
+                service_name: service_name.to_owned(),
+                ip: service_address.clone(),
+                port,
+                protocol,
+            })
+            .collect()
     }
 
     /// Extract list of ServiceNodes from consul service json of a specific service
@@ -195,38 +453,151 @@ impl ConsulClient {
 
         let nodes = services
             .iter()
-            .map(|val| ConsulClient::get_service_address_port(&service_name, val))
+            .flat_map(|val| ConsulClient::get_service_nodes(&service_name, val))
             .collect::<Vec<ServiceNode>>();
 
         nodes
     }
 
-    /// Get watch index value
+    /// Create the ServiceNode of a Connect sidecar proxy from the health api
+    ///
+    /// The sidecar is reached on its service address, the address of its node if none
+    ///
+    /// # Arguments
+    ///
+    /// * `service_name` - name of the service in consul
+    /// * `entry` - json of a sidecar proxy returned by the health api
+    /// * `mtls` - whether the sidecar is probed with the leaf certificate
+    ///
+    /// # Return
+    ///
+    /// * Option ServiceNode - the sidecar to probe, None if the entry is not a sidecar proxy
+    ///
+    fn get_sidecar_node(service_name: &str, entry: &Value, mtls: bool) -> Option<ServiceNode> {
+        let service = entry.get("Service")?;
+        // Only sidecar proxies declare the proxied service
+        service.get("Proxy")?;
+        let port = service.get("Port").and_then(Value::as_u64)? as u16;
+        let address = service
+            .get("Address")
+            .and_then(Value::as_str)
+            .filter(|address| !address.is_empty())
+            .or_else(|| entry.get("Node")?.get("Address")?.as_str())?;
+        // Sidecars inherit the meta and tags of their service by default
+        let protocol = match mtls {
+            true => Some(Protocol::Tls),
+            false => ConsulClient::get_declared_protocol(service.get("Meta"), service.get("Tags")),
+        };
+
+        Some(ServiceNode {
+            service_name: service_name.to_owned(),
+            ip: address.to_string(),
+            port,
+            protocol,
+        })
+    }
+
+    /// Extract list of ServiceNodes from the Connect sidecar proxies of a specific service
+    ///
+    /// # Arguments
+    ///
+    /// * `service_name` - name of the service in consul
+    /// * `body_json` - json from the consul health api of the sidecar proxies of the service
+    /// * `mtls` - whether the sidecars are probed with the leaf certificate
+    ///
+    /// # Return
+    ///
+    /// * List ServiceNode - the list of sidecars to probe for a specific service
+    ///
+    fn extract_sidecar_nodes(
+        service_name: String,
+        body_json: Value,
+        mtls: bool,
+    ) -> Vec<ServiceNode> {
+        match body_json.as_array() {
+            Some(entries) => entries
+                .iter()
+                .filter_map(|entry| ConsulClient::get_sidecar_node(&service_name, entry, mtls))
+                .collect(),
+            None => {
+                warn!("Returned body is not an array of sidecars: {}", body_json);
+                Vec::new()
+            }
+        }
+    }
+
+    /// Get the reason to reset an index returned by consul
     ///
-    /// The index returned by consul for watch must be greater than 0 and grater than previous index
+    /// The index returned by consul must be greater than 0 and greater than previous index
     /// otherwise it should be reset to 0
     ///
     /// # Arguments
     ///
     /// * `prev_index` - previous index value
-    /// * `_index` - new index value
+    /// * `index` - new index value, none if consul did not return it
+    ///
+    /// # Return
+    ///
+    /// * Option of str - reason to reset the index to 0, none if the index can be used
+    ///
+    fn index_reset_reason(prev_index: i64, index: Option<i64>) -> Option<&'static str> {
+        match index {
+            None if prev_index > 0 => Some("missing_index"),
+            Some(_index) if _index < prev_index => Some("lower_index"),
+            Some(_index) if _index < 0 => Some("negative_index"),
+            _ => None,
+        }
+    }
+
+    /// Index of the catalog watch, reset to 0 if not usable
+    ///
+    /// # Arguments
+    ///
+    /// * `prev_index` - previous index value
+    /// * `index` - new index value, none if consul did not return it
     ///
     /// # Return
     ///
     /// * int - index value that will be used on next watch
     ///
-    fn get_watch_index(prev_index: i64, _index: i64) -> i64 {
-        if _index < prev_index {
-            warn!("Consul index querying list of services is lower than previous one. Will need to reset it to 0");
+    fn get_watch_index(prev_index: i64, index: Option<i64>) -> i64 {
+        if let Some(reason) = ConsulClient::index_reset_reason(prev_index, index) {
+            warn!(
+                "Consul index {:?} of the list of services is not usable ({}). Will need to reset it to 0",
+                index, reason
+            );
+            CONSUL_WATCH_INDEX_RESETS.with_label_values(&[reason]).inc();
             return 0;
         }
 
-        if _index < 0 {
-            warn!("Consul index < 0. . Will need to reset it to 0");
+        index.unwrap_or(0)
+    }
+
+    /// Index of a service query, reset to 0 if not usable
+    ///
+    /// # Arguments
+    ///
+    /// * `service_name` - name of the consul service
+    /// * `prev_index` - previous index value of the service
+    /// * `index` - new index value, none if consul did not return it
+    ///
+    /// # Return
+    ///
+    /// * int - index value that will be used on next query of the service
+    ///
+    fn get_service_index(service_name: &str, prev_index: i64, index: Option<i64>) -> i64 {
+        if let Some(reason) = ConsulClient::index_reset_reason(prev_index, index) {
+            warn!(
+                "Consul index {:?} of service {} is not usable ({}). Will need to reset it to 0",
+                index, service_name, reason
+            );
+            CONSUL_SERVICE_INDEX_RESETS
+                .with_label_values(&[service_name, reason])
+                .inc();
             return 0;
         }
 
-        _index
+        index.unwrap_or(0)
     }
 
     /// Manage http call to consul agent endpoint
@@ -237,18 +608,20 @@ impl ConsulClient {
     ///
     /// * `uri_str` - consul uri to call
     /// * `prev_index` - index value of last http call
+    /// * `wait` - max wait for the index to advance
     ///
     /// # Return
     ///
-    /// * Result of HttpCall or Error - HttpCall contains the new index for watching
+    /// * Result of HttpCall or Error - HttpCall contains the index returned by consul
     ///   and the return json body from consul
     ///
     async fn http_call(
-        &mut self,
+        &self,
         uri_str: String,
         prev_index: i64,
-    ) -> Result<HttpCall, Box<dyn std::error::Error + Send + Sync>> {
-        let query_uri = format!("{uri_str}?index={prev_index}&wait=5m");
+        wait: &str,
+    ) -> Result<HttpCall, ConsulError> {
+        let query_uri = format!("{uri_str}?index={prev_index}&wait={wait}");
         debug!("Query consul: {}", query_uri);
         let uri = match query_uri.as_str().parse::<Uri>() {
             Err(issue) => {
@@ -258,26 +631,28 @@ impl ConsulClient {
             Ok(_uri) => _uri,
         };
 
-        let resp = self.client.get(uri).await?;
+        let mut request = Request::builder().uri(uri);
+        if !self.token.is_empty() {
+            request = request.header("x-consul-token", self.token.as_str());
+        }
+        let resp = self.client.request(request.body(Body::empty())?).await?;
 
         if !resp.status().is_success() {
             error!("Failed to query consul, http status code {}", resp.status());
-            return Err(format!(
-                "Issue query: {} - status code: {}",
-                query_uri,
-                resp.status()
-            )
-            .into());
+            return Err(ConsulError::Status {
+                uri: query_uri,
+                status: resp.status().as_u16(),
+            });
         }
 
         let (parts, body) = resp.into_parts();
 
-        let resp_index: i64 = if let Some(consul_index) = parts.headers.get("x-consul-index") {
-            let mut _index = consul_index.to_str().unwrap().parse()?;
-            ConsulClient::get_watch_index(prev_index, _index)
-        } else {
-            warn!("Missing x-consul-index header. Setting index to 0");
-            0
+        let resp_index: Option<i64> = match parts.headers.get("x-consul-index") {
+            Some(consul_index) => Some(consul_index.to_str().unwrap().parse()?),
+            None => {
+                warn!("Missing x-consul-index header on {}", query_uri);
+                None
+            }
         };
 
         let bytes = hyper::body::to_bytes(body).await?;
@@ -299,28 +674,84 @@ impl ConsulClient {
 
     /// Get the list of nodes for a service from consul endpoint
     ///
+    /// The nodes of the last query are kept while the index of the service does not advance
+    ///
     /// # Arguments
     ///
     /// * `service_name` - name of the consul service
     ///
     /// # Return
     ///
-    /// * Result of List ServiceNode or Error
+    /// * Result of the index and List ServiceNode of the service or Error
     ///
     async fn list_nodes_for_service(
-        &mut self,
-        service_name: String,
-    ) -> Result<Vec<ServiceNode>, Box<dyn std::error::Error + Send + Sync>> {
-        let service_uri = format!("{}/v1/catalog/service/{}", self.fqdn, service_name);
+        &self,
+        service_name: &str,
+    ) -> Result<(i64, Vec<ServiceNode>), ConsulError> {
+        let service_uri = if self.connect {
+            format!("{}/v1/health/connect/{}", self.fqdn, service_name)
+        } else {
+            format!("{}/v1/catalog/service/{}", self.fqdn, service_name)
+        };
+        let cached = self.service_nodes.get(service_name);
+        let cached_index = cached.map_or(0, |cache| cache.index);
+
+        let response = self
+            .http_call(service_uri, cached_index, SERVICE_INDEX_WAIT)
+            .await?;
+        let index = ConsulClient::get_service_index(service_name, cached_index, response.index);
+
+        if let Some(cache) = cached {
+            if cache.index > 0 && cache.index == index {
+                debug!("Nodes of service {} are unchanged", service_name);
+                return Ok((index, cache.nodes.clone()));
+            }
+        }
 
-        let response = self.http_call(service_uri, 0).await?;
+        let nodes = if self.connect {
+            let mtls = !self.connect_mtls_service.is_empty();
+            ConsulClient::extract_sidecar_nodes(service_name.to_string(), response.body_json, mtls)
+        } else {
+            ConsulClient::extract_nodes(service_name.to_string(), response.body_json)
+        };
+        Ok((index, nodes))
+    }
 
-        let service_node = ConsulClient::extract_nodes(service_name, response.body_json);
-        Ok(service_node)
+    /// Fetch the leaf certificate of the mTLS service identity from the agent
+    /// and present it on the tls probes when it changed
+    ///
+    /// The agent renews the certificate before its expiry
+    async fn refresh_leaf_cert(&mut self) -> Result<(), ConsulError> {
+        let leaf_uri = format!(
+            "{}/v1/agent/connect/ca/leaf/{}",
+            self.fqdn, self.connect_mtls_service
+        );
+        let response = self.http_call(leaf_uri, 0, CATALOG_WATCH_WAIT).await?;
+        let pem = |key: &'static str| {
+            response
+                .body_json
+                .get(key)
+                .and_then(Value::as_str)
+                .ok_or(ConsulError::MissingLeafCert(key))
+        };
+        let (cert_pem, key_pem) = (pem("CertPEM")?, pem("PrivateKeyPEM")?);
+        if cert_pem != self.leaf_cert {
+            info!(
+                "Present the leaf certificate of {} on tls probes",
+                self.connect_mtls_service
+            );
+            tcp::set_client_certificate(cert_pem, key_pem)?;
+            self.leaf_cert = cert_pem.to_string();
+        }
+        Ok(())
     }
 
     /// Get the list of nodes for all services with tags matching the tag for probing
     ///
+    /// Nodes of the services are listed concurrently.
+    /// A service is only queried again once the catalog index advanced since its last query.
+    /// The last nodes of a failing service are kept, the listing only fails if all services fail
+    ///
     /// # Arguments
     ///
     /// * `prev_index` - index value of last consul watch
@@ -330,55 +761,250 @@ impl ConsulClient {
     ///
     /// * Result of List ServiceNode or Error
     ///
+    #[instrument(skip(self))]
     pub async fn list_matching_nodes(
         &mut self,
         prev_index: i64,
         tag: &str,
-    ) -> Result<ServiceNodes, Box<dyn std::error::Error + Send + Sync>> {
+    ) -> Result<ServiceNodes, ConsulError> {
         let services_uri = format!("{}/v1/catalog/services", self.fqdn);
 
-        let response = self.http_call(services_uri, prev_index).await?;
+        self.refresh_token();
+        let response = self
+            .http_call(services_uri, prev_index, CATALOG_WATCH_WAIT)
+            .await?;
+        let catalog_index = ConsulClient::get_watch_index(prev_index, response.index);
+        // A fresh watch or an unusable catalog index queries all the services again
+        let refresh_all = prev_index == 0 || catalog_index == 0;
 
         let matching_services = ConsulClient::extract_matching_services(tag, response.body_json);
+        self.service_nodes
+            .retain(|service_name, _| matching_services.contains(service_name));
+        if !self.connect_mtls_service.is_empty() {
+            self.refresh_leaf_cert().await?;
+        }
+
+        let consul_client = &*self;
+        let fetches: Vec<(String, Result<(i64, Vec<ServiceNode>), ConsulError>)> =
+            stream::iter(matching_services.iter())
+                .map(|matching_service| async move {
+                    // No service can have changed while the catalog index did not advance
+                    if let Some(cache) = consul_client
+                        .service_nodes
+                        .get(matching_service)
+                        .filter(|cache| !refresh_all && cache.catalog_index == catalog_index)
+                    {
+                        debug!(
+                            "Catalog unchanged, keep nodes of service {}",
+                            matching_service
+                        );
+                        return (
+                            matching_service.clone(),
+                            Ok((cache.index, cache.nodes.clone())),
+                        );
+                    }
+                    let fetch_res = consul_client.list_nodes_for_service(matching_service).await;
+                    (matching_service.clone(), fetch_res)
+                })
+                .buffer_unordered(SERVICE_FETCH_CONCURRENCY)
+                .collect()
+                .await;
 
         let mut services_nodes: HashMap<String, ServiceNode> = HashMap::new();
-        for matching_service in matching_services {
-            match self.list_nodes_for_service(matching_service).await {
-                Ok(service_nodes) => {
-                    for service_node in service_nodes {
-                        services_nodes.insert(service_node.to_string(), service_node);
+        let mut failures: Vec<(String, ConsulError)> = Vec::new();
+        for (matching_service, fetch_res) in fetches {
+            match fetch_res {
+                Ok((index, service_nodes)) => {
+                    for service_node in service_nodes.iter() {
+                        services_nodes.insert(service_node.to_string(), service_node.clone());
+                    }
+                    self.service_nodes.insert(
+                        matching_service,
+                        ServiceCache {
+                            index,
+                            catalog_index,
+                            nodes: service_nodes,
+                        },
+                    );
+                }
+                Err(issue) => {
+                    error!(
+                        "Failed to list the nodes of service {}: {}",
+                        matching_service, issue
+                    );
+                    CONSUL_SERVICE_FAILURES
+                        .with_label_values(&[matching_service.as_str()])
+                        .inc();
+                    if let Some(cache) = self.service_nodes.get(&matching_service) {
+                        for service_node in cache.nodes.iter() {
+                            services_nodes.insert(service_node.to_string(), service_node.clone());
+                        }
                     }
+                    failures.push((matching_service, issue));
                 }
-                Err(issue) => return Err(issue),
             }
         }
+        if !failures.is_empty() && failures.len() == matching_services.len() {
+            return Err(ConsulError::Services(failures));
+        }
 
         Ok(ServiceNodes {
-            index: response.index,
+            // Retry the failing services on next pass without waiting for the catalog to change
+            index: if failures.is_empty() {
+                catalog_index
+            } else {
+                0
+            },
+            services: matching_services,
             nodes: services_nodes,
         })
     }
 }
 
+// Discovery watching the services with the probing tag in the consul catalog
+//
+// Rely on the consul watch mechanism, rate limited and slowed down on consul backpressure
+pub struct ConsulDiscovery {
+    consul_client: ConsulClient,
+    services_tag: String,
+    rate_limiter: Box<dyn RateLimiter>,
+    discovery_rate: AdaptiveRate,
+    // Index of the last successful watch
+    index: i64,
+    // A requested refresh bypasses the rate limiter and the watch index once
+    force_refresh: bool,
+}
+
+impl ConsulDiscovery {
+    /// Returns a ConsulDiscovery
+    ///
+    /// # Arguments
+    ///
+    /// * `consul_client` - a consul client
+    /// * `services_tag` - comma separated tags, one of them needed on service to enable probing
+    /// * `rate_limiter` - rate limiter implementation of the consul calls
+    ///
+    pub fn new(
+        consul_client: ConsulClient,
+        services_tag: &str,
+        rate_limiter: RateLimiterKind,
+    ) -> Self {
+        let rate_limiter = rate_limiter.build("consul_discovery", 180.0, 1.0, 60);
+        // Discovery rate shrinks on consul backpressure down to one call every 10 minutes
+        let discovery_rate = AdaptiveRate::new(rate_limiter.quantum(), 0.1);
+        CONSUL_DISCOVERY_RATE.set(discovery_rate.current());
+        ConsulDiscovery {
+            consul_client,
+            services_tag: services_tag.to_string(),
+            rate_limiter,
+            discovery_rate,
+            index: 0,
+            force_refresh: false,
+        }
+    }
+
+    async fn watch(&mut self) -> Result<ServiceNodes, DiscoveryError> {
+        loop {
+            if !self.force_refresh {
+                self.force_refresh = tokio::select! {
+                    wait_res = self.rate_limiter.acquire(60) => wait_res
+                        .map(|_| false)
+                        .map_err(|issue| DiscoveryError::RateLimiter(issue.to_string()))?,
+                    _ = PROBER_STATE.refresh_requested() => true,
+                };
+            }
+
+            // Watch from scratch the services of a tag changed at runtime
+            if let Some(services_tag) = PROBER_STATE.services_tag() {
+                if services_tag != self.services_tag {
+                    info!(
+                        "Watch services with tag {} instead of {}",
+                        services_tag, self.services_tag
+                    );
+                    self.services_tag = services_tag;
+                    self.force_refresh = true;
+                }
+            }
+
+            // Index 0 returns immediately instead of waiting for a change
+            let watch_index = if self.force_refresh { 0 } else { self.index };
+            if self.force_refresh {
+                info!("Refresh services discovery immediately");
+            }
+            self.force_refresh = false;
+
+            let watch_start = Instant::now();
+            let discovery_res = tokio::select! {
+                discovery_res = self
+                    .consul_client
+                    .list_matching_nodes(watch_index, &self.services_tag) => Some(discovery_res),
+                // Interrupt the long poll to refresh immediately
+                _ = PROBER_STATE.refresh_requested() => None,
+            };
+            let Some(discovery_res) = discovery_res else {
+                self.force_refresh = true;
+                continue;
+            };
+            CONSUL_WATCH_DURATION.observe(watch_start.elapsed().as_secs_f64());
+
+            let rate_update = match &discovery_res {
+                Ok(_) => self.discovery_rate.on_success(),
+                Err(consul_err) if consul_err.is_throttled() => {
+                    // Do not burst remaining token while consul asks to slow down
+                    self.rate_limiter.drain();
+                    self.discovery_rate.on_backpressure()
+                }
+                Err(consul_err) if consul_err.is_server_error() => {
+                    self.discovery_rate.on_server_error()
+                }
+                Err(_) => None,
+            };
+            if let Some(rate) = rate_update {
+                self.rate_limiter.set_quantum(rate);
+                CONSUL_DISCOVERY_RATE.set(rate);
+            }
+
+            self.index = match &discovery_res {
+                Ok(discovered_nodes) => discovered_nodes.index,
+                Err(_) => {
+                    CONSUL_WATCH_INDEX_RESETS
+                        .with_label_values(&["discovery_failure"])
+                        .inc();
+                    0
+                }
+            };
+            CONSUL_WATCH_INDEX.set(self.index);
+            return discovery_res;
+        }
+    }
+}
+
+impl fmt::Display for ConsulDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "consul services with tag {}", self.services_tag)
+    }
+}
+
+impl Discovery for ConsulDiscovery {
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+        Box::pin(self.watch())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
 
     use serde_json::Value;
-    use wiremock::matchers::{method, path};
+    use wiremock::matchers::{header, method, path, query_param};
     use wiremock::{Mock, MockServer, ResponseTemplate};
 
-    use crate::consul::{ConsulClient, ServiceNode, ServiceNodes};
-
-    #[test]
-    fn service_node_to_string() {
-        let node = ServiceNode {
-            service_name: "service_name".to_string(),
-            ip: "0.0.0.0".to_string(),
-            port: 12500,
-        };
-        assert_eq!("service_name:0.0.0.0:12500".to_string(), node.to_string());
-    }
+    use crate::consul::{ConsulClient, ConsulError};
+    use crate::probes::discovery::{ServiceNode, ServiceNodes};
+    use crate::probes::prometheus::{
+        CONSUL_SERVICE_FAILURES, CONSUL_SERVICE_INDEX_RESETS, CONSUL_WATCH_INDEX_RESETS,
+    };
+    use crate::probes::protocol::Protocol;
 
     #[test]
     fn get_string_value() {
@@ -409,6 +1035,13 @@ mod tests {
             &"elasticsearch".to_string(),
             None,
         ));
+        assert!(ConsulClient::is_matching_service(
+            "elasticsearch,memcached",
+            Some(&vec![
+                Value::String("memcached".to_string()),
+                Value::String("tcp".to_string()),
+            ]),
+        ));
     }
 
     #[test]
@@ -438,23 +1071,112 @@ mod tests {
 
     #[test]
     fn get_watch_index() {
-        assert_eq!(5, ConsulClient::get_watch_index(1, 5));
-        assert_eq!(0, ConsulClient::get_watch_index(5, 1));
-        assert_eq!(0, ConsulClient::get_watch_index(1, -5));
+        let lower_index_resets = CONSUL_WATCH_INDEX_RESETS
+            .with_label_values(&["lower_index"])
+            .get();
+        assert_eq!(5, ConsulClient::get_watch_index(1, Some(5)));
+        assert_eq!(0, ConsulClient::get_watch_index(5, Some(1)));
+        assert_eq!(0, ConsulClient::get_watch_index(1, Some(-5)));
+        assert_eq!(0, ConsulClient::get_watch_index(0, None));
+        // Other tests running in parallel may reset the index too
+        assert!(
+            CONSUL_WATCH_INDEX_RESETS
+                .with_label_values(&["lower_index"])
+                .get()
+                >= lower_index_resets + 2
+        );
     }
 
     #[test]
-    fn get_service_address_port() {
+    fn get_service_index() {
+        let service_resets = |reason| {
+            CONSUL_SERVICE_INDEX_RESETS
+                .with_label_values(&["service_index_test", reason])
+                .get()
+        };
+
+        assert_eq!(
+            5,
+            ConsulClient::get_service_index("service_index_test", 1, Some(5))
+        );
+        assert_eq!(
+            0,
+            ConsulClient::get_service_index("service_index_test", 5, Some(1))
+        );
+        assert_eq!(
+            0,
+            ConsulClient::get_service_index("service_index_test", 5, None)
+        );
+        assert_eq!(
+            0,
+            ConsulClient::get_service_index("service_index_test", 0, None)
+        );
+        assert_eq!(1, service_resets("lower_index"));
+        assert_eq!(1, service_resets("missing_index"));
+    }
+
+    #[test]
+    fn get_service_nodes() {
         let node_value =
             serde_json::from_str("{\"ServiceAddress\":\"127.0.0.1\",\"ServicePort\":1045}")
                 .unwrap();
         assert_eq!(
-            ServiceNode {
+            vec![ServiceNode {
                 service_name: "service_test".to_string(),
                 ip: "127.0.0.1".to_string(),
                 port: 1045,
-            },
-            ConsulClient::get_service_address_port("service_test", &node_value)
+                protocol: None,
+            }],
+            ConsulClient::get_service_nodes("service_test", &node_value)
+        );
+    }
+
+    #[test]
+    fn get_service_nodes_multiple_ports() {
+        let node_value = serde_json::from_str(
+            "{\"ServiceAddress\":\"127.0.0.1\",\"ServicePort\":1045,\
+            \"ServiceMeta\":{\"probe-ports\":\"1046, 1045,bad\"},\
+            \"ServiceTags\":[\"memcached\",\"probe-port=1047\"]}",
+        )
+        .unwrap();
+        let ports = ConsulClient::get_service_nodes("service_test", &node_value)
+            .into_iter()
+            .map(|node| node.port)
+            .collect::<Vec<u16>>();
+        assert_eq!(vec![1045, 1046, 1047], ports);
+    }
+
+    #[test]
+    fn get_service_nodes_protocol() {
+        let node_value = serde_json::from_str(
+            "{\"ServiceAddress\":\"127.0.0.1\",\"ServicePort\":6379,\
+            \"ServiceTags\":[\"memcached\",\"probe-protocol=redis\"]}",
+        )
+        .unwrap();
+        assert_eq!(
+            Some(Protocol::Redis),
+            ConsulClient::get_service_nodes("service_test", &node_value)[0].protocol
+        );
+
+        let node_value = serde_json::from_str(
+            "{\"ServiceAddress\":\"127.0.0.1\",\"ServicePort\":6379,\
+            \"ServiceTags\":[\"probe-protocol=unknown\"]}",
+        )
+        .unwrap();
+        assert_eq!(
+            None,
+            ConsulClient::get_service_nodes("service_test", &node_value)[0].protocol
+        );
+
+        let node_value = serde_json::from_str(
+            "{\"ServiceAddress\":\"127.0.0.1\",\"ServicePort\":4222,\
+            \"ServiceMeta\":{\"probe-protocol\":\"nats\"},\
+            \"ServiceTags\":[\"probe-protocol=redis\"]}",
+        )
+        .unwrap();
+        assert_eq!(
+            Some(Protocol::Nats),
+            ConsulClient::get_service_nodes("service_test", &node_value)[0].protocol
         );
     }
 
@@ -466,11 +1188,13 @@ mod tests {
                 service_name: "service_test".to_string(),
                 ip: "127.0.0.1".to_string(),
                 port: 1045,
+                protocol: None,
             },
             ServiceNode {
                 service_name: "service_test".to_string(),
                 ip: "127.0.0.2".to_string(),
                 port: 1045,
+                protocol: None,
             },
         ];
         assert_eq!(
@@ -492,6 +1216,104 @@ mod tests {
         );
     }
 
+    #[test]
+    fn extract_sidecar_nodes() {
+        let sidecars_value = serde_json::json!([
+            {
+                "Node": {"Address": "10.0.0.1"},
+                "Service": {"Address": "", "Port": 21000, "Tags": ["probe-protocol=redis"],
+                    "Proxy": {"DestinationServiceName": "sessions"}}
+            },
+            {
+                "Node": {"Address": "10.0.0.2"},
+                "Service": {"Address": "10.0.1.2", "Port": 21000,
+                    "Proxy": {"DestinationServiceName": "sessions"}}
+            },
+            {
+                "Node": {"Address": "10.0.0.3"},
+                "Service": {"Address": "10.0.1.3", "Port": 6379}
+            }
+        ]);
+        assert_eq!(
+            vec![
+                ServiceNode {
+                    service_name: "sessions".to_string(),
+                    ip: "10.0.0.1".to_string(),
+                    port: 21000,
+                    protocol: Some(Protocol::Redis),
+                },
+                ServiceNode {
+                    service_name: "sessions".to_string(),
+                    ip: "10.0.1.2".to_string(),
+                    port: 21000,
+                    protocol: None,
+                }
+            ],
+            ConsulClient::extract_sidecar_nodes(
+                "sessions".to_string(),
+                sidecars_value.clone(),
+                false
+            )
+        );
+
+        // Sidecars only accept the mTLS handshake
+        let sidecar_nodes =
+            ConsulClient::extract_sidecar_nodes("sessions".to_string(), sidecars_value, true);
+        assert!(sidecar_nodes
+            .iter()
+            .all(|sidecar_node| sidecar_node.protocol == Some(Protocol::Tls)));
+
+        let empty: Vec<ServiceNode> = Vec::new();
+        assert_eq!(
+            empty,
+            ConsulClient::extract_sidecar_nodes("sessions".to_string(), Value::Null, false)
+        );
+    }
+
+    #[tokio::test]
+    async fn list_matching_nodes_connect() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/catalog/services"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("{\"sessions\":[\"memcached\"]}")
+                    .insert_header("x-consul-index", "12"),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/health/connect/sessions"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "[{\"Node\":{\"Address\":\"10.0.0.1\"},\
+                \"Service\":{\"Port\":21000,\"Proxy\":{}}}]",
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/agent/connect/ca/leaf/probes"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("{\"CertPEM\":\"\",\"PrivateKeyPEM\":\"\"}"),
+            )
+            .mount(&mock_server)
+            .await;
+
+        let mut consul_client = ConsulClient::new(mock_server.uri()).with_connect("");
+        let res = consul_client
+            .list_matching_nodes(0, "memcached")
+            .await
+            .unwrap();
+        assert!(res.nodes.contains_key("sessions:10.0.0.1:21000"));
+
+        // An invalid leaf certificate fails the discovery
+        let mut consul_client = ConsulClient::new(mock_server.uri()).with_connect("probes");
+        assert!(consul_client
+            .list_matching_nodes(0, "memcached")
+            .await
+            .is_err());
+    }
+
     async fn init_consul_client() -> ConsulClient {
         let mock_server = MockServer::start().await;
 
@@ -532,10 +1354,10 @@ mod tests {
 
     #[tokio::test]
     async fn list_nodes_for_service() {
-        let mut consul_client = init_consul_client().await;
+        let consul_client = init_consul_client().await;
 
-        let res = consul_client
-            .list_nodes_for_service("memcached-1".to_string())
+        let (_, res) = consul_client
+            .list_nodes_for_service("memcached-1")
             .await
             .unwrap();
 
@@ -544,19 +1366,21 @@ mod tests {
                 ServiceNode {
                     service_name: "memcached-1".to_string(),
                     ip: "1.2.2.15".to_string(),
-                    port: 11213
+                    port: 11213,
+                    protocol: None,
                 },
                 ServiceNode {
                     service_name: "memcached-1".to_string(),
                     ip: "1.2.2.16".to_string(),
-                    port: 11213
+                    port: 11213,
+                    protocol: None,
                 }
             ],
             res
         );
 
-        let res = consul_client
-            .list_nodes_for_service("service_name_non_parsable_json".to_string())
+        let (_, res) = consul_client
+            .list_nodes_for_service("service_name_non_parsable_json")
             .await
             .unwrap();
 
@@ -564,6 +1388,165 @@ mod tests {
         assert_eq!(res_vec, res);
     }
 
+    #[tokio::test]
+    async fn list_matching_nodes_cached() {
+        let mock_server = MockServer::start().await;
+        for (index, new_index) in [("0", "110"), ("110", "110"), ("110", "111"), ("111", "112")] {
+            Mock::given(method("GET"))
+                .and(path("/v1/catalog/services"))
+                .and(query_param("index", index))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string("{\"memcached-1\":[\"memcached\"]}")
+                        .insert_header("x-consul-index", new_index),
+                )
+                .up_to_n_times(1)
+                .expect(1)
+                .mount(&mock_server)
+                .await;
+        }
+        for (index, new_index, body, queries) in [
+            (
+                "0",
+                "20",
+                "[{\"ServiceAddress\":\"1.2.2.15\",\"ServicePort\":11213}]",
+                1,
+            ),
+            ("20", "20", "[]", 1),
+            ("20", "21", "[]", 1),
+        ] {
+            Mock::given(method("GET"))
+                .and(path("/v1/catalog/service/memcached-1"))
+                .and(query_param("index", index))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string(body)
+                        .insert_header("x-consul-index", new_index),
+                )
+                .up_to_n_times(1)
+                .expect(queries)
+                .mount(&mock_server)
+                .await;
+        }
+        let mut consul_client = ConsulClient::new(mock_server.uri());
+        let nodes = HashMap::from([(
+            "memcached-1:1.2.2.15:11213".to_string(),
+            ServiceNode {
+                service_name: "memcached-1".to_string(),
+                ip: "1.2.2.15".to_string(),
+                port: 11213,
+                protocol: None,
+            },
+        )]);
+
+        // The service is not queried while the catalog index does not advance
+        for (prev_index, expected_nodes) in [
+            (0, nodes.clone()),
+            (110, nodes.clone()),
+            (110, nodes),
+            (111, HashMap::new()),
+        ] {
+            assert_eq!(
+                expected_nodes,
+                consul_client
+                    .list_matching_nodes(prev_index, "memcached")
+                    .await
+                    .unwrap()
+                    .nodes
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn list_matching_nodes_partial_failure() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/catalog/services"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(
+                        "{\"partial-1\":[\"memcached\"], \"partial-2\":[\"memcached\"], \"partial-3\":[\"memcached\"]}",
+                    )
+                    .insert_header("x-consul-index", "110"),
+            )
+            .mount(&mock_server)
+            .await;
+        for (service_name, ip) in [("partial-1", "1.2.2.15"), ("partial-2", "1.2.2.16")] {
+            Mock::given(method("GET"))
+                .and(path(format!("/v1/catalog/service/{service_name}")))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string(format!(
+                            "[{{\"ServiceAddress\":\"{ip}\",\"ServicePort\":11213}}]"
+                        ))
+                        .insert_header("x-consul-index", "20"),
+                )
+                .up_to_n_times(if service_name == "partial-1" { 2 } else { 1 })
+                .mount(&mock_server)
+                .await;
+        }
+        for service_name in ["partial-1", "partial-2", "partial-3"] {
+            Mock::given(method("GET"))
+                .and(path(format!("/v1/catalog/service/{service_name}")))
+                .respond_with(ResponseTemplate::new(503))
+                .mount(&mock_server)
+                .await;
+        }
+        let mut consul_client = ConsulClient::new(mock_server.uri());
+        let node = |service_name: &str, ip: &str| {
+            (
+                format!("{service_name}:{ip}:11213"),
+                ServiceNode {
+                    service_name: service_name.to_string(),
+                    ip: ip.to_string(),
+                    port: 11213,
+                    protocol: None,
+                },
+            )
+        };
+        let nodes = HashMap::from([node("partial-1", "1.2.2.15"), node("partial-2", "1.2.2.16")]);
+
+        // The nodes of the failing services are kept from their last successful query
+        for _ in 0..2 {
+            let res = consul_client
+                .list_matching_nodes(0, "memcached")
+                .await
+                .unwrap();
+            assert_eq!(nodes, res.nodes);
+            assert_eq!(0, res.index);
+        }
+        assert_eq!(
+            1,
+            CONSUL_SERVICE_FAILURES
+                .with_label_values(&["partial-2"])
+                .get()
+        );
+        assert_eq!(
+            2,
+            CONSUL_SERVICE_FAILURES
+                .with_label_values(&["partial-3"])
+                .get()
+        );
+
+        // The listing fails once all services fail
+        let err = consul_client
+            .list_matching_nodes(0, "memcached")
+            .await
+            .err()
+            .unwrap();
+        let ConsulError::Services(failures) = &err else {
+            panic!("Unexpected error {err}");
+        };
+        let mut failed_services: Vec<&str> = failures
+            .iter()
+            .map(|(service_name, _)| service_name.as_str())
+            .collect();
+        failed_services.sort();
+        assert_eq!(vec!["partial-1", "partial-2", "partial-3"], failed_services);
+        assert!(err.is_server_error());
+        assert!(!err.is_throttled());
+    }
+
     #[tokio::test]
     async fn list_matching_nodes() {
         let mut consul_client = init_consul_client().await;
@@ -579,6 +1562,7 @@ mod tests {
                     service_name: "memcached-1".to_string(),
                     ip: "1.2.2.15".to_string(),
                     port: 11213,
+                    protocol: None,
                 },
             ),
             (
@@ -587,9 +1571,68 @@ mod tests {
                     service_name: "memcached-1".to_string(),
                     ip: "1.2.2.16".to_string(),
                     port: 11213,
+                    protocol: None,
                 },
             ),
         ]);
-        assert_eq!(ServiceNodes { index: 110, nodes }, res);
+        assert_eq!(
+            ServiceNodes {
+                index: 110,
+                services: vec!["memcached-1".to_string()],
+                nodes
+            },
+            res
+        );
+    }
+
+    #[tokio::test]
+    async fn list_matching_nodes_throttled() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/catalog/services"))
+            .respond_with(ResponseTemplate::new(429))
+            .mount(&mock_server)
+            .await;
+        let mut consul_client = ConsulClient::new(mock_server.uri());
+
+        let err = consul_client
+            .list_matching_nodes(0, "memcached")
+            .await
+            .err()
+            .unwrap();
+        assert!(matches!(err, ConsulError::Status { status: 429, .. }));
+        assert!(err.is_throttled());
+        assert!(!err.is_server_error());
+    }
+
+    #[tokio::test]
+    async fn list_matching_nodes_token_file() {
+        let mock_server = MockServer::start().await;
+        for (token, queries) in [("first", 1), ("rotated", 2)] {
+            Mock::given(method("GET"))
+                .and(path("/v1/catalog/services"))
+                .and(header("x-consul-token", token))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_string("{\"memcached-1\":[\"net\"]}")
+                        .insert_header("x-consul-index", "110"),
+                )
+                .expect(queries)
+                .mount(&mock_server)
+                .await;
+        }
+        let token_file =
+            std::env::temp_dir().join(format!("probes-consul-token-{}", std::process::id()));
+        std::fs::write(&token_file, "first\n").unwrap();
+        let mut consul_client = ConsulClient::new(mock_server.uri())
+            .with_token("ignored", token_file.to_str().unwrap());
+
+        consul_client.list_matching_nodes(0, "other").await.unwrap();
+        std::fs::write(&token_file, "rotated\n").unwrap();
+        consul_client.list_matching_nodes(0, "other").await.unwrap();
+
+        // The last token read is kept while the file is missing
+        std::fs::remove_file(&token_file).unwrap();
+        consul_client.list_matching_nodes(0, "other").await.unwrap();
     }
 }
@@ -0,0 +1,414 @@
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, BytesMut};
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::time::error::Elapsed;
+use tracing::{info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+// Recursion desired
+const QUERY_FLAGS: u16 = 0x0100;
+const HEADER_LEN: usize = 12;
+
+// Max size of a response without EDNS
+const MAX_RESPONSE_SIZE: usize = 512;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// Name resolved and records expected by the probe, only set once from main
+static DNS_CONFIG: OnceLock<DnsConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+struct DnsConfig {
+    name: String,
+    // Empty to accept any A record
+    expected_records: Vec<Ipv4Addr>,
+}
+
+impl Default for DnsConfig {
+    fn default() -> Self {
+        DnsConfig {
+            name: "localhost".to_string(),
+            expected_records: Vec::new(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum DnsClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid name {0}.")]
+    InvalidName(String),
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("Response code {0}.")]
+    ResponseCode(String),
+    #[error("Unexpected records {records:?}, expected {expected:?}.")]
+    UnexpectedRecords {
+        records: Vec<Ipv4Addr>,
+        expected: Vec<Ipv4Addr>,
+    },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Set the name resolved and the records expected by the probe
+///
+/// # Arguments
+///
+/// * `name` - name resolved on each dns server
+/// * `expected_records` - comma separated list of expected A records, empty for any
+///
+pub fn set_config(name: &str, expected_records: &str) -> Result<(), String> {
+    let expected_records = expected_records
+        .split(',')
+        .map(str::trim)
+        .filter(|record| !record.is_empty())
+        .map(|record| {
+            record
+                .parse()
+                .map_err(|_| format!("Invalid expected record {record}"))
+        })
+        .collect::<Result<Vec<Ipv4Addr>, String>>()?;
+    info!("Resolve {} on dns nodes", name);
+    DNS_CONFIG
+        .set(DnsConfig {
+            name: name.to_string(),
+            expected_records,
+        })
+        .map_err(|_| "Dns config is already initialized".to_string())
+}
+
+/// Return the name of a response code
+pub(crate) fn rcode_name(rcode: u16) -> String {
+    match rcode {
+        0 => "NOERROR".to_string(),
+        1 => "FORMERR".to_string(),
+        2 => "SERVFAIL".to_string(),
+        3 => "NXDOMAIN".to_string(),
+        4 => "NOTIMP".to_string(),
+        5 => "REFUSED".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Encode a query
+///
+/// # Arguments
+///
+/// * `id` - id returned in the response
+/// * `name` - name resolved
+/// * `record_type` - type of the records queried
+///
+pub(crate) fn encode_query(
+    id: u16,
+    name: &str,
+    record_type: u16,
+) -> Result<Vec<u8>, DnsClientError> {
+    let mut query = BytesMut::with_capacity(HEADER_LEN + name.len() + 6);
+    query.put_u16(id);
+    query.put_u16(QUERY_FLAGS);
+    // One question, no answer, authority nor additional record
+    query.put_u16(1);
+    query.put_slice(&[0; 6]);
+    for label in name.trim_end_matches('.').split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(DnsClientError::InvalidName(name.to_string()));
+        }
+        query.put_u8(label.len() as u8);
+        query.put_slice(label.as_bytes());
+    }
+    query.put_u8(0);
+    query.put_u16(record_type);
+    query.put_u16(CLASS_IN);
+    Ok(query.to_vec())
+}
+
+/// Skip a possibly compressed name
+fn skip_name(response: &mut &[u8]) -> Result<(), DnsClientError> {
+    loop {
+        if response.is_empty() {
+            return Err(DnsClientError::InvalidResponse(
+                "truncated name".to_string(),
+            ));
+        }
+        let len = response.get_u8();
+        match len {
+            0 => return Ok(()),
+            // Pointer, the name ends with it
+            len if len & 0xc0 == 0xc0 => {
+                if response.is_empty() {
+                    return Err(DnsClientError::InvalidResponse(
+                        "truncated pointer".to_string(),
+                    ));
+                }
+                response.advance(1);
+                return Ok(());
+            }
+            len => {
+                if response.len() < len as usize {
+                    return Err(DnsClientError::InvalidResponse(
+                        "truncated label".to_string(),
+                    ));
+                }
+                response.advance(len as usize);
+            }
+        }
+    }
+}
+
+/// Parse a response
+///
+/// # Arguments
+///
+/// * `id` - id of the query
+/// * `response` - datagram received from the node
+///
+/// # Return
+///
+/// * Response code and A records of the answer section
+///
+fn parse_response(id: u16, mut response: &[u8]) -> Result<(u16, Vec<Ipv4Addr>), DnsClientError> {
+    if response.len() < HEADER_LEN {
+        return Err(DnsClientError::InvalidResponse(
+            "truncated header".to_string(),
+        ));
+    }
+    let response_id = response.get_u16();
+    if response_id != id {
+        return Err(DnsClientError::InvalidResponse(format!(
+            "unexpected id {response_id}, expected {id}"
+        )));
+    }
+    let rcode = response.get_u16() & 0x000f;
+    let question_count = response.get_u16();
+    let answer_count = response.get_u16();
+    // Authority and additional records
+    response.advance(4);
+
+    for _ in 0..question_count {
+        skip_name(&mut response)?;
+        if response.len() < 4 {
+            return Err(DnsClientError::InvalidResponse(
+                "truncated question".to_string(),
+            ));
+        }
+        response.advance(4);
+    }
+    let mut records = Vec::new();
+    for _ in 0..answer_count {
+        skip_name(&mut response)?;
+        if response.len() < 10 {
+            return Err(DnsClientError::InvalidResponse(
+                "truncated answer".to_string(),
+            ));
+        }
+        let record_type = response.get_u16();
+        let record_class = response.get_u16();
+        // Ttl
+        response.advance(4);
+        let len = response.get_u16() as usize;
+        if response.len() < len {
+            return Err(DnsClientError::InvalidResponse(
+                "truncated record".to_string(),
+            ));
+        }
+        if record_type == TYPE_A && record_class == CLASS_IN && len == 4 {
+            records.push(Ipv4Addr::new(
+                response[0],
+                response[1],
+                response[2],
+                response[3],
+            ));
+        }
+        response.advance(len);
+    }
+    Ok((rcode, records))
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, DnsClientError> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+    socket.connect(addr).await?;
+    Ok(Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        socket,
+        id: 0,
+        config: DNS_CONFIG.get().cloned().unwrap_or_default(),
+    })
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    socket: UdpSocket,
+    id: u16,
+    config: DnsConfig,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one A query and check the records
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), DnsClientError> {
+        let mut records = self.handler_with_timeout("query").await?;
+        if self.config.expected_records.is_empty() {
+            return Ok(());
+        }
+        records.sort();
+        let mut expected = self.config.expected_records.clone();
+        expected.sort();
+        if records != expected {
+            return Err(DnsClientError::UnexpectedRecords { records, expected });
+        }
+        Ok(())
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+    ) -> Result<Vec<Ipv4Addr>, DnsClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type)).await {
+            Ok(records_res) => records_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(DnsClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform dns request
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    ///
+    #[instrument(skip(self))]
+    pub async fn handle_request(
+        &mut self,
+        cmd_type: &str,
+    ) -> Result<Vec<Ipv4Addr>, DnsClientError> {
+        let start = Instant::now();
+        self.id = self.id.wrapping_add(1);
+        self.socket
+            .send(&encode_query(self.id, &self.config.name, TYPE_A)?)
+            .await?;
+
+        let mut buffer = [0; MAX_RESPONSE_SIZE];
+        let (rcode, records) = loop {
+            let len = self.socket.recv(&mut buffer).await?;
+            // Skip late responses of previous queries
+            if len >= 2 && buffer[..2] != self.id.to_be_bytes() {
+                continue;
+            }
+            break parse_response(self.id, &buffer[..len])?;
+        };
+        let elapsed = start.elapsed();
+        let status = rcode_name(rcode);
+        self.request_metrics.count_request(&status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+
+        if rcode != 0 {
+            return Err(DnsClientError::ResponseCode(status));
+        }
+        Ok(records)
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use tokio::net::UdpSocket;
+
+    use crate::dns::{connect, encode_query, parse_response, rcode_name, DnsClientError, TYPE_A};
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+
+    fn response(query: &[u8], rcode: u8) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2] = 0x81;
+        response[3] = 0x80 | rcode;
+        // One answer pointing to the question name
+        response[7] = 1;
+        response.extend_from_slice(&[0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 60, 0, 4, 127, 0, 0, 1]);
+        response
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(
+            vec![0, 7, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0, 1, b'a', 2, b'b', b'c', 0, 0, 1, 0, 1],
+            encode_query(7, "a.bc.", TYPE_A).unwrap()
+        );
+        assert!(matches!(
+            encode_query(7, "a..bc", TYPE_A),
+            Err(DnsClientError::InvalidName(_))
+        ));
+    }
+
+    #[test]
+    fn parse() {
+        let query = encode_query(7, "localhost", TYPE_A).unwrap();
+        assert_eq!(
+            (0, vec![Ipv4Addr::new(127, 0, 0, 1)]),
+            parse_response(7, &response(&query, 0)).unwrap()
+        );
+        assert_eq!(3, parse_response(7, &response(&query, 3)).unwrap().0);
+        assert!(parse_response(8, &response(&query, 0)).is_err());
+        assert!(parse_response(7, &response(&query, 0)[..20]).is_err());
+        assert_eq!("NXDOMAIN", rcode_name(3));
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            let (len, peer) = server.recv_from(&mut buffer).await.unwrap();
+            server
+                .send_to(&response(&buffer[..len], 0), peer)
+                .await
+                .unwrap();
+        });
+
+        let mut client = connect("dns_probe", &addr).await.unwrap();
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["dns_probe", &addr, "NOERROR", "query"])
+                .unwrap()
+                .get()
+        );
+    }
+}
@@ -0,0 +1,386 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use hyper::body::HttpBody;
+use hyper::header::HOST;
+use hyper::{Body, Request, Response};
+use serde_json::{json, Value};
+use thiserror::Error;
+use tokio::net::UnixStream;
+use tokio::time::sleep;
+use tracing::{debug, info, warn};
+
+use crate::probes::discovery::{
+    url_encode, Discovery, DiscoveryFuture, PollInterval, ServiceNode, ServiceNodes,
+};
+use crate::probes::protocol::Protocol;
+
+// Container label enabling the probing of the container
+const PROBE_ENABLE_LABEL: &str = "probe.enable";
+// Container label selecting the container port whose published port is probed, all if missing
+const PROBE_PORT_LABEL: &str = "probe.port";
+// Container label naming the cluster of the container, compose service or container name if missing
+const PROBE_CLUSTER_NAME_LABEL: &str = "probe.cluster_name";
+const COMPOSE_SERVICE_LABEL: &str = "com.docker.compose.service";
+// Container label declaring the protocol used to probe the container
+const PROBE_PROTOCOL_LABEL: &str = "probe.protocol";
+
+#[derive(Error, Debug)]
+pub enum DockerError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Issue query: {uri} - status code: {status}")]
+    Status { uri: String, status: u16 },
+    #[error("Invalid json: {source}")]
+    Json {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("Event stream closed")]
+    EventsClosed,
+}
+
+// List containers with the probing label through the docker engine api
+#[derive(Debug)]
+pub struct DockerClient {
+    // Unix socket of the docker engine
+    socket: PathBuf,
+}
+
+/// Returns the query filtering containers with the probing label
+///
+/// # Arguments
+///
+/// * `filters` - docker filters, the probing label is added
+///
+fn filters_query(mut filters: Value) -> String {
+    filters["label"] = json!([format!("{PROBE_ENABLE_LABEL}=true")]);
+    format!("filters={}", url_encode(&filters.to_string()))
+}
+
+/// Create the nodes of the published ports of the containers
+///
+/// A port published on all interfaces is probed on localhost
+///
+/// # Arguments
+///
+/// * `containers` - json of the containers listed by the docker engine
+///
+fn containers_to_nodes(containers: &[Value]) -> ServiceNodes {
+    let mut services = BTreeSet::new();
+    let mut nodes = HashMap::new();
+    for container in containers {
+        let label = |name: &str| {
+            container
+                .get("Labels")
+                .and_then(|labels| labels.get(name))
+                .and_then(Value::as_str)
+        };
+        let container_name = container
+            .get("Names")
+            .and_then(|names| names.get(0))
+            .and_then(Value::as_str)
+            .map(|name| name.trim_start_matches('/'));
+        let service_name = match label(PROBE_CLUSTER_NAME_LABEL)
+            .or_else(|| label(COMPOSE_SERVICE_LABEL))
+            .or(container_name)
+        {
+            Some(service_name) => service_name.to_string(),
+            None => continue,
+        };
+        let probe_port = match label(PROBE_PORT_LABEL).map(str::parse::<u64>) {
+            Some(Ok(probe_port)) => Some(probe_port),
+            Some(Err(_)) => {
+                warn!(
+                    "Invalid {} label on container {}",
+                    PROBE_PORT_LABEL, service_name
+                );
+                continue;
+            }
+            None => None,
+        };
+        let protocol =
+            label(PROBE_PROTOCOL_LABEL).and_then(|protocol| match protocol.parse::<Protocol>() {
+                Ok(protocol) => Some(protocol),
+                Err(issue) => {
+                    warn!("Invalid probe protocol: {}", issue);
+                    None
+                }
+            });
+
+        let ports = container
+            .get("Ports")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+            .filter(|port| port.get("Type").and_then(Value::as_str) == Some("tcp"))
+            .filter(|port| {
+                probe_port.map_or(true, |probe_port| {
+                    port.get("PrivatePort").and_then(Value::as_u64) == Some(probe_port)
+                })
+            });
+        for port in ports {
+            let public_port = match port.get("PublicPort").and_then(Value::as_u64) {
+                Some(public_port) => public_port as u16,
+                // Not published
+                None => continue,
+            };
+            let ip = match port.get("IP").and_then(Value::as_str) {
+                None | Some("") | Some("0.0.0.0") | Some("::") => "127.0.0.1",
+                Some(ip) => ip,
+            };
+            let service_node = ServiceNode {
+                service_name: service_name.clone(),
+                ip: ip.to_string(),
+                port: public_port,
+                protocol,
+            };
+            nodes.insert(service_node.to_string(), service_node);
+        }
+        services.insert(service_name);
+    }
+    ServiceNodes {
+        index: 0,
+        services: services.into_iter().collect(),
+        nodes,
+    }
+}
+
+impl DockerClient {
+    /// Returns a docker engine client
+    ///
+    /// # Arguments
+    ///
+    /// * `socket` - path of the unix socket of the docker engine
+    ///
+    pub fn new(socket: &str) -> Result<Self, String> {
+        if socket.is_empty() {
+            return Err("No docker socket".to_string());
+        }
+        info!(
+            "List containers with label {}=true from {}",
+            PROBE_ENABLE_LABEL, socket
+        );
+        Ok(DockerClient {
+            socket: PathBuf::from(socket),
+        })
+    }
+
+    /// Send a GET request on a new connection to the docker socket
+    ///
+    /// # Arguments
+    ///
+    /// * `uri` - path and query of the request
+    ///
+    async fn get(&self, uri: &str) -> Result<Response<Body>, DockerError> {
+        let stream = UnixStream::connect(&self.socket).await?;
+        let (mut sender, connection) = hyper::client::conn::handshake(stream).await?;
+        tokio::spawn(async move {
+            if let Err(err) = connection.await {
+                debug!("Docker connection closed: {}", err);
+            }
+        });
+
+        let request = Request::builder()
+            .uri(uri)
+            .header(HOST, "docker")
+            .body(Body::empty())?;
+        let response = sender.send_request(request).await?;
+        if !response.status().is_success() {
+            return Err(DockerError::Status {
+                uri: uri.to_string(),
+                status: response.status().as_u16(),
+            });
+        }
+        Ok(response)
+    }
+
+    /// List the running containers with the probing label
+    ///
+    /// # Return
+    ///
+    /// * ServiceNodes - one node per probed published port of the containers
+    ///
+    pub async fn list_nodes(&self) -> Result<ServiceNodes, DockerError> {
+        let uri = format!(
+            "/containers/json?{}",
+            filters_query(json!({"status": ["running"]}))
+        );
+        let response = self.get(&uri).await?;
+        let bytes = hyper::body::to_bytes(response.into_body()).await?;
+        let containers: Vec<Value> = serde_json::from_slice(&bytes)?;
+        Ok(containers_to_nodes(&containers))
+    }
+
+    /// Wait for a container with the probing label to start or stop
+    pub async fn container_event(&self) -> Result<(), DockerError> {
+        let uri = format!(
+            "/events?{}",
+            filters_query(json!({"type": ["container"], "event": ["start", "die"]}))
+        );
+        let mut body = self.get(&uri).await?.into_body();
+        match body.data().await {
+            Some(chunk) => {
+                debug!("Docker event: {:?}", chunk?);
+                Ok(())
+            }
+            None => Err(DockerError::EventsClosed),
+        }
+    }
+}
+
+// Discovery listing the containers again on each container start or stop, or on each interval
+#[derive(Debug)]
+pub struct DockerDiscovery {
+    docker_client: DockerClient,
+    poll_interval: PollInterval,
+}
+
+impl DockerDiscovery {
+    /// Returns a DockerDiscovery
+    ///
+    /// # Arguments
+    ///
+    /// * `docker_client` - a docker engine client
+    /// * `refresh_interval` - max interval between each listing of the containers
+    ///
+    pub fn new(docker_client: DockerClient, refresh_interval: Duration) -> Self {
+        DockerDiscovery {
+            docker_client,
+            poll_interval: PollInterval::new(refresh_interval),
+        }
+    }
+}
+
+impl fmt::Display for DockerDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "docker containers")
+    }
+}
+
+impl Discovery for DockerDiscovery {
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+        Box::pin(async move {
+            let docker_client = &self.docker_client;
+            let interval = self.poll_interval.interval();
+            self.poll_interval
+                .tick_or(async move {
+                    if let Err(err) = docker_client.container_event().await {
+                        // Rely on the interval only until the next listing
+                        warn!("Failed to watch docker events: {}", err);
+                        sleep(interval).await;
+                    }
+                })
+                .await;
+            self.docker_client.list_nodes().await.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+
+    use hyper::server::conn::Http;
+    use hyper::service::service_fn;
+    use hyper::{Body, Request, Response};
+    use serde_json::json;
+    use tokio::net::UnixListener;
+
+    use crate::docker::{containers_to_nodes, filters_query, DockerClient};
+    use crate::probes::protocol::Protocol;
+
+    fn containers() -> serde_json::Value {
+        json!([
+            {
+                "Names": ["/staging-redis-1"],
+                "Labels": {"probe.enable": "true", "probe.port": "6379",
+                    "probe.protocol": "redis", "com.docker.compose.service": "redis"},
+                "Ports": [
+                    {"IP": "0.0.0.0", "PrivatePort": 6379, "PublicPort": 32768, "Type": "tcp"},
+                    {"IP": "::", "PrivatePort": 6379, "PublicPort": 32768, "Type": "tcp"},
+                    {"IP": "0.0.0.0", "PrivatePort": 8080, "PublicPort": 32769, "Type": "tcp"}
+                ]
+            },
+            {
+                "Names": ["/memcached"],
+                "Labels": {"probe.enable": "true", "probe.cluster_name": "cache"},
+                "Ports": [
+                    {"IP": "10.0.0.1", "PrivatePort": 11211, "PublicPort": 11211, "Type": "tcp"},
+                    {"IP": "10.0.0.1", "PrivatePort": 11211, "PublicPort": 11211, "Type": "udp"},
+                    {"PrivatePort": 9150, "Type": "tcp"}
+                ]
+            }
+        ])
+    }
+
+    #[test]
+    fn query() {
+        let query = filters_query(json!({"status": ["running"]}));
+        assert!(query.starts_with("filters=%7B"), "{query}");
+        assert!(
+            query.contains("%22label%22%3A%5B%22probe.enable%3Dtrue%22%5D"),
+            "{query}"
+        );
+        assert!(
+            query.contains("%22status%22%3A%5B%22running%22%5D"),
+            "{query}"
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let service_nodes = containers_to_nodes(containers().as_array().unwrap());
+        assert_eq!(
+            vec!["cache".to_string(), "redis".to_string()],
+            service_nodes.services
+        );
+        assert_eq!(2, service_nodes.nodes.len());
+        let service_node = service_nodes.nodes.get("redis:127.0.0.1:32768").unwrap();
+        assert_eq!(Some(Protocol::Redis), service_node.protocol);
+        assert!(service_nodes.nodes.contains_key("cache:10.0.0.1:11211"));
+    }
+
+    #[tokio::test]
+    async fn list_nodes() {
+        let socket =
+            std::env::temp_dir().join(format!("probes-docker-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&socket);
+        let listener = UnixListener::bind(&socket).unwrap();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|request: Request<Body>| async move {
+                    let body = match request.uri().path() {
+                        "/containers/json" => containers().to_string(),
+                        _ => "{\"Type\": \"container\", \"Action\": \"start\"}\n".to_string(),
+                    };
+                    Ok::<_, Infallible>(Response::new(Body::from(body)))
+                });
+                tokio::spawn(Http::new().serve_connection(stream, service));
+            }
+        });
+
+        let docker_client = DockerClient::new(socket.to_str().unwrap()).unwrap();
+        let service_nodes = docker_client.list_nodes().await.unwrap();
+        assert_eq!(2, service_nodes.nodes.len());
+        docker_client.container_event().await.unwrap();
+        std::fs::remove_file(&socket).unwrap();
+        assert!(DockerClient::new("").is_err());
+    }
+}
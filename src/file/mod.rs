@@ -0,0 +1,410 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::time::Duration;
+use std::time::SystemTime;
+
+use serde_json::Value;
+use thiserror::Error;
+use tracing::{debug, info};
+
+use crate::probes::discovery::{
+    Discovery, DiscoveryFuture, PollInterval, ServiceNode, ServiceNodes,
+};
+use crate::probes::protocol::Protocol;
+use crate::probes::state::PROBER_STATE;
+
+// Label of a file_sd target group naming the cluster of its targets, job label used if missing
+const CLUSTER_NAME_LABEL: &str = "cluster_name";
+const JOB_LABEL: &str = "job";
+// Label of a file_sd target group declaring the protocol of its targets
+const PROBE_PROTOCOL_LABEL: &str = "probe_protocol";
+
+#[derive(Error, Debug)]
+pub enum FileError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid yaml or json: {source}")]
+    Yaml {
+        #[from]
+        source: serde_yaml::Error,
+    },
+    #[error("Invalid targets: {0}")]
+    InvalidTargets(String),
+}
+
+// Read nodes to probe from a targets file, reloaded when it changes
+#[derive(Debug)]
+pub struct FileClient {
+    path: PathBuf,
+    // Modification time and size of the last successfully loaded file
+    loaded_version: Option<(SystemTime, u64)>,
+}
+
+/// Parse an ip:port or host:port target
+fn parse_target(service_name: &str, target: &Value) -> Result<(String, u16), FileError> {
+    let invalid =
+        || FileError::InvalidTargets(format!("invalid target {target} of {service_name}"));
+    let (host, port) = target
+        .as_str()
+        .and_then(|target| target.rsplit_once(':'))
+        .ok_or_else(invalid)?;
+    let port = port.parse().map_err(|_| invalid())?;
+    if host.is_empty() {
+        return Err(invalid());
+    }
+    Ok((host.to_string(), port))
+}
+
+/// Parse the protocol declared for the targets of a service
+fn parse_protocol(
+    service_name: &str,
+    protocol: Option<&str>,
+) -> Result<Option<Protocol>, FileError> {
+    protocol
+        .map(|protocol| {
+            protocol
+                .parse::<Protocol>()
+                .map_err(|issue| FileError::InvalidTargets(format!("{service_name}: {issue}")))
+        })
+        .transpose()
+}
+
+/// Parse target groups in the Prometheus file_sd and http_sd format
+///
+/// The cluster name is taken from the cluster_name label, or the job label if missing,
+/// and the protocol from the probe_protocol label
+///
+/// ```json
+/// [{"targets": ["10.0.0.1:11211"], "labels": {"job": "memcached"}}]
+/// ```
+///
+/// # Arguments
+///
+/// * `groups` - target groups with their labels
+///
+pub(crate) fn parse_target_groups(groups: &[Value]) -> Result<ServiceNodes, FileError> {
+    let mut services = BTreeSet::new();
+    let mut nodes = HashMap::new();
+    for group in groups {
+        let labels = group.get("labels");
+        let label = |name: &str| {
+            labels
+                .and_then(|labels| labels.get(name))
+                .and_then(Value::as_str)
+        };
+        let service_name = label(CLUSTER_NAME_LABEL)
+            .or_else(|| label(JOB_LABEL))
+            .ok_or_else(|| {
+                FileError::InvalidTargets(format!(
+                    "missing {CLUSTER_NAME_LABEL} or {JOB_LABEL} label in {group}"
+                ))
+            })?
+            .to_string();
+        let protocol = parse_protocol(&service_name, label(PROBE_PROTOCOL_LABEL))?;
+        let targets = group
+            .get("targets")
+            .and_then(Value::as_array)
+            .ok_or_else(|| {
+                FileError::InvalidTargets(format!("missing targets of {service_name}"))
+            })?;
+        for target in targets {
+            let (ip, port) = parse_target(&service_name, target)?;
+            let service_node = ServiceNode {
+                service_name: service_name.clone(),
+                ip,
+                port,
+                protocol,
+            };
+            nodes.insert(service_node.to_string(), service_node);
+        }
+        services.insert(service_name);
+    }
+    Ok(ServiceNodes {
+        index: 0,
+        services: services.into_iter().collect(),
+        nodes,
+    })
+}
+
+/// Parse the content of a targets file
+///
+/// Targets are listed by service, either directly or with the protocol of the service,
+/// a list of target groups in the Prometheus file_sd format is also accepted
+///
+/// ```yaml
+/// memcached:
+///   - 10.0.0.1:11211
+/// sessions:
+///   protocol: redis
+///   targets:
+///     - 10.0.0.2:6379
+/// ```
+///
+/// # Arguments
+///
+/// * `content` - yaml or json content of the file, json being valid yaml
+///
+fn parse_targets(content: &str) -> Result<ServiceNodes, FileError> {
+    let root: Value = serde_yaml::from_str(content)?;
+    let services = match root {
+        Value::Object(services) => services,
+        Value::Array(groups) => return parse_target_groups(&groups),
+        // Empty file
+        Value::Null => Default::default(),
+        _ => {
+            return Err(FileError::InvalidTargets(
+                "expected a map of services or a list of target groups".to_string(),
+            ))
+        }
+    };
+
+    let mut service_nodes = ServiceNodes {
+        index: 0,
+        services: Vec::new(),
+        nodes: HashMap::new(),
+    };
+    for (service_name, spec) in services {
+        let (targets, protocol) = match &spec {
+            Value::Array(targets) => (targets, None),
+            Value::Object(spec) => {
+                let targets = spec
+                    .get("targets")
+                    .and_then(Value::as_array)
+                    .ok_or_else(|| {
+                        FileError::InvalidTargets(format!("missing targets of {service_name}"))
+                    })?;
+                let protocol =
+                    parse_protocol(&service_name, spec.get("protocol").and_then(Value::as_str))?;
+                (targets, protocol)
+            }
+            _ => {
+                return Err(FileError::InvalidTargets(format!(
+                    "expected a list of targets or a map for {service_name}"
+                )))
+            }
+        };
+        for target in targets {
+            let (ip, port) = parse_target(&service_name, target)?;
+            let service_node = ServiceNode {
+                service_name: service_name.clone(),
+                ip,
+                port,
+                protocol,
+            };
+            service_nodes
+                .nodes
+                .insert(service_node.to_string(), service_node);
+        }
+        service_nodes.services.push(service_name);
+    }
+    Ok(service_nodes)
+}
+
+impl FileClient {
+    /// Returns a targets file client
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - path of the yaml or json targets file
+    ///
+    pub fn new(path: &str) -> Result<Self, String> {
+        if path.is_empty() {
+            return Err("No targets file".to_string());
+        }
+        info!("Read nodes to probe from {}", path);
+        Ok(FileClient {
+            path: PathBuf::from(path),
+            loaded_version: None,
+        })
+    }
+
+    /// Load the targets file if it changed since the last successful load
+    ///
+    /// A file failing to load is loaded again on the next call
+    ///
+    /// # Return
+    ///
+    /// * ServiceNodes - the nodes of the file, None if it did not change
+    ///
+    pub async fn list_nodes_if_changed(&mut self) -> Result<Option<ServiceNodes>, FileError> {
+        let metadata = tokio::fs::metadata(&self.path).await?;
+        let version = (metadata.modified()?, metadata.len());
+        if self.loaded_version == Some(version) {
+            return Ok(None);
+        }
+
+        debug!("Load targets file {}", self.path.display());
+        let content = tokio::fs::read_to_string(&self.path).await?;
+        let service_nodes = parse_targets(&content)?;
+        self.loaded_version = Some(version);
+        Ok(Some(service_nodes))
+    }
+}
+
+// Discovery loading the targets file again when it changed, checked on each interval
+#[derive(Debug)]
+pub struct FileDiscovery {
+    file_client: FileClient,
+    poll_interval: PollInterval,
+}
+
+impl FileDiscovery {
+    /// Returns a FileDiscovery
+    ///
+    /// # Arguments
+    ///
+    /// * `file_client` - a targets file client
+    /// * `refresh_interval` - interval between each check of the file
+    ///
+    pub fn new(file_client: FileClient, refresh_interval: Duration) -> Self {
+        FileDiscovery {
+            file_client,
+            poll_interval: PollInterval::new(refresh_interval),
+        }
+    }
+}
+
+impl fmt::Display for FileDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "targets file")
+    }
+}
+
+impl Discovery for FileDiscovery {
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+        Box::pin(async move {
+            loop {
+                self.poll_interval.tick().await;
+                match self.file_client.list_nodes_if_changed().await? {
+                    Some(service_nodes) => return Ok(service_nodes),
+                    // Nodes are unchanged, the discovery is still up to date
+                    None => PROBER_STATE.discovery_succeeded(),
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::file::{parse_targets, FileClient, FileError};
+    use crate::probes::protocol::Protocol;
+
+    #[test]
+    fn parse() {
+        let service_nodes = parse_targets(
+            "memcached:\n  - 10.0.0.1:11211\n  - 10.0.0.2:11211\n\
+            sessions:\n  protocol: redis\n  targets: [\"10.0.0.3:6379\"]\n",
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["memcached".to_string(), "sessions".to_string()],
+            service_nodes.services
+        );
+        assert_eq!(3, service_nodes.nodes.len());
+        assert_eq!(
+            Some(Protocol::Redis),
+            service_nodes
+                .nodes
+                .get("sessions:10.0.0.3:6379")
+                .unwrap()
+                .protocol
+        );
+
+        // Json is valid yaml
+        let service_nodes = parse_targets("{\"memcached\": [\"10.0.0.1:11211\"]}").unwrap();
+        assert!(service_nodes.nodes.contains_key("memcached:10.0.0.1:11211"));
+        assert!(parse_targets("").unwrap().nodes.is_empty());
+    }
+
+    #[test]
+    fn parse_file_sd() {
+        let service_nodes = parse_targets(
+            "[{\"targets\": [\"10.0.0.1:11211\", \"10.0.0.2:11211\"], \"labels\": {\"job\": \"memcached\"}},\
+            {\"targets\": [\"10.0.0.3:6379\"], \"labels\": {\"job\": \"blackbox\", \
+            \"cluster_name\": \"sessions\", \"probe_protocol\": \"redis\"}}]",
+        )
+        .unwrap();
+        assert_eq!(
+            vec!["memcached".to_string(), "sessions".to_string()],
+            service_nodes.services
+        );
+        assert_eq!(3, service_nodes.nodes.len());
+        let service_node = service_nodes.nodes.get("sessions:10.0.0.3:6379").unwrap();
+        assert_eq!(Some(Protocol::Redis), service_node.protocol);
+        assert_eq!(
+            None,
+            service_nodes
+                .nodes
+                .get("memcached:10.0.0.1:11211")
+                .unwrap()
+                .protocol
+        );
+
+        for content in [
+            "[{\"targets\": [\"10.0.0.1:11211\"]}]",
+            "[{\"labels\": {\"job\": \"memcached\"}}]",
+            "[{\"targets\": [\"10.0.0.1:11211\"], \"labels\": {\"job\": \"memcached\", \
+            \"probe_protocol\": \"http\"}}]",
+        ] {
+            assert!(
+                matches!(parse_targets(content), Err(FileError::InvalidTargets(_))),
+                "{content}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_invalid() {
+        for content in [
+            "10.0.0.1:11211",
+            "memcached: 10.0.0.1:11211",
+            "memcached:\n  - 10.0.0.1",
+            "memcached:\n  - 10.0.0.1:port",
+            "memcached:\n  protocol: http\n  targets: [\"10.0.0.1:80\"]",
+            "memcached:\n  protocol: redis",
+        ] {
+            assert!(
+                matches!(parse_targets(content), Err(FileError::InvalidTargets(_))),
+                "{content}"
+            );
+        }
+        assert!(matches!(
+            parse_targets("memcached: ["),
+            Err(FileError::Yaml { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn reload() {
+        let path = std::env::temp_dir().join(format!("probes-targets-{}.yml", std::process::id()));
+        tokio::fs::write(&path, "memcached:\n  - 10.0.0.1:11211\n")
+            .await
+            .unwrap();
+
+        let mut file_client = FileClient::new(path.to_str().unwrap()).unwrap();
+        let service_nodes = file_client.list_nodes_if_changed().await.unwrap().unwrap();
+        assert_eq!(1, service_nodes.nodes.len());
+        assert!(file_client.list_nodes_if_changed().await.unwrap().is_none());
+
+        tokio::fs::write(
+            &path,
+            "memcached:\n  - 10.0.0.1:11211\n  - 10.0.0.2:11211\n",
+        )
+        .await
+        .unwrap();
+        let service_nodes = file_client.list_nodes_if_changed().await.unwrap().unwrap();
+        assert_eq!(2, service_nodes.nodes.len());
+
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(matches!(
+            file_client.list_nodes_if_changed().await,
+            Err(FileError::Io { .. })
+        ));
+    }
+}
@@ -0,0 +1,404 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use hyper::body::HttpBody;
+use hyper::client::HttpConnector;
+use hyper::header::HeaderValue;
+use hyper::{Body, Client as HttpClient, HeaderMap, Method, Request};
+use thiserror::Error;
+use tokio::time::error::Elapsed;
+use tracing::{info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const HEALTH_CHECK_PATH: &str = "/grpc.health.v1.Health/Check";
+
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+// Service checked by the probe, only set once from main
+static GRPC_HEALTH_SERVICE: OnceLock<String> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum GrpcClientError {
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("Grpc status {status}: {message}")]
+    GrpcStatus { status: String, message: String },
+    #[error("Service is {0}.")]
+    NotServing(String),
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Set the service checked by the probe
+///
+/// # Arguments
+///
+/// * `service` - name of the service, empty for the overall server health
+///
+pub fn set_health_service(service: &str) -> Result<(), String> {
+    if service.is_empty() {
+        return Ok(());
+    }
+    info!("Check health of grpc service {}", service);
+    GRPC_HEALTH_SERVICE
+        .set(service.to_string())
+        .map_err(|_| "Grpc health service is already initialized".to_string())
+}
+
+/// Return the name of a serving status
+fn serving_status_name(status: u64) -> String {
+    match status {
+        0 => "UNKNOWN".to_string(),
+        1 => "SERVING".to_string(),
+        2 => "NOT_SERVING".to_string(),
+        3 => "SERVICE_UNKNOWN".to_string(),
+        other => other.to_string(),
+    }
+}
+
+/// Return the name of a grpc status code
+fn grpc_status_name(code: &str) -> String {
+    match code {
+        "0" => "OK",
+        "1" => "CANCELLED",
+        "2" => "UNKNOWN",
+        "3" => "INVALID_ARGUMENT",
+        "4" => "DEADLINE_EXCEEDED",
+        "5" => "NOT_FOUND",
+        "7" => "PERMISSION_DENIED",
+        "8" => "RESOURCE_EXHAUSTED",
+        "12" => "UNIMPLEMENTED",
+        "13" => "INTERNAL",
+        "14" => "UNAVAILABLE",
+        "16" => "UNAUTHENTICATED",
+        other => other,
+    }
+    .to_string()
+}
+
+/// Encode a HealthCheckRequest in a grpc message frame
+///
+/// # Arguments
+///
+/// * `service` - name of the service, empty for the overall server health
+///
+fn encode_request(service: &str) -> Bytes {
+    let mut message = BytesMut::new();
+    if !service.is_empty() {
+        // Field 1, length delimited
+        message.put_u8(0x0a);
+        put_varint(&mut message, service.len() as u64);
+        message.put_slice(service.as_bytes());
+    }
+    let mut frame = BytesMut::with_capacity(5 + message.len());
+    // Not compressed
+    frame.put_u8(0);
+    frame.put_u32(message.len() as u32);
+    frame.put_slice(&message);
+    frame.freeze()
+}
+
+fn put_varint(buffer: &mut BytesMut, mut value: u64) {
+    while value >= 0x80 {
+        buffer.put_u8((value as u8 & 0x7f) | 0x80);
+        value >>= 7;
+    }
+    buffer.put_u8(value as u8);
+}
+
+fn get_varint(buffer: &mut &[u8]) -> Result<u64, GrpcClientError> {
+    let mut value = 0;
+    for shift in (0..64).step_by(7) {
+        if buffer.is_empty() {
+            return Err(GrpcClientError::InvalidResponse(
+                "truncated varint".to_string(),
+            ));
+        }
+        let byte = buffer.get_u8();
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    Err(GrpcClientError::InvalidResponse(
+        "varint too long".to_string(),
+    ))
+}
+
+/// Parse a grpc message frame holding a HealthCheckResponse
+///
+/// # Return
+///
+/// * Serving status, unknown if not set
+///
+fn parse_response(mut frame: &[u8]) -> Result<u64, GrpcClientError> {
+    if frame.len() < 5 {
+        return Err(GrpcClientError::InvalidResponse(
+            "truncated frame".to_string(),
+        ));
+    }
+    if frame.get_u8() != 0 {
+        return Err(GrpcClientError::InvalidResponse(
+            "compressed message".to_string(),
+        ));
+    }
+    let len = frame.get_u32() as usize;
+    if frame.len() < len {
+        return Err(GrpcClientError::InvalidResponse(
+            "truncated message".to_string(),
+        ));
+    }
+    let mut message = &frame[..len];
+    let mut status = 0;
+    while !message.is_empty() {
+        let key = get_varint(&mut message)?;
+        match (key >> 3, key & 0x07) {
+            (1, 0) => status = get_varint(&mut message)?,
+            // Unknown varint field
+            (_, 0) => {
+                get_varint(&mut message)?;
+            }
+            // Unknown length delimited field
+            (_, 2) => {
+                let len = get_varint(&mut message)? as usize;
+                if message.len() < len {
+                    return Err(GrpcClientError::InvalidResponse(
+                        "truncated field".to_string(),
+                    ));
+                }
+                message.advance(len);
+            }
+            (_, wire_type) => {
+                return Err(GrpcClientError::InvalidResponse(format!(
+                    "unsupported wire type {wire_type}"
+                )))
+            }
+        }
+    }
+    Ok(status)
+}
+
+/// Return the grpc status and message from trailers or trailers-only headers
+fn grpc_status(headers: &HeaderMap) -> Option<(String, String)> {
+    let status = headers.get("grpc-status")?.to_str().ok()?.to_string();
+    let message = headers
+        .get("grpc-message")
+        .and_then(|message| message.to_str().ok())
+        .unwrap_or("")
+        .to_string();
+    Some((status, message))
+}
+
+/// Create a client checking the health of a grpc server
+///
+/// The http2 connection is opened and kept alive by the http client on first request
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node
+/// * `addr` - ip:port of the node
+///
+pub fn connect(cluster_name: &str, addr: &str) -> Client {
+    Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        service: GRPC_HEALTH_SERVICE.get().cloned().unwrap_or_default(),
+        http_client: HttpClient::builder().http2_only(true).build_http(),
+    }
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    // Empty for the overall server health
+    service: String,
+    http_client: HttpClient<HttpConnector>,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one health check, the node is down if not serving
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), GrpcClientError> {
+        let status = self.handler_with_timeout("check").await?;
+        if status != 1 {
+            return Err(GrpcClientError::NotServing(serving_status_name(status)));
+        }
+        Ok(())
+    }
+
+    async fn handler_with_timeout(&mut self, cmd_type: &str) -> Result<u64, GrpcClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type)).await {
+            Ok(status_res) => status_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(GrpcClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform grpc health check request
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    ///
+    /// # Return
+    ///
+    /// * Serving status of the service
+    ///
+    #[instrument(skip(self))]
+    pub async fn handle_request(&mut self, cmd_type: &str) -> Result<u64, GrpcClientError> {
+        let start = Instant::now();
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(format!("http://{}{}", self.addr, HEALTH_CHECK_PATH))
+            .header("content-type", HeaderValue::from_static("application/grpc"))
+            .header("te", HeaderValue::from_static("trailers"))
+            .body(Body::from(encode_request(&self.service)))?;
+        let response = self.http_client.request(request).await?;
+        let headers = response.headers().clone();
+        let mut body = response.into_body();
+        let mut frame = BytesMut::new();
+        while let Some(chunk) = body.data().await {
+            frame.extend_from_slice(&chunk?);
+        }
+        let trailers = body.trailers().await?;
+        let elapsed = start.elapsed();
+
+        let (status, message) = trailers
+            .as_ref()
+            .and_then(grpc_status)
+            .or_else(|| grpc_status(&headers))
+            .unwrap_or_else(|| ("2".to_string(), "missing grpc-status".to_string()));
+        if status != "0" {
+            let status = grpc_status_name(&status);
+            self.request_metrics.count_request(&status, cmd_type);
+            self.request_metrics
+                .observe_response_time(cmd_type, elapsed);
+            return Err(GrpcClientError::GrpcStatus { status, message });
+        }
+        let serving_status = parse_response(&frame)?;
+        self.request_metrics
+            .count_request(&serving_status_name(serving_status), cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+        Ok(serving_status)
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use std::net::TcpListener;
+
+    use hyper::service::{make_service_fn, service_fn};
+    use hyper::{Body, HeaderMap, Response, Server};
+
+    use crate::grpc::{connect, encode_request, parse_response, GrpcClientError};
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+
+    /// Start a http2 server answering health checks with a serving status
+    fn init_grpc(serving_status: u8) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        let make_service = make_service_fn(move |_| async move {
+            Ok::<_, Infallible>(service_fn(move |_request| async move {
+                let (mut sender, body) = Body::channel();
+                tokio::spawn(async move {
+                    sender
+                        .send_data(vec![0, 0, 0, 0, 2, 0x08, serving_status].into())
+                        .await
+                        .unwrap();
+                    let mut trailers = HeaderMap::new();
+                    trailers.insert("grpc-status", "0".parse().unwrap());
+                    sender.send_trailers(trailers).await.unwrap();
+                });
+                Ok::<_, Infallible>(
+                    Response::builder()
+                        .header("content-type", "application/grpc")
+                        .body(body)
+                        .unwrap(),
+                )
+            }))
+        });
+        let server = Server::from_tcp(listener)
+            .unwrap()
+            .http2_only(true)
+            .serve(make_service);
+        tokio::spawn(server);
+        addr
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(&[0, 0, 0, 0, 0][..], &encode_request("")[..]);
+        assert_eq!(
+            &[0, 0, 0, 0, 5, 0x0a, 3, b'a', b'p', b'i'][..],
+            &encode_request("api")[..]
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(1, parse_response(&[0, 0, 0, 0, 2, 0x08, 1]).unwrap());
+        assert_eq!(0, parse_response(&[0, 0, 0, 0, 0]).unwrap());
+        assert!(parse_response(&[0, 0, 0, 0, 2, 0x08]).is_err());
+        assert!(parse_response(&[1, 0, 0, 0, 0]).is_err());
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let addr = init_grpc(1);
+        let mut client = connect("grpc_probe", &addr);
+
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["grpc_probe", &addr, "SERVING", "check"])
+                .unwrap()
+                .get()
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_not_serving() {
+        let addr = init_grpc(2);
+        let mut client = connect("grpc_probe_not_serving", &addr);
+
+        assert!(matches!(
+            client.probe().await,
+            Err(GrpcClientError::NotServing(_))
+        ));
+    }
+}
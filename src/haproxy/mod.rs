@@ -0,0 +1,325 @@
+use std::collections::BTreeMap;
+use std::io;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client as HttpClient, Method, Request};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::{debug, info, instrument};
+
+use crate::probes::prometheus::{BACKEND_QUEUE_DEPTH, BACKEND_SERVERS};
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+// Server states exported for each backend
+const SERVER_STATES: [&str; 3] = ["up", "down", "other"];
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// Path of the csv stats page, only set once from main
+static HAPROXY_STATS_PATH: OnceLock<String> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum HaproxyClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Unexpected status code {0} on stats.")]
+    Status(u16),
+    #[error("Invalid stats: {0}")]
+    InvalidStats(String),
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+// Servers and queue of a backend
+#[derive(Debug, Default, PartialEq)]
+struct BackendStats {
+    up: i64,
+    down: i64,
+    // Maintenance, drain or servers without check
+    other: i64,
+    queue_depth: i64,
+}
+
+/// Set the path of the csv stats page
+///
+/// # Arguments
+///
+/// * `path` - path of the stats page on the node, empty to query the stats socket
+///
+pub fn set_stats_path(path: &str) -> Result<(), String> {
+    info!("Query haproxy stats on {}", path);
+    HAPROXY_STATS_PATH
+        .set(path.to_string())
+        .map_err(|_| "Haproxy stats path is already initialized".to_string())
+}
+
+/// Parse csv stats and aggregate them by backend
+///
+/// # Arguments
+///
+/// * `csv` - output of the show stat command, with its header line
+///
+fn parse_stats(csv: &str) -> Result<BTreeMap<String, BackendStats>, HaproxyClientError> {
+    let mut lines = csv.lines();
+    let header = lines
+        .next()
+        .and_then(|header| header.strip_prefix("# "))
+        .ok_or_else(|| HaproxyClientError::InvalidStats("missing header".to_string()))?;
+    let columns: Vec<&str> = header.split(',').collect();
+    let column = |name: &str| {
+        columns
+            .iter()
+            .position(|column| *column == name)
+            .ok_or_else(|| HaproxyClientError::InvalidStats(format!("missing column {name}")))
+    };
+    let (proxy_index, server_index) = (column("pxname")?, column("svname")?);
+    let (queue_index, status_index) = (column("qcur")?, column("status")?);
+
+    let mut backends: BTreeMap<String, BackendStats> = BTreeMap::new();
+    for line in lines.filter(|line| !line.is_empty()) {
+        let fields: Vec<&str> = line.split(',').collect();
+        let field = |index: usize| fields.get(index).copied().unwrap_or("");
+        match field(server_index) {
+            "FRONTEND" => {}
+            "BACKEND" => {
+                backends
+                    .entry(field(proxy_index).to_string())
+                    .or_default()
+                    .queue_depth = field(queue_index).parse().unwrap_or(0);
+            }
+            _ => {
+                let backend = backends.entry(field(proxy_index).to_string()).or_default();
+                match field(status_index) {
+                    status if status.starts_with("UP") => backend.up += 1,
+                    status if status.starts_with("DOWN") => backend.down += 1,
+                    _ => backend.other += 1,
+                }
+            }
+        }
+    }
+    Ok(backends)
+}
+
+/// Create a client probing the stats of a haproxy node
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node
+/// * `addr` - ip:port of the stats page or socket of the node
+///
+pub fn connect(cluster_name: &str, addr: &str) -> Client {
+    Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stats_path: HAPROXY_STATS_PATH
+            .get()
+            .cloned()
+            .unwrap_or_else(|| "/stats;csv".to_string()),
+        http_client: HttpClient::new(),
+    }
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    // Empty to query the stats socket
+    stats_path: String,
+    http_client: HttpClient<HttpConnector>,
+}
+
+impl Client {
+    /// Probe action
+    /// * fetch the csv stats
+    /// * export servers by state and queue depth of each backend
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), HaproxyClientError> {
+        let csv = self.handler_with_timeout("stats").await?;
+        for (backend, stats) in parse_stats(&csv)? {
+            let labels = [
+                self.cluster_name.as_str(),
+                self.addr.as_str(),
+                backend.as_str(),
+            ];
+            for (state, count) in SERVER_STATES
+                .into_iter()
+                .zip([stats.up, stats.down, stats.other])
+            {
+                BACKEND_SERVERS
+                    .with_label_values(&[labels[0], labels[1], labels[2], state])
+                    .set(count);
+            }
+            BACKEND_QUEUE_DEPTH
+                .with_label_values(&labels)
+                .set(stats.queue_depth);
+        }
+        Ok(())
+    }
+
+    async fn handler_with_timeout(&mut self, cmd_type: &str) -> Result<String, HaproxyClientError> {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(TIMEOUT, self.handle_request()).await {
+            Ok(result) => result,
+            Err(_timeout_elapsed) => Err(HaproxyClientError::from(_timeout_elapsed)),
+        };
+        let status = match &result {
+            Ok(_) => "OK".to_string(),
+            Err(HaproxyClientError::Status(status)) => status.to_string(),
+            Err(HaproxyClientError::Timeout { .. }) => "Timeout".to_string(),
+            Err(_) => "Error".to_string(),
+        };
+        self.request_metrics.count_request(&status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, start.elapsed().min(TIMEOUT));
+        result
+    }
+
+    /// Fetch the csv stats from the stats page or the stats socket
+    #[instrument(skip(self))]
+    pub async fn handle_request(&self) -> Result<String, HaproxyClientError> {
+        if self.stats_path.is_empty() {
+            debug!("Query haproxy stats socket: {}", self.addr);
+            let mut stream = TcpStream::connect(&self.addr).await?;
+            stream.write_all(b"show stat\n").await?;
+            // The socket is closed once the command output is sent
+            let mut csv = String::new();
+            stream.read_to_string(&mut csv).await?;
+            return Ok(csv);
+        }
+
+        debug!("Query haproxy stats: {}{}", self.addr, self.stats_path);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", self.addr, self.stats_path))
+            .body(Body::empty())?;
+        let response = self.http_client.request(request).await?;
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await?;
+        if !status.is_success() {
+            return Err(HaproxyClientError::Status(status.as_u16()));
+        }
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::haproxy::{connect, parse_stats, BackendStats, HaproxyClientError};
+    use crate::probes::prometheus::{BACKEND_QUEUE_DEPTH, BACKEND_SERVERS};
+
+    const STATS: &str = "# pxname,svname,qcur,qmax,scur,status,\n\
+        http-in,FRONTEND,,,3,OPEN,\n\
+        cache,cache1,0,0,1,UP,\n\
+        cache,cache2,0,0,0,DOWN 1/2,\n\
+        cache,cache3,0,0,0,MAINT,\n\
+        cache,BACKEND,4,9,1,UP,\n";
+
+    #[test]
+    fn parse() {
+        let backends = parse_stats(STATS).unwrap();
+        assert_eq!(1, backends.len());
+        assert_eq!(
+            &BackendStats {
+                up: 1,
+                down: 1,
+                other: 1,
+                queue_depth: 4
+            },
+            backends.get("cache").unwrap()
+        );
+        assert!(matches!(
+            parse_stats("pxname,svname\n"),
+            Err(HaproxyClientError::InvalidStats(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/stats;csv"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(STATS))
+            .mount(&mock_server)
+            .await;
+
+        let socket = mock_server.address().to_string();
+        let mut client = connect("haproxy_probe", &socket);
+        client.probe().await.unwrap();
+        for (state, count) in [("up", 1), ("down", 1), ("other", 1)] {
+            assert_eq!(
+                count,
+                BACKEND_SERVERS
+                    .get_metric_with_label_values(&["haproxy_probe", &socket, "cache", state])
+                    .unwrap()
+                    .get()
+            );
+        }
+        assert_eq!(
+            4,
+            BACKEND_QUEUE_DEPTH
+                .get_metric_with_label_values(&["haproxy_probe", &socket, "cache"])
+                .unwrap()
+                .get()
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_stats_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let socket = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 64];
+            stream.read(&mut buffer).await.unwrap();
+            stream.write_all(STATS.as_bytes()).await.unwrap();
+        });
+
+        let mut client = connect("haproxy_probe_socket", &socket);
+        client.stats_path = "".to_string();
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            BACKEND_SERVERS
+                .get_metric_with_label_values(&["haproxy_probe_socket", &socket, "cache", "up"])
+                .unwrap()
+                .get()
+        );
+    }
+}
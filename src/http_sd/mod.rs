@@ -0,0 +1,191 @@
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+use hyper::Uri;
+use serde_json::Value;
+use thiserror::Error;
+use tokio::time::error::Elapsed;
+use tokio::time::timeout;
+use tracing::{debug, info};
+
+use crate::file::{parse_target_groups, FileError};
+use crate::probes::discovery::{Discovery, DiscoveryFuture, PollInterval, ServiceNodes};
+use crate::probes::http_client::{shared_client, HttpClient};
+
+// Max time to fetch the targets from the http_sd endpoint
+const FETCH_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Error, Debug)]
+pub enum HttpSdError {
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Issue query: {uri} - status code: {status}")]
+    Status { uri: String, status: u16 },
+    #[error("Invalid json: {source}")]
+    Json {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("{source}")]
+    Targets {
+        #[from]
+        source: FileError,
+    },
+    #[error("Timeout fetching targets")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+// Fetch nodes to probe from an endpoint in the Prometheus http_sd format
+#[derive(Debug)]
+pub struct HttpSdClient {
+    url: Uri,
+    client: Arc<HttpClient>,
+}
+
+impl HttpSdClient {
+    /// Returns a http_sd client
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - url of the endpoint returning the target groups
+    ///
+    pub fn new(url: &str) -> Result<Self, String> {
+        if url.is_empty() {
+            return Err("No http_sd url".to_string());
+        }
+        let url = url
+            .parse::<Uri>()
+            .map_err(|issue| format!("Invalid http_sd url {url}: {issue}"))?;
+        info!("Fetch nodes to probe from {}", url);
+
+        Ok(HttpSdClient {
+            url,
+            client: shared_client(),
+        })
+    }
+
+    /// Fetch the target groups of the endpoint
+    ///
+    /// # Return
+    ///
+    /// * ServiceNodes - the nodes of the target groups
+    ///
+    pub async fn list_nodes(&self) -> Result<ServiceNodes, HttpSdError> {
+        timeout(FETCH_TIMEOUT, self.fetch_nodes()).await?
+    }
+
+    async fn fetch_nodes(&self) -> Result<ServiceNodes, HttpSdError> {
+        debug!("Fetch targets from {}", self.url);
+        let resp = self.client.get(self.url.clone()).await?;
+        if !resp.status().is_success() {
+            return Err(HttpSdError::Status {
+                uri: self.url.to_string(),
+                status: resp.status().as_u16(),
+            });
+        }
+
+        let bytes = hyper::body::to_bytes(resp.into_body()).await?;
+        match serde_json::from_slice::<Value>(&bytes)? {
+            Value::Array(groups) => Ok(parse_target_groups(&groups)?),
+            _ => Err(
+                FileError::InvalidTargets("expected a list of target groups".to_string()).into(),
+            ),
+        }
+    }
+}
+
+// Discovery fetching the target groups of the http_sd endpoint again on each interval
+#[derive(Debug)]
+pub struct HttpSdDiscovery {
+    http_sd_client: HttpSdClient,
+    poll_interval: PollInterval,
+}
+
+impl HttpSdDiscovery {
+    /// Returns a HttpSdDiscovery
+    ///
+    /// # Arguments
+    ///
+    /// * `http_sd_client` - a http_sd client
+    /// * `refresh_interval` - interval between each fetch of the target groups
+    ///
+    pub fn new(http_sd_client: HttpSdClient, refresh_interval: Duration) -> Self {
+        HttpSdDiscovery {
+            http_sd_client,
+            poll_interval: PollInterval::new(refresh_interval),
+        }
+    }
+}
+
+impl fmt::Display for HttpSdDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "http_sd endpoint")
+    }
+}
+
+impl Discovery for HttpSdDiscovery {
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+        Box::pin(async move {
+            self.poll_interval.tick().await;
+            self.http_sd_client.list_nodes().await.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::http_sd::{HttpSdClient, HttpSdError};
+    use crate::probes::protocol::Protocol;
+
+    #[tokio::test]
+    async fn list_nodes() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/targets"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(
+                "[{\"targets\": [\"10.0.0.1:6379\"], \
+                \"labels\": {\"cluster_name\": \"sessions\", \"probe_protocol\": \"redis\"}}]",
+            ))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/invalid"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .mount(&mock_server)
+            .await;
+
+        let http_sd_client = HttpSdClient::new(&format!("{}/targets", mock_server.uri())).unwrap();
+        let service_nodes = http_sd_client.list_nodes().await.unwrap();
+        assert_eq!(vec!["sessions".to_string()], service_nodes.services);
+        assert_eq!(
+            Some(Protocol::Redis),
+            service_nodes
+                .nodes
+                .get("sessions:10.0.0.1:6379")
+                .unwrap()
+                .protocol
+        );
+
+        let http_sd_client = HttpSdClient::new(&format!("{}/invalid", mock_server.uri())).unwrap();
+        assert!(matches!(
+            http_sd_client.list_nodes().await,
+            Err(HttpSdError::Targets { .. })
+        ));
+        let http_sd_client = HttpSdClient::new(&format!("{}/missing", mock_server.uri())).unwrap();
+        assert!(matches!(
+            http_sd_client.list_nodes().await,
+            Err(HttpSdError::Status { status: 404, .. })
+        ));
+        assert!(HttpSdClient::new("").is_err());
+    }
+}
@@ -0,0 +1,347 @@
+use std::io;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, Bytes, BytesMut};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::instrument;
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const CLIENT_ID: &str = "probes";
+
+const API_VERSIONS_KEY: i16 = 18;
+const METADATA_KEY: i16 = 3;
+// Metadata v1 is the first version where an empty topic list returns no topic
+const METADATA_VERSION: i16 = 1;
+
+// Max size of a response, metadata without topics is small
+const MAX_RESPONSE_SIZE: usize = 1024 * 1024;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Error, Debug)]
+pub enum KafkaClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("Unexpected correlation id {received}, expected {expected}.")]
+    CorrelationId { expected: i32, received: i32 },
+    #[error("Error code {error_code} on {cmd_type}.")]
+    ErrorCode { cmd_type: String, error_code: i16 },
+    #[error("No broker in metadata.")]
+    NoBroker,
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Encode a request with a v1 request header
+///
+/// # Arguments
+///
+/// * `api_key` - key of the api called
+/// * `api_version` - version of the api called
+/// * `correlation_id` - id returned in the response
+/// * `body` - encoded body of the request
+///
+fn encode_request(api_key: i16, api_version: i16, correlation_id: i32, body: &[u8]) -> Vec<u8> {
+    let mut request = BytesMut::with_capacity(14 + CLIENT_ID.len() + body.len());
+    let size = 2 + 2 + 4 + 2 + CLIENT_ID.len() + body.len();
+    request.put_i32(size as i32);
+    request.put_i16(api_key);
+    request.put_i16(api_version);
+    request.put_i32(correlation_id);
+    request.put_i16(CLIENT_ID.len() as i16);
+    request.put_slice(CLIENT_ID.as_bytes());
+    request.put_slice(body);
+    request.to_vec()
+}
+
+/// Check that enough bytes remain in a response
+fn ensure_remaining(response: &Bytes, len: usize) -> Result<(), KafkaClientError> {
+    if response.remaining() < len {
+        return Err(KafkaClientError::InvalidResponse(format!(
+            "{} bytes remaining, {} needed",
+            response.remaining(),
+            len
+        )));
+    }
+    Ok(())
+}
+
+/// Skip a string (int16 length prefix, -1 for null)
+fn skip_string(response: &mut Bytes) -> Result<(), KafkaClientError> {
+    ensure_remaining(response, 2)?;
+    let len = response.get_i16();
+    if len > 0 {
+        ensure_remaining(response, len as usize)?;
+        response.advance(len as usize);
+    }
+    Ok(())
+}
+
+/// Parse an ApiVersions v0 response body
+///
+/// # Return
+///
+/// * Error code and number of supported apis
+///
+fn parse_api_versions(mut response: Bytes) -> Result<(i16, usize), KafkaClientError> {
+    ensure_remaining(&response, 6)?;
+    let error_code = response.get_i16();
+    let api_count = response.get_i32().max(0) as usize;
+    ensure_remaining(&response, api_count * 6)?;
+    Ok((error_code, api_count))
+}
+
+/// Parse a Metadata v1 response body
+///
+/// # Return
+///
+/// * Number of brokers of the cluster
+///
+fn parse_metadata(mut response: Bytes) -> Result<usize, KafkaClientError> {
+    ensure_remaining(&response, 4)?;
+    let broker_count = response.get_i32().max(0) as usize;
+    for _ in 0..broker_count {
+        // node_id, host, port, rack
+        ensure_remaining(&response, 4)?;
+        response.advance(4);
+        skip_string(&mut response)?;
+        ensure_remaining(&response, 4)?;
+        response.advance(4);
+        skip_string(&mut response)?;
+    }
+    Ok(broker_count)
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, KafkaClientError> {
+    let stream = TcpStream::connect(addr).await?;
+    Ok(Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stream,
+        correlation_id: 0,
+    })
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    stream: TcpStream,
+    correlation_id: i32,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one api versions
+    /// * issue one metadata without topics
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), KafkaClientError> {
+        self.api_versions().await?;
+        self.metadata().await
+    }
+
+    /// ApiVersions call
+    pub async fn api_versions(&mut self) -> Result<(), KafkaClientError> {
+        let response = self
+            .handler_with_timeout("api_versions", API_VERSIONS_KEY, 0, &[])
+            .await?;
+        let (error_code, _) = parse_api_versions(response)?;
+        self.request_metrics
+            .count_request(&error_code.to_string(), "api_versions");
+        if error_code != 0 {
+            return Err(KafkaClientError::ErrorCode {
+                cmd_type: "api_versions".to_string(),
+                error_code,
+            });
+        }
+        Ok(())
+    }
+
+    /// Metadata call, only brokers are requested
+    pub async fn metadata(&mut self) -> Result<(), KafkaClientError> {
+        // Empty topic array
+        let body = 0i32.to_be_bytes();
+        let response = self
+            .handler_with_timeout("metadata", METADATA_KEY, METADATA_VERSION, &body)
+            .await?;
+        let broker_count = parse_metadata(response)?;
+        self.request_metrics.count_request("0", "metadata");
+        if broker_count == 0 {
+            return Err(KafkaClientError::NoBroker);
+        }
+        Ok(())
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        api_key: i16,
+        api_version: i16,
+        body: &[u8],
+    ) -> Result<Bytes, KafkaClientError> {
+        let start = Instant::now();
+        match tokio::time::timeout(TIMEOUT, self.handle_request(api_key, api_version, body)).await {
+            Ok(Ok(response)) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, start.elapsed());
+                Ok(response)
+            }
+            Ok(Err(error)) => Err(error),
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(KafkaClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform kafka request
+    ///
+    /// # Arguments
+    ///
+    /// * `api_key` - key of the api called
+    /// * `api_version` - version of the api called
+    /// * `body` - encoded body of the request
+    ///
+    /// # Return
+    ///
+    /// * Body of the response after the correlation id
+    ///
+    async fn handle_request(
+        &mut self,
+        api_key: i16,
+        api_version: i16,
+        body: &[u8],
+    ) -> Result<Bytes, KafkaClientError> {
+        self.correlation_id = self.correlation_id.wrapping_add(1);
+        let request = encode_request(api_key, api_version, self.correlation_id, body);
+        self.stream.write_all(&request).await?;
+
+        let size = self.stream.read_i32().await?;
+        if size < 4 || size as usize > MAX_RESPONSE_SIZE {
+            return Err(KafkaClientError::InvalidResponse(format!(
+                "invalid size {size}"
+            )));
+        }
+        let mut response = vec![0; size as usize];
+        self.stream.read_exact(&mut response).await?;
+
+        let mut response = Bytes::from(response);
+        let correlation_id = response.get_i32();
+        if correlation_id != self.correlation_id {
+            return Err(KafkaClientError::CorrelationId {
+                expected: self.correlation_id,
+                received: correlation_id,
+            });
+        }
+        Ok(response)
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, Bytes, BytesMut};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::kafka::{connect, encode_request, parse_api_versions, parse_metadata};
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+
+    fn api_versions_response() -> BytesMut {
+        let mut body = BytesMut::new();
+        body.put_i16(0);
+        body.put_i32(1);
+        body.put_i16(18);
+        body.put_i16(0);
+        body.put_i16(3);
+        body
+    }
+
+    fn metadata_response() -> BytesMut {
+        let mut body = BytesMut::new();
+        body.put_i32(1);
+        body.put_i32(1001);
+        body.put_i16(9);
+        body.put_slice(b"127.0.0.1");
+        body.put_i32(9092);
+        body.put_i16(-1);
+        // controller id and no topic
+        body.put_i32(1001);
+        body.put_i32(0);
+        body
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(
+            vec![0, 0, 0, 16, 0, 18, 0, 0, 0, 0, 0, 7, 0, 6, b'p', b'r', b'o', b'b', b'e', b's'],
+            encode_request(18, 0, 7, &[])
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            (0, 1),
+            parse_api_versions(api_versions_response().freeze()).unwrap()
+        );
+        assert!(parse_api_versions(Bytes::from_static(&[0, 0, 0, 0, 0, 2])).is_err());
+        assert_eq!(1, parse_metadata(metadata_response().freeze()).unwrap());
+        assert!(parse_metadata(Bytes::from_static(&[0, 0, 0, 1, 0])).is_err());
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            for body in [api_versions_response(), metadata_response()] {
+                let size = socket.read_i32().await.unwrap();
+                let mut request = vec![0; size as usize];
+                socket.read_exact(&mut request).await.unwrap();
+
+                let mut response = BytesMut::new();
+                response.put_i32(4 + body.len() as i32);
+                // Correlation id
+                response.put_slice(&request[4..8]);
+                response.put_slice(&body);
+                socket.write_all(&response).await.unwrap();
+            }
+        });
+
+        let mut client = connect("kafka_probe", &addr).await.unwrap();
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["kafka_probe", &addr, "0", "metadata"])
+                .unwrap()
+                .get()
+        );
+    }
+}
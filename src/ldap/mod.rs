@@ -0,0 +1,567 @@
+use std::io;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BytesMut};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::{info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+// Ber tags of the ldap messages
+const TAG_BOOLEAN: u8 = 0x01;
+const TAG_INTEGER: u8 = 0x02;
+const TAG_OCTET_STRING: u8 = 0x04;
+const TAG_ENUMERATED: u8 = 0x0a;
+const TAG_SEQUENCE: u8 = 0x30;
+const TAG_BIND_REQUEST: u8 = 0x60;
+const TAG_BIND_RESPONSE: u8 = 0x61;
+const TAG_SEARCH_REQUEST: u8 = 0x63;
+const TAG_SEARCH_ENTRY: u8 = 0x64;
+const TAG_SEARCH_DONE: u8 = 0x65;
+const TAG_SEARCH_REFERENCE: u8 = 0x73;
+// Context specific tags of the simple authentication and the present filter
+const TAG_SIMPLE_AUTH: u8 = 0x80;
+const TAG_FILTER_PRESENT: u8 = 0x87;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// Bind credentials and searched entry, only set once from main
+static LDAP_CONFIG: OnceLock<LdapConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct LdapConfig {
+    // Empty for an anonymous bind
+    bind_dn: String,
+    password: String,
+    // Empty to search the root DSE
+    base_dn: String,
+}
+
+#[derive(Error, Debug)]
+pub enum LdapClientError {
+    #[error("Empty or incomplete response.")]
+    EmptyOrIncompleteResponse,
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Connection reset by peer.")]
+    ConnectionReset,
+    #[error("Invalid message: {0}")]
+    InvalidMessage(String),
+    #[error("Unexpected message with tag {tag:#04x} on {cmd_type}.")]
+    UnexpectedMessage { cmd_type: String, tag: u8 },
+    #[error("Result {} on {cmd_type}: {message}", result_name(*.code))]
+    Result {
+        cmd_type: String,
+        code: u32,
+        message: String,
+    },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+// Message received from the server, only the fields used by the probe are decoded
+#[derive(Debug, PartialEq)]
+struct Message {
+    id: i64,
+    tag: u8,
+    // Set on bind responses and search done
+    result_code: Option<u32>,
+    diagnostic_message: String,
+}
+
+/// Set the bind credentials and the entry searched on ldap nodes
+///
+/// # Arguments
+///
+/// * `bind_dn` - dn of the simple bind, empty for an anonymous bind
+/// * `password` - password of the simple bind
+/// * `base_dn` - dn of the entry searched with a base scope, empty for the root DSE
+///
+pub fn set_config(bind_dn: &str, password: &str, base_dn: &str) -> Result<(), String> {
+    if bind_dn.is_empty() {
+        info!("Anonymous bind on ldap nodes, search base {:?}", base_dn);
+    } else {
+        info!(
+            "Bind as {} on ldap nodes, search base {:?}",
+            bind_dn, base_dn
+        );
+    }
+    LDAP_CONFIG
+        .set(LdapConfig {
+            bind_dn: bind_dn.to_string(),
+            password: password.to_string(),
+            base_dn: base_dn.to_string(),
+        })
+        .map_err(|_| "Ldap config is already initialized".to_string())
+}
+
+/// Return the name of a ldap result code exported in the number of requests metric
+fn result_name(code: u32) -> &'static str {
+    match code {
+        0 => "success",
+        1 => "operationsError",
+        2 => "protocolError",
+        3 => "timeLimitExceeded",
+        4 => "sizeLimitExceeded",
+        32 => "noSuchObject",
+        34 => "invalidDNSyntax",
+        48 => "inappropriateAuthentication",
+        49 => "invalidCredentials",
+        50 => "insufficientAccessRights",
+        51 => "busy",
+        52 => "unavailable",
+        53 => "unwillingToPerform",
+        _ => "Unknown",
+    }
+}
+
+/// Encode a ber element
+///
+/// # Arguments
+///
+/// * `tag` - tag of the element
+/// * `value` - encoded content of the element
+///
+fn encode_tlv(tag: u8, value: &[u8]) -> Vec<u8> {
+    let mut element = vec![tag];
+    if value.len() < 0x80 {
+        element.push(value.len() as u8);
+    } else {
+        let len_bytes: Vec<u8> = (value.len() as u32)
+            .to_be_bytes()
+            .into_iter()
+            .skip_while(|byte| *byte == 0)
+            .collect();
+        element.push(0x80 | len_bytes.len() as u8);
+        element.extend_from_slice(&len_bytes);
+    }
+    element.extend_from_slice(value);
+    element
+}
+
+/// Encode a non negative ber integer with its minimal length
+fn encode_integer(tag: u8, value: i64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let mut start = 0;
+    // Keep a leading zero byte when the next byte would make the value negative
+    while start < bytes.len() - 1 && bytes[start] == 0 && bytes[start + 1] < 0x80 {
+        start += 1;
+    }
+    encode_tlv(tag, &bytes[start..])
+}
+
+/// Wrap a protocol operation in a ldap message
+fn encode_message(id: i64, operation: Vec<u8>) -> Vec<u8> {
+    let mut content = encode_integer(TAG_INTEGER, id);
+    content.extend(operation);
+    encode_tlv(TAG_SEQUENCE, &content)
+}
+
+/// Encode a simple bind request, anonymous if the dn is empty
+fn encode_bind(id: i64, bind_dn: &str, password: &str) -> Vec<u8> {
+    let mut content = encode_integer(TAG_INTEGER, 3);
+    content.extend(encode_tlv(TAG_OCTET_STRING, bind_dn.as_bytes()));
+    content.extend(encode_tlv(TAG_SIMPLE_AUTH, password.as_bytes()));
+    encode_message(id, encode_tlv(TAG_BIND_REQUEST, &content))
+}
+
+/// Encode a base scope search of the entry, requesting no attribute
+fn encode_search(id: i64, base_dn: &str) -> Vec<u8> {
+    let mut content = encode_tlv(TAG_OCTET_STRING, base_dn.as_bytes());
+    // Base object scope, never deref aliases
+    content.extend(encode_tlv(TAG_ENUMERATED, &[0]));
+    content.extend(encode_tlv(TAG_ENUMERATED, &[0]));
+    // One entry at most, server side time limit of 1 second
+    content.extend(encode_integer(TAG_INTEGER, 1));
+    content.extend(encode_integer(TAG_INTEGER, 1));
+    content.extend(encode_tlv(TAG_BOOLEAN, &[0]));
+    content.extend(encode_tlv(TAG_FILTER_PRESENT, b"objectClass"));
+    // 1.1 is the oid requesting no attribute
+    content.extend(encode_tlv(
+        TAG_SEQUENCE,
+        &encode_tlv(TAG_OCTET_STRING, b"1.1"),
+    ));
+    encode_message(id, encode_tlv(TAG_SEARCH_REQUEST, &content))
+}
+
+/// Decode a ber element
+///
+/// # Arguments
+///
+/// * `buffer` - bytes starting with the element
+///
+/// # Return
+///
+/// * The tag, the content and the length of the element, None if not enough bytes
+///
+fn decode_tlv(buffer: &[u8]) -> Result<Option<(u8, &[u8], usize)>, LdapClientError> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    let (len, header_len) = match buffer[1] {
+        len if len < 0x80 => (len as usize, 2),
+        len if (0x81..=0x84).contains(&len) => {
+            let len_size = (len & 0x7f) as usize;
+            if buffer.len() < 2 + len_size {
+                return Ok(None);
+            }
+            let len = buffer[2..2 + len_size]
+                .iter()
+                .fold(0, |len, byte| (len << 8) | *byte as usize);
+            (len, 2 + len_size)
+        }
+        len => {
+            return Err(LdapClientError::InvalidMessage(format!(
+                "unsupported length {len:#04x}"
+            )))
+        }
+    };
+    if buffer.len() < header_len + len {
+        return Ok(None);
+    }
+    Ok(Some((
+        buffer[0],
+        &buffer[header_len..header_len + len],
+        header_len + len,
+    )))
+}
+
+/// Decode a complete ber element from the content of a parent element
+fn decode_element(content: &[u8]) -> Result<(u8, &[u8], usize), LdapClientError> {
+    decode_tlv(content)?
+        .ok_or_else(|| LdapClientError::InvalidMessage("truncated element".to_string()))
+}
+
+/// Decode a ber integer
+fn decode_integer(value: &[u8]) -> i64 {
+    let initial = if value.first().is_some_and(|byte| *byte >= 0x80) {
+        -1
+    } else {
+        0
+    };
+    value
+        .iter()
+        .fold(initial, |integer, byte| (integer << 8) | *byte as i64)
+}
+
+/// Parse a ldap message from a buffer
+///
+/// # Arguments
+///
+/// * `buffer` - bytes received from the server
+///
+/// # Return
+///
+/// * The message and the number of bytes it used, None if not enough bytes
+///
+fn parse_message(buffer: &[u8]) -> Result<Option<(Message, usize)>, LdapClientError> {
+    let Some((tag, content, len)) = decode_tlv(buffer)? else {
+        return Ok(None);
+    };
+    if tag != TAG_SEQUENCE {
+        return Err(LdapClientError::InvalidMessage(format!(
+            "unexpected tag {tag:#04x}"
+        )));
+    }
+    let (_, id, id_len) = decode_element(content)?;
+    let (operation_tag, operation, _) = decode_element(&content[id_len..])?;
+    let mut message = Message {
+        id: decode_integer(id),
+        tag: operation_tag,
+        result_code: None,
+        diagnostic_message: "".to_string(),
+    };
+    if operation_tag == TAG_BIND_RESPONSE || operation_tag == TAG_SEARCH_DONE {
+        // resultCode, matchedDN then diagnosticMessage
+        let (_, result_code, result_len) = decode_element(operation)?;
+        let (_, _, matched_len) = decode_element(&operation[result_len..])?;
+        let (_, diagnostic, _) = decode_element(&operation[result_len + matched_len..])?;
+        message.result_code = Some(decode_integer(result_code) as u32);
+        message.diagnostic_message = String::from_utf8_lossy(diagnostic).to_string();
+    }
+    Ok(Some((message, len)))
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, LdapClientError> {
+    let socket = TcpStream::connect(addr).await?;
+    let config = LDAP_CONFIG.get().cloned().unwrap_or_default();
+    Ok(Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stream: BufWriter::new(socket),
+        buffer: BytesMut::with_capacity(4096),
+        message_id: 0,
+        bind_dn: config.bind_dn,
+        password: config.password,
+        base_dn: config.base_dn,
+    })
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    stream: BufWriter<TcpStream>,
+    buffer: BytesMut,
+    // Id of the last request sent on the connection
+    message_id: i64,
+    bind_dn: String,
+    password: String,
+    base_dn: String,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one bind, anonymous if no bind dn is set
+    /// * issue one base scope search of the configured entry
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), LdapClientError> {
+        self.bind().await?;
+        self.search().await
+    }
+
+    /// Bind call
+    pub async fn bind(&mut self) -> Result<(), LdapClientError> {
+        self.message_id += 1;
+        let request = encode_bind(self.message_id, &self.bind_dn, &self.password);
+        self.handler_with_timeout("bind", request, TAG_BIND_RESPONSE)
+            .await
+    }
+
+    /// Search call
+    pub async fn search(&mut self) -> Result<(), LdapClientError> {
+        self.message_id += 1;
+        let request = encode_search(self.message_id, &self.base_dn);
+        self.handler_with_timeout("search", request, TAG_SEARCH_DONE)
+            .await
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        request: Vec<u8>,
+        done_tag: u8,
+    ) -> Result<(), LdapClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type, request, done_tag)).await
+        {
+            Ok(response_res) => response_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics.count_request("Timeout", cmd_type);
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(LdapClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform ldap request
+    ///
+    /// Entries and references returned by a search are skipped until the search done
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    /// * `request` - the encoded ldap message
+    /// * `done_tag` - tag of the message ending the response
+    ///
+    #[instrument(skip(self, request))]
+    pub async fn handle_request(
+        &mut self,
+        cmd_type: &str,
+        request: Vec<u8>,
+        done_tag: u8,
+    ) -> Result<(), LdapClientError> {
+        let start = Instant::now();
+        self.stream.write_all(&request).await?;
+        self.stream.flush().await?;
+
+        let result = loop {
+            let message = self.read_message().await?;
+            // Skip late responses of previous timed out requests
+            if message.id != self.message_id {
+                continue;
+            }
+            match message.tag {
+                TAG_SEARCH_ENTRY | TAG_SEARCH_REFERENCE if done_tag == TAG_SEARCH_DONE => {}
+                tag if tag == done_tag => match message.result_code {
+                    Some(0) => break Ok(()),
+                    code => {
+                        break Err(LdapClientError::Result {
+                            cmd_type: cmd_type.to_string(),
+                            code: code.unwrap_or_default(),
+                            message: message.diagnostic_message,
+                        })
+                    }
+                },
+                tag => {
+                    break Err(LdapClientError::UnexpectedMessage {
+                        cmd_type: cmd_type.to_string(),
+                        tag,
+                    })
+                }
+            }
+        };
+        let status = match &result {
+            Ok(_) => result_name(0),
+            Err(LdapClientError::Result { code, .. }) => result_name(*code),
+            Err(_) => "Error",
+        };
+        self.request_metrics.count_request(status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, start.elapsed());
+        result
+    }
+
+    /// Get message from tcp stream
+    async fn read_message(&mut self) -> Result<Message, LdapClientError> {
+        loop {
+            if let Some((message, len)) = parse_message(&self.buffer[..])? {
+                self.buffer.advance(len);
+                return Ok(message);
+            }
+            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                return if self.buffer.is_empty() {
+                    Err(LdapClientError::EmptyOrIncompleteResponse)
+                } else {
+                    Err(LdapClientError::ConnectionReset)
+                };
+            }
+        }
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::ldap::{
+        connect, decode_integer, encode_bind, encode_integer, encode_search, encode_tlv,
+        parse_message, LdapClientError, Message, TAG_BIND_RESPONSE, TAG_INTEGER,
+    };
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+
+    // Bind response of message 1 with success
+    const BIND_SUCCESS: &str = "300c02010161070a010004000400";
+    // Search entry of the root DSE then search done of message 2 with success
+    const SEARCH_SUCCESS: &str = "3009020102640404003000300c02010265070a010004000400";
+    // Bind response of message 1 with invalid credentials
+    const BIND_INVALID_CREDENTIALS: &str = "301502010161100a013104000409626164207061737321";
+
+    /// Start a server answering each request in order
+    async fn init_ldap(responses: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            for response in responses {
+                let mut buffer = [0; 256];
+                stream.read(&mut buffer).await.unwrap();
+                stream
+                    .write_all(&hex::decode(response).unwrap())
+                    .await
+                    .unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!("020100", hex::encode(encode_integer(TAG_INTEGER, 0)));
+        assert_eq!("02020080", hex::encode(encode_integer(TAG_INTEGER, 128)));
+        assert_eq!("0481c800", hex::encode(&encode_tlv(0x04, &[0; 200])[..4]));
+        assert_eq!(
+            "300c020101600702010304008000",
+            hex::encode(encode_bind(1, "", ""))
+        );
+        assert_eq!(
+            "302a020102632504000a01000a0100020101020101010100870b6f626a656374436c6173733005040\
+            3312e31",
+            hex::encode(encode_search(2, ""))
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(-1, decode_integer(&[0xff]));
+        assert_eq!(
+            Some((
+                Message {
+                    id: 1,
+                    tag: TAG_BIND_RESPONSE,
+                    result_code: Some(49),
+                    diagnostic_message: "bad pass!".to_string(),
+                },
+                23
+            )),
+            parse_message(&hex::decode(BIND_INVALID_CREDENTIALS).unwrap()).unwrap()
+        );
+        assert_eq!(
+            None,
+            parse_message(&hex::decode(&BIND_SUCCESS[..10]).unwrap()).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let addr = init_ldap(vec![BIND_SUCCESS, SEARCH_SUCCESS]).await;
+
+        let mut client = connect("ldap_probe", &addr).await.unwrap();
+        client.probe().await.unwrap();
+        for cmd_type in ["bind", "search"] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&["ldap_probe", &addr, "success", cmd_type])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_invalid_credentials() {
+        let addr = init_ldap(vec![BIND_INVALID_CREDENTIALS]).await;
+
+        let mut client = connect("ldap_probe_invalid", &addr).await.unwrap();
+        assert!(matches!(
+            client.probe().await,
+            Err(LdapClientError::Result { code: 49, .. })
+        ));
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&[
+                    "ldap_probe_invalid",
+                    &addr,
+                    "invalidCredentials",
+                    "bind"
+                ])
+                .unwrap()
+                .get()
+        );
+    }
+}
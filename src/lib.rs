@@ -1,4 +1,59 @@
+#[cfg(feature = "aerospike")]
+pub mod aerospike;
+#[cfg(feature = "cassandra")]
+pub mod cassandra;
+pub mod cli;
+#[cfg(feature = "clickhouse")]
+pub mod clickhouse;
+pub mod clock;
+#[cfg(feature = "cloud")]
+pub mod cloud;
+#[cfg(feature = "consul")]
 pub mod consul;
+#[cfg(feature = "dns")]
+pub mod dns;
+#[cfg(feature = "docker")]
+pub mod docker;
+#[cfg(feature = "elasticsearch")]
+pub mod elasticsearch;
+#[cfg(feature = "file")]
+pub mod file;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "haproxy")]
+pub mod haproxy;
+#[cfg(feature = "http-sd")]
+pub mod http_sd;
+#[cfg(feature = "kafka")]
+pub mod kafka;
+#[cfg(feature = "ldap")]
+pub mod ldap;
+#[cfg(feature = "mdns")]
+pub mod mdns;
+#[cfg(feature = "memcached")]
 pub mod memcached;
+#[cfg(feature = "mongodb")]
+pub mod mongodb;
+#[cfg(feature = "mysql")]
+pub mod mysql;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(feature = "nomad")]
+pub mod nomad;
 pub mod probes;
+#[cfg(feature = "rabbitmq")]
+pub mod rabbitmq;
+#[cfg(feature = "redis")]
+pub mod redis;
+#[cfg(feature = "s3")]
+pub mod s3;
+#[cfg(feature = "smtp")]
+pub mod smtp;
+#[cfg(feature = "solr")]
+pub mod solr;
+#[cfg(feature = "srv")]
+pub mod srv;
+pub mod tcp;
 pub mod token_bucket;
+#[cfg(feature = "varnish")]
+pub mod varnish;
@@ -0,0 +1,511 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::io;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::net::UdpSocket;
+use tokio::time::{timeout_at, Instant};
+use tracing::{debug, info, warn};
+
+use crate::probes::discovery::{
+    Discovery, DiscoveryFuture, PollInterval, ServiceNode, ServiceNodes,
+};
+use crate::probes::protocol::Protocol;
+use crate::srv::{read_name, read_u16, service_protocol, SrvError};
+
+const TYPE_A: u16 = 1;
+const TYPE_PTR: u16 = 12;
+const TYPE_TXT: u16 = 16;
+const TYPE_SRV: u16 = 33;
+const CLASS_IN: u16 = 1;
+// Question bit asking for a unicast response
+const UNICAST_RESPONSE: u16 = 0x8000;
+const FLAG_RESPONSE: u16 = 0x8000;
+const HEADER_LEN: usize = 12;
+// Max size of a multicast dns message
+const MAX_RESPONSE_SIZE: usize = 9000;
+
+const MDNS_GROUP: &str = "224.0.0.251:5353";
+const LOCAL_DOMAIN: &str = "local";
+
+// Txt key naming the cluster of the instance, the service type if missing
+const CLUSTER_NAME_KEY: &str = "cluster_name";
+// Txt key declaring the protocol used to probe the instance
+const PROBE_PROTOCOL_KEY: &str = "probe_protocol";
+
+#[derive(Error, Debug)]
+pub enum MdnsError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid name {0}.")]
+    InvalidName(String),
+    #[error("{source}")]
+    Response {
+        #[from]
+        source: SrvError,
+    },
+}
+
+// Records gathered from the responses of a browse, keyed by lowercase names
+#[derive(Debug, Default)]
+struct Records {
+    // Instances of each service type
+    instances: HashMap<String, BTreeSet<String>>,
+    // Target host and port of each instance
+    targets: HashMap<String, (String, u16)>,
+    // Txt key/values of each instance
+    texts: HashMap<String, HashMap<String, String>>,
+    // Ipv4 addresses of each host
+    addresses: HashMap<String, BTreeSet<Ipv4Addr>>,
+}
+
+// Browse mDNS service types on the local segment into nodes to probe
+#[derive(Debug)]
+pub struct MdnsClient {
+    // Service types browsed, like _memcache._tcp
+    service_types: Vec<String>,
+    // Time waiting for the responders after each query
+    browse_duration: Duration,
+    // Multicast group queried
+    group: SocketAddr,
+    id: u16,
+}
+
+/// Encode a query asking for unicast responses
+///
+/// Sent from an ephemeral port, responders answer to that port with the query id
+///
+/// # Arguments
+///
+/// * `id` - id returned in the responses
+/// * `questions` - name and type of the records queried
+///
+fn encode_questions(id: u16, questions: &[(String, u16)]) -> Result<Vec<u8>, MdnsError> {
+    let mut query = Vec::with_capacity(HEADER_LEN + questions.len() * 64);
+    query.extend_from_slice(&id.to_be_bytes());
+    // Standard query, no flag
+    query.extend_from_slice(&[0; 2]);
+    query.extend_from_slice(&(questions.len() as u16).to_be_bytes());
+    query.extend_from_slice(&[0; 6]);
+    for (name, record_type) in questions {
+        for label in name.split('.') {
+            if label.is_empty() || label.len() > 63 {
+                return Err(MdnsError::InvalidName(name.to_string()));
+            }
+            query.push(label.len() as u8);
+            query.extend_from_slice(label.as_bytes());
+        }
+        query.push(0);
+        query.extend_from_slice(&record_type.to_be_bytes());
+        query.extend_from_slice(&(CLASS_IN | UNICAST_RESPONSE).to_be_bytes());
+    }
+    Ok(query)
+}
+
+/// Parse the key/values of a txt record, keys are lowercase
+fn parse_txt(data: &[u8]) -> HashMap<String, String> {
+    let mut texts = HashMap::new();
+    let mut pos = 0;
+    while let Some(len) = data.get(pos).map(|len| *len as usize) {
+        let Some(text) = data.get(pos + 1..pos + 1 + len) else {
+            break;
+        };
+        let text = String::from_utf8_lossy(text);
+        if let Some((key, value)) = text.split_once('=') {
+            texts.insert(key.to_lowercase(), value.to_string());
+        }
+        pos += 1 + len;
+    }
+    texts
+}
+
+/// Gather the records of a response, in any of its sections
+///
+/// # Arguments
+///
+/// * `message` - response received from a responder
+/// * `records` - records gathered so far
+///
+fn parse_response(message: &[u8], records: &mut Records) -> Result<(), MdnsError> {
+    if message.len() < HEADER_LEN {
+        return Err(SrvError::InvalidResponse("truncated header".to_string()).into());
+    }
+    // Skip the queries of other hosts
+    if read_u16(message, 2)? & FLAG_RESPONSE == 0 {
+        return Ok(());
+    }
+    let question_count = read_u16(message, 4)?;
+    let record_count = read_u16(message, 6)? as usize
+        + read_u16(message, 8)? as usize
+        + read_u16(message, 10)? as usize;
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..question_count {
+        // Name then type and class
+        pos = read_name(message, pos)?.1 + 4;
+    }
+    for _ in 0..record_count {
+        let (name, data) = read_name(message, pos)?;
+        let name = name.to_lowercase();
+        let record_type = read_u16(message, data)?;
+        // Class and ttl
+        let len = read_u16(message, data + 8)? as usize;
+        let data = data + 10;
+        if message.len() < data + len {
+            return Err(SrvError::InvalidResponse("truncated record".to_string()).into());
+        }
+        match record_type {
+            TYPE_PTR => {
+                let instance = read_name(message, data)?.0.to_lowercase();
+                records.instances.entry(name).or_default().insert(instance);
+            }
+            TYPE_SRV => {
+                let target = read_name(message, data + 6)?.0.to_lowercase();
+                let port = read_u16(message, data + 4)?;
+                records.targets.insert(name, (target, port));
+            }
+            TYPE_TXT => {
+                records
+                    .texts
+                    .insert(name, parse_txt(&message[data..data + len]));
+            }
+            TYPE_A if len == 4 => {
+                let ip = Ipv4Addr::new(
+                    message[data],
+                    message[data + 1],
+                    message[data + 2],
+                    message[data + 3],
+                );
+                records.addresses.entry(name).or_default().insert(ip);
+            }
+            _ => {}
+        }
+        pos = data + len;
+    }
+    Ok(())
+}
+
+impl MdnsClient {
+    /// Returns a mDNS client
+    ///
+    /// # Arguments
+    ///
+    /// * `service_types` - comma separated list of service types to browse, like _memcache._tcp
+    /// * `browse_duration` - time waiting for the responders after each query
+    ///
+    pub fn new(service_types: &str, browse_duration: Duration) -> Result<Self, String> {
+        let service_types: Vec<String> = service_types
+            .split(',')
+            .map(|service_type| {
+                service_type
+                    .trim()
+                    .trim_end_matches('.')
+                    .trim_end_matches(".local")
+                    .to_lowercase()
+            })
+            .filter(|service_type| !service_type.is_empty())
+            .collect();
+        if service_types.is_empty() {
+            return Err("No mDNS service type to browse".to_string());
+        }
+        if browse_duration.is_zero() {
+            return Err("No mDNS browse duration".to_string());
+        }
+        info!("Browse mDNS service types {:?}", service_types);
+        Ok(MdnsClient {
+            service_types,
+            browse_duration,
+            group: MDNS_GROUP.parse().expect("valid mdns group"),
+            id: 0,
+        })
+    }
+
+    /// Send a query then gather the responses until the end of the browse duration
+    ///
+    /// # Arguments
+    ///
+    /// * `socket` - socket receiving the unicast responses
+    /// * `questions` - name and type of the records queried
+    /// * `records` - records gathered so far
+    ///
+    async fn browse(
+        &mut self,
+        socket: &UdpSocket,
+        questions: &[(String, u16)],
+        records: &mut Records,
+    ) -> Result<(), MdnsError> {
+        self.id = self.id.wrapping_add(1);
+        socket
+            .send_to(&encode_questions(self.id, questions)?, self.group)
+            .await?;
+
+        let deadline = Instant::now() + self.browse_duration;
+        let mut buffer = vec![0; MAX_RESPONSE_SIZE];
+        loop {
+            let (len, responder) = match timeout_at(deadline, socket.recv_from(&mut buffer)).await {
+                Ok(recv_res) => recv_res?,
+                Err(_browse_ended) => return Ok(()),
+            };
+            if let Err(err) = parse_response(&buffer[..len], records) {
+                debug!("Invalid mDNS response from {}: {}", responder, err);
+            }
+        }
+    }
+
+    /// Browse the instances of the service types into nodes to probe
+    ///
+    /// The target and address of the instances missing from the responses are queried once again
+    ///
+    /// # Return
+    ///
+    /// * ServiceNodes - one node per ipv4 address of the instances which answered
+    ///
+    pub async fn list_nodes(&mut self) -> Result<ServiceNodes, MdnsError> {
+        let socket = UdpSocket::bind("0.0.0.0:0").await?;
+        let mut records = Records::default();
+        let questions = self
+            .service_types
+            .iter()
+            .map(|service_type| (format!("{service_type}.{LOCAL_DOMAIN}"), TYPE_PTR))
+            .collect::<Vec<_>>();
+        self.browse(&socket, &questions, &mut records).await?;
+
+        let mut questions = Vec::new();
+        for instance in records.instances.values().flatten() {
+            match records.targets.get(instance) {
+                None => {
+                    questions.push((instance.clone(), TYPE_SRV));
+                    questions.push((instance.clone(), TYPE_TXT));
+                }
+                Some((target, _)) if !records.addresses.contains_key(target) => {
+                    questions.push((target.clone(), TYPE_A));
+                }
+                Some(_) => {}
+            }
+        }
+        if !questions.is_empty() {
+            debug!("Query missing mDNS records {:?}", questions);
+            self.browse(&socket, &questions, &mut records).await?;
+        }
+        Ok(self.records_to_nodes(&records))
+    }
+
+    /// Create the nodes of the instances of the service types
+    ///
+    /// # Arguments
+    ///
+    /// * `records` - records gathered from the responses
+    ///
+    fn records_to_nodes(&self, records: &Records) -> ServiceNodes {
+        let mut services = BTreeSet::new();
+        let mut nodes = HashMap::new();
+        for service_type in &self.service_types {
+            let instances = records
+                .instances
+                .get(&format!("{service_type}.{LOCAL_DOMAIN}"));
+            for instance in instances.into_iter().flatten() {
+                let Some((target, port)) = records.targets.get(instance) else {
+                    debug!("No mDNS target for {}", instance);
+                    continue;
+                };
+                let text = |key: &str| records.texts.get(instance).and_then(|texts| texts.get(key));
+                let service_name = text(CLUSTER_NAME_KEY)
+                    .cloned()
+                    .unwrap_or_else(|| service_type.clone());
+                let protocol =
+                    match text(PROBE_PROTOCOL_KEY).map(|protocol| protocol.parse::<Protocol>()) {
+                        Some(Ok(protocol)) => Some(protocol),
+                        Some(Err(issue)) => {
+                            warn!("Invalid probe protocol: {}", issue);
+                            service_protocol(service_type)
+                        }
+                        None => service_protocol(service_type),
+                    };
+                for ip in records.addresses.get(target).into_iter().flatten() {
+                    let service_node = ServiceNode {
+                        service_name: service_name.clone(),
+                        ip: ip.to_string(),
+                        port: *port,
+                        protocol,
+                    };
+                    nodes.insert(service_node.to_string(), service_node);
+                }
+                services.insert(service_name);
+            }
+        }
+        ServiceNodes {
+            index: 0,
+            services: services.into_iter().collect(),
+            nodes,
+        }
+    }
+}
+
+// Discovery browsing the mDNS service types again on each interval
+#[derive(Debug)]
+pub struct MdnsDiscovery {
+    mdns_client: MdnsClient,
+    poll_interval: PollInterval,
+}
+
+impl MdnsDiscovery {
+    /// Returns a MdnsDiscovery
+    ///
+    /// # Arguments
+    ///
+    /// * `mdns_client` - a mDNS client
+    /// * `refresh_interval` - interval between each browse
+    ///
+    pub fn new(mdns_client: MdnsClient, refresh_interval: Duration) -> Self {
+        MdnsDiscovery {
+            mdns_client,
+            poll_interval: PollInterval::new(refresh_interval),
+        }
+    }
+}
+
+impl fmt::Display for MdnsDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "mdns services")
+    }
+}
+
+impl Discovery for MdnsDiscovery {
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+        Box::pin(async move {
+            self.poll_interval.tick().await;
+            self.mdns_client.list_nodes().await.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::net::UdpSocket;
+
+    use crate::mdns::{
+        encode_questions, parse_response, parse_txt, MdnsClient, Records, TYPE_A, TYPE_PTR,
+        TYPE_SRV, TYPE_TXT,
+    };
+    use crate::probes::protocol::Protocol;
+
+    fn name(name: &str) -> Vec<u8> {
+        let mut encoded = Vec::new();
+        for label in name.split('.') {
+            encoded.push(label.len() as u8);
+            encoded.extend_from_slice(label.as_bytes());
+        }
+        encoded.push(0);
+        encoded
+    }
+
+    fn record(owner: &str, record_type: u16, data: &[u8]) -> Vec<u8> {
+        let mut record = name(owner);
+        record.extend_from_slice(&record_type.to_be_bytes());
+        // Class in with cache flush, ttl 120
+        record.extend_from_slice(&[0x80, 0x01, 0, 0, 0, 120]);
+        record.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        record.extend_from_slice(data);
+        record
+    }
+
+    /// Build a response with the ptr answer and the srv, txt and a additional records
+    fn response(id: u16, instance: &str, texts: &[&str], port: u16) -> Vec<u8> {
+        let mut response = id.to_be_bytes().to_vec();
+        response.extend_from_slice(&[0x84, 0x00, 0, 0, 0, 1, 0, 0, 0, 3]);
+        response.extend(record("_memcache._tcp.local", TYPE_PTR, &name(instance)));
+        let mut srv = vec![0, 0, 0, 0];
+        srv.extend_from_slice(&port.to_be_bytes());
+        srv.extend(name("cache-1.local"));
+        response.extend(record(instance, TYPE_SRV, &srv));
+        let mut txt = Vec::new();
+        for text in texts {
+            txt.push(text.len() as u8);
+            txt.extend_from_slice(text.as_bytes());
+        }
+        response.extend(record(instance, TYPE_TXT, &txt));
+        response.extend(record("cache-1.local", TYPE_A, &[127, 0, 0, 1]));
+        response
+    }
+
+    #[test]
+    fn encode() {
+        let query = encode_questions(7, &[("_memcache._tcp.local".to_string(), TYPE_PTR)]).unwrap();
+        assert_eq!(&[0, 7, 0, 0, 0, 1], &query[..6]);
+        assert_eq!(&[0, 12, 0x80, 1], &query[query.len() - 4..]);
+        assert!(encode_questions(7, &[("_memcache..local".to_string(), TYPE_PTR)]).is_err());
+    }
+
+    #[test]
+    fn txt() {
+        let texts = parse_txt(b"\x0ecluster_name=a\x04flag\x0aProtocol=b");
+        assert_eq!(Some(&"a".to_string()), texts.get("cluster_name"));
+        assert_eq!(Some(&"b".to_string()), texts.get("protocol"));
+        assert_eq!(2, texts.len());
+        // Truncated string
+        assert!(parse_txt(b"\x10short").is_empty());
+    }
+
+    #[test]
+    fn parse() {
+        let mut records = Records::default();
+        let message = response(
+            1,
+            "Cache 1._memcache._tcp.local",
+            &["probe_protocol=redis"],
+            11211,
+        );
+        parse_response(&message, &mut records).unwrap();
+        assert!(records.instances["_memcache._tcp.local"].contains("cache 1._memcache._tcp.local"));
+        assert_eq!(
+            Some(&("cache-1.local".to_string(), 11211)),
+            records.targets.get("cache 1._memcache._tcp.local")
+        );
+        assert_eq!(1, records.addresses["cache-1.local"].len());
+
+        let mdns_client = MdnsClient::new("_memcache._tcp", Duration::from_secs(1)).unwrap();
+        let service_nodes = mdns_client.records_to_nodes(&records);
+        assert_eq!(vec!["_memcache._tcp".to_string()], service_nodes.services);
+        let service_node = service_nodes
+            .nodes
+            .get("_memcache._tcp:127.0.0.1:11211")
+            .unwrap();
+        assert_eq!(Some(Protocol::Redis), service_node.protocol);
+
+        assert!(parse_response(&message[..20], &mut Records::default()).is_err());
+    }
+
+    #[tokio::test]
+    async fn list_nodes() {
+        let responder = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let mut mdns_client =
+            MdnsClient::new("_memcache._tcp.local.", Duration::from_millis(200)).unwrap();
+        mdns_client.group = responder.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            let (_, querier) = responder.recv_from(&mut buffer).await.unwrap();
+            let id = u16::from_be_bytes([buffer[0], buffer[1]]);
+            let message = response(
+                id,
+                "cache-1._memcache._tcp.local",
+                &["cluster_name=cache"],
+                11211,
+            );
+            responder.send_to(&message, querier).await.unwrap();
+        });
+
+        let service_nodes = mdns_client.list_nodes().await.unwrap();
+        assert_eq!(vec!["cache".to_string()], service_nodes.services);
+        assert!(service_nodes.nodes.contains_key("cache:127.0.0.1:11211"));
+
+        assert!(MdnsClient::new(" ", Duration::from_secs(1)).is_err());
+        assert!(MdnsClient::new("_memcache._tcp", Duration::ZERO).is_err());
+    }
+}
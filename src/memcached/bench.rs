@@ -0,0 +1,288 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use tokio::time::sleep;
+use tracing::warn;
+
+use crate::memcached::command::{Command, Get, Set};
+use crate::memcached::{connect, payload, Client, KEY};
+use crate::probes::quantiles::nearest_rank;
+use crate::token_bucket::{RateLimiter, RateLimiterKind};
+
+// Quantiles of the response times reported by the bench
+const BENCH_QUANTILES: [(&str, f64); 4] =
+    [("p50", 0.5), ("p90", 0.9), ("p99", 0.99), ("p99.9", 0.999)];
+
+// Delay before reconnecting a connection that failed
+const RECONNECT_DELAY: Duration = Duration::from_millis(100);
+
+// Load generated against a node
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BenchConfig {
+    // Requests per second over all connections, 0 for unlimited
+    pub rate: u64,
+    // Number of connections issuing requests concurrently
+    pub concurrency: usize,
+    pub duration: Duration,
+}
+
+// Response times and failures of a command type
+#[derive(Debug, Default)]
+pub struct CommandStats {
+    response_times: Vec<Duration>,
+    errors: u64,
+}
+
+impl CommandStats {
+    /// Add the stats of another connection
+    fn merge(&mut self, other: CommandStats) {
+        self.response_times.extend(other.response_times);
+        self.errors += other.errors;
+    }
+
+    /// Return the number of answered requests
+    pub fn count(&self) -> usize {
+        self.response_times.len()
+    }
+}
+
+impl fmt::Display for CommandStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "count={} errors={}", self.count(), self.errors)?;
+        let mut response_times = self.response_times.clone();
+        response_times.sort();
+        let quantiles = BENCH_QUANTILES
+            .iter()
+            .map(|(name, quantile)| (*name, nearest_rank(&response_times, *quantile)))
+            .chain([("max", response_times.last().copied())]);
+        for (name, response_time) in quantiles {
+            if let Some(response_time) = response_time {
+                write!(f, " {}={:.3}ms", name, response_time.as_secs_f64() * 1000.0)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Outcome of a bench against a node
+#[derive(Debug)]
+pub struct BenchReport {
+    pub socket: String,
+    pub elapsed: Duration,
+    pub connection_errors: u64,
+    pub set: CommandStats,
+    pub get: CommandStats,
+}
+
+impl BenchReport {
+    /// Return true if no connection or request failed
+    pub fn is_ok(&self) -> bool {
+        self.connection_errors == 0 && self.set.errors == 0 && self.get.errors == 0
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let requests = self.set.count() + self.get.count();
+        writeln!(
+            f,
+            "memcached {}: {} requests in {:.3}s, {:.1} req/s, {} connection errors",
+            self.socket,
+            requests,
+            self.elapsed.as_secs_f64(),
+            requests as f64 / self.elapsed.as_secs_f64(),
+            self.connection_errors
+        )?;
+        writeln!(f, "  set {}", self.set)?;
+        writeln!(f, "  get {}", self.get)
+    }
+}
+
+/// Time a command, reconnection is required on failure
+///
+/// # Arguments
+///
+/// * `client` - connection to the node
+/// * `cmd_type` - the string representation of the command
+/// * `cmd` - the memcached command to perform
+/// * `stats` - stats of the command type
+///
+/// # Return
+///
+/// * False if the command failed
+///
+async fn timed(
+    client: &mut Client,
+    cmd_type: &str,
+    cmd: impl Command,
+    stats: &mut CommandStats,
+) -> bool {
+    let start = Instant::now();
+    match client.handler_with_timeout(cmd_type, cmd).await {
+        Ok(_response) => {
+            stats.response_times.push(start.elapsed());
+            true
+        }
+        Err(issue) => {
+            warn!("Bench {} failed due to {}", cmd_type, issue);
+            stats.errors += 1;
+            false
+        }
+    }
+}
+
+/// Issue set and get on a connection until the deadline, reconnecting on failure
+///
+/// # Arguments
+///
+/// * `socket` - ip:port of the node
+/// * `rate` - requests per second on this connection, 0 for unlimited
+/// * `deadline` - end of the bench
+///
+async fn run_connection(socket: String, rate: f64, deadline: Instant) -> BenchReport {
+    let mut limiter: Option<Box<dyn RateLimiter>> =
+        (rate > 0.0).then(|| RateLimiterKind::Gcra.build("bench", 1.0, rate, 1));
+    let mut report = BenchReport {
+        socket: socket.clone(),
+        elapsed: Duration::ZERO,
+        connection_errors: 0,
+        set: CommandStats::default(),
+        get: CommandStats::default(),
+    };
+    let payload = payload();
+
+    while Instant::now() < deadline {
+        let mut client = match connect("bench", &socket).await {
+            Ok(client) => client,
+            Err(issue) => {
+                warn!("Issue to connect to {} due to {}", socket, issue);
+                report.connection_errors += 1;
+                sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        loop {
+            if let Some(limiter) = limiter.as_mut() {
+                let _ = limiter.acquire(1).await;
+            }
+            if Instant::now() >= deadline
+                || !timed(
+                    &mut client,
+                    "set",
+                    Set::new(KEY, &payload.value, payload.ttl),
+                    &mut report.set,
+                )
+                .await
+            {
+                break;
+            }
+            if let Some(limiter) = limiter.as_mut() {
+                let _ = limiter.acquire(1).await;
+            }
+            if Instant::now() >= deadline
+                || !timed(&mut client, "get", Get::new(KEY), &mut report.get).await
+            {
+                break;
+            }
+        }
+    }
+    report
+}
+
+/// Drive set and get against a node at a given rate and concurrency
+///
+/// # Arguments
+///
+/// * `socket` - ip:port of the node
+/// * `config` - rate, concurrency and duration of the load
+///
+/// # Return
+///
+/// * Response times of each command type over all connections
+///
+pub async fn bench(socket: &str, config: BenchConfig) -> BenchReport {
+    let start = Instant::now();
+    let deadline = start + config.duration;
+    let concurrency = config.concurrency.max(1);
+    let connection_rate = config.rate as f64 / concurrency as f64;
+
+    let connections: Vec<_> = (0..concurrency)
+        .map(|_| {
+            tokio::spawn(run_connection(
+                socket.to_string(),
+                connection_rate,
+                deadline,
+            ))
+        })
+        .collect();
+
+    let mut report = BenchReport {
+        socket: socket.to_string(),
+        elapsed: Duration::ZERO,
+        connection_errors: 0,
+        set: CommandStats::default(),
+        get: CommandStats::default(),
+    };
+    for connection in connections {
+        match connection.await {
+            Ok(connection_report) => {
+                report.connection_errors += connection_report.connection_errors;
+                report.set.merge(connection_report.set);
+                report.get.merge(connection_report.get);
+            }
+            Err(issue) => {
+                warn!("Bench connection panicked due to {}", issue);
+                report.connection_errors += 1;
+            }
+        }
+    }
+    report.elapsed = start.elapsed();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::memcached::bench::{bench, BenchConfig};
+
+    #[tokio::test]
+    async fn bench_node() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            loop {
+                let (mut socket, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let mut buffer = [0; 2048];
+                    while socket.read(&mut buffer).await.unwrap_or(0) > 0 {
+                        let response = hex::decode(format!("810000000000{}", "00".repeat(18)))
+                            .expect("Decoding failed");
+                        if socket.write_all(&response).await.is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+        });
+
+        let report = bench(
+            &addr,
+            BenchConfig {
+                rate: 200,
+                concurrency: 2,
+                duration: Duration::from_millis(300),
+            },
+        )
+        .await;
+        assert!(report.is_ok());
+        assert!(report.set.count() > 0);
+        assert!(report.get.count() > 0);
+        // Rate limited to about 60 requests
+        assert!(report.set.count() + report.get.count() <= 80);
+        assert!(report.to_string().contains("  set count="));
+    }
+}
@@ -1,24 +1,75 @@
+use bytes::{BufMut, BytesMut};
+
 use crate::memcached::header::RequestHeader;
 
 const SET_EXTRA_LEN: u8 = 8;
 
 pub const GET_OPCODE: u8 = 0;
 
-pub struct Get {
+pub struct Get<'a> {
     header: RequestHeader,
-    key: &'static [u8],
+    key: &'a [u8],
 }
 
 pub const SET_OPCODE: u8 = 1;
 
-pub struct Set {
+pub struct Set<'a> {
     header: RequestHeader,
-    key: &'static [u8],
-    value: &'static [u8],
+    key: &'a [u8],
+    value: &'a [u8],
     extra_field: [u8; SET_EXTRA_LEN as usize],
 }
 
-impl Set {
+pub const DELETE_OPCODE: u8 = 0x04;
+
+pub struct Delete<'a> {
+    header: RequestHeader,
+    key: &'a [u8],
+}
+
+pub const VERSION_OPCODE: u8 = 0x0b;
+
+pub struct Version {
+    header: RequestHeader,
+}
+
+pub const STAT_OPCODE: u8 = 0x10;
+
+pub struct Stat<'a> {
+    header: RequestHeader,
+    // Group of stats, empty for the general stats
+    key: &'a [u8],
+}
+
+pub const HELLO_OPCODE: u8 = 0x1f;
+
+// Name of the client reported to the node
+const HELLO_AGENT: &[u8] = b"probes";
+// Features negotiated with couchbase data nodes: xerror and select bucket
+const HELLO_FEATURES: [u16; 2] = [0x07, 0x08];
+
+pub struct Hello {
+    header: RequestHeader,
+}
+
+pub const SASL_AUTH_OPCODE: u8 = 0x21;
+
+const SASL_PLAIN_MECHANISM: &[u8] = b"PLAIN";
+
+pub struct SaslAuth<'a> {
+    header: RequestHeader,
+    username: &'a str,
+    password: &'a str,
+}
+
+pub const SELECT_BUCKET_OPCODE: u8 = 0x89;
+
+pub struct SelectBucket<'a> {
+    header: RequestHeader,
+    key: &'a [u8],
+}
+
+impl<'a> Set<'a> {
     /// Create a new Set command
     ///
     /// # Arguments
@@ -31,7 +82,7 @@ impl Set {
     ///
     /// * Set
     ///
-    pub fn new(key: &'static [u8], value: &'static [u8], ttl: u64) -> Set {
+    pub fn new(key: &'a [u8], value: &'a [u8], ttl: u64) -> Set<'a> {
         let extra_field: [u8; SET_EXTRA_LEN as usize] = ttl.to_be_bytes();
 
         let header = RequestHeader::new(
@@ -49,7 +100,7 @@ impl Set {
     }
 }
 
-impl Get {
+impl<'a> Get<'a> {
     /// Create a new Get command
     ///
     /// # Arguments
@@ -60,56 +111,290 @@ impl Get {
     ///
     /// * Get
     ///
-    pub fn new(key: &'static [u8]) -> Get {
+    pub fn new(key: &'a [u8]) -> Get<'a> {
         let header = RequestHeader::new(GET_OPCODE, key.len() as u16, 0, 0);
         Get { header, key }
     }
 }
 
+impl<'a> Delete<'a> {
+    /// Create a new Delete command
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the key as bytes
+    ///
+    /// # Return
+    ///
+    /// * Delete
+    ///
+    pub fn new(key: &'a [u8]) -> Delete<'a> {
+        let header = RequestHeader::new(DELETE_OPCODE, key.len() as u16, 0, 0);
+        Delete { header, key }
+    }
+}
+
+impl Version {
+    /// Create a new Version command
+    ///
+    /// # Return
+    ///
+    /// * Version
+    ///
+    pub fn new() -> Version {
+        let header = RequestHeader::new(VERSION_OPCODE, 0, 0, 0);
+        Version { header }
+    }
+}
+
+impl Default for Version {
+    fn default() -> Self {
+        Version::new()
+    }
+}
+
+impl<'a> Stat<'a> {
+    /// Create a new Stat command, answered with one response per stat
+    ///
+    /// # Arguments
+    ///
+    /// * `group` - group of stats like items or slabs, empty for the general stats
+    ///
+    /// # Return
+    ///
+    /// * Stat
+    ///
+    pub fn new(group: &'a str) -> Stat<'a> {
+        let key = group.as_bytes();
+        let header = RequestHeader::new(STAT_OPCODE, key.len() as u16, 0, 0);
+        Stat { header, key }
+    }
+}
+
+impl Hello {
+    /// Create a new Hello command negotiating the features used by the probe
+    ///
+    /// # Return
+    ///
+    /// * Hello
+    ///
+    pub fn new() -> Hello {
+        let header = RequestHeader::new(
+            HELLO_OPCODE,
+            HELLO_AGENT.len() as u16,
+            0,
+            (HELLO_FEATURES.len() * 2) as u32,
+        );
+        Hello { header }
+    }
+}
+
+impl Default for Hello {
+    fn default() -> Self {
+        Hello::new()
+    }
+}
+
+impl<'a> SaslAuth<'a> {
+    /// Create a new SaslAuth command using the PLAIN mechanism
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - the user authenticated
+    /// * `password` - the password of the user
+    ///
+    /// # Return
+    ///
+    /// * SaslAuth
+    ///
+    pub fn new(username: &'a str, password: &'a str) -> SaslAuth<'a> {
+        let header = RequestHeader::new(
+            SASL_AUTH_OPCODE,
+            SASL_PLAIN_MECHANISM.len() as u16,
+            0,
+            (username.len() + password.len() + 2) as u32,
+        );
+        SaslAuth {
+            header,
+            username,
+            password,
+        }
+    }
+}
+
+impl<'a> SelectBucket<'a> {
+    /// Create a new SelectBucket command
+    ///
+    /// # Arguments
+    ///
+    /// * `bucket` - the name of the bucket used by next commands
+    ///
+    /// # Return
+    ///
+    /// * SelectBucket
+    ///
+    pub fn new(bucket: &'a str) -> SelectBucket<'a> {
+        let key = bucket.as_bytes();
+        let header = RequestHeader::new(SELECT_BUCKET_OPCODE, key.len() as u16, 0, 0);
+        SelectBucket { header, key }
+    }
+}
+
 pub trait Command {
-    fn as_bytes(&mut self) -> Vec<u8>;
+    /// Write the request of the command to a buffer, up to its value
+    fn write_head(&self, dst: &mut BytesMut);
+
+    /// Value ending the request, sent from where it is stored instead of being copied
+    fn value(&self) -> &[u8] {
+        &[]
+    }
+
+    /// Write the whole request of the command to a buffer
+    fn write_to(&self, dst: &mut BytesMut) {
+        self.write_head(dst);
+        dst.put_slice(self.value());
+    }
+}
+
+impl Command for Set<'_> {
+    fn write_head(&self, dst: &mut BytesMut) {
+        self.header.write_to(dst);
+        dst.put_slice(&self.extra_field);
+        dst.put_slice(self.key);
+    }
+
+    fn value(&self) -> &[u8] {
+        self.value
+    }
 }
 
-impl Command for Set {
-    /// Return representation of Set as bytes
-    fn as_bytes(&mut self) -> Vec<u8> {
-        let mut req: Vec<u8> = Vec::new();
-        req.extend(self.header.as_bytes());
-        req.extend(self.extra_field);
-        req.extend(self.key);
-        req.extend(self.value);
-        req
+impl Command for Get<'_> {
+    fn write_head(&self, dst: &mut BytesMut) {
+        self.header.write_to(dst);
+        dst.put_slice(self.key);
     }
 }
 
-impl Command for Get {
-    /// Return representation of Get as bytes
-    fn as_bytes(&mut self) -> Vec<u8> {
-        let mut req: Vec<u8> = Vec::new();
-        req.extend(self.header.as_bytes());
-        req.extend(self.key);
-        req
+impl Command for Delete<'_> {
+    fn write_head(&self, dst: &mut BytesMut) {
+        self.header.write_to(dst);
+        dst.put_slice(self.key);
+    }
+}
+
+impl Command for Version {
+    fn write_head(&self, dst: &mut BytesMut) {
+        self.header.write_to(dst);
+    }
+}
+
+impl Command for Stat<'_> {
+    fn write_head(&self, dst: &mut BytesMut) {
+        self.header.write_to(dst);
+        dst.put_slice(self.key);
+    }
+}
+
+impl Command for Hello {
+    fn write_head(&self, dst: &mut BytesMut) {
+        self.header.write_to(dst);
+        dst.put_slice(HELLO_AGENT);
+        for feature in HELLO_FEATURES {
+            dst.put_u16(feature);
+        }
+    }
+}
+
+impl Command for SaslAuth<'_> {
+    fn write_head(&self, dst: &mut BytesMut) {
+        self.header.write_to(dst);
+        dst.put_slice(SASL_PLAIN_MECHANISM);
+        dst.put_u8(0);
+        dst.put_slice(self.username.as_bytes());
+        dst.put_u8(0);
+        dst.put_slice(self.password.as_bytes());
+    }
+}
+
+impl Command for SelectBucket<'_> {
+    fn write_head(&self, dst: &mut BytesMut) {
+        self.header.write_to(dst);
+        dst.put_slice(self.key);
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::memcached::command::{Command, Get, Set};
+    use bytes::BytesMut;
+
+    use crate::memcached::command::{
+        Command, Delete, Get, Hello, SaslAuth, SelectBucket, Set, Stat, Version,
+    };
+
+    fn encode(cmd: impl Command) -> BytesMut {
+        let mut dst = BytesMut::new();
+        cmd.write_to(&mut dst);
+        dst
+    }
 
     #[test]
-    fn set_as_bytes() {
+    fn set_write_to() {
         let input =
             "80010004080000000000001100000000000000000000000000000000000000647465737476616c7565";
         let decoded = hex::decode(input).expect("Decoding failed");
-        let mut set = Set::new("test".as_bytes(), "value".as_bytes(), 100);
-        assert_eq!(set.as_bytes(), decoded)
+        assert_eq!(
+            encode(Set::new("test".as_bytes(), "value".as_bytes(), 100)),
+            decoded
+        )
     }
 
     #[test]
-    fn get_as_bytes() {
+    fn get_write_to() {
         let input = "80000004000000000000000400000000000000000000000074657374";
         let decoded = hex::decode(input).expect("Decoding failed");
-        let mut get = Get::new("test".as_bytes());
-        assert_eq!(get.as_bytes(), decoded)
+        assert_eq!(encode(Get::new("test".as_bytes())), decoded)
+    }
+
+    #[test]
+    fn delete_write_to() {
+        let input = "80040004000000000000000400000000000000000000000074657374";
+        let decoded = hex::decode(input).expect("Decoding failed");
+        assert_eq!(encode(Delete::new("test".as_bytes())), decoded)
+    }
+
+    #[test]
+    fn version_write_to() {
+        let input = "800b00000000000000000000000000000000000000000000";
+        let decoded = hex::decode(input).expect("Decoding failed");
+        assert_eq!(encode(Version::new()), decoded)
+    }
+
+    #[test]
+    fn stat_write_to() {
+        let input = "8010000500000000000000050000000000000000000000006974656d73";
+        let decoded = hex::decode(input).expect("Decoding failed");
+        assert_eq!(encode(Stat::new("items")), decoded)
+    }
+
+    #[test]
+    fn hello_write_to() {
+        let input = "801f0006000000000000000a00000000000000000000000070726f62657300070008";
+        let decoded = hex::decode(input).expect("Decoding failed");
+        assert_eq!(encode(Hello::new()), decoded)
+    }
+
+    #[test]
+    fn sasl_auth_write_to() {
+        let input =
+            "802100050000000000000010000000000000000000000000504c41494e0075736572007061737377";
+        let decoded = hex::decode(input).expect("Decoding failed");
+        assert_eq!(encode(SaslAuth::new("user", "passw")), decoded)
+    }
+
+    #[test]
+    fn select_bucket_write_to() {
+        let input = "80890006000000000000000600000000000000000000000062756b6b6574";
+        let decoded = hex::decode(input).expect("Decoding failed");
+        assert_eq!(encode(SelectBucket::new("bukket")), decoded)
     }
 }
@@ -1,12 +1,11 @@
-use std::io::Cursor;
-
-use bytes::{Buf, Bytes};
+use bytes::{Buf, BufMut, BytesMut};
 
 use crate::memcached::MemcachedError;
 
-const HEADER_SIZE: u8 = 24;
+pub(crate) const HEADER_SIZE: u8 = 24;
 
 const REQUEST_PACKET: u8 = 128;
+const RESPONSE_PACKET: u8 = 129;
 const DATA_TYPE: u8 = 0;
 const RESERVED: u16 = 0;
 const OPAQUE: u32 = 0;
@@ -54,33 +53,41 @@ impl RequestHeader {
         }
     }
 
-    /// Get representation of the request header as bytes
-    pub fn as_bytes(&mut self) -> Vec<u8> {
-        let mut request_bytes: Vec<u8> = Vec::with_capacity(HEADER_SIZE as usize);
-        request_bytes.push(self.magic);
-        request_bytes.push(self.opcode);
-        request_bytes.extend(self.key_length);
-        request_bytes.push(self.extra_length);
-        request_bytes.push(self.data_type);
-        request_bytes.extend(self.reserved);
-        request_bytes.extend(self.total_body_length);
-        request_bytes.extend(self.opaque);
-        request_bytes.extend(self.cas);
-        request_bytes
+    /// Write the request header to a buffer, reserving room for the extras and the key
+    ///
+    /// # Arguments
+    ///
+    /// * `dst` - buffer of the request
+    ///
+    pub fn write_to(&self, dst: &mut BytesMut) {
+        dst.reserve(
+            HEADER_SIZE as usize
+                + u16::from_be_bytes(self.key_length) as usize
+                + self.extra_length as usize,
+        );
+        dst.put_u8(self.magic);
+        dst.put_u8(self.opcode);
+        dst.put_slice(&self.key_length);
+        dst.put_u8(self.extra_length);
+        dst.put_u8(self.data_type);
+        dst.put_slice(&self.reserved);
+        dst.put_slice(&self.total_body_length);
+        dst.put_slice(&self.opaque);
+        dst.put_slice(&self.cas);
     }
 }
 
 #[derive(Debug, PartialEq)]
 pub struct ResponseHeader {
-    magic: Bytes,
-    opcode: Bytes,
+    magic: u8,
+    opcode: u8,
     pub(crate) key_length: u16,
     pub(crate) extra_length: u8,
-    data_type: Bytes,
+    data_type: u8,
     pub status: u16,
     pub(crate) total_body_length: u32,
-    opaque: Bytes,
-    cas: Bytes,
+    opaque: u32,
+    cas: u64,
 }
 
 impl ResponseHeader {
@@ -88,23 +95,23 @@ impl ResponseHeader {
     ///
     /// # Arguments
     ///
-    /// * `src` - buffer of bytes
+    /// * `src` - buffer of bytes, checked to hold a whole header
     ///
     /// # Return
     ///
     /// * ResponseHeader
     ///
-    pub(crate) fn parse(src: &mut Cursor<&[u8]>) -> ResponseHeader {
+    pub(crate) fn parse(mut src: &[u8]) -> ResponseHeader {
         ResponseHeader {
-            magic: src.copy_to_bytes(1),
-            opcode: src.copy_to_bytes(1),
+            magic: src.get_u8(),
+            opcode: src.get_u8(),
             key_length: src.get_u16(),
             extra_length: src.get_u8(),
-            data_type: src.copy_to_bytes(1),
+            data_type: src.get_u8(),
             status: src.get_u16(),
             total_body_length: src.get_u32(),
-            opaque: src.copy_to_bytes(4),
-            cas: src.copy_to_bytes(8),
+            opaque: src.get_u32(),
+            cas: src.get_u64(),
         }
     }
 
@@ -121,14 +128,14 @@ impl ResponseHeader {
     ///   or an incomplete error if there are not enough bytes from the buffer
     ///   or an Other error if header magic is not of type response (x81)
     ///
-    pub fn check(src: &mut Cursor<&[u8]>) -> Result<usize, MemcachedError> {
+    pub fn check(mut src: &[u8]) -> Result<usize, MemcachedError> {
         // Check enough bytes to read for a response header
         if src.remaining() < HEADER_SIZE as usize {
             return Err(MemcachedError::Incomplete);
         }
 
         // CHeck magic field is the one forResponse Packet
-        if src.copy_to_bytes(1) != Bytes::from_static(b"\x81") {
+        if src.get_u8() != RESPONSE_PACKET {
             return Err(MemcachedError::Other);
         }
 
@@ -137,18 +144,13 @@ impl ResponseHeader {
         let total_body_size = src.get_u32();
         let total_len: usize = (HEADER_SIZE as u32 + total_body_size) as usize;
 
-        // Reset position
-        src.set_position(0);
-
         Ok(total_len)
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
-
-    use bytes::Bytes;
+    use bytes::BytesMut;
 
     use crate::memcached::command::SET_OPCODE;
     use crate::memcached::header::{RequestHeader, ResponseHeader};
@@ -158,18 +160,17 @@ mod tests {
     fn parse_response_header() {
         let input = "8100000004000000000000050000000000000000000000010000000030";
         let decoded = hex::decode(input).expect("Decoding failed");
-        let mut cursor = Cursor::new(decoded.as_slice());
-        let res = ResponseHeader::parse(&mut cursor);
+        let res = ResponseHeader::parse(decoded.as_slice());
         let response = ResponseHeader {
-            magic: Bytes::from_static(b"\x81"),
-            opcode: Bytes::from_static(b"\0"),
+            magic: 0x81,
+            opcode: 0,
             key_length: 0,
             extra_length: 4,
-            data_type: Bytes::from_static(b"\0"),
+            data_type: 0,
             status: 0,
             total_body_length: 5,
-            opaque: Bytes::from_static(b"\0\0\0\0"),
-            cas: Bytes::from_static(b"\0\0\0\0\0\0\0\x01"),
+            opaque: 0,
+            cas: 1,
         };
         assert_eq!(res, response);
     }
@@ -178,8 +179,8 @@ mod tests {
     fn check_response_header() {
         let input = "8100000004000000000000050000000000000000000000010000000030";
         let decoded = hex::decode(input).expect("Decoding failed");
-        let mut cursor = Cursor::new(decoded.as_slice());
-        let res = ResponseHeader::check(&mut cursor).expect("Failed to get total length response");
+        let res =
+            ResponseHeader::check(decoded.as_slice()).expect("Failed to get total length response");
         assert_eq!(res, 29);
     }
 
@@ -187,8 +188,7 @@ mod tests {
     fn check_response_header_bad_magic() {
         let input = "8000000004000000000000050000000000000000000000010000000030";
         let decoded = hex::decode(input).expect("Decoding failed");
-        let mut cursor = Cursor::new(decoded.as_slice());
-        let res = ResponseHeader::check(&mut cursor);
+        let res = ResponseHeader::check(decoded.as_slice());
         assert!(res.is_err());
         assert_eq!(res.err().unwrap(), MemcachedError::Other);
     }
@@ -197,20 +197,22 @@ mod tests {
     fn check_response_header_incomplete() {
         let input = "81000000040000000000000500000000000000000000";
         let decoded = hex::decode(input).expect("Decoding failed");
-        let mut cursor = Cursor::new(decoded.as_slice());
-        let res = ResponseHeader::check(&mut cursor);
+        let res = ResponseHeader::check(decoded.as_slice());
         assert!(res.is_err());
         assert_eq!(res.err().unwrap(), MemcachedError::Incomplete);
     }
 
     #[test]
-    fn header_as_bytes() {
+    fn header_write_to() {
         let input = "800100010800000000000011000000000000000000000000";
         let decoded = hex::decode(input).expect("Decoding failed");
         let key_length: u16 = 1;
         let extra_length = 8;
         let value_length: u32 = 8;
-        let mut header = RequestHeader::new(SET_OPCODE, key_length, extra_length, value_length);
-        assert_eq!(header.as_bytes(), decoded)
+        let header = RequestHeader::new(SET_OPCODE, key_length, extra_length, value_length);
+        let mut dst = BytesMut::new();
+        header.write_to(&mut dst);
+        assert_eq!(dst, decoded);
+        assert!(dst.capacity() >= 24 + 9)
     }
 }
@@ -1,29 +1,96 @@
 use std::collections::HashMap;
 use std::io;
-use std::io::Cursor;
+use std::io::IoSlice;
+use std::sync::OnceLock;
 use std::time::{Duration, Instant};
 
-use bytes::{Buf, BytesMut};
+use bytes::BytesMut;
 use lazy_static::lazy_static;
 use thiserror::Error;
-use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
 use tokio::net::TcpStream;
 use tokio::time::error::Elapsed;
+use tracing::{info, instrument};
 
-use crate::memcached::command::{Command, Get, Set};
+use crate::memcached::command::{
+    Command, Delete, Get, Hello, SaslAuth, SelectBucket, Set, Stat, Version,
+};
 use crate::memcached::response::Response;
-use crate::probes::prometheus::{NUMBER_OF_REQUESTS, RESPONSE_TIME_COLLECTOR};
+use crate::probes::prometheus::{BYTES_RECEIVED, BYTES_SENT};
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
 
+pub mod bench;
 mod command;
 mod header;
 mod response;
+pub mod tool;
 
 const KEY: &[u8] = "mempoke_key".as_bytes();
-const VALUE: &[u8] = "aaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaaa".as_bytes();
+const VALUE_BYTES: usize = 1024;
 const TTL: u64 = 300;
 
 const TIMEOUT: Duration = Duration::from_millis(100);
 
+const NO_ERROR: u16 = 0;
+const KEY_NOT_FOUND: u16 = 1;
+
+// Timeout of each command, only set once from main
+static PROBE_TIMEOUT: OnceLock<Duration> = OnceLock::new();
+
+// Value set by the probe and its ttl, only set once from main
+static PAYLOAD: OnceLock<Payload> = OnceLock::new();
+
+#[derive(Debug, PartialEq)]
+struct Payload {
+    value: Vec<u8>,
+    // Seconds before the key expires, 0 to never expire
+    ttl: u64,
+}
+
+impl Payload {
+    /// Returns a Payload
+    ///
+    /// # Arguments
+    ///
+    /// * `value_bytes` - size of the value set by the probe
+    /// * `ttl` - seconds before the key expires, 0 to never expire
+    ///
+    fn new(value_bytes: usize, ttl: u64) -> Payload {
+        Payload {
+            value: vec![b'a'; value_bytes],
+            ttl,
+        }
+    }
+}
+
+/// Return the payload set by the probe, 1KB with a 300s ttl if not configured
+fn payload() -> &'static Payload {
+    PAYLOAD.get_or_init(|| Payload::new(VALUE_BYTES, TTL))
+}
+
+// Bucket selected on couchbase data nodes, only set once from main
+static BUCKET_CONFIG: OnceLock<BucketConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct BucketConfig {
+    // Empty to probe plain memcached nodes
+    bucket: String,
+    // Empty to skip sasl authentication
+    username: String,
+    password: String,
+}
+
+// Keys probed through a memcached proxy, one per upstream shard, only set once from main
+static SHARD_KEYS: OnceLock<Vec<ShardKey>> = OnceLock::new();
+
+#[derive(Debug, PartialEq)]
+struct ShardKey {
+    key: Vec<u8>,
+    // Command types exported for the shard
+    set_cmd_type: String,
+    get_cmd_type: String,
+}
+
 lazy_static! {
     pub static ref STATUS_CODE: HashMap<u16, &'static str> = HashMap::from([
         (0, "NoError"),
@@ -33,6 +100,10 @@ lazy_static! {
         (4, "InvalidArguments"),
         (5, "ItemNotStored"),
         (6, "IncrDecrOnNonNumericValue"),
+        (7, "NotMyVbucket"),
+        (32, "AuthError"),
+        (33, "AuthContinue"),
+        (36, "AccessError"),
         (129, "UnknownCommand"),
         (130, "OutOfMemory"),
     ]);
@@ -62,6 +133,8 @@ pub enum MemcachedClientError {
         #[from]
         source: MemcachedError,
     },
+    #[error("Command {cmd_type} failed with status {status}.")]
+    Status { cmd_type: String, status: String },
     #[error("Timeout error: {source}.")]
     Timeout {
         #[from]
@@ -69,19 +142,146 @@ pub enum MemcachedClientError {
     },
 }
 
+/// Set the bucket selected on couchbase data nodes
+///
+/// # Arguments
+///
+/// * `bucket` - bucket probed on each node, empty for plain memcached nodes
+/// * `username` - user authenticated with sasl, empty to skip authentication
+/// * `password` - password of the user
+///
+pub fn set_bucket_config(bucket: &str, username: &str, password: &str) -> Result<(), String> {
+    if !bucket.is_empty() {
+        info!("Select bucket {} on memcached nodes", bucket);
+    }
+    BUCKET_CONFIG
+        .set(BucketConfig {
+            bucket: bucket.to_string(),
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+        .map_err(|_| "Memcached bucket config is already initialized".to_string())
+}
+
+/// Set the timeout of each command
+///
+/// # Arguments
+///
+/// * `timeout` - timeout of each command, shorter than the interval between each check
+///
+pub fn set_timeout(timeout: Duration) -> Result<(), String> {
+    PROBE_TIMEOUT
+        .set(timeout)
+        .map_err(|_| "Memcached timeout is already initialized".to_string())
+}
+
+/// Set the payload of the probe, generated once at startup
+///
+/// # Arguments
+///
+/// * `value_bytes` - size of the value set by the probe
+/// * `ttl` - seconds before the key expires, 0 to never expire
+///
+pub fn set_payload(value_bytes: usize, ttl: u64) -> Result<(), String> {
+    PAYLOAD
+        .set(Payload::new(value_bytes, ttl))
+        .map_err(|_| "Memcached payload is already initialized".to_string())
+}
+
+/// Build the keys probed through a memcached proxy
+///
+/// # Arguments
+///
+/// * `tags` - comma separated list of hash tags, each one routed to a different shard
+/// * `hash_tag` - the two delimiters of the part of the key hashed by the proxy
+///
+/// # Return
+///
+/// * One key per tag
+///
+fn build_shard_keys(tags: &str, hash_tag: &str) -> Result<Vec<ShardKey>, String> {
+    let delimiters: Vec<char> = hash_tag.chars().collect();
+    if delimiters.len() != 2 {
+        return Err(format!(
+            "Invalid hash tag {hash_tag}, expected two delimiters"
+        ));
+    }
+    Ok(tags
+        .split(',')
+        .map(str::trim)
+        .filter(|tag| !tag.is_empty())
+        .map(|tag| ShardKey {
+            key: format!(
+                "{}{}{tag}{}",
+                String::from_utf8_lossy(KEY),
+                delimiters[0],
+                delimiters[1]
+            )
+            .into_bytes(),
+            set_cmd_type: format!("set:{tag}"),
+            get_cmd_type: format!("get:{tag}"),
+        })
+        .collect())
+}
+
+/// Set the keys probed through a memcached proxy, one per upstream shard
+///
+/// # Arguments
+///
+/// * `tags` - comma separated list of hash tags, empty to probe a single key
+/// * `hash_tag` - the two delimiters of the part of the key hashed by the proxy
+///
+pub fn set_shard_keys(tags: &str, hash_tag: &str) -> Result<(), String> {
+    let shard_keys = build_shard_keys(tags, hash_tag)?;
+    if shard_keys.is_empty() {
+        return Ok(());
+    }
+    info!("Probe {} shards behind memcached proxies", shard_keys.len());
+    SHARD_KEYS
+        .set(shard_keys)
+        .map_err(|_| "Memcached shard keys are already initialized".to_string())
+}
+
+/// Return the name of a response status
+fn status_name(status: u16) -> &'static str {
+    STATUS_CODE.get(&status).copied().unwrap_or("Unknown")
+}
+
+/// Build the error of a command answered with a status other than NoError
+fn status_error(cmd_type: &str, status: u16) -> MemcachedClientError {
+    MemcachedClientError::Status {
+        cmd_type: cmd_type.to_string(),
+        status: status_name(status).to_string(),
+    }
+}
+
 pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, MemcachedClientError> {
     let socket = TcpStream::connect(addr).await?;
     let connection = Connection::new(socket);
-    Ok(Client {
+    let mut client = Client {
         cluster_name: cluster_name.to_owned(),
         addr: addr.to_owned(),
         connection,
-    })
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        shard_keys: SHARD_KEYS.get().map_or(&[], Vec::as_slice),
+        timeout: PROBE_TIMEOUT.get().copied().unwrap_or(TIMEOUT),
+    };
+    let config = BUCKET_CONFIG.get().cloned().unwrap_or_default();
+    if !config.bucket.is_empty() {
+        client.select_bucket(&config).await?;
+    }
+    Ok(client)
 }
 
 pub struct Connection {
-    stream: BufWriter<TcpStream>,
+    stream: TcpStream,
     buffer: BytesMut,
+    // Request being sent, reused by each command of the connection
+    write_buffer: BytesMut,
+    // Bytes of requests sent since last take
+    bytes_sent: u64,
+    // Bytes of responses received since last take
+    bytes_received: u64,
 }
 
 impl Connection {
@@ -97,23 +297,41 @@ impl Connection {
     ///
     pub fn new(socket: TcpStream) -> Self {
         Connection {
-            stream: BufWriter::new(socket),
+            stream: socket,
             buffer: BytesMut::with_capacity(4096),
+            write_buffer: BytesMut::with_capacity(4096),
+            bytes_sent: 0,
+            bytes_received: 0,
         }
     }
 
+    /// Return and reset the bytes sent and received since last call
+    ///
+    /// # Return
+    ///
+    /// * (bytes sent, bytes received)
+    ///
+    pub fn take_transferred_bytes(&mut self) -> (u64, u64) {
+        let transferred_bytes = (self.bytes_sent, self.bytes_received);
+        self.bytes_sent = 0;
+        self.bytes_received = 0;
+        transferred_bytes
+    }
+
     /// Send request to memcached node through the tcp stream
     ///
+    /// The value of the command is written along the rest of the request without being copied
+    ///
     /// # Arguments
     ///
     /// * `cmd` - memcached command to execute
     ///
-    pub async fn send_request(
-        &mut self,
-        mut cmd: impl Command,
-    ) -> Result<(), MemcachedClientError> {
-        self.stream.write_all(cmd.as_bytes().as_slice()).await?;
-        self.stream.flush().await?;
+    pub async fn send_request(&mut self, cmd: impl Command) -> Result<(), MemcachedClientError> {
+        self.write_buffer.clear();
+        cmd.write_head(&mut self.write_buffer);
+        let value = cmd.value();
+        write_all_vectored(&mut self.stream, &self.write_buffer, value).await?;
+        self.bytes_sent += (self.write_buffer.len() + value.len()) as u64;
         Ok(())
     }
 
@@ -147,8 +365,8 @@ impl Connection {
 
     /// Parse buffer to get response
     ///
-    /// Use a cursor on to of the buffer in order to be able to first check the buffer
-    /// and then rewind to really consume the flow of bytes from buffer
+    /// The buffer is first checked to hold a whole response which is then
+    /// split off the buffer without being copied
     ///
     /// # Return
     ///
@@ -157,12 +375,10 @@ impl Connection {
     ///   or an Other error from response header check
     ///
     fn parse_response(&mut self) -> Result<Response, MemcachedError> {
-        let mut buf = Cursor::new(&self.buffer[..]);
-
-        match Response::check(&mut buf) {
+        match Response::check(&self.buffer) {
             Ok(len) => {
-                let response = Response::parse(&mut buf);
-                self.buffer.advance(len);
+                let response = Response::parse(self.buffer.split_to(len).freeze());
+                self.bytes_received += len as u64;
                 Ok(response)
             }
             Err(issue) => Err(issue),
@@ -170,49 +386,287 @@ impl Connection {
     }
 }
 
+/// Write two buffers to a stream with as few writes as possible
+///
+/// # Arguments
+///
+/// * `stream` - tcp stream socket
+/// * `head` - first bytes to write
+/// * `tail` - bytes written after the head
+///
+async fn write_all_vectored(
+    stream: &mut TcpStream,
+    mut head: &[u8],
+    mut tail: &[u8],
+) -> io::Result<()> {
+    while !head.is_empty() || !tail.is_empty() {
+        let written = stream
+            .write_vectored(&[IoSlice::new(head), IoSlice::new(tail)])
+            .await?;
+        if written == 0 {
+            return Err(io::ErrorKind::WriteZero.into());
+        }
+        let head_written = written.min(head.len());
+        head = &head[head_written..];
+        tail = &tail[written - head_written..];
+    }
+    Ok(())
+}
+
 pub struct Client {
     cluster_name: String,
     addr: String,
     connection: Connection,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    // Empty when not probing through a proxy
+    shard_keys: &'static [ShardKey],
+    // Timeout of each command
+    timeout: Duration,
 }
 
 impl Client {
+    /// Set the timeout of each command of the client
+    ///
+    /// # Arguments
+    ///
+    /// * `timeout` - timeout of each command
+    ///
+    pub fn with_timeout(mut self, timeout: Duration) -> Client {
+        self.timeout = timeout;
+        self
+    }
+
     /// Probe action
     /// * issue one set
     /// * issue one get
+    ///
+    /// Through a proxy, one set and one get are issued per shard key and
+    /// all shards are probed even if one fails
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
     pub async fn probe(&mut self) -> Result<(), MemcachedClientError> {
-        self.set().await?;
-        self.get().await
+        if self.shard_keys.is_empty() {
+            self.set().await?;
+            return self.get().await;
+        }
+        let mut result = Ok(());
+        for shard_key in self.shard_keys {
+            let shard_result = self.probe_shard(shard_key).await;
+            if result.is_ok() {
+                result = shard_result;
+            }
+        }
+        result
+    }
+
+    /// Set and get calls on the key of a shard
+    async fn probe_shard(
+        &mut self,
+        shard_key: &'static ShardKey,
+    ) -> Result<(), MemcachedClientError> {
+        let key = shard_key.key.as_slice();
+        let payload = payload();
+        self.handler_with_timeout(
+            &shard_key.set_cmd_type,
+            Set::new(key, &payload.value, payload.ttl),
+        )
+        .await?;
+        self.handler_with_timeout(&shard_key.get_cmd_type, Get::new(key))
+            .await?;
+        Ok(())
     }
 
     /// Set call
     pub async fn set(&mut self) -> Result<(), MemcachedClientError> {
-        self.handler_with_timeout("set", Set::new(KEY, VALUE, TTL))
-            .await
+        let payload = payload();
+        self.handler_with_timeout("set", Set::new(KEY, &payload.value, payload.ttl))
+            .await?;
+        Ok(())
     }
 
     /// Get call
     pub async fn get(&mut self) -> Result<(), MemcachedClientError> {
-        self.handler_with_timeout("get", Get::new(KEY)).await
+        self.handler_with_timeout("get", Get::new(KEY)).await?;
+        Ok(())
+    }
+
+    /// Get the value of a key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the key as bytes
+    ///
+    /// # Return
+    ///
+    /// * Value of the key, None if the key is not found
+    ///
+    pub async fn get_value(&mut self, key: &[u8]) -> Result<Option<Vec<u8>>, MemcachedClientError> {
+        let response = self.handler_with_timeout("get", Get::new(key)).await?;
+        match response.header.status {
+            NO_ERROR => Ok(Some(response.value().to_vec())),
+            KEY_NOT_FOUND => Ok(None),
+            status => Err(status_error("get", status)),
+        }
+    }
+
+    /// Set the value of a key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the key as bytes
+    /// * `value` - the value as bytes
+    /// * `ttl` - seconds before the key expires, 0 to never expire
+    ///
+    pub async fn set_value(
+        &mut self,
+        key: &[u8],
+        value: &[u8],
+        ttl: u32,
+    ) -> Result<(), MemcachedClientError> {
+        self.check_status("set", Set::new(key, value, u64::from(ttl)))
+            .await?;
+        Ok(())
+    }
+
+    /// Delete a key
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - the key as bytes
+    ///
+    /// # Return
+    ///
+    /// * False if the key is not found
+    ///
+    pub async fn delete(&mut self, key: &[u8]) -> Result<bool, MemcachedClientError> {
+        let response = self
+            .handler_with_timeout("delete", Delete::new(key))
+            .await?;
+        match response.header.status {
+            NO_ERROR => Ok(true),
+            KEY_NOT_FOUND => Ok(false),
+            status => Err(status_error("delete", status)),
+        }
+    }
+
+    /// Get the version of the node
+    pub async fn version(&mut self) -> Result<String, MemcachedClientError> {
+        let response = self.check_status("version", Version::new()).await?;
+        Ok(String::from_utf8_lossy(response.value()).into_owned())
+    }
+
+    /// Get the stats of the node
+    ///
+    /// All the responses, ended by one without key, are read within the timeout
+    ///
+    /// # Arguments
+    ///
+    /// * `group` - group of stats like items or slabs, empty for the general stats
+    ///
+    /// # Return
+    ///
+    /// * Name and value of each stat
+    ///
+    pub async fn stats(
+        &mut self,
+        group: &str,
+    ) -> Result<Vec<(String, String)>, MemcachedClientError> {
+        tokio::time::timeout(self.timeout, self.read_stats(group)).await?
+    }
+
+    /// Send a stat command then read its responses until the one without key
+    async fn read_stats(
+        &mut self,
+        group: &str,
+    ) -> Result<Vec<(String, String)>, MemcachedClientError> {
+        let mut response = self.handle_request("stat", Stat::new(group)).await?;
+        let mut stats = Vec::new();
+        loop {
+            if response.header.status != NO_ERROR {
+                return Err(status_error("stat", response.header.status));
+            }
+            if response.key().is_empty() {
+                return Ok(stats);
+            }
+            stats.push((
+                String::from_utf8_lossy(response.key()).into_owned(),
+                String::from_utf8_lossy(response.value()).into_owned(),
+            ));
+            response = self.connection.read_response().await?;
+            self.record_transferred_bytes("stat");
+        }
+    }
+
+    /// Negotiate features, authenticate and select the bucket of a couchbase data node
+    ///
+    /// Keys are not mapped to their vbucket so set and get can be answered
+    /// with NotMyVbucket by nodes not owning the key, which is still counted
+    ///
+    /// # Arguments
+    ///
+    /// * `config` - bucket, username and password used on the node
+    ///
+    async fn select_bucket(&mut self, config: &BucketConfig) -> Result<(), MemcachedClientError> {
+        self.handler_with_timeout("hello", Hello::new()).await?;
+        if !config.username.is_empty() {
+            self.check_status(
+                "sasl_auth",
+                SaslAuth::new(&config.username, &config.password),
+            )
+            .await?;
+        }
+        self.check_status("select_bucket", SelectBucket::new(&config.bucket))
+            .await?;
+        Ok(())
+    }
+
+    /// Perform a command failing on any status other than NoError
+    async fn check_status(
+        &mut self,
+        cmd_type: &str,
+        cmd: impl Command,
+    ) -> Result<Response, MemcachedClientError> {
+        let response = self.handler_with_timeout(cmd_type, cmd).await?;
+        if response.header.status != NO_ERROR {
+            return Err(status_error(cmd_type, response.header.status));
+        }
+        Ok(response)
     }
 
     async fn handler_with_timeout(
         &mut self,
         cmd_type: &str,
         cmd: impl Command,
-    ) -> Result<(), MemcachedClientError> {
-        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type, cmd)).await {
-            Ok(Err(error)) => Err(error),
+    ) -> Result<Response, MemcachedClientError> {
+        match tokio::time::timeout(self.timeout, self.handle_request(cmd_type, cmd)).await {
+            Ok(response_res) => response_res,
             Err(_timeout_elapsed) => {
-                RESPONSE_TIME_COLLECTOR
-                    .with_label_values(&[self.cluster_name.as_str(), self.addr.as_str(), cmd_type])
-                    .observe(TIMEOUT.as_secs_f64());
+                self.request_metrics
+                    .observe_response_time(cmd_type, self.timeout);
                 Err(MemcachedClientError::from(_timeout_elapsed))
             }
-            _ => Ok(()),
         }
     }
 
+    /// Export bytes transferred on the connection since last call
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    ///
+    fn record_transferred_bytes(&mut self, cmd_type: &str) {
+        let (bytes_sent, bytes_received) = self.connection.take_transferred_bytes();
+        let labels = [self.cluster_name.as_str(), self.addr.as_str(), cmd_type];
+        BYTES_SENT.with_label_values(&labels).inc_by(bytes_sent);
+        BYTES_RECEIVED
+            .with_label_values(&labels)
+            .inc_by(bytes_received);
+    }
+
     /// Perform memcached request
     ///
     /// # Arguments
@@ -220,32 +674,251 @@ impl Client {
     /// * `cmd_type` - the string represensatation of the command
     /// * `cmd` - the memcached command to perform
     ///
+    /// # Return
+    ///
+    /// * Response of the node
+    ///
+    #[instrument(skip(self, cmd))]
     pub async fn handle_request(
         &mut self,
         cmd_type: &str,
         cmd: impl Command,
-    ) -> Result<(), MemcachedClientError> {
+    ) -> Result<Response, MemcachedClientError> {
         let start = Instant::now();
 
-        self.connection.send_request(cmd).await?;
+        if let Err(issue) = self.connection.send_request(cmd).await {
+            self.record_transferred_bytes(cmd_type);
+            return Err(issue);
+        }
+
+        let response_res = self.connection.read_response().await;
+        self.record_transferred_bytes(cmd_type);
 
-        match self.connection.read_response().await {
+        match response_res {
             Err(issue) => Err(issue),
             Ok(result) => {
-                NUMBER_OF_REQUESTS
-                    .with_label_values(&[
-                        self.cluster_name.as_str(),
-                        self.addr.as_str(),
-                        STATUS_CODE.get(&result.header.status).unwrap(),
-                        cmd_type,
-                    ])
-                    .inc();
+                let elapsed = start.elapsed();
+                let status = status_name(result.header.status);
+                self.request_metrics.count_request(status, cmd_type);
                 // TODO measure only succeed?
-                RESPONSE_TIME_COLLECTOR
-                    .with_label_values(&[self.cluster_name.as_str(), self.addr.as_str(), cmd_type])
-                    .observe(start.elapsed().as_secs_f64());
-                Ok(())
+                self.request_metrics
+                    .observe_response_time(cmd_type, elapsed);
+                Ok(result)
             }
         }
     }
 }
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bytes::BytesMut;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::memcached::command::{Command, Get, Set};
+    use crate::memcached::{
+        build_shard_keys, payload, BucketConfig, Client, Connection, MemcachedClientError, Payload,
+        KEY, TIMEOUT, TTL, VALUE_BYTES,
+    };
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+    use crate::probes::protocol::RequestMetrics;
+
+    #[tokio::test]
+    async fn connection_transferred_bytes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let response =
+                hex::decode("8100000004000000000000050000000000000000000000010000000030")
+                    .expect("Decoding failed");
+            socket.write_all(&response).await.unwrap();
+        });
+
+        let mut connection = Connection::new(TcpStream::connect(addr).await.unwrap());
+        connection.send_request(Get::new(KEY)).await.unwrap();
+        connection.read_response().await.unwrap();
+
+        assert_eq!(
+            (24 + KEY.len() as u64, 29),
+            connection.take_transferred_bytes()
+        );
+        assert_eq!((0, 0), connection.take_transferred_bytes());
+    }
+
+    #[tokio::test]
+    async fn send_request_large_value() {
+        let value: &'static [u8] = Box::leak(vec![b'a'; 1 << 20].into_boxed_slice());
+        let mut expected = BytesMut::new();
+        Set::new(KEY, value, TTL).write_to(&mut expected);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = Vec::new();
+            socket.read_to_end(&mut request).await.unwrap();
+            request
+        });
+
+        let mut connection = Connection::new(TcpStream::connect(addr).await.unwrap());
+        connection
+            .send_request(Set::new(KEY, value, TTL))
+            .await
+            .unwrap();
+        assert_eq!(
+            (expected.len() as u64, 0),
+            connection.take_transferred_bytes()
+        );
+        drop(connection);
+
+        assert_eq!(expected, server.await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn select_bucket_auth_error() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // Answer hello then reject the sasl authentication
+            for status in ["0000", "0020"] {
+                let mut buffer = [0; 256];
+                socket.read(&mut buffer).await.unwrap();
+                let response = hex::decode(format!(
+                    "810000000000{status}00000000000000000000000000000000"
+                ))
+                .expect("Decoding failed");
+                socket.write_all(&response).await.unwrap();
+            }
+        });
+
+        let mut client = Client {
+            cluster_name: "memcached_bucket".to_string(),
+            addr: addr.to_string(),
+            connection: Connection::new(TcpStream::connect(addr).await.unwrap()),
+            request_metrics: RequestMetrics::new("memcached_bucket", &addr.to_string()),
+            shard_keys: &[],
+            timeout: TIMEOUT,
+        };
+        let config = BucketConfig {
+            bucket: "default".to_string(),
+            username: "probes".to_string(),
+            password: "secret".to_string(),
+        };
+        match client.select_bucket(&config).await {
+            Err(MemcachedClientError::Status { cmd_type, status }) => {
+                assert_eq!("sasl_auth", cmd_type);
+                assert_eq!("AuthError", status);
+            }
+            _ => panic!("Sasl authentication should fail"),
+        }
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&[
+                    "memcached_bucket",
+                    &addr.to_string(),
+                    "NoError",
+                    "hello"
+                ])
+                .unwrap()
+                .get()
+        );
+    }
+
+    #[test]
+    fn default_payload() {
+        assert_eq!(&Payload::new(VALUE_BYTES, TTL), payload());
+        assert_eq!(1024, payload().value.len());
+        let payload = Payload::new(16, 60);
+        assert_eq!(b"aaaaaaaaaaaaaaaa".to_vec(), payload.value);
+        assert_eq!(60, payload.ttl);
+    }
+
+    #[test]
+    fn shard_keys() {
+        let shard_keys = build_shard_keys("a, b,", "{}").unwrap();
+        assert_eq!(2, shard_keys.len());
+        assert_eq!(b"mempoke_key{b}".to_vec(), shard_keys[1].key);
+        assert_eq!("get:b", shard_keys[1].get_cmd_type);
+        assert!(build_shard_keys("a", "{").is_err());
+    }
+
+    #[tokio::test]
+    async fn probe_shards() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // Set and get of both shards, the get of the second one misses
+            for status in ["0000", "0000", "0000", "0001"] {
+                let mut buffer = [0; 2048];
+                socket.read(&mut buffer).await.unwrap();
+                let response = hex::decode(format!(
+                    "810000000000{status}00000000000000000000000000000000"
+                ))
+                .expect("Decoding failed");
+                socket.write_all(&response).await.unwrap();
+            }
+        });
+
+        let mut client = Client {
+            cluster_name: "memcached_shards".to_string(),
+            addr: addr.to_string(),
+            connection: Connection::new(TcpStream::connect(addr).await.unwrap()),
+            request_metrics: RequestMetrics::new("memcached_shards", &addr.to_string()),
+            shard_keys: Box::leak(build_shard_keys("a,b", "{}").unwrap().into_boxed_slice()),
+            timeout: TIMEOUT,
+        };
+        client.probe().await.unwrap();
+        for (status, cmd_type) in [("NoError", "get:a"), ("KeyNotFound", "get:b")] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&[
+                        "memcached_shards",
+                        &addr.to_string(),
+                        status,
+                        cmd_type
+                    ])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Never answer the set
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 2048];
+            socket.read(&mut buffer).await.unwrap();
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        });
+
+        let mut client = Client {
+            cluster_name: "memcached_timeout".to_string(),
+            addr: addr.to_string(),
+            connection: Connection::new(TcpStream::connect(addr).await.unwrap()),
+            request_metrics: RequestMetrics::new("memcached_timeout", &addr.to_string()),
+            shard_keys: &[],
+            timeout: Duration::from_millis(20),
+        };
+        assert!(matches!(
+            client.probe().await,
+            Err(MemcachedClientError::Timeout { .. })
+        ));
+    }
+}
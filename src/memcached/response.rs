@@ -1,12 +1,12 @@
-use std::io::Cursor;
+use bytes::Bytes;
 
-use bytes::Buf;
-
-use crate::memcached::header::ResponseHeader;
+use crate::memcached::header::{ResponseHeader, HEADER_SIZE};
 use crate::memcached::MemcachedError;
 
 pub struct Response {
     pub header: ResponseHeader,
+    // Extras, key and value following the header
+    body: Bytes,
 }
 
 impl Response {
@@ -23,11 +23,11 @@ impl Response {
     ///   or an incomplete error if there are not enough bytes from the buffer
     ///   or an Other error from response header check
     ///
-    pub fn check(src: &mut Cursor<&[u8]>) -> Result<usize, MemcachedError> {
+    pub fn check(src: &[u8]) -> Result<usize, MemcachedError> {
         let total_len = ResponseHeader::check(src)?;
 
         // Check remaining
-        if src.remaining() < total_len {
+        if src.len() < total_len {
             return Err(MemcachedError::Incomplete);
         }
 
@@ -37,29 +37,43 @@ impl Response {
     ///
     /// # Arguments
     ///
-    /// * `src` - buffer of bytes
+    /// * `src` - bytes of a whole response, as checked by check
     ///
     /// # Return
     ///
     /// * Response
     ///
-    pub fn parse(src: &mut Cursor<&[u8]>) -> Response {
-        let header = ResponseHeader::parse(src);
-        Response { header }
+    pub fn parse(src: Bytes) -> Response {
+        let header = ResponseHeader::parse(&src);
+        let body = src.slice(HEADER_SIZE as usize..);
+        Response { header, body }
+    }
+
+    /// Return the key of the response, empty if not returned by the command
+    pub fn key(&self) -> &[u8] {
+        let start = self.header.extra_length as usize;
+        self.body
+            .get(start..start + self.header.key_length as usize)
+            .unwrap_or(&[])
+    }
+
+    /// Return the value of the response, empty if not returned by the command
+    pub fn value(&self) -> &[u8] {
+        let start = self.header.extra_length as usize + self.header.key_length as usize;
+        self.body.get(start..).unwrap_or(&[])
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use std::io::Cursor;
+    use bytes::Bytes;
 
     use crate::memcached::response::Response;
     use crate::memcached::MemcachedError;
 
     fn check(input: &str) -> Result<usize, MemcachedError> {
         let decoded = hex::decode(input).expect("Decoding failed");
-        let mut cursor = Cursor::new(decoded.as_slice());
-        Response::check(&mut cursor)
+        Response::check(decoded.as_slice())
     }
 
     #[test]
@@ -81,8 +95,19 @@ mod tests {
         let decoded =
             hex::decode("81000000040000000000000c00000000000000000000000100000000546573744e69636f")
                 .expect("Decoding failed");
-        let mut cursor = Cursor::new(decoded.as_slice());
-        let response = Response::parse(&mut cursor);
+        let response = Response::parse(Bytes::from(decoded));
         assert_eq!(response.header.total_body_length, 12);
+        assert!(response.key().is_empty());
+        assert_eq!(response.value(), b"TestNico");
+    }
+
+    #[test]
+    fn parse_response_truncated_key() {
+        // Key length larger than the body
+        let decoded = hex::decode("810000100000000000000002000000000000000000000000abcd")
+            .expect("Decoding failed");
+        let response = Response::parse(Bytes::from(decoded));
+        assert!(response.key().is_empty());
+        assert!(response.value().is_empty());
     }
 }
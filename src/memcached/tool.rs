@@ -0,0 +1,238 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use crate::memcached::{connect, MemcachedClientError};
+
+// Command run against a single node
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ToolCommand {
+    Get {
+        key: String,
+    },
+    Set {
+        key: String,
+        value: String,
+        // Seconds before the key expires, 0 to never expire
+        ttl: u32,
+    },
+    Delete {
+        key: String,
+    },
+    Stats {
+        // Group of stats like items or slabs, empty for the general stats
+        group: String,
+    },
+    Version,
+}
+
+impl ToolCommand {
+    /// Return the string representation of the command
+    pub fn cmd_type(&self) -> &'static str {
+        match self {
+            ToolCommand::Get { .. } => "get",
+            ToolCommand::Set { .. } => "set",
+            ToolCommand::Delete { .. } => "delete",
+            ToolCommand::Stats { .. } => "stats",
+            ToolCommand::Version => "version",
+        }
+    }
+}
+
+// Answer of the node to a command
+#[derive(Debug, PartialEq, Eq)]
+pub enum ToolOutput {
+    Value(Vec<u8>),
+    NotFound,
+    Stored,
+    Deleted,
+    Stats(Vec<(String, String)>),
+    Version(String),
+}
+
+impl fmt::Display for ToolOutput {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ToolOutput::Value(value) => match std::str::from_utf8(value) {
+                Ok(text) => write!(f, "found {} bytes\n{}", value.len(), text),
+                Err(_) => write!(f, "found {} bytes\n0x{}", value.len(), hex::encode(value)),
+            },
+            ToolOutput::NotFound => write!(f, "not found"),
+            ToolOutput::Stored => write!(f, "stored"),
+            ToolOutput::Deleted => write!(f, "deleted"),
+            ToolOutput::Stats(stats) => {
+                write!(f, "{} stats", stats.len())?;
+                let width = stats.iter().map(|(name, _)| name.len()).max().unwrap_or(0);
+                for (name, value) in stats {
+                    write!(f, "\n  {name:width$} {value}")?;
+                }
+                Ok(())
+            }
+            ToolOutput::Version(version) => write!(f, "version {version}"),
+        }
+    }
+}
+
+// Outcome of a command against a node
+#[derive(Debug)]
+pub struct ToolReport {
+    pub socket: String,
+    pub cmd_type: &'static str,
+    pub elapsed: Duration,
+    pub output: Result<ToolOutput, MemcachedClientError>,
+}
+
+impl ToolReport {
+    /// Return true if the node answered the command, even if the key is not found
+    pub fn is_ok(&self) -> bool {
+        self.output.is_ok()
+    }
+}
+
+impl fmt::Display for ToolReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "memcached {} {}: ", self.socket, self.cmd_type)?;
+        match &self.output {
+            Ok(output) => writeln!(
+                f,
+                "{} in {:.3}ms",
+                output,
+                self.elapsed.as_secs_f64() * 1000.0
+            ),
+            Err(issue) => writeln!(f, "failed due to {issue}"),
+        }
+    }
+}
+
+/// Connect to a node and perform a single command
+///
+/// # Arguments
+///
+/// * `socket` - ip:port of the node
+/// * `command` - the command to perform
+/// * `timeout` - timeout of the connection and of the command
+///
+/// # Return
+///
+/// * Answer of the node
+///
+async fn execute(
+    socket: &str,
+    command: &ToolCommand,
+    timeout: Duration,
+) -> Result<ToolOutput, MemcachedClientError> {
+    let mut client = tokio::time::timeout(timeout, connect("tool", socket))
+        .await??
+        .with_timeout(timeout);
+    match command {
+        ToolCommand::Get { key } => Ok(client
+            .get_value(key.as_bytes())
+            .await?
+            .map_or(ToolOutput::NotFound, ToolOutput::Value)),
+        ToolCommand::Set { key, value, ttl } => {
+            client
+                .set_value(key.as_bytes(), value.as_bytes(), *ttl)
+                .await?;
+            Ok(ToolOutput::Stored)
+        }
+        ToolCommand::Delete { key } => Ok(if client.delete(key.as_bytes()).await? {
+            ToolOutput::Deleted
+        } else {
+            ToolOutput::NotFound
+        }),
+        ToolCommand::Stats { group } => Ok(ToolOutput::Stats(client.stats(group).await?)),
+        ToolCommand::Version => Ok(ToolOutput::Version(client.version().await?)),
+    }
+}
+
+/// Perform a single command against a node, for manual checks of the node
+///
+/// # Arguments
+///
+/// * `socket` - ip:port of the node
+/// * `command` - the command to perform
+/// * `timeout` - timeout of the connection and of the command
+///
+/// # Return
+///
+/// * Answer of the node and the time it took
+///
+pub async fn run_command(socket: &str, command: &ToolCommand, timeout: Duration) -> ToolReport {
+    let start = Instant::now();
+    let output = execute(socket, command, timeout).await;
+    ToolReport {
+        socket: socket.to_string(),
+        cmd_type: command.cmd_type(),
+        elapsed: start.elapsed(),
+        output,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::memcached::tool::{run_command, ToolCommand, ToolOutput};
+
+    // Answer the first request of a connection with the given responses
+    async fn serve(responses: &'static [&'static str]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 256];
+            socket.read(&mut buffer).await.unwrap();
+            for response in responses {
+                let response = hex::decode(response).expect("Decoding failed");
+                socket.write_all(&response).await.unwrap();
+            }
+            tokio::time::sleep(Duration::from_secs(1)).await;
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn get_value() {
+        let addr = serve(&[concat!(
+            "810000000400000000000007000000000000000000000000",
+            "00000000626172"
+        )])
+        .await;
+        let report = run_command(
+            &addr,
+            &ToolCommand::Get {
+                key: "foo".to_string(),
+            },
+            Duration::from_millis(500),
+        )
+        .await;
+        assert!(report.is_ok());
+        assert_eq!(ToolOutput::Value(b"bar".to_vec()), report.output.unwrap());
+    }
+
+    #[tokio::test]
+    async fn stats() {
+        let addr = serve(&[
+            "811000030000000000000004000000000000000000000000",
+            "70696431",
+            "811000000000000000000000000000000000000000000000",
+        ])
+        .await;
+        let report = run_command(
+            &addr,
+            &ToolCommand::Stats {
+                group: String::new(),
+            },
+            Duration::from_millis(500),
+        )
+        .await;
+        let output = report.output.unwrap();
+        assert_eq!(
+            ToolOutput::Stats(vec![("pid".to_string(), "1".to_string())]),
+            output
+        );
+        assert_eq!("1 stats\n  pid 1", output.to_string());
+    }
+}
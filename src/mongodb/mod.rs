@@ -0,0 +1,508 @@
+use std::collections::HashMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, BytesMut};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::instrument;
+
+use crate::probes::prometheus::NODE_ROLE;
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const OP_MSG: i32 = 2013;
+const HEADER_LEN: usize = 16;
+
+// Max size of a reply, hello and ping replies are small
+const MAX_REPLY_SIZE: usize = 1024 * 1024;
+
+const ROLES: [&str; 3] = ["primary", "secondary", "other"];
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+#[derive(Error, Debug)]
+pub enum MongodbClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid reply: {0}")]
+    InvalidReply(String),
+    #[error("Unexpected response to {received}, expected {expected}.")]
+    ResponseTo { expected: i32, received: i32 },
+    #[error("Command {cmd_type} failed: {message}")]
+    CommandFailed { cmd_type: String, message: String },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+// Top level value of a BSON reply, nested values are not needed by the probe
+#[derive(Debug, PartialEq, Clone)]
+pub enum BsonValue {
+    Double(f64),
+    String(String),
+    Bool(bool),
+    Int32(i32),
+    Int64(i64),
+    Other,
+}
+
+impl BsonValue {
+    fn as_f64(&self) -> Option<f64> {
+        match self {
+            BsonValue::Double(value) => Some(*value),
+            BsonValue::Int32(value) => Some(*value as f64),
+            BsonValue::Int64(value) => Some(*value as f64),
+            _ => None,
+        }
+    }
+}
+
+/// Encode a command document `{<command>: 1, $db: "admin"}`
+///
+/// # Arguments
+///
+/// * `command` - name of the command
+///
+fn encode_command_document(command: &str) -> Vec<u8> {
+    let mut elements = BytesMut::new();
+    elements.put_u8(0x10);
+    elements.put_slice(command.as_bytes());
+    elements.put_u8(0);
+    elements.put_i32_le(1);
+    elements.put_u8(0x02);
+    elements.put_slice(b"$db\0");
+    elements.put_i32_le(6);
+    elements.put_slice(b"admin\0");
+
+    let mut document = BytesMut::with_capacity(elements.len() + 5);
+    document.put_i32_le((elements.len() + 5) as i32);
+    document.put_slice(&elements);
+    document.put_u8(0);
+    document.to_vec()
+}
+
+/// Encode an OP_MSG with a single body section
+///
+/// # Arguments
+///
+/// * `request_id` - id returned in the response to field of the reply
+/// * `command` - name of the command
+///
+fn encode_op_msg(request_id: i32, command: &str) -> Vec<u8> {
+    let document = encode_command_document(command);
+    let mut message = BytesMut::with_capacity(HEADER_LEN + 5 + document.len());
+    message.put_i32_le((HEADER_LEN + 5 + document.len()) as i32);
+    message.put_i32_le(request_id);
+    message.put_i32_le(0);
+    message.put_i32_le(OP_MSG);
+    // Flag bits and body section kind
+    message.put_u32_le(0);
+    message.put_u8(0);
+    message.put_slice(&document);
+    message.to_vec()
+}
+
+fn invalid(message: &str) -> MongodbClientError {
+    MongodbClientError::InvalidReply(message.to_string())
+}
+
+/// Read the length prefix of a value, checked against the remaining elements
+///
+/// # Arguments
+///
+/// * `elements` - remaining elements starting with the value
+/// * `extra` - bytes of the value not counted by its length prefix
+/// * `message` - error returned for a negative or too large length
+///
+/// # Return
+///
+/// * Number of bytes of the value
+///
+fn prefixed_len(elements: &[u8], extra: usize, message: &str) -> Result<usize, MongodbClientError> {
+    if elements.len() < 4 {
+        return Err(invalid(message));
+    }
+    let len = i32::from_le_bytes([elements[0], elements[1], elements[2], elements[3]]);
+    usize::try_from(len)
+        .ok()
+        .and_then(|len| len.checked_add(extra))
+        .filter(|value_len| *value_len <= elements.len())
+        .ok_or_else(|| invalid(message))
+}
+
+/// Parse the top level fields of a BSON document
+///
+/// # Arguments
+///
+/// * `document` - BSON document, starting with its length
+///
+fn parse_document(mut document: &[u8]) -> Result<HashMap<String, BsonValue>, MongodbClientError> {
+    if document.len() < 5 {
+        return Err(invalid("truncated document"));
+    }
+    let len = document.get_i32_le() as usize;
+    if len < 5 || document.len() < len - 4 {
+        return Err(invalid("truncated document"));
+    }
+    let mut elements = &document[..len - 5];
+    let mut fields = HashMap::new();
+    while !elements.is_empty() {
+        let element_type = elements.get_u8();
+        let name_end = elements
+            .iter()
+            .position(|byte| *byte == 0)
+            .ok_or_else(|| invalid("missing NUL terminator"))?;
+        let name = String::from_utf8_lossy(&elements[..name_end]).to_string();
+        elements.advance(name_end + 1);
+
+        let value_len = match element_type {
+            0x01 | 0x09 | 0x11 | 0x12 => 8,
+            0x02 | 0x0d | 0x0e => prefixed_len(elements, 4, "truncated string")?,
+            0x03 | 0x04 => prefixed_len(elements, 0, "truncated document")?,
+            0x05 => prefixed_len(elements, 5, "truncated binary")?,
+            0x07 => 12,
+            0x08 => 1,
+            0x06 | 0x0a | 0x7f | 0xff => 0,
+            0x10 => 4,
+            0x13 => 16,
+            other => return Err(invalid(&format!("unsupported type {other:#x}"))),
+        };
+        if elements.len() < value_len {
+            return Err(invalid("truncated value"));
+        }
+        let mut value = &elements[..value_len];
+        let value = match element_type {
+            0x01 => BsonValue::Double(value.get_f64_le()),
+            0x02 => BsonValue::String(
+                String::from_utf8_lossy(&value[4..value_len.saturating_sub(1).max(4)]).to_string(),
+            ),
+            0x08 => BsonValue::Bool(value[0] != 0),
+            0x10 => BsonValue::Int32(value.get_i32_le()),
+            0x12 => BsonValue::Int64(value.get_i64_le()),
+            _ => BsonValue::Other,
+        };
+        elements.advance(value_len);
+        fields.insert(name, value);
+    }
+    Ok(fields)
+}
+
+/// Return the role of the node from a hello reply
+fn role(reply: &HashMap<String, BsonValue>) -> &'static str {
+    let is_true = |field: &str| reply.get(field) == Some(&BsonValue::Bool(true));
+    if is_true("isWritablePrimary") || is_true("ismaster") {
+        "primary"
+    } else if is_true("secondary") {
+        "secondary"
+    } else {
+        "other"
+    }
+}
+
+/// Return the status exported in the number of requests metric
+///
+/// The code name is used for failed commands
+fn reply_status(reply: &HashMap<String, BsonValue>) -> String {
+    if reply.get("ok").and_then(BsonValue::as_f64) == Some(1.0) {
+        return "OK".to_string();
+    }
+    match reply.get("codeName") {
+        Some(BsonValue::String(code_name)) => code_name.clone(),
+        _ => "Failed".to_string(),
+    }
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, MongodbClientError> {
+    let stream = TcpStream::connect(addr).await?;
+    Ok(Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stream,
+        request_id: 0,
+    })
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    stream: TcpStream,
+    request_id: i32,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one hello and export the role of the node
+    /// * issue one ping
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), MongodbClientError> {
+        self.hello().await?;
+        self.ping().await
+    }
+
+    /// Hello call
+    pub async fn hello(&mut self) -> Result<(), MongodbClientError> {
+        let reply = self.handler_with_timeout("hello").await?;
+        let current_role = role(&reply);
+        for role in ROLES {
+            NODE_ROLE
+                .with_label_values(&[&self.cluster_name, &self.addr, role])
+                .set((role == current_role) as i64);
+        }
+        Ok(())
+    }
+
+    /// Ping call
+    pub async fn ping(&mut self) -> Result<(), MongodbClientError> {
+        self.handler_with_timeout("ping").await.map(|_| ())
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+    ) -> Result<HashMap<String, BsonValue>, MongodbClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type)).await {
+            Ok(reply_res) => reply_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(MongodbClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform mongodb request
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - name of the command run on the admin database
+    ///
+    #[instrument(skip(self))]
+    pub async fn handle_request(
+        &mut self,
+        cmd_type: &str,
+    ) -> Result<HashMap<String, BsonValue>, MongodbClientError> {
+        let start = Instant::now();
+        self.request_id = self.request_id.wrapping_add(1);
+        self.stream
+            .write_all(&encode_op_msg(self.request_id, cmd_type))
+            .await?;
+
+        let reply = self.read_reply().await?;
+        let elapsed = start.elapsed();
+        let status = reply_status(&reply);
+        self.request_metrics.count_request(&status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+
+        if status != "OK" {
+            let message = match reply.get("errmsg") {
+                Some(BsonValue::String(errmsg)) => errmsg.clone(),
+                _ => status,
+            };
+            return Err(MongodbClientError::CommandFailed {
+                cmd_type: cmd_type.to_string(),
+                message,
+            });
+        }
+        Ok(reply)
+    }
+
+    /// Read an OP_MSG reply and parse its body section
+    async fn read_reply(&mut self) -> Result<HashMap<String, BsonValue>, MongodbClientError> {
+        let mut header = [0; HEADER_LEN];
+        self.stream.read_exact(&mut header).await?;
+        let mut header = &header[..];
+        let len = header.get_i32_le() as usize;
+        // Request id of the reply
+        header.advance(4);
+        let response_to = header.get_i32_le();
+        let op_code = header.get_i32_le();
+        if !(HEADER_LEN + 5..=MAX_REPLY_SIZE).contains(&len) {
+            return Err(invalid(&format!("invalid size {len}")));
+        }
+        let mut body = vec![0; len - HEADER_LEN];
+        self.stream.read_exact(&mut body).await?;
+
+        if response_to != self.request_id {
+            return Err(MongodbClientError::ResponseTo {
+                expected: self.request_id,
+                received: response_to,
+            });
+        }
+        if op_code != OP_MSG {
+            return Err(invalid(&format!("unexpected op code {op_code}")));
+        }
+        // Flag bits, then a body section is expected
+        if body[4] != 0 {
+            return Err(invalid("unexpected section kind"));
+        }
+        parse_document(&body[5..])
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::{BufMut, BytesMut};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::mongodb::{
+        connect, encode_command_document, parse_document, reply_status, role, BsonValue,
+        MongodbClientError,
+    };
+    use crate::probes::prometheus::{NODE_ROLE, NUMBER_OF_REQUESTS};
+
+    fn document(elements: &[u8]) -> Vec<u8> {
+        let mut document = BytesMut::new();
+        document.put_i32_le(elements.len() as i32 + 5);
+        document.put_slice(elements);
+        document.put_u8(0);
+        document.to_vec()
+    }
+
+    fn hello_reply() -> Vec<u8> {
+        let mut elements = BytesMut::new();
+        elements.put_u8(0x08);
+        elements.put_slice(b"isWritablePrimary\0");
+        elements.put_u8(0);
+        elements.put_u8(0x08);
+        elements.put_slice(b"secondary\0");
+        elements.put_u8(1);
+        elements.put_u8(0x02);
+        elements.put_slice(b"setName\0");
+        elements.put_i32_le(4);
+        elements.put_slice(b"rs0\0");
+        elements.put_u8(0x04);
+        elements.put_slice(b"hosts\0");
+        elements.put_slice(&document(&[]));
+        elements.put_u8(0x01);
+        elements.put_slice(b"ok\0");
+        elements.put_f64_le(1.0);
+        document(&elements)
+    }
+
+    #[test]
+    fn encode() {
+        let document = encode_command_document("ping");
+        assert_eq!(document.len(), document[0] as usize);
+        let fields = parse_document(&document).unwrap();
+        assert_eq!(Some(&BsonValue::Int32(1)), fields.get("ping"));
+        assert_eq!(
+            Some(&BsonValue::String("admin".to_string())),
+            fields.get("$db")
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let reply = parse_document(&hello_reply()).unwrap();
+        assert_eq!("secondary", role(&reply));
+        assert_eq!("OK", reply_status(&reply));
+        assert_eq!(
+            Some(&BsonValue::String("rs0".to_string())),
+            reply.get("setName")
+        );
+        assert!(parse_document(&[10, 0, 0, 0, 0x08]).is_err());
+
+        let mut elements = BytesMut::new();
+        elements.put_u8(0x01);
+        elements.put_slice(b"ok\0");
+        elements.put_f64_le(0.0);
+        elements.put_u8(0x02);
+        elements.put_slice(b"codeName\0");
+        elements.put_i32_le(13);
+        elements.put_slice(b"Unauthorized\0");
+        let reply = parse_document(&document(&elements)).unwrap();
+        assert_eq!("Unauthorized", reply_status(&reply));
+        assert_eq!("other", role(&reply));
+    }
+
+    #[test]
+    fn parse_string_length() {
+        let string_document = |len: i32| {
+            let mut elements = BytesMut::new();
+            elements.put_u8(0x02);
+            elements.put_slice(b"setName\0");
+            elements.put_i32_le(len);
+            elements.put_slice(b"rs0\0");
+            document(&elements)
+        };
+        assert_eq!(
+            Some(&BsonValue::String("rs0".to_string())),
+            parse_document(&string_document(4)).unwrap().get("setName")
+        );
+        for len in [-1, i32::MIN, 5, i32::MAX] {
+            assert!(matches!(
+                parse_document(&string_document(len)),
+                Err(MongodbClientError::InvalidReply(message)) if message == "truncated string"
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            for reply in [
+                hello_reply(),
+                document(&[0x01, b'o', b'k', 0, 0, 0, 0, 0, 0, 0, 0xf0, 0x3f]),
+            ] {
+                let len = socket.read_i32_le().await.unwrap();
+                let mut request = vec![0; len as usize - 4];
+                socket.read_exact(&mut request).await.unwrap();
+
+                let mut message = BytesMut::new();
+                message.put_i32_le(16 + 5 + reply.len() as i32);
+                message.put_i32_le(1);
+                // Response to the request id
+                message.put_slice(&request[..4]);
+                message.put_i32_le(2013);
+                message.put_u32_le(0);
+                message.put_u8(0);
+                message.put_slice(&reply);
+                socket.write_all(&message).await.unwrap();
+            }
+        });
+
+        let mut client = connect("mongodb_probe", &addr).await.unwrap();
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["mongodb_probe", &addr, "OK", "ping"])
+                .unwrap()
+                .get()
+        );
+        for (role, value) in [("primary", 0), ("secondary", 1)] {
+            assert_eq!(
+                value,
+                NODE_ROLE
+                    .get_metric_with_label_values(&["mongodb_probe", &addr, role])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+}
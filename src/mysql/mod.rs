@@ -0,0 +1,512 @@
+use std::io;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, BytesMut};
+use sha1::{Digest, Sha1};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::{info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const NATIVE_PASSWORD_PLUGIN: &str = "mysql_native_password";
+
+// CLIENT_LONG_PASSWORD | CLIENT_PROTOCOL_41 | CLIENT_TRANSACTIONS
+// | CLIENT_SECURE_CONNECTION | CLIENT_PLUGIN_AUTH
+const CAPABILITIES: u32 = 0x0000_0001 | 0x0000_0200 | 0x0000_2000 | 0x0000_8000 | 0x0008_0000;
+const MAX_PACKET_SIZE: u32 = 16 * 1024 * 1024;
+// utf8mb4_general_ci
+const CHARSET: u8 = 45;
+
+const COM_QUERY: u8 = 0x03;
+const COM_PING: u8 = 0x0e;
+
+const OK_PACKET: u8 = 0x00;
+const EOF_PACKET: u8 = 0xfe;
+const ERR_PACKET: u8 = 0xff;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// Credentials and health query of the probe, only set once from main
+static MYSQL_CONFIG: OnceLock<MysqlConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+struct MysqlConfig {
+    username: String,
+    // Empty to authenticate without password
+    password: String,
+    // Empty to only ping the node
+    health_query: String,
+}
+
+impl Default for MysqlConfig {
+    fn default() -> Self {
+        MysqlConfig {
+            username: "probes".to_string(),
+            password: "".to_string(),
+            health_query: "SELECT 1".to_string(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum MysqlClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid packet: {0}")]
+    InvalidPacket(String),
+    #[error("Unsupported auth plugin {0}.")]
+    UnsupportedAuthPlugin(String),
+    #[error("Error {code} on {cmd_type}: {message}")]
+    ErrorPacket {
+        cmd_type: String,
+        code: u16,
+        message: String,
+    },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Set the credentials and health query of the probe
+///
+/// # Arguments
+///
+/// * `username` - user authenticated on the nodes
+/// * `password` - password of the user, empty for none
+/// * `health_query` - query run after the ping, empty to disable it
+///
+pub fn set_config(username: &str, password: &str, health_query: &str) -> Result<(), String> {
+    info!("Probe mysql nodes as {}", username);
+    MYSQL_CONFIG
+        .set(MysqlConfig {
+            username: username.to_string(),
+            password: password.to_string(),
+            health_query: health_query.to_string(),
+        })
+        .map_err(|_| "Mysql config is already initialized".to_string())
+}
+
+/// Compute the mysql_native_password auth response
+///
+/// SHA1(password) XOR SHA1(scramble + SHA1(SHA1(password)))
+///
+/// # Arguments
+///
+/// * `password` - password of the user
+/// * `scramble` - 20 bytes nonce sent by the server
+///
+fn scramble_native_password(password: &str, scramble: &[u8]) -> Vec<u8> {
+    if password.is_empty() {
+        return Vec::new();
+    }
+    let stage1 = Sha1::digest(password.as_bytes());
+    let stage2 = Sha1::digest(stage1);
+    let mut hasher = Sha1::new();
+    hasher.update(scramble);
+    hasher.update(stage2);
+    let stage3 = hasher.finalize();
+    stage1
+        .iter()
+        .zip(stage3.iter())
+        .map(|(left, right)| left ^ right)
+        .collect()
+}
+
+/// Read a NUL terminated string
+fn get_null_terminated(payload: &mut &[u8]) -> Result<String, MysqlClientError> {
+    let end = payload
+        .iter()
+        .position(|byte| *byte == 0)
+        .ok_or_else(|| MysqlClientError::InvalidPacket("missing NUL terminator".to_string()))?;
+    let value = String::from_utf8_lossy(&payload[..end]).to_string();
+    payload.advance(end + 1);
+    Ok(value)
+}
+
+/// Parse the initial handshake v10 sent by the server
+///
+/// # Return
+///
+/// * Auth plugin name and scramble
+///
+fn parse_handshake(mut payload: &[u8]) -> Result<(String, Vec<u8>), MysqlClientError> {
+    if payload.first() == Some(&ERR_PACKET) {
+        return Err(parse_error("handshake", payload));
+    }
+    if payload.is_empty() || payload.get_u8() != 10 {
+        return Err(MysqlClientError::InvalidPacket(
+            "unsupported protocol version".to_string(),
+        ));
+    }
+    // Server version
+    get_null_terminated(&mut payload)?;
+    if payload.len() < 4 + 8 + 1 + 2 + 1 + 2 + 2 + 1 + 10 {
+        return Err(MysqlClientError::InvalidPacket(
+            "truncated handshake".to_string(),
+        ));
+    }
+    // Connection id
+    payload.advance(4);
+    let mut scramble = payload[..8].to_vec();
+    // Scramble part 1, filler, capabilities, charset, status, capabilities upper
+    payload.advance(8 + 1 + 2 + 1 + 2 + 2);
+    let scramble_len = payload.get_u8() as usize;
+    // Reserved
+    payload.advance(10);
+    let part2_len = scramble_len.saturating_sub(8).max(13);
+    if payload.len() < part2_len {
+        return Err(MysqlClientError::InvalidPacket(
+            "truncated scramble".to_string(),
+        ));
+    }
+    // Part 2 is NUL terminated
+    scramble.extend_from_slice(&payload[..part2_len - 1]);
+    payload.advance(part2_len);
+    let plugin = if payload.is_empty() {
+        NATIVE_PASSWORD_PLUGIN.to_string()
+    } else {
+        get_null_terminated(&mut payload)?
+    };
+    Ok((plugin, scramble))
+}
+
+/// Parse an error packet
+fn parse_error(cmd_type: &str, mut payload: &[u8]) -> MysqlClientError {
+    if payload.len() < 3 {
+        return MysqlClientError::InvalidPacket("truncated error".to_string());
+    }
+    payload.advance(1);
+    let code = payload.get_u16_le();
+    // Skip the sql state marker and sql state
+    if payload.first() == Some(&b'#') && payload.len() >= 6 {
+        payload.advance(6);
+    }
+    MysqlClientError::ErrorPacket {
+        cmd_type: cmd_type.to_string(),
+        code,
+        message: String::from_utf8_lossy(payload).to_string(),
+    }
+}
+
+/// Encode the handshake response 41
+///
+/// # Arguments
+///
+/// * `username` - user authenticated on the node
+/// * `auth_response` - scrambled password
+///
+fn encode_handshake_response(username: &str, auth_response: &[u8]) -> Vec<u8> {
+    let mut payload = BytesMut::with_capacity(64 + username.len());
+    payload.put_u32_le(CAPABILITIES);
+    payload.put_u32_le(MAX_PACKET_SIZE);
+    payload.put_u8(CHARSET);
+    payload.put_slice(&[0; 23]);
+    payload.put_slice(username.as_bytes());
+    payload.put_u8(0);
+    payload.put_u8(auth_response.len() as u8);
+    payload.put_slice(auth_response);
+    payload.put_slice(NATIVE_PASSWORD_PLUGIN.as_bytes());
+    payload.put_u8(0);
+    payload.to_vec()
+}
+
+/// Return the status exported in the number of requests metric
+fn packet_status(payload: &[u8]) -> String {
+    match payload.first() {
+        Some(&ERR_PACKET) if payload.len() >= 3 => {
+            u16::from_le_bytes([payload[1], payload[2]]).to_string()
+        }
+        _ => "OK".to_string(),
+    }
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, MysqlClientError> {
+    let stream = TcpStream::connect(addr).await?;
+    let mut client = Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stream,
+        sequence_id: 0,
+        config: MYSQL_CONFIG.get().cloned().unwrap_or_default(),
+    };
+    tokio::time::timeout(TIMEOUT, client.handshake()).await??;
+    Ok(client)
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    stream: TcpStream,
+    // Sequence id of the next packet, reset on each command
+    sequence_id: u8,
+    config: MysqlConfig,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one ping
+    /// * issue the health query
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), MysqlClientError> {
+        self.ping().await?;
+        if !self.config.health_query.is_empty() {
+            self.query().await?;
+        }
+        Ok(())
+    }
+
+    /// Handshake with the node and authenticate with mysql_native_password
+    async fn handshake(&mut self) -> Result<(), MysqlClientError> {
+        let payload = self.read_packet().await?;
+        let (plugin, scramble) = parse_handshake(&payload)?;
+        if plugin != NATIVE_PASSWORD_PLUGIN {
+            return Err(MysqlClientError::UnsupportedAuthPlugin(plugin));
+        }
+        let auth_response = scramble_native_password(&self.config.password, &scramble);
+        let response = encode_handshake_response(&self.config.username, &auth_response);
+        self.write_packet(&response).await?;
+
+        let mut payload = self.read_packet().await?;
+        if payload.first() == Some(&EOF_PACKET) {
+            // Auth switch request
+            let mut switch = &payload[1..];
+            let plugin = get_null_terminated(&mut switch)?;
+            if plugin != NATIVE_PASSWORD_PLUGIN {
+                return Err(MysqlClientError::UnsupportedAuthPlugin(plugin));
+            }
+            let scramble = switch.strip_suffix(&[0]).unwrap_or(switch);
+            let auth_response = scramble_native_password(&self.config.password, scramble);
+            self.write_packet(&auth_response).await?;
+            payload = self.read_packet().await?;
+        }
+        match payload.first() {
+            Some(&OK_PACKET) => Ok(()),
+            Some(&ERR_PACKET) => Err(parse_error("handshake", &payload)),
+            _ => Err(MysqlClientError::InvalidPacket(
+                "unexpected handshake reply".to_string(),
+            )),
+        }
+    }
+
+    /// Ping call
+    pub async fn ping(&mut self) -> Result<(), MysqlClientError> {
+        self.handler_with_timeout("ping", vec![COM_PING]).await
+    }
+
+    /// Health query call, the result set is read and dropped
+    pub async fn query(&mut self) -> Result<(), MysqlClientError> {
+        let mut command = vec![COM_QUERY];
+        command.extend_from_slice(self.config.health_query.as_bytes());
+        self.handler_with_timeout("query", command).await
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        command: Vec<u8>,
+    ) -> Result<(), MysqlClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type, command)).await {
+            Ok(response_res) => response_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(MysqlClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform mysql request
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    /// * `command` - the command byte followed by its arguments
+    ///
+    #[instrument(skip(self, command))]
+    pub async fn handle_request(
+        &mut self,
+        cmd_type: &str,
+        command: Vec<u8>,
+    ) -> Result<(), MysqlClientError> {
+        let start = Instant::now();
+        self.sequence_id = 0;
+        self.write_packet(&command).await?;
+
+        let payload = self.read_packet().await?;
+        if !matches!(payload.first(), Some(&OK_PACKET) | Some(&ERR_PACKET)) {
+            self.read_result_set().await?;
+        }
+        let elapsed = start.elapsed();
+        self.request_metrics
+            .count_request(&packet_status(&payload), cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+
+        if payload.first() == Some(&ERR_PACKET) {
+            return Err(parse_error(cmd_type, &payload));
+        }
+        Ok(())
+    }
+
+    /// Read column definitions and rows up to the final EOF packet
+    async fn read_result_set(&mut self) -> Result<(), MysqlClientError> {
+        let mut eof_count = 0;
+        while eof_count < 2 {
+            let payload = self.read_packet().await?;
+            match payload.first() {
+                Some(&EOF_PACKET) if payload.len() < 9 => eof_count += 1,
+                Some(&ERR_PACKET) => return Err(parse_error("query", &payload)),
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Write a payload in one packet
+    async fn write_packet(&mut self, payload: &[u8]) -> Result<(), MysqlClientError> {
+        let mut packet = BytesMut::with_capacity(4 + payload.len());
+        packet.put_uint_le(payload.len() as u64, 3);
+        packet.put_u8(self.sequence_id);
+        packet.put_slice(payload);
+        self.sequence_id = self.sequence_id.wrapping_add(1);
+        self.stream.write_all(&packet).await?;
+        Ok(())
+    }
+
+    /// Read the payload of one packet
+    async fn read_packet(&mut self) -> Result<Vec<u8>, MysqlClientError> {
+        let mut header = [0; 4];
+        self.stream.read_exact(&mut header).await?;
+        let len = u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize;
+        self.sequence_id = header[3].wrapping_add(1);
+        let mut payload = vec![0; len];
+        self.stream.read_exact(&mut payload).await?;
+        Ok(payload)
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::mysql::{
+        connect, packet_status, parse_handshake, scramble_native_password, MysqlClientError,
+    };
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+
+    fn handshake() -> Vec<u8> {
+        let mut payload = vec![10];
+        payload.extend_from_slice(b"8.0.36\0");
+        payload.extend_from_slice(&[1, 0, 0, 0]);
+        payload.extend_from_slice(b"abcdefgh");
+        payload.push(0);
+        payload.extend_from_slice(&[0xff, 0xff, 45, 2, 0, 0xff, 0xff, 21]);
+        payload.extend_from_slice(&[0; 10]);
+        payload.extend_from_slice(b"ijklmnopqrst\0");
+        payload.extend_from_slice(b"mysql_native_password\0");
+        payload
+    }
+
+    async fn write_packet(socket: &mut TcpStream, sequence_id: u8, payload: &[u8]) {
+        let len = (payload.len() as u32).to_le_bytes();
+        socket
+            .write_all(&[len[0], len[1], len[2], sequence_id])
+            .await
+            .unwrap();
+        socket.write_all(payload).await.unwrap();
+    }
+
+    async fn read_packet(socket: &mut TcpStream) -> Vec<u8> {
+        let mut header = [0; 4];
+        socket.read_exact(&mut header).await.unwrap();
+        let mut payload =
+            vec![0; u32::from_le_bytes([header[0], header[1], header[2], 0]) as usize];
+        socket.read_exact(&mut payload).await.unwrap();
+        payload
+    }
+
+    #[test]
+    fn parse() {
+        let (plugin, scramble) = parse_handshake(&handshake()).unwrap();
+        assert_eq!("mysql_native_password", plugin);
+        assert_eq!(b"abcdefghijklmnopqrst".to_vec(), scramble);
+        assert!(matches!(
+            parse_handshake(&[0xff, 0x15, 0x04, b'#', b'2', b'8', b'0', b'0', b'0', b'd']),
+            Err(MysqlClientError::ErrorPacket { code: 1045, .. })
+        ));
+        assert_eq!("1045", packet_status(&[0xff, 0x15, 0x04]));
+        assert_eq!("OK", packet_status(&[0x00, 0x00, 0x00]));
+    }
+
+    #[test]
+    fn scramble() {
+        assert!(scramble_native_password("", b"abcdefghijklmnopqrst").is_empty());
+        let scramble: Vec<u8> = (1..=20).collect();
+        assert_eq!(
+            "b32bb3a583e1340c0a1108d58b1be49781ad8c2f",
+            hex::encode(scramble_native_password("secret", &scramble))
+        );
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            write_packet(&mut socket, 0, &handshake()).await;
+            read_packet(&mut socket).await;
+            write_packet(&mut socket, 2, &[0, 0, 0, 2, 0, 0, 0]).await;
+            // Ping
+            read_packet(&mut socket).await;
+            write_packet(&mut socket, 1, &[0, 0, 0, 2, 0, 0, 0]).await;
+            // Select 1, one column and one row
+            read_packet(&mut socket).await;
+            write_packet(&mut socket, 1, &[1]).await;
+            write_packet(
+                &mut socket,
+                2,
+                b"\x03def\0\0\0\x011\0\x0c\x3f\0\x01\0\0\0\x08\x81\0\0\0\0",
+            )
+            .await;
+            write_packet(&mut socket, 3, &[0xfe, 0, 0, 2, 0]).await;
+            write_packet(&mut socket, 4, b"\x011").await;
+            write_packet(&mut socket, 5, &[0xfe, 0, 0, 2, 0]).await;
+        });
+
+        let mut client = connect("mysql_probe", &addr).await.unwrap();
+        client.probe().await.unwrap();
+        for cmd_type in ["ping", "query"] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&["mysql_probe", &addr, "OK", cmd_type])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+}
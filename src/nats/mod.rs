@@ -0,0 +1,396 @@
+use std::io;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BytesMut};
+use serde_json::json;
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::{info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+// Subscription id of the canary subject
+const CANARY_SID: &str = "1";
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// Subject of the canary round trip, only set once from main
+static NATS_CANARY_SUBJECT: OnceLock<String> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum NatsClientError {
+    #[error("Empty or incomplete response.")]
+    EmptyOrIncompleteResponse,
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Connection reset by peer.")]
+    ConnectionReset,
+    #[error("Invalid operation: {0}")]
+    InvalidOperation(String),
+    #[error("Error: {0}")]
+    ErrorOperation(String),
+    #[error("Unexpected operation on {cmd_type}: {operation:?}")]
+    UnexpectedOperation {
+        cmd_type: String,
+        operation: Operation,
+    },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+// Operation sent by the server
+#[derive(Debug, PartialEq, Clone)]
+pub enum Operation {
+    Info,
+    Ping,
+    Pong,
+    Ok,
+    Err(String),
+    Msg { sid: String, payload: Vec<u8> },
+}
+
+/// Set the subject of the canary round trip
+///
+/// # Arguments
+///
+/// * `subject` - subject subscribed and published to, empty to disable the round trip
+///
+pub fn set_canary_subject(subject: &str) -> Result<(), String> {
+    if subject.is_empty() {
+        return Ok(());
+    }
+    info!("Round trip on nats canary subject {}", subject);
+    NATS_CANARY_SUBJECT
+        .set(subject.to_string())
+        .map_err(|_| "Nats canary subject is already initialized".to_string())
+}
+
+/// Parse an operation from a buffer
+///
+/// # Arguments
+///
+/// * `buffer` - bytes received from the server
+///
+/// # Return
+///
+/// * The operation and the number of bytes it used, None if not enough bytes
+///
+fn parse_operation(buffer: &[u8]) -> Result<Option<(Operation, usize)>, NatsClientError> {
+    let Some(line_end) = buffer.windows(2).position(|window| window == b"\r\n") else {
+        return Ok(None);
+    };
+    let line = String::from_utf8_lossy(&buffer[..line_end]).to_string();
+    let line_len = line_end + 2;
+    let mut parts = line.split_whitespace();
+    let operation = match parts.next().map(str::to_ascii_uppercase).as_deref() {
+        Some("INFO") => Operation::Info,
+        Some("PING") => Operation::Ping,
+        Some("PONG") => Operation::Pong,
+        Some("+OK") => Operation::Ok,
+        Some("-ERR") => Operation::Err(line[4..].trim().trim_matches('\'').to_string()),
+        Some("MSG") => {
+            // MSG <subject> <sid> [reply-to] <#bytes>
+            let arguments: Vec<&str> = parts.collect();
+            if !(3..=4).contains(&arguments.len()) {
+                return Err(NatsClientError::InvalidOperation(line));
+            }
+            let len: usize = arguments[arguments.len() - 1]
+                .parse()
+                .map_err(|_| NatsClientError::InvalidOperation(line.clone()))?;
+            let end = line_len + len;
+            if buffer.len() < end + 2 {
+                return Ok(None);
+            }
+            return Ok(Some((
+                Operation::Msg {
+                    sid: arguments[1].to_string(),
+                    payload: buffer[line_len..end].to_vec(),
+                },
+                end + 2,
+            )));
+        }
+        _ => return Err(NatsClientError::InvalidOperation(line)),
+    };
+    Ok(Some((operation, line_len)))
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, NatsClientError> {
+    let socket = TcpStream::connect(addr).await?;
+    let mut client = Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stream: BufWriter::new(socket),
+        buffer: BytesMut::with_capacity(4096),
+        canary_subject: NATS_CANARY_SUBJECT.get().cloned().unwrap_or_default(),
+        subscribed: false,
+        round_trip_id: 0,
+    };
+    tokio::time::timeout(TIMEOUT, client.handshake()).await??;
+    Ok(client)
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    stream: BufWriter<TcpStream>,
+    buffer: BytesMut,
+    // Empty to disable the round trip
+    canary_subject: String,
+    // Canary subject is subscribed once per connection
+    subscribed: bool,
+    // Id sent as payload of the last canary message
+    round_trip_id: u64,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one ping
+    /// * publish one canary message and wait for it if enabled
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), NatsClientError> {
+        self.ping().await?;
+        if !self.canary_subject.is_empty() {
+            self.round_trip().await?;
+        }
+        Ok(())
+    }
+
+    /// Wait for the server info and send connect, errors are returned on the next ping
+    async fn handshake(&mut self) -> Result<(), NatsClientError> {
+        match self.read_operation().await? {
+            Operation::Info => {}
+            operation => {
+                return Err(NatsClientError::UnexpectedOperation {
+                    cmd_type: "connect".to_string(),
+                    operation,
+                })
+            }
+        }
+        let connect = json!({
+            "verbose": false,
+            "pedantic": false,
+            "name": "probes",
+            "lang": "rust",
+            "version": env!("CARGO_PKG_VERSION"),
+            "protocol": 1,
+        });
+        self.stream
+            .write_all(format!("CONNECT {connect}\r\n").as_bytes())
+            .await?;
+        self.stream.flush().await?;
+        Ok(())
+    }
+
+    /// Ping call
+    pub async fn ping(&mut self) -> Result<(), NatsClientError> {
+        self.handler_with_timeout("ping", b"PING\r\n".to_vec())
+            .await
+    }
+
+    /// Publish a canary message on the subscribed canary subject and wait for it
+    pub async fn round_trip(&mut self) -> Result<(), NatsClientError> {
+        let mut command = Vec::new();
+        if !self.subscribed {
+            command.extend_from_slice(
+                format!("SUB {} {}\r\n", self.canary_subject, CANARY_SID).as_bytes(),
+            );
+            self.subscribed = true;
+        }
+        self.round_trip_id += 1;
+        let payload = self.round_trip_id.to_string();
+        command.extend_from_slice(
+            format!(
+                "PUB {} {}\r\n{}\r\n",
+                self.canary_subject,
+                payload.len(),
+                payload
+            )
+            .as_bytes(),
+        );
+        self.handler_with_timeout("round_trip", command).await
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        command: Vec<u8>,
+    ) -> Result<(), NatsClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type, command)).await {
+            Ok(response_res) => response_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(NatsClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform nats request
+    ///
+    /// Server pings are answered and info updates skipped while waiting for the response
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    /// * `command` - the protocol operations to send
+    ///
+    #[instrument(skip(self, command))]
+    pub async fn handle_request(
+        &mut self,
+        cmd_type: &str,
+        command: Vec<u8>,
+    ) -> Result<(), NatsClientError> {
+        let start = Instant::now();
+        self.stream.write_all(&command).await?;
+        self.stream.flush().await?;
+
+        let expected_payload = self.round_trip_id.to_string();
+        let result = loop {
+            match self.read_operation().await? {
+                Operation::Ping => {
+                    self.stream.write_all(b"PONG\r\n").await?;
+                    self.stream.flush().await?;
+                }
+                Operation::Info | Operation::Ok => {}
+                Operation::Pong if cmd_type == "ping" => break Ok(()),
+                Operation::Msg { sid, payload }
+                    if cmd_type == "round_trip" && sid == CANARY_SID =>
+                {
+                    // Skip late canary messages of previous probes
+                    if payload == expected_payload.as_bytes() {
+                        break Ok(());
+                    }
+                }
+                Operation::Err(message) => break Err(NatsClientError::ErrorOperation(message)),
+                operation => {
+                    break Err(NatsClientError::UnexpectedOperation {
+                        cmd_type: cmd_type.to_string(),
+                        operation,
+                    })
+                }
+            }
+        };
+        let status = match &result {
+            Ok(_) => "OK",
+            Err(_) => "ERR",
+        };
+        self.request_metrics.count_request(status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, start.elapsed());
+        result
+    }
+
+    /// Get operation from tcp stream
+    async fn read_operation(&mut self) -> Result<Operation, NatsClientError> {
+        loop {
+            if let Some((operation, len)) = parse_operation(&self.buffer[..])? {
+                self.buffer.advance(len);
+                return Ok(operation);
+            }
+            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                return if self.buffer.is_empty() {
+                    Err(NatsClientError::EmptyOrIncompleteResponse)
+                } else {
+                    Err(NatsClientError::ConnectionReset)
+                };
+            }
+        }
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::nats::{connect, parse_operation, NatsClientError, Operation};
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Some((Operation::Info, 14)),
+            parse_operation(b"INFO {\"a\":1}\r\n").unwrap()
+        );
+        assert_eq!(
+            Some((Operation::Pong, 6)),
+            parse_operation(b"PONG\r\nPING\r\n").unwrap()
+        );
+        assert_eq!(
+            Some((Operation::Err("Authorization Violation".to_string()), 32)),
+            parse_operation(b"-ERR 'Authorization Violation'\r\n").unwrap()
+        );
+        assert_eq!(
+            Some((
+                Operation::Msg {
+                    sid: "1".to_string(),
+                    payload: b"42".to_vec()
+                },
+                20
+            )),
+            parse_operation(b"MSG canary 1 2\r\n42\r\n").unwrap()
+        );
+        assert_eq!(None, parse_operation(b"MSG canary 1 2\r\n4").unwrap());
+        assert_eq!(None, parse_operation(b"PON").unwrap());
+        assert!(matches!(
+            parse_operation(b"FOO\r\n"),
+            Err(NatsClientError::InvalidOperation(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket
+                .write_all(b"INFO {\"server_id\":\"test\"}\r\n")
+                .await
+                .unwrap();
+            let mut buffer = [0; 1024];
+            let mut received = Vec::new();
+            while !received.ends_with(b"PING\r\n") {
+                let len = socket.read(&mut buffer).await.unwrap();
+                received.extend_from_slice(&buffer[..len]);
+            }
+            assert!(received.starts_with(b"CONNECT {"));
+            // Server ping answered while waiting for the pong
+            socket.write_all(b"PING\r\nPONG\r\n").await.unwrap();
+            let len = socket.read(&mut buffer).await.unwrap();
+            assert_eq!(b"PONG\r\n", &buffer[..len]);
+        });
+
+        let mut client = connect("nats_probe", &addr).await.unwrap();
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["nats_probe", &addr, "OK", "ping"])
+                .unwrap()
+                .get()
+        );
+    }
+}
@@ -0,0 +1,466 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use hyper::{Body, Request};
+use serde_json::Value;
+use thiserror::Error;
+use tracing::log::warn;
+use tracing::{debug, info, instrument};
+
+use crate::probes::discovery::{
+    url_encode, Discovery, DiscoveryError, DiscoveryFuture, ServiceNode, ServiceNodes,
+};
+use crate::probes::http_client::{shared_client, HttpClient};
+use crate::probes::protocol::Protocol;
+use crate::probes::state::PROBER_STATE;
+use crate::token_bucket::{RateLimiter, RateLimiterKind};
+
+// Service tag prefix declaring an additional port to probe
+const PROBE_PORT_TAG_PREFIX: &str = "probe-port=";
+// Service tag prefix declaring the protocol used to probe the service
+const PROBE_PROTOCOL_TAG_PREFIX: &str = "probe-protocol=";
+
+#[derive(Error, Debug)]
+pub enum NomadError {
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Issue query: {uri} - status code: {status}")]
+    Status { uri: String, status: u16 },
+    #[error("Invalid json: {source}")]
+    Json {
+        #[from]
+        source: serde_json::Error,
+    },
+}
+
+// Represent a client of the nomad service registry
+#[derive(Debug)]
+pub struct NomadClient {
+    // Address of the nomad agent to query
+    address: String,
+    // Acl token, none if acls are disabled
+    token: String,
+    // Namespace of the services, * for all namespaces
+    namespace: String,
+    client: Arc<HttpClient>,
+}
+
+/// Get the string tags of a service registration or a service of the list
+fn get_tags(value: &Value) -> Vec<&str> {
+    value
+        .get("Tags")
+        .and_then(Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(Value::as_str)
+        .collect()
+}
+
+/// Create ServiceNodes from a nomad service registration
+/// One ServiceNode is created for the service port and each `probe-port=<port>` tag
+///
+/// # Arguments
+///
+/// * `registration` - json of a registration of the service
+///
+fn get_service_nodes(registration: &Value) -> Vec<ServiceNode> {
+    let (Some(service_name), Some(address)) = (
+        registration.get("ServiceName").and_then(Value::as_str),
+        registration.get("Address").and_then(Value::as_str),
+    ) else {
+        warn!("Invalid nomad service registration: {}", registration);
+        return Vec::new();
+    };
+    let tags = get_tags(registration);
+    let protocol = tags
+        .iter()
+        .find_map(|tag| tag.strip_prefix(PROBE_PROTOCOL_TAG_PREFIX))
+        .and_then(|protocol| match protocol.parse::<Protocol>() {
+            Ok(protocol) => Some(protocol),
+            Err(issue) => {
+                warn!("Invalid probe protocol: {}", issue);
+                None
+            }
+        });
+
+    let mut ports: Vec<u16> = Vec::new();
+    if let Some(port) = registration.get("Port").and_then(Value::as_u64) {
+        ports.push(port as u16);
+    }
+    for tag in tags {
+        if let Some(port) = tag.strip_prefix(PROBE_PORT_TAG_PREFIX) {
+            match port.parse::<u16>() {
+                Ok(port) if !ports.contains(&port) => ports.push(port),
+                Ok(_) => {}
+                Err(_) => warn!("Invalid probe port tag: {}", tag),
+            }
+        }
+    }
+
+    ports
+        .into_iter()
+        .map(|port| ServiceNode {
+            service_name: service_name.to_string(),
+            ip: address.to_string(),
+            port,
+            protocol,
+        })
+        .collect()
+}
+
+/// Extract the namespace and name of the services with the tag for probing
+///
+/// # Arguments
+///
+/// * `tag` - comma separated tags, one of them needed on service to enable probing
+/// * `body_json` - json of the services list, grouped by namespace
+///
+fn extract_matching_services(tag: &str, body_json: &Value) -> Vec<(String, String)> {
+    let tags: Vec<&str> = tag.split(',').map(str::trim).collect();
+    let mut matching_services = Vec::new();
+    for namespace in body_json.as_array().into_iter().flatten() {
+        let namespace_name = namespace
+            .get("Namespace")
+            .and_then(Value::as_str)
+            .unwrap_or("default");
+        for service in namespace
+            .get("Services")
+            .and_then(Value::as_array)
+            .into_iter()
+            .flatten()
+        {
+            if let Some(service_name) = service.get("ServiceName").and_then(Value::as_str) {
+                if get_tags(service).iter().any(|tag| tags.contains(tag)) {
+                    matching_services.push((namespace_name.to_string(), service_name.to_string()));
+                }
+            }
+        }
+    }
+    matching_services
+}
+
+impl NomadClient {
+    /// Returns a nomad client
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - address of the nomad agent
+    /// * `token` - acl token, empty if acls are disabled
+    /// * `namespace` - namespace of the services, * for all namespaces
+    ///
+    pub fn new(address: &str, token: &str, namespace: &str) -> Self {
+        info!(
+            "Watch nomad services of namespace {} on {}",
+            namespace, address
+        );
+
+        NomadClient {
+            address: address.trim_end_matches('/').to_string(),
+            token: token.to_string(),
+            namespace: namespace.to_string(),
+            client: shared_client(),
+        }
+    }
+
+    /// Query the nomad http api
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - path of the api
+    /// * `query` - query of the request
+    ///
+    /// # Return
+    ///
+    /// * The index of the response, 0 if missing, and its json body
+    ///
+    async fn http_call(&self, path: &str, query: &str) -> Result<(i64, Value), NomadError> {
+        let uri = format!("{}{path}?{query}", self.address);
+        debug!("Query nomad: {}", uri);
+        let mut request = Request::builder().uri(&uri);
+        if !self.token.is_empty() {
+            request = request.header("x-nomad-token", &self.token);
+        }
+        let resp = self.client.request(request.body(Body::empty())?).await?;
+        if !resp.status().is_success() {
+            return Err(NomadError::Status {
+                uri,
+                status: resp.status().as_u16(),
+            });
+        }
+
+        let index = resp
+            .headers()
+            .get("x-nomad-index")
+            .and_then(|index| index.to_str().ok())
+            .and_then(|index| index.parse().ok())
+            .unwrap_or(0);
+        let bytes = hyper::body::to_bytes(resp.into_body()).await?;
+        Ok((index, serde_json::from_slice(&bytes)?))
+    }
+
+    /// Get the list of nodes for all services with tags matching the tag for probing
+    ///
+    /// Blocks until the services change since the previous index
+    ///
+    /// # Arguments
+    ///
+    /// * `prev_index` - index value of last nomad watch, 0 to return immediately
+    /// * `tag` - tag needed on service to enable probing
+    ///
+    #[instrument(skip(self))]
+    pub async fn list_matching_nodes(
+        &self,
+        prev_index: i64,
+        tag: &str,
+    ) -> Result<ServiceNodes, NomadError> {
+        let (index, body_json) = self
+            .http_call(
+                "/v1/services",
+                &format!(
+                    "namespace={}&index={prev_index}&wait=5m",
+                    url_encode(&self.namespace)
+                ),
+            )
+            .await?;
+        // A lower index means nomad state was restored, watch again from scratch
+        let index = if index < prev_index { 0 } else { index };
+
+        let matching_services = extract_matching_services(tag, &body_json);
+        let mut nodes: HashMap<String, ServiceNode> = HashMap::new();
+        for (namespace, service_name) in &matching_services {
+            let (_, registrations) = self
+                .http_call(
+                    &format!("/v1/service/{}", url_encode(service_name)),
+                    &format!("namespace={}", url_encode(namespace)),
+                )
+                .await?;
+            for service_node in registrations
+                .as_array()
+                .into_iter()
+                .flatten()
+                .flat_map(get_service_nodes)
+            {
+                nodes.insert(service_node.to_string(), service_node);
+            }
+        }
+
+        let mut services: Vec<String> = matching_services
+            .into_iter()
+            .map(|(_, service_name)| service_name)
+            .collect();
+        services.sort();
+        services.dedup();
+        Ok(ServiceNodes {
+            index,
+            services,
+            nodes,
+        })
+    }
+}
+
+// Discovery watching the services with the probing tag in the nomad service registry
+//
+// Rely on nomad blocking queries, rate limited like the consul watch
+pub struct NomadDiscovery {
+    nomad_client: NomadClient,
+    services_tag: String,
+    rate_limiter: Box<dyn RateLimiter>,
+    // Index of the last successful watch
+    index: i64,
+    // A requested refresh bypasses the rate limiter and the watch index once
+    force_refresh: bool,
+}
+
+impl NomadDiscovery {
+    /// Returns a NomadDiscovery
+    ///
+    /// # Arguments
+    ///
+    /// * `nomad_client` - a nomad client
+    /// * `services_tag` - comma separated tags, one of them needed on service to enable probing
+    /// * `rate_limiter` - rate limiter implementation of the nomad calls
+    ///
+    pub fn new(
+        nomad_client: NomadClient,
+        services_tag: &str,
+        rate_limiter: RateLimiterKind,
+    ) -> Self {
+        NomadDiscovery {
+            nomad_client,
+            services_tag: services_tag.to_string(),
+            rate_limiter: rate_limiter.build("nomad_discovery", 180.0, 1.0, 60),
+            index: 0,
+            force_refresh: false,
+        }
+    }
+
+    async fn watch(&mut self) -> Result<ServiceNodes, DiscoveryError> {
+        loop {
+            if !self.force_refresh {
+                self.force_refresh = tokio::select! {
+                    wait_res = self.rate_limiter.acquire(60) => wait_res
+                        .map(|_| false)
+                        .map_err(|issue| DiscoveryError::RateLimiter(issue.to_string()))?,
+                    _ = PROBER_STATE.refresh_requested() => true,
+                };
+            }
+
+            // Watch from scratch the services of a tag changed at runtime
+            if let Some(services_tag) = PROBER_STATE.services_tag() {
+                if services_tag != self.services_tag {
+                    info!(
+                        "Watch services with tag {} instead of {}",
+                        services_tag, self.services_tag
+                    );
+                    self.services_tag = services_tag;
+                    self.force_refresh = true;
+                }
+            }
+
+            // Index 0 returns immediately instead of waiting for a change
+            let watch_index = if self.force_refresh { 0 } else { self.index };
+            if self.force_refresh {
+                info!("Refresh services discovery immediately");
+            }
+            self.force_refresh = false;
+
+            let discovery_res = tokio::select! {
+                discovery_res = self
+                    .nomad_client
+                    .list_matching_nodes(watch_index, &self.services_tag) => Some(discovery_res),
+                // Interrupt the long poll to refresh immediately
+                _ = PROBER_STATE.refresh_requested() => None,
+            };
+            let Some(discovery_res) = discovery_res else {
+                self.force_refresh = true;
+                continue;
+            };
+            self.index = match &discovery_res {
+                Ok(discovered_nodes) => discovered_nodes.index,
+                Err(_) => 0,
+            };
+            return discovery_res.map_err(Into::into);
+        }
+    }
+}
+
+impl fmt::Display for NomadDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "nomad services with tag {}", self.services_tag)
+    }
+}
+
+impl Discovery for NomadDiscovery {
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+        Box::pin(self.watch())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+    use wiremock::matchers::{header, method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::nomad::{extract_matching_services, get_service_nodes, NomadClient, NomadError};
+    use crate::probes::protocol::Protocol;
+
+    #[test]
+    fn matching_services() {
+        let body_json = json!([
+            {"Namespace": "default", "Services": [
+                {"ServiceName": "sessions", "Tags": ["probe", "probe-protocol=redis"]},
+                {"ServiceName": "web", "Tags": ["http"]}
+            ]},
+            {"Namespace": "staging", "Services": [
+                {"ServiceName": "cache", "Tags": ["probe"]}
+            ]}
+        ]);
+        assert_eq!(
+            vec![
+                ("default".to_string(), "sessions".to_string()),
+                ("staging".to_string(), "cache".to_string())
+            ],
+            extract_matching_services("probe", &body_json)
+        );
+        assert_eq!(
+            vec![
+                ("default".to_string(), "sessions".to_string()),
+                ("default".to_string(), "web".to_string()),
+                ("staging".to_string(), "cache".to_string())
+            ],
+            extract_matching_services("probe, http", &body_json)
+        );
+        assert!(extract_matching_services("probe", &json!({})).is_empty());
+    }
+
+    #[test]
+    fn service_nodes() {
+        let service_nodes = get_service_nodes(&json!({
+            "ServiceName": "sessions",
+            "Address": "10.0.0.1",
+            "Port": 6379,
+            "Tags": ["probe", "probe-protocol=redis", "probe-port=6380", "probe-port=6379"]
+        }));
+        assert_eq!(2, service_nodes.len());
+        assert_eq!("sessions:10.0.0.1:6379", service_nodes[0].to_string());
+        assert_eq!("sessions:10.0.0.1:6380", service_nodes[1].to_string());
+        assert_eq!(Some(Protocol::Redis), service_nodes[0].protocol);
+        assert!(get_service_nodes(&json!({"ServiceName": "sessions"})).is_empty());
+    }
+
+    #[tokio::test]
+    async fn list_matching_nodes() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/v1/services"))
+            .and(query_param("namespace", "*"))
+            .and(header("x-nomad-token", "secret"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("x-nomad-index", "42")
+                    .set_body_json(json!([{"Namespace": "staging", "Services": [
+                        {"ServiceName": "sessions", "Tags": ["probe"]}
+                    ]}])),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/v1/service/sessions"))
+            .and(query_param("namespace", "staging"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!([
+                {"ServiceName": "sessions", "Namespace": "staging", "Address": "10.0.0.1",
+                    "Port": 6379, "Tags": ["probe"]},
+                {"ServiceName": "sessions", "Namespace": "staging", "Address": "10.0.0.2",
+                    "Port": 6379, "Tags": ["probe"]}
+            ])))
+            .mount(&mock_server)
+            .await;
+
+        let nomad_client = NomadClient::new(&mock_server.uri(), "secret", "*");
+        let service_nodes = nomad_client.list_matching_nodes(0, "probe").await.unwrap();
+        assert_eq!(42, service_nodes.index);
+        assert_eq!(vec!["sessions".to_string()], service_nodes.services);
+        assert_eq!(2, service_nodes.nodes.len());
+        assert!(service_nodes.nodes.contains_key("sessions:10.0.0.2:6379"));
+
+        // Index lower than the previous one is reset
+        let service_nodes = nomad_client.list_matching_nodes(50, "probe").await.unwrap();
+        assert_eq!(0, service_nodes.index);
+
+        let nomad_client = NomadClient::new(&mock_server.uri(), "", "*");
+        assert!(matches!(
+            nomad_client.list_matching_nodes(0, "probe").await,
+            Err(NomadError::Status { status: 404, .. })
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+use axum::extract::State;
+use axum::http::{header, HeaderValue, Request, StatusCode};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use tracing::warn;
+
+// Represent the authentication required on protected http endpoints
+// Basic auth and bearer token can be enabled together, any of them is then accepted
+#[derive(Debug, Clone, Default)]
+pub struct HttpAuth {
+    // Expected user:password for basic auth
+    basic_credentials: Option<String>,
+    // Expected bearer token
+    bearer_token: Option<String>,
+}
+
+impl HttpAuth {
+    /// Returns an HttpAuth
+    ///
+    /// # Arguments
+    ///
+    /// * `username` - username for basic auth, empty to disable basic auth
+    /// * `password` - password for basic auth
+    /// * `bearer_token` - bearer token, empty to disable it
+    ///
+    pub fn new(username: &str, password: &str, bearer_token: &str) -> HttpAuth {
+        HttpAuth {
+            basic_credentials: (!username.is_empty()).then(|| format!("{username}:{password}")),
+            bearer_token: (!bearer_token.is_empty()).then(|| bearer_token.to_string()),
+        }
+    }
+
+    /// Return true if any authentication is required
+    pub fn is_enabled(&self) -> bool {
+        self.basic_credentials.is_some() || self.bearer_token.is_some()
+    }
+
+    /// Check the authorization header against the expected credentials
+    ///
+    /// # Arguments
+    ///
+    /// * `authorization` - value of the authorization header if any
+    ///
+    /// # Return
+    ///
+    /// * bool - true if authentication is disabled or credentials are valid
+    ///
+    pub fn authorize(&self, authorization: Option<&str>) -> bool {
+        if !self.is_enabled() {
+            return true;
+        }
+
+        let Some((scheme, credentials)) = authorization.and_then(|value| value.split_once(' '))
+        else {
+            return false;
+        };
+
+        match scheme.to_ascii_lowercase().as_str() {
+            "basic" => self.basic_credentials.as_ref().is_some_and(|expected| {
+                STANDARD
+                    .decode(credentials.trim())
+                    .map(|decoded| constant_time_eq(&decoded, expected.as_bytes()))
+                    .unwrap_or(false)
+            }),
+            "bearer" => self.bearer_token.as_ref().is_some_and(|expected| {
+                constant_time_eq(credentials.trim().as_bytes(), expected.as_bytes())
+            }),
+            _ => false,
+        }
+    }
+}
+
+/// Compare two byte slices without leaking the position of the first difference
+fn constant_time_eq(left: &[u8], right: &[u8]) -> bool {
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right.iter())
+            .fold(0, |acc, (l, r)| acc | (l ^ r))
+            == 0
+}
+
+/// Middleware rejecting requests without valid credentials
+///
+/// # Return
+///
+/// * Return the response of the inner handler or an unauthorized status code
+///
+pub async fn auth_middleware<B>(
+    State(auth): State<HttpAuth>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let authorization = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok());
+
+    if auth.authorize(authorization) {
+        return next.run(request).await;
+    }
+
+    warn!("Unauthorized request on {}", request.uri().path());
+    let mut response = StatusCode::UNAUTHORIZED.into_response();
+    if auth.basic_credentials.is_some() {
+        response.headers_mut().insert(
+            header::WWW_AUTHENTICATE,
+            HeaderValue::from_static("Basic realm=\"probes\""),
+        );
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::probes::auth::HttpAuth;
+
+    #[test]
+    fn authorize_disabled() {
+        let auth = HttpAuth::new("", "", "");
+        assert!(!auth.is_enabled());
+        assert!(auth.authorize(None));
+    }
+
+    #[test]
+    fn authorize_basic() {
+        let auth = HttpAuth::new("user", "pass", "");
+        assert!(auth.authorize(Some("Basic dXNlcjpwYXNz")));
+        assert!(!auth.authorize(Some("Basic dXNlcjpvdGhlcg==")));
+        assert!(!auth.authorize(Some("Bearer token")));
+        assert!(!auth.authorize(None));
+    }
+
+    #[test]
+    fn authorize_bearer() {
+        let auth = HttpAuth::new("", "", "token");
+        assert!(auth.authorize(Some("Bearer token")));
+        assert!(!auth.authorize(Some("Bearer other")));
+        assert!(!auth.authorize(Some("Basic dXNlcjpwYXNz")));
+    }
+}
@@ -0,0 +1,208 @@
+use std::time::Duration;
+
+use prometheus::Registry;
+use thiserror::Error;
+
+use crate::probes::dedup::DedupPolicy;
+use crate::probes::discovery::Discovery;
+use crate::probes::exit::ExitError;
+use crate::probes::init_probing;
+use crate::probes::prometheus::ProberCollector;
+use crate::probes::protocol::Protocol;
+use crate::probes::ProbeServices;
+
+// Interval between each probe of a node when no probe is set
+const DEFAULT_INTERVAL_CHECK_MS: u64 = 1000;
+
+#[derive(Error, Debug)]
+pub enum ProbesError {
+    #[error("Missing discovery of the nodes to probe")]
+    MissingDiscovery,
+    #[error("Failed to register the prober metrics: {source}")]
+    Registry {
+        #[from]
+        source: prometheus::Error,
+    },
+    #[error(transparent)]
+    Exit(#[from] ExitError),
+}
+
+// Probing engine embedded in another service
+pub struct Probes {
+    discovery: Box<dyn Discovery>,
+    probe: ProbeServices,
+    // Registry of the service exposing the prober metrics, default registry if none
+    metrics_registry: Option<Registry>,
+    // Time without update before the series of a node expire, never if 0
+    idle_series_expiry: Duration,
+}
+
+impl Probes {
+    /// Returns a ProbesBuilder to configure the probing engine
+    pub fn builder() -> ProbesBuilder {
+        ProbesBuilder::default()
+    }
+
+    /// Probe the discovered nodes until a condition of the exit policy of the probe is met
+    ///
+    /// Metrics are updated on the registry, the webserver of the mempoke binary is not started
+    ///
+    /// # Return
+    ///
+    /// * Condition of the exit policy that has been met
+    ///
+    pub async fn run(self) -> Result<(), ProbesError> {
+        if let Some(metrics_registry) = self.metrics_registry {
+            metrics_registry.register(Box::new(ProberCollector))?;
+        }
+        init_probing(self.discovery, self.probe, self.idle_series_expiry).await?;
+        Ok(())
+    }
+}
+
+// Builder of the probing engine
+#[derive(Default)]
+pub struct ProbesBuilder {
+    discovery: Option<Box<dyn Discovery>>,
+    probe: Option<ProbeServices>,
+    metrics_registry: Option<Registry>,
+    idle_series_expiry: Duration,
+}
+
+impl ProbesBuilder {
+    /// Discover the nodes to probe from a source, required
+    ///
+    /// # Arguments
+    ///
+    /// * `discovery` - source of the nodes to probe
+    ///
+    pub fn discovery(mut self, discovery: impl Discovery + 'static) -> Self {
+        self.discovery = Some(Box::new(discovery));
+        self
+    }
+
+    /// Probe the discovered nodes, with the default protocol every second if not set
+    ///
+    /// # Arguments
+    ///
+    /// * `probe` - probes of the discovered nodes
+    ///
+    pub fn probe(mut self, probe: ProbeServices) -> Self {
+        self.probe = Some(probe);
+        self
+    }
+
+    /// Expose the prober metrics on a registry other than the default one
+    ///
+    /// # Arguments
+    ///
+    /// * `metrics_registry` - registry gathered by the service
+    ///
+    pub fn metrics_registry(mut self, metrics_registry: Registry) -> Self {
+        self.metrics_registry = Some(metrics_registry);
+        self
+    }
+
+    /// Remove the series of nodes not probed for some time
+    ///
+    /// # Arguments
+    ///
+    /// * `idle_series_expiry` - time without update before the series of a node expire, never if 0
+    ///
+    pub fn idle_series_expiry(mut self, idle_series_expiry: Duration) -> Self {
+        self.idle_series_expiry = idle_series_expiry;
+        self
+    }
+
+    /// Returns the configured Probes
+    pub fn build(self) -> Result<Probes, ProbesError> {
+        Ok(Probes {
+            discovery: self.discovery.ok_or(ProbesError::MissingDiscovery)?,
+            probe: self.probe.unwrap_or_else(|| {
+                ProbeServices::new(
+                    DEFAULT_INTERVAL_CHECK_MS,
+                    DedupPolicy::default(),
+                    0,
+                    Protocol::default(),
+                )
+            }),
+            metrics_registry: self.metrics_registry,
+            idle_series_expiry: self.idle_series_expiry,
+        })
+    }
+
+    /// Build and run the probing engine
+    ///
+    /// # Return
+    ///
+    /// * Condition of the exit policy that has been met or a configuration issue
+    ///
+    pub async fn run(self) -> Result<(), ProbesError> {
+        self.build()?.run().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+    use std::time::Duration;
+
+    use prometheus::Registry;
+
+    use crate::probes::builder::{Probes, ProbesError};
+    use crate::probes::dedup::DedupPolicy;
+    use crate::probes::discovery::{Discovery, DiscoveryError, DiscoveryFuture};
+    use crate::probes::exit::{ExitError, ExitPolicy};
+    use crate::probes::protocol::Protocol;
+    use crate::probes::ProbeServices;
+
+    struct StoppedDiscovery;
+
+    impl fmt::Display for StoppedDiscovery {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "stopped")
+        }
+    }
+
+    impl Discovery for StoppedDiscovery {
+        fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Err(DiscoveryError::SourcesStopped)
+            })
+        }
+    }
+
+    #[tokio::test]
+    async fn run_missing_discovery() {
+        assert!(matches!(
+            Probes::builder().run().await,
+            Err(ProbesError::MissingDiscovery)
+        ));
+    }
+
+    #[tokio::test]
+    async fn run_until_exit_policy() {
+        let registry = Registry::new();
+        let probe = ProbeServices::new(1000, DedupPolicy::Disabled, 0, Protocol::Tcp)
+            .with_exit_policy(ExitPolicy {
+                discovery_failure: Some(Duration::from_millis(30)),
+                ..ExitPolicy::default()
+            });
+        let run_res = tokio::time::timeout(
+            Duration::from_secs(1),
+            Probes::builder()
+                .discovery(StoppedDiscovery)
+                .probe(probe)
+                .metrics_registry(registry.clone())
+                .run(),
+        )
+        .await
+        .expect("Discovery failure should stop the probes");
+        assert!(matches!(
+            run_res,
+            Err(ProbesError::Exit(ExitError::DiscoveryFailure(_)))
+        ));
+        assert!(!registry.gather().is_empty());
+    }
+}
@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+use prometheus::core::Collector;
+
+use crate::probes::prometheus::{NUMBER_OF_REQUESTS, RESPONSE_TIME_COLLECTOR};
+use crate::probes::protocol::Protocol;
+
+// Outcome of a command issued while checking a node
+#[derive(Debug, PartialEq)]
+pub struct CommandReport {
+    pub cmd_type: String,
+    // Comma separated statuses of the responses, empty if the node never answered
+    pub status: String,
+    pub response_time: Duration,
+}
+
+// Outcome of a single probe run against a node
+#[derive(Debug)]
+pub struct CheckReport {
+    pub protocol: Protocol,
+    pub socket: String,
+    pub elapsed: Duration,
+    // Commands in alphabetical order of their type
+    pub commands: Vec<CommandReport>,
+    // None if the probe succeeded
+    pub issue: Option<String>,
+}
+
+impl CheckReport {
+    /// Return true if the probe succeeded
+    pub fn is_ok(&self) -> bool {
+        self.issue.is_none()
+    }
+}
+
+/// Format a duration as milliseconds
+fn as_millis(duration: Duration) -> String {
+    format!("{:.3}ms", duration.as_secs_f64() * 1000.0)
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.issue {
+            None => writeln!(
+                f,
+                "{} {} OK in {}",
+                self.protocol,
+                self.socket,
+                as_millis(self.elapsed)
+            )?,
+            Some(issue) => writeln!(
+                f,
+                "{} {} FAILED in {}: {}",
+                self.protocol,
+                self.socket,
+                as_millis(self.elapsed),
+                issue
+            )?,
+        }
+        for command in &self.commands {
+            let status = if command.status.is_empty() {
+                "NoResponse"
+            } else {
+                command.status.as_str()
+            };
+            writeln!(
+                f,
+                "  {:<16} {:<20} {}",
+                command.cmd_type,
+                status,
+                as_millis(command.response_time)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Return the value of a label of a metric
+fn label_value<'a>(metric: &'a prometheus::proto::Metric, name: &str) -> &'a str {
+    metric
+        .get_label()
+        .iter()
+        .find(|label| label.get_name() == name)
+        .map_or("", |label| label.get_value())
+}
+
+/// Collect the commands issued on a node from the exported metrics
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node, only used by the check
+/// * `socket` - ip:port of the node
+///
+/// # Return
+///
+/// * Status and total response time of each command type
+///
+fn collect_commands(cluster_name: &str, socket: &str) -> Vec<CommandReport> {
+    let is_node = |metric: &&prometheus::proto::Metric| {
+        label_value(metric, "cluster_name") == cluster_name
+            && label_value(metric, "socket") == socket
+    };
+
+    let mut commands: BTreeMap<String, CommandReport> = BTreeMap::new();
+    for family in RESPONSE_TIME_COLLECTOR.collect() {
+        for metric in family.get_metric().iter().filter(is_node) {
+            let cmd_type = label_value(metric, "type").to_string();
+            commands.insert(
+                cmd_type.clone(),
+                CommandReport {
+                    cmd_type,
+                    status: String::new(),
+                    response_time: Duration::from_secs_f64(metric.get_histogram().get_sample_sum()),
+                },
+            );
+        }
+    }
+    for family in NUMBER_OF_REQUESTS.collect() {
+        for metric in family.get_metric().iter().filter(is_node) {
+            let cmd_type = label_value(metric, "type");
+            let status = label_value(metric, "status");
+            let command = commands
+                .entry(cmd_type.to_string())
+                .or_insert_with(|| CommandReport {
+                    cmd_type: cmd_type.to_string(),
+                    status: String::new(),
+                    response_time: Duration::ZERO,
+                });
+            if !command.status.is_empty() {
+                command.status.push(',');
+            }
+            command.status.push_str(status);
+        }
+    }
+    commands.into_values().collect()
+}
+
+/// Run the probe of a protocol once against a single node
+///
+/// # Arguments
+///
+/// * `protocol` - protocol of the node
+/// * `cluster_name` - name of the service of the node, only used by the check
+/// * `socket` - ip:port of the node
+/// * `timeout` - max duration of the connection and the probe
+///
+/// # Return
+///
+/// * Outcome of the probe and of each command it issued
+///
+pub async fn check_node(
+    protocol: Protocol,
+    cluster_name: &str,
+    socket: &str,
+    timeout: Duration,
+) -> CheckReport {
+    let start = Instant::now();
+    let probe_res = tokio::time::timeout(timeout, async {
+        let mut connection = protocol.connect(cluster_name, socket).await?;
+        connection.probe().await
+    })
+    .await;
+    let issue = match probe_res {
+        Ok(Ok(())) => None,
+        Ok(Err(issue)) => Some(issue.to_string()),
+        Err(_) => Some(format!("No answer within {}", as_millis(timeout))),
+    };
+    CheckReport {
+        protocol,
+        socket: socket.to_string(),
+        elapsed: start.elapsed(),
+        commands: collect_commands(cluster_name, socket),
+        issue,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::probes::check::check_node;
+    use crate::probes::protocol::Protocol;
+
+    #[cfg(feature = "memcached")]
+    #[tokio::test]
+    async fn check_memcached_node() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // Answer the set then miss the get
+            for status in ["0000", "0001"] {
+                let mut buffer = [0; 2048];
+                socket.read(&mut buffer).await.unwrap();
+                let response = hex::decode(format!(
+                    "810000000000{status}00000000000000000000000000000000"
+                ))
+                .expect("Decoding failed");
+                socket.write_all(&response).await.unwrap();
+            }
+        });
+
+        let report = check_node(
+            Protocol::Memcached,
+            "check_memcached",
+            &addr,
+            Duration::from_secs(1),
+        )
+        .await;
+        assert!(report.is_ok());
+        let commands: Vec<(&str, &str)> = report
+            .commands
+            .iter()
+            .map(|command| (command.cmd_type.as_str(), command.status.as_str()))
+            .collect();
+        assert_eq!(vec![("get", "KeyNotFound"), ("set", "NoError")], commands);
+        assert!(report.to_string().contains(" OK in "));
+    }
+
+    #[tokio::test]
+    async fn check_unreachable_node() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let report = check_node(
+            Protocol::Memcached,
+            "check_unreachable",
+            &addr,
+            Duration::from_secs(1),
+        )
+        .await;
+        assert!(!report.is_ok());
+        assert!(report.commands.is_empty());
+        assert!(report.to_string().contains(" FAILED in "));
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::str::FromStr;
+
+use crate::probes::discovery::ServiceNode;
+use crate::probes::protocol::Protocol;
+
+// Policy applied when the same ip:port is registered under multiple matching services
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DedupPolicy {
+    // Probe the socket once per owning service
+    #[default]
+    Disabled,
+    // Probe the socket once, labelled with the first owning service (alphabetical order)
+    First,
+    // Probe the socket once, labelled with all owning services joined by a comma
+    All,
+}
+
+impl FromStr for DedupPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "disabled" => Ok(DedupPolicy::Disabled),
+            "first" => Ok(DedupPolicy::First),
+            "all" => Ok(DedupPolicy::All),
+            _ => Err(format!(
+                "Invalid dedup policy {s}, expected one of disabled, first, all"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DedupPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DedupPolicy::Disabled => write!(f, "disabled"),
+            DedupPolicy::First => write!(f, "first"),
+            DedupPolicy::All => write!(f, "all"),
+        }
+    }
+}
+
+impl DedupPolicy {
+    /// Deduplicate discovered nodes sharing the same ip:port
+    ///
+    /// # Arguments
+    ///
+    /// * `discovered_nodes` - hash of nodes discovered in consul with matching tag
+    ///
+    /// # Return
+    ///
+    /// * Hash of nodes to probe keyed by their string representation
+    ///
+    pub fn apply(
+        &self,
+        discovered_nodes: HashMap<String, ServiceNode>,
+    ) -> HashMap<String, ServiceNode> {
+        if *self == DedupPolicy::Disabled {
+            return discovered_nodes;
+        }
+
+        // Group owning services by socket, sorted to keep a stable labelling
+        let mut sockets: BTreeMap<(String, u16), Vec<(String, Option<Protocol>)>> = BTreeMap::new();
+        for service_node in discovered_nodes.into_values() {
+            sockets
+                .entry((service_node.ip, service_node.port))
+                .or_default()
+                .push((service_node.service_name, service_node.protocol));
+        }
+
+        sockets
+            .into_iter()
+            .map(|((ip, port), mut owners)| {
+                owners.sort();
+                // The socket is probed with the protocol of the first owning service
+                let protocol = owners[0].1;
+                let service_name = match self {
+                    DedupPolicy::All => owners
+                        .into_iter()
+                        .map(|(service_name, _)| service_name)
+                        .collect::<Vec<String>>()
+                        .join(","),
+                    _ => owners.swap_remove(0).0,
+                };
+                let service_node = ServiceNode {
+                    service_name,
+                    ip,
+                    port,
+                    protocol,
+                };
+                (service_node.to_string(), service_node)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::str::FromStr;
+
+    use crate::probes::dedup::DedupPolicy;
+    use crate::probes::discovery::ServiceNode;
+
+    fn discovered_nodes() -> HashMap<String, ServiceNode> {
+        [
+            ("memcached-b", 11211),
+            ("memcached-a", 11211),
+            ("memcached-a", 11212),
+        ]
+        .into_iter()
+        .map(|(service_name, port)| {
+            let service_node = ServiceNode {
+                service_name: service_name.to_string(),
+                ip: "127.0.0.1".to_string(),
+                port,
+                protocol: None,
+            };
+            (service_node.to_string(), service_node)
+        })
+        .collect()
+    }
+
+    fn sorted_keys(nodes: HashMap<String, ServiceNode>) -> Vec<String> {
+        let mut keys = nodes.into_keys().collect::<Vec<String>>();
+        keys.sort();
+        keys
+    }
+
+    #[test]
+    fn from_str() {
+        assert_eq!(DedupPolicy::All, DedupPolicy::from_str("all").unwrap());
+        assert_eq!(DedupPolicy::First, DedupPolicy::from_str("first").unwrap());
+        assert_eq!(
+            DedupPolicy::Disabled,
+            DedupPolicy::from_str("disabled").unwrap()
+        );
+        assert!(DedupPolicy::from_str("other").is_err());
+    }
+
+    #[test]
+    fn apply_disabled() {
+        assert_eq!(
+            vec![
+                "memcached-a:127.0.0.1:11211",
+                "memcached-a:127.0.0.1:11212",
+                "memcached-b:127.0.0.1:11211"
+            ],
+            sorted_keys(DedupPolicy::Disabled.apply(discovered_nodes()))
+        );
+    }
+
+    #[test]
+    fn apply_first() {
+        assert_eq!(
+            vec!["memcached-a:127.0.0.1:11211", "memcached-a:127.0.0.1:11212"],
+            sorted_keys(DedupPolicy::First.apply(discovered_nodes()))
+        );
+    }
+
+    #[test]
+    fn apply_all() {
+        assert_eq!(
+            vec![
+                "memcached-a,memcached-b:127.0.0.1:11211",
+                "memcached-a:127.0.0.1:11212"
+            ],
+            sorted_keys(DedupPolicy::All.apply(discovered_nodes()))
+        );
+    }
+}
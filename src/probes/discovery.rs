@@ -0,0 +1,694 @@
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::future::{self, Future};
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::sync::mpsc;
+use tokio::time::sleep;
+use tracing::info;
+
+#[cfg(feature = "cloud")]
+use crate::cloud::CloudError;
+#[cfg(feature = "consul")]
+use crate::consul::ConsulError;
+#[cfg(feature = "docker")]
+use crate::docker::DockerError;
+#[cfg(feature = "file")]
+use crate::file::FileError;
+#[cfg(feature = "http-sd")]
+use crate::http_sd::HttpSdError;
+#[cfg(feature = "mdns")]
+use crate::mdns::MdnsError;
+#[cfg(feature = "nomad")]
+use crate::nomad::NomadError;
+use crate::probes::prometheus::{DISCOVERY_SOURCE_NODES, FAILURE_DISCOVERY_SOURCE};
+use crate::probes::protocol::Protocol;
+use crate::probes::state::PROBER_STATE;
+#[cfg(feature = "srv")]
+use crate::srv::SrvError;
+
+// Node to probe, found by a discovery source
+#[derive(Debug, PartialEq, Clone)]
+pub struct ServiceNode {
+    pub service_name: String,
+    pub ip: String,
+    pub port: u16,
+    // Protocol declared on the service, the prober default one if none
+    pub protocol: Option<Protocol>,
+}
+
+impl fmt::Display for ServiceNode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.service_name, self.ip, self.port)
+    }
+}
+
+// Nodes found by a discovery source
+#[derive(Debug, PartialEq, Clone)]
+pub struct ServiceNodes {
+    pub index: i64,
+    // Services matching the tag, including the ones without nodes
+    pub services: Vec<String>,
+    pub nodes: HashMap<String, ServiceNode>,
+}
+
+/// Percent encode a value of a query or form, keeping only unreserved characters
+pub(crate) fn url_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+// Kind of the source of the nodes to probe
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DiscoveryKind {
+    // Services with the probing tag in the consul catalog
+    #[default]
+    Consul,
+    // Targets of SRV records
+    Srv,
+    // Targets listed in a yaml or json file
+    File,
+    // Target groups fetched from a Prometheus http_sd endpoint
+    Http,
+    // Running aws ec2 instances with a tag
+    Ec2,
+    // Running google compute engine instances with a label
+    Gce,
+    // Containers with the probing label on the local docker engine
+    Docker,
+    // Services with the probing tag in the nomad service registry
+    Nomad,
+    // Instances of mDNS service types answering on the local segment
+    Mdns,
+}
+
+impl FromStr for DiscoveryKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "consul" => Ok(DiscoveryKind::Consul),
+            "srv" => Ok(DiscoveryKind::Srv),
+            "file" => Ok(DiscoveryKind::File),
+            "http" => Ok(DiscoveryKind::Http),
+            "ec2" => Ok(DiscoveryKind::Ec2),
+            "gce" => Ok(DiscoveryKind::Gce),
+            "docker" => Ok(DiscoveryKind::Docker),
+            "nomad" => Ok(DiscoveryKind::Nomad),
+            "mdns" => Ok(DiscoveryKind::Mdns),
+            _ => Err(format!(
+                "Invalid discovery {s}, expected one of consul, srv, file, http, ec2, gce, docker, nomad, mdns"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for DiscoveryKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DiscoveryKind::Consul => write!(f, "consul"),
+            DiscoveryKind::Srv => write!(f, "srv"),
+            DiscoveryKind::File => write!(f, "file"),
+            DiscoveryKind::Http => write!(f, "http"),
+            DiscoveryKind::Ec2 => write!(f, "ec2"),
+            DiscoveryKind::Gce => write!(f, "gce"),
+            DiscoveryKind::Docker => write!(f, "docker"),
+            DiscoveryKind::Nomad => write!(f, "nomad"),
+            DiscoveryKind::Mdns => write!(f, "mdns"),
+        }
+    }
+}
+
+impl DiscoveryKind {
+    /// Return true if the discovery is compiled in, through its cargo feature
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            DiscoveryKind::Consul => cfg!(feature = "consul"),
+            DiscoveryKind::Srv => cfg!(feature = "srv"),
+            DiscoveryKind::File => cfg!(feature = "file"),
+            DiscoveryKind::Http => cfg!(feature = "http-sd"),
+            DiscoveryKind::Ec2 | DiscoveryKind::Gce => cfg!(feature = "cloud"),
+            DiscoveryKind::Docker => cfg!(feature = "docker"),
+            DiscoveryKind::Nomad => cfg!(feature = "nomad"),
+            DiscoveryKind::Mdns => cfg!(feature = "mdns"),
+        }
+    }
+
+    /// Return the issue reported when the discovery is not compiled in
+    pub fn disabled_issue(&self) -> String {
+        let feature = match self {
+            DiscoveryKind::Http => "http-sd".to_string(),
+            DiscoveryKind::Ec2 | DiscoveryKind::Gce => "cloud".to_string(),
+            kind => kind.to_string(),
+        };
+        format!("Discovery {self} is not compiled in, rebuild with --features {feature}")
+    }
+}
+
+// Kinds of the sources of the nodes to probe, in priority order
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct DiscoveryKinds(Vec<DiscoveryKind>);
+
+impl Default for DiscoveryKinds {
+    fn default() -> Self {
+        DiscoveryKinds(vec![DiscoveryKind::default()])
+    }
+}
+
+impl FromStr for DiscoveryKinds {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut kinds = Vec::new();
+        for kind in s.split(',').map(str::trim) {
+            let kind = kind.parse::<DiscoveryKind>()?;
+            if kinds.contains(&kind) {
+                return Err(format!("Discovery {kind} listed more than once"));
+            }
+            kinds.push(kind);
+        }
+        Ok(DiscoveryKinds(kinds))
+    }
+}
+
+impl fmt::Display for DiscoveryKinds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds = self.0.iter().map(ToString::to_string).collect::<Vec<_>>();
+        write!(f, "{}", kinds.join(","))
+    }
+}
+
+impl DiscoveryKinds {
+    /// Returns the kinds of discovery, in priority order
+    pub fn kinds(&self) -> &[DiscoveryKind] {
+        &self.0
+    }
+}
+
+// Policy applied to merge the nodes of multiple discovery sources
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum MergePolicy {
+    // Probe the nodes of all sources, a node found by several sources is taken from the first one
+    #[default]
+    Union,
+    // Probe the nodes of a service from the first source listing that service only
+    Priority,
+}
+
+impl FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "union" => Ok(MergePolicy::Union),
+            "priority" => Ok(MergePolicy::Priority),
+            _ => Err(format!(
+                "Invalid merge policy {s}, expected one of union, priority"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for MergePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MergePolicy::Union => write!(f, "union"),
+            MergePolicy::Priority => write!(f, "priority"),
+        }
+    }
+}
+
+// Error of a failed discovery attempt, logged before the next attempt
+#[derive(Error, Debug)]
+pub enum DiscoveryError {
+    #[cfg(feature = "consul")]
+    #[error(transparent)]
+    Consul(#[from] ConsulError),
+    #[cfg(feature = "srv")]
+    #[error(transparent)]
+    Srv(#[from] SrvError),
+    #[cfg(feature = "file")]
+    #[error(transparent)]
+    File(#[from] FileError),
+    #[cfg(feature = "http-sd")]
+    #[error(transparent)]
+    HttpSd(#[from] HttpSdError),
+    #[cfg(feature = "cloud")]
+    #[error(transparent)]
+    Cloud(#[from] CloudError),
+    #[cfg(feature = "docker")]
+    #[error(transparent)]
+    Docker(#[from] DockerError),
+    #[cfg(feature = "nomad")]
+    #[error(transparent)]
+    Nomad(#[from] NomadError),
+    #[cfg(feature = "mdns")]
+    #[error(transparent)]
+    Mdns(#[from] MdnsError),
+    #[error("Rate limiter failed: {0}")]
+    RateLimiter(String),
+    // Failure of one of the merged sources
+    #[error("{description}: {issue}")]
+    Source {
+        description: String,
+        #[source]
+        issue: Box<DiscoveryError>,
+    },
+    #[error("Discovery sources stopped")]
+    SourcesStopped,
+}
+
+// Future returned by a discovery snapshot
+pub type DiscoveryFuture<'a> =
+    Pin<Box<dyn Future<Output = Result<ServiceNodes, DiscoveryError>> + Send + 'a>>;
+
+// Source of the nodes to probe
+pub trait Discovery: fmt::Display + Send {
+    /// Wait for the next snapshot of the nodes to probe
+    ///
+    /// The first snapshot is returned immediately, the next ones once the nodes may have changed.
+    /// An error is a failed attempt, the next call waits before trying again
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_>;
+}
+
+// Wait between each poll of an interval based discovery
+#[derive(Debug)]
+pub struct PollInterval {
+    interval: Duration,
+    // Set once the first poll happened
+    polled: bool,
+}
+
+impl PollInterval {
+    /// Returns a PollInterval
+    ///
+    /// # Arguments
+    ///
+    /// * `interval` - interval between each poll
+    ///
+    pub fn new(interval: Duration) -> Self {
+        PollInterval {
+            interval,
+            polled: false,
+        }
+    }
+
+    /// Wait for the next poll, immediate on the first one or on a requested refresh
+    pub async fn tick(&mut self) {
+        self.tick_or(future::pending()).await
+    }
+
+    /// Wait for the next poll, also polled as soon as `wake` completes
+    ///
+    /// # Arguments
+    ///
+    /// * `wake` - backend specific trigger of an early poll
+    ///
+    pub async fn tick_or(&mut self, wake: impl Future<Output = ()>) {
+        if !std::mem::replace(&mut self.polled, true) {
+            return;
+        }
+        tokio::select! {
+            _ = sleep(self.interval) => {}
+            _ = PROBER_STATE.refresh_requested() => {
+                info!("Refresh services discovery immediately");
+            }
+            _ = wake => {}
+        }
+    }
+
+    /// Returns the interval between each poll
+    pub fn interval(&self) -> Duration {
+        self.interval
+    }
+}
+
+// Snapshot sent by a discovery source, with the position of the source
+type SourceSnapshot = (usize, Result<ServiceNodes, DiscoveryError>);
+
+// Discovery merging the snapshots of multiple sources run simultaneously
+//
+// Each source runs in its own task so a slow source never delays the others
+pub struct MergedDiscovery {
+    // Name of each source used as metric label, in priority order
+    names: Vec<String>,
+    // Description of each source, in priority order
+    descriptions: Vec<String>,
+    merge_policy: MergePolicy,
+    // Sources not started yet, started on the first snapshot
+    sources: Vec<Box<dyn Discovery>>,
+    snapshots_tx: mpsc::Sender<SourceSnapshot>,
+    snapshots_rx: mpsc::Receiver<SourceSnapshot>,
+    // Last successful snapshot of each source, kept while the source fails
+    snapshots: Vec<Option<ServiceNodes>>,
+    // Set once a source returned its first snapshot or error
+    reported: Vec<bool>,
+}
+
+impl MergedDiscovery {
+    /// Returns a MergedDiscovery
+    ///
+    /// # Arguments
+    ///
+    /// * `sources` - name and discovery of each source, in priority order
+    /// * `merge_policy` - policy applied to merge the nodes of the sources
+    ///
+    pub fn new(sources: Vec<(String, Box<dyn Discovery>)>, merge_policy: MergePolicy) -> Self {
+        let (snapshots_tx, snapshots_rx) = mpsc::channel(sources.len().max(1));
+        // A requested refresh wakes every source
+        PROBER_STATE.set_discovery_sources(sources.len());
+        let (names, sources): (Vec<_>, Vec<_>) = sources.into_iter().unzip();
+        MergedDiscovery {
+            descriptions: sources.iter().map(ToString::to_string).collect(),
+            snapshots: names.iter().map(|_| None).collect(),
+            reported: vec![false; names.len()],
+            names,
+            merge_policy,
+            sources,
+            snapshots_tx,
+            snapshots_rx,
+        }
+    }
+
+    /// Start a task sending the snapshots of each source not started yet
+    fn start_sources(&mut self) {
+        for (index, mut source) in self.sources.drain(..).enumerate() {
+            let snapshots_tx = self.snapshots_tx.clone();
+            tokio::spawn(async move {
+                loop {
+                    let snapshot = source.next_snapshot().await;
+                    if snapshots_tx.send((index, snapshot)).await.is_err() {
+                        return;
+                    }
+                }
+            });
+        }
+    }
+
+    /// Merge the last successful snapshot of each source
+    ///
+    /// # Return
+    ///
+    /// * ServiceNodes - services and nodes of all sources, per the merge policy
+    ///
+    fn merge(&self) -> ServiceNodes {
+        // Source of each service, the first one listing it
+        let mut owners: HashMap<&str, usize> = HashMap::new();
+        for (index, snapshot) in self.snapshots.iter().enumerate() {
+            for service_node in snapshot.iter().flat_map(|snapshot| snapshot.nodes.values()) {
+                owners
+                    .entry(service_node.service_name.as_str())
+                    .or_insert(index);
+            }
+        }
+
+        let mut services = BTreeSet::new();
+        let mut nodes: HashMap<String, ServiceNode> = HashMap::new();
+        for (index, snapshot) in self.snapshots.iter().enumerate() {
+            let mut source_nodes = 0;
+            if let Some(snapshot) = snapshot {
+                services.extend(snapshot.services.iter().cloned());
+                for (key, service_node) in &snapshot.nodes {
+                    if self.merge_policy == MergePolicy::Priority
+                        && owners.get(service_node.service_name.as_str()) != Some(&index)
+                    {
+                        continue;
+                    }
+                    if !nodes.contains_key(key) {
+                        nodes.insert(key.clone(), service_node.clone());
+                        source_nodes += 1;
+                    }
+                }
+            }
+            DISCOVERY_SOURCE_NODES
+                .with_label_values(&[&self.names[index]])
+                .set(source_nodes);
+        }
+        ServiceNodes {
+            index: 0,
+            services: services.into_iter().collect(),
+            nodes,
+        }
+    }
+
+    /// Wait for a source to change its nodes, or to fail
+    ///
+    /// The first snapshot waits for every source to return its first snapshot or error
+    async fn watch(&mut self) -> Result<ServiceNodes, DiscoveryError> {
+        self.start_sources();
+        loop {
+            let (index, snapshot) = self
+                .snapshots_rx
+                .recv()
+                .await
+                .ok_or(DiscoveryError::SourcesStopped)?;
+            self.reported[index] = true;
+            match snapshot {
+                Ok(snapshot) => self.snapshots[index] = Some(snapshot),
+                Err(err) => {
+                    FAILURE_DISCOVERY_SOURCE
+                        .with_label_values(&[&self.names[index]])
+                        .inc();
+                    return Err(DiscoveryError::Source {
+                        description: self.descriptions[index].clone(),
+                        issue: Box::new(err),
+                    });
+                }
+            }
+            if self.reported.iter().all(|reported| *reported) {
+                return Ok(self.merge());
+            }
+        }
+    }
+}
+
+impl fmt::Display for MergedDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.descriptions.join(", "))
+    }
+}
+
+impl Discovery for MergedDiscovery {
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+        Box::pin(self.watch())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+    use std::fmt;
+    use std::time::Duration;
+
+    #[cfg(feature = "consul")]
+    use crate::consul::ConsulError;
+    use crate::probes::discovery::{
+        url_encode, Discovery, DiscoveryError, DiscoveryFuture, DiscoveryKind, DiscoveryKinds,
+        MergePolicy, MergedDiscovery, PollInterval, ServiceNode, ServiceNodes,
+    };
+
+    // Discovery returning each snapshot once after its delay then waiting forever
+    struct MockDiscovery {
+        snapshots: VecDeque<(u64, Result<ServiceNodes, DiscoveryError>)>,
+    }
+
+    impl fmt::Display for MockDiscovery {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "mock")
+        }
+    }
+
+    impl Discovery for MockDiscovery {
+        fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+            Box::pin(async move {
+                match self.snapshots.pop_front() {
+                    Some((delay_secs, snapshot)) => {
+                        tokio::time::sleep(Duration::from_secs(delay_secs)).await;
+                        snapshot
+                    }
+                    None => std::future::pending().await,
+                }
+            })
+        }
+    }
+
+    fn source(
+        name: &str,
+        snapshots: Vec<(u64, Result<ServiceNodes, DiscoveryError>)>,
+    ) -> (String, Box<dyn Discovery>) {
+        (
+            name.to_string(),
+            Box::new(MockDiscovery {
+                snapshots: VecDeque::from(snapshots),
+            }),
+        )
+    }
+
+    fn snapshot(nodes: &[(&str, u16)]) -> Result<ServiceNodes, DiscoveryError> {
+        let nodes = nodes
+            .iter()
+            .map(|(service_name, port)| {
+                let service_node = ServiceNode {
+                    service_name: service_name.to_string(),
+                    ip: "ip".to_string(),
+                    port: *port,
+                    protocol: None,
+                };
+                (service_node.to_string(), service_node)
+            })
+            .collect::<std::collections::HashMap<_, _>>();
+        let mut services = nodes
+            .values()
+            .map(|service_node| service_node.service_name.clone())
+            .collect::<Vec<_>>();
+        services.sort();
+        services.dedup();
+        Ok(ServiceNodes {
+            index: 0,
+            services,
+            nodes,
+        })
+    }
+
+    fn sorted_nodes(service_nodes: &ServiceNodes) -> Vec<String> {
+        let mut nodes = service_nodes.nodes.keys().cloned().collect::<Vec<_>>();
+        nodes.sort();
+        nodes
+    }
+
+    #[test]
+    fn parse_discovery_kind() {
+        assert_eq!(Ok(DiscoveryKind::Consul), "consul".parse());
+        assert_eq!(Ok(DiscoveryKind::Srv), "srv".parse());
+        assert_eq!(Ok(DiscoveryKind::File), "file".parse());
+        assert_eq!(Ok(DiscoveryKind::Http), "http".parse());
+        assert_eq!(Ok(DiscoveryKind::Gce), "gce".parse());
+        assert_eq!(Ok(DiscoveryKind::Docker), "docker".parse());
+        assert_eq!(Ok(DiscoveryKind::Nomad), "nomad".parse());
+        assert_eq!(Ok(DiscoveryKind::Mdns), "mdns".parse());
+        assert!("k8s".parse::<DiscoveryKind>().is_err());
+        assert_eq!("srv", DiscoveryKind::Srv.to_string());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn poll_interval() {
+        let mut poll_interval = PollInterval::new(Duration::from_secs(30));
+        let start = tokio::time::Instant::now();
+        poll_interval.tick().await;
+        assert_eq!(Duration::ZERO, start.elapsed());
+
+        // Woken before the interval
+        poll_interval.tick_or(async {}).await;
+        assert_eq!(Duration::ZERO, start.elapsed());
+
+        // A refresh requested by another test may shorten the wait
+        poll_interval.tick().await;
+        assert!(start.elapsed() <= Duration::from_secs(30));
+    }
+
+    #[test]
+    fn parse_discovery_kinds() {
+        assert_eq!(&[DiscoveryKind::Consul], DiscoveryKinds::default().kinds());
+        let discovery_kinds = "consul, file".parse::<DiscoveryKinds>().unwrap();
+        assert_eq!(
+            &[DiscoveryKind::Consul, DiscoveryKind::File],
+            discovery_kinds.kinds()
+        );
+        assert_eq!("consul,file", discovery_kinds.to_string());
+        assert!("consul,consul".parse::<DiscoveryKinds>().is_err());
+        assert!("consul,k8s".parse::<DiscoveryKinds>().is_err());
+
+        assert_eq!(Ok(MergePolicy::Priority), "priority".parse());
+        assert!("first".parse::<MergePolicy>().is_err());
+        assert_eq!("union", MergePolicy::default().to_string());
+    }
+
+    #[cfg(feature = "consul")]
+    #[tokio::test(start_paused = true)]
+    async fn merged_discovery() {
+        let sources = || {
+            vec![
+                source(
+                    "consul",
+                    vec![
+                        (0, snapshot(&[("cache", 1), ("cache", 2), ("sessions", 1)])),
+                        (
+                            10,
+                            Err(ConsulError::Status {
+                                uri: "/v1/catalog/services".to_string(),
+                                status: 503,
+                            }
+                            .into()),
+                        ),
+                        (10, snapshot(&[("cache", 1)])),
+                    ],
+                ),
+                source(
+                    "file",
+                    vec![(0, snapshot(&[("cache", 2), ("cache", 9), ("pinned", 1)]))],
+                ),
+            ]
+        };
+
+        let mut discovery = MergedDiscovery::new(sources(), MergePolicy::Union);
+        assert_eq!("mock, mock", discovery.to_string());
+        let service_nodes = discovery.next_snapshot().await.unwrap();
+        assert_eq!(
+            vec![
+                "cache:ip:1",
+                "cache:ip:2",
+                "cache:ip:9",
+                "pinned:ip:1",
+                "sessions:ip:1"
+            ],
+            sorted_nodes(&service_nodes)
+        );
+        assert_eq!(vec!["cache", "pinned", "sessions"], service_nodes.services);
+        // A failing source keeps its last nodes
+        let err = discovery.next_snapshot().await.unwrap_err();
+        assert_eq!(
+            "mock: Issue query: /v1/catalog/services - status code: 503",
+            err.to_string()
+        );
+        let service_nodes = discovery.next_snapshot().await.unwrap();
+        assert_eq!(
+            vec!["cache:ip:1", "cache:ip:2", "cache:ip:9", "pinned:ip:1"],
+            sorted_nodes(&service_nodes)
+        );
+
+        // Nodes of a service come from the first source listing it
+        let mut discovery = MergedDiscovery::new(sources(), MergePolicy::Priority);
+        let service_nodes = discovery.next_snapshot().await.unwrap();
+        assert_eq!(
+            vec!["cache:ip:1", "cache:ip:2", "pinned:ip:1", "sessions:ip:1"],
+            sorted_nodes(&service_nodes)
+        );
+    }
+
+    #[test]
+    fn service_node_to_string() {
+        let node = ServiceNode {
+            service_name: "service_name".to_string(),
+            ip: "0.0.0.0".to_string(),
+            port: 12500,
+            protocol: None,
+        };
+        assert_eq!("service_name:0.0.0.0:12500".to_string(), node.to_string());
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!("tag%3Aservice", url_encode("tag:service"));
+        assert_eq!("a-b_c.d~e%20f%2F", url_encode("a-b_c.d~e f/"));
+    }
+}
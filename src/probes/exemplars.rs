@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use prometheus::proto::LabelPair;
+
+lazy_static! {
+    pub static ref EXEMPLARS: ExemplarStore = ExemplarStore::new();
+}
+
+// Exemplar of a histogram bucket linking an observation to its trace
+#[derive(Debug, Clone, PartialEq)]
+pub struct Exemplar {
+    pub trace_id: String,
+    pub value: f64,
+    // Seconds since unix epoch of the observation
+    pub timestamp: f64,
+}
+
+// Exemplars of all buckets of an histogram serie
+#[derive(Debug)]
+struct SeriesExemplars {
+    labels: Vec<(String, String)>,
+    // Last exemplar per bucket index, the +Inf bucket being after the last upper bound
+    buckets: HashMap<usize, Exemplar>,
+}
+
+// Keep the last exemplar of each histogram bucket
+// Exemplars are only exposed through the OpenMetrics format
+#[derive(Debug, Default)]
+pub struct ExemplarStore {
+    series: RwLock<HashMap<String, SeriesExemplars>>,
+}
+
+/// Build the key of a serie from its metric name and labels sorted by name
+fn series_key<'a>(name: &str, labels: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+    let mut labels = labels.collect::<Vec<(&str, &str)>>();
+    labels.sort();
+    let mut key = name.to_string();
+    for (label_name, label_value) in labels {
+        key.push_str(&format!(",{label_name}={label_value}"));
+    }
+    key
+}
+
+/// Return the trace id of the current span if exported through OTLP
+#[cfg(feature = "otlp")]
+fn current_trace_id() -> Option<String> {
+    use opentelemetry::trace::TraceContextExt;
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+
+    let context = tracing::Span::current().context();
+    let span_context = context.span().span_context().clone();
+    span_context
+        .is_valid()
+        .then(|| format!("{:032x}", span_context.trace_id()))
+}
+
+/// Return the trace id of the current span if exported through OTLP
+#[cfg(not(feature = "otlp"))]
+fn current_trace_id() -> Option<String> {
+    None
+}
+
+impl ExemplarStore {
+    /// Returns an empty ExemplarStore
+    pub fn new() -> ExemplarStore {
+        ExemplarStore {
+            series: RwLock::new(HashMap::new()),
+        }
+    }
+
+    /// Record an exemplar for an observation done within the current span
+    ///
+    /// Nothing is recorded if the current span is not exported through OTLP
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the histogram
+    /// * `labels` - labels of the histogram serie
+    /// * `buckets` - upper bounds of the histogram buckets
+    /// * `value` - observed value
+    ///
+    pub fn observe(&self, name: &str, labels: &[(&str, &str)], buckets: &[f64], value: f64) {
+        if let Some(trace_id) = current_trace_id() {
+            self.record(name, labels, buckets, value, trace_id);
+        }
+    }
+
+    /// Record an exemplar for an observation
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the histogram
+    /// * `labels` - labels of the histogram serie
+    /// * `buckets` - upper bounds of the histogram buckets
+    /// * `value` - observed value
+    /// * `trace_id` - trace id of the observation
+    ///
+    pub fn record(
+        &self,
+        name: &str,
+        labels: &[(&str, &str)],
+        buckets: &[f64],
+        value: f64,
+        trace_id: String,
+    ) {
+        let bucket_index = buckets
+            .iter()
+            .position(|upper_bound| value <= *upper_bound)
+            .unwrap_or(buckets.len());
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs_f64())
+            .unwrap_or(0.0);
+
+        self.series
+            .write()
+            .unwrap()
+            .entry(series_key(name, labels.iter().copied()))
+            .or_insert_with(|| SeriesExemplars {
+                labels: labels
+                    .iter()
+                    .map(|(label_name, label_value)| {
+                        (label_name.to_string(), label_value.to_string())
+                    })
+                    .collect(),
+                buckets: HashMap::new(),
+            })
+            .buckets
+            .insert(
+                bucket_index,
+                Exemplar {
+                    trace_id,
+                    value,
+                    timestamp,
+                },
+            );
+    }
+
+    /// Return the exemplar of a bucket if any
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the histogram
+    /// * `labels` - labels of the histogram serie
+    /// * `bucket_index` - index of the bucket in the histogram
+    ///
+    pub fn get(&self, name: &str, labels: &[LabelPair], bucket_index: usize) -> Option<Exemplar> {
+        let series = self.series.read().unwrap();
+        if series.is_empty() {
+            return None;
+        }
+        let key = series_key(
+            name,
+            labels
+                .iter()
+                .map(|label| (label.get_name(), label.get_value())),
+        );
+        series
+            .get(&key)
+            .and_then(|series_exemplars| series_exemplars.buckets.get(&bucket_index))
+            .cloned()
+    }
+
+    /// Remove exemplars of all series having the given labels
+    ///
+    /// # Arguments
+    ///
+    /// * `labels` - labels the removed series must have
+    ///
+    pub fn remove_matching(&self, labels: &[(&str, &str)]) {
+        self.series.write().unwrap().retain(|_, series_exemplars| {
+            !labels.iter().all(|(label_name, label_value)| {
+                series_exemplars
+                    .labels
+                    .iter()
+                    .any(|(name, value)| name == label_name && value == label_value)
+            })
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::proto::LabelPair;
+
+    use crate::probes::exemplars::ExemplarStore;
+
+    const LABELS: [(&str, &str); 3] = [
+        ("cluster_name", "memcached"),
+        ("socket", "ip:0"),
+        ("type", "get"),
+    ];
+
+    fn label_pairs() -> Vec<LabelPair> {
+        LABELS
+            .iter()
+            .map(|(name, value)| {
+                let mut label = LabelPair::new();
+                label.set_name(name.to_string());
+                label.set_value(value.to_string());
+                label
+            })
+            .collect()
+    }
+
+    #[test]
+    fn record_and_get() {
+        let store = ExemplarStore::new();
+        store.record("latency", &LABELS, &[0.1, 1.0], 0.5, "abc".to_string());
+        store.record("latency", &LABELS, &[0.1, 1.0], 5.0, "def".to_string());
+
+        assert!(store.get("latency", &label_pairs(), 0).is_none());
+        let exemplar = store.get("latency", &label_pairs(), 1).unwrap();
+        assert_eq!("abc", exemplar.trace_id);
+        assert_eq!(0.5, exemplar.value);
+        assert_eq!(
+            "def",
+            store.get("latency", &label_pairs(), 2).unwrap().trace_id
+        );
+    }
+
+    #[test]
+    fn remove_matching() {
+        let store = ExemplarStore::new();
+        store.record("latency", &LABELS, &[0.1], 0.05, "abc".to_string());
+
+        store.remove_matching(&[("cluster_name", "memcached"), ("socket", "other:0")]);
+        assert!(store.get("latency", &label_pairs(), 0).is_some());
+
+        store.remove_matching(&[("cluster_name", "memcached"), ("socket", "ip:0")]);
+        assert!(store.get("latency", &label_pairs(), 0).is_none());
+    }
+}
@@ -0,0 +1,97 @@
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::time::interval;
+
+use crate::probes::readiness::READINESS;
+
+// Interval between each check of the probe failures of the first discovery
+const STARTUP_PROBES_CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+// Conditions making the prober exit with a non zero code instead of retrying
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitPolicy {
+    // Exit once the discovery has been failing for that long, retried forever if none
+    pub discovery_failure: Option<Duration>,
+    // Exit once that many nodes of the first discovery failed their first probe, never if 0
+    pub startup_probe_failures: usize,
+    // Exit if the metrics endpoint fails, keep probing and retry to bind its port otherwise
+    pub http_failure: bool,
+}
+
+impl Default for ExitPolicy {
+    fn default() -> Self {
+        ExitPolicy {
+            discovery_failure: None,
+            startup_probe_failures: 0,
+            http_failure: true,
+        }
+    }
+}
+
+// Condition of the exit policy that has been met
+#[derive(Error, Debug, PartialEq)]
+pub enum ExitError {
+    #[error("Discovery failing for {0:?}.")]
+    DiscoveryFailure(Duration),
+    #[error("Metrics endpoint failed: {0}.")]
+    HttpFailure(String),
+    #[error("{0} nodes failed their first probe.")]
+    StartupProbeFailures(usize),
+}
+
+impl ExitError {
+    /// Return the exit code of the process
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            ExitError::DiscoveryFailure(_) => 2,
+            ExitError::HttpFailure(_) => 10,
+            ExitError::StartupProbeFailures(_) => 11,
+        }
+    }
+}
+
+/// Wait until enough nodes of the first discovery failed their first probe
+///
+/// Never completes if the prober becomes ready below the threshold
+///
+/// # Arguments
+///
+/// * `threshold` - number of failed nodes, greater than 0
+///
+/// # Return
+///
+/// * Number of nodes that failed their first probe
+///
+pub async fn startup_probes_failed(threshold: usize) -> usize {
+    let mut ticks = interval(STARTUP_PROBES_CHECK_INTERVAL);
+    loop {
+        ticks.tick().await;
+        let failures = READINESS.startup_probe_failures();
+        if failures >= threshold {
+            return failures;
+        }
+        // No more first probe once ready
+        if READINESS.is_ready() {
+            return std::future::pending().await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::probes::exit::{ExitError, ExitPolicy};
+
+    #[test]
+    fn exit_code() {
+        assert!(ExitPolicy::default().http_failure);
+        assert_eq!(
+            2,
+            ExitError::DiscoveryFailure(Duration::from_secs(60)).exit_code()
+        );
+        assert_eq!(10, ExitError::HttpFailure("bind".to_string()).exit_code());
+        assert_eq!(11, ExitError::StartupProbeFailures(3).exit_code());
+    }
+}
@@ -0,0 +1,174 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use hyper::client::connect::{Connected, Connection};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::{Body, Client, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+
+use crate::probes::prometheus::{HTTP_CLIENT_CONNECTIONS, HTTP_CLIENT_OPEN_CONNECTIONS};
+
+// Idle connections kept per host, discovery components only query a few agents
+const POOL_MAX_IDLE_PER_HOST: usize = 8;
+// Time before an idle connection of the pool is closed
+const POOL_IDLE_TIMEOUT: Duration = Duration::from_secs(90);
+
+// Http client of the discovery components
+pub type HttpClient = Client<HttpsConnector<CountingConnector>, Body>;
+
+// Built on first use and shared by all discovery components
+static HTTP_CLIENT: OnceLock<Arc<HttpClient>> = OnceLock::new();
+
+/// Return the http client shared by the discovery components
+///
+/// Connections of its pool are exported as metrics
+pub fn shared_client() -> Arc<HttpClient> {
+    HTTP_CLIENT
+        .get_or_init(|| {
+            let https = HttpsConnectorBuilder::new()
+                .with_native_roots()
+                .https_or_http()
+                .enable_http1()
+                .wrap_connector(CountingConnector::new());
+            Arc::new(
+                Client::builder()
+                    .pool_idle_timeout(POOL_IDLE_TIMEOUT)
+                    .pool_max_idle_per_host(POOL_MAX_IDLE_PER_HOST)
+                    .build(https),
+            )
+        })
+        .clone()
+}
+
+// Http connector counting the connections it opens
+#[derive(Debug, Clone)]
+pub struct CountingConnector {
+    http: HttpConnector,
+}
+
+impl CountingConnector {
+    /// Returns a CountingConnector also connecting to https uris, tls being added on top
+    fn new() -> Self {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        CountingConnector { http }
+    }
+}
+
+impl Service<Uri> for CountingConnector {
+    type Response = CountedStream;
+    type Error = <HttpConnector as Service<Uri>>::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<CountedStream, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.http.poll_ready(cx)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let connecting = self.http.call(uri);
+        Box::pin(async move { Ok(CountedStream::new(connecting.await?)) })
+    }
+}
+
+// Connection of the pool, counted as open until dropped
+#[derive(Debug)]
+pub struct CountedStream {
+    stream: TcpStream,
+}
+
+impl CountedStream {
+    fn new(stream: TcpStream) -> Self {
+        HTTP_CLIENT_CONNECTIONS.inc();
+        HTTP_CLIENT_OPEN_CONNECTIONS.inc();
+        CountedStream { stream }
+    }
+}
+
+impl Drop for CountedStream {
+    fn drop(&mut self) {
+        HTTP_CLIENT_OPEN_CONNECTIONS.dec();
+    }
+}
+
+impl Connection for CountedStream {
+    fn connected(&self) -> Connected {
+        self.stream.connected()
+    }
+}
+
+impl AsyncRead for CountedStream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for CountedStream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write(cx, buf)
+    }
+
+    fn poll_write_vectored(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[io::IoSlice<'_>],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.stream).poll_write_vectored(cx, bufs)
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        self.stream.is_write_vectored()
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.stream).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use wiremock::matchers::method;
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::probes::http_client::shared_client;
+    use crate::probes::prometheus::HTTP_CLIENT_CONNECTIONS;
+
+    #[tokio::test]
+    async fn shared_client_counts_connections() {
+        assert!(Arc::ptr_eq(&shared_client(), &shared_client()));
+
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        let connections = HTTP_CLIENT_CONNECTIONS.get();
+
+        let response = shared_client()
+            .get(mock_server.uri().parse().unwrap())
+            .await
+            .unwrap();
+        assert!(response.status().is_success());
+        assert!(HTTP_CLIENT_CONNECTIONS.get() > connections);
+    }
+}
@@ -1,47 +1,275 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::fmt;
 use std::fmt::Debug;
-use std::time::Duration;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
+use prometheus::core::{Collector, MetricVec, MetricVecBuilder};
+use prometheus::{Gauge, IntCounter, IntGauge};
+use serde_json::{json, Value};
 use tokio::sync::oneshot;
 use tokio::sync::oneshot::error::TryRecvError;
 use tokio::time::sleep;
 use tracing::log::warn;
 use tracing::{debug, error, info};
 
-use crate::consul::{ConsulClient, ServiceNode};
-use crate::memcached;
-use crate::memcached::{MemcachedClientError, STATUS_CODE};
+use crate::probes::dedup::DedupPolicy;
+use crate::probes::discovery::{Discovery, DiscoveryError, ServiceNode, ServiceNodes};
+use crate::probes::exemplars::EXEMPLARS;
+use crate::probes::exit::{startup_probes_failed, ExitError, ExitPolicy};
 use crate::probes::prometheus::{
-    FAILURE_PROBE, FAILURE_SERVICES_DISCOVERY, NUMBER_OF_REQUESTS, RESPONSE_TIME_COLLECTOR,
+    node_series_removed, series_generation, BACKEND_QUEUE_DEPTH, BACKEND_SERVERS, BYTES_RECEIVED,
+    BYTES_SENT, CLUSTER_HEALTH, DISCOVERED_NODES, DISCOVERED_SERVICES, EXPIRED_NODE_SERIES,
+    FAILURE_PROBE, FAILURE_SERVICES_DISCOVERY, NODE_DISTRIBUTION, NODE_RECONNECTS, NODE_ROLE,
+    NODE_STARTTLS, NUMBER_OF_REQUESTS, PROBES_REJECTED, PROBES_STARTED, PROBES_STOPPED,
+    PROBE_LAST_SUCCESS, PROBE_NODE_UP, RESPONSE_TIME_COLLECTOR, RUNNING_PROBES,
 };
-use crate::token_bucket::TokenBucket;
+use crate::probes::protocol::Protocol;
+use crate::probes::readiness::READINESS;
+use crate::probes::shard::Shard;
+use crate::probes::state::{ProbeStatus, TaskHeartbeat, TaskState, PROBER_STATE};
+use crate::token_bucket::{RateLimiter, RateLimiterKind};
 
+pub mod auth;
+pub mod builder;
+pub mod check;
+pub mod dedup;
+pub mod discovery;
+pub mod exemplars;
+pub mod exit;
+pub mod http_client;
+pub mod openmetrics;
 pub mod prometheus;
+pub mod protocol;
+pub mod quantiles;
+pub mod readiness;
+pub mod remote_write;
+pub mod runtime;
+pub mod shard;
+pub mod state;
+pub mod statsd;
+pub mod systemd;
+pub mod telemetry;
 
+/// Probe the discovered nodes until a condition of the exit policy is met
+///
+/// # Arguments
+///
+/// * `discovery` - source of the nodes to probe
+/// * `probe` - probes of the discovered nodes
+/// * `idle_series_expiry` - time without update before the series of a node expire, 0 to never
+///
+/// # Return
+///
+/// * Condition of the exit policy that has been met
+///
 pub async fn init_probing(
-    services_tag: String,
-    consul_fqdn: String,
-    interval_check_ms: u64,
-) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let consul_client = ConsulClient::new(consul_fqdn);
-    let mut probe = ProbeServices::new(consul_client, services_tag, interval_check_ms);
-    probe.watch_matching_services().await?;
-    Ok(())
+    mut discovery: Box<dyn Discovery>,
+    mut probe: ProbeServices,
+    idle_series_expiry: Duration,
+) -> Result<(), ExitError> {
+    info!(
+        "Discover nodes of shard {} to probe from {}",
+        probe.shard, discovery
+    );
+    let startup_probe_failures = probe.exit_policy.startup_probe_failures;
+    tokio::select! {
+        watch_res = probe.watch(discovery.as_mut()) => watch_res,
+        failures = startup_probes_failed(startup_probe_failures), if startup_probe_failures > 0 => {
+            Err(ExitError::StartupProbeFailures(failures))
+        }
+        // Expiring idle series never ends, it stops along with the probing
+        _ = expire_idle_series(idle_series_expiry), if !idle_series_expiry.is_zero() => {
+            unreachable!("expiry of idle series ended")
+        }
+    }
+}
+
+/// Run the discovery once and return the nodes that would be probed
+///
+/// # Arguments
+///
+/// * `discovery` - source of the nodes to probe
+/// * `dedup_policy` - policy for nodes registered under multiple services
+/// * `protocol` - protocol of the nodes without a declared one
+/// * `shard` - part of the nodes probed by this prober
+///
+/// # Return
+///
+/// * Discovered services and targets sorted by node key as json
+///
+pub async fn discover_targets(
+    discovery: &mut dyn Discovery,
+    dedup_policy: DedupPolicy,
+    protocol: Protocol,
+    shard: Shard,
+) -> Result<Value, DiscoveryError> {
+    let discovered_nodes = discovery.next_snapshot().await?;
+    let nodes: BTreeMap<String, ServiceNode> = shard
+        .apply(dedup_policy.apply(discovered_nodes.nodes))
+        .into_iter()
+        .collect();
+
+    Ok(json!({
+        "services": discovered_nodes.services,
+        "targets": nodes
+            .iter()
+            .map(|(key, service_node)| {
+                json!({
+                    "key": key,
+                    "service": service_node.service_name,
+                    "ip": service_node.ip,
+                    "port": service_node.port,
+                    "protocol": service_node.protocol.unwrap_or(protocol).to_string(),
+                })
+            })
+            .collect::<Vec<Value>>(),
+    }))
+}
+
+/// Periodically remove metrics of nodes that have not been probed recently
+///
+/// Protect from stuck probes leaving stale series behind, never ends
+///
+/// # Arguments
+///
+/// * `idle_series_expiry` - max time since the last probe attempt of a node
+///
+async fn expire_idle_series(idle_series_expiry: Duration) {
+    let mut expired_nodes: HashSet<String> = HashSet::new();
+    loop {
+        sleep((idle_series_expiry / 2).max(Duration::from_secs(1))).await;
+
+        let idle_nodes = PROBER_STATE.idle_nodes(idle_series_expiry);
+        for (key, cluster_name, socket) in idle_nodes.iter() {
+            if !expired_nodes.contains(key) {
+                warn!("Expire metrics of node {} not probed recently", key);
+                remove_node_metrics(cluster_name, socket);
+                EXPIRED_NODE_SERIES.inc();
+            }
+        }
+        // Nodes probed again can expire again
+        expired_nodes = idle_nodes.into_iter().map(|(key, _, _)| key).collect();
+    }
+}
+
+/// Remove all series of a metric vec belonging to a node whatever their other labels
+///
+/// # Arguments
+///
+/// * `metric_vec` - metric vec with cluster_name and socket labels
+/// * `cluster_name` - name of the service of the node
+/// * `socket` - ip:port of the node
+///
+fn remove_node_series<T: MetricVecBuilder>(
+    metric_vec: &MetricVec<T>,
+    cluster_name: &str,
+    socket: &str,
+) {
+    for metric_family in metric_vec.collect() {
+        for metric in metric_family.get_metric() {
+            let labels: HashMap<&str, &str> = metric
+                .get_label()
+                .iter()
+                .map(|label| (label.get_name(), label.get_value()))
+                .collect();
+            if labels.get("cluster_name") == Some(&cluster_name)
+                && labels.get("socket") == Some(&socket)
+            {
+                metric_vec.remove(&labels).unwrap_or(());
+            }
+        }
+    }
+}
+
+/// Remove all prometheus metrics of a node
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node
+/// * `socket` - ip:port of the node
+///
+fn remove_node_metrics(cluster_name: &str, socket: &str) {
+    // Invalidate the handles resolved by probes before their series are removed
+    node_series_removed();
+    FAILURE_PROBE
+        .remove_label_values(&[cluster_name, socket])
+        .unwrap_or(());
+    PROBE_NODE_UP
+        .remove_label_values(&[cluster_name, socket])
+        .unwrap_or(());
+    PROBE_LAST_SUCCESS
+        .remove_label_values(&[cluster_name, socket])
+        .unwrap_or(());
+    NODE_RECONNECTS
+        .remove_label_values(&[cluster_name, socket])
+        .unwrap_or(());
+    EXEMPLARS.remove_matching(&[("cluster_name", cluster_name), ("socket", socket)]);
+    quantiles::remove(cluster_name, socket);
+
+    // Command types and statuses depend on the protocol of the node
+    remove_node_series(&RESPONSE_TIME_COLLECTOR, cluster_name, socket);
+    remove_node_series(&BYTES_SENT, cluster_name, socket);
+    remove_node_series(&BYTES_RECEIVED, cluster_name, socket);
+    remove_node_series(&NUMBER_OF_REQUESTS, cluster_name, socket);
+    remove_node_series(&NODE_ROLE, cluster_name, socket);
+    remove_node_series(&NODE_DISTRIBUTION, cluster_name, socket);
+    remove_node_series(&CLUSTER_HEALTH, cluster_name, socket);
+    remove_node_series(&BACKEND_SERVERS, cluster_name, socket);
+    remove_node_series(&BACKEND_QUEUE_DEPTH, cluster_name, socket);
+    remove_node_series(&NODE_STARTTLS, cluster_name, socket);
+}
+
+// Series updated on each probe of a node, resolved once instead of on each probe
+#[derive(Debug)]
+struct NodeSeries {
+    // Generation of the series of nodes the handles have been resolved under
+    generation: u64,
+    failure_probe: IntCounter,
+    node_up: IntGauge,
+    last_success: Gauge,
+}
+
+impl NodeSeries {
+    /// Returns the resolved series of a node
+    ///
+    /// # Arguments
+    ///
+    /// * `cluster_name` - name of the service of the node
+    /// * `socket` - ip:port of the node
+    ///
+    fn resolve(cluster_name: &str, socket: &str) -> Self {
+        NodeSeries {
+            generation: series_generation(),
+            failure_probe: FAILURE_PROBE.with_label_values(&[cluster_name, socket]),
+            node_up: PROBE_NODE_UP.with_label_values(&[cluster_name, socket]),
+            last_success: PROBE_LAST_SUCCESS.with_label_values(&[cluster_name, socket]),
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct ProbeNode {
+    protocol: Protocol,
     cluster_name: String,
-    ip: String,
-    port: u16,
     socket: String,
+    // Key of the node in the prober state
+    key: String,
     interval_check_ms: u64,
+    // Rate limiter implementation spacing the probes
+    rate_limiter: RateLimiterKind,
     stop_probe_resp_rx: oneshot::Receiver<u8>,
+    // Resolved again once series of nodes have been removed
+    series: NodeSeries,
+    // Step of the probe task, updated without locking the prober state
+    heartbeat: Arc<TaskHeartbeat>,
+    // Outcome of the last probe, updated without locking the prober state
+    probe_status: Arc<ProbeStatus>,
 }
 
 impl ProbeNode {
     fn new(
+        protocol: Protocol,
         cluster_name: String,
         ip: String,
         port: u16,
@@ -49,52 +277,124 @@ impl ProbeNode {
         stop_probe_resp_rx: oneshot::Receiver<u8>,
     ) -> Self {
         let socket = format!("{ip}:{port}");
+        let key = format!("{cluster_name}:{socket}");
+        let series = NodeSeries::resolve(&cluster_name, &socket);
+        let heartbeat = PROBER_STATE.task_heartbeat(&key).unwrap_or_default();
+        let probe_status = PROBER_STATE.probe_status(&key).unwrap_or_default();
         ProbeNode {
+            protocol,
             cluster_name,
-            ip,
-            port,
             socket,
+            key,
             interval_check_ms,
+            rate_limiter: RateLimiterKind::default(),
             stop_probe_resp_rx,
+            series,
+            heartbeat,
+            probe_status,
+        }
+    }
+
+    /// Returns the series of that node, resolved again if series of nodes have been removed
+    fn series(&mut self) -> &NodeSeries {
+        if self.series.generation != series_generation() {
+            self.series = NodeSeries::resolve(&self.cluster_name, &self.socket);
         }
+        &self.series
     }
 
-    /// Remove all prometheus metrics of that memcached node
+    /// Remove all prometheus metrics of that node
     ///
     fn stop(&mut self) {
-        FAILURE_PROBE
-            .remove_label_values(&[self.cluster_name.as_str(), self.socket.as_str()])
-            .unwrap_or(());
-
-        for cmd_type in ["set", "get"] {
-            RESPONSE_TIME_COLLECTOR
-                .remove_label_values(&[self.cluster_name.as_str(), self.socket.as_str(), cmd_type])
-                .unwrap_or(());
-
-            for status in STATUS_CODE.keys() {
-                NUMBER_OF_REQUESTS
-                    .remove_label_values(&[
-                        self.cluster_name.as_str(),
-                        self.socket.as_str(),
-                        STATUS_CODE.get(status).unwrap(),
-                        cmd_type,
-                    ])
-                    .unwrap_or(());
+        remove_node_metrics(self.cluster_name.as_str(), self.socket.as_str());
+    }
+
+    /// Check if the probe of that node has to end
+    ///
+    /// Metrics are removed only when the node is no longer discovered, a closed channel
+    /// means the prober is shutting down and keeps serving them while draining
+    ///
+    /// # Return
+    ///
+    /// * true if the probe loop has to end
+    ///
+    fn stop_requested(&mut self) -> bool {
+        match self.stop_probe_resp_rx.try_recv() {
+            Ok(_) => {
+                info!("Stop to probe node: {}:{}", self.cluster_name, self.socket);
+                self.stop();
+                true
             }
+            Err(TryRecvError::Closed) => {
+                debug!("Stop to probe node {} on shutdown", self.key);
+                true
+            }
+            Err(TryRecvError::Empty) => false,
         }
     }
 
-    fn manage_failure(&mut self, issue: MemcachedClientError) {
-        FAILURE_PROBE
-            .with_label_values(&[self.cluster_name.as_str(), self.socket.as_str()])
-            .inc();
-        error!("Failed to probe {} due to {}", self.to_string(), issue);
+    fn manage_failure(&mut self, issue: impl fmt::Display) {
+        let series = self.series();
+        series.failure_probe.inc();
+        series.node_up.set(0);
+        statsd::count(
+            "failure_probe",
+            &[
+                ("cluster_name", self.cluster_name.as_str()),
+                ("socket", self.socket.as_str()),
+            ],
+            1,
+        );
+        self.probe_status.failed(issue.to_string());
+        error!("Failed to probe {} due to {}", self.key, issue);
+    }
+
+    fn manage_success(&mut self, latency: Duration) {
+        let series = self.series();
+        series.node_up.set(1);
+        if let Ok(now) = SystemTime::now().duration_since(UNIX_EPOCH) {
+            series.last_success.set(now.as_secs_f64());
+        }
+        self.probe_status.succeeded(latency);
     }
 
-    /// The memcached probe
-    /// Manage connection to the memcached
+    /// Pace the probes of that node with its rate limiter
+    ///
+    /// # Arguments
+    ///
+    /// * `rate_limiter` - rate limiter implementation spacing the probes by the interval
+    ///
+    fn with_rate_limiter(mut self, rate_limiter: RateLimiterKind) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
+    /// Build the rate limiter spacing the probes by the interval, none without interval
+    fn build_pacing(&self) -> Option<Box<dyn RateLimiter>> {
+        (self.interval_check_ms > 0).then(|| {
+            self.rate_limiter
+                .build("node_probe", 1.0, 1000.0 / self.interval_check_ms as f64, 1)
+        })
+    }
+
+    /// Notify the prober state of the current step of the probe task
+    fn heartbeat(&self, task_state: TaskState) {
+        self.heartbeat.beat(task_state);
+    }
+
+    /// Notify readiness that a probe attempt has been done on that node
+    fn attempted(&self, succeeded: bool) {
+        if !succeeded {
+            READINESS.probe_failed(&self.key);
+        }
+        READINESS.probe_attempted(&self.key);
+    }
+
+    /// The node probe
+    /// Manage connection to the node with its protocol
     /// Check if any message have been send on the stop_probe_resp channel
     /// If it is the case remove all related prometheus metrics and break the probe loop
+    /// The loop also breaks, keeping the metrics, once the channel is closed
     ///
     /// # Arguments
     ///
@@ -102,53 +402,78 @@ impl ProbeNode {
     /// * `stop_probe_resp_rx` - receiver for stop probe channel dedicated to that probe
     ///
     async fn start(&mut self) {
+        let mut pacing = self.build_pacing();
         loop {
-            match memcached::connect(&self.cluster_name, &self.socket).await {
-                Ok(mut c_memcache) => loop {
-                    match self.stop_probe_resp_rx.try_recv() {
-                        Ok(_) | Err(TryRecvError::Closed) => {
-                            info!("Stop to probe node: {}:{}", self.cluster_name, self.socket);
-                            return self.stop();
-                        }
-                        Err(TryRecvError::Empty) => {
-                            if let Err(issue) = c_memcache.probe().await {
-                                self.manage_failure(issue);
-                                break;
-                            }
+            // The first probe of a connection is done right away
+            if let Some(pacing) = pacing.as_mut() {
+                pacing.drain();
+            }
+            self.heartbeat(TaskState::Connecting);
+            match self
+                .protocol
+                .connect(&self.cluster_name, &self.socket)
+                .await
+            {
+                Ok(mut connection) => loop {
+                    if self.stop_requested() {
+                        return;
+                    }
+                    self.heartbeat(TaskState::Probing);
+                    let probe_start = Instant::now();
+                    let probe_res = connection.probe().await;
+                    self.attempted(probe_res.is_ok());
+                    match probe_res {
+                        Ok(()) => self.manage_success(probe_start.elapsed()),
+                        Err(issue) => {
+                            self.manage_failure(issue);
+                            break;
                         }
                     }
-                    if self.interval_check_ms > 0 {
-                        sleep(Duration::from_millis(self.interval_check_ms)).await;
+                    self.heartbeat(TaskState::Sleeping);
+                    if let Some(pacing) = pacing.as_mut() {
+                        if let Err(issue) = pacing.acquire(1).await {
+                            warn!("Failed to pace the probes of {}: {}", self.key, issue);
+                            sleep(Duration::from_millis(self.interval_check_ms)).await;
+                        }
                     }
                 },
                 Err(issue) => {
+                    self.attempted(false);
                     self.manage_failure(issue);
                 }
             }
-            match self.stop_probe_resp_rx.try_recv() {
-                Ok(_) | Err(TryRecvError::Closed) => {
-                    info!("Stop to probe node: {}:{}", self.cluster_name, self.socket);
-                    return self.stop();
-                }
-                Err(TryRecvError::Empty) => {
-                    sleep(Duration::from_millis(500)).await;
-                }
+            if self.stop_requested() {
+                return;
             }
+            self.heartbeat(TaskState::Reconnecting);
+            NODE_RECONNECTS
+                .with_label_values(&[self.cluster_name.as_str(), self.socket.as_str()])
+                .inc();
+            sleep(Duration::from_millis(500)).await;
         }
     }
 }
 
 impl fmt::Display for ProbeNode {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}:{}:{}", self.cluster_name, self.ip, self.port)
+        f.write_str(&self.key)
     }
 }
 
 #[derive(Debug)]
 pub struct ProbeServices {
-    consul_client: ConsulClient,
-    tag: String,
     interval_check_ms: u64,
+    dedup_policy: DedupPolicy,
+    // Max number of nodes probed at the same time, 0 for unlimited
+    max_probed_nodes: usize,
+    // Protocol used to probe the discovered nodes without declared protocol
+    protocol: Protocol,
+    // Part of the discovered nodes probed by this prober
+    shard: Shard,
+    // Conditions stopping the probes
+    exit_policy: ExitPolicy,
+    // Rate limiter implementation spacing the probes of each node
+    rate_limiter: RateLimiterKind,
     probe_nodes: HashMap<String, oneshot::Sender<u8>>,
 }
 
@@ -158,21 +483,67 @@ impl ProbeServices {
     ///
     /// # Arguments
     ///
-    /// * `consul_client` - a consul client
-    /// * `tag` - tag needed on service to enable probing
     /// * `interval_check_ms` - interval between each check
+    /// * `dedup_policy` - policy for nodes registered under multiple matching services
+    /// * `max_probed_nodes` - max number of nodes probed at the same time, 0 for unlimited
+    /// * `protocol` - protocol used to probe the discovered nodes without declared protocol
     ///
     ///
-    pub fn new(consul_client: ConsulClient, tag: String, interval_check_ms: u64) -> ProbeServices {
-        debug!("Create a probe for services with tag {}", tag);
+    pub fn new(
+        interval_check_ms: u64,
+        dedup_policy: DedupPolicy,
+        max_probed_nodes: usize,
+        protocol: Protocol,
+    ) -> ProbeServices {
+        debug!(
+            "Create a {} probe with dedup policy {}",
+            protocol, dedup_policy
+        );
         ProbeServices {
-            consul_client,
-            tag,
             interval_check_ms,
+            dedup_policy,
+            max_probed_nodes,
+            protocol,
+            shard: Shard::default(),
+            exit_policy: ExitPolicy::default(),
+            rate_limiter: RateLimiterKind::default(),
             probe_nodes: HashMap::new(),
         }
     }
 
+    /// Probe only the discovered nodes of a shard
+    ///
+    /// # Arguments
+    ///
+    /// * `shard` - part of the discovered nodes probed by this prober
+    ///
+    pub fn with_shard(mut self, shard: Shard) -> Self {
+        self.shard = shard;
+        self
+    }
+
+    /// Stop watching the discovery per the exit policy
+    ///
+    /// # Arguments
+    ///
+    /// * `exit_policy` - conditions stopping the probes
+    ///
+    pub fn with_exit_policy(mut self, exit_policy: ExitPolicy) -> Self {
+        self.exit_policy = exit_policy;
+        self
+    }
+
+    /// Space the probes of each node with a rate limiter implementation
+    ///
+    /// # Arguments
+    ///
+    /// * `rate_limiter` - token-bucket or gcra, both allowing one probe per interval
+    ///
+    pub fn with_rate_limiter(mut self, rate_limiter: RateLimiterKind) -> Self {
+        self.rate_limiter = rate_limiter;
+        self
+    }
+
     /// Stop probing nodes that are not part of newly discovered nodes
     ///
     /// # Arguments
@@ -189,27 +560,35 @@ impl ProbeServices {
 
         for probe_node_to_stop in probe_nodes_to_stop.iter() {
             info!("Request to stop to probe node: {}", probe_node_to_stop);
+            READINESS.node_removed(probe_node_to_stop);
+            PROBER_STATE.node_stopped(probe_node_to_stop);
             match self.probe_nodes.remove(probe_node_to_stop) {
                 Some(stop_probe_resp_tx) => {
                     stop_probe_resp_tx.send(1).unwrap_or(());
+                    PROBES_STOPPED.inc();
                 }
                 None => warn!("Node {} is not a monitored node", probe_node_to_stop),
             }
         }
+        RUNNING_PROBES.set(self.probe_nodes.len() as i64);
     }
 
     async fn start_node_probe(
+        protocol: Protocol,
         service_node: ServiceNode,
         interval_check_ms: u64,
+        rate_limiter: RateLimiterKind,
         stop_probe_resp_rx: oneshot::Receiver<u8>,
     ) {
         ProbeNode::new(
+            protocol,
             service_node.service_name,
             service_node.ip,
             service_node.port,
             interval_check_ms,
             stop_probe_resp_rx,
         )
+        .with_rate_limiter(rate_limiter)
         .start()
         .await;
     }
@@ -222,77 +601,232 @@ impl ProbeServices {
     /// * `discovered_nodes` - hash of new nodes discovered in consul with matching tag
     ///
     fn start_nodes_probe(&mut self, discovered_nodes: &HashMap<String, ServiceNode>) {
+        let mut rejected_nodes = 0;
         for discovered_node in discovered_nodes.iter() {
             let key_node = discovered_node.0.as_str();
             let service_node = discovered_node.1;
             if !self.probe_nodes.contains_key(key_node) {
+                // Cap the cardinality of the per node metrics
+                if self.max_probed_nodes > 0 && self.probe_nodes.len() >= self.max_probed_nodes {
+                    READINESS.node_removed(key_node);
+                    PROBES_REJECTED.inc();
+                    rejected_nodes += 1;
+                    continue;
+                }
+
                 info!("Start to probe node: {}", key_node);
 
+                PROBER_STATE.node_started(
+                    key_node,
+                    &service_node.service_name,
+                    &format!("{}:{}", service_node.ip, service_node.port),
+                );
+
                 let (stop_probe_resp_tx, stop_probe_resp_rx) = oneshot::channel();
                 self.probe_nodes
                     .insert(key_node.to_string(), stop_probe_resp_tx);
 
                 tokio::spawn(ProbeServices::start_node_probe(
+                    service_node.protocol.unwrap_or(self.protocol),
                     (*service_node).clone(),
                     self.interval_check_ms,
+                    self.rate_limiter,
                     stop_probe_resp_rx,
                 ));
+                PROBES_STARTED.inc();
             }
         }
+        if rejected_nodes > 0 {
+            warn!(
+                "Max probed nodes {} reached, {} nodes are not probed",
+                self.max_probed_nodes, rejected_nodes
+            );
+        }
+        RUNNING_PROBES.set(self.probe_nodes.len() as i64);
     }
 
-    /// Manage services/nodes discovery from consul
+    /// Apply the dedup policy on newly discovered nodes
     /// and call for probes to stop and add
-    pub async fn watch_matching_services(
-        &mut self,
-    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        let mut token_bucket = TokenBucket::new(180, 1);
-        let mut index = 0;
+    ///
+    /// # Arguments
+    ///
+    /// * `discovered_nodes` - services and nodes of a successful discovery
+    ///
+    fn sync_discovered_nodes(&mut self, discovered_nodes: ServiceNodes) {
+        DISCOVERED_SERVICES.set(discovered_nodes.services.len() as i64);
+        let nodes = self.dedup_policy.apply(discovered_nodes.nodes);
+        DISCOVERED_NODES.set(nodes.len() as i64);
+        let nodes = self.shard.apply(nodes);
 
+        PROBER_STATE.discovery_succeeded();
+        READINESS.discovered(nodes.keys());
+        // Stop first to free slots when the max probed nodes is reached
+        self.stop_nodes_probe(&nodes);
+        self.start_nodes_probe(&nodes);
+    }
+
+    /// Sync the probes with each snapshot of the nodes to probe
+    ///
+    /// Probes are kept untouched while the discovery fails
+    ///
+    /// # Arguments
+    ///
+    /// * `discovery` - source of the nodes to probe
+    ///
+    /// # Return
+    ///
+    /// * Error once the discovery has been failing for longer than allowed by the exit policy
+    ///
+    pub async fn watch(&mut self, discovery: &mut dyn Discovery) -> Result<(), ExitError> {
+        // Pinged alongside the discovery so that a wedged loop gets restarted by systemd
+        let mut watchdog = systemd::Watchdog::from_env();
+        let mut failing_since: Option<Instant> = None;
         loop {
-            token_bucket.wait_for(60).await?;
+            let next_snapshot = discovery.next_snapshot();
+            tokio::pin!(next_snapshot);
+            let snapshot = loop {
+                tokio::select! {
+                    snapshot = &mut next_snapshot => break snapshot,
+                    _ = watchdog.ping() => {}
+                }
+            };
 
-            match self
-                .consul_client
-                .list_matching_nodes(index, &self.tag)
-                .await
-            {
+            match snapshot {
                 Ok(discovered_nodes) => {
-                    index = discovered_nodes.index;
-
-                    self.start_nodes_probe(&discovered_nodes.nodes);
-                    self.stop_nodes_probe(&discovered_nodes.nodes);
+                    failing_since = None;
+                    self.sync_discovered_nodes(discovered_nodes);
+                    systemd::discovered();
                 }
                 Err(err) => {
-                    index = 0;
-
                     FAILURE_SERVICES_DISCOVERY.inc();
-                    error!("Failed to sync services: {}", err);
+                    statsd::count("failure_services_discovery", &[], 1);
+                    error!("Failed to discover nodes from {}: {}", discovery, err);
+                    let failing_for = failing_since.get_or_insert_with(Instant::now).elapsed();
+                    if let Some(discovery_failure) = self.exit_policy.discovery_failure {
+                        if failing_for >= discovery_failure {
+                            return Err(ExitError::DiscoveryFailure(failing_for));
+                        }
+                    }
                 }
-            };
+            }
         }
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use tokio::sync::oneshot;
     use tokio::sync::oneshot::Sender;
 
+    use std::collections::{HashMap, VecDeque};
+    use std::fmt;
+
+    use prometheus::core::Collector;
+    #[cfg(feature = "memcached")]
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    #[cfg(feature = "memcached")]
+    use tokio::net::TcpListener;
+
+    #[cfg(feature = "memcached")]
     use crate::memcached::MemcachedClientError;
-    use crate::probes::prometheus::{FAILURE_PROBE, NUMBER_OF_REQUESTS};
-    use crate::probes::ProbeNode;
+    use crate::probes::dedup::DedupPolicy;
+    use crate::probes::discovery::{
+        Discovery, DiscoveryError, DiscoveryFuture, ServiceNode, ServiceNodes,
+    };
+    use crate::probes::exit::{ExitError, ExitPolicy};
+    use crate::probes::prometheus::{
+        FAILURE_PROBE, NUMBER_OF_REQUESTS, PROBES_REJECTED, PROBES_STARTED, PROBES_STOPPED,
+        PROBE_LAST_SUCCESS, PROBE_NODE_UP, RUNNING_PROBES,
+    };
+    use crate::probes::protocol::Protocol;
+    use crate::probes::shard::Shard;
+    use crate::probes::{discover_targets, init_probing, ProbeNode, ProbeServices};
+    use crate::token_bucket::RateLimiterKind;
 
+    // Discovery returning each snapshot once then waiting forever
+    struct MockDiscovery {
+        snapshots: VecDeque<ServiceNodes>,
+    }
+
+    impl fmt::Display for MockDiscovery {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "mock")
+        }
+    }
+
+    impl Discovery for MockDiscovery {
+        fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+            Box::pin(async move {
+                match self.snapshots.pop_front() {
+                    Some(snapshot) => Ok(snapshot),
+                    None => std::future::pending().await,
+                }
+            })
+        }
+    }
+
+    // Discovery always failing after a short wait
+    struct FailingDiscovery;
+
+    impl fmt::Display for FailingDiscovery {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "failing")
+        }
+    }
+
+    impl Discovery for FailingDiscovery {
+        fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+            Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+                Err(DiscoveryError::SourcesStopped)
+            })
+        }
+    }
+
+    fn snapshot(ports: &[u16]) -> ServiceNodes {
+        ServiceNodes {
+            index: 0,
+            services: vec!["watched".to_string()],
+            nodes: ports
+                .iter()
+                .map(|port| {
+                    let service_node = ServiceNode {
+                        service_name: "watched".to_string(),
+                        ip: "ip".to_string(),
+                        port: *port,
+                        protocol: None,
+                    };
+                    (service_node.to_string(), service_node)
+                })
+                .collect(),
+        }
+    }
+
+    #[cfg(feature = "memcached")]
     fn return_error() -> Result<(), MemcachedClientError> {
         Err(MemcachedClientError::EmptyOrIncompleteResponse)
     }
 
-    fn get_probe() -> (ProbeNode, Sender<u8>) {
+    // Returns true if a series of the metric is gathered for the cluster
+    fn gathered(metric: &impl Collector, cluster_name: &str) -> bool {
+        metric.collect().iter().any(|metric_family| {
+            metric_family.get_metric().iter().any(|metric| {
+                metric.get_label().iter().any(|label| {
+                    label.get_name() == "cluster_name" && label.get_value() == cluster_name
+                })
+            })
+        })
+    }
+
+    fn get_probe(cluster_name: &str) -> (ProbeNode, Sender<u8>) {
         let (stop_probe_resp_tx, stop_probe_resp_rx) = oneshot::channel();
 
         (
             ProbeNode::new(
-                "cluster_name".to_string(),
+                Protocol::Memcached,
+                cluster_name.to_string(),
                 "ip".to_string(),
                 0,
                 1,
@@ -316,7 +850,7 @@ mod tests {
                 .get()
         );
 
-        get_probe().0.stop();
+        get_probe("cluster_name").0.stop();
 
         assert_eq!(
             0,
@@ -327,6 +861,7 @@ mod tests {
         );
     }
 
+    #[cfg(feature = "memcached")]
     #[test]
     fn probe_manage_failure() {
         assert_eq!(
@@ -336,7 +871,9 @@ mod tests {
                 .unwrap()
                 .get()
         );
-        get_probe().0.manage_failure(return_error().err().unwrap());
+        get_probe("cluster_name")
+            .0
+            .manage_failure(return_error().err().unwrap());
 
         assert_eq!(
             1,
@@ -345,5 +882,240 @@ mod tests {
                 .unwrap()
                 .get()
         );
+        assert_eq!(
+            0,
+            PROBE_NODE_UP
+                .get_metric_with_label_values(&["cluster_name", "ip:0"])
+                .unwrap()
+                .get()
+        );
+    }
+
+    #[test]
+    fn probe_manage_success() {
+        let (mut probe, _stop_probe_resp_tx) = get_probe("cluster_success");
+        probe.manage_success(Duration::from_millis(1));
+
+        assert_eq!(
+            1,
+            PROBE_NODE_UP
+                .get_metric_with_label_values(&["cluster_success", "ip:0"])
+                .unwrap()
+                .get()
+        );
+        assert!(
+            PROBE_LAST_SUCCESS
+                .get_metric_with_label_values(&["cluster_success", "ip:0"])
+                .unwrap()
+                .get()
+                > 0.0
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_pacing() {
+        let (probe, _stop_probe_resp_tx) = get_probe("cluster_pacing");
+        let pacing = probe
+            .with_rate_limiter(RateLimiterKind::Gcra)
+            .build_pacing()
+            .expect("Probes with an interval are paced");
+        assert_eq!(1000.0, pacing.quantum());
+    }
+
+    #[test]
+    fn probe_series_resolved_again_after_stop() {
+        let (mut probe, _stop_probe_resp_tx) = get_probe("cluster_resolved");
+        probe.manage_failure("refused");
+        probe.stop();
+        probe.manage_failure("refused");
+
+        assert_eq!(
+            1,
+            FAILURE_PROBE
+                .get_metric_with_label_values(&["cluster_resolved", "ip:0"])
+                .unwrap()
+                .get()
+        );
+    }
+
+    #[cfg(feature = "memcached")]
+    #[tokio::test]
+    async fn probe_series_kept_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            // Answer each set and get with NoError, then fail the next probe
+            for _ in 0..2 {
+                let mut buffer = [0; 2048];
+                socket.read(&mut buffer).await.unwrap();
+                let response = hex::decode("810000000000000000000000000000000000000000000000")
+                    .expect("Decoding failed");
+                socket.write_all(&response).await.unwrap();
+            }
+        });
+
+        let service_node = ServiceNode {
+            service_name: "drained".to_string(),
+            ip: "127.0.0.1".to_string(),
+            port,
+            protocol: None,
+        };
+        let discovery = MockDiscovery {
+            snapshots: VecDeque::from([ServiceNodes {
+                index: 0,
+                services: vec!["drained".to_string()],
+                nodes: HashMap::from([(service_node.to_string(), service_node)]),
+            }]),
+        };
+        let probing = tokio::time::timeout(
+            Duration::from_millis(200),
+            init_probing(
+                Box::new(discovery),
+                ProbeServices::new(10, DedupPolicy::Disabled, 0, Protocol::Memcached),
+                Duration::ZERO,
+            ),
+        );
+        assert!(probing.await.is_err());
+
+        // Let the probe task see the closed channel once its reconnection delay is over
+        tokio::time::sleep(Duration::from_millis(700)).await;
+        assert!(gathered(&*FAILURE_PROBE, "drained"));
+        assert!(gathered(&*PROBE_NODE_UP, "drained"));
+        assert!(gathered(&*PROBE_LAST_SUCCESS, "drained"));
+    }
+
+    #[tokio::test]
+    async fn probe_services_inventory() {
+        let mut probe_services =
+            ProbeServices::new(1000, DedupPolicy::Disabled, 0, Protocol::Memcached);
+        let probes_started = PROBES_STARTED.get();
+        let probes_stopped = PROBES_STOPPED.get();
+
+        let service_node = ServiceNode {
+            service_name: "inventory".to_string(),
+            ip: "ip".to_string(),
+            port: 0,
+            protocol: None,
+        };
+        let nodes = HashMap::from([(service_node.to_string(), service_node)]);
+        probe_services.start_nodes_probe(&nodes);
+        probe_services.start_nodes_probe(&nodes);
+        assert_eq!(probes_started + 1, PROBES_STARTED.get());
+        assert_eq!(1, RUNNING_PROBES.get());
+
+        probe_services.stop_nodes_probe(&HashMap::new());
+        assert_eq!(probes_stopped + 1, PROBES_STOPPED.get());
+        assert_eq!(0, RUNNING_PROBES.get());
+
+        // Nodes above the max probed nodes are rejected
+        let mut probe_services =
+            ProbeServices::new(1000, DedupPolicy::Disabled, 1, Protocol::Memcached);
+        let probes_rejected = PROBES_REJECTED.get();
+
+        let nodes = (0..2)
+            .map(|port| {
+                let service_node = ServiceNode {
+                    service_name: "max_probed".to_string(),
+                    ip: "ip".to_string(),
+                    port,
+                    protocol: None,
+                };
+                (service_node.to_string(), service_node)
+            })
+            .collect::<HashMap<String, ServiceNode>>();
+        probe_services.start_nodes_probe(&nodes);
+        assert_eq!(1, probe_services.probe_nodes.len());
+        assert_eq!(probes_rejected + 1, PROBES_REJECTED.get());
+
+        probe_services.stop_nodes_probe(&HashMap::new());
+
+        // Probes follow the snapshots of the discovery
+        let mut probe_services =
+            ProbeServices::new(1000, DedupPolicy::Disabled, 0, Protocol::Memcached);
+        let mut discovery = MockDiscovery {
+            snapshots: VecDeque::from([snapshot(&[0, 1]), snapshot(&[1, 2, 3])]),
+        };
+        let watch = tokio::time::timeout(
+            Duration::from_millis(100),
+            probe_services.watch(&mut discovery),
+        );
+        assert!(watch.await.is_err());
+        let mut probed_nodes = probe_services
+            .probe_nodes
+            .keys()
+            .cloned()
+            .collect::<Vec<_>>();
+        probed_nodes.sort();
+        assert_eq!(
+            vec!["watched:ip:1", "watched:ip:2", "watched:ip:3"],
+            probed_nodes
+        );
+
+        probe_services.stop_nodes_probe(&HashMap::new());
+    }
+
+    #[tokio::test]
+    async fn discover_targets_once() {
+        let mut discovered_nodes = snapshot(&[2, 1]);
+        let service_node = ServiceNode {
+            service_name: "declared".to_string(),
+            ip: "ip".to_string(),
+            port: 3,
+            protocol: Some(Protocol::Redis),
+        };
+        discovered_nodes
+            .nodes
+            .insert(service_node.to_string(), service_node);
+        let mut discovery = MockDiscovery {
+            snapshots: VecDeque::from([discovered_nodes]),
+        };
+
+        let targets = discover_targets(
+            &mut discovery,
+            DedupPolicy::Disabled,
+            Protocol::Memcached,
+            Shard::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            serde_json::json!({
+                "services": ["watched"],
+                "targets": [
+                    {"key": "declared:ip:3", "service": "declared", "ip": "ip", "port": 3, "protocol": "redis"},
+                    {"key": "watched:ip:1", "service": "watched", "ip": "ip", "port": 1, "protocol": "memcached"},
+                    {"key": "watched:ip:2", "service": "watched", "ip": "ip", "port": 2, "protocol": "memcached"},
+                ],
+            }),
+            targets
+        );
+    }
+
+    #[tokio::test]
+    async fn watch_exits_on_discovery_failure() {
+        let mut probe_services =
+            ProbeServices::new(1000, DedupPolicy::Disabled, 0, Protocol::Memcached)
+                .with_exit_policy(ExitPolicy {
+                    discovery_failure: Some(Duration::from_millis(30)),
+                    ..ExitPolicy::default()
+                });
+        let watch_res = tokio::time::timeout(
+            Duration::from_secs(1),
+            probe_services.watch(&mut FailingDiscovery),
+        )
+        .await
+        .expect("Discovery failure should stop the watch");
+        assert!(matches!(watch_res, Err(ExitError::DiscoveryFailure(_))));
+
+        // Retried forever by default
+        let mut probe_services =
+            ProbeServices::new(1000, DedupPolicy::Disabled, 0, Protocol::Memcached);
+        assert!(tokio::time::timeout(
+            Duration::from_millis(100),
+            probe_services.watch(&mut FailingDiscovery),
+        )
+        .await
+        .is_err());
     }
 }
@@ -0,0 +1,397 @@
+//! Encoding of the gathered metrics in the OpenMetrics text format
+//!
+//! Native histograms are not implemented, histograms are only exposed with their classic buckets
+
+use std::fmt::{Result, Write};
+
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+
+use crate::probes::exemplars::{Exemplar, EXEMPLARS};
+
+pub const OPENMETRICS_CONTENT_TYPE: &str =
+    "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Check if the client accepts the OpenMetrics exposition format
+///
+/// # Arguments
+///
+/// * `accept` - value of the Accept http header
+///
+/// # Return
+///
+/// * bool - true if OpenMetrics format has been requested
+///
+pub fn accept_openmetrics(accept: &str) -> bool {
+    accept.split(',').any(|media_range| {
+        media_range
+            .trim()
+            .starts_with("application/openmetrics-text")
+    })
+}
+
+/// Encode metric families in the OpenMetrics text format
+///
+/// Histograms are encoded with their classic buckets, the text format having no native histograms
+///
+/// # Arguments
+///
+/// * `metric_families` - gathered metric families
+/// * `static_labels` - labels added to the gathered metrics, exemplars being recorded without them
+/// * `writer` - output of the encoding
+///
+pub fn encode(
+    metric_families: &[MetricFamily],
+    static_labels: &[(String, String)],
+    writer: &mut String,
+) -> Result {
+    for metric_family in metric_families {
+        encode_metric_family(metric_family, static_labels, writer)?;
+    }
+    writer.write_str("# EOF\n")
+}
+
+fn encode_metric_family(
+    metric_family: &MetricFamily,
+    static_labels: &[(String, String)],
+    writer: &mut String,
+) -> Result {
+    let metric_type = metric_family.get_field_type();
+    // Counter family name must not contain the _total suffix of its samples
+    let name = match metric_type {
+        MetricType::COUNTER => metric_family
+            .get_name()
+            .strip_suffix("_total")
+            .unwrap_or(metric_family.get_name()),
+        _ => metric_family.get_name(),
+    };
+
+    let type_str = match metric_type {
+        MetricType::COUNTER => "counter",
+        MetricType::GAUGE => "gauge",
+        MetricType::HISTOGRAM => "histogram",
+        MetricType::SUMMARY => "summary",
+        MetricType::UNTYPED => "unknown",
+    };
+    writeln!(writer, "# TYPE {name} {type_str}")?;
+    if !metric_family.get_help().is_empty() {
+        writeln!(
+            writer,
+            "# HELP {} {}",
+            name,
+            escape(metric_family.get_help(), false)
+        )?;
+    }
+
+    for metric in metric_family.get_metric() {
+        let labels = metric.get_label();
+        match metric_type {
+            MetricType::COUNTER => {
+                write_sample(
+                    writer,
+                    name,
+                    "_total",
+                    labels,
+                    None,
+                    metric.get_counter().get_value(),
+                )?;
+            }
+            MetricType::GAUGE => {
+                write_sample(
+                    writer,
+                    name,
+                    "",
+                    labels,
+                    None,
+                    metric.get_gauge().get_value(),
+                )?;
+            }
+            MetricType::UNTYPED => {
+                write_sample(
+                    writer,
+                    name,
+                    "",
+                    labels,
+                    None,
+                    metric.get_untyped().get_value(),
+                )?;
+            }
+            MetricType::HISTOGRAM => {
+                let histogram = metric.get_histogram();
+                let exemplar_labels = labels
+                    .iter()
+                    .filter(|label| {
+                        !static_labels.iter().any(|(name, value)| {
+                            label.get_name() == name && label.get_value() == value
+                        })
+                    })
+                    .cloned()
+                    .collect::<Vec<LabelPair>>();
+                let mut inf_seen = false;
+                for (bucket_index, bucket) in histogram.get_bucket().iter().enumerate() {
+                    let upper_bound = bucket.get_upper_bound();
+                    inf_seen |= upper_bound == f64::INFINITY;
+                    write_sample_with_exemplar(
+                        writer,
+                        name,
+                        "_bucket",
+                        labels,
+                        Some(("le", format_bound(upper_bound))),
+                        bucket.get_cumulative_count() as f64,
+                        EXEMPLARS.get(name, &exemplar_labels, bucket_index),
+                    )?;
+                }
+                if !inf_seen {
+                    write_sample_with_exemplar(
+                        writer,
+                        name,
+                        "_bucket",
+                        labels,
+                        Some(("le", "+Inf".to_string())),
+                        histogram.get_sample_count() as f64,
+                        EXEMPLARS.get(name, &exemplar_labels, histogram.get_bucket().len()),
+                    )?;
+                }
+                write_sample(
+                    writer,
+                    name,
+                    "_count",
+                    labels,
+                    None,
+                    histogram.get_sample_count() as f64,
+                )?;
+                write_sample(
+                    writer,
+                    name,
+                    "_sum",
+                    labels,
+                    None,
+                    histogram.get_sample_sum(),
+                )?;
+            }
+            MetricType::SUMMARY => {
+                let summary = metric.get_summary();
+                for quantile in summary.get_quantile() {
+                    write_sample(
+                        writer,
+                        name,
+                        "",
+                        labels,
+                        Some(("quantile", format_bound(quantile.get_quantile()))),
+                        quantile.get_value(),
+                    )?;
+                }
+                write_sample(
+                    writer,
+                    name,
+                    "_count",
+                    labels,
+                    None,
+                    summary.get_sample_count() as f64,
+                )?;
+                write_sample(writer, name, "_sum", labels, None, summary.get_sample_sum())?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn write_sample(
+    writer: &mut String,
+    name: &str,
+    suffix: &str,
+    labels: &[LabelPair],
+    additional_label: Option<(&str, String)>,
+    value: f64,
+) -> Result {
+    write_sample_with_exemplar(writer, name, suffix, labels, additional_label, value, None)
+}
+
+fn write_sample_with_exemplar(
+    writer: &mut String,
+    name: &str,
+    suffix: &str,
+    labels: &[LabelPair],
+    additional_label: Option<(&str, String)>,
+    value: f64,
+    exemplar: Option<Exemplar>,
+) -> Result {
+    writer.write_str(name)?;
+    writer.write_str(suffix)?;
+
+    let mut label_strs = labels
+        .iter()
+        .map(|label| {
+            format!(
+                "{}=\"{}\"",
+                label.get_name(),
+                escape(label.get_value(), true)
+            )
+        })
+        .collect::<Vec<String>>();
+    if let Some((label_name, label_value)) = additional_label {
+        label_strs.push(format!("{label_name}=\"{label_value}\""));
+    }
+    if !label_strs.is_empty() {
+        write!(writer, "{{{}}}", label_strs.join(","))?;
+    }
+
+    write!(writer, " {}", format_float(value))?;
+    if let Some(exemplar) = exemplar {
+        write!(
+            writer,
+            " # {{trace_id=\"{}\"}} {} {}",
+            exemplar.trace_id,
+            format_float(exemplar.value),
+            exemplar.timestamp
+        )?;
+    }
+    writeln!(writer)
+}
+
+fn format_float(value: f64) -> String {
+    if value == f64::INFINITY {
+        "+Inf".to_string()
+    } else if value == f64::NEG_INFINITY {
+        "-Inf".to_string()
+    } else if value.is_nan() {
+        "NaN".to_string()
+    } else {
+        value.to_string()
+    }
+}
+
+/// Format a bucket bound or a quantile as a float, as canonical in OpenMetrics label values
+fn format_bound(value: f64) -> String {
+    if value.is_finite() && value.fract() == 0.0 {
+        format!("{value:.1}")
+    } else {
+        format_float(value)
+    }
+}
+
+fn escape(value: &str, include_double_quote: bool) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '"' if include_double_quote => escaped.push_str("\\\""),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{Counter, Histogram, HistogramOpts, Opts, Registry};
+
+    use crate::probes::exemplars::EXEMPLARS;
+    use crate::probes::openmetrics::{accept_openmetrics, encode};
+    use crate::probes::prometheus::add_static_labels;
+
+    #[test]
+    fn test_accept_openmetrics() {
+        assert!(accept_openmetrics(
+            "application/openmetrics-text;version=1.0.0,text/plain;version=0.0.4;q=0.5"
+        ));
+        assert!(!accept_openmetrics("text/plain;version=0.0.4"));
+        assert!(!accept_openmetrics(""));
+    }
+
+    #[test]
+    fn test_encode() {
+        let registry = Registry::new();
+        let counter = Counter::with_opts(
+            Opts::new("test_counter_total", "Help \\ test").const_label("label", "a\"b"),
+        )
+        .unwrap();
+        counter.inc();
+        registry.register(Box::new(counter)).unwrap();
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_histogram", "Histogram").buckets(vec![0.5, 1.0]),
+        )
+        .unwrap();
+        histogram.observe(0.75);
+        registry.register(Box::new(histogram)).unwrap();
+
+        let mut output = String::new();
+        encode(&registry.gather(), &[], &mut output).unwrap();
+
+        assert_eq!(
+            "# TYPE test_counter counter\n\
+            # HELP test_counter Help \\\\ test\n\
+            test_counter_total{label=\"a\\\"b\"} 1\n\
+            # TYPE test_histogram histogram\n\
+            # HELP test_histogram Histogram\n\
+            test_histogram_bucket{le=\"0.5\"} 0\n\
+            test_histogram_bucket{le=\"1.0\"} 1\n\
+            test_histogram_bucket{le=\"+Inf\"} 1\n\
+            test_histogram_count 1\n\
+            test_histogram_sum 0.75\n\
+            # EOF\n",
+            output
+        );
+    }
+
+    #[test]
+    fn test_encode_exemplar() {
+        let registry = Registry::new();
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_exemplar_histogram", "Histogram")
+                .const_label("socket", "ip:0")
+                .buckets(vec![0.5]),
+        )
+        .unwrap();
+        histogram.observe(0.75);
+        registry.register(Box::new(histogram)).unwrap();
+        EXEMPLARS.record(
+            "test_exemplar_histogram",
+            &[("socket", "ip:0")],
+            &[0.5],
+            0.75,
+            "0af7651916cd43dd8448eb211c80319c".to_string(),
+        );
+
+        let mut output = String::new();
+        encode(&registry.gather(), &[], &mut output).unwrap();
+
+        assert!(output.contains("test_exemplar_histogram_bucket{socket=\"ip:0\",le=\"0.5\"} 0\n"));
+        assert!(output.contains(
+            "test_exemplar_histogram_bucket{socket=\"ip:0\",le=\"+Inf\"} 1 \
+            # {trace_id=\"0af7651916cd43dd8448eb211c80319c\"} 0.75 "
+        ));
+    }
+
+    #[test]
+    fn test_encode_exemplar_static_labels() {
+        let registry = Registry::new();
+        let histogram = Histogram::with_opts(
+            HistogramOpts::new("test_static_exemplar_histogram", "Histogram")
+                .const_label("socket", "ip:0")
+                .buckets(vec![0.5]),
+        )
+        .unwrap();
+        histogram.observe(0.25);
+        registry.register(Box::new(histogram)).unwrap();
+        EXEMPLARS.record(
+            "test_static_exemplar_histogram",
+            &[("socket", "ip:0")],
+            &[0.5],
+            0.25,
+            "0af7651916cd43dd8448eb211c80319c".to_string(),
+        );
+        let static_labels = vec![("dc".to_string(), "eu-west".to_string())];
+        let mut metric_families = registry.gather();
+        add_static_labels(&mut metric_families, &static_labels);
+
+        let mut output = String::new();
+        encode(&metric_families, &static_labels, &mut output).unwrap();
+
+        assert!(output.contains(
+            "test_static_exemplar_histogram_bucket{dc=\"eu-west\",socket=\"ip:0\",le=\"0.5\"} 1 \
+            # {trace_id=\"0af7651916cd43dd8448eb211c80319c\"} 0.25 "
+        ));
+    }
+}
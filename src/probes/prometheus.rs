@@ -1,14 +1,85 @@
-use std::net::SocketAddr;
+use std::cell::Cell;
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::OnceLock;
+use std::time::Duration;
 
-use axum::http::StatusCode;
-use axum::routing::get;
-use axum::Router;
+use axum::extract::State;
+use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+use axum::middleware;
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use axum_server::tls_rustls::RustlsConfig;
+use axum_server::Handle;
 use lazy_static::lazy_static;
+use prometheus::core::{Collector, Desc};
+use prometheus::proto::{LabelPair, MetricFamily};
 use prometheus::{
-    register_histogram_vec, register_int_counter, register_int_counter_vec, HistogramOpts,
-    HistogramVec, IntCounter, IntCounterVec, Opts,
+    register_gauge, register_gauge_vec, register_histogram, register_histogram_vec,
+    register_int_counter, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Gauge, GaugeVec, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts,
 };
-use tracing::{error, info};
+use serde_json::{json, Value};
+use socket2::{Domain, Socket, Type};
+use tokio::sync::oneshot;
+use tower_http::compression::CompressionLayer;
+use tower_http::trace::{DefaultMakeSpan, DefaultOnResponse, TraceLayer};
+use tower_http::LatencyUnit;
+use tracing::{error, info, Level};
+
+use crate::probes::auth::{auth_middleware, HttpAuth};
+use crate::probes::openmetrics;
+use crate::probes::openmetrics::OPENMETRICS_CONTENT_TYPE;
+use crate::probes::quantiles;
+use crate::probes::readiness::READINESS;
+use crate::probes::state::PROBER_STATE;
+use crate::probes::systemd;
+
+pub const DEFAULT_RESPONSE_TIME_BUCKETS: [f64; 16] = [
+    0.00001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5,
+    5.0, 10.0,
+];
+
+// Consul blocking queries wait up to 5 minutes for a change
+const CONSUL_WATCH_DURATION_BUCKETS: [f64; 12] = [
+    0.01, 0.05, 0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0,
+];
+
+// Max time to wait for the tokio runtime to run a task on healthz check
+const RUNTIME_CHECK_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Max time to wait for in-flight requests on shutdown
+const GRACEFUL_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+// Backlog of pending connections on the listener of the webserver
+const LISTEN_BACKLOG: i32 = 1024;
+
+// Share the port of the webserver with the next prober on upgrades, only set once from main
+static HTTP_REUSE_PORT: OnceLock<bool> = OnceLock::new();
+
+// Retry to bind the port of the webserver instead of failing, only set once from main
+static HTTP_BIND_RETRY: OnceLock<bool> = OnceLock::new();
+
+// Listening address of the healthz and ready endpoints, served with the metrics if none,
+// only set once from main
+static HEALTH_HTTP_ADDR: OnceLock<Option<SocketAddr>> = OnceLock::new();
+
+// Delay between each attempt to bind the port of the webserver
+const HTTP_BIND_RETRY_DELAY: Duration = Duration::from_secs(5);
+
+// Effective configuration of the prober served on /config
+static EFFECTIVE_CONFIG: OnceLock<Value> = OnceLock::new();
+
+// Bumped each time series of nodes are removed, invalidating the series resolved by probes
+static SERIES_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Labels added to all exported metrics
+static STATIC_LABELS: OnceLock<Vec<(String, String)>> = OnceLock::new();
+
+// Buckets of the response time histogram, must be set before the first probe
+static RESPONSE_TIME_BUCKETS: OnceLock<Vec<f64>> = OnceLock::new();
 
 lazy_static! {
     pub static ref NUMBER_OF_REQUESTS: IntCounterVec = register_int_counter_vec!(
@@ -17,10 +88,21 @@ lazy_static! {
     )
     .expect("metric can be created");
     pub static ref RESPONSE_TIME_COLLECTOR: HistogramVec = register_histogram_vec!(
-        HistogramOpts::new("response_time_seconds", "Response Times").buckets(vec![
-            0.00001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0,
-            2.5, 5.0, 10.0,
-        ]),
+        HistogramOpts::new("response_time_seconds", "Response Times")
+            .buckets(response_time_buckets().to_vec()),
+        &["cluster_name", "socket", "type"]
+    )
+    .expect("metric can be created");
+    pub static ref BYTES_SENT: IntCounterVec = register_int_counter_vec!(
+        Opts::new("bytes_sent", "Number of bytes sent by probe requests"),
+        &["cluster_name", "socket", "type"]
+    )
+    .expect("metric can be created");
+    pub static ref BYTES_RECEIVED: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "bytes_received",
+            "Number of bytes received in probe responses"
+        ),
         &["cluster_name", "socket", "type"]
     )
     .expect("metric can be created");
@@ -34,32 +116,634 @@ lazy_static! {
         &["cluster_name", "socket"]
     )
     .expect("metric can be created");
+    pub static ref FAILURE_REMOTE_WRITE: IntCounter = register_int_counter!(
+        "failure_remote_write",
+        "Number of failed pushes to the remote write endpoint"
+    )
+    .expect("metric can be created");
+    pub static ref BUILD_INFO: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "mempoke_build_info",
+            "Build information of the prober, value is always 1"
+        ),
+        &["version", "commit", "rustc"]
+    )
+    .expect("metric can be created");
+    pub static ref PROBE_NODE_UP: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "probe_node_up",
+            "Whether the last probe of the node succeeded (1) or failed (0)"
+        ),
+        &["cluster_name", "socket"]
+    )
+    .expect("metric can be created");
+    pub static ref PROBE_LAST_SUCCESS: GaugeVec = register_gauge_vec!(
+        Opts::new(
+            "probe_last_success_timestamp_seconds",
+            "Unix time of the last successful probe of the node"
+        ),
+        &["cluster_name", "socket"]
+    )
+    .expect("metric can be created");
+    pub static ref NODE_RECONNECTS: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "node_reconnects",
+            "Number of reconnections to the node after a failure"
+        ),
+        &["cluster_name", "socket"]
+    )
+    .expect("metric can be created");
+    pub static ref NODE_ROLE: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "node_role",
+            "Whether the node currently has the role (1) or not (0)"
+        ),
+        &["cluster_name", "socket", "role"]
+    )
+    .expect("metric can be created");
+    pub static ref NODE_DISTRIBUTION: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "node_distribution",
+            "Distribution and version reported by the node"
+        ),
+        &["cluster_name", "socket", "distribution", "version"]
+    )
+    .expect("metric can be created");
+    pub static ref CLUSTER_HEALTH: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "cluster_health",
+            "Whether the cluster reported by the node has the health status (1) or not (0)"
+        ),
+        &["cluster_name", "socket", "status"]
+    )
+    .expect("metric can be created");
+    pub static ref BACKEND_SERVERS: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "backend_servers",
+            "Number of servers of a backend of the load balancer node by state"
+        ),
+        &["cluster_name", "socket", "backend", "state"]
+    )
+    .expect("metric can be created");
+    pub static ref BACKEND_QUEUE_DEPTH: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "backend_queue_depth",
+            "Number of requests queued on a backend of the load balancer node"
+        ),
+        &["cluster_name", "socket", "backend"]
+    )
+    .expect("metric can be created");
+    pub static ref NODE_STARTTLS: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "node_starttls",
+            "Whether the mail relay node advertises STARTTLS (1) or not (0)"
+        ),
+        &["cluster_name", "socket"]
+    )
+    .expect("metric can be created");
+    pub static ref DISCOVERED_SERVICES: IntGauge = register_int_gauge!(
+        "discovered_services",
+        "Number of services matching the probing tag on last discovery"
+    )
+    .expect("metric can be created");
+    pub static ref DISCOVERED_NODES: IntGauge = register_int_gauge!(
+        "discovered_nodes",
+        "Number of nodes to probe on last discovery"
+    )
+    .expect("metric can be created");
+    pub static ref DISCOVERY_SOURCE_NODES: IntGaugeVec = register_int_gauge_vec!(
+        Opts::new(
+            "discovery_source_nodes",
+            "Number of nodes to probe taken from each discovery source on last discovery"
+        ),
+        &["source"]
+    )
+    .expect("metric can be created");
+    pub static ref FAILURE_DISCOVERY_SOURCE: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "failure_discovery_source",
+            "Number of discovery failed per discovery source"
+        ),
+        &["source"]
+    )
+    .expect("metric can be created");
+    pub static ref HTTP_CLIENT_CONNECTIONS: IntCounter = register_int_counter!(
+        "http_client_connections",
+        "Number of connections opened by the http client of the discovery"
+    )
+    .expect("metric can be created");
+    pub static ref HTTP_CLIENT_OPEN_CONNECTIONS: IntGauge = register_int_gauge!(
+        "http_client_open_connections",
+        "Number of connections currently open in the pool of the http client of the discovery"
+    )
+    .expect("metric can be created");
+    pub static ref RUNNING_PROBES: IntGauge =
+        register_int_gauge!("running_probes", "Number of nodes currently probed")
+            .expect("metric can be created");
+    pub static ref PROBES_STARTED: IntCounter =
+        register_int_counter!("probes_started", "Number of probes started")
+            .expect("metric can be created");
+    pub static ref PROBES_STOPPED: IntCounter =
+        register_int_counter!("probes_stopped", "Number of probes stopped")
+            .expect("metric can be created");
+    pub static ref PROBES_REJECTED: IntCounter = register_int_counter!(
+        "probes_rejected",
+        "Number of nodes not probed due to the max probed nodes limit"
+    )
+    .expect("metric can be created");
+    pub static ref EXPIRED_NODE_SERIES: IntCounter = register_int_counter!(
+        "expired_node_series",
+        "Number of nodes for which metrics expired as they have not been probed recently"
+    )
+    .expect("metric can be created");
+    pub static ref CONSUL_WATCH_INDEX: IntGauge = register_int_gauge!(
+        "consul_watch_index",
+        "Index used by the next consul blocking query"
+    )
+    .expect("metric can be created");
+    pub static ref CONSUL_WATCH_INDEX_RESETS: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "consul_watch_index_resets",
+            "Number of consul blocking query index resets to 0"
+        ),
+        &["reason"]
+    )
+    .expect("metric can be created");
+    pub static ref CONSUL_SERVICE_INDEX_RESETS: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "consul_service_index_resets",
+            "Number of consul service query index resets to 0"
+        ),
+        &["service", "reason"]
+    )
+    .expect("metric can be created");
+    pub static ref CONSUL_SERVICE_FAILURES: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "consul_service_failures",
+            "Number of failed consul queries listing the nodes of a service"
+        ),
+        &["service"]
+    )
+    .expect("metric can be created");
+    pub static ref CONSUL_WATCH_DURATION: Histogram = register_histogram!(HistogramOpts::new(
+        "consul_watch_duration_seconds",
+        "Duration of each consul watch iteration"
+    )
+    .buckets(CONSUL_WATCH_DURATION_BUCKETS.to_vec()))
+    .expect("metric can be created");
+    pub static ref CONSUL_DISCOVERY_RATE: Gauge = register_gauge!(
+        "consul_discovery_effective_rate",
+        "Current token bucket quantum of the consul discovery, reduced on backpressure"
+    )
+    .expect("metric can be created");
+}
+
+/// Parse a comma separated list of histogram buckets
+///
+/// # Arguments
+///
+/// * `buckets_str` - comma separated list of upper bounds in seconds
+///
+/// # Return
+///
+/// * List of buckets or an error if the list is empty, not parsable or not strictly increasing
+///
+pub fn parse_buckets(buckets_str: &str) -> Result<Vec<f64>, String> {
+    let buckets = buckets_str
+        .split(',')
+        .map(|bucket| {
+            bucket
+                .trim()
+                .parse::<f64>()
+                .map_err(|issue| format!("Invalid bucket {bucket}: {issue}"))
+        })
+        .collect::<Result<Vec<f64>, String>>()?;
+
+    if buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+        return Err(format!(
+            "Buckets must be in strictly increasing order: {buckets_str}"
+        ));
+    }
+
+    Ok(buckets)
+}
+
+/// Set the buckets of the response time histogram
+///
+/// Must be called before any response time is observed
+///
+/// # Arguments
+///
+/// * `buckets` - list of upper bounds in seconds
+///
+pub fn set_response_time_buckets(buckets: Vec<f64>) -> Result<(), String> {
+    RESPONSE_TIME_BUCKETS
+        .set(buckets)
+        .map_err(|_| "Response time buckets are already set".to_string())
+}
+
+/// Share the port of the webserver with other processes through SO_REUSEPORT
+///
+/// A new prober started during an upgrade binds the port while the previous
+/// one keeps serving until it is drained, so that no scrape is refused
+///
+/// # Arguments
+///
+/// * `reuse_port` - set SO_REUSEPORT on the listener of the webserver
+///
+pub fn set_http_reuse_port(reuse_port: bool) -> Result<(), String> {
+    HTTP_REUSE_PORT
+        .set(reuse_port)
+        .map_err(|_| "Http reuse port is already set".to_string())
+}
+
+/// Retry to bind the port of the webserver until it is released instead of failing
+///
+/// # Arguments
+///
+/// * `retry` - retry to bind the port every 5s
+///
+pub fn set_http_bind_retry(retry: bool) -> Result<(), String> {
+    HTTP_BIND_RETRY
+        .set(retry)
+        .map_err(|_| "Http bind retry is already set".to_string())
+}
+
+/// Serve the healthz and ready endpoints on their own address instead of the metrics port
+///
+/// Orchestrator probes can then reach them on an interface not exposing the metrics
+///
+/// # Arguments
+///
+/// * `addr` - listening address of the healthz and ready endpoints, with the metrics if none
+///
+pub fn set_health_http_addr(addr: Option<SocketAddr>) -> Result<(), String> {
+    HEALTH_HTTP_ADDR
+        .set(addr)
+        .map_err(|_| "Health http address is already set".to_string())
+}
+
+/// Bind the listener of the webserver
+///
+/// # Arguments
+///
+/// * `addr` - listening address of the webserver
+/// * `reuse_port` - set SO_REUSEPORT to share the port with other processes
+///
+/// # Return
+///
+/// * Non blocking listener
+///
+fn bind_listener(addr: SocketAddr, reuse_port: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(addr), Type::STREAM, None)?;
+    socket.set_reuse_address(true)?;
+    if reuse_port {
+        socket.set_reuse_port(true)?;
+    }
+    socket.bind(&addr.into())?;
+    socket.listen(LISTEN_BACKLOG)?;
+    socket.set_nonblocking(true)?;
+    Ok(socket.into())
+}
+
+/// Parse static labels given as key=value
+///
+/// # Arguments
+///
+/// * `labels_str` - list of key=value pairs
+///
+/// # Return
+///
+/// * List of labels or an error if a pair is malformed, a name is invalid or duplicated
+///
+pub fn parse_static_labels(labels_str: &[String]) -> Result<Vec<(String, String)>, String> {
+    let mut labels: Vec<(String, String)> = Vec::new();
+    for label_str in labels_str {
+        let (name, value) = label_str
+            .split_once('=')
+            .ok_or_else(|| format!("Invalid label {label_str}, expected key=value"))?;
+        let name = name.trim();
+
+        let mut chars = name.chars();
+        let valid_name = chars
+            .next()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+            && !name.starts_with("__");
+        if !valid_name {
+            return Err(format!("Invalid label name {name}"));
+        }
+        if labels.iter().any(|(label_name, _)| label_name == name) {
+            return Err(format!("Duplicated label name {name}"));
+        }
+
+        labels.push((name.to_string(), value.to_string()));
+    }
+
+    Ok(labels)
+}
+
+/// Set the labels added to all exported metrics
+///
+/// # Arguments
+///
+/// * `labels` - list of label names and values
+///
+pub fn set_static_labels(labels: Vec<(String, String)>) -> Result<(), String> {
+    STATIC_LABELS
+        .set(labels)
+        .map_err(|_| "Static labels are already set".to_string())
+}
+
+/// Return the labels added to all exported metrics
+pub fn static_labels() -> &'static [(String, String)] {
+    STATIC_LABELS.get().map_or(&[], Vec::as_slice)
+}
+
+/// Gather metrics of the default registry with static labels added
+///
+/// Labels already defined on a metric are kept untouched
+pub fn gather() -> Vec<MetricFamily> {
+    quantiles::export();
+    let mut metric_families = prometheus::gather();
+    add_static_labels(&mut metric_families, static_labels());
+    metric_families
+}
+
+/// Add labels to gathered metrics, labels already defined on a metric are kept untouched
+///
+/// # Arguments
+///
+/// * `metric_families` - gathered metric families
+/// * `static_labels` - list of label names and values
+///
+pub fn add_static_labels(metric_families: &mut [MetricFamily], static_labels: &[(String, String)]) {
+    if static_labels.is_empty() {
+        return;
+    }
+
+    for metric_family in metric_families.iter_mut() {
+        for metric in metric_family.mut_metric().iter_mut() {
+            let mut labels = metric.take_label().into_vec();
+            for (name, value) in static_labels {
+                if labels.iter().all(|label| label.get_name() != name) {
+                    let mut label = LabelPair::new();
+                    label.set_name(name.clone());
+                    label.set_value(value.clone());
+                    labels.push(label);
+                }
+            }
+            labels.sort_by(|left, right| left.get_name().cmp(right.get_name()));
+            metric.set_label(labels.into());
+        }
+    }
+}
+
+thread_local! {
+    // Set while the prober metrics are gathered to stop a registry gathering itself
+    static GATHERING: Cell<bool> = const { Cell::new(false) };
+}
+
+// Collector exposing the metrics of the prober on another registry
+//
+// Not meant for the default registry which already holds them
+#[derive(Debug, Default)]
+pub struct ProberCollector;
+
+impl Collector for ProberCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        Vec::new()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        // Registered on the default registry, gathered by the caller
+        if GATHERING.with(|gathering| gathering.replace(true)) {
+            return Vec::new();
+        }
+        let metric_families = gather();
+        GATHERING.with(|gathering| gathering.set(false));
+        metric_families
+    }
+}
+
+/// Return the current generation of the series of nodes
+///
+/// Series resolved under a previous generation may have been removed and must be resolved again
+pub fn series_generation() -> u64 {
+    SERIES_GENERATION.load(Ordering::Acquire)
+}
+
+/// Invalidate the series resolved by probes, called before series of nodes are removed
+pub fn node_series_removed() {
+    SERIES_GENERATION.fetch_add(1, Ordering::AcqRel);
+}
+
+/// Hide a secret value of the configuration, keeping whether it is set
+pub fn redact(secret: &str) -> &'static str {
+    if secret.is_empty() {
+        ""
+    } else {
+        "<redacted>"
+    }
+}
+
+/// Set the effective configuration served on /config
+///
+/// # Arguments
+///
+/// * `config` - resolved configuration with secrets redacted
+///
+pub fn set_effective_config(config: Value) -> Result<(), String> {
+    EFFECTIVE_CONFIG
+        .set(config)
+        .map_err(|_| "Effective configuration is already set".to_string())
+}
+
+/// Return the buckets of the response time histogram
+pub fn response_time_buckets() -> &'static [f64] {
+    RESPONSE_TIME_BUCKETS
+        .get()
+        .map(Vec::as_slice)
+        .unwrap_or(&DEFAULT_RESPONSE_TIME_BUCKETS)
+}
+
+/// Register the build info metric with the version, git commit
+/// and rustc version used to build the prober
+pub fn init_build_info() {
+    BUILD_INFO
+        .with_label_values(&[
+            env!("CARGO_PKG_VERSION"),
+            env!("PROBES_GIT_COMMIT"),
+            env!("PROBES_RUSTC_VERSION"),
+        ])
+        .set(1);
+}
+
+/// Build the unhealthy response of the healthz endpoint
+fn unhealthy(reason: String) -> (StatusCode, Json<Value>) {
+    error!("Prober is unhealthy: {}", reason);
+    (
+        StatusCode::SERVICE_UNAVAILABLE,
+        Json(json!({ "status": "unhealthy", "reason": reason })),
+    )
 }
 
 /// Handler of healthz endpoint
 ///
+/// The prober is healthy if the tokio runtime still schedules tasks
+/// and a consul discovery succeeded recently
+///
+/// # Arguments
+///
+/// * `max_discovery_age` - max time since the last successful discovery
+///
 /// # Return
 ///
-/// * Return ok string
+/// * Return ok string or service unavailable status code with the reason as json
 ///
-async fn healthz_handler() -> Result<&'static str, StatusCode> {
+async fn healthz_handler(
+    State(max_discovery_age): State<Duration>,
+) -> Result<&'static str, (StatusCode, Json<Value>)> {
+    let runtime_alive = tokio::time::timeout(RUNTIME_CHECK_TIMEOUT, tokio::spawn(async {}))
+        .await
+        .map(|join_res| join_res.is_ok())
+        .unwrap_or(false);
+    if !runtime_alive {
+        return Err(unhealthy(
+            "tokio runtime failed to schedule a task".to_string(),
+        ));
+    }
+
+    PROBER_STATE
+        .check_discovery(max_discovery_age)
+        .map_err(unhealthy)?;
+
     Ok("ok")
 }
 
-/// Handler of metrics endpoint
+/// Handler of ready endpoint
 ///
-/// transform default and custom metrics to a string
+/// The prober is ready once a first discovery succeeded
+/// and all discovered nodes have been probed at least once,
+/// until the metrics endpoint is drained on shutdown
+///
+/// # Return
+///
+/// * Return ready string or service unavailable status code if not yet ready
+///
+async fn ready_handler() -> Result<&'static str, StatusCode> {
+    if READINESS.is_ready() {
+        Ok("ready")
+    } else {
+        Err(StatusCode::SERVICE_UNAVAILABLE)
+    }
+}
+
+/// Handler of status endpoint
+///
+/// # Return
+///
+/// * Return json with uptime, last discovery and status of each probed node
+///
+async fn status_handler() -> Json<Value> {
+    Json(PROBER_STATE.to_json())
+}
+
+/// Handler of targets endpoint
+///
+/// # Return
+///
+/// * Return probed nodes in the prometheus http_sd format
+///
+async fn targets_handler() -> Json<Value> {
+    Json(PROBER_STATE.to_http_sd())
+}
+
+/// Handler of config endpoint
+///
+/// # Return
+///
+/// * Return the effective configuration of the prober
+///
+async fn config_handler() -> Json<Value> {
+    Json(EFFECTIVE_CONFIG.get().cloned().unwrap_or(Value::Null))
+}
+
+/// Handler of debug tasks endpoint
+///
+/// # Return
+///
+/// * Return the probe tasks with their current step and time since last heartbeat
+///
+async fn tasks_handler() -> Json<Value> {
+    Json(PROBER_STATE.to_tasks_json())
+}
+
+/// Handler of admin refresh endpoint
+///
+/// Wake the discovery loop to pick up topology changes immediately
+///
+/// # Return
+///
+/// * Return accepted status code, the discovery being done asynchronously
+///
+async fn refresh_handler() -> (StatusCode, &'static str) {
+    info!("Immediate discovery requested through admin endpoint");
+    PROBER_STATE.request_refresh();
+    (StatusCode::ACCEPTED, "refresh requested")
+}
+
+/// Handler of admin services tag endpoint
+///
+/// Switch the services watched by the consul and nomad discoveries without restart,
+/// probes are then reconciled with the services of the new tag
+///
+/// # Arguments
+///
+/// * `services_tag` - comma separated tags sent as body
+///
+/// # Return
+///
+/// * Return accepted status code, the discovery being done asynchronously
+///
+async fn services_tag_handler(services_tag: String) -> (StatusCode, &'static str) {
+    let services_tag = services_tag.trim();
+    if services_tag.is_empty() {
+        return (StatusCode::BAD_REQUEST, "services tag is required");
+    }
+    info!(
+        "Services tag {} requested through admin endpoint",
+        services_tag
+    );
+    PROBER_STATE.set_services_tag(services_tag);
+    (StatusCode::ACCEPTED, "services tag updated")
+}
+
+/// Encode default and custom metrics to a string
+///
+/// # Arguments
+///
+/// * `openmetrics` - encode using the OpenMetrics text format instead of the prometheus one
 ///
 /// # Return
 ///
 /// * Return prometheus metrics string or https status code representing the faced issue
 ///
-async fn metrics_handler() -> Result<String, StatusCode> {
+fn encode_metrics(openmetrics: bool) -> Result<String, StatusCode> {
+    if openmetrics {
+        let mut res = String::new();
+        if let Err(e) = openmetrics::encode(&gather(), static_labels(), &mut res) {
+            error!("could not encode openmetrics metrics: {}", e);
+            return Err(StatusCode::INTERNAL_SERVER_ERROR);
+        }
+        return Ok(res);
+    }
+
     use prometheus::Encoder;
     let encoder = prometheus::TextEncoder::new();
 
     let mut buffer = Vec::new();
-    if let Err(_e) = encoder.encode(&prometheus::gather(), &mut buffer) {
+    if let Err(_e) = encoder.encode(&gather(), &mut buffer) {
         //error!("could not encode prometheus metrics: {}", e.into());
         return Err(StatusCode::INTERNAL_SERVER_ERROR);
     };
@@ -73,38 +757,401 @@ async fn metrics_handler() -> Result<String, StatusCode> {
     Ok(res)
 }
 
-/// Initialize the webserver for healthz and metrics endpoint
+/// Handler of metrics endpoint
+///
+/// transform default and custom metrics to a string
+/// using the OpenMetrics format if requested through the Accept header
+///
+/// # Return
+///
+/// * Return prometheus metrics string or https status code representing the faced issue
+///
+async fn metrics_handler(headers: HeaderMap) -> Result<(HeaderMap, String), StatusCode> {
+    let openmetrics = headers
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .map(openmetrics::accept_openmetrics)
+        .unwrap_or(false);
+
+    let content_type = if openmetrics {
+        OPENMETRICS_CONTENT_TYPE
+    } else {
+        prometheus::TEXT_FORMAT
+    };
+    let mut response_headers = HeaderMap::new();
+    response_headers.insert(header::CONTENT_TYPE, HeaderValue::from_static(content_type));
+
+    Ok((response_headers, encode_metrics(openmetrics)?))
+}
+
+/// Initialize the webserver for healthz, ready, status, targets and metrics endpoint
 /// Used to expose prometheus metrics
 ///
+/// The webserver serves https if both a certificate and a key are provided,
+/// healthz and ready are served on their own plaintext http listener if its address is set
+///
 /// # Arguments
 ///
 /// * `http_port` - listening port of the webserver
+/// * `tls_cert_path` - path of the PEM certificate chain, empty to serve plaintext http
+/// * `tls_key_path` - path of the PEM private key, empty to serve plaintext http
+/// * `auth` - authentication required on the metrics, status and targets endpoints
+/// * `max_discovery_age` - max time since the last successful discovery to be healthy
+/// * `shutdown_rx` - stop accepting connections and wait for in-flight requests on reception
+/// * `access_log` - log method, path, status and duration of each request at info level
 ///
 pub async fn init_prometheus_http_endpoint(
     http_port: u16,
+    tls_cert_path: &str,
+    tls_key_path: &str,
+    auth: HttpAuth,
+    max_discovery_age: Duration,
+    mut shutdown_rx: oneshot::Receiver<()>,
+    access_log: bool,
 ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    let app = Router::new()
+    // Endpoints requiring authentication if enabled
+    let protected = Router::new()
+        // Compress the exposition when requested through accept-encoding
+        .route(
+            "/metrics",
+            get(metrics_handler).layer(CompressionLayer::new()),
+        )
+        .route("/status", get(status_handler))
+        .route("/targets", get(targets_handler))
+        .route("/admin/refresh", post(refresh_handler))
+        .route("/admin/services-tag", post(services_tag_handler))
+        .route("/debug/tasks", get(tasks_handler))
+        .route("/config", get(config_handler))
+        .route_layer(middleware::from_fn_with_state(auth, auth_middleware));
+
+    let health = Router::new()
         .route("/healthz", get(healthz_handler))
-        .route("/metrics", get(metrics_handler));
+        .with_state(max_discovery_age)
+        .route("/ready", get(ready_handler));
 
+    if tls_cert_path.is_empty() != tls_key_path.is_empty() {
+        return Err("Both tls certificate and key are required to serve https".into());
+    }
     let addr = SocketAddr::from(([0, 0, 0, 0], http_port));
-    info!("Http server for metrics endpoint listening on {}", addr);
-    match axum::Server::try_bind(&addr) {
-        Ok(server) => server.serve(app.into_make_service()).await?,
-        Err(issue) => return Err(issue.into()),
+    let Some(listener) = bind_with_retry(addr, &mut shutdown_rx).await? else {
+        return Ok(());
+    };
+
+    let Some(health_addr) = HEALTH_HTTP_ADDR.get().copied().flatten() else {
+        let app = with_access_log(health.merge(protected), access_log);
+        return serve_metrics(listener, app, tls_cert_path, tls_key_path, shutdown_rx).await;
+    };
+    let Some(health_listener) = bind_with_retry(health_addr, &mut shutdown_rx).await? else {
+        return Ok(());
+    };
+    // Both servers stop together, on shutdown or once one of them failed
+    let (metrics_shutdown_tx, metrics_shutdown_rx) = oneshot::channel();
+    let (health_shutdown_tx, health_shutdown_rx) = oneshot::channel();
+    tokio::spawn(async move {
+        let _ = shutdown_rx.await;
+        let _ = metrics_shutdown_tx.send(());
+        let _ = health_shutdown_tx.send(());
+    });
+    info!(
+        "Http server for healthz and ready endpoints listening on {}",
+        health_addr
+    );
+    tokio::try_join!(
+        serve_metrics(
+            listener,
+            with_access_log(protected, access_log),
+            tls_cert_path,
+            tls_key_path,
+            metrics_shutdown_rx,
+        ),
+        serve_health(
+            health_listener,
+            with_access_log(health, access_log),
+            health_shutdown_rx,
+        ),
+    )?;
+    Ok(())
+}
+
+/// Log method, path, status and duration of each request at info level if enabled
+///
+/// # Arguments
+///
+/// * `app` - routes of the webserver
+/// * `access_log` - enable the access log
+///
+fn with_access_log(app: Router, access_log: bool) -> Router {
+    if !access_log {
+        return app;
     }
+    app.layer(
+        TraceLayer::new_for_http()
+            .make_span_with(DefaultMakeSpan::new().level(Level::INFO))
+            .on_response(
+                DefaultOnResponse::new()
+                    .level(Level::INFO)
+                    .latency_unit(LatencyUnit::Millis),
+            ),
+    )
+}
 
+/// Bind the listener of the webserver, retrying until the port is released if enabled
+///
+/// # Arguments
+///
+/// * `addr` - listening address of the webserver
+/// * `shutdown_rx` - stop retrying on reception
+///
+/// # Return
+///
+/// * Non blocking listener, None if stopped while retrying
+///
+async fn bind_with_retry(
+    addr: SocketAddr,
+    shutdown_rx: &mut oneshot::Receiver<()>,
+) -> io::Result<Option<TcpListener>> {
+    let reuse_port = HTTP_REUSE_PORT.get().copied().unwrap_or(false);
+    loop {
+        match bind_listener(addr, reuse_port) {
+            Ok(listener) => return Ok(Some(listener)),
+            Err(issue) if HTTP_BIND_RETRY.get().copied().unwrap_or(false) => {
+                error!(
+                    "Issue to bind {} due to {}, retry in {:?}",
+                    addr, issue, HTTP_BIND_RETRY_DELAY
+                );
+                tokio::select! {
+                    _ = tokio::time::sleep(HTTP_BIND_RETRY_DELAY) => {}
+                    // Sender dropped also means the process is stopping
+                    _ = &mut *shutdown_rx => return Ok(None),
+                }
+            }
+            Err(issue) => return Err(issue),
+        }
+    }
+}
+
+/// Serve the metrics endpoint, over https if both a certificate and a key are provided
+///
+/// # Arguments
+///
+/// * `listener` - bound listener of the webserver
+/// * `app` - routes of the webserver
+/// * `tls_cert_path` - path of the PEM certificate chain, empty to serve plaintext http
+/// * `tls_key_path` - path of the PEM private key, empty to serve plaintext http
+/// * `shutdown_rx` - stop accepting connections and wait for in-flight requests on reception
+///
+async fn serve_metrics(
+    listener: TcpListener,
+    app: Router,
+    tls_cert_path: &str,
+    tls_key_path: &str,
+    shutdown_rx: oneshot::Receiver<()>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let addr = listener.local_addr()?;
+    if !tls_cert_path.is_empty() {
+        let tls_config = RustlsConfig::from_pem_file(tls_cert_path, tls_key_path).await?;
+        let handle = Handle::new();
+        let shutdown_handle = handle.clone();
+        tokio::spawn(async move {
+            // Sender dropped also means the process is stopping
+            let _ = shutdown_rx.await;
+            info!("Shutting down https server for metrics endpoint");
+            shutdown_handle.graceful_shutdown(Some(GRACEFUL_SHUTDOWN_TIMEOUT));
+        });
+        let listening_handle = handle.clone();
+        tokio::spawn(async move {
+            if listening_handle.listening().await.is_some() {
+                systemd::http_listening();
+            }
+        });
+        info!("Https server for metrics endpoint listening on {}", addr);
+        axum_server::from_tcp_rustls(listener, tls_config)
+            .handle(handle)
+            .serve(app.into_make_service())
+            .await?;
+        return Ok(());
+    }
+
+    info!("Http server for metrics endpoint listening on {}", addr);
+    let server = axum::Server::from_tcp(listener)?;
+    systemd::http_listening();
+    server
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async {
+            // Sender dropped also means the process is stopping
+            let _ = shutdown_rx.await;
+            info!("Shutting down http server for metrics endpoint");
+        })
+        .await?;
+    Ok(())
+}
+
+/// Serve the healthz and ready endpoints over plaintext http, for orchestrator probes
+///
+/// # Arguments
+///
+/// * `listener` - bound listener of the health endpoints
+/// * `app` - healthz and ready routes
+/// * `shutdown_rx` - stop accepting connections and wait for in-flight requests on reception
+///
+async fn serve_health(
+    listener: TcpListener,
+    app: Router,
+    shutdown_rx: oneshot::Receiver<()>,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    axum::Server::from_tcp(listener)?
+        .serve(app.into_make_service())
+        .with_graceful_shutdown(async {
+            // Sender dropped also means the process is stopping
+            let _ = shutdown_rx.await;
+            info!("Shutting down http server for healthz and ready endpoints");
+        })
+        .await?;
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
-    use crate::probes::prometheus::{healthz_handler, metrics_handler};
+    use std::net::SocketAddr;
+    use std::time::Duration;
+
+    use axum::extract::State;
+    use axum::http::{header, HeaderMap, HeaderValue, StatusCode};
+    use axum::routing::get;
+    use axum::Router;
+    use tokio::sync::oneshot;
+
+    use crate::probes::auth::HttpAuth;
+    use crate::probes::prometheus::{
+        bind_listener, config_handler, healthz_handler, init_prometheus_http_endpoint,
+        metrics_handler, parse_buckets, parse_static_labels, ready_handler, redact,
+        refresh_handler, serve_health, services_tag_handler, status_handler, targets_handler,
+        tasks_handler, ProberCollector,
+    };
+    use crate::probes::prometheus::{NUMBER_OF_REQUESTS, PROBES_STARTED};
+
+    #[test]
+    fn test_parse_buckets() {
+        assert_eq!(
+            vec![0.0001, 0.001, 1.0],
+            parse_buckets("0.0001, 0.001,1").unwrap()
+        );
+        assert!(parse_buckets("").is_err());
+        assert!(parse_buckets("0.1,a").is_err());
+        assert!(parse_buckets("0.1,0.01").is_err());
+        assert!(parse_buckets("0.1,0.1").is_err());
+    }
+
+    #[test]
+    fn test_parse_static_labels() {
+        assert_eq!(
+            vec![
+                ("dc".to_string(), "eu-west".to_string()),
+                ("env".to_string(), "a=b".to_string())
+            ],
+            parse_static_labels(&["dc=eu-west".to_string(), "env=a=b".to_string()]).unwrap()
+        );
+        assert!(parse_static_labels(&["dc".to_string()]).is_err());
+        assert!(parse_static_labels(&["1dc=a".to_string()]).is_err());
+        assert!(parse_static_labels(&["__dc=a".to_string()]).is_err());
+        assert!(parse_static_labels(&["dc=a".to_string(), "dc=b".to_string()]).is_err());
+    }
+
+    #[test]
+    fn test_prober_collector() {
+        PROBES_STARTED.get();
+        let registry = prometheus::Registry::new();
+        registry.register(Box::new(ProberCollector)).unwrap();
+        assert!(registry
+            .gather()
+            .iter()
+            .any(|metric_family| metric_family.get_name() == "probes_started"));
+    }
+
+    #[tokio::test]
+    async fn test_init_prometheus_http_endpoint_missing_tls_key() {
+        assert!(init_prometheus_http_endpoint(
+            0,
+            "cert.pem",
+            "",
+            HttpAuth::default(),
+            Duration::from_secs(60),
+            oneshot::channel().1,
+            false
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_init_prometheus_http_endpoint_graceful_shutdown() {
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let server = tokio::spawn(init_prometheus_http_endpoint(
+            0,
+            "",
+            "",
+            HttpAuth::default(),
+            Duration::from_secs(60),
+            shutdown_rx,
+            true,
+        ));
+        shutdown_tx.send(()).unwrap();
+        assert!(server.await.unwrap().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_status_handler() {
+        let status = status_handler().await.0;
+        assert!(status["uptime_seconds"].is_f64());
+        assert!(status["nodes"].is_array());
+    }
+
+    #[tokio::test]
+    async fn test_targets_handler() {
+        assert!(targets_handler().await.0.is_array());
+    }
+
+    #[test]
+    fn test_redact() {
+        assert_eq!("", redact(""));
+        assert_eq!("<redacted>", redact("secret"));
+    }
+
+    #[tokio::test]
+    async fn test_config_handler() {
+        assert!(config_handler().await.0.is_null());
+    }
+
+    #[tokio::test]
+    async fn test_tasks_handler() {
+        assert!(tasks_handler().await.0.is_array());
+    }
+
+    #[tokio::test]
+    async fn test_refresh_handler() {
+        assert_eq!(StatusCode::ACCEPTED, refresh_handler().await.0);
+    }
+
+    #[tokio::test]
+    async fn test_services_tag_handler() {
+        // A valid tag is not set to not switch the discoveries of other tests
+        assert_eq!(
+            StatusCode::BAD_REQUEST,
+            services_tag_handler(" ".to_string()).await.0
+        );
+    }
 
     #[tokio::test]
     async fn test_healthz_handler() {
-        assert_eq!("ok", healthz_handler().await.unwrap());
+        assert_eq!(
+            "ok",
+            healthz_handler(State(Duration::from_secs(3600)))
+                .await
+                .unwrap()
+        );
+        let (status_code, reason) = healthz_handler(State(Duration::ZERO)).await.unwrap_err();
+        assert_eq!(StatusCode::SERVICE_UNAVAILABLE, status_code);
+        assert_eq!("unhealthy", reason.0["status"]);
     }
 
     #[tokio::test]
@@ -118,9 +1165,65 @@ mod tests {
         NUMBER_OF_REQUESTS
             .with_label_values(&["cluster_name", "addr", "status_code", "set"])
             .inc();
-        let metrics = metrics_handler().await.unwrap();
+        let (headers, metrics) = metrics_handler(HeaderMap::new()).await.unwrap();
+        assert_eq!(
+            prometheus::TEXT_FORMAT,
+            headers.get(header::CONTENT_TYPE).unwrap().to_str().unwrap()
+        );
         assert!(metrics.contains("process_cpu_seconds_total"));
         assert!(metrics.contains("number_of_requests{cluster_name=\"cluster_name\",socket=\"addr\",status=\"status_code\",type=\"get\"} 2"));
         assert!(metrics.contains("number_of_requests{cluster_name=\"cluster_name\",socket=\"addr\",status=\"status_code\",type=\"set\"} 1"));
     }
+
+    #[tokio::test]
+    async fn test_metrics_handler_openmetrics() {
+        let mut request_headers = HeaderMap::new();
+        request_headers.insert(
+            header::ACCEPT,
+            HeaderValue::from_static("application/openmetrics-text; version=1.0.0"),
+        );
+        let (headers, metrics) = metrics_handler(request_headers).await.unwrap();
+        assert_eq!(
+            "application/openmetrics-text; version=1.0.0; charset=utf-8",
+            headers.get(header::CONTENT_TYPE).unwrap().to_str().unwrap()
+        );
+        assert!(metrics.contains("# TYPE process_cpu_seconds counter"));
+        assert!(metrics.ends_with("# EOF\n"));
+    }
+
+    #[test]
+    fn bind_listener_reuse_port() {
+        let listener = bind_listener(SocketAddr::from(([127, 0, 0, 1], 0)), true).unwrap();
+        let addr = listener.local_addr().unwrap();
+        // The next prober binds the port while the previous one still listens
+        assert!(bind_listener(addr, true).is_ok());
+
+        let listener = bind_listener(SocketAddr::from(([127, 0, 0, 1], 0)), false).unwrap();
+        assert!(bind_listener(listener.local_addr().unwrap(), false).is_err());
+    }
+
+    #[tokio::test]
+    async fn serve_health_only() {
+        let listener = bind_listener(SocketAddr::from(([127, 0, 0, 1], 0)), false).unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = Router::new().route("/ready", get(ready_handler));
+        let (shutdown_tx, shutdown_rx) = oneshot::channel();
+        let server = tokio::spawn(serve_health(listener, app, shutdown_rx));
+
+        let client = hyper::Client::new();
+        let ready = client
+            .get(format!("http://{addr}/ready").parse().unwrap())
+            .await
+            .unwrap();
+        assert_ne!(StatusCode::NOT_FOUND, ready.status());
+        let metrics = client
+            .get(format!("http://{addr}/metrics").parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(StatusCode::NOT_FOUND, metrics.status());
+
+        drop(client);
+        shutdown_tx.send(()).unwrap();
+        assert!(server.await.unwrap().is_ok());
+    }
 }
@@ -0,0 +1,589 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+
+use prometheus::{Histogram, IntCounter};
+use thiserror::Error;
+
+#[cfg(feature = "aerospike")]
+use crate::aerospike::{self, AerospikeClientError};
+#[cfg(feature = "cassandra")]
+use crate::cassandra::{self, CassandraClientError};
+#[cfg(feature = "clickhouse")]
+use crate::clickhouse::{self, ClickhouseClientError};
+#[cfg(feature = "dns")]
+use crate::dns::{self, DnsClientError};
+#[cfg(feature = "elasticsearch")]
+use crate::elasticsearch::{self, ElasticsearchClientError};
+#[cfg(feature = "grpc")]
+use crate::grpc::{self, GrpcClientError};
+#[cfg(feature = "haproxy")]
+use crate::haproxy::{self, HaproxyClientError};
+#[cfg(feature = "kafka")]
+use crate::kafka::{self, KafkaClientError};
+#[cfg(feature = "ldap")]
+use crate::ldap::{self, LdapClientError};
+#[cfg(feature = "memcached")]
+use crate::memcached::{self, MemcachedClientError};
+#[cfg(feature = "mongodb")]
+use crate::mongodb::{self, MongodbClientError};
+#[cfg(feature = "mysql")]
+use crate::mysql::{self, MysqlClientError};
+#[cfg(feature = "nats")]
+use crate::nats::{self, NatsClientError};
+use crate::probes::exemplars::EXEMPLARS;
+use crate::probes::prometheus::{
+    response_time_buckets, series_generation, NUMBER_OF_REQUESTS, RESPONSE_TIME_COLLECTOR,
+};
+use crate::probes::{quantiles, statsd};
+#[cfg(feature = "rabbitmq")]
+use crate::rabbitmq::{self, RabbitmqClientError};
+#[cfg(feature = "redis")]
+use crate::redis::{self, RedisClientError};
+#[cfg(feature = "s3")]
+use crate::s3::{self, S3ClientError};
+#[cfg(feature = "smtp")]
+use crate::smtp::{self, SmtpClientError};
+#[cfg(feature = "solr")]
+use crate::solr::{self, SolrClientError};
+use crate::tcp::{self, TcpClientError};
+#[cfg(feature = "varnish")]
+use crate::varnish::{self, VarnishClientError};
+
+// Error returned by a probe whatever the protocol
+#[derive(Error, Debug)]
+pub enum ProbeError {
+    #[cfg(feature = "memcached")]
+    #[error(transparent)]
+    Memcached(#[from] MemcachedClientError),
+    #[cfg(feature = "elasticsearch")]
+    #[error(transparent)]
+    Elasticsearch(#[from] ElasticsearchClientError),
+    #[cfg(feature = "redis")]
+    #[error(transparent)]
+    Redis(#[from] RedisClientError),
+    #[cfg(feature = "kafka")]
+    #[error(transparent)]
+    Kafka(#[from] KafkaClientError),
+    #[cfg(feature = "mysql")]
+    #[error(transparent)]
+    Mysql(#[from] MysqlClientError),
+    #[cfg(feature = "mongodb")]
+    #[error(transparent)]
+    Mongodb(#[from] MongodbClientError),
+    #[cfg(feature = "cassandra")]
+    #[error(transparent)]
+    Cassandra(#[from] CassandraClientError),
+    #[error(transparent)]
+    Tcp(#[from] TcpClientError),
+    #[cfg(feature = "dns")]
+    #[error(transparent)]
+    Dns(#[from] DnsClientError),
+    #[cfg(feature = "grpc")]
+    #[error(transparent)]
+    Grpc(#[from] GrpcClientError),
+    #[cfg(feature = "rabbitmq")]
+    #[error(transparent)]
+    Rabbitmq(#[from] RabbitmqClientError),
+    #[cfg(feature = "nats")]
+    #[error(transparent)]
+    Nats(#[from] NatsClientError),
+    #[cfg(feature = "aerospike")]
+    #[error(transparent)]
+    Aerospike(#[from] AerospikeClientError),
+    #[cfg(feature = "solr")]
+    #[error(transparent)]
+    Solr(#[from] SolrClientError),
+    #[cfg(feature = "clickhouse")]
+    #[error(transparent)]
+    Clickhouse(#[from] ClickhouseClientError),
+    #[cfg(feature = "varnish")]
+    #[error(transparent)]
+    Varnish(#[from] VarnishClientError),
+    #[cfg(feature = "haproxy")]
+    #[error(transparent)]
+    Haproxy(#[from] HaproxyClientError),
+    #[cfg(feature = "s3")]
+    #[error(transparent)]
+    S3(#[from] S3ClientError),
+    #[cfg(feature = "smtp")]
+    #[error(transparent)]
+    Smtp(#[from] SmtpClientError),
+    #[cfg(feature = "ldap")]
+    #[error(transparent)]
+    Ldap(#[from] LdapClientError),
+    #[error("Protocol {0} is not compiled in, rebuild with --features {0}")]
+    Disabled(Protocol),
+}
+
+// Future returned by a probe run
+pub type ProbeFuture<'a> = Pin<Box<dyn Future<Output = Result<(), ProbeError>> + Send + 'a>>;
+
+// Future returned by a protocol connect
+pub type ConnectFuture =
+    Pin<Box<dyn Future<Output = Result<Box<dyn ProbeConnection>, ProbeError>> + Send>>;
+
+// Connection to a node able to run the probe of its protocol
+pub trait ProbeConnection: Send {
+    /// Run one probe on the node
+    fn probe(&mut self) -> ProbeFuture<'_>;
+}
+
+// Protocol of the probed nodes
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Default)]
+pub enum Protocol {
+    // Set and get a key with the memcached binary protocol
+    #[default]
+    Memcached,
+    // Check cluster health and index, search and delete a canary document
+    Elasticsearch,
+    // Set, get and delete a key with the RESP protocol
+    Redis,
+    // ApiVersions and Metadata round trip with the kafka protocol
+    Kafka,
+    // Ping and run a health query with the mysql protocol
+    Mysql,
+    // Hello and ping with OP_MSG, the role of the node is exported
+    Mongodb,
+    // Options and a select of the local node time with the CQL native protocol
+    Cassandra,
+    // Only measure the tcp connect time
+    Tcp,
+    // Measure the tcp connect and tls handshake times
+    Tls,
+    // Resolve a name and check the A records
+    Dns,
+    // Standard grpc.health.v1 health check over http2
+    Grpc,
+    // Connection and channel open with AMQP 0-9-1, optional canary round trip
+    Rabbitmq,
+    // Ping and optional canary round trip with the nats protocol
+    Nats,
+    // Info requests and canary read and write on aerospike nodes
+    Aerospike,
+    // Core pings and optional canary query on solr nodes
+    Solr,
+    // Health query over http and optional native handshake on clickhouse nodes
+    Clickhouse,
+    // Test request and optional cli ping on varnish nodes
+    Varnish,
+    // Backend servers and queues from the stats of haproxy nodes
+    Haproxy,
+    // Put, get and delete of a canary object on s3 compatible endpoints
+    S3,
+    // Banner, ehlo and optional starttls upgrade on mail relays
+    Smtp,
+    // Bind and base scope search on directory servers
+    Ldap,
+}
+
+impl FromStr for Protocol {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "memcached" => Ok(Protocol::Memcached),
+            "elasticsearch" => Ok(Protocol::Elasticsearch),
+            "redis" => Ok(Protocol::Redis),
+            "kafka" => Ok(Protocol::Kafka),
+            "mysql" => Ok(Protocol::Mysql),
+            "mongodb" => Ok(Protocol::Mongodb),
+            "cassandra" => Ok(Protocol::Cassandra),
+            "tcp" => Ok(Protocol::Tcp),
+            "tls" => Ok(Protocol::Tls),
+            "dns" => Ok(Protocol::Dns),
+            "grpc" => Ok(Protocol::Grpc),
+            "rabbitmq" => Ok(Protocol::Rabbitmq),
+            "nats" => Ok(Protocol::Nats),
+            "aerospike" => Ok(Protocol::Aerospike),
+            "solr" => Ok(Protocol::Solr),
+            "clickhouse" => Ok(Protocol::Clickhouse),
+            "varnish" => Ok(Protocol::Varnish),
+            "haproxy" => Ok(Protocol::Haproxy),
+            "s3" => Ok(Protocol::S3),
+            "smtp" => Ok(Protocol::Smtp),
+            "ldap" => Ok(Protocol::Ldap),
+            _ => Err(format!(
+                "Invalid protocol {s}, expected one of memcached, elasticsearch, redis, \
+                kafka, mysql, mongodb, cassandra, tcp, tls, dns, grpc, rabbitmq, nats, \
+                aerospike, solr, clickhouse, varnish, haproxy, s3, smtp, ldap"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protocol::Memcached => write!(f, "memcached"),
+            Protocol::Elasticsearch => write!(f, "elasticsearch"),
+            Protocol::Redis => write!(f, "redis"),
+            Protocol::Kafka => write!(f, "kafka"),
+            Protocol::Mysql => write!(f, "mysql"),
+            Protocol::Mongodb => write!(f, "mongodb"),
+            Protocol::Cassandra => write!(f, "cassandra"),
+            Protocol::Tcp => write!(f, "tcp"),
+            Protocol::Tls => write!(f, "tls"),
+            Protocol::Dns => write!(f, "dns"),
+            Protocol::Grpc => write!(f, "grpc"),
+            Protocol::Rabbitmq => write!(f, "rabbitmq"),
+            Protocol::Nats => write!(f, "nats"),
+            Protocol::Aerospike => write!(f, "aerospike"),
+            Protocol::Solr => write!(f, "solr"),
+            Protocol::Clickhouse => write!(f, "clickhouse"),
+            Protocol::Varnish => write!(f, "varnish"),
+            Protocol::Haproxy => write!(f, "haproxy"),
+            Protocol::S3 => write!(f, "s3"),
+            Protocol::Smtp => write!(f, "smtp"),
+            Protocol::Ldap => write!(f, "ldap"),
+        }
+    }
+}
+
+impl Protocol {
+    /// Return true if the probe of the protocol is compiled in, through its cargo feature
+    pub fn is_enabled(&self) -> bool {
+        match self {
+            Protocol::Memcached => cfg!(feature = "memcached"),
+            Protocol::Elasticsearch => cfg!(feature = "elasticsearch"),
+            Protocol::Redis => cfg!(feature = "redis"),
+            Protocol::Kafka => cfg!(feature = "kafka"),
+            Protocol::Mysql => cfg!(feature = "mysql"),
+            Protocol::Mongodb => cfg!(feature = "mongodb"),
+            Protocol::Cassandra => cfg!(feature = "cassandra"),
+            Protocol::Tcp | Protocol::Tls => true,
+            Protocol::Dns => cfg!(feature = "dns"),
+            Protocol::Grpc => cfg!(feature = "grpc"),
+            Protocol::Rabbitmq => cfg!(feature = "rabbitmq"),
+            Protocol::Nats => cfg!(feature = "nats"),
+            Protocol::Aerospike => cfg!(feature = "aerospike"),
+            Protocol::Solr => cfg!(feature = "solr"),
+            Protocol::Clickhouse => cfg!(feature = "clickhouse"),
+            Protocol::Varnish => cfg!(feature = "varnish"),
+            Protocol::Haproxy => cfg!(feature = "haproxy"),
+            Protocol::S3 => cfg!(feature = "s3"),
+            Protocol::Smtp => cfg!(feature = "smtp"),
+            Protocol::Ldap => cfg!(feature = "ldap"),
+        }
+    }
+
+    /// Return the issue reported when the protocol is not compiled in
+    pub fn disabled_issue(&self) -> String {
+        ProbeError::Disabled(*self).to_string()
+    }
+
+    /// Connect to a node
+    ///
+    /// # Arguments
+    ///
+    /// * `cluster_name` - name of the service of the node
+    /// * `socket` - ip:port of the node
+    ///
+    /// # Return
+    ///
+    /// * Connection used to probe the node
+    ///
+    pub fn connect(&self, cluster_name: &str, socket: &str) -> ConnectFuture {
+        let cluster_name = cluster_name.to_string();
+        let socket = socket.to_string();
+        match self {
+            #[cfg(feature = "memcached")]
+            Protocol::Memcached => Box::pin(async move {
+                let client = memcached::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "elasticsearch")]
+            Protocol::Elasticsearch => Box::pin(async move {
+                let client = elasticsearch::connect(&cluster_name, &socket);
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "redis")]
+            Protocol::Redis => Box::pin(async move {
+                let client = redis::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "kafka")]
+            Protocol::Kafka => Box::pin(async move {
+                let client = kafka::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "mysql")]
+            Protocol::Mysql => Box::pin(async move {
+                let client = mysql::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "mongodb")]
+            Protocol::Mongodb => Box::pin(async move {
+                let client = mongodb::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "cassandra")]
+            Protocol::Cassandra => Box::pin(async move {
+                let client = cassandra::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            Protocol::Tcp => Box::pin(async move {
+                let client = tcp::connect(&cluster_name, &socket, false)?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            Protocol::Tls => Box::pin(async move {
+                let client = tcp::connect(&cluster_name, &socket, true)?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "dns")]
+            Protocol::Dns => Box::pin(async move {
+                let client = dns::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "grpc")]
+            Protocol::Grpc => Box::pin(async move {
+                let client = grpc::connect(&cluster_name, &socket);
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "rabbitmq")]
+            Protocol::Rabbitmq => Box::pin(async move {
+                let client = rabbitmq::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "nats")]
+            Protocol::Nats => Box::pin(async move {
+                let client = nats::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "aerospike")]
+            Protocol::Aerospike => Box::pin(async move {
+                let client = aerospike::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "solr")]
+            Protocol::Solr => Box::pin(async move {
+                let client = solr::connect(&cluster_name, &socket);
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "clickhouse")]
+            Protocol::Clickhouse => Box::pin(async move {
+                let client = clickhouse::connect(&cluster_name, &socket)?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "varnish")]
+            Protocol::Varnish => Box::pin(async move {
+                let client = varnish::connect(&cluster_name, &socket)?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "haproxy")]
+            Protocol::Haproxy => Box::pin(async move {
+                let client = haproxy::connect(&cluster_name, &socket);
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "s3")]
+            Protocol::S3 => Box::pin(async move {
+                let client = s3::connect(&cluster_name, &socket);
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "smtp")]
+            Protocol::Smtp => Box::pin(async move {
+                let client = smtp::connect(&cluster_name, &socket)?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            #[cfg(feature = "ldap")]
+            Protocol::Ldap => Box::pin(async move {
+                let client = ldap::connect(&cluster_name, &socket).await?;
+                Ok(Box::new(client) as Box<dyn ProbeConnection>)
+            }),
+            // Protocols whose feature is disabled in this build
+            #[allow(unreachable_patterns)]
+            protocol => {
+                let protocol = *protocol;
+                Box::pin(async move { Err(ProbeError::Disabled(protocol)) })
+            }
+        }
+    }
+}
+
+/// Export the response time of a request on its resolved histogram
+fn record_response_time(
+    histogram: &Histogram,
+    cluster_name: &str,
+    socket: &str,
+    cmd_type: &str,
+    elapsed: Duration,
+) {
+    histogram.observe(elapsed.as_secs_f64());
+    EXEMPLARS.observe(
+        "response_time_seconds",
+        &[
+            ("cluster_name", cluster_name),
+            ("socket", socket),
+            ("type", cmd_type),
+        ],
+        response_time_buckets(),
+        elapsed.as_secs_f64(),
+    );
+    quantiles::observe(cluster_name, socket, cmd_type, elapsed.as_secs_f64());
+    statsd::timing(
+        "response_time",
+        &[
+            ("cluster_name", cluster_name),
+            ("socket", socket),
+            ("type", cmd_type),
+        ],
+        elapsed,
+    );
+}
+
+/// Count a request answered by a node on its resolved counter
+fn record_request(
+    counter: &IntCounter,
+    cluster_name: &str,
+    socket: &str,
+    status: &str,
+    cmd_type: &str,
+) {
+    counter.inc();
+    statsd::count(
+        "number_of_requests",
+        &[
+            ("cluster_name", cluster_name),
+            ("socket", socket),
+            ("status", status),
+            ("type", cmd_type),
+        ],
+        1,
+    );
+}
+
+// Series of the requests to a node, resolved once per command type and status
+#[derive(Debug)]
+pub struct RequestMetrics {
+    cluster_name: String,
+    socket: String,
+    // Generation of the series of nodes the handles have been resolved under
+    generation: u64,
+    // Request counters per command type then status
+    requests: HashMap<String, HashMap<String, IntCounter>>,
+    // Response time histograms per command type
+    response_times: HashMap<String, Histogram>,
+}
+
+impl RequestMetrics {
+    /// Returns a RequestMetrics without any resolved series
+    ///
+    /// # Arguments
+    ///
+    /// * `cluster_name` - name of the service of the node
+    /// * `socket` - ip:port of the node
+    ///
+    pub fn new(cluster_name: &str, socket: &str) -> Self {
+        RequestMetrics {
+            cluster_name: cluster_name.to_owned(),
+            socket: socket.to_owned(),
+            generation: series_generation(),
+            requests: HashMap::new(),
+            response_times: HashMap::new(),
+        }
+    }
+
+    /// Drop the resolved series if series of nodes have been removed since they were resolved
+    fn refresh_generation(&mut self) {
+        let generation = series_generation();
+        if generation != self.generation {
+            self.requests.clear();
+            self.response_times.clear();
+            self.generation = generation;
+        }
+    }
+
+    /// Export the response time of a request to the node
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - type of the command
+    /// * `elapsed` - response time of the request
+    ///
+    pub fn observe_response_time(&mut self, cmd_type: &str, elapsed: Duration) {
+        self.refresh_generation();
+        if !self.response_times.contains_key(cmd_type) {
+            let histogram = RESPONSE_TIME_COLLECTOR.with_label_values(&[
+                self.cluster_name.as_str(),
+                self.socket.as_str(),
+                cmd_type,
+            ]);
+            self.response_times.insert(cmd_type.to_owned(), histogram);
+        }
+        record_response_time(
+            &self.response_times[cmd_type],
+            &self.cluster_name,
+            &self.socket,
+            cmd_type,
+            elapsed,
+        );
+    }
+
+    /// Count a request answered by the node
+    ///
+    /// # Arguments
+    ///
+    /// * `status` - status of the response
+    /// * `cmd_type` - type of the command
+    ///
+    pub fn count_request(&mut self, status: &str, cmd_type: &str) {
+        self.refresh_generation();
+        if !self
+            .requests
+            .get(cmd_type)
+            .is_some_and(|statuses| statuses.contains_key(status))
+        {
+            let counter = NUMBER_OF_REQUESTS.with_label_values(&[
+                self.cluster_name.as_str(),
+                self.socket.as_str(),
+                status,
+                cmd_type,
+            ]);
+            self.requests
+                .entry(cmd_type.to_owned())
+                .or_default()
+                .insert(status.to_owned(), counter);
+        }
+        record_request(
+            &self.requests[cmd_type][status],
+            &self.cluster_name,
+            &self.socket,
+            status,
+            cmd_type,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::probes::prometheus::{
+        node_series_removed, NUMBER_OF_REQUESTS, RESPONSE_TIME_COLLECTOR,
+    };
+    use crate::probes::protocol::RequestMetrics;
+
+    #[test]
+    fn request_metrics_resolved_again_after_removal() {
+        let labels = ["cluster_request_metrics", "ip:0", "NoError", "get"];
+        let mut metrics = RequestMetrics::new("cluster_request_metrics", "ip:0");
+        metrics.count_request("NoError", "get");
+        metrics.count_request("NoError", "get");
+        metrics.observe_response_time("get", Duration::from_millis(1));
+        assert_eq!(NUMBER_OF_REQUESTS.with_label_values(&labels).get(), 2);
+        assert_eq!(
+            RESPONSE_TIME_COLLECTOR
+                .with_label_values(&["cluster_request_metrics", "ip:0", "get"])
+                .get_sample_count(),
+            1
+        );
+
+        NUMBER_OF_REQUESTS.remove_label_values(&labels).unwrap();
+        node_series_removed();
+        metrics.count_request("NoError", "get");
+        assert_eq!(NUMBER_OF_REQUESTS.with_label_values(&labels).get(), 1);
+    }
+}
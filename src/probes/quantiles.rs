@@ -0,0 +1,316 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, GaugeVec, Opts};
+use tracing::info;
+
+// Quantiles exported for each node and command
+pub const QUANTILES: [f64; 3] = [0.5, 0.99, 0.999];
+
+// Max number of samples kept per window to bound memory with short intervals
+const MAX_SAMPLES: usize = 4096;
+
+// Global response time quantiles, only maintained once initialized
+static RESPONSE_TIME_QUANTILES: OnceLock<QuantileTracker> = OnceLock::new();
+
+lazy_static! {
+    pub static ref RESPONSE_TIME_QUANTILE: GaugeVec = register_gauge_vec!(
+        Opts::new(
+            "response_time_quantile_seconds",
+            "Response time quantiles over a sliding window"
+        ),
+        &["cluster_name", "socket", "type", "quantile"]
+    )
+    .expect("metric can be created");
+}
+
+// Samples observed over a sliding window
+#[derive(Debug, Default)]
+pub struct QuantileWindow {
+    samples: VecDeque<(Instant, f64)>,
+}
+
+impl QuantileWindow {
+    /// Add a sample and drop the ones older than the window
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - time of the observation
+    /// * `window` - duration of the sliding window
+    /// * `value` - observed value
+    ///
+    pub fn observe(&mut self, now: Instant, window: Duration, value: f64) {
+        self.expire(now, window, MAX_SAMPLES - 1);
+        self.samples.push_back((now, value));
+    }
+
+    /// Drop the samples older than the window and the oldest ones above a max number of samples
+    ///
+    /// # Arguments
+    ///
+    /// * `now` - current time
+    /// * `window` - duration of the sliding window
+    /// * `max_samples` - max number of samples kept
+    ///
+    fn expire(&mut self, now: Instant, window: Duration, max_samples: usize) {
+        while let Some((observed, _)) = self.samples.front() {
+            if now.duration_since(*observed) <= window && self.samples.len() <= max_samples {
+                break;
+            }
+            self.samples.pop_front();
+        }
+    }
+
+    /// Return the values of the samples of the window
+    fn values(&self) -> Vec<f64> {
+        self.samples.iter().map(|(_, value)| *value).collect()
+    }
+
+    /// Compute quantiles of the samples of the window using the nearest rank method
+    ///
+    /// # Arguments
+    ///
+    /// * `quantiles` - list of quantiles between 0 and 1
+    ///
+    /// # Return
+    ///
+    /// * Value of each quantile, empty if no sample
+    ///
+    pub fn quantiles(&self, quantiles: &[f64]) -> Vec<f64> {
+        compute_quantiles(self.values(), quantiles)
+    }
+}
+
+/// Compute quantiles of values using the nearest rank method
+///
+/// # Arguments
+///
+/// * `values` - values in any order
+/// * `quantiles` - list of quantiles between 0 and 1
+///
+/// # Return
+///
+/// * Value of each quantile, empty if no value
+///
+fn compute_quantiles(mut values: Vec<f64>, quantiles: &[f64]) -> Vec<f64> {
+    values.sort_by(f64::total_cmp);
+    quantiles
+        .iter()
+        .filter_map(|quantile| nearest_rank(&values, *quantile))
+        .collect()
+}
+
+/// Return a quantile of sorted values using the nearest rank method
+///
+/// # Arguments
+///
+/// * `sorted_values` - values in increasing order
+/// * `quantile` - quantile between 0 and 1
+///
+/// # Return
+///
+/// * Value of the quantile, none if no value
+///
+pub fn nearest_rank<T: Copy>(sorted_values: &[T], quantile: f64) -> Option<T> {
+    if sorted_values.is_empty() {
+        return None;
+    }
+    let rank = (quantile * sorted_values.len() as f64).ceil() as usize;
+    Some(sorted_values[rank.clamp(1, sorted_values.len()) - 1])
+}
+
+// Sliding windows of response times per node and command
+#[derive(Debug)]
+pub struct QuantileTracker {
+    window: Duration,
+    windows: Mutex<HashMap<(String, String, String), QuantileWindow>>,
+}
+
+impl QuantileTracker {
+    /// Returns a QuantileTracker
+    ///
+    /// # Arguments
+    ///
+    /// * `window` - duration of the sliding window
+    ///
+    pub fn new(window: Duration) -> QuantileTracker {
+        QuantileTracker {
+            window,
+            windows: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Add a response time to the window of the node and command
+    ///
+    /// Quantiles are only computed when exported to keep probes cheap
+    ///
+    /// # Arguments
+    ///
+    /// * `cluster_name` - name of the service of the node
+    /// * `socket` - ip:port of the node
+    /// * `cmd_type` - type of the command
+    /// * `value` - response time in seconds
+    ///
+    pub fn observe(&self, cluster_name: &str, socket: &str, cmd_type: &str, value: f64) {
+        let mut windows = self.windows.lock().unwrap();
+        let window = windows
+            .entry((
+                cluster_name.to_string(),
+                socket.to_string(),
+                cmd_type.to_string(),
+            ))
+            .or_default();
+        window.observe(Instant::now(), self.window, value);
+    }
+
+    /// Export the quantiles of all windows, called when metrics are gathered
+    ///
+    /// Windows without recent samples keep their last exported quantiles
+    pub fn export(&self) {
+        let now = Instant::now();
+        // Sort outside of the lock to not block the probes
+        let snapshots = {
+            let mut windows = self.windows.lock().unwrap();
+            windows
+                .iter_mut()
+                .map(|(key, window)| {
+                    window.expire(now, self.window, MAX_SAMPLES);
+                    (key.clone(), window.values())
+                })
+                .collect::<Vec<((String, String, String), Vec<f64>)>>()
+        };
+
+        for ((cluster_name, socket, cmd_type), values) in snapshots {
+            for (quantile, quantile_value) in
+                QUANTILES.iter().zip(compute_quantiles(values, &QUANTILES))
+            {
+                RESPONSE_TIME_QUANTILE
+                    .with_label_values(&[&cluster_name, &socket, &cmd_type, &quantile.to_string()])
+                    .set(quantile_value);
+            }
+        }
+    }
+
+    /// Drop the windows and quantiles of a node
+    ///
+    /// # Arguments
+    ///
+    /// * `cluster_name` - name of the service of the node
+    /// * `socket` - ip:port of the node
+    ///
+    pub fn remove(&self, cluster_name: &str, socket: &str) {
+        let mut windows = self.windows.lock().unwrap();
+        windows.retain(|(window_cluster_name, window_socket, cmd_type), _| {
+            if window_cluster_name != cluster_name || window_socket != socket {
+                return true;
+            }
+            for quantile in QUANTILES {
+                RESPONSE_TIME_QUANTILE
+                    .remove_label_values(&[cluster_name, socket, cmd_type, &quantile.to_string()])
+                    .unwrap_or(());
+            }
+            false
+        });
+    }
+}
+
+/// Enable the response time quantiles
+///
+/// # Arguments
+///
+/// * `window` - duration of the sliding window
+///
+pub fn init_response_time_quantiles(window: Duration) -> Result<(), String> {
+    info!(
+        "Export response time quantiles over a {}s sliding window",
+        window.as_secs()
+    );
+    RESPONSE_TIME_QUANTILES
+        .set(QuantileTracker::new(window))
+        .map_err(|_| "Response time quantiles are already initialized".to_string())
+}
+
+/// Add a response time to the global quantiles if enabled
+pub fn observe(cluster_name: &str, socket: &str, cmd_type: &str, value: f64) {
+    if let Some(tracker) = RESPONSE_TIME_QUANTILES.get() {
+        tracker.observe(cluster_name, socket, cmd_type, value);
+    }
+}
+
+/// Export the global quantiles if enabled
+pub fn export() {
+    if let Some(tracker) = RESPONSE_TIME_QUANTILES.get() {
+        tracker.export();
+    }
+}
+
+/// Drop the quantiles of a node from the global quantiles if enabled
+pub fn remove(cluster_name: &str, socket: &str) {
+    if let Some(tracker) = RESPONSE_TIME_QUANTILES.get() {
+        tracker.remove(cluster_name, socket);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::probes::quantiles::{
+        nearest_rank, QuantileTracker, QuantileWindow, RESPONSE_TIME_QUANTILE,
+    };
+
+    #[test]
+    fn window_quantiles() {
+        let mut window = QuantileWindow::default();
+        assert!(window.quantiles(&[0.5]).is_empty());
+
+        let now = Instant::now();
+        for value in 1..=100 {
+            window.observe(now, Duration::from_secs(60), value as f64);
+        }
+        assert_eq!(
+            vec![50.0, 99.0, 100.0],
+            window.quantiles(&[0.5, 0.99, 0.999])
+        );
+    }
+
+    #[test]
+    fn nearest_rank_of_sorted_values() {
+        assert_eq!(None, nearest_rank::<u64>(&[], 0.5));
+        assert_eq!(Some(1), nearest_rank(&[1, 2, 3, 4], 0.0));
+        assert_eq!(Some(2), nearest_rank(&[1, 2, 3, 4], 0.5));
+        assert_eq!(Some(4), nearest_rank(&[1, 2, 3, 4], 0.99));
+    }
+
+    #[test]
+    fn window_expiry() {
+        let mut window = QuantileWindow::default();
+        let now = Instant::now();
+        window.observe(now, Duration::from_secs(1), 10.0);
+        window.observe(now + Duration::from_secs(2), Duration::from_secs(1), 1.0);
+        assert_eq!(vec![1.0], window.quantiles(&[0.999]));
+    }
+
+    #[test]
+    fn tracker_observe_and_remove() {
+        let tracker = QuantileTracker::new(Duration::from_secs(60));
+        tracker.observe("quantiles", "ip:0", "get", 0.002);
+        assert!(RESPONSE_TIME_QUANTILE
+            .get_metric_with_label_values(&["quantiles", "ip:0", "get", "0.99"])
+            .is_ok_and(|gauge| gauge.get() == 0.0));
+
+        tracker.export();
+        assert_eq!(
+            0.002,
+            RESPONSE_TIME_QUANTILE
+                .get_metric_with_label_values(&["quantiles", "ip:0", "get", "0.99"])
+                .unwrap()
+                .get()
+        );
+
+        tracker.remove("quantiles", "ip:0");
+        assert!(tracker.windows.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use lazy_static::lazy_static;
+use tracing::info;
+
+lazy_static! {
+    pub static ref READINESS: Readiness = Readiness::new();
+}
+
+// Represent the readiness state of the prober
+//
+// The prober is ready once a first consul discovery succeeded
+// and each node discovered by that pass has been probed at least once
+#[derive(Debug, Default)]
+pub struct Readiness {
+    // Set once the first discovery has succeeded
+    discovered: AtomicBool,
+    // Set once the prober is ready, never reset afterward
+    ready: AtomicBool,
+    // Nodes from the first discovery still waiting for a probe attempt
+    pending_nodes: Mutex<HashSet<String>>,
+    // Set on shutdown while the metrics endpoint is drained, never reset afterward
+    draining: AtomicBool,
+    // Nodes from the first discovery that failed their first probe attempt
+    startup_probe_failures: AtomicUsize,
+}
+
+impl Readiness {
+    /// Returns a not ready Readiness
+    pub fn new() -> Readiness {
+        Readiness::default()
+    }
+
+    /// Register the result of a successful discovery
+    ///
+    /// Only the first discovery is taken into account to compute readiness
+    ///
+    /// # Arguments
+    ///
+    /// * `nodes` - keys of the discovered nodes
+    ///
+    pub fn discovered<'a>(&self, nodes: impl Iterator<Item = &'a String>) {
+        if self.discovered.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut pending_nodes = self.pending_nodes.lock().unwrap();
+        pending_nodes.extend(nodes.cloned());
+        self.discovered.store(true, Ordering::Release);
+        self.update(&pending_nodes);
+    }
+
+    /// Register a probe attempt, successful or not, on a node
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - key of the probed node
+    ///
+    pub fn probe_attempted(&self, node: &str) {
+        if self.ready.load(Ordering::Acquire) {
+            return;
+        }
+
+        let mut pending_nodes = self.pending_nodes.lock().unwrap();
+        pending_nodes.remove(node);
+        self.update(&pending_nodes);
+    }
+
+    /// Register a failed probe attempt on a node, before registering the attempt
+    ///
+    /// Only the first attempt on the nodes of the first discovery is counted
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - key of the probed node
+    ///
+    pub fn probe_failed(&self, node: &str) {
+        if self.ready.load(Ordering::Acquire) {
+            return;
+        }
+
+        if self.pending_nodes.lock().unwrap().contains(node) {
+            self.startup_probe_failures.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    /// Return the number of nodes from the first discovery that failed their first probe
+    pub fn startup_probe_failures(&self) -> usize {
+        self.startup_probe_failures.load(Ordering::Acquire)
+    }
+
+    /// Register a node that is no more probed
+    /// so that it does not block readiness
+    ///
+    /// # Arguments
+    ///
+    /// * `node` - key of the removed node
+    ///
+    pub fn node_removed(&self, node: &str) {
+        self.probe_attempted(node);
+    }
+
+    /// Mark the prober as not ready anymore while the metrics endpoint is drained
+    pub fn drain(&self) {
+        if !self.draining.swap(true, Ordering::AcqRel) {
+            info!("Prober is draining");
+        }
+    }
+
+    /// Return true once the prober is ready, until it is drained
+    pub fn is_ready(&self) -> bool {
+        self.ready.load(Ordering::Acquire) && !self.draining.load(Ordering::Acquire)
+    }
+
+    fn update(&self, pending_nodes: &HashSet<String>) {
+        if self.discovered.load(Ordering::Acquire)
+            && pending_nodes.is_empty()
+            && !self.ready.swap(true, Ordering::AcqRel)
+        {
+            info!("Prober is ready");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::probes::readiness::Readiness;
+
+    #[test]
+    fn not_ready_before_discovery() {
+        let readiness = Readiness::new();
+        readiness.probe_attempted("service:ip:0");
+        assert!(!readiness.is_ready());
+    }
+
+    #[test]
+    fn ready_after_all_nodes_attempted() {
+        let readiness = Readiness::new();
+        let nodes = vec!["service:ip:0".to_string(), "service:ip:1".to_string()];
+        readiness.discovered(nodes.iter());
+        assert!(!readiness.is_ready());
+
+        readiness.probe_attempted("service:ip:0");
+        assert!(!readiness.is_ready());
+
+        readiness.node_removed("service:ip:1");
+        assert!(readiness.is_ready());
+
+        // Later discoveries do not reset readiness
+        let nodes = vec!["service:ip:2".to_string()];
+        readiness.discovered(nodes.iter());
+        assert!(readiness.is_ready());
+    }
+
+    #[test]
+    fn count_startup_probe_failures() {
+        let readiness = Readiness::new();
+        let nodes = vec!["service:ip:0".to_string(), "service:ip:1".to_string()];
+        readiness.discovered(nodes.iter());
+
+        readiness.probe_failed("service:ip:0");
+        readiness.probe_attempted("service:ip:0");
+        // Only the first attempt is counted
+        readiness.probe_failed("service:ip:0");
+        readiness.probe_attempted("service:ip:0");
+        // Nodes from later discoveries are not counted
+        readiness.probe_failed("service:ip:2");
+        assert_eq!(1, readiness.startup_probe_failures());
+
+        readiness.probe_failed("service:ip:1");
+        readiness.probe_attempted("service:ip:1");
+        assert_eq!(2, readiness.startup_probe_failures());
+        assert!(readiness.is_ready());
+    }
+
+    #[test]
+    fn ready_on_empty_discovery() {
+        let readiness = Readiness::new();
+        readiness.discovered(Vec::new().iter());
+        assert!(readiness.is_ready());
+    }
+
+    #[test]
+    fn not_ready_while_draining() {
+        let readiness = Readiness::new();
+        readiness.discovered(Vec::new().iter());
+        assert!(readiness.is_ready());
+
+        readiness.drain();
+        assert!(!readiness.is_ready());
+    }
+}
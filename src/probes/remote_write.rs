@@ -0,0 +1,300 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::client::HttpConnector;
+use hyper::header::{AUTHORIZATION, CONTENT_ENCODING, CONTENT_TYPE, USER_AGENT};
+use hyper::{Body, Client, Method, Request, Uri};
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use prometheus::proto::{LabelPair, MetricFamily, MetricType};
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+use crate::probes::prometheus::{gather, FAILURE_REMOTE_WRITE};
+
+const REMOTE_WRITE_VERSION: &str = "0.1.0";
+
+// Protobuf wire types used by the remote write WriteRequest message
+const WIRE_TYPE_VARINT: u8 = 0;
+const WIRE_TYPE_FIXED64: u8 = 1;
+const WIRE_TYPE_LEN: u8 = 2;
+
+// Represent a prometheus remote write client
+// periodically pushing the content of the default registry
+pub struct RemoteWriteClient {
+    uri: Uri,
+    // Value of the authorization header if any
+    authorization: Option<String>,
+    interval: Duration,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+// A serie of the remote write request
+struct TimeSeries {
+    labels: Vec<(String, String)>,
+    value: f64,
+}
+
+impl RemoteWriteClient {
+    /// Returns a remote write client
+    ///
+    /// # Arguments
+    ///
+    /// * `url` - url of the remote write endpoint
+    /// * `username` - username for basic auth, empty to disable basic auth
+    /// * `password` - password for basic auth
+    /// * `bearer_token` - bearer token, empty to disable it
+    /// * `interval_ms` - interval between each push
+    ///
+    pub fn new(
+        url: &str,
+        username: &str,
+        password: &str,
+        bearer_token: &str,
+        interval_ms: u64,
+    ) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        let uri = url.parse::<Uri>()?;
+        let authorization = if !bearer_token.is_empty() {
+            Some(format!("Bearer {bearer_token}"))
+        } else if !username.is_empty() {
+            Some(format!(
+                "Basic {}",
+                STANDARD.encode(format!("{username}:{password}"))
+            ))
+        } else {
+            None
+        };
+
+        let https = HttpsConnectorBuilder::new()
+            .with_native_roots()
+            .https_or_http()
+            .enable_http1()
+            .build();
+
+        Ok(RemoteWriteClient {
+            uri,
+            authorization,
+            interval: Duration::from_millis(interval_ms),
+            client: Client::builder().build::<_, Body>(https),
+        })
+    }
+
+    /// Push metrics to the remote write endpoint every interval
+    pub async fn run(self) {
+        info!("Push metrics to remote write endpoint {}", self.uri);
+        loop {
+            sleep(self.interval).await;
+            if let Err(issue) = self.push(&gather()).await {
+                FAILURE_REMOTE_WRITE.inc();
+                error!("Failed to push metrics to remote write endpoint: {}", issue);
+            }
+        }
+    }
+
+    /// Push metric families to the remote write endpoint
+    ///
+    /// # Arguments
+    ///
+    /// * `metric_families` - gathered metric families
+    ///
+    async fn push(
+        &self,
+        metric_families: &[MetricFamily],
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        let timestamp_ms = SystemTime::now().duration_since(UNIX_EPOCH)?.as_millis() as i64;
+        let write_request = encode_write_request(metric_families, timestamp_ms);
+        let body = snap::raw::Encoder::new().compress_vec(&write_request)?;
+
+        let mut request = Request::builder()
+            .method(Method::POST)
+            .uri(self.uri.clone())
+            .header(CONTENT_TYPE, "application/x-protobuf")
+            .header(CONTENT_ENCODING, "snappy")
+            .header(USER_AGENT, concat!("probes/", env!("CARGO_PKG_VERSION")))
+            .header("X-Prometheus-Remote-Write-Version", REMOTE_WRITE_VERSION);
+        if let Some(authorization) = &self.authorization {
+            request = request.header(AUTHORIZATION, authorization);
+        }
+
+        debug!("Push {} bytes to remote write endpoint", body.len());
+        let resp = self.client.request(request.body(Body::from(body))?).await?;
+        if !resp.status().is_success() {
+            return Err(format!(
+                "remote write endpoint returned status code {}",
+                resp.status()
+            )
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+/// Convert metric families to remote write series
+///
+/// Histograms and summaries are flattened the same way as the text exposition format
+fn to_time_series(metric_families: &[MetricFamily]) -> Vec<TimeSeries> {
+    let mut time_series = Vec::new();
+    for metric_family in metric_families {
+        let name = metric_family.get_name();
+        for metric in metric_family.get_metric() {
+            let labels = metric.get_label();
+            let mut push = |suffix: &str, extra: Option<(&str, String)>, value: f64| {
+                time_series.push(TimeSeries {
+                    labels: series_labels(&format!("{name}{suffix}"), labels, extra),
+                    value,
+                })
+            };
+            match metric_family.get_field_type() {
+                MetricType::COUNTER => push("", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => push("", None, metric.get_gauge().get_value()),
+                MetricType::UNTYPED => push("", None, metric.get_untyped().get_value()),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        push(
+                            "_bucket",
+                            Some(("le", bucket.get_upper_bound().to_string())),
+                            bucket.get_cumulative_count() as f64,
+                        );
+                    }
+                    push(
+                        "_bucket",
+                        Some(("le", "+Inf".to_string())),
+                        histogram.get_sample_count() as f64,
+                    );
+                    push("_sum", None, histogram.get_sample_sum());
+                    push("_count", None, histogram.get_sample_count() as f64);
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        push(
+                            "",
+                            Some(("quantile", quantile.get_quantile().to_string())),
+                            quantile.get_value(),
+                        );
+                    }
+                    push("_sum", None, summary.get_sample_sum());
+                    push("_count", None, summary.get_sample_count() as f64);
+                }
+            }
+        }
+    }
+    time_series
+}
+
+/// Build the labels of a serie sorted by name as required by remote write
+fn series_labels(
+    name: &str,
+    labels: &[LabelPair],
+    extra: Option<(&str, String)>,
+) -> Vec<(String, String)> {
+    let mut series_labels = vec![("__name__".to_string(), name.to_string())];
+    series_labels.extend(
+        labels
+            .iter()
+            .map(|label| (label.get_name().to_string(), label.get_value().to_string())),
+    );
+    if let Some((label_name, label_value)) = extra {
+        series_labels.push((label_name.to_string(), label_value));
+    }
+    series_labels.sort();
+    series_labels
+}
+
+/// Encode metric families as a protobuf remote write WriteRequest
+///
+/// # Arguments
+///
+/// * `metric_families` - gathered metric families
+/// * `timestamp_ms` - timestamp of all samples in milliseconds
+///
+fn encode_write_request(metric_families: &[MetricFamily], timestamp_ms: i64) -> Vec<u8> {
+    let mut write_request = Vec::new();
+    for time_series in to_time_series(metric_families) {
+        let mut time_series_buf = Vec::new();
+        for (name, value) in time_series.labels {
+            let mut label_buf = Vec::new();
+            encode_len_field(&mut label_buf, 1, name.as_bytes());
+            encode_len_field(&mut label_buf, 2, value.as_bytes());
+            encode_len_field(&mut time_series_buf, 1, &label_buf);
+        }
+
+        let mut sample_buf = Vec::new();
+        encode_key(&mut sample_buf, 1, WIRE_TYPE_FIXED64);
+        sample_buf.extend(time_series.value.to_le_bytes());
+        encode_key(&mut sample_buf, 2, WIRE_TYPE_VARINT);
+        encode_varint(&mut sample_buf, timestamp_ms as u64);
+        encode_len_field(&mut time_series_buf, 2, &sample_buf);
+
+        encode_len_field(&mut write_request, 1, &time_series_buf);
+    }
+    write_request
+}
+
+fn encode_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn encode_key(buf: &mut Vec<u8>, field_number: u8, wire_type: u8) {
+    encode_varint(buf, ((field_number << 3) | wire_type) as u64);
+}
+
+fn encode_len_field(buf: &mut Vec<u8>, field_number: u8, value: &[u8]) {
+    encode_key(buf, field_number, WIRE_TYPE_LEN);
+    encode_varint(buf, value.len() as u64);
+    buf.extend(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{Gauge, Registry};
+    use wiremock::matchers::{header, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::probes::remote_write::{encode_write_request, RemoteWriteClient};
+
+    fn registry() -> Registry {
+        let registry = Registry::new();
+        let gauge = Gauge::new("up", "Up").unwrap();
+        gauge.set(1.0);
+        registry.register(Box::new(gauge)).unwrap();
+        registry
+    }
+
+    #[test]
+    fn test_encode_write_request() {
+        let expected =
+            hex::decode("0a1e0a0e0a085f5f6e616d655f5f12027570120c09000000000000f03f10e807")
+                .expect("Decoding failed");
+        assert_eq!(expected, encode_write_request(&registry().gather(), 1000));
+    }
+
+    #[tokio::test]
+    async fn test_push() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/api/v1/write"))
+            .and(header("Content-Encoding", "snappy"))
+            .and(header("Authorization", "Basic dXNlcjpwYXNz"))
+            .respond_with(ResponseTemplate::new(204))
+            .expect(1)
+            .mount(&mock_server)
+            .await;
+
+        let client = RemoteWriteClient::new(
+            &format!("{}/api/v1/write", mock_server.uri()),
+            "user",
+            "pass",
+            "",
+            1000,
+        )
+        .unwrap();
+        assert!(client.push(&registry().gather()).await.is_ok());
+    }
+}
@@ -0,0 +1,177 @@
+use tokio::runtime::Handle;
+#[cfg(tokio_unstable)]
+use tracing::info;
+#[cfg(not(tokio_unstable))]
+use tracing::warn;
+
+#[cfg(tokio_unstable)]
+use prometheus::core::{Collector, Desc};
+#[cfg(tokio_unstable)]
+use prometheus::proto::MetricFamily;
+#[cfg(tokio_unstable)]
+use prometheus::{IntCounter, IntGauge};
+
+// Collector exporting tokio runtime metrics at scrape time
+// Runtime metrics are only available when built with --cfg tokio_unstable
+#[cfg(tokio_unstable)]
+pub struct TokioCollector {
+    handle: Handle,
+    workers: IntGauge,
+    blocking_threads: IntGauge,
+    idle_blocking_threads: IntGauge,
+    injection_queue_depth: IntGauge,
+    local_queue_depth: IntGauge,
+    blocking_queue_depth: IntGauge,
+    polls: IntCounter,
+    steals: IntCounter,
+}
+
+#[cfg(tokio_unstable)]
+impl TokioCollector {
+    /// Returns a collector for the runtime of the handle
+    ///
+    /// # Arguments
+    ///
+    /// * `handle` - handle of the tokio runtime to monitor
+    ///
+    pub fn new(handle: Handle) -> prometheus::Result<TokioCollector> {
+        Ok(TokioCollector {
+            handle,
+            workers: IntGauge::new("tokio_workers", "Number of worker threads")?,
+            blocking_threads: IntGauge::new(
+                "tokio_blocking_threads",
+                "Number of additional threads spawned by the runtime",
+            )?,
+            idle_blocking_threads: IntGauge::new(
+                "tokio_idle_blocking_threads",
+                "Number of idle threads spawned by the runtime for spawn_blocking",
+            )?,
+            injection_queue_depth: IntGauge::new(
+                "tokio_injection_queue_depth",
+                "Number of tasks currently scheduled in the runtime's injection queue",
+            )?,
+            local_queue_depth: IntGauge::new(
+                "tokio_local_queue_depth",
+                "Number of tasks currently scheduled in the workers' local queues",
+            )?,
+            blocking_queue_depth: IntGauge::new(
+                "tokio_blocking_queue_depth",
+                "Number of tasks currently scheduled in the blocking thread pool",
+            )?,
+            polls: IntCounter::new("tokio_worker_polls_total", "Number of tasks polled")?,
+            steals: IntCounter::new(
+                "tokio_worker_steals_total",
+                "Number of tasks stolen between workers",
+            )?,
+        })
+    }
+
+    fn update(&self) {
+        let metrics = self.handle.metrics();
+        let workers = metrics.num_workers();
+
+        self.workers.set(workers as i64);
+        self.blocking_threads
+            .set(metrics.num_blocking_threads() as i64);
+        self.idle_blocking_threads
+            .set(metrics.num_idle_blocking_threads() as i64);
+        self.injection_queue_depth
+            .set(metrics.injection_queue_depth() as i64);
+        self.blocking_queue_depth
+            .set(metrics.blocking_queue_depth() as i64);
+        self.local_queue_depth.set(
+            (0..workers)
+                .map(|worker| metrics.worker_local_queue_depth(worker) as i64)
+                .sum(),
+        );
+
+        let polls: u64 = (0..workers)
+            .map(|worker| metrics.worker_poll_count(worker))
+            .sum();
+        self.polls.inc_by(polls.saturating_sub(self.polls.get()));
+        let steals: u64 = (0..workers)
+            .map(|worker| metrics.worker_steal_count(worker))
+            .sum();
+        self.steals.inc_by(steals.saturating_sub(self.steals.get()));
+    }
+}
+
+#[cfg(tokio_unstable)]
+impl Collector for TokioCollector {
+    fn desc(&self) -> Vec<&Desc> {
+        [
+            self.workers.desc(),
+            self.blocking_threads.desc(),
+            self.idle_blocking_threads.desc(),
+            self.injection_queue_depth.desc(),
+            self.local_queue_depth.desc(),
+            self.blocking_queue_depth.desc(),
+            self.polls.desc(),
+            self.steals.desc(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+
+    fn collect(&self) -> Vec<MetricFamily> {
+        self.update();
+        [
+            self.workers.collect(),
+            self.blocking_threads.collect(),
+            self.idle_blocking_threads.collect(),
+            self.injection_queue_depth.collect(),
+            self.local_queue_depth.collect(),
+            self.blocking_queue_depth.collect(),
+            self.polls.collect(),
+            self.steals.collect(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect()
+    }
+}
+
+/// Register the tokio runtime metrics in the default prometheus registry
+///
+/// Process metrics (cpu, memory, fds) are already exported by the default registry
+///
+/// # Arguments
+///
+/// * `handle` - handle of the tokio runtime to monitor
+///
+#[cfg(tokio_unstable)]
+pub fn register_runtime_metrics(handle: Handle) -> prometheus::Result<()> {
+    info!("Register tokio runtime metrics");
+    prometheus::register(Box::new(TokioCollector::new(handle)?))
+}
+
+/// Register the tokio runtime metrics in the default prometheus registry
+///
+/// Runtime metrics require to build with --cfg tokio_unstable, nothing is registered otherwise
+///
+/// # Arguments
+///
+/// * `_handle` - handle of the tokio runtime to monitor
+///
+#[cfg(not(tokio_unstable))]
+pub fn register_runtime_metrics(_handle: Handle) -> prometheus::Result<()> {
+    warn!("Tokio runtime metrics require to build with --cfg tokio_unstable");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::runtime::Handle;
+
+    use crate::probes::runtime::register_runtime_metrics;
+
+    #[tokio::test]
+    async fn test_register_runtime_metrics() {
+        assert!(register_runtime_metrics(Handle::current()).is_ok());
+        #[cfg(tokio_unstable)]
+        assert!(prometheus::gather()
+            .iter()
+            .any(|metric_family| metric_family.get_name() == "tokio_workers"));
+    }
+}
@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crate::probes::discovery::ServiceNode;
+
+// Part of the discovered nodes probed by one of several prober replicas
+//
+// Nodes are assigned with a jump consistent hash of their key so that
+// each replica computes the same split without coordination, and only
+// the nodes of the new shards move when the shard count grows
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Shard {
+    index: u32,
+    count: u32,
+}
+
+impl Default for Shard {
+    fn default() -> Self {
+        Shard { index: 0, count: 1 }
+    }
+}
+
+impl fmt::Display for Shard {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.index, self.count)
+    }
+}
+
+/// Hash bytes with 64 bits FNV-1a, stable across builds and platforms
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Jump consistent hash of Lamping and Veach
+///
+/// # Arguments
+///
+/// * `key` - hash of the item to assign
+/// * `buckets` - number of buckets, greater than 0
+///
+/// # Return
+///
+/// * Bucket of the item
+///
+fn jump_hash(mut key: u64, buckets: u32) -> u32 {
+    let mut bucket: i64 = -1;
+    let mut next: i64 = 0;
+    while next < i64::from(buckets) {
+        bucket = next;
+        key = key.wrapping_mul(2862933555777941757).wrapping_add(1);
+        next = ((bucket + 1) as f64 * ((1u64 << 31) as f64 / ((key >> 33) + 1) as f64)) as i64;
+    }
+    bucket as u32
+}
+
+impl Shard {
+    /// Returns a Shard
+    ///
+    /// # Arguments
+    ///
+    /// * `index` - index of the shard probed by this prober, from 0
+    /// * `count` - number of shards the nodes are split into
+    ///
+    pub fn new(index: u32, count: u32) -> Result<Shard, String> {
+        if count == 0 {
+            return Err("Shard count must be greater than 0".to_string());
+        }
+        if index >= count {
+            return Err(format!(
+                "Shard index {index} must be lower than the shard count {count}"
+            ));
+        }
+        Ok(Shard { index, count })
+    }
+
+    /// Return true if the node belongs to this shard
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the node
+    ///
+    pub fn owns(&self, key: &str) -> bool {
+        jump_hash(fnv1a(key.as_bytes()), self.count) == self.index
+    }
+
+    /// Keep the discovered nodes belonging to this shard
+    ///
+    /// # Arguments
+    ///
+    /// * `discovered_nodes` - hash of nodes to probe keyed by their string representation
+    ///
+    /// # Return
+    ///
+    /// * Hash of nodes to probe by this prober
+    ///
+    pub fn apply(
+        &self,
+        discovered_nodes: HashMap<String, ServiceNode>,
+    ) -> HashMap<String, ServiceNode> {
+        if self.count == 1 {
+            return discovered_nodes;
+        }
+        discovered_nodes
+            .into_iter()
+            .filter(|(key, _)| self.owns(key))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use crate::probes::discovery::ServiceNode;
+    use crate::probes::shard::Shard;
+
+    fn discovered_nodes(count: u16) -> HashMap<String, ServiceNode> {
+        (0..count)
+            .map(|port| {
+                let service_node = ServiceNode {
+                    service_name: "cache".to_string(),
+                    ip: "10.0.0.1".to_string(),
+                    port,
+                    protocol: None,
+                };
+                (service_node.to_string(), service_node)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn new() {
+        assert_eq!(Shard::default(), Shard::new(0, 1).unwrap());
+        assert_eq!("2/3", Shard::new(2, 3).unwrap().to_string());
+        assert!(Shard::new(0, 0).is_err());
+        assert!(Shard::new(3, 3).is_err());
+    }
+
+    #[test]
+    fn apply_splits_nodes() {
+        let nodes = discovered_nodes(1000);
+        assert_eq!(nodes, Shard::default().apply(nodes.clone()));
+
+        let shards: Vec<HashMap<String, ServiceNode>> = (0..4)
+            .map(|index| Shard::new(index, 4).unwrap().apply(nodes.clone()))
+            .collect();
+        // Each node is probed by exactly one shard
+        assert_eq!(1000, shards.iter().map(HashMap::len).sum::<usize>());
+        for key in nodes.keys() {
+            assert_eq!(
+                1,
+                shards
+                    .iter()
+                    .filter(|shard| shard.contains_key(key))
+                    .count()
+            );
+        }
+        // Shards are balanced
+        assert!(shards.iter().all(|shard| shard.len() > 150));
+    }
+
+    #[test]
+    fn growing_shard_count_only_moves_nodes_to_new_shard() {
+        let nodes = discovered_nodes(1000);
+        for key in nodes.keys() {
+            let before = (0..3).find(|index| Shard::new(*index, 3).unwrap().owns(key));
+            let after = (0..4).find(|index| Shard::new(*index, 4).unwrap().owns(key));
+            assert!(before == after || after == Some(3));
+        }
+    }
+}
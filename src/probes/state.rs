@@ -0,0 +1,748 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::atomic::{AtomicU64, AtomicU8, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use tokio::sync::Notify;
+
+lazy_static! {
+    pub static ref PROBER_STATE: ProberState = ProberState::new();
+}
+
+// State of a probed node
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum NodeState {
+    // Probe started but no attempt done yet
+    Pending,
+    // Last probe succeeded
+    Up,
+    // Last probe failed
+    Down,
+}
+
+impl NodeState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            NodeState::Pending => "pending",
+            NodeState::Up => "up",
+            NodeState::Down => "down",
+        }
+    }
+}
+
+// What the probe task of a node is currently doing
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TaskState {
+    // Task spawned but not yet running
+    Starting = 0,
+    // Connecting to the node
+    Connecting = 1,
+    // Running the probe action
+    Probing = 2,
+    // Waiting for the next probe
+    Sleeping = 3,
+    // Waiting before reconnecting after a failure
+    Reconnecting = 4,
+}
+
+impl TaskState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TaskState::Starting => "starting",
+            TaskState::Connecting => "connecting",
+            TaskState::Probing => "probing",
+            TaskState::Sleeping => "sleeping",
+            TaskState::Reconnecting => "reconnecting",
+        }
+    }
+
+    fn from_u8(value: u8) -> TaskState {
+        match value {
+            1 => TaskState::Connecting,
+            2 => TaskState::Probing,
+            3 => TaskState::Sleeping,
+            4 => TaskState::Reconnecting,
+            _ => TaskState::Starting,
+        }
+    }
+}
+
+// Current step of the probe task of a node, updated by the task without locking the prober state
+#[derive(Debug)]
+pub struct TaskHeartbeat {
+    // Reference of the heartbeat times
+    created: Instant,
+    task_state: AtomicU8,
+    // Milliseconds between the creation and the last step change
+    last_heartbeat_ms: AtomicU64,
+}
+
+impl Default for TaskHeartbeat {
+    fn default() -> Self {
+        TaskHeartbeat::new()
+    }
+}
+
+impl TaskHeartbeat {
+    /// Returns a TaskHeartbeat of a starting task
+    pub fn new() -> TaskHeartbeat {
+        TaskHeartbeat {
+            created: Instant::now(),
+            task_state: AtomicU8::new(TaskState::Starting as u8),
+            last_heartbeat_ms: AtomicU64::new(0),
+        }
+    }
+
+    /// Register a step change of the probe task
+    ///
+    /// # Arguments
+    ///
+    /// * `task_state` - new step of the probe task
+    ///
+    pub fn beat(&self, task_state: TaskState) {
+        self.task_state.store(task_state as u8, Ordering::Relaxed);
+        self.last_heartbeat_ms
+            .store(self.created.elapsed().as_millis() as u64, Ordering::Relaxed);
+    }
+
+    /// Return the current step of the probe task
+    pub fn task_state(&self) -> TaskState {
+        TaskState::from_u8(self.task_state.load(Ordering::Relaxed))
+    }
+
+    /// Return the time elapsed since the last step change of the probe task
+    pub fn since_last_heartbeat(&self) -> Duration {
+        self.created.elapsed().saturating_sub(Duration::from_millis(
+            self.last_heartbeat_ms.load(Ordering::Relaxed),
+        ))
+    }
+}
+
+// Outcome of the last probe of a node
+#[derive(Debug, Clone)]
+struct LastProbe {
+    state: NodeState,
+    // Error of the last failed probe
+    last_error: Option<String>,
+    // Duration of the last successful probe
+    last_latency: Option<Duration>,
+    // Time of the last probe attempt
+    last_probe: Option<SystemTime>,
+}
+
+// Outcome of the last probe of a node, updated by the probe task without locking the prober state
+#[derive(Debug)]
+pub struct ProbeStatus {
+    last: Mutex<LastProbe>,
+}
+
+impl Default for ProbeStatus {
+    fn default() -> Self {
+        ProbeStatus::new()
+    }
+}
+
+impl ProbeStatus {
+    /// Returns a ProbeStatus of a node not probed yet
+    pub fn new() -> ProbeStatus {
+        ProbeStatus {
+            last: Mutex::new(LastProbe {
+                state: NodeState::Pending,
+                last_error: None,
+                last_latency: None,
+                last_probe: None,
+            }),
+        }
+    }
+
+    /// Register a successful probe
+    ///
+    /// # Arguments
+    ///
+    /// * `latency` - duration of the probe
+    ///
+    pub fn succeeded(&self, latency: Duration) {
+        let mut last_probe = self.last.lock().unwrap();
+        last_probe.state = NodeState::Up;
+        last_probe.last_error = None;
+        last_probe.last_latency = Some(latency);
+        last_probe.last_probe = Some(SystemTime::now());
+    }
+
+    /// Register a failed probe
+    ///
+    /// # Arguments
+    ///
+    /// * `error` - reason of the failure
+    ///
+    pub fn failed(&self, error: String) {
+        let mut last_probe = self.last.lock().unwrap();
+        last_probe.state = NodeState::Down;
+        last_probe.last_error = Some(error);
+        last_probe.last_probe = Some(SystemTime::now());
+    }
+}
+
+// Probed node registered in the prober state
+#[derive(Debug)]
+struct ProbedNode {
+    cluster_name: String,
+    socket: String,
+    // Current step of the probe task, shared with the task
+    heartbeat: Arc<TaskHeartbeat>,
+    // Outcome of the last probe, shared with the task
+    probe_status: Arc<ProbeStatus>,
+}
+
+// Status of a probed node
+#[derive(Debug, Clone)]
+pub struct NodeStatus {
+    pub cluster_name: String,
+    pub socket: String,
+    pub state: NodeState,
+    // Error of the last failed probe
+    pub last_error: Option<String>,
+    // Duration of the last successful probe
+    pub last_latency: Option<Duration>,
+    // Time of the last probe attempt
+    pub last_probe: Option<SystemTime>,
+    // Current step of the probe task, shared with the task
+    pub heartbeat: Arc<TaskHeartbeat>,
+}
+
+impl From<&ProbedNode> for NodeStatus {
+    fn from(probed_node: &ProbedNode) -> Self {
+        let last_probe = probed_node.probe_status.last.lock().unwrap().clone();
+        NodeStatus {
+            cluster_name: probed_node.cluster_name.clone(),
+            socket: probed_node.socket.clone(),
+            state: last_probe.state,
+            last_error: last_probe.last_error,
+            last_latency: last_probe.last_latency,
+            last_probe: last_probe.last_probe,
+            heartbeat: probed_node.heartbeat.clone(),
+        }
+    }
+}
+
+// Represent the state of the prober shared between probes and http endpoints
+#[derive(Debug)]
+pub struct ProberState {
+    started: Instant,
+    last_discovery: RwLock<Option<SystemTime>>,
+    // Only locked to register or unregister nodes, probes update their own status
+    nodes: RwLock<HashMap<String, ProbedNode>>,
+    // Wake the discovery loop for an immediate refresh
+    refresh: Notify,
+    // Number of discovery sources each waiting for a refresh
+    discovery_sources: AtomicUsize,
+    // Services tag set at runtime, overriding the one of the consul and nomad discoveries
+    services_tag: RwLock<Option<String>>,
+}
+
+impl Default for ProberState {
+    fn default() -> Self {
+        ProberState::new()
+    }
+}
+
+/// Convert a system time to seconds since unix epoch
+fn unix_seconds(time: SystemTime) -> f64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs_f64())
+        .unwrap_or(0.0)
+}
+
+/// Group sockets of probed nodes by service, both sorted
+fn group_by_service(nodes: &HashMap<String, NodeStatus>) -> BTreeMap<&str, BTreeSet<&str>> {
+    let mut services: BTreeMap<&str, BTreeSet<&str>> = BTreeMap::new();
+    for node_status in nodes.values() {
+        services
+            .entry(node_status.cluster_name.as_str())
+            .or_default()
+            .insert(node_status.socket.as_str());
+    }
+    services
+}
+
+impl ProberState {
+    /// Returns an empty ProberState
+    pub fn new() -> ProberState {
+        ProberState {
+            started: Instant::now(),
+            last_discovery: RwLock::new(None),
+            nodes: RwLock::new(HashMap::new()),
+            refresh: Notify::new(),
+            discovery_sources: AtomicUsize::new(1),
+            services_tag: RwLock::new(None),
+        }
+    }
+
+    /// Register a successful discovery
+    pub fn discovery_succeeded(&self) {
+        *self.last_discovery.write().unwrap() = Some(SystemTime::now());
+    }
+
+    /// Return the time of the last successful discovery if any
+    pub fn last_discovery(&self) -> Option<SystemTime> {
+        *self.last_discovery.read().unwrap()
+    }
+
+    /// Check a discovery succeeded recently
+    ///
+    /// # Arguments
+    ///
+    /// * `max_discovery_age` - max time since the last successful discovery,
+    ///   or since startup if no discovery succeeded yet
+    ///
+    /// # Return
+    ///
+    /// * Ok or the reason why discovery is considered unhealthy
+    ///
+    pub fn check_discovery(&self, max_discovery_age: Duration) -> Result<(), String> {
+        let discovery_age = match self.last_discovery() {
+            Some(last_discovery) => last_discovery.elapsed().unwrap_or_default(),
+            None => self.uptime(),
+        };
+
+        if discovery_age > max_discovery_age {
+            return Err(format!(
+                "no successful consul discovery for {}s",
+                discovery_age.as_secs()
+            ));
+        }
+
+        Ok(())
+    }
+
+    /// Request an immediate discovery
+    ///
+    /// The request is kept until the discovery loop waits for it,
+    /// each waiting discovery source is woken
+    pub fn request_refresh(&self) {
+        for _ in 0..self.discovery_sources.load(Ordering::Relaxed) {
+            self.refresh.notify_one();
+        }
+    }
+
+    /// Register the number of discovery sources woken by a requested refresh
+    ///
+    /// # Arguments
+    ///
+    /// * `discovery_sources` - number of discovery sources run simultaneously
+    ///
+    pub fn set_discovery_sources(&self, discovery_sources: usize) {
+        self.discovery_sources
+            .store(discovery_sources.max(1), Ordering::Relaxed);
+    }
+
+    /// Change the services tag of the consul and nomad discoveries
+    /// and request an immediate discovery with it
+    ///
+    /// # Arguments
+    ///
+    /// * `services_tag` - comma separated tags selecting the services to probe
+    ///
+    pub fn set_services_tag(&self, services_tag: &str) {
+        *self.services_tag.write().unwrap() = Some(services_tag.to_string());
+        self.request_refresh();
+    }
+
+    /// Return the services tag set at runtime if any
+    pub fn services_tag(&self) -> Option<String> {
+        self.services_tag.read().unwrap().clone()
+    }
+
+    /// Wait for an immediate discovery to be requested
+    pub async fn refresh_requested(&self) {
+        self.refresh.notified().await;
+    }
+
+    /// Return the time elapsed since the prober started
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Register a newly probed node
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the node
+    /// * `cluster_name` - name of the service of the node
+    /// * `socket` - ip:port of the node
+    ///
+    pub fn node_started(&self, key: &str, cluster_name: &str, socket: &str) {
+        self.nodes.write().unwrap().insert(
+            key.to_string(),
+            ProbedNode {
+                cluster_name: cluster_name.to_string(),
+                socket: socket.to_string(),
+                heartbeat: Arc::new(TaskHeartbeat::new()),
+                probe_status: Arc::new(ProbeStatus::new()),
+            },
+        );
+    }
+
+    /// Register a step change of the probe task of a node
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the node
+    /// * `task_state` - new step of the probe task
+    ///
+    pub fn heartbeat(&self, key: &str, task_state: TaskState) {
+        if let Some(heartbeat) = self.task_heartbeat(key) {
+            heartbeat.beat(task_state);
+        }
+    }
+
+    /// Return the heartbeat of the probe task of a node, updated by the task on each step change
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the node
+    ///
+    pub fn task_heartbeat(&self, key: &str) -> Option<Arc<TaskHeartbeat>> {
+        self.nodes
+            .read()
+            .unwrap()
+            .get(key)
+            .map(|probed_node| probed_node.heartbeat.clone())
+    }
+
+    /// Return the outcome of the last probe of a node, updated by the task on each probe
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the node
+    ///
+    pub fn probe_status(&self, key: &str) -> Option<Arc<ProbeStatus>> {
+        self.nodes
+            .read()
+            .unwrap()
+            .get(key)
+            .map(|probed_node| probed_node.probe_status.clone())
+    }
+
+    /// Unregister a node that is no more probed
+    pub fn node_stopped(&self, key: &str) {
+        self.nodes.write().unwrap().remove(key);
+    }
+
+    /// Register a successful probe of a node
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the node
+    /// * `latency` - duration of the probe
+    ///
+    pub fn probe_succeeded(&self, key: &str, latency: Duration) {
+        if let Some(probe_status) = self.probe_status(key) {
+            probe_status.succeeded(latency);
+        }
+    }
+
+    /// Register a failed probe of a node
+    ///
+    /// # Arguments
+    ///
+    /// * `key` - key of the node
+    /// * `error` - reason of the failure
+    ///
+    pub fn probe_failed(&self, key: &str, error: String) {
+        if let Some(probe_status) = self.probe_status(key) {
+            probe_status.failed(error);
+        }
+    }
+
+    /// Return the nodes not probed since a given time
+    ///
+    /// Nodes never probed are ignored as they have no metric yet
+    ///
+    /// # Arguments
+    ///
+    /// * `max_idle` - max time since the last probe attempt
+    ///
+    /// # Return
+    ///
+    /// * key, cluster_name and socket of idle nodes
+    ///
+    pub fn idle_nodes(&self, max_idle: Duration) -> Vec<(String, String, String)> {
+        self.nodes()
+            .into_iter()
+            .filter(|(_, node_status)| {
+                node_status
+                    .last_probe
+                    .is_some_and(|last_probe| last_probe.elapsed().unwrap_or_default() > max_idle)
+            })
+            .map(|(key, node_status)| (key, node_status.cluster_name, node_status.socket))
+            .collect()
+    }
+
+    /// Return a snapshot of the status of all probed nodes
+    pub fn nodes(&self) -> HashMap<String, NodeStatus> {
+        self.nodes
+            .read()
+            .unwrap()
+            .iter()
+            .map(|(key, probed_node)| (key.clone(), NodeStatus::from(probed_node)))
+            .collect()
+    }
+
+    /// Return the probed nodes in the prometheus http_sd format
+    ///
+    /// One target group is returned per service with the service as cluster_name label
+    pub fn to_http_sd(&self) -> Value {
+        let nodes = self.nodes();
+
+        let services = group_by_service(&nodes);
+
+        Value::Array(
+            services
+                .into_iter()
+                .map(|(cluster_name, sockets)| {
+                    json!({
+                        "targets": sockets,
+                        "labels": {"cluster_name": cluster_name},
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// Return the probe tasks as json
+    ///
+    /// Tasks are sorted by node key, a long time since the last heartbeat
+    /// outside of sleeping points to a stuck task
+    pub fn to_tasks_json(&self) -> Value {
+        let nodes = self.nodes();
+        let sorted_nodes: BTreeMap<&String, &NodeStatus> = nodes.iter().collect();
+
+        Value::Array(
+            sorted_nodes
+                .into_iter()
+                .map(|(key, node_status)| {
+                    json!({
+                        "key": key,
+                        "cluster_name": node_status.cluster_name,
+                        "socket": node_status.socket,
+                        "task_state": node_status.heartbeat.task_state().as_str(),
+                        "node_state": node_status.state.as_str(),
+                        "seconds_since_heartbeat": node_status.heartbeat.since_last_heartbeat().as_secs_f64(),
+                    })
+                })
+                .collect(),
+        )
+    }
+
+    /// Return the state of the prober as json
+    ///
+    /// Nodes are grouped by service and sorted to ease reading
+    pub fn to_json(&self) -> Value {
+        let nodes = self.nodes();
+
+        let services = group_by_service(&nodes);
+
+        let sorted_nodes: BTreeMap<&String, &NodeStatus> = nodes.iter().collect();
+        let nodes_json = sorted_nodes
+            .into_iter()
+            .map(|(key, node_status)| {
+                json!({
+                    "key": key,
+                    "cluster_name": node_status.cluster_name,
+                    "socket": node_status.socket,
+                    "state": node_status.state.as_str(),
+                    "last_error": node_status.last_error,
+                    "last_latency_seconds": node_status.last_latency.map(|latency| latency.as_secs_f64()),
+                    "last_probe_timestamp_seconds": node_status.last_probe.map(unix_seconds),
+                })
+            })
+            .collect::<Vec<Value>>();
+
+        json!({
+            "uptime_seconds": self.uptime().as_secs_f64(),
+            "last_discovery_timestamp_seconds": self.last_discovery().map(unix_seconds),
+            "services": services,
+            "nodes": nodes_json,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::probes::state::{NodeState, ProberState, TaskState};
+
+    #[test]
+    fn node_lifecycle() {
+        let state = ProberState::new();
+        state.node_started("service:ip:0", "service", "ip:0");
+        assert_eq!(NodeState::Pending, state.nodes()["service:ip:0"].state);
+
+        state.probe_succeeded("service:ip:0", Duration::from_millis(2));
+        let node_status = &state.nodes()["service:ip:0"];
+        assert_eq!(NodeState::Up, node_status.state);
+        assert_eq!(Some(Duration::from_millis(2)), node_status.last_latency);
+
+        state.probe_failed("service:ip:0", "Timeout".to_string());
+        let node_status = &state.nodes()["service:ip:0"];
+        assert_eq!(NodeState::Down, node_status.state);
+        assert_eq!(Some("Timeout".to_string()), node_status.last_error);
+
+        state.node_stopped("service:ip:0");
+        assert!(state.nodes().is_empty());
+    }
+
+    #[test]
+    fn idle_nodes() {
+        let state = ProberState::new();
+        state.node_started("service:ip:0", "service", "ip:0");
+        state.node_started("service:ip:1", "service", "ip:1");
+        state.probe_succeeded("service:ip:1", Duration::from_millis(2));
+
+        assert!(state.idle_nodes(Duration::from_secs(60)).is_empty());
+        assert_eq!(
+            vec![(
+                "service:ip:1".to_string(),
+                "service".to_string(),
+                "ip:1".to_string()
+            )],
+            state.idle_nodes(Duration::ZERO)
+        );
+    }
+
+    #[test]
+    fn check_discovery() {
+        let state = ProberState::new();
+        assert!(state.check_discovery(Duration::from_secs(60)).is_ok());
+        assert!(state.check_discovery(Duration::ZERO).is_err());
+
+        state.discovery_succeeded();
+        assert!(state.check_discovery(Duration::from_secs(60)).is_ok());
+    }
+
+    #[tokio::test]
+    async fn set_services_tag() {
+        let state = ProberState::new();
+        assert_eq!(None, state.services_tag());
+
+        state.set_services_tag("cache,sessions");
+        assert_eq!(Some("cache,sessions".to_string()), state.services_tag());
+        // The discovery is woken to pick up the new tag
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), state.refresh_requested())
+                .await
+                .is_ok()
+        );
+    }
+
+    #[tokio::test]
+    async fn request_refresh() {
+        let state = ProberState::new();
+        state.request_refresh();
+        assert!(
+            tokio::time::timeout(Duration::from_secs(1), state.refresh_requested())
+                .await
+                .is_ok()
+        );
+        assert!(
+            tokio::time::timeout(Duration::from_millis(10), state.refresh_requested())
+                .await
+                .is_err()
+        );
+
+        // Every waiting source is woken
+        state.set_discovery_sources(2);
+        let sources = async { tokio::join!(state.refresh_requested(), state.refresh_requested()) };
+        let refresh = async {
+            tokio::task::yield_now().await;
+            state.request_refresh();
+        };
+        assert!(tokio::time::timeout(Duration::from_secs(1), async {
+            tokio::join!(sources, refresh)
+        })
+        .await
+        .is_ok());
+    }
+
+    #[test]
+    fn to_http_sd() {
+        let state = ProberState::new();
+        state.node_started("service:ip:1", "service", "ip:1");
+        state.node_started("service:ip:0", "service", "ip:0");
+        state.node_started("other:ip:0", "other", "ip:0");
+
+        assert_eq!(
+            serde_json::json!([
+                {"targets": ["ip:0"], "labels": {"cluster_name": "other"}},
+                {"targets": ["ip:0", "ip:1"], "labels": {"cluster_name": "service"}},
+            ]),
+            state.to_http_sd()
+        );
+    }
+
+    #[test]
+    fn to_tasks_json() {
+        let state = ProberState::new();
+        state.node_started("service:ip:0", "service", "ip:0");
+        assert_eq!("starting", state.to_tasks_json()[0]["task_state"]);
+
+        state.heartbeat("service:ip:0", TaskState::Probing);
+        let tasks = state.to_tasks_json();
+        assert_eq!("service:ip:0", tasks[0]["key"]);
+        assert_eq!("probing", tasks[0]["task_state"]);
+        assert_eq!("pending", tasks[0]["node_state"]);
+        assert!(tasks[0]["seconds_since_heartbeat"].is_f64());
+    }
+
+    #[test]
+    fn task_heartbeat() {
+        let state = ProberState::new();
+        assert!(state.task_heartbeat("service:ip:0").is_none());
+        state.node_started("service:ip:0", "service", "ip:0");
+
+        let heartbeat = state.task_heartbeat("service:ip:0").unwrap();
+        heartbeat.beat(TaskState::Sleeping);
+        assert_eq!(TaskState::Sleeping, heartbeat.task_state());
+        assert!(heartbeat.since_last_heartbeat() < Duration::from_secs(1));
+        assert_eq!("sleeping", state.to_tasks_json()[0]["task_state"]);
+    }
+
+    #[test]
+    fn probe_status() {
+        let state = ProberState::new();
+        assert!(state.probe_status("service:ip:0").is_none());
+        state.node_started("service:ip:0", "service", "ip:0");
+
+        let probe_status = state.probe_status("service:ip:0").unwrap();
+        probe_status.failed("Timeout".to_string());
+        assert_eq!(NodeState::Down, state.nodes()["service:ip:0"].state);
+        probe_status.succeeded(Duration::from_millis(2));
+        let node_status = &state.nodes()["service:ip:0"];
+        assert_eq!(NodeState::Up, node_status.state);
+        assert_eq!(None, node_status.last_error);
+
+        // A stopped node no more shares the status of its task
+        state.node_stopped("service:ip:0");
+        probe_status.failed("Timeout".to_string());
+        assert!(state.nodes().is_empty());
+    }
+
+    #[test]
+    fn to_json() {
+        let state = ProberState::new();
+        state.discovery_succeeded();
+        state.node_started("service:ip:0", "service", "ip:0");
+        state.probe_failed("service:ip:0", "Timeout".to_string());
+
+        let status = state.to_json();
+        assert_eq!(status["services"]["service"][0], "ip:0");
+        assert_eq!(status["nodes"][0]["state"], "down");
+        assert_eq!(status["nodes"][0]["last_error"], "Timeout");
+        assert!(status["nodes"][0]["last_latency_seconds"].is_null());
+        assert!(status["last_discovery_timestamp_seconds"].is_f64());
+    }
+}
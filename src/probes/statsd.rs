@@ -0,0 +1,289 @@
+use std::fmt;
+use std::io;
+use std::net::UdpSocket;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use tracing::{debug, info};
+
+use crate::probes::prometheus::static_labels;
+
+// Global statsd sink, metrics are only emitted once initialized
+static STATSD: OnceLock<StatsdSink> = OnceLock::new();
+
+// Line protocol used to emit metrics
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum StatsdFlavor {
+    // Plain statsd, labels are appended to the metric name
+    #[default]
+    Statsd,
+    // DogStatsD, labels are sent as tags
+    DogStatsd,
+}
+
+impl FromStr for StatsdFlavor {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "statsd" => Ok(StatsdFlavor::Statsd),
+            "dogstatsd" => Ok(StatsdFlavor::DogStatsd),
+            _ => Err(format!(
+                "Invalid statsd flavor {s}, expected one of statsd, dogstatsd"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for StatsdFlavor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatsdFlavor::Statsd => write!(f, "statsd"),
+            StatsdFlavor::DogStatsd => write!(f, "dogstatsd"),
+        }
+    }
+}
+
+// Represent a statsd sink sending metrics over udp
+#[derive(Debug)]
+pub struct StatsdSink {
+    socket: UdpSocket,
+    prefix: String,
+    flavor: StatsdFlavor,
+    // Labels added to all emitted metrics
+    static_labels: Vec<(String, String)>,
+}
+
+impl StatsdSink {
+    /// Returns a statsd sink
+    ///
+    /// # Arguments
+    ///
+    /// * `address` - host:port of the statsd agent
+    /// * `prefix` - prefix of all metric names
+    /// * `flavor` - line protocol used to emit metrics
+    ///
+    pub fn new(address: &str, prefix: &str, flavor: StatsdFlavor) -> io::Result<StatsdSink> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(address)?;
+        // Never block the probes on the statsd agent
+        socket.set_nonblocking(true)?;
+
+        Ok(StatsdSink {
+            socket,
+            prefix: prefix.to_string(),
+            flavor,
+            static_labels: Vec::new(),
+        })
+    }
+
+    /// Add labels to all emitted metrics, labels of a metric taking precedence
+    ///
+    /// # Arguments
+    ///
+    /// * `static_labels` - list of label names and values
+    ///
+    pub fn with_static_labels(mut self, static_labels: Vec<(String, String)>) -> Self {
+        self.static_labels = static_labels;
+        self
+    }
+
+    /// Format a metric line
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the metric
+    /// * `labels` - labels of the metric
+    /// * `value` - value of the metric
+    /// * `metric_type` - statsd type of the metric (c, ms, g)
+    ///
+    fn format(
+        &self,
+        name: &str,
+        labels: &[(&str, &str)],
+        value: &str,
+        metric_type: &str,
+    ) -> String {
+        let mut metric_name = if self.prefix.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}.{}", self.prefix, name)
+        };
+        let labels = labels.iter().copied().chain(
+            self.static_labels
+                .iter()
+                .filter(|(static_name, _)| {
+                    labels.iter().all(|(name, _)| *name != static_name.as_str())
+                })
+                .map(|(static_name, static_value)| (static_name.as_str(), static_value.as_str())),
+        );
+
+        match self.flavor {
+            StatsdFlavor::Statsd => {
+                for (_, label_value) in labels {
+                    metric_name.push('.');
+                    metric_name.push_str(&sanitize(label_value));
+                }
+                format!("{metric_name}:{value}|{metric_type}")
+            }
+            StatsdFlavor::DogStatsd => {
+                let tags = labels
+                    .map(|(label_name, label_value)| {
+                        format!("{}:{}", label_name, label_value.replace([',', '|'], "_"))
+                    })
+                    .collect::<Vec<String>>()
+                    .join(",");
+                if tags.is_empty() {
+                    return format!("{metric_name}:{value}|{metric_type}");
+                }
+                format!("{metric_name}:{value}|{metric_type}|#{tags}")
+            }
+        }
+    }
+
+    fn send(&self, line: String) {
+        if let Err(issue) = self.socket.send(line.as_bytes()) {
+            debug!("Failed to send statsd metric {}: {}", line, issue);
+        }
+    }
+
+    /// Emit a counter
+    pub fn count(&self, name: &str, labels: &[(&str, &str)], value: u64) {
+        self.send(self.format(name, labels, &value.to_string(), "c"));
+    }
+
+    /// Emit a timing
+    pub fn timing(&self, name: &str, labels: &[(&str, &str)], duration: Duration) {
+        let value = format!("{:.3}", duration.as_secs_f64() * 1000.0);
+        self.send(self.format(name, labels, &value, "ms"));
+    }
+}
+
+/// Replace characters having a meaning in the statsd line protocol
+fn sanitize(value: &str) -> String {
+    value.replace(['.', ':', '|', '@', '#', ','], "_")
+}
+
+/// Initialize the global statsd sink
+///
+/// # Arguments
+///
+/// * `address` - host:port of the statsd agent
+/// * `prefix` - prefix of all metric names
+/// * `flavor` - line protocol used to emit metrics
+///
+pub fn init_statsd(
+    address: &str,
+    prefix: &str,
+    flavor: StatsdFlavor,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    info!("Emit {} metrics to {}", flavor, address);
+    let sink =
+        StatsdSink::new(address, prefix, flavor)?.with_static_labels(static_labels().to_vec());
+    STATSD
+        .set(sink)
+        .map_err(|_| "Statsd sink is already initialized".into())
+}
+
+/// Emit a counter on the global statsd sink if initialized
+pub fn count(name: &str, labels: &[(&str, &str)], value: u64) {
+    if let Some(sink) = STATSD.get() {
+        sink.count(name, labels, value);
+    }
+}
+
+/// Emit a timing on the global statsd sink if initialized
+pub fn timing(name: &str, labels: &[(&str, &str)], duration: Duration) {
+    if let Some(sink) = STATSD.get() {
+        sink.timing(name, labels, duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::UdpSocket;
+    use std::str::FromStr;
+    use std::time::Duration;
+
+    use crate::probes::statsd::{StatsdFlavor, StatsdSink};
+
+    const LABELS: [(&str, &str); 2] = [("cluster_name", "memcached"), ("socket", "1.2.3.4:11211")];
+
+    #[test]
+    fn from_str() {
+        assert_eq!(
+            StatsdFlavor::Statsd,
+            StatsdFlavor::from_str("statsd").unwrap()
+        );
+        assert_eq!(
+            StatsdFlavor::DogStatsd,
+            StatsdFlavor::from_str("dogstatsd").unwrap()
+        );
+        assert!(StatsdFlavor::from_str("other").is_err());
+    }
+
+    #[test]
+    fn format_statsd() {
+        let sink = StatsdSink::new("127.0.0.1:8125", "mempoke", StatsdFlavor::Statsd).unwrap();
+        assert_eq!(
+            "mempoke.failure_probe.memcached.1_2_3_4_11211:1|c",
+            sink.format("failure_probe", &LABELS, "1", "c")
+        );
+    }
+
+    #[test]
+    fn format_dogstatsd() {
+        let sink = StatsdSink::new("127.0.0.1:8125", "", StatsdFlavor::DogStatsd).unwrap();
+        assert_eq!(
+            "failure_probe:1|c|#cluster_name:memcached,socket:1.2.3.4:11211",
+            sink.format("failure_probe", &LABELS, "1", "c")
+        );
+        assert_eq!(
+            "failure_services_discovery:1|c",
+            sink.format("failure_services_discovery", &[], "1", "c")
+        );
+    }
+
+    #[test]
+    fn format_static_labels() {
+        let static_labels = vec![
+            ("dc".to_string(), "eu-west".to_string()),
+            ("socket".to_string(), "ignored".to_string()),
+        ];
+        let sink = StatsdSink::new("127.0.0.1:8125", "", StatsdFlavor::DogStatsd)
+            .unwrap()
+            .with_static_labels(static_labels.clone());
+        assert_eq!(
+            "failure_probe:1|c|#cluster_name:memcached,socket:1.2.3.4:11211,dc:eu-west",
+            sink.format("failure_probe", &LABELS, "1", "c")
+        );
+
+        let sink = StatsdSink::new("127.0.0.1:8125", "mempoke", StatsdFlavor::Statsd)
+            .unwrap()
+            .with_static_labels(static_labels);
+        assert_eq!(
+            "mempoke.failure_probe.memcached.1_2_3_4_11211.eu-west:1|c",
+            sink.format("failure_probe", &LABELS, "1", "c")
+        );
+    }
+
+    #[test]
+    fn send_timing() {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let sink = StatsdSink::new(
+            &agent.local_addr().unwrap().to_string(),
+            "mempoke",
+            StatsdFlavor::DogStatsd,
+        )
+        .unwrap();
+        sink.timing("response_time", &LABELS, Duration::from_micros(1500));
+
+        let mut buf = [0; 256];
+        let len = agent.recv(&mut buf).unwrap();
+        assert_eq!(
+            "mempoke.response_time:1.500|ms|#cluster_name:memcached,socket:1.2.3.4:11211",
+            String::from_utf8_lossy(&buf[..len])
+        );
+    }
+}
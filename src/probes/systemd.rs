@@ -0,0 +1,173 @@
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use tokio::time::{interval, Interval, MissedTickBehavior};
+use tracing::{info, warn};
+
+// Set once the http server of the metrics endpoint listens
+static HTTP_LISTENING: AtomicBool = AtomicBool::new(false);
+// Set once a first discovery pass succeeded
+static DISCOVERED: AtomicBool = AtomicBool::new(false);
+// Set once READY=1 has been sent
+static READY_NOTIFIED: AtomicBool = AtomicBool::new(false);
+
+/// Send a state to the service manager, noop if not started by systemd with Type=notify
+///
+/// # Arguments
+///
+/// * `state` - newline separated assignments like READY=1
+///
+/// # Return
+///
+/// * Error if the notify socket is unreachable
+///
+pub fn notify(state: &str) -> io::Result<()> {
+    let notify_socket = match std::env::var("NOTIFY_SOCKET") {
+        Ok(notify_socket) if !notify_socket.is_empty() => notify_socket,
+        _ => return Ok(()),
+    };
+
+    let socket = UnixDatagram::unbound()?;
+    match notify_socket.strip_prefix('@') {
+        // Abstract namespace socket
+        Some(name) => {
+            use std::os::linux::net::SocketAddrExt;
+            let addr = std::os::unix::net::SocketAddr::from_abstract_name(name)?;
+            socket.send_to_addr(state.as_bytes(), &addr)?;
+        }
+        None => {
+            socket.send_to(state.as_bytes(), notify_socket)?;
+        }
+    };
+    Ok(())
+}
+
+/// Send READY=1 once both the http server listens and a first discovery pass succeeded
+fn notify_ready_if_complete() {
+    if HTTP_LISTENING.load(Ordering::Acquire)
+        && DISCOVERED.load(Ordering::Acquire)
+        && !READY_NOTIFIED.swap(true, Ordering::AcqRel)
+    {
+        match notify("READY=1") {
+            Ok(()) => info!("Notified systemd of readiness"),
+            Err(issue) => warn!("Issue to notify systemd of readiness due to {}", issue),
+        }
+    }
+}
+
+/// Register that the http server of the metrics endpoint listens
+pub fn http_listening() {
+    HTTP_LISTENING.store(true, Ordering::Release);
+    notify_ready_if_complete();
+}
+
+/// Register a successful discovery pass
+pub fn discovered() {
+    DISCOVERED.store(true, Ordering::Release);
+    notify_ready_if_complete();
+}
+
+/// Returns the interval between each watchdog ping requested by systemd
+///
+/// Half of WATCHDOG_USEC as recommended by sd_watchdog_enabled,
+/// none if the watchdog is disabled or enabled for another process
+///
+/// # Arguments
+///
+/// * `watchdog_usec` - value of the WATCHDOG_USEC env var
+/// * `watchdog_pid` - value of the WATCHDOG_PID env var
+///
+fn watchdog_interval(watchdog_usec: Option<&str>, watchdog_pid: Option<&str>) -> Option<Duration> {
+    if let Some(watchdog_pid) = watchdog_pid {
+        if watchdog_pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None;
+        }
+    }
+    watchdog_usec
+        .and_then(|watchdog_usec| watchdog_usec.parse::<u64>().ok())
+        .filter(|watchdog_usec| *watchdog_usec > 0)
+        .map(|watchdog_usec| Duration::from_micros(watchdog_usec) / 2)
+}
+
+// Periodic WATCHDOG=1 pings, never ticking if the watchdog is disabled
+pub struct Watchdog {
+    interval: Option<Interval>,
+}
+
+impl Watchdog {
+    /// Returns a Watchdog configured from the WATCHDOG_USEC and WATCHDOG_PID env vars
+    pub fn from_env() -> Self {
+        let watchdog_usec = std::env::var("WATCHDOG_USEC").ok();
+        let watchdog_pid = std::env::var("WATCHDOG_PID").ok();
+        let interval = watchdog_interval(watchdog_usec.as_deref(), watchdog_pid.as_deref()).map(
+            |watchdog_interval| {
+                info!("Ping systemd watchdog every {:?}", watchdog_interval);
+                let mut ticks = interval(watchdog_interval);
+                ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+                ticks
+            },
+        );
+        Watchdog { interval }
+    }
+
+    /// Wait for the next tick then ping the watchdog
+    pub async fn ping(&mut self) {
+        match self.interval.as_mut() {
+            Some(interval) => {
+                interval.tick().await;
+                if let Err(issue) = notify("WATCHDOG=1") {
+                    warn!("Issue to ping systemd watchdog due to {}", issue);
+                }
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+    use std::time::Duration;
+
+    use crate::probes::systemd::{notify, watchdog_interval};
+
+    #[test]
+    fn watchdog_interval_from_env() {
+        let pid = std::process::id().to_string();
+        assert_eq!(None, watchdog_interval(None, None));
+        assert_eq!(None, watchdog_interval(Some("0"), None));
+        assert_eq!(None, watchdog_interval(Some("invalid"), None));
+        assert_eq!(
+            Some(Duration::from_secs(15)),
+            watchdog_interval(Some("30000000"), None)
+        );
+        assert_eq!(
+            Some(Duration::from_secs(15)),
+            watchdog_interval(Some("30000000"), Some(&pid))
+        );
+        assert_eq!(None, watchdog_interval(Some("30000000"), Some("1")));
+    }
+
+    #[test]
+    fn notify_socket() {
+        let dir = std::env::temp_dir().join(format!("probes-notify-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("notify.sock");
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        std::env::set_var("NOTIFY_SOCKET", &path);
+        notify("READY=1").unwrap();
+        std::env::remove_var("NOTIFY_SOCKET");
+
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(b"READY=1", &buf[..len]);
+        // Noop without notify socket
+        notify("READY=1").unwrap();
+
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
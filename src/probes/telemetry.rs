@@ -0,0 +1,202 @@
+use std::fmt;
+use std::str::FromStr;
+
+use tracing_subscriber::filter::ParseError;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
+use tracing_subscriber::{EnvFilter, Layer};
+
+#[cfg(feature = "otlp")]
+use opentelemetry::sdk::trace::Tracer;
+#[cfg(feature = "otlp")]
+use opentelemetry::trace::TraceError;
+#[cfg(feature = "otlp")]
+use tracing_subscriber::filter::LevelFilter;
+
+// Output format of the logs
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum LogFormat {
+    // Human readable lines
+    #[default]
+    Text,
+    // One json object per line for log pipelines
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!(
+                "Invalid log format {s}, expected one of text, json"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for LogFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LogFormat::Text => write!(f, "text"),
+            LogFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Build the filter of the logs
+///
+/// # Arguments
+///
+/// * `log_level` - level or directives like info,probes=debug, empty to use RUST_LOG env var
+///
+/// # Return
+///
+/// * Filter of the logs or an error if the directives are invalid
+///
+fn log_filter(log_level: &str) -> Result<EnvFilter, ParseError> {
+    if log_level.is_empty() {
+        Ok(EnvFilter::from_default_env())
+    } else {
+        EnvFilter::try_new(log_level)
+    }
+}
+
+/// Create an OTLP tracer exporting spans in batch to an OpenTelemetry collector
+///
+/// Must be called from within a tokio runtime
+///
+/// # Arguments
+///
+/// * `service_name` - name of the service set on exported spans
+/// * `otlp_endpoint` - grpc endpoint of the OpenTelemetry collector
+///
+#[cfg(feature = "otlp")]
+fn otlp_tracer(service_name: &str, otlp_endpoint: &str) -> Result<Tracer, TraceError> {
+    use opentelemetry::sdk::{trace, Resource};
+    use opentelemetry::KeyValue;
+    use opentelemetry_otlp::WithExportConfig;
+
+    opentelemetry_otlp::new_pipeline()
+        .tracing()
+        .with_exporter(
+            opentelemetry_otlp::new_exporter()
+                .tonic()
+                .with_endpoint(otlp_endpoint),
+        )
+        .with_trace_config(
+            trace::config().with_resource(Resource::new(vec![KeyValue::new(
+                "service.name",
+                service_name.to_string(),
+            )])),
+        )
+        .install_batch(opentelemetry::runtime::Tokio)
+}
+
+/// Install the global tracing subscriber
+///
+/// * logs are written as text or json lines
+/// * logs are filtered based on the log level, or RUST_LOG env var if not set
+/// * tokio console layer is added if enabled
+/// * spans are exported through OTLP if an endpoint is set (requires the otlp feature)
+///
+/// Must be called from within a tokio runtime when OTLP export is enabled
+///
+/// # Arguments
+///
+/// * `service_name` - name of the service set on exported spans
+/// * `tokio_console` - enable console subscriber for the tokio console
+/// * `log_format` - output format of the logs
+/// * `log_level` - level or directives filtering the logs, empty to use RUST_LOG env var
+/// * `log_to_stderr` - write the logs to stderr instead of stdout
+/// * `otlp_endpoint` - grpc endpoint of the OpenTelemetry collector, empty to disable export
+///
+pub fn init_tracing(
+    service_name: &str,
+    tokio_console: bool,
+    log_format: LogFormat,
+    log_level: &str,
+    log_to_stderr: bool,
+    otlp_endpoint: &str,
+) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    // Used to debug trace async task with https://github.com/tokio-rs/console
+    let console_layer = if tokio_console {
+        Some(console_subscriber::spawn())
+    } else {
+        None
+    };
+    let writer = if log_to_stderr {
+        BoxMakeWriter::new(std::io::stderr)
+    } else {
+        BoxMakeWriter::new(std::io::stdout)
+    };
+    let fmt_layer = match log_format {
+        LogFormat::Text => tracing_subscriber::fmt::layer().with_writer(writer).boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_writer(writer)
+            .boxed(),
+    }
+    .with_filter(log_filter(log_level)?);
+    let registry = tracing_subscriber::registry()
+        .with(console_layer)
+        .with(fmt_layer);
+
+    #[cfg(feature = "otlp")]
+    {
+        let otlp_layer = if otlp_endpoint.is_empty() {
+            None
+        } else {
+            Some(
+                tracing_opentelemetry::layer()
+                    .with_tracer(otlp_tracer(service_name, otlp_endpoint)?)
+                    .with_filter(LevelFilter::INFO),
+            )
+        };
+        registry.with(otlp_layer).try_init()?;
+    }
+
+    #[cfg(not(feature = "otlp"))]
+    {
+        if !otlp_endpoint.is_empty() {
+            return Err(format!(
+                "OTLP export of {service_name} spans requires to build with the otlp feature"
+            )
+            .into());
+        }
+        registry.try_init()?;
+    }
+
+    Ok(())
+}
+
+/// Flush and stop exporting spans
+pub fn shutdown_tracing() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use crate::probes::telemetry::{log_filter, LogFormat};
+
+    #[test]
+    fn from_str() {
+        assert_eq!(LogFormat::Text, LogFormat::from_str("text").unwrap());
+        assert_eq!(LogFormat::Json, LogFormat::from_str("json").unwrap());
+        assert!(LogFormat::from_str("other").is_err());
+    }
+
+    #[test]
+    fn log_filter_directives() {
+        assert!(log_filter("").is_ok());
+        assert!(log_filter("debug").is_ok());
+        assert!(log_filter("info,probes=debug").is_ok());
+        assert!(log_filter("probes=verbose").is_err());
+    }
+}
@@ -0,0 +1,664 @@
+use std::io;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BufMut, BytesMut};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::{info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const PROTOCOL_HEADER: &[u8] = b"AMQP\x00\x00\x09\x01";
+
+const FRAME_METHOD: u8 = 1;
+const FRAME_HEADER: u8 = 2;
+const FRAME_BODY: u8 = 3;
+const FRAME_HEARTBEAT: u8 = 8;
+const FRAME_END: u8 = 0xce;
+const FRAME_HEADER_LEN: usize = 7;
+
+// (class id, method id)
+const CONNECTION_START: (u16, u16) = (10, 10);
+const CONNECTION_START_OK: (u16, u16) = (10, 11);
+const CONNECTION_TUNE: (u16, u16) = (10, 30);
+const CONNECTION_TUNE_OK: (u16, u16) = (10, 31);
+const CONNECTION_OPEN: (u16, u16) = (10, 40);
+const CONNECTION_OPEN_OK: (u16, u16) = (10, 41);
+const CONNECTION_CLOSE: (u16, u16) = (10, 50);
+const CHANNEL_OPEN: (u16, u16) = (20, 10);
+const CHANNEL_OPEN_OK: (u16, u16) = (20, 11);
+const CHANNEL_CLOSE: (u16, u16) = (20, 40);
+const CHANNEL_CLOSE_OK: (u16, u16) = (20, 41);
+const QUEUE_DECLARE: (u16, u16) = (50, 10);
+const QUEUE_DECLARE_OK: (u16, u16) = (50, 11);
+const BASIC_PUBLISH: (u16, u16) = (60, 40);
+const BASIC_GET: (u16, u16) = (60, 70);
+const BASIC_GET_OK: (u16, u16) = (60, 71);
+const BASIC_GET_EMPTY: (u16, u16) = (60, 72);
+
+const CHANNEL: u16 = 1;
+const CANARY_BODY: &[u8] = b"probes_canary_message";
+// Canary messages are dropped if not consumed
+const CANARY_TTL_MS: &str = "60000";
+
+// Max size of a received frame, also sent as frame max on tune
+const MAX_FRAME_SIZE: u32 = 128 * 1024;
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// Credentials, virtual host and canary queue of the probe, only set once from main
+static RABBITMQ_CONFIG: OnceLock<RabbitmqConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+struct RabbitmqConfig {
+    username: String,
+    password: String,
+    vhost: String,
+    // Empty to disable the publish and consume round trip
+    canary_queue: String,
+}
+
+impl Default for RabbitmqConfig {
+    fn default() -> Self {
+        RabbitmqConfig {
+            username: "guest".to_string(),
+            password: "guest".to_string(),
+            vhost: "/".to_string(),
+            canary_queue: "".to_string(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum RabbitmqClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid frame: {0}")]
+    InvalidFrame(String),
+    #[error("Closed by server with {reply_code} on {cmd_type}: {reply_text}")]
+    Closed {
+        cmd_type: String,
+        reply_code: u16,
+        reply_text: String,
+    },
+    #[error("Unexpected method {method:?} on {cmd_type}.")]
+    UnexpectedMethod {
+        cmd_type: String,
+        method: (u16, u16),
+    },
+    #[error("Canary message not received.")]
+    CanaryNotReceived,
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Set the credentials, virtual host and canary queue of the probe
+///
+/// # Arguments
+///
+/// * `username` - user authenticated with PLAIN
+/// * `password` - password of the user
+/// * `vhost` - virtual host opened
+/// * `canary_queue` - queue used for the publish and consume round trip, empty to disable it
+///
+pub fn set_config(
+    username: &str,
+    password: &str,
+    vhost: &str,
+    canary_queue: &str,
+) -> Result<(), String> {
+    info!("Probe rabbitmq nodes as {} on vhost {}", username, vhost);
+    RABBITMQ_CONFIG
+        .set(RabbitmqConfig {
+            username: username.to_string(),
+            password: password.to_string(),
+            vhost: vhost.to_string(),
+            canary_queue: canary_queue.to_string(),
+        })
+        .map_err(|_| "Rabbitmq config is already initialized".to_string())
+}
+
+fn put_shortstr(buffer: &mut BytesMut, value: &str) {
+    buffer.put_u8(value.len() as u8);
+    buffer.put_slice(value.as_bytes());
+}
+
+fn put_longstr(buffer: &mut BytesMut, value: &[u8]) {
+    buffer.put_u32(value.len() as u32);
+    buffer.put_slice(value);
+}
+
+fn get_shortstr(payload: &mut &[u8]) -> Result<String, RabbitmqClientError> {
+    if payload.is_empty() {
+        return Err(invalid("truncated short string"));
+    }
+    let len = payload.get_u8() as usize;
+    if payload.len() < len {
+        return Err(invalid("truncated short string"));
+    }
+    let value = String::from_utf8_lossy(&payload[..len]).to_string();
+    payload.advance(len);
+    Ok(value)
+}
+
+fn invalid(message: &str) -> RabbitmqClientError {
+    RabbitmqClientError::InvalidFrame(message.to_string())
+}
+
+/// Encode a frame
+///
+/// # Arguments
+///
+/// * `frame_type` - type of the frame
+/// * `channel` - channel of the frame, 0 for the connection
+/// * `payload` - payload of the frame
+///
+fn encode_frame(frame_type: u8, channel: u16, payload: &[u8]) -> Vec<u8> {
+    let mut frame = BytesMut::with_capacity(FRAME_HEADER_LEN + payload.len() + 1);
+    frame.put_u8(frame_type);
+    frame.put_u16(channel);
+    frame.put_u32(payload.len() as u32);
+    frame.put_slice(payload);
+    frame.put_u8(FRAME_END);
+    frame.to_vec()
+}
+
+/// Encode a method frame
+///
+/// # Arguments
+///
+/// * `channel` - channel of the frame, 0 for the connection
+/// * `method` - class and method ids
+/// * `arguments` - encoded arguments of the method
+///
+fn encode_method(channel: u16, method: (u16, u16), arguments: &[u8]) -> Vec<u8> {
+    let mut payload = BytesMut::with_capacity(4 + arguments.len());
+    payload.put_u16(method.0);
+    payload.put_u16(method.1);
+    payload.put_slice(arguments);
+    encode_frame(FRAME_METHOD, channel, &payload)
+}
+
+/// Parse the reply code and text of a close method
+fn parse_close(cmd_type: &str, mut arguments: &[u8]) -> RabbitmqClientError {
+    if arguments.len() < 2 {
+        return invalid("truncated close");
+    }
+    let reply_code = arguments.get_u16();
+    let reply_text = get_shortstr(&mut arguments).unwrap_or_default();
+    RabbitmqClientError::Closed {
+        cmd_type: cmd_type.to_string(),
+        reply_code,
+        reply_text,
+    }
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, RabbitmqClientError> {
+    let stream = TcpStream::connect(addr).await?;
+    let mut client = Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stream,
+        config: RABBITMQ_CONFIG.get().cloned().unwrap_or_default(),
+        queue_declared: false,
+    };
+    client.handshake().await?;
+    Ok(client)
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    stream: TcpStream,
+    config: RabbitmqConfig,
+    // Canary queue is declared once per connection
+    queue_declared: bool,
+}
+
+impl Client {
+    /// Probe action
+    /// * open one channel
+    /// * publish and get one canary message if enabled
+    /// * close the channel
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), RabbitmqClientError> {
+        self.channel_open().await?;
+        if !self.config.canary_queue.is_empty() {
+            if !self.queue_declared {
+                self.queue_declare().await?;
+                self.queue_declared = true;
+            }
+            self.round_trip().await?;
+        }
+        self.channel_close().await
+    }
+
+    /// Connection handshake, only done once per connection
+    async fn handshake(&mut self) -> Result<(), RabbitmqClientError> {
+        let start = Instant::now();
+        match tokio::time::timeout(TIMEOUT, self.handle_handshake()).await {
+            Ok(handshake_res) => {
+                let status = match &handshake_res {
+                    Ok(_) => "OK".to_string(),
+                    Err(RabbitmqClientError::Closed { reply_code, .. }) => reply_code.to_string(),
+                    Err(_) => "Error".to_string(),
+                };
+                self.request_metrics.count_request(&status, "handshake");
+                self.request_metrics
+                    .observe_response_time("handshake", start.elapsed());
+                handshake_res
+            }
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time("handshake", TIMEOUT);
+                Err(RabbitmqClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    async fn handle_handshake(&mut self) -> Result<(), RabbitmqClientError> {
+        self.stream.write_all(PROTOCOL_HEADER).await?;
+        self.expect_method("handshake", CONNECTION_START).await?;
+
+        let mut arguments = BytesMut::new();
+        // Empty client properties
+        arguments.put_u32(0);
+        put_shortstr(&mut arguments, "PLAIN");
+        let response = format!("\0{}\0{}", self.config.username, self.config.password);
+        put_longstr(&mut arguments, response.as_bytes());
+        put_shortstr(&mut arguments, "en_US");
+        self.write_method(0, CONNECTION_START_OK, &arguments)
+            .await?;
+
+        let mut tune = self.expect_method("handshake", CONNECTION_TUNE).await?;
+        if tune.len() < 8 {
+            return Err(invalid("truncated tune"));
+        }
+        let channel_max = tune.get_u16();
+        let frame_max = match tune.get_u32() {
+            0 => MAX_FRAME_SIZE,
+            frame_max => frame_max.min(MAX_FRAME_SIZE),
+        };
+        let mut arguments = BytesMut::new();
+        arguments.put_u16(channel_max);
+        arguments.put_u32(frame_max);
+        // Heartbeats disabled, the probe interval keeps the connection active
+        arguments.put_u16(0);
+        self.write_method(0, CONNECTION_TUNE_OK, &arguments).await?;
+
+        let mut arguments = BytesMut::new();
+        put_shortstr(&mut arguments, &self.config.vhost);
+        put_shortstr(&mut arguments, "");
+        arguments.put_u8(0);
+        self.write_method(0, CONNECTION_OPEN, &arguments).await?;
+        self.expect_method("handshake", CONNECTION_OPEN_OK).await?;
+        Ok(())
+    }
+
+    /// Channel open call
+    pub async fn channel_open(&mut self) -> Result<(), RabbitmqClientError> {
+        let mut arguments = BytesMut::new();
+        put_shortstr(&mut arguments, "");
+        self.handler_with_timeout(
+            "channel_open",
+            CHANNEL_OPEN,
+            arguments.to_vec(),
+            CHANNEL_OPEN_OK,
+        )
+        .await
+    }
+
+    /// Channel close call
+    pub async fn channel_close(&mut self) -> Result<(), RabbitmqClientError> {
+        let mut arguments = BytesMut::new();
+        arguments.put_u16(200);
+        put_shortstr(&mut arguments, "probe done");
+        arguments.put_u32(0);
+        self.handler_with_timeout(
+            "channel_close",
+            CHANNEL_CLOSE,
+            arguments.to_vec(),
+            CHANNEL_CLOSE_OK,
+        )
+        .await
+    }
+
+    /// Queue declare call of the canary queue, messages expire if not consumed
+    pub async fn queue_declare(&mut self) -> Result<(), RabbitmqClientError> {
+        let mut arguments = BytesMut::new();
+        arguments.put_u16(0);
+        put_shortstr(&mut arguments, &self.config.canary_queue);
+        // Not passive, durable, exclusive nor auto delete, wait for the reply
+        arguments.put_u8(0);
+        let mut table = BytesMut::new();
+        put_shortstr(&mut table, "x-message-ttl");
+        table.put_u8(b'S');
+        put_longstr(&mut table, CANARY_TTL_MS.as_bytes());
+        put_longstr(&mut arguments, &table);
+        self.handler_with_timeout(
+            "queue_declare",
+            QUEUE_DECLARE,
+            arguments.to_vec(),
+            QUEUE_DECLARE_OK,
+        )
+        .await
+    }
+
+    /// Publish a canary message on the default exchange and get it back
+    pub async fn round_trip(&mut self) -> Result<(), RabbitmqClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_round_trip()).await {
+            Ok(round_trip_res) => round_trip_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time("round_trip", TIMEOUT);
+                Err(RabbitmqClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    async fn handle_round_trip(&mut self) -> Result<(), RabbitmqClientError> {
+        let start = Instant::now();
+        let mut arguments = BytesMut::new();
+        arguments.put_u16(0);
+        put_shortstr(&mut arguments, "");
+        put_shortstr(&mut arguments, &self.config.canary_queue);
+        // Not mandatory nor immediate
+        arguments.put_u8(0);
+        let mut request = encode_method(CHANNEL, BASIC_PUBLISH, &arguments);
+        let mut header = BytesMut::new();
+        header.put_u16(BASIC_PUBLISH.0);
+        header.put_u16(0);
+        header.put_u64(CANARY_BODY.len() as u64);
+        // No property
+        header.put_u16(0);
+        request.extend(encode_frame(FRAME_HEADER, CHANNEL, &header));
+        request.extend(encode_frame(FRAME_BODY, CHANNEL, CANARY_BODY));
+
+        let mut arguments = BytesMut::new();
+        arguments.put_u16(0);
+        put_shortstr(&mut arguments, &self.config.canary_queue);
+        // No ack
+        arguments.put_u8(1);
+        request.extend(encode_method(CHANNEL, BASIC_GET, &arguments));
+        self.stream.write_all(&request).await?;
+
+        let (method, _) = self.read_method().await?;
+        let result = match method {
+            BASIC_GET_OK => {
+                // Content header and body of the message
+                self.read_frame().await?;
+                let (_, body) = self.read_frame().await?;
+                if body == CANARY_BODY {
+                    Ok(())
+                } else {
+                    Err(RabbitmqClientError::CanaryNotReceived)
+                }
+            }
+            BASIC_GET_EMPTY => Err(RabbitmqClientError::CanaryNotReceived),
+            method => Err(RabbitmqClientError::UnexpectedMethod {
+                cmd_type: "round_trip".to_string(),
+                method,
+            }),
+        };
+        let status = match &result {
+            Ok(_) => "OK",
+            Err(_) => "NotReceived",
+        };
+        self.request_metrics.count_request(status, "round_trip");
+        self.request_metrics
+            .observe_response_time("round_trip", start.elapsed());
+        result
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        method: (u16, u16),
+        arguments: Vec<u8>,
+        expected: (u16, u16),
+    ) -> Result<(), RabbitmqClientError> {
+        match tokio::time::timeout(
+            TIMEOUT,
+            self.handle_request(cmd_type, method, arguments, expected),
+        )
+        .await
+        {
+            Ok(response_res) => response_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(RabbitmqClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform rabbitmq request on the probe channel
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    /// * `method` - class and method ids of the request
+    /// * `arguments` - encoded arguments of the request
+    /// * `expected` - class and method ids of the expected reply
+    ///
+    #[instrument(skip(self, arguments))]
+    pub async fn handle_request(
+        &mut self,
+        cmd_type: &str,
+        method: (u16, u16),
+        arguments: Vec<u8>,
+        expected: (u16, u16),
+    ) -> Result<(), RabbitmqClientError> {
+        let start = Instant::now();
+        self.write_method(CHANNEL, method, &arguments).await?;
+        let result = self.expect_method(cmd_type, expected).await.map(|_| ());
+        let status = match &result {
+            Ok(_) => "OK".to_string(),
+            Err(RabbitmqClientError::Closed { reply_code, .. }) => reply_code.to_string(),
+            Err(_) => "Error".to_string(),
+        };
+        self.request_metrics.count_request(&status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, start.elapsed());
+        result
+    }
+
+    async fn write_method(
+        &mut self,
+        channel: u16,
+        method: (u16, u16),
+        arguments: &[u8],
+    ) -> Result<(), RabbitmqClientError> {
+        self.stream
+            .write_all(&encode_method(channel, method, arguments))
+            .await?;
+        Ok(())
+    }
+
+    /// Read a method and check it is the expected one
+    ///
+    /// # Return
+    ///
+    /// * Arguments of the method
+    ///
+    async fn expect_method(
+        &mut self,
+        cmd_type: &str,
+        expected: (u16, u16),
+    ) -> Result<Vec<u8>, RabbitmqClientError> {
+        let (method, arguments) = self.read_method().await?;
+        match method {
+            method if method == expected => Ok(arguments),
+            CONNECTION_CLOSE | CHANNEL_CLOSE => Err(parse_close(cmd_type, &arguments)),
+            method => Err(RabbitmqClientError::UnexpectedMethod {
+                cmd_type: cmd_type.to_string(),
+                method,
+            }),
+        }
+    }
+
+    /// Read the next method frame, heartbeats are skipped
+    async fn read_method(&mut self) -> Result<((u16, u16), Vec<u8>), RabbitmqClientError> {
+        loop {
+            let (frame_type, payload) = self.read_frame().await?;
+            match frame_type {
+                FRAME_HEARTBEAT => continue,
+                FRAME_METHOD if payload.len() >= 4 => {
+                    let mut method = &payload[..4];
+                    let method = (method.get_u16(), method.get_u16());
+                    return Ok((method, payload[4..].to_vec()));
+                }
+                frame_type => {
+                    return Err(RabbitmqClientError::InvalidFrame(format!(
+                        "unexpected frame type {frame_type}"
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Read a frame
+    ///
+    /// # Return
+    ///
+    /// * Type and payload of the frame
+    ///
+    async fn read_frame(&mut self) -> Result<(u8, Vec<u8>), RabbitmqClientError> {
+        let mut header = [0; FRAME_HEADER_LEN];
+        self.stream.read_exact(&mut header).await?;
+        let mut header = &header[..];
+        let frame_type = header.get_u8();
+        // Channel
+        header.advance(2);
+        let size = header.get_u32();
+        if size > MAX_FRAME_SIZE {
+            return Err(RabbitmqClientError::InvalidFrame(format!(
+                "invalid size {size}"
+            )));
+        }
+        let mut payload = vec![0; size as usize + 1];
+        self.stream.read_exact(&mut payload).await?;
+        if payload.pop() != Some(FRAME_END) {
+            return Err(invalid("missing frame end"));
+        }
+        Ok((frame_type, payload))
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+    use crate::rabbitmq::{
+        connect, encode_method, parse_close, RabbitmqClientError, CHANNEL_CLOSE_OK,
+        CHANNEL_OPEN_OK, CONNECTION_OPEN_OK, CONNECTION_START, CONNECTION_TUNE,
+    };
+
+    async fn read_frame(socket: &mut TcpStream) -> (u8, Vec<u8>) {
+        let mut header = [0; 7];
+        socket.read_exact(&mut header).await.unwrap();
+        let size = u32::from_be_bytes([header[3], header[4], header[5], header[6]]);
+        let mut payload = vec![0; size as usize + 1];
+        socket.read_exact(&mut payload).await.unwrap();
+        payload.pop();
+        (header[0], payload)
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(
+            vec![1, 0, 1, 0, 0, 0, 5, 0, 20, 0, 10, 0, 0xce],
+            encode_method(1, (20, 10), &[0])
+        );
+    }
+
+    #[test]
+    fn parse() {
+        let mut arguments = vec![1, 0x93, 14];
+        arguments.extend_from_slice(b"ACCESS_REFUSED");
+        assert!(matches!(
+            parse_close("handshake", &arguments),
+            RabbitmqClientError::Closed {
+                reply_code: 403,
+                ..
+            }
+        ));
+        assert!(matches!(
+            parse_close("handshake", &[1]),
+            RabbitmqClientError::InvalidFrame(_)
+        ));
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut protocol_header = [0; 8];
+            socket.read_exact(&mut protocol_header).await.unwrap();
+            assert_eq!(b"AMQP\x00\x00\x09\x01", &protocol_header);
+
+            let start = encode_method(0, CONNECTION_START, &[0, 9, 0, 0, 0, 0, 0, 0, 0, 0]);
+            socket.write_all(&start).await.unwrap();
+            // Start ok
+            read_frame(&mut socket).await;
+            let tune = encode_method(0, CONNECTION_TUNE, &[0, 10, 0, 2, 0, 0, 0, 60]);
+            socket.write_all(&tune).await.unwrap();
+            // Tune ok and open
+            read_frame(&mut socket).await;
+            read_frame(&mut socket).await;
+            socket
+                .write_all(&encode_method(0, CONNECTION_OPEN_OK, &[0]))
+                .await
+                .unwrap();
+
+            read_frame(&mut socket).await;
+            socket
+                .write_all(&encode_method(1, CHANNEL_OPEN_OK, &[0, 0, 0, 0]))
+                .await
+                .unwrap();
+            read_frame(&mut socket).await;
+            socket
+                .write_all(&encode_method(1, CHANNEL_CLOSE_OK, &[]))
+                .await
+                .unwrap();
+        });
+
+        let mut client = connect("rabbitmq_probe", &addr).await.unwrap();
+        client.probe().await.unwrap();
+        for cmd_type in ["handshake", "channel_open", "channel_close"] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&["rabbitmq_probe", &addr, "OK", cmd_type])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+}
@@ -0,0 +1,402 @@
+use std::io;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use bytes::{Buf, BytesMut};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt, BufWriter};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::{info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const KEY: &[u8] = b"probes:canary";
+const VALUE: &[u8] = b"probes_canary_value";
+const TTL: u64 = 300;
+
+const TIMEOUT: Duration = Duration::from_millis(100);
+
+// Credentials sent with AUTH on connect, only set once from main
+static REDIS_AUTH: OnceLock<RedisAuth> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+struct RedisAuth {
+    // Empty for the legacy password only AUTH
+    username: String,
+    password: String,
+}
+
+#[derive(Error, Debug)]
+pub enum RedisClientError {
+    #[error("Empty or incomplete response.")]
+    EmptyOrIncompleteResponse,
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Connection reset by peer.")]
+    ConnectionReset,
+    #[error("Invalid reply: {0}")]
+    InvalidReply(String),
+    #[error("Error reply: {0}")]
+    ErrorReply(String),
+    #[error("Unexpected reply on {cmd_type}: {reply:?}")]
+    UnexpectedReply { cmd_type: String, reply: Reply },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+// Reply of the RESP protocol, arrays are not needed by the probe
+#[derive(Debug, PartialEq, Clone)]
+pub enum Reply {
+    Simple(String),
+    Error(String),
+    Integer(i64),
+    Bulk(Vec<u8>),
+    Nil,
+}
+
+impl Reply {
+    /// Return the status exported in the number of requests metric
+    ///
+    /// The error kind (first word of the error) is used for errors
+    fn status(&self) -> &str {
+        match self {
+            Reply::Error(message) => message.split(' ').next().unwrap_or("ERR"),
+            Reply::Nil => "Nil",
+            _ => "OK",
+        }
+    }
+}
+
+/// Set the credentials sent with AUTH on connect
+///
+/// # Arguments
+///
+/// * `username` - ACL username, empty to only send the password
+/// * `password` - password, empty to disable AUTH
+///
+pub fn set_auth(username: &str, password: &str) -> Result<(), String> {
+    if password.is_empty() {
+        return Ok(());
+    }
+    info!("Authenticate on redis nodes");
+    REDIS_AUTH
+        .set(RedisAuth {
+            username: username.to_string(),
+            password: password.to_string(),
+        })
+        .map_err(|_| "Redis auth is already initialized".to_string())
+}
+
+/// Encode a command as a RESP array of bulk strings
+///
+/// # Arguments
+///
+/// * `args` - command name and arguments
+///
+fn encode_command(args: &[&[u8]]) -> Vec<u8> {
+    let mut request = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        request.extend_from_slice(format!("${}\r\n", arg.len()).as_bytes());
+        request.extend_from_slice(arg);
+        request.extend_from_slice(b"\r\n");
+    }
+    request
+}
+
+/// Parse a reply from a buffer
+///
+/// # Arguments
+///
+/// * `buffer` - bytes received from the node
+///
+/// # Return
+///
+/// * The reply and the number of bytes it used, None if not enough bytes
+///
+fn parse_reply(buffer: &[u8]) -> Result<Option<(Reply, usize)>, RedisClientError> {
+    let Some(line_end) = buffer.windows(2).position(|window| window == b"\r\n") else {
+        return Ok(None);
+    };
+    if line_end == 0 {
+        return Err(RedisClientError::InvalidReply("empty line".to_string()));
+    }
+    let line = String::from_utf8_lossy(&buffer[1..line_end]).to_string();
+    let line_len = line_end + 2;
+
+    let reply = match buffer[0] {
+        b'+' => Reply::Simple(line),
+        b'-' => Reply::Error(line),
+        b':' => Reply::Integer(
+            line.parse()
+                .map_err(|_| RedisClientError::InvalidReply(line.clone()))?,
+        ),
+        b'$' => {
+            let len: i64 = line
+                .parse()
+                .map_err(|_| RedisClientError::InvalidReply(line.clone()))?;
+            if len < 0 {
+                return Ok(Some((Reply::Nil, line_len)));
+            }
+            let end = line_len + len as usize;
+            if buffer.len() < end + 2 {
+                return Ok(None);
+            }
+            return Ok(Some((Reply::Bulk(buffer[line_len..end].to_vec()), end + 2)));
+        }
+        other => {
+            return Err(RedisClientError::InvalidReply(format!(
+                "unsupported type {}",
+                other as char
+            )))
+        }
+    };
+    Ok(Some((reply, line_len)))
+}
+
+pub async fn connect(cluster_name: &str, addr: &str) -> Result<Client, RedisClientError> {
+    let socket = TcpStream::connect(addr).await?;
+    let mut client = Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        stream: BufWriter::new(socket),
+        buffer: BytesMut::with_capacity(4096),
+    };
+    if let Some(auth) = REDIS_AUTH.get() {
+        client.auth(auth).await?;
+    }
+    Ok(client)
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    stream: BufWriter<TcpStream>,
+    buffer: BytesMut,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue one set
+    /// * issue one get
+    /// * issue one del
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), RedisClientError> {
+        self.set().await?;
+        self.get().await?;
+        self.del().await
+    }
+
+    /// Auth call
+    async fn auth(&mut self, auth: &RedisAuth) -> Result<(), RedisClientError> {
+        let command = if auth.username.is_empty() {
+            encode_command(&[b"AUTH", auth.password.as_bytes()])
+        } else {
+            encode_command(&[b"AUTH", auth.username.as_bytes(), auth.password.as_bytes()])
+        };
+        match self.handler_with_timeout("auth", command).await? {
+            Reply::Simple(_) => Ok(()),
+            reply => Err(RedisClientError::UnexpectedReply {
+                cmd_type: "auth".to_string(),
+                reply,
+            }),
+        }
+    }
+
+    /// Set call
+    pub async fn set(&mut self) -> Result<(), RedisClientError> {
+        let ttl = TTL.to_string();
+        let command = encode_command(&[b"SET", KEY, VALUE, b"EX", ttl.as_bytes()]);
+        match self.handler_with_timeout("set", command).await? {
+            Reply::Simple(_) => Ok(()),
+            reply => Err(RedisClientError::UnexpectedReply {
+                cmd_type: "set".to_string(),
+                reply,
+            }),
+        }
+    }
+
+    /// Get call
+    pub async fn get(&mut self) -> Result<(), RedisClientError> {
+        match self
+            .handler_with_timeout("get", encode_command(&[b"GET", KEY]))
+            .await?
+        {
+            Reply::Bulk(value) if value == VALUE => Ok(()),
+            reply => Err(RedisClientError::UnexpectedReply {
+                cmd_type: "get".to_string(),
+                reply,
+            }),
+        }
+    }
+
+    /// Del call
+    pub async fn del(&mut self) -> Result<(), RedisClientError> {
+        match self
+            .handler_with_timeout("del", encode_command(&[b"DEL", KEY]))
+            .await?
+        {
+            Reply::Integer(_) => Ok(()),
+            reply => Err(RedisClientError::UnexpectedReply {
+                cmd_type: "del".to_string(),
+                reply,
+            }),
+        }
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        command: Vec<u8>,
+    ) -> Result<Reply, RedisClientError> {
+        match tokio::time::timeout(TIMEOUT, self.handle_request(cmd_type, command)).await {
+            Ok(reply_res) => reply_res,
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(RedisClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform redis request
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string represensatation of the command
+    /// * `command` - the RESP encoded command to perform
+    ///
+    #[instrument(skip(self, command))]
+    pub async fn handle_request(
+        &mut self,
+        cmd_type: &str,
+        command: Vec<u8>,
+    ) -> Result<Reply, RedisClientError> {
+        let start = Instant::now();
+        self.stream.write_all(&command).await?;
+        self.stream.flush().await?;
+
+        let reply = self.read_reply().await?;
+        let elapsed = start.elapsed();
+        self.request_metrics.count_request(reply.status(), cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+
+        match reply {
+            Reply::Error(message) => Err(RedisClientError::ErrorReply(message)),
+            reply => Ok(reply),
+        }
+    }
+
+    /// Get reply from tcp stream
+    async fn read_reply(&mut self) -> Result<Reply, RedisClientError> {
+        loop {
+            if let Some((reply, len)) = parse_reply(&self.buffer[..])? {
+                self.buffer.advance(len);
+                return Ok(reply);
+            }
+            if 0 == self.stream.read_buf(&mut self.buffer).await? {
+                return if self.buffer.is_empty() {
+                    Err(RedisClientError::EmptyOrIncompleteResponse)
+                } else {
+                    Err(RedisClientError::ConnectionReset)
+                };
+            }
+        }
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+    use crate::redis::{connect, encode_command, parse_reply, Reply};
+
+    #[test]
+    fn encode() {
+        assert_eq!(
+            b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n".to_vec(),
+            encode_command(&[b"GET", b"key"])
+        );
+    }
+
+    #[test]
+    fn parse() {
+        assert_eq!(
+            Some((Reply::Simple("OK".to_string()), 5)),
+            parse_reply(b"+OK\r\n").unwrap()
+        );
+        assert_eq!(
+            Some((
+                Reply::Error("NOAUTH Authentication required.".to_string()),
+                34
+            )),
+            parse_reply(b"-NOAUTH Authentication required.\r\n").unwrap()
+        );
+        assert_eq!(
+            Some((Reply::Integer(1), 4)),
+            parse_reply(b":1\r\n").unwrap()
+        );
+        assert_eq!(
+            Some((Reply::Bulk(b"value".to_vec()), 11)),
+            parse_reply(b"$5\r\nvalue\r\n").unwrap()
+        );
+        assert_eq!(Some((Reply::Nil, 5)), parse_reply(b"$-1\r\n").unwrap());
+        assert_eq!(None, parse_reply(b"$5\r\nval").unwrap());
+        assert_eq!(None, parse_reply(b"+O").unwrap());
+        assert!(parse_reply(b"?\r\n").is_err());
+        assert_eq!(
+            "NOAUTH",
+            Reply::Error("NOAUTH Authentication required.".to_string()).status()
+        );
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut buffer = [0; 1024];
+            for reply in [
+                "+OK\r\n".to_string(),
+                format!("$19\r\n{}\r\n", "probes_canary_value"),
+                ":1\r\n".to_string(),
+            ] {
+                socket.read(&mut buffer).await.unwrap();
+                socket.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+
+        let mut client = connect("redis_probe", &addr).await.unwrap();
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["redis_probe", &addr, "OK", "get"])
+                .unwrap()
+                .get()
+        );
+    }
+}
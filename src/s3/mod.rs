@@ -0,0 +1,465 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use hmac::{Hmac, Mac};
+use hyper::client::HttpConnector;
+use hyper::header::HOST;
+use hyper::{Body, Client as HttpClient, Method, Request, StatusCode};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::time::error::Elapsed;
+use tracing::{debug, info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// Bucket and credentials of the s3 endpoints, only set once from main
+static S3_CONFIG: OnceLock<S3Config> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+struct S3Config {
+    bucket: String,
+    access_key: String,
+    secret_key: String,
+    region: String,
+    // Bucket in the host instead of the path
+    virtual_host: bool,
+}
+
+impl Default for S3Config {
+    fn default() -> Self {
+        S3Config {
+            bucket: "probes".to_string(),
+            access_key: "".to_string(),
+            secret_key: "".to_string(),
+            region: "us-east-1".to_string(),
+            virtual_host: false,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum S3ClientError {
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Unexpected status code {status} on {cmd_type}.")]
+    Status { cmd_type: String, status: u16 },
+    #[error("Canary object content mismatch.")]
+    CanaryMismatch,
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Set the bucket and the credentials used on s3 endpoints
+///
+/// # Arguments
+///
+/// * `bucket` - bucket holding the canary objects
+/// * `access_key` - access key id signing the requests
+/// * `secret_key` - secret access key signing the requests
+/// * `region` - region of the signing scope
+/// * `virtual_host` - whether the bucket is addressed in the host instead of the path
+///
+pub fn set_config(
+    bucket: &str,
+    access_key: &str,
+    secret_key: &str,
+    region: &str,
+    virtual_host: bool,
+) -> Result<(), String> {
+    info!("Probe s3 endpoints on bucket {}", bucket);
+    S3_CONFIG
+        .set(S3Config {
+            bucket: bucket.to_string(),
+            access_key: access_key.to_string(),
+            secret_key: secret_key.to_string(),
+            region: region.to_string(),
+            virtual_host,
+        })
+        .map_err(|_| "S3 config is already initialized".to_string())
+}
+
+fn hmac_sha256(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("hmac accepts any key size");
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Format a unix time as the basic iso 8601 format used by sigv4
+///
+/// # Arguments
+///
+/// * `seconds` - seconds since unix epoch
+///
+pub(crate) fn amz_date(seconds: u64) -> String {
+    let (days, time) = (seconds / 86_400, seconds % 86_400);
+    // Civil date from days since epoch
+    let z = days as i64 + 719_468;
+    let era = z.div_euclid(146_097);
+    let day_of_era = z.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}{month:02}{day:02}T{:02}{:02}{:02}Z",
+        time / 3600,
+        time % 3600 / 60,
+        time % 60
+    )
+}
+
+// Signing key scope of sigv4 requests
+pub(crate) struct SigV4<'a> {
+    pub access_key: &'a str,
+    pub secret_key: &'a str,
+    pub region: &'a str,
+    pub service: &'a str,
+}
+
+impl SigV4<'_> {
+    /// Compute the sigv4 authorization header of a request
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - http method of the request
+    /// * `path` - path of the request, without query
+    /// * `headers` - signed headers of the request, lowercase and sorted by name
+    /// * `payload_hash` - hex sha256 of the body
+    /// * `amz_date` - date of the request
+    ///
+    pub(crate) fn authorization(
+        &self,
+        method: &Method,
+        path: &str,
+        headers: &[(&str, &str)],
+        payload_hash: &str,
+        amz_date: &str,
+    ) -> String {
+        let date = &amz_date[..8];
+        let canonical_headers: String = headers
+            .iter()
+            .map(|(name, value)| format!("{name}:{value}\n"))
+            .collect();
+        let signed_headers = headers
+            .iter()
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join(";");
+        let canonical_request =
+            format!("{method}\n{path}\n\n{canonical_headers}\n{signed_headers}\n{payload_hash}");
+        let scope = format!("{date}/{}/{}/aws4_request", self.region, self.service);
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        let date_key = hmac_sha256(format!("AWS4{}", self.secret_key).as_bytes(), date);
+        let region_key = hmac_sha256(&date_key, self.region);
+        let service_key = hmac_sha256(&region_key, self.service);
+        let signing_key = hmac_sha256(&service_key, "aws4_request");
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={signed_headers}, Signature={}",
+            self.access_key,
+            hex::encode(hmac_sha256(&signing_key, &string_to_sign))
+        )
+    }
+}
+
+/// Compute the sigv4 authorization header of an s3 request
+///
+/// # Arguments
+///
+/// * `config` - credentials and region signing the request
+/// * `method` - http method of the request
+/// * `host` - host header of the request
+/// * `path` - path of the request, without query
+/// * `payload_hash` - hex sha256 of the body
+/// * `amz_date` - date of the request
+///
+fn authorization(
+    config: &S3Config,
+    method: &Method,
+    host: &str,
+    path: &str,
+    payload_hash: &str,
+    amz_date: &str,
+) -> String {
+    let sigv4 = SigV4 {
+        access_key: &config.access_key,
+        secret_key: &config.secret_key,
+        region: &config.region,
+        service: "s3",
+    };
+    sigv4.authorization(
+        method,
+        path,
+        &[
+            ("host", host),
+            ("x-amz-content-sha256", payload_hash),
+            ("x-amz-date", amz_date),
+        ],
+        payload_hash,
+        amz_date,
+    )
+}
+
+/// Create a client probing an s3 endpoint
+///
+/// Connections are opened and kept alive by the http client on first request
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node
+/// * `addr` - ip:port of the node
+///
+pub fn connect(cluster_name: &str, addr: &str) -> Client {
+    Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        object_key: format!("probes-canary-{}", addr.replace([':', '.'], "-")),
+        config: S3_CONFIG.get().cloned().unwrap_or_default(),
+        http_client: HttpClient::new(),
+    }
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    // Key of the canary object of that node
+    object_key: String,
+    config: S3Config,
+    http_client: HttpClient<HttpConnector>,
+}
+
+impl Client {
+    /// Probe action
+    /// * put a canary object
+    /// * get the canary object and check its content
+    /// * delete the canary object
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), S3ClientError> {
+        let content = self.cluster_name.clone().into_bytes();
+        self.handler_with_timeout("put", Method::PUT, content.clone())
+            .await?;
+        let object = self
+            .handler_with_timeout("get", Method::GET, Vec::new())
+            .await?;
+        if object != content {
+            return Err(S3ClientError::CanaryMismatch);
+        }
+        self.handler_with_timeout("delete", Method::DELETE, Vec::new())
+            .await?;
+        Ok(())
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        method: Method,
+        body: Vec<u8>,
+    ) -> Result<Vec<u8>, S3ClientError> {
+        let start = Instant::now();
+        match tokio::time::timeout(TIMEOUT, self.handle_request(method, body)).await {
+            Ok(Ok((status, object))) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, start.elapsed());
+                self.request_metrics
+                    .count_request(status.as_str(), cmd_type);
+                if !status.is_success() {
+                    return Err(S3ClientError::Status {
+                        cmd_type: cmd_type.to_string(),
+                        status: status.as_u16(),
+                    });
+                }
+                Ok(object)
+            }
+            Ok(Err(error)) => Err(error),
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(S3ClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform signed s3 request on the canary object
+    ///
+    /// # Arguments
+    ///
+    /// * `method` - http method of the request
+    /// * `body` - content of the object, empty if none
+    ///
+    /// # Return
+    ///
+    /// * Status code and body of the response
+    ///
+    async fn handle_request(
+        &self,
+        method: Method,
+        body: Vec<u8>,
+    ) -> Result<(StatusCode, Vec<u8>), S3ClientError> {
+        let (host, path) = if self.config.virtual_host {
+            (
+                format!("{}.{}", self.config.bucket, self.addr),
+                format!("/{}", self.object_key),
+            )
+        } else {
+            (
+                self.addr.clone(),
+                format!("/{}/{}", self.config.bucket, self.object_key),
+            )
+        };
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        let amz_date = amz_date(seconds);
+        let payload_hash = hex::encode(Sha256::digest(&body));
+        let authorization = authorization(
+            &self.config,
+            &method,
+            &host,
+            &path,
+            &payload_hash,
+            &amz_date,
+        );
+
+        debug!("Query s3: {} {}{}", method, self.addr, path);
+        let request = Request::builder()
+            .method(method)
+            .uri(format!("http://{}{}", self.addr, path))
+            .header(HOST, host)
+            .header("x-amz-content-sha256", payload_hash)
+            .header("x-amz-date", amz_date)
+            .header("authorization", authorization)
+            .body(Body::from(body))?;
+        let response = self.http_client.request(request).await?;
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await?;
+        Ok((status, bytes.to_vec()))
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::Method;
+    use wiremock::matchers::{header_exists, method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+    use crate::s3::{amz_date, authorization, connect, S3ClientError, S3Config};
+
+    async fn init_s3(object: &str) -> MockServer {
+        let mock_server = MockServer::start().await;
+        let object_path = format!(
+            "/probes/probes-canary-{}",
+            mock_server.address().to_string().replace([':', '.'], "-")
+        );
+        Mock::given(method("PUT"))
+            .and(path(object_path.as_str()))
+            .and(header_exists("authorization"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(object_path.as_str()))
+            .respond_with(ResponseTemplate::new(200).set_body_string(object))
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("DELETE"))
+            .and(path(object_path.as_str()))
+            .respond_with(ResponseTemplate::new(204))
+            .mount(&mock_server)
+            .await;
+        mock_server
+    }
+
+    #[test]
+    fn date() {
+        assert_eq!("20240102T030405Z", amz_date(1_704_164_645));
+        assert_eq!("19700101T000000Z", amz_date(0));
+        assert_eq!("20000229T235959Z", amz_date(951_868_799));
+    }
+
+    #[test]
+    fn sign() {
+        let config = S3Config {
+            access_key: "accesskey".to_string(),
+            secret_key: "secretkey".to_string(),
+            ..S3Config::default()
+        };
+        assert_eq!(
+            "AWS4-HMAC-SHA256 Credential=accesskey/20240102/us-east-1/s3/aws4_request, \
+            SignedHeaders=host;x-amz-content-sha256;x-amz-date, \
+            Signature=c3f4bd3ad890e8a36107bc34c32ce58322905d04c74ec50ffa3a56a5968d7eb9",
+            authorization(
+                &config,
+                &Method::PUT,
+                "127.0.0.1:9000",
+                "/probes/probes-canary-127-0-0-1-9000",
+                "e100fbce008c04ec40637af0af91fb2f05aeedc23f856a2d3c0b1580625d755e",
+                "20240102T030405Z"
+            )
+        );
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let mock_server = init_s3("s3_probe").await;
+        let socket = mock_server.address().to_string();
+        let mut client = connect("s3_probe", &socket);
+
+        client.probe().await.unwrap();
+        for (status, cmd_type) in [("200", "put"), ("200", "get"), ("204", "delete")] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&["s3_probe", &socket, status, cmd_type])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_canary_mismatch() {
+        let mock_server = init_s3("stale").await;
+        let mut client = connect("s3_probe_mismatch", &mock_server.address().to_string());
+
+        assert!(matches!(
+            client.probe().await,
+            Err(S3ClientError::CanaryMismatch)
+        ));
+    }
+}
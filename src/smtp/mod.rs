@@ -0,0 +1,448 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncWrite, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tokio_rustls::rustls::ServerName;
+use tracing::{info, instrument};
+
+use crate::probes::prometheus::NODE_STARTTLS;
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+use crate::tcp::tls_connector;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// Domain sent on ehlo and starttls usage, only set once from main
+static SMTP_CONFIG: OnceLock<SmtpConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+struct SmtpConfig {
+    ehlo_domain: String,
+    // Upgrade the connection with starttls after the ehlo
+    starttls: bool,
+}
+
+impl Default for SmtpConfig {
+    fn default() -> Self {
+        SmtpConfig {
+            ehlo_domain: "localhost".to_string(),
+            starttls: false,
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum SmtpClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid socket {0}.")]
+    InvalidSocket(String),
+    #[error("Invalid reply: {0}")]
+    InvalidReply(String),
+    #[error("Unexpected reply {code} on {cmd_type}: {message}")]
+    UnexpectedReply {
+        cmd_type: String,
+        code: u16,
+        message: String,
+    },
+    #[error("STARTTLS is not advertised by the node.")]
+    StartTlsUnsupported,
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+// Reply of the server, lines are stripped of their code
+#[derive(Debug, PartialEq)]
+struct Reply {
+    code: u16,
+    lines: Vec<String>,
+}
+
+impl Reply {
+    /// Whether an ehlo reply advertises the extension
+    ///
+    /// # Arguments
+    ///
+    /// * `extension` - keyword of the extension, case insensitive
+    ///
+    fn has_extension(&self, extension: &str) -> bool {
+        // First line is the greeting of the server
+        self.lines.iter().skip(1).any(|line| {
+            line.split_whitespace()
+                .next()
+                .is_some_and(|keyword| keyword.eq_ignore_ascii_case(extension))
+        })
+    }
+}
+
+/// Set the domain sent on ehlo and whether starttls is used
+///
+/// # Arguments
+///
+/// * `ehlo_domain` - domain identifying the prober on ehlo
+/// * `starttls` - whether the connection is upgraded with starttls
+///
+pub fn set_config(ehlo_domain: &str, starttls: bool) -> Result<(), String> {
+    info!(
+        "Send ehlo {} on smtp nodes, starttls: {}",
+        ehlo_domain, starttls
+    );
+    SMTP_CONFIG
+        .set(SmtpConfig {
+            ehlo_domain: ehlo_domain.to_string(),
+            starttls,
+        })
+        .map_err(|_| "Smtp config is already initialized".to_string())
+}
+
+/// Parse one line of a reply
+///
+/// # Arguments
+///
+/// * `line` - line received from the server, with or without its line ending
+///
+/// # Return
+///
+/// * The code, whether more lines follow and the text of the line
+///
+fn parse_reply_line(line: &str) -> Result<(u16, bool, String), SmtpClientError> {
+    let line = line.trim_end_matches(['\r', '\n']);
+    let code = line
+        .get(..3)
+        .filter(|code| code.bytes().all(|byte| byte.is_ascii_digit()))
+        .and_then(|code| code.parse().ok())
+        .ok_or_else(|| SmtpClientError::InvalidReply(line.to_string()))?;
+    match line.as_bytes().get(3) {
+        None => Ok((code, false, "".to_string())),
+        Some(b' ') => Ok((code, false, line[4..].to_string())),
+        Some(b'-') => Ok((code, true, line[4..].to_string())),
+        Some(_) => Err(SmtpClientError::InvalidReply(line.to_string())),
+    }
+}
+
+/// Read a single or multiline reply
+async fn read_reply<S: AsyncBufRead + Unpin>(stream: &mut S) -> Result<Reply, SmtpClientError> {
+    let mut reply = Reply {
+        code: 0,
+        lines: Vec::new(),
+    };
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if stream.read_line(&mut line).await? == 0 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        let (code, more, text) = parse_reply_line(&line)?;
+        if !reply.lines.is_empty() && code != reply.code {
+            return Err(SmtpClientError::InvalidReply(line.trim_end().to_string()));
+        }
+        reply.code = code;
+        reply.lines.push(text);
+        if !more {
+            return Ok(reply);
+        }
+    }
+}
+
+/// Send a command and check the code of its reply
+///
+/// # Arguments
+///
+/// * `stream` - connection to the node
+/// * `cmd_type` - the string represensatation of the command
+/// * `command` - command line to send, None to only read a reply like the banner
+/// * `expected_code` - code of a successful reply
+///
+async fn request<S: AsyncBufRead + AsyncWrite + Unpin>(
+    stream: &mut S,
+    cmd_type: &str,
+    command: Option<&str>,
+    expected_code: u16,
+) -> Result<Reply, SmtpClientError> {
+    if let Some(command) = command {
+        stream
+            .write_all(format!("{command}\r\n").as_bytes())
+            .await?;
+        stream.flush().await?;
+    }
+    let reply = read_reply(stream).await?;
+    if reply.code != expected_code {
+        return Err(SmtpClientError::UnexpectedReply {
+            cmd_type: cmd_type.to_string(),
+            code: reply.code,
+            message: reply.lines.join(" "),
+        });
+    }
+    Ok(reply)
+}
+
+/// Return the status exported in the number of requests metric
+///
+/// The reply code is used for unexpected replies
+fn error_status(error: &SmtpClientError) -> String {
+    match error {
+        SmtpClientError::Io { source } => format!("{:?}", source.kind()),
+        SmtpClientError::UnexpectedReply { code, .. } => code.to_string(),
+        SmtpClientError::Timeout { .. } => "Timeout".to_string(),
+        _ => "Error".to_string(),
+    }
+}
+
+/// Create a client probing a mail relay
+///
+/// A new connection is opened on each probe as relays close idle connections
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node
+/// * `addr` - ip:port of the node
+///
+pub fn connect(cluster_name: &str, addr: &str) -> Result<Client, SmtpClientError> {
+    let socket_addr: SocketAddr = addr
+        .parse()
+        .map_err(|_| SmtpClientError::InvalidSocket(addr.to_string()))?;
+    let config = SMTP_CONFIG.get().cloned().unwrap_or_default();
+    Ok(Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        socket_addr,
+        ehlo_domain: config.ehlo_domain,
+        starttls: config.starttls,
+    })
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    socket_addr: SocketAddr,
+    ehlo_domain: String,
+    starttls: bool,
+}
+
+impl Client {
+    /// Probe action
+    /// * open one connection and wait for the banner
+    /// * send ehlo and export whether starttls is advertised
+    /// * upgrade the connection with starttls and send ehlo again if enabled
+    /// * quit
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), SmtpClientError> {
+        let socket_addr = self.socket_addr;
+        let stream = self
+            .handler_with_timeout("connect", async move {
+                Ok(TcpStream::connect(socket_addr).await?)
+            })
+            .await?;
+        let mut stream = BufReader::new(stream);
+        self.handler_with_timeout("banner", request(&mut stream, "banner", None, 220))
+            .await?;
+
+        let ehlo = format!("EHLO {}", self.ehlo_domain);
+        let reply = self
+            .handler_with_timeout("ehlo", request(&mut stream, "ehlo", Some(&ehlo), 250))
+            .await?;
+        let starttls_advertised = reply.has_extension("STARTTLS");
+        NODE_STARTTLS
+            .with_label_values(&[self.cluster_name.as_str(), self.addr.as_str()])
+            .set(starttls_advertised as i64);
+
+        if !self.starttls {
+            return self.quit(&mut stream).await;
+        }
+        if !starttls_advertised {
+            return Err(SmtpClientError::StartTlsUnsupported);
+        }
+        self.handler_with_timeout(
+            "starttls",
+            request(&mut stream, "starttls", Some("STARTTLS"), 220),
+        )
+        .await?;
+        let server_name = ServerName::IpAddress(self.socket_addr.ip());
+        let tls_stream = self
+            .handler_with_timeout("tls_handshake", async {
+                Ok(tls_connector()
+                    .connect(server_name, stream.into_inner())
+                    .await?)
+            })
+            .await?;
+        let mut tls_stream = BufReader::new(tls_stream);
+        self.handler_with_timeout(
+            "ehlo_tls",
+            request(&mut tls_stream, "ehlo_tls", Some(&ehlo), 250),
+        )
+        .await?;
+        self.quit(&mut tls_stream).await
+    }
+
+    /// Quit call, the node closes the connection after its reply
+    async fn quit<S: AsyncBufRead + AsyncWrite + Unpin>(
+        &mut self,
+        stream: &mut S,
+    ) -> Result<(), SmtpClientError> {
+        self.handler_with_timeout("quit", request(stream, "quit", Some("QUIT"), 221))
+            .await?;
+        Ok(())
+    }
+
+    async fn handler_with_timeout<T>(
+        &mut self,
+        cmd_type: &str,
+        step: impl std::future::Future<Output = Result<T, SmtpClientError>>,
+    ) -> Result<T, SmtpClientError> {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(TIMEOUT, step).await {
+            Ok(step_res) => step_res,
+            Err(_timeout_elapsed) => Err(SmtpClientError::from(_timeout_elapsed)),
+        };
+        let elapsed = start.elapsed().min(TIMEOUT);
+        let status = match &result {
+            Ok(_) => "OK".to_string(),
+            Err(error) => error_status(error),
+        };
+        self.request_metrics.count_request(&status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+        result
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+    use tokio::net::TcpListener;
+
+    use crate::probes::prometheus::{NODE_STARTTLS, NUMBER_OF_REQUESTS};
+    use crate::smtp::{connect, parse_reply_line, SmtpClientError};
+
+    /// Start a relay sending the banner then answering each command line in order
+    async fn init_relay(banner: &'static str, replies: Vec<&'static str>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut stream = BufReader::new(stream);
+            stream.write_all(banner.as_bytes()).await.unwrap();
+            for reply in replies {
+                let mut line = String::new();
+                stream.read_line(&mut line).await.unwrap();
+                stream.write_all(reply.as_bytes()).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    #[test]
+    fn parse_reply() {
+        assert_eq!(
+            (250, true, "PIPELINING".to_string()),
+            parse_reply_line("250-PIPELINING\r\n").unwrap()
+        );
+        assert_eq!(
+            (221, false, "bye".to_string()),
+            parse_reply_line("221 bye\r\n").unwrap()
+        );
+        assert_eq!(
+            (250, false, "".to_string()),
+            parse_reply_line("250").unwrap()
+        );
+        assert!(matches!(
+            parse_reply_line("hello"),
+            Err(SmtpClientError::InvalidReply(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let addr = init_relay(
+            "220 mail.test ESMTP\r\n",
+            vec![
+                "250-mail.test\r\n250-PIPELINING\r\n250 STARTTLS\r\n",
+                "221 bye\r\n",
+            ],
+        )
+        .await;
+
+        let mut client = connect("smtp_probe", &addr).unwrap();
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            NODE_STARTTLS
+                .get_metric_with_label_values(&["smtp_probe", &addr])
+                .unwrap()
+                .get()
+        );
+        for cmd_type in ["connect", "banner", "ehlo", "quit"] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&["smtp_probe", &addr, "OK", cmd_type])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_unavailable() {
+        let addr = init_relay("554 no service\r\n", vec![]).await;
+
+        let mut client = connect("smtp_probe_unavailable", &addr).unwrap();
+        assert!(matches!(
+            client.probe().await,
+            Err(SmtpClientError::UnexpectedReply { code: 554, .. })
+        ));
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["smtp_probe_unavailable", &addr, "554", "banner"])
+                .unwrap()
+                .get()
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_starttls_unsupported() {
+        let addr = init_relay(
+            "220 mail.test ESMTP\r\n",
+            vec!["250-mail.test\r\n250 PIPELINING\r\n"],
+        )
+        .await;
+
+        let mut client = connect("smtp_probe_plain", &addr).unwrap();
+        client.starttls = true;
+        assert!(matches!(
+            client.probe().await,
+            Err(SmtpClientError::StartTlsUnsupported)
+        ));
+        assert_eq!(
+            0,
+            NODE_STARTTLS
+                .get_metric_with_label_values(&["smtp_probe_plain", &addr])
+                .unwrap()
+                .get()
+        );
+    }
+}
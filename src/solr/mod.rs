@@ -0,0 +1,320 @@
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use hyper::client::HttpConnector;
+use hyper::{Body, Client as HttpClient, Method, Request, StatusCode};
+use serde_json::{Map, Value};
+use thiserror::Error;
+use tokio::time::error::Elapsed;
+use tracing::{debug, info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// Query run on each core, only set once from main
+static SOLR_CANARY_QUERY: OnceLock<String> = OnceLock::new();
+
+#[derive(Error, Debug)]
+pub enum SolrClientError {
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Invalid json response: {source}")]
+    Json {
+        #[from]
+        source: serde_json::Error,
+    },
+    #[error("Unexpected status code {status} on {cmd_type}.")]
+    Status { cmd_type: String, status: u16 },
+    #[error("Ping of core {core} returned {status}.")]
+    PingFailed { core: String, status: String },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Set the query run on each core of the solr nodes
+///
+/// # Arguments
+///
+/// * `query` - solr query parameter, empty to only ping the cores
+///
+pub fn set_canary_query(query: &str) -> Result<(), String> {
+    if query.is_empty() {
+        return Ok(());
+    }
+    info!("Query {} on solr cores", query);
+    SOLR_CANARY_QUERY
+        .set(query.to_string())
+        .map_err(|_| "Solr canary query is already initialized".to_string())
+}
+
+/// Create a client probing a solr node
+///
+/// Connections are opened and kept alive by the http client on first request
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node
+/// * `addr` - ip:port of the node
+///
+pub fn connect(cluster_name: &str, addr: &str) -> Client {
+    Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        canary_query: SOLR_CANARY_QUERY.get().cloned().unwrap_or_default(),
+        http_client: HttpClient::new(),
+    }
+}
+
+/// Return an error if the status code is not a success
+///
+/// # Arguments
+///
+/// * `cmd_type` - the string represensatation of the command
+/// * `status` - status code of the response
+///
+fn check_status(cmd_type: &str, status: StatusCode) -> Result<(), SolrClientError> {
+    if status.is_success() {
+        return Ok(());
+    }
+    Err(SolrClientError::Status {
+        cmd_type: cmd_type.to_string(),
+        status: status.as_u16(),
+    })
+}
+
+/// Percent encode a query parameter value
+fn encode_param(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (byte as char).to_string()
+            }
+            byte => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    // Empty to only ping the cores
+    canary_query: String,
+    http_client: HttpClient<HttpConnector>,
+}
+
+impl Client {
+    /// Probe action
+    /// * list the cores of the node
+    /// * ping each core
+    /// * run the canary query on each core if set
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), SolrClientError> {
+        for core in self.cores().await? {
+            self.ping(&core).await?;
+            if !self.canary_query.is_empty() {
+                self.query(&core).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Core status call
+    ///
+    /// # Return
+    ///
+    /// * Name of the cores served by the node
+    ///
+    pub async fn cores(&mut self) -> Result<Vec<String>, SolrClientError> {
+        let (status, response) = self
+            .handler_with_timeout("cores", "/solr/admin/cores?action=STATUS&wt=json")
+            .await?;
+        self.request_metrics.count_request(status.as_str(), "cores");
+        check_status("cores", status)?;
+        Ok(response
+            .get("status")
+            .and_then(Value::as_object)
+            .map(Map::keys)
+            .map_or_else(Vec::new, |cores| cores.cloned().collect()))
+    }
+
+    /// Ping call of a core, any solr status other than OK is a failure
+    pub async fn ping(&mut self, core: &str) -> Result<(), SolrClientError> {
+        let path = format!("/solr/{}/admin/ping?wt=json", encode_param(core));
+        let (status, response) = self.handler_with_timeout("ping", &path).await?;
+        let ping_status = response
+            .get("status")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown");
+        self.request_metrics.count_request(ping_status, "ping");
+        check_status("ping", status)?;
+        if ping_status != "OK" {
+            return Err(SolrClientError::PingFailed {
+                core: core.to_string(),
+                status: ping_status.to_string(),
+            });
+        }
+        Ok(())
+    }
+
+    /// Canary query call of a core, no document is returned
+    pub async fn query(&mut self, core: &str) -> Result<(), SolrClientError> {
+        let path = format!(
+            "/solr/{}/select?q={}&rows=0&wt=json",
+            encode_param(core),
+            encode_param(&self.canary_query)
+        );
+        let (status, _) = self.handler_with_timeout("query", &path).await?;
+        self.request_metrics.count_request(status.as_str(), "query");
+        check_status("query", status)
+    }
+
+    async fn handler_with_timeout(
+        &mut self,
+        cmd_type: &str,
+        path: &str,
+    ) -> Result<(StatusCode, Value), SolrClientError> {
+        let start = Instant::now();
+        match tokio::time::timeout(TIMEOUT, self.handle_request(path)).await {
+            Ok(Ok(response)) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, start.elapsed());
+                Ok(response)
+            }
+            Ok(Err(error)) => Err(error),
+            Err(_timeout_elapsed) => {
+                self.request_metrics
+                    .observe_response_time(cmd_type, TIMEOUT);
+                Err(SolrClientError::from(_timeout_elapsed))
+            }
+        }
+    }
+
+    /// Perform solr get request
+    ///
+    /// # Arguments
+    ///
+    /// * `path` - path and query of the request
+    ///
+    /// # Return
+    ///
+    /// * Status code and json body of the response, null if empty
+    ///
+    async fn handle_request(&self, path: &str) -> Result<(StatusCode, Value), SolrClientError> {
+        debug!("Query solr: {}{}", self.addr, path);
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", self.addr, path))
+            .body(Body::empty())?;
+        let response = self.http_client.request(request).await?;
+        let status = response.status();
+        let bytes = hyper::body::to_bytes(response.into_body()).await?;
+        if bytes.is_empty() {
+            return Ok((status, Value::Null));
+        }
+        Ok((status, serde_json::from_slice(&bytes)?))
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use wiremock::matchers::{method, path, query_param};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+    use crate::solr::{connect, encode_param, SolrClientError};
+
+    async fn init_solr(ping_status: &str) -> MockServer {
+        let mock_server = MockServer::start().await;
+
+        Mock::given(method("GET"))
+            .and(path("/solr/admin/cores"))
+            .and(query_param("action", "STATUS"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("{\"status\":{\"products\":{\"name\":\"products\"}}}"),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/solr/products/admin/ping"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string(format!("{{\"status\":\"{ping_status}\"}}")),
+            )
+            .mount(&mock_server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/solr/products/select"))
+            .and(query_param("q", "*:*"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_string("{\"response\":{\"numFound\":3,\"docs\":[]}}"),
+            )
+            .mount(&mock_server)
+            .await;
+
+        mock_server
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!("%2A%3A%2A", encode_param("*:*"));
+        assert_eq!("products_v2", encode_param("products_v2"));
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let mock_server = init_solr("OK").await;
+        let mut client = connect("solr_probe", &mock_server.address().to_string());
+        client.canary_query = "*:*".to_string();
+
+        client.probe().await.unwrap();
+        let socket = mock_server.address().to_string();
+        for (status, cmd_type) in [("200", "cores"), ("OK", "ping"), ("200", "query")] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&["solr_probe", &socket, status, cmd_type])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_ping_failed() {
+        let mock_server = init_solr("FAIL").await;
+        let mut client = connect("solr_probe_failed", &mock_server.address().to_string());
+
+        assert!(matches!(
+            client.probe().await,
+            Err(SolrClientError::PingFailed { .. })
+        ));
+    }
+}
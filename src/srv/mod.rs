@@ -0,0 +1,539 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::time::Duration;
+
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{lookup_host, TcpStream, UdpSocket};
+use tokio::time::error::Elapsed;
+use tracing::{debug, info, warn};
+
+use crate::dns::{encode_query, rcode_name, DnsClientError};
+use crate::probes::discovery::{
+    Discovery, DiscoveryFuture, PollInterval, ServiceNode, ServiceNodes,
+};
+use crate::probes::protocol::Protocol;
+
+const TYPE_SRV: u16 = 33;
+const HEADER_LEN: usize = 12;
+// Truncated flag, the query is sent again over tcp
+const FLAG_TRUNCATED: u16 = 0x0200;
+const RCODE_NXDOMAIN: u16 = 3;
+// Max number of compression pointers followed in a name
+const MAX_POINTERS: usize = 16;
+
+// Max size of a response over udp without EDNS
+const MAX_UDP_RESPONSE_SIZE: usize = 512;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// Resolvers used when none is set
+const RESOLV_CONF: &str = "/etc/resolv.conf";
+const DEFAULT_RESOLVER: &str = "127.0.0.1:53";
+
+#[derive(Error, Debug)]
+pub enum SrvError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid query: {source}")]
+    Query {
+        #[from]
+        source: DnsClientError,
+    },
+    #[error("Invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("Response code {rcode} on {name}.")]
+    ResponseCode { name: String, rcode: String },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+// Target of a SRV record
+#[derive(Debug, PartialEq)]
+struct SrvRecord {
+    priority: u16,
+    weight: u16,
+    port: u16,
+    // Empty if the service is decidedly not available
+    target: String,
+}
+
+// Header flags and SRV records of a response
+#[derive(Debug, PartialEq)]
+struct SrvResponse {
+    truncated: bool,
+    rcode: u16,
+    records: Vec<SrvRecord>,
+}
+
+// Resolve SRV records into nodes to probe
+#[derive(Debug)]
+pub struct SrvClient {
+    names: Vec<String>,
+    resolver: SocketAddr,
+    id: u16,
+    // Nodes of the last successful resolution of each name, kept on failures
+    last_nodes: HashMap<String, Vec<ServiceNode>>,
+}
+
+/// Read a possibly compressed name
+///
+/// # Arguments
+///
+/// * `message` - whole dns message, compression pointers are offsets in it
+/// * `pos` - offset of the name
+///
+/// # Return
+///
+/// * The name without trailing dot and the offset following it
+///
+pub(crate) fn read_name(message: &[u8], mut pos: usize) -> Result<(String, usize), SrvError> {
+    let mut labels: Vec<String> = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = *message
+            .get(pos)
+            .ok_or_else(|| SrvError::InvalidResponse("truncated name".to_string()))?
+            as usize;
+        if len == 0 {
+            return Ok((labels.join("."), end.unwrap_or(pos + 1)));
+        }
+        if len & 0xc0 == 0xc0 {
+            let low = *message
+                .get(pos + 1)
+                .ok_or_else(|| SrvError::InvalidResponse("truncated pointer".to_string()))?
+                as usize;
+            pointers += 1;
+            if pointers > MAX_POINTERS {
+                return Err(SrvError::InvalidResponse("pointer loop".to_string()));
+            }
+            end.get_or_insert(pos + 2);
+            pos = ((len & 0x3f) << 8) | low;
+            continue;
+        }
+        let label = message
+            .get(pos + 1..pos + 1 + len)
+            .ok_or_else(|| SrvError::InvalidResponse("truncated label".to_string()))?;
+        labels.push(String::from_utf8_lossy(label).to_string());
+        pos += 1 + len;
+    }
+}
+
+/// Read a big endian u16 at an offset
+pub(crate) fn read_u16(message: &[u8], pos: usize) -> Result<u16, SrvError> {
+    message
+        .get(pos..pos + 2)
+        .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+        .ok_or_else(|| SrvError::InvalidResponse("truncated message".to_string()))
+}
+
+/// Parse a response to a SRV query
+///
+/// # Arguments
+///
+/// * `id` - id of the query
+/// * `message` - response received from the resolver
+///
+fn parse_response(id: u16, message: &[u8]) -> Result<SrvResponse, SrvError> {
+    if message.len() < HEADER_LEN {
+        return Err(SrvError::InvalidResponse("truncated header".to_string()));
+    }
+    let response_id = read_u16(message, 0)?;
+    if response_id != id {
+        return Err(SrvError::InvalidResponse(format!(
+            "unexpected id {response_id}, expected {id}"
+        )));
+    }
+    let flags = read_u16(message, 2)?;
+    let question_count = read_u16(message, 4)?;
+    let answer_count = read_u16(message, 6)?;
+    let mut response = SrvResponse {
+        truncated: flags & FLAG_TRUNCATED != 0,
+        rcode: flags & 0x000f,
+        records: Vec::new(),
+    };
+    if response.truncated {
+        return Ok(response);
+    }
+
+    let mut pos = HEADER_LEN;
+    for _ in 0..question_count {
+        // Name then type and class
+        pos = read_name(message, pos)?.1 + 4;
+    }
+    for _ in 0..answer_count {
+        pos = read_name(message, pos)?.1;
+        let record_type = read_u16(message, pos)?;
+        // Class and ttl
+        let len = read_u16(message, pos + 8)? as usize;
+        let data = pos + 10;
+        if message.len() < data + len {
+            return Err(SrvError::InvalidResponse("truncated record".to_string()));
+        }
+        if record_type == TYPE_SRV {
+            response.records.push(SrvRecord {
+                priority: read_u16(message, data)?,
+                weight: read_u16(message, data + 2)?,
+                port: read_u16(message, data + 4)?,
+                target: read_name(message, data + 6)?.0,
+            });
+        }
+        pos = data + len;
+    }
+    Ok(response)
+}
+
+/// Return the protocol named by the service label of a SRV name
+///
+/// `_redis._tcp.example.com` is probed with the redis protocol
+pub(crate) fn service_protocol(name: &str) -> Option<Protocol> {
+    name.split('.')
+        .next()
+        .and_then(|service| service.strip_prefix('_'))
+        .and_then(|service| service.parse().ok())
+}
+
+/// Parse a resolver address, the dns port is used if missing
+fn parse_resolver(resolver: &str) -> Option<SocketAddr> {
+    resolver.parse().ok().or_else(|| {
+        resolver
+            .parse::<IpAddr>()
+            .ok()
+            .map(|ip| SocketAddr::new(ip, 53))
+    })
+}
+
+/// Return the first nameserver of the system resolver configuration
+fn system_resolver() -> Option<SocketAddr> {
+    std::fs::read_to_string(RESOLV_CONF)
+        .ok()?
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some("nameserver"), Some(nameserver)) => parse_resolver(nameserver),
+                _ => None,
+            }
+        })
+}
+
+impl SrvClient {
+    /// Returns a SRV client
+    ///
+    /// # Arguments
+    ///
+    /// * `names` - comma separated list of SRV names to resolve
+    /// * `resolver` - ip[:port] of the resolver, empty for the system one
+    ///
+    pub fn new(names: &str, resolver: &str) -> Result<Self, String> {
+        let names: Vec<String> = names
+            .split(',')
+            .map(|name| name.trim().trim_end_matches('.'))
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect();
+        if names.is_empty() {
+            return Err("No SRV name to resolve".to_string());
+        }
+        let resolver = if resolver.is_empty() {
+            system_resolver().or_else(|| parse_resolver(DEFAULT_RESOLVER))
+        } else {
+            parse_resolver(resolver)
+        }
+        .ok_or_else(|| format!("Invalid SRV resolver {resolver}"))?;
+        info!("Resolve SRV records {:?} with {}", names, resolver);
+        Ok(SrvClient {
+            names,
+            resolver,
+            id: 0,
+            last_nodes: HashMap::new(),
+        })
+    }
+
+    /// Query the SRV records of a name, over tcp if the udp response is truncated
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - SRV name to resolve
+    ///
+    async fn query(&mut self, name: &str) -> Result<Vec<SrvRecord>, SrvError> {
+        self.id = self.id.wrapping_add(1);
+        let query = encode_query(self.id, name, TYPE_SRV)?;
+
+        let local_addr = if self.resolver.is_ipv4() {
+            "0.0.0.0:0"
+        } else {
+            "[::]:0"
+        };
+        let socket = UdpSocket::bind(local_addr).await?;
+        socket.connect(self.resolver).await?;
+        socket.send(&query).await?;
+        let mut buffer = [0; MAX_UDP_RESPONSE_SIZE];
+        let mut response = loop {
+            let len = socket.recv(&mut buffer).await?;
+            // Skip late responses of previous queries
+            if len >= 2 && buffer[..2] != self.id.to_be_bytes() {
+                continue;
+            }
+            break parse_response(self.id, &buffer[..len])?;
+        };
+
+        if response.truncated {
+            debug!("Truncated SRV response for {}, retry over tcp", name);
+            let mut stream = TcpStream::connect(self.resolver).await?;
+            stream.write_u16(query.len() as u16).await?;
+            stream.write_all(&query).await?;
+            let mut message = vec![0; stream.read_u16().await? as usize];
+            stream.read_exact(&mut message).await?;
+            response = parse_response(self.id, &message)?;
+        }
+
+        match response.rcode {
+            0 => Ok(response.records),
+            // The service does not exist anymore
+            RCODE_NXDOMAIN => Ok(Vec::new()),
+            rcode => Err(SrvError::ResponseCode {
+                name: name.to_string(),
+                rcode: rcode_name(rcode),
+            }),
+        }
+    }
+
+    /// Resolve the nodes of a SRV name
+    ///
+    /// All targets are probed whatever their priority and weight,
+    /// only their ipv4 addresses are kept
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - SRV name to resolve, used as cluster name of the nodes
+    ///
+    async fn resolve(&mut self, name: &str) -> Result<Vec<ServiceNode>, SrvError> {
+        let protocol = service_protocol(name);
+        let mut service_nodes = Vec::new();
+        for record in self.query(name).await? {
+            if record.target.is_empty() {
+                continue;
+            }
+            debug!(
+                "SRV record {} target {}:{} priority {} weight {}",
+                name, record.target, record.port, record.priority, record.weight
+            );
+            for addr in lookup_host((record.target.as_str(), record.port)).await? {
+                if let IpAddr::V4(ip) = addr.ip() {
+                    service_nodes.push(ServiceNode {
+                        service_name: name.to_string(),
+                        ip: ip.to_string(),
+                        port: record.port,
+                        protocol,
+                    });
+                }
+            }
+        }
+        Ok(service_nodes)
+    }
+
+    /// Resolve all SRV names into nodes to probe
+    ///
+    /// Nodes of the last successful resolution are kept for names failing to resolve
+    ///
+    /// # Return
+    ///
+    /// * ServiceNodes - the resolved nodes, an error if all names failed to resolve
+    ///
+    pub async fn list_nodes(&mut self) -> Result<ServiceNodes, SrvError> {
+        let mut nodes = HashMap::new();
+        let mut failures = 0;
+        let mut last_error = None;
+        for name in self.names.clone() {
+            let resolve_res = match tokio::time::timeout(TIMEOUT, self.resolve(&name)).await {
+                Ok(resolve_res) => resolve_res,
+                Err(_timeout_elapsed) => Err(SrvError::from(_timeout_elapsed)),
+            };
+            match resolve_res {
+                Ok(service_nodes) => {
+                    self.last_nodes.insert(name.clone(), service_nodes);
+                }
+                Err(err) => {
+                    warn!("Failed to resolve SRV record {}: {}", name, err);
+                    failures += 1;
+                    last_error = Some(err);
+                }
+            }
+            for service_node in self.last_nodes.get(&name).into_iter().flatten() {
+                nodes.insert(service_node.to_string(), service_node.clone());
+            }
+        }
+        match last_error {
+            Some(err) if failures == self.names.len() => Err(err),
+            _ => Ok(ServiceNodes {
+                index: 0,
+                services: self.names.clone(),
+                nodes,
+            }),
+        }
+    }
+}
+
+// Discovery resolving the SRV records again on each interval
+#[derive(Debug)]
+pub struct SrvDiscovery {
+    srv_client: SrvClient,
+    poll_interval: PollInterval,
+}
+
+impl SrvDiscovery {
+    /// Returns a SrvDiscovery
+    ///
+    /// # Arguments
+    ///
+    /// * `srv_client` - a SRV client
+    /// * `refresh_interval` - interval between each resolution
+    ///
+    pub fn new(srv_client: SrvClient, refresh_interval: Duration) -> Self {
+        SrvDiscovery {
+            srv_client,
+            poll_interval: PollInterval::new(refresh_interval),
+        }
+    }
+}
+
+impl fmt::Display for SrvDiscovery {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "srv records")
+    }
+}
+
+impl Discovery for SrvDiscovery {
+    fn next_snapshot(&mut self) -> DiscoveryFuture<'_> {
+        Box::pin(async move {
+            self.poll_interval.tick().await;
+            self.srv_client.list_nodes().await.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::SocketAddr;
+
+    use tokio::net::UdpSocket;
+
+    use crate::dns::encode_query;
+    use crate::probes::protocol::Protocol;
+    use crate::srv::{
+        parse_resolver, parse_response, read_name, service_protocol, SrvClient, SrvRecord, TYPE_SRV,
+    };
+
+    /// Build a response to a SRV query with one record per target
+    fn response(query: &[u8], targets: &[(&str, u16)]) -> Vec<u8> {
+        let mut response = query.to_vec();
+        response[2] = 0x81;
+        response[3] = 0x80;
+        response[7] = targets.len() as u8;
+        for (target, port) in targets {
+            let mut data = vec![0, 10, 0, 5];
+            data.extend_from_slice(&port.to_be_bytes());
+            for label in target.split('.') {
+                data.push(label.len() as u8);
+                data.extend_from_slice(label.as_bytes());
+            }
+            data.push(0);
+            // Name pointing to the question, SRV type, IN class and ttl
+            response.extend_from_slice(&[0xc0, 12, 0, 33, 0, 1, 0, 0, 0, 60]);
+            response.extend_from_slice(&(data.len() as u16).to_be_bytes());
+            response.extend_from_slice(&data);
+        }
+        response
+    }
+
+    #[test]
+    fn name() {
+        let message = [3, b'f', b'o', b'o', 0, 3, b'b', b'a', b'r', 0xc0, 0];
+        assert_eq!(("foo".to_string(), 5), read_name(&message, 0).unwrap());
+        assert_eq!(("bar.foo".to_string(), 11), read_name(&message, 5).unwrap());
+        assert!(read_name(&[0xc0, 0], 0).is_err());
+        assert!(read_name(&[3, b'f'], 0).is_err());
+    }
+
+    #[test]
+    fn parse() {
+        let query = encode_query(7, "_redis._tcp.example.com", TYPE_SRV).unwrap();
+        let srv_response = parse_response(7, &response(&query, &[("10.0.0.1", 6379)])).unwrap();
+        assert_eq!(
+            vec![SrvRecord {
+                priority: 10,
+                weight: 5,
+                port: 6379,
+                target: "10.0.0.1".to_string(),
+            }],
+            srv_response.records
+        );
+        assert!(!srv_response.truncated);
+        assert!(parse_response(8, &response(&query, &[])).is_err());
+    }
+
+    #[test]
+    fn protocol() {
+        assert_eq!(
+            Some(Protocol::Redis),
+            service_protocol("_redis._tcp.example.com")
+        );
+        assert_eq!(None, service_protocol("_http._tcp.example.com"));
+        assert_eq!(None, service_protocol("example.com"));
+    }
+
+    #[test]
+    fn resolver() {
+        assert_eq!(
+            Some("10.0.0.2:53".parse::<SocketAddr>().unwrap()),
+            parse_resolver("10.0.0.2")
+        );
+        assert_eq!(
+            Some("10.0.0.2:5353".parse::<SocketAddr>().unwrap()),
+            parse_resolver("10.0.0.2:5353")
+        );
+        assert!(SrvClient::new("_redis._tcp.example.com", "resolver").is_err());
+        assert!(SrvClient::new(" , ", "10.0.0.2").is_err());
+    }
+
+    #[tokio::test]
+    async fn list_nodes() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let resolver = server.local_addr().unwrap().to_string();
+        tokio::spawn(async move {
+            let mut buffer = [0; 512];
+            let (len, peer) = server.recv_from(&mut buffer).await.unwrap();
+            let targets = [("127.0.0.1", 11211), ("127.0.0.2", 11211)];
+            server
+                .send_to(&response(&buffer[..len], &targets), peer)
+                .await
+                .unwrap();
+        });
+
+        let mut srv_client = SrvClient::new("_memcached._tcp.example.com.", &resolver).unwrap();
+        let service_nodes = srv_client.list_nodes().await.unwrap();
+        assert_eq!(
+            vec!["_memcached._tcp.example.com".to_string()],
+            service_nodes.services
+        );
+        assert_eq!(2, service_nodes.nodes.len());
+        let service_node = service_nodes
+            .nodes
+            .get("_memcached._tcp.example.com:127.0.0.1:11211")
+            .unwrap();
+        assert_eq!(Some(Protocol::Memcached), service_node.protocol);
+
+        // The resolver is gone, the discovery fails as no name resolves
+        assert!(srv_client.list_nodes().await.is_err());
+    }
+}
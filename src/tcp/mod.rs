@@ -0,0 +1,270 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{Duration, Instant, SystemTime};
+
+use rustls_pemfile::Item;
+use thiserror::Error;
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tokio_rustls::rustls::client::{ServerCertVerified, ServerCertVerifier};
+use tokio_rustls::rustls::{Certificate, ClientConfig, PrivateKey, ServerName};
+use tokio_rustls::TlsConnector;
+use tracing::instrument;
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+// Shared by all tls probes, built on first use
+static TLS_CONNECTOR: OnceLock<TlsConnector> = OnceLock::new();
+// Presenting a client certificate to the nodes asking for one, replaced on certificate rotation
+static CLIENT_CERT_TLS_CONNECTOR: RwLock<Option<TlsConnector>> = RwLock::new(None);
+
+#[derive(Error, Debug)]
+pub enum TcpClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid socket {0}.")]
+    InvalidSocket(String),
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+    #[error("Invalid client certificate: {0}.")]
+    InvalidCertificate(String),
+}
+
+// Only the handshake time is measured, certificates are not verified as nodes
+// are reached by ip and often use an internal CA
+struct NoCertificateVerification;
+
+impl ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &Certificate,
+        _intermediates: &[Certificate],
+        _server_name: &ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<ServerCertVerified, tokio_rustls::rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+}
+
+pub(crate) fn tls_connector() -> &'static TlsConnector {
+    TLS_CONNECTOR.get_or_init(|| {
+        let config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_custom_certificate_verifier(Arc::new(NoCertificateVerification))
+            .with_no_client_auth();
+        TlsConnector::from(Arc::new(config))
+    })
+}
+
+/// Present a client certificate on the tls probes of the nodes asking for one
+///
+/// # Arguments
+///
+/// * `cert_pem` - pem of the certificate chain
+/// * `key_pem` - pem of the private key of the certificate
+///
+pub fn set_client_certificate(cert_pem: &str, key_pem: &str) -> Result<(), TcpClientError> {
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_bytes())?
+        .into_iter()
+        .map(Certificate)
+        .collect::<Vec<_>>();
+    if certs.is_empty() {
+        return Err(TcpClientError::InvalidCertificate(
+            "no certificate".to_string(),
+        ));
+    }
+    let key = rustls_pemfile::read_all(&mut key_pem.as_bytes())?
+        .into_iter()
+        .find_map(|item| match item {
+            Item::PKCS8Key(key) | Item::RSAKey(key) | Item::ECKey(key) => Some(PrivateKey(key)),
+            _ => None,
+        })
+        .ok_or_else(|| TcpClientError::InvalidCertificate("no private key".to_string()))?;
+
+    let config = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_custom_certificate_verifier(Arc::new(NoCertificateVerification))
+        .with_client_auth_cert(certs, key)
+        .map_err(|err| TcpClientError::InvalidCertificate(err.to_string()))?;
+    *CLIENT_CERT_TLS_CONNECTOR.write().unwrap() = Some(TlsConnector::from(Arc::new(config)));
+    Ok(())
+}
+
+/// Return the status exported in the number of requests metric
+///
+/// The io error kind is used for failures
+fn error_status(error: &TcpClientError) -> String {
+    match error {
+        TcpClientError::Io { source } => format!("{:?}", source.kind()),
+        TcpClientError::InvalidSocket(_) => "InvalidSocket".to_string(),
+        TcpClientError::Timeout { .. } => "Timeout".to_string(),
+        TcpClientError::InvalidCertificate(_) => "InvalidCertificate".to_string(),
+    }
+}
+
+/// Create a client probing the reachability of a node
+///
+/// A new connection is opened on each probe
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node
+/// * `addr` - ip:port of the node
+/// * `tls` - whether a tls handshake is done after the connect
+///
+pub fn connect(cluster_name: &str, addr: &str, tls: bool) -> Result<Client, TcpClientError> {
+    let socket_addr: SocketAddr = addr
+        .parse()
+        .map_err(|_| TcpClientError::InvalidSocket(addr.to_string()))?;
+    Ok(Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        socket_addr,
+        tls,
+    })
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    socket_addr: SocketAddr,
+    tls: bool,
+}
+
+impl Client {
+    /// Probe action
+    /// * open one tcp connection
+    /// * do one tls handshake if enabled
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), TcpClientError> {
+        let stream = self
+            .handler_with_timeout("connect", TcpStream::connect(self.socket_addr))
+            .await?;
+        if self.tls {
+            let server_name = ServerName::IpAddress(self.socket_addr.ip());
+            let tls_connector = CLIENT_CERT_TLS_CONNECTOR
+                .read()
+                .unwrap()
+                .clone()
+                .unwrap_or_else(|| tls_connector().clone());
+            self.handler_with_timeout("tls_handshake", tls_connector.connect(server_name, stream))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn handler_with_timeout<T>(
+        &mut self,
+        cmd_type: &str,
+        step: impl std::future::Future<Output = io::Result<T>>,
+    ) -> Result<T, TcpClientError> {
+        let start = Instant::now();
+        let result = match tokio::time::timeout(TIMEOUT, step).await {
+            Ok(step_res) => step_res.map_err(TcpClientError::from),
+            Err(_timeout_elapsed) => Err(TcpClientError::from(_timeout_elapsed)),
+        };
+        let elapsed = start.elapsed().min(TIMEOUT);
+        let status = match &result {
+            Ok(_) => "OK".to_string(),
+            Err(error) => error_status(error),
+        };
+        self.request_metrics.count_request(&status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+        result
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::TcpListener;
+
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+    use crate::tcp::{connect, set_client_certificate, TcpClientError};
+
+    #[tokio::test]
+    async fn probe() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+
+        let mut client = connect("tcp_probe", &addr, false).unwrap();
+        client.probe().await.unwrap();
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&["tcp_probe", &addr, "OK", "connect"])
+                .unwrap()
+                .get()
+        );
+    }
+
+    #[tokio::test]
+    async fn probe_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().to_string();
+        drop(listener);
+
+        let mut client = connect("tcp_probe_refused", &addr, false).unwrap();
+        assert!(matches!(
+            client.probe().await,
+            Err(TcpClientError::Io { .. })
+        ));
+        assert_eq!(
+            1,
+            NUMBER_OF_REQUESTS
+                .get_metric_with_label_values(&[
+                    "tcp_probe_refused",
+                    &addr,
+                    "ConnectionRefused",
+                    "connect"
+                ])
+                .unwrap()
+                .get()
+        );
+    }
+
+    #[test]
+    fn invalid_socket() {
+        assert!(matches!(
+            connect("tcp_probe_invalid", "localhost", true),
+            Err(TcpClientError::InvalidSocket(_))
+        ));
+    }
+
+    #[test]
+    fn invalid_client_certificate() {
+        assert!(matches!(
+            set_client_certificate("", ""),
+            Err(TcpClientError::InvalidCertificate(_))
+        ));
+        let cert_pem = "-----BEGIN CERTIFICATE-----\nMIIB\n-----END CERTIFICATE-----\n";
+        assert!(matches!(
+            set_client_certificate(cert_pem, "not a key"),
+            Err(TcpClientError::InvalidCertificate(_))
+        ));
+    }
+}
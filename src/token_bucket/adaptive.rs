@@ -0,0 +1,130 @@
+use tracing::{info, warn};
+
+// Rate is divided by this factor on backpressure
+const DECREASE_FACTOR: f64 = 2.0;
+// Rate is multiplied by this factor on each success until the base rate is restored
+const INCREASE_FACTOR: f64 = 1.25;
+// Consecutive server errors considered as backpressure
+const SERVER_ERRORS_THRESHOLD: u32 = 3;
+
+// Represent a rate shrinking on backpressure and restored gradually on success
+#[derive(Debug)]
+pub struct AdaptiveRate {
+    // Rate used when there is no backpressure
+    base: f64,
+    // Lowest rate reachable on backpressure
+    min: f64,
+    // Current effective rate
+    current: f64,
+    // Number of consecutive server errors since last success
+    server_errors: u32,
+}
+
+impl AdaptiveRate {
+    /// Returns an adaptive rate starting at the base rate
+    ///
+    /// # Arguments
+    ///
+    /// * `base` - rate used when there is no backpressure
+    /// * `min` - lowest rate reachable on backpressure
+    ///
+    pub fn new(base: f64, min: f64) -> AdaptiveRate {
+        AdaptiveRate {
+            base,
+            min: min.min(base),
+            current: base,
+            server_errors: 0,
+        }
+    }
+
+    /// Return the current effective rate
+    pub fn current(&self) -> f64 {
+        self.current
+    }
+
+    /// Shrink the rate as the server asked to slow down
+    ///
+    /// # Return
+    ///
+    /// * The new rate if it changed
+    ///
+    pub fn on_backpressure(&mut self) -> Option<f64> {
+        let rate = (self.current / DECREASE_FACTOR).max(self.min);
+        self.update(rate)
+    }
+
+    /// Register a server error, the rate shrinks after consecutive errors
+    ///
+    /// # Return
+    ///
+    /// * The new rate if it changed
+    ///
+    pub fn on_server_error(&mut self) -> Option<f64> {
+        self.server_errors += 1;
+        if self.server_errors < SERVER_ERRORS_THRESHOLD {
+            return None;
+        }
+        self.server_errors = 0;
+        self.on_backpressure()
+    }
+
+    /// Restore the rate gradually
+    ///
+    /// # Return
+    ///
+    /// * The new rate if it changed
+    ///
+    pub fn on_success(&mut self) -> Option<f64> {
+        self.server_errors = 0;
+        let rate = (self.current * INCREASE_FACTOR).min(self.base);
+        self.update(rate)
+    }
+
+    fn update(&mut self, rate: f64) -> Option<f64> {
+        if rate == self.current {
+            return None;
+        }
+        if rate < self.current {
+            warn!("Backpressure detected, reduce rate to {}/s", rate);
+        } else {
+            info!("Restore rate to {}/s", rate);
+        }
+        self.current = rate;
+        Some(rate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::token_bucket::adaptive::AdaptiveRate;
+
+    #[test]
+    fn backpressure_and_recovery() {
+        let mut rate = AdaptiveRate::new(1.0, 0.3);
+        assert_eq!(None, rate.on_success());
+
+        assert_eq!(Some(0.5), rate.on_backpressure());
+        assert_eq!(Some(0.3), rate.on_backpressure());
+        assert_eq!(None, rate.on_backpressure());
+
+        assert_eq!(Some(0.375), rate.on_success());
+        rate.on_success();
+        rate.on_success();
+        rate.on_success();
+        assert_eq!(Some(1.0), rate.on_success());
+        assert_eq!(1.0, rate.current());
+    }
+
+    #[test]
+    fn consecutive_server_errors() {
+        let mut rate = AdaptiveRate::new(1.0, 0.1);
+        assert_eq!(None, rate.on_server_error());
+        assert_eq!(None, rate.on_server_error());
+        assert_eq!(Some(0.5), rate.on_server_error());
+
+        // A success resets consecutive errors
+        rate.on_server_error();
+        rate.on_success();
+        assert_eq!(None, rate.on_server_error());
+    }
+}
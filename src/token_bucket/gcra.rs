@@ -0,0 +1,232 @@
+use tokio::time::{Duration, Instant};
+use tracing::debug;
+
+use crate::clock::{Clock, TokioClock};
+use crate::token_bucket::{record_acquire, Acquire, RateLimiter, DEFAULT_NAME};
+
+// Represent a generic cell rate algorithm (GCRA) rate limiter
+// Requests are spaced by the emission interval of their token instead of
+// consuming a burst of token and starving until the next refill
+pub struct Gcra<C: Clock = TokioClock> {
+    // Name used as limiter label of the metrics
+    name: String,
+    // Source of time
+    clock: C,
+    // Number of token that can be requested ahead of the theoretical arrival time
+    burst: f64,
+    // Number of token retrieved every sec
+    quantum: f64,
+    // Theoretical arrival time of the next token
+    tat: Instant,
+}
+
+impl<C: Clock> Gcra<C> {
+    /// Returns a GCRA rate limiter relying on the given clock
+    ///
+    /// # Arguments
+    ///
+    /// * `burst` - Number of token that can be requested at once
+    /// * `quantum` - Number of token retrieved every sec, can be lower than 1
+    /// * `clock` - source of time used to compute and wait arrival times
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use probes::clock::ManualClock;
+    /// use probes::token_bucket::gcra::Gcra;
+    /// let mut gcra = Gcra::with_clock(1.0, 0.5, ManualClock::new());
+    /// ```
+    pub fn with_clock(burst: f64, quantum: f64, clock: C) -> Gcra<C> {
+        debug!("Create GCRA with burst {}, quantum {}", burst, quantum);
+        let tat = clock.now();
+        Gcra {
+            name: DEFAULT_NAME.to_string(),
+            clock,
+            burst,
+            quantum,
+            tat,
+        }
+    }
+
+    /// Set the name used as limiter label of the metrics
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name of the rate limiter
+    ///
+    pub fn with_name(mut self, name: &str) -> Gcra<C> {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Return the duration needed to retrieve a number of token
+    fn emission_interval(&self, token: f64) -> Duration {
+        Duration::from_secs_f64(token / self.quantum)
+    }
+
+    /// Compute the duration that need to be wait before authorizing action
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - number of token to consume
+    ///
+    /// # Return
+    ///
+    /// * Duration to wait
+    ///
+    fn compute_wait_duration(&self, token: f64) -> Duration {
+        let now = self.clock.now();
+        let new_tat = self.tat.max(now) + self.emission_interval(token);
+        let allow_at = new_tat
+            .checked_sub(self.emission_interval(self.burst))
+            .unwrap_or(now);
+        allow_at.saturating_duration_since(now)
+    }
+
+    /// Wait for the number of requested token
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Number of token requested
+    ///
+    pub async fn wait_for(
+        &mut self,
+        token: u64,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        if self.burst < token as f64 {
+            return Err(format!(
+                "Number of requested token ({}) is greater than the burst ({}) of the GCRA",
+                token, self.burst
+            )
+            .into());
+        }
+        if token == 0 {
+            return Ok(());
+        }
+        if self.quantum <= 0.0 {
+            return Err(format!(
+                "GCRA with quantum {} will never get {} token",
+                self.quantum, token
+            )
+            .into());
+        }
+
+        let wait_duration = self.compute_wait_duration(token as f64);
+        record_acquire(&self.name, token, wait_duration);
+        if !wait_duration.is_zero() {
+            debug!(
+                "Wait for {}s to get enough token",
+                wait_duration.as_secs_f64()
+            );
+            self.clock.sleep(wait_duration).await;
+        }
+
+        // Only move the theoretical arrival time once the action is authorized
+        self.tat = self.tat.max(self.clock.now()) + self.emission_interval(token as f64);
+        Ok(())
+    }
+}
+
+impl<C: Clock + 'static> RateLimiter for Gcra<C> {
+    fn acquire(&mut self, token: u64) -> Acquire<'_> {
+        Box::pin(self.wait_for(token))
+    }
+
+    fn quantum(&self) -> f64 {
+        self.quantum
+    }
+
+    fn set_quantum(&mut self, quantum: f64) {
+        debug!("Update GCRA quantum {} -> {}", self.quantum, quantum);
+        self.quantum = quantum;
+    }
+
+    fn try_acquire(&mut self, token: u64) -> bool {
+        if self.burst < token as f64 || self.quantum <= 0.0 {
+            return false;
+        }
+        if token == 0 {
+            return true;
+        }
+        if !self.compute_wait_duration(token as f64).is_zero() {
+            return false;
+        }
+        record_acquire(&self.name, token, Duration::ZERO);
+        self.tat = self.tat.max(self.clock.now()) + self.emission_interval(token as f64);
+        true
+    }
+
+    fn drain(&mut self) {
+        if self.quantum <= 0.0 {
+            return;
+        }
+        self.tat = self.tat.max(self.clock.now()) + self.emission_interval(self.burst);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::clock::{Clock, ManualClock};
+    use crate::token_bucket::gcra::Gcra;
+    use crate::token_bucket::RateLimiter;
+
+    #[tokio::test]
+    async fn wait_for_evenly_spaced() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut gcra = Gcra::with_clock(60.0, 1.0, clock.clone());
+
+        // First request is authorized immediately
+        gcra.wait_for(60).await.unwrap();
+        assert_eq!(Duration::ZERO, clock.now() - start);
+
+        // Next requests are spaced without burst
+        gcra.wait_for(60).await.unwrap();
+        assert_eq!(Duration::from_secs(60), clock.now() - start);
+        gcra.wait_for(60).await.unwrap();
+        assert_eq!(Duration::from_secs(120), clock.now() - start);
+
+        // Idle time is not accumulated as burst
+        clock.advance(Duration::from_secs(600));
+        gcra.wait_for(60).await.unwrap();
+        gcra.wait_for(60).await.unwrap();
+        assert_eq!(Duration::from_secs(780), clock.now() - start);
+    }
+
+    #[tokio::test]
+    async fn set_quantum_and_drain() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut gcra = Gcra::with_clock(1.0, 1.0, clock.clone());
+
+        gcra.drain();
+        gcra.set_quantum(0.5);
+        assert_eq!(0.5, RateLimiter::quantum(&gcra));
+        // Drained token is retrieved after 1s at the previous quantum
+        gcra.acquire(1).await.unwrap();
+        assert_eq!(Duration::from_secs(1), clock.now() - start);
+
+        // Next token is retrieved after 2s at the new quantum
+        gcra.acquire(1).await.unwrap();
+        assert_eq!(Duration::from_secs(3), clock.now() - start);
+    }
+
+    #[tokio::test]
+    async fn wait_for_bigger_than_burst() {
+        let mut gcra = Gcra::with_clock(1.0, 1.0, ManualClock::new());
+        assert!(gcra.wait_for(2).await.is_err());
+    }
+
+    #[test]
+    fn try_acquire() {
+        let clock = ManualClock::new();
+        let mut gcra = Gcra::with_clock(1.0, 1.0, clock.clone());
+
+        assert!(gcra.try_acquire(1));
+        assert!(!gcra.try_acquire(1));
+        clock.advance(Duration::from_secs(1));
+        assert!(gcra.try_acquire(1));
+    }
+}
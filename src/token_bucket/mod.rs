@@ -1,20 +1,195 @@
+pub mod adaptive;
+pub mod gcra;
+
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+
+use lazy_static::lazy_static;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, HistogramOpts, HistogramVec, IntCounterVec,
+    Opts,
+};
+use tokio::time::Duration;
 use tokio::time::Instant;
-use tokio::time::{sleep, Duration};
 use tracing::{debug, error};
 
+use crate::clock::{Clock, TokioClock};
+use crate::token_bucket::gcra::Gcra;
+
+// Name of a token bucket when none is given
+const DEFAULT_NAME: &str = "default";
+
+// Upper bounds in seconds of the token bucket wait durations
+const WAIT_DURATION_BUCKETS: [f64; 10] =
+    [0.1, 0.5, 1.0, 5.0, 10.0, 30.0, 60.0, 120.0, 300.0, 600.0];
+
+lazy_static! {
+    pub static ref TOKEN_BUCKET_REQUESTED_TOKENS: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "token_bucket_requested_tokens",
+            "Number of token requested to the token bucket"
+        ),
+        &["limiter"]
+    )
+    .expect("metric can be created");
+    pub static ref TOKEN_BUCKET_WAITS: IntCounterVec = register_int_counter_vec!(
+        Opts::new(
+            "token_bucket_waits",
+            "Number of requests which had to wait for token"
+        ),
+        &["limiter"]
+    )
+    .expect("metric can be created");
+    pub static ref TOKEN_BUCKET_WAIT_DURATION: HistogramVec = register_histogram_vec!(
+        HistogramOpts::new(
+            "token_bucket_wait_duration_seconds",
+            "Time spent waiting for token"
+        )
+        .buckets(WAIT_DURATION_BUCKETS.to_vec()),
+        &["limiter"]
+    )
+    .expect("metric can be created");
+}
+
+// Future returned by a rate limiter acquire
+pub type Acquire<'a> =
+    Pin<Box<dyn Future<Output = Result<(), Box<dyn std::error::Error + Send + Sync>>> + Send + 'a>>;
+
+// Rate limiter shared by the token bucket and the GCRA implementations
+pub trait RateLimiter: Send {
+    /// Wait until the number of requested token can be consumed
+    ///
+    /// Dropping the returned future before completion does not consume token
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Number of token requested
+    ///
+    fn acquire(&mut self, token: u64) -> Acquire<'_>;
+
+    /// Return the number of token retrieved every sec
+    fn quantum(&self) -> f64;
+
+    /// Change the number of token retrieved every sec
+    fn set_quantum(&mut self, quantum: f64);
+
+    /// Drop all available token so the next call waits for a refill
+    fn drain(&mut self);
+
+    /// Consume the number of requested token only if immediately available
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Number of token requested
+    ///
+    /// # Return
+    ///
+    /// * True if token have been consumed, false if the action should be dropped
+    ///
+    fn try_acquire(&mut self, token: u64) -> bool;
+}
+
+// Rate limiter implementation selectable from the configuration
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum RateLimiterKind {
+    // Allow bursts up to the capacity then one request per refill
+    #[default]
+    TokenBucket,
+    // Space requests evenly without bursts
+    Gcra,
+}
+
+impl FromStr for RateLimiterKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "token-bucket" => Ok(RateLimiterKind::TokenBucket),
+            "gcra" => Ok(RateLimiterKind::Gcra),
+            _ => Err(format!(
+                "Invalid rate limiter {s}, expected one of token-bucket, gcra"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for RateLimiterKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RateLimiterKind::TokenBucket => write!(f, "token-bucket"),
+            RateLimiterKind::Gcra => write!(f, "gcra"),
+        }
+    }
+}
+
+impl RateLimiterKind {
+    /// Build a rate limiter of this kind
+    ///
+    /// # Arguments
+    ///
+    /// * `name` - name used as limiter label of the metrics
+    /// * `capacity` - max number of token available at once for the token bucket
+    /// * `quantum` - number of token retrieved every sec
+    /// * `cost` - number of token requested per action, the only burst allowed by GCRA
+    ///
+    pub fn build(
+        &self,
+        name: &str,
+        capacity: f64,
+        quantum: f64,
+        cost: u64,
+    ) -> Box<dyn RateLimiter> {
+        match self {
+            RateLimiterKind::TokenBucket => {
+                Box::new(TokenBucket::with_rate(capacity, quantum).with_name(name))
+            }
+            RateLimiterKind::Gcra => {
+                Box::new(Gcra::with_clock(cost as f64, quantum, TokioClock).with_name(name))
+            }
+        }
+    }
+}
+
+/// Record a request to a rate limiter in the metrics
+///
+/// # Arguments
+///
+/// * `name` - name of the rate limiter
+/// * `token` - number of token requested
+/// * `wait_duration` - time to wait for token, zero if available immediately
+///
+fn record_acquire(name: &str, token: u64, wait_duration: Duration) {
+    TOKEN_BUCKET_REQUESTED_TOKENS
+        .with_label_values(&[name])
+        .inc_by(token);
+    if !wait_duration.is_zero() {
+        TOKEN_BUCKET_WAITS.with_label_values(&[name]).inc();
+        TOKEN_BUCKET_WAIT_DURATION
+            .with_label_values(&[name])
+            .observe(wait_duration.as_secs_f64());
+    }
+}
+
 // Represent a token bucket rate limiter
-pub struct TokenBucket {
+// Tokens are accounted as f64 to support fractional rates and sub-second refills
+pub struct TokenBucket<C: Clock = TokioClock> {
+    // Name used as limiter label of the metrics
+    name: String,
+    // Source of time
+    clock: C,
     // Max capacity of the token bucket
-    capacity: u64,
+    capacity: f64,
     // Number of token retrieved every sec
-    quantum: u64,
+    quantum: f64,
     // Number of available token
-    available: u64,
+    available: f64,
     // Last time available token has been computed
     last: Instant,
 }
 
-impl TokenBucket {
+impl TokenBucket<TokioClock> {
     /// Returns a token bucket
     ///
     /// # Arguments
@@ -29,41 +204,166 @@ impl TokenBucket {
     /// let mut token_bucket = TokenBucket::new(60, 1);
     /// ```
     pub fn new(capacity: u64, quantum: u64) -> TokenBucket {
+        TokenBucket::with_rate(capacity as f64, quantum as f64)
+    }
+
+    /// Returns a token bucket refilled at a fractional rate
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Max capacity of the bucket token and number of available token at startup
+    /// * `quantum` - Number of token retrieved every sec, can be lower than 1
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use probes::token_bucket::TokenBucket;
+    /// // One token every 2 seconds
+    /// let mut token_bucket = TokenBucket::with_rate(1.0, 0.5);
+    /// ```
+    pub fn with_rate(capacity: f64, quantum: f64) -> TokenBucket {
+        TokenBucket::with_clock(capacity, quantum, TokioClock)
+    }
+}
+
+impl<C: Clock> TokenBucket<C> {
+    /// Returns a token bucket relying on the given clock
+    ///
+    /// # Arguments
+    ///
+    /// * `capacity` - Max capacity of the bucket token and number of available token at startup
+    /// * `quantum` - Number of token retrieved every sec, can be lower than 1
+    /// * `clock` - source of time used to refill and wait
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use probes::clock::ManualClock;
+    /// use probes::token_bucket::TokenBucket;
+    /// let mut token_bucket = TokenBucket::with_clock(60.0, 1.0, ManualClock::new());
+    /// ```
+    pub fn with_clock(capacity: f64, quantum: f64, clock: C) -> TokenBucket<C> {
         debug!(
             "Create token bucket with capacity {}, quantum {}",
             capacity, quantum
         );
+        let last = clock.now();
         TokenBucket {
+            name: DEFAULT_NAME.to_string(),
+            clock,
             capacity,
             quantum,
             available: capacity,
-            last: Instant::now(),
+            last,
         }
     }
 
-    /// Return the number of available token since n seconds
+    /// Set the name used as limiter label of the metrics
     ///
     /// # Arguments
     ///
-    /// * `elapsed` - number of seconds elapsed since last check
+    /// * `name` - name of the token bucket
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use probes::token_bucket::TokenBucket;
+    /// let mut token_bucket = TokenBucket::new(60, 1).with_name("discovery");
+    /// ```
+    pub fn with_name(mut self, name: &str) -> TokenBucket<C> {
+        self.name = name.to_string();
+        self
+    }
+
+    /// Return the number of token retrieved every sec
+    pub fn quantum(&self) -> f64 {
+        self.quantum
+    }
+
+    /// Change the number of token retrieved every sec
+    ///
+    /// Token retrieved until now are accounted with the previous quantum
+    ///
+    /// # Arguments
+    ///
+    /// * `quantum` - Number of token retrieved every sec, can be lower than 1
+    ///
+    pub fn set_quantum(&mut self, quantum: f64) {
+        debug!(
+            "Update token bucket quantum {} -> {}",
+            self.quantum, quantum
+        );
+        self.refill();
+        self.quantum = quantum;
+    }
+
+    /// Drop all available token so the next call waits for a refill
+    pub fn drain(&mut self) {
+        self.refill();
+        self.available = 0.0;
+    }
+
+    /// Consume the number of requested token only if immediately available
+    ///
+    /// Never wait, used to drop actions instead of delaying them
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - Number of token requested
+    ///
+    /// # Return
+    ///
+    /// * True if token have been consumed, false otherwise
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use probes::token_bucket::TokenBucket;
+    /// let mut token_bucket = TokenBucket::new(1, 1);
+    /// assert!(token_bucket.try_acquire(1));
+    /// assert!(!token_bucket.try_acquire(1));
+    /// ```
+    pub fn try_acquire(&mut self, token: u64) -> bool {
+        self.refill();
+        if self.available < token as f64 {
+            debug!("Not enough available token {} < {}", self.available, token);
+            return false;
+        }
+        record_acquire(&self.name, token, Duration::ZERO);
+        self.update_counter(token as f64);
+        true
+    }
+
+    /// Return the number of available token after a given duration
+    ///
+    /// # Arguments
+    ///
+    /// * `elapsed` - duration elapsed since last check
     ///
     /// # Return
     ///
     /// * Return the number of available token
     ///
-    fn available_token_since(&mut self, elapsed: u64) -> u64 {
-        self.capacity.min(self.available + elapsed * self.quantum)
+    fn available_token_since(&self, elapsed: Duration) -> f64 {
+        self.capacity
+            .min(self.available + elapsed.as_secs_f64() * self.quantum)
     }
 
-    /// Update available token and last time token has been consumed field
+    /// Add token retrieved since last refill, max by the capacity
+    fn refill(&mut self) {
+        let now = self.clock.now();
+        self.available = self.available_token_since(now.duration_since(self.last));
+        self.last = now;
+    }
+
+    /// Update available token
     ///
     /// # Arguments
     ///
     /// * `token` - number of token consumed
     ///
-    fn update_counter(&mut self, token: u64) {
-        self.available -= token;
-        self.last = Instant::now();
+    fn update_counter(&mut self, token: f64) {
+        self.available = (self.available - token).max(0.0);
     }
 
     /// Compute the duration that need to be wait before authorizing action
@@ -76,9 +376,9 @@ impl TokenBucket {
     ///
     /// * Duration to wait
     ///
-    fn compute_wait_duration(&mut self, token: u64) -> Duration {
-        let token_needed: u64 = token - self.available;
-        let time_to_wait: f64 = token_needed as f64 / self.quantum as f64;
+    fn compute_wait_duration(&self, token: f64) -> Duration {
+        let token_needed = (token - self.available).max(0.0);
+        let time_to_wait: f64 = token_needed / self.quantum;
         debug!("Wait for {}s to get enough token", time_to_wait);
         Duration::from_secs_f64(time_to_wait)
     }
@@ -87,6 +387,7 @@ impl TokenBucket {
         &mut self,
         token: u64,
     ) -> Result<bool, Box<dyn std::error::Error + Send + Sync>> {
+        let token = token as f64;
         if self.capacity < token {
             error!(
                 "Requested token is bigger than max capacity {} < {}",
@@ -100,12 +401,12 @@ impl TokenBucket {
             .into());
         }
 
-        if token == 0 {
+        if token == 0.0 {
             return Ok(false);
         }
 
         // Update number of available token from time elapsed since last time max by the capacity
-        self.available = self.available_token_since(self.last.elapsed().as_secs());
+        self.refill();
 
         if self.available >= token {
             debug!(
@@ -115,6 +416,14 @@ impl TokenBucket {
             self.update_counter(token);
             return Ok(false);
         }
+
+        if self.quantum <= 0.0 {
+            return Err(format!(
+                "Token bucket with quantum {} will never get {} token",
+                self.quantum, token
+            )
+            .into());
+        }
         Ok(true)
     }
 
@@ -131,53 +440,109 @@ impl TokenBucket {
     ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         match self.need_to_wait(token) {
             Ok(true) => {
-                sleep(self.compute_wait_duration(token)).await;
+                let wait_duration = self.compute_wait_duration(token as f64);
+                record_acquire(&self.name, token, wait_duration);
+                self.clock.sleep(wait_duration).await;
 
-                // Reset available and last time
-                self.available = 0;
-                self.last = Instant::now();
+                // Consume token retrieved while waiting
+                self.refill();
+                self.update_counter(token as f64);
             }
-            Ok(false) => {}
+            Ok(false) => record_acquire(&self.name, token, Duration::ZERO),
             Err(issue) => return Err(issue),
         }
         Ok(())
     }
 }
 
+impl<C: Clock + 'static> RateLimiter for TokenBucket<C> {
+    fn acquire(&mut self, token: u64) -> Acquire<'_> {
+        Box::pin(self.wait_for(token))
+    }
+
+    fn quantum(&self) -> f64 {
+        TokenBucket::quantum(self)
+    }
+
+    fn set_quantum(&mut self, quantum: f64) {
+        TokenBucket::set_quantum(self, quantum)
+    }
+
+    fn drain(&mut self) {
+        TokenBucket::drain(self)
+    }
+
+    fn try_acquire(&mut self, token: u64) -> bool {
+        TokenBucket::try_acquire(self, token)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use std::time::Duration;
 
-    use crate::token_bucket::TokenBucket;
+    use crate::clock::{Clock, ManualClock};
+    use crate::token_bucket::{TokenBucket, TOKEN_BUCKET_REQUESTED_TOKENS, TOKEN_BUCKET_WAITS};
 
     #[test]
     fn available_token_since() {
         let mut token_bucket = TokenBucket::new(10, 1);
         // Max capacity
-        assert_eq!(token_bucket.available_token_since(1), 10);
+        assert_eq!(
+            token_bucket.available_token_since(Duration::from_secs(1)),
+            10.0
+        );
 
         // Add 2 * quantum
-        token_bucket.available = 0;
-        assert_eq!(token_bucket.available_token_since(2), 2);
+        token_bucket.available = 0.0;
+        assert_eq!(
+            token_bucket.available_token_since(Duration::from_secs(2)),
+            2.0
+        );
+    }
+
+    #[test]
+    fn available_token_since_sub_second() {
+        let mut token_bucket = TokenBucket::with_rate(10.0, 4.0);
+        token_bucket.available = 0.0;
+        assert_eq!(
+            token_bucket.available_token_since(Duration::from_millis(250)),
+            1.0
+        );
+
+        let mut token_bucket = TokenBucket::with_rate(1.0, 0.5);
+        token_bucket.available = 0.0;
+        assert_eq!(
+            token_bucket.available_token_since(Duration::from_secs(1)),
+            0.5
+        );
     }
 
     #[test]
     fn update_counter() {
         let mut token_bucket = TokenBucket::new(10, 1);
         // Reduce availabel token by 5
-        assert_eq!(token_bucket.available, 10);
-        token_bucket.update_counter(5);
-        assert_eq!(token_bucket.available, 5);
+        assert_eq!(token_bucket.available, 10.0);
+        token_bucket.update_counter(5.0);
+        assert_eq!(token_bucket.available, 5.0);
     }
 
     #[test]
     fn compute_wait_duration() {
         let mut token_bucket = TokenBucket::new(10, 1);
-        token_bucket.available = 0;
+        token_bucket.available = 0.0;
         assert_eq!(
-            token_bucket.compute_wait_duration(5),
+            token_bucket.compute_wait_duration(5.0),
             Duration::from_secs_f64(5.00)
         );
+
+        // Fractional rate
+        let mut token_bucket = TokenBucket::with_rate(1.0, 0.5);
+        token_bucket.available = 0.0;
+        assert_eq!(
+            token_bucket.compute_wait_duration(1.0),
+            Duration::from_secs(2)
+        );
     }
 
     #[test]
@@ -188,7 +553,7 @@ mod tests {
         assert!(token_bucket.need_to_wait(10).unwrap());
         //assert!(token_bucket.need_wait(100).unwrap());
 
-        token_bucket.available = 0;
+        token_bucket.available = 0.0;
         assert!(!token_bucket.need_to_wait(0).unwrap());
     }
 
@@ -202,4 +567,75 @@ mod tests {
             token_bucket.need_to_wait(100).err().unwrap().to_string()
         );
     }
+
+    #[tokio::test]
+    async fn wait_for_with_manual_clock() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut token_bucket = TokenBucket::with_clock(1.0, 0.5, clock.clone());
+
+        // Initial token is available without waiting
+        token_bucket.wait_for(1).await.unwrap();
+        assert_eq!(Duration::ZERO, clock.now() - start);
+
+        // Next token is retrieved after 2s
+        token_bucket.wait_for(1).await.unwrap();
+        assert_eq!(Duration::from_secs(2), clock.now() - start);
+
+        // Token retrieved while time elapsed are consumed first
+        clock.advance(Duration::from_secs(1));
+        token_bucket.wait_for(1).await.unwrap();
+        assert_eq!(Duration::from_secs(4), clock.now() - start);
+    }
+
+    #[tokio::test]
+    async fn set_quantum_and_drain() {
+        let clock = ManualClock::new();
+        let start = clock.now();
+        let mut token_bucket = TokenBucket::with_clock(2.0, 1.0, clock.clone());
+
+        // Drained token bucket waits for a refill at the new quantum
+        token_bucket.drain();
+        token_bucket.set_quantum(0.25);
+        assert_eq!(0.25, token_bucket.quantum());
+        token_bucket.wait_for(1).await.unwrap();
+        assert_eq!(Duration::from_secs(4), clock.now() - start);
+    }
+
+    #[tokio::test]
+    async fn wait_for_metrics() {
+        let clock = ManualClock::new();
+        let mut token_bucket =
+            TokenBucket::with_clock(1.0, 1.0, clock.clone()).with_name("wait_for_metrics");
+
+        token_bucket.wait_for(1).await.unwrap();
+        token_bucket.wait_for(1).await.unwrap();
+        assert_eq!(
+            2,
+            TOKEN_BUCKET_REQUESTED_TOKENS
+                .with_label_values(&["wait_for_metrics"])
+                .get()
+        );
+        assert_eq!(
+            1,
+            TOKEN_BUCKET_WAITS
+                .with_label_values(&["wait_for_metrics"])
+                .get()
+        );
+    }
+
+    #[test]
+    fn try_acquire() {
+        let clock = ManualClock::new();
+        let mut token_bucket = TokenBucket::with_clock(2.0, 1.0, clock.clone());
+
+        assert!(token_bucket.try_acquire(2));
+        // Not enough token, nothing is consumed
+        assert!(!token_bucket.try_acquire(1));
+
+        clock.advance(Duration::from_millis(1500));
+        assert!(!token_bucket.try_acquire(2));
+        assert!(token_bucket.try_acquire(1));
+        assert_eq!(0.5, token_bucket.available);
+    }
 }
@@ -0,0 +1,459 @@
+use std::io;
+use std::net::SocketAddr;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+
+use hyper::client::HttpConnector;
+use hyper::header::HOST;
+use hyper::{Body, Client as HttpClient, HeaderMap, Method, Request};
+use sha2::{Digest, Sha256};
+use thiserror::Error;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::error::Elapsed;
+use tracing::{debug, info, instrument};
+
+use crate::probes::protocol::{ProbeConnection, ProbeFuture, RequestMetrics};
+
+// Cli status codes
+const CLI_OK: u16 = 200;
+const CLI_AUTH: u16 = 107;
+// Status code and body length line of a cli response
+const CLI_HEADER_LEN: usize = 13;
+const CLI_CHALLENGE_LEN: usize = 32;
+// Guard against allocating a corrupted response size
+const MAX_CLI_BODY_SIZE: usize = 64 * 1024;
+
+const TIMEOUT: Duration = Duration::from_secs(2);
+
+// Test request and cli access of the varnish nodes, only set once from main
+static VARNISH_CONFIG: OnceLock<VarnishConfig> = OnceLock::new();
+
+#[derive(Debug, Clone, PartialEq)]
+struct VarnishConfig {
+    path: String,
+    // Empty to use the socket of the node
+    host: String,
+    // 0 to skip the cli ping
+    admin_port: u16,
+    // Content of the secret file shared with varnishadm
+    secret: Vec<u8>,
+}
+
+impl Default for VarnishConfig {
+    fn default() -> Self {
+        VarnishConfig {
+            path: "/".to_string(),
+            host: "".to_string(),
+            admin_port: 0,
+            secret: Vec::new(),
+        }
+    }
+}
+
+#[derive(Error, Debug)]
+pub enum VarnishClientError {
+    #[error("I/O error: {source}")]
+    Io {
+        #[from]
+        source: io::Error,
+    },
+    #[error("Invalid request: {source}")]
+    Request {
+        #[from]
+        source: hyper::http::Error,
+    },
+    #[error("Http error: {source}")]
+    Http {
+        #[from]
+        source: hyper::Error,
+    },
+    #[error("Invalid socket {0}.")]
+    InvalidSocket(String),
+    #[error("Unexpected status code {0} on test request.")]
+    Status(u16),
+    #[error("Invalid cli response: {0}")]
+    InvalidResponse(String),
+    #[error("Cli status {status}: {message}")]
+    CliStatus { status: u16, message: String },
+    #[error("Timeout error: {source}.")]
+    Timeout {
+        #[from]
+        source: Elapsed,
+    },
+}
+
+/// Set the test request and the cli access used on varnish nodes
+///
+/// # Arguments
+///
+/// * `path` - path of the test request
+/// * `host` - host header of the test request, empty to use the socket of the node
+/// * `admin_port` - port of the cli, 0 to skip the ping
+/// * `secret_file` - file holding the cli secret, empty if no authentication is required
+///
+pub fn set_config(
+    path: &str,
+    host: &str,
+    admin_port: u16,
+    secret_file: &str,
+) -> Result<(), String> {
+    let secret = match secret_file {
+        "" => Vec::new(),
+        secret_file => std::fs::read(secret_file)
+            .map_err(|issue| format!("Failed to read secret file {secret_file}: {issue}"))?,
+    };
+    info!("Request {} on varnish nodes", path);
+    VARNISH_CONFIG
+        .set(VarnishConfig {
+            path: path.to_string(),
+            host: host.to_string(),
+            admin_port,
+            secret,
+        })
+        .map_err(|_| "Varnish config is already initialized".to_string())
+}
+
+/// Return whether the response was served from the cache
+///
+/// X-Varnish holds the id of the request and, on hits, the id of the request
+/// which populated the cache. X-Cache is used when set by the vcl.
+fn cache_status(headers: &HeaderMap) -> &'static str {
+    if let Some(x_cache) = headers.get("x-cache").and_then(|value| value.to_str().ok()) {
+        return if x_cache.to_ascii_uppercase().contains("HIT") {
+            "hit"
+        } else {
+            "miss"
+        };
+    }
+    match headers
+        .get("x-varnish")
+        .and_then(|value| value.to_str().ok())
+        .map(|ids| ids.split_whitespace().count())
+    {
+        Some(2) => "hit",
+        Some(_) => "miss",
+        None => "unknown",
+    }
+}
+
+/// Compute the response to a cli authentication challenge
+///
+/// # Arguments
+///
+/// * `challenge` - challenge sent by the node
+/// * `secret` - content of the secret file
+///
+fn auth_response(challenge: &[u8], secret: &[u8]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(challenge);
+    hasher.update(b"\n");
+    hasher.update(secret);
+    hasher.update(challenge);
+    hasher.update(b"\n");
+    hex::encode(hasher.finalize())
+}
+
+/// Read a cli response
+///
+/// # Return
+///
+/// * Status code and body of the response
+///
+async fn read_cli_response(stream: &mut TcpStream) -> Result<(u16, Vec<u8>), VarnishClientError> {
+    let mut header = [0; CLI_HEADER_LEN];
+    stream.read_exact(&mut header).await?;
+    let header = String::from_utf8_lossy(&header).to_string();
+    let mut fields = header.split_whitespace();
+    let status: u16 = fields
+        .next()
+        .and_then(|status| status.parse().ok())
+        .ok_or_else(|| VarnishClientError::InvalidResponse(header.clone()))?;
+    let len: usize = fields
+        .next()
+        .and_then(|len| len.parse().ok())
+        .ok_or_else(|| VarnishClientError::InvalidResponse(header.clone()))?;
+    if len > MAX_CLI_BODY_SIZE {
+        return Err(VarnishClientError::InvalidResponse(format!(
+            "body of {len} bytes"
+        )));
+    }
+    // Body is followed by a new line
+    let mut body = vec![0; len + 1];
+    stream.read_exact(&mut body).await?;
+    body.truncate(len);
+    Ok((status, body))
+}
+
+fn check_cli_status(status: u16, body: &[u8]) -> Result<(), VarnishClientError> {
+    if status != CLI_OK {
+        return Err(VarnishClientError::CliStatus {
+            status,
+            message: String::from_utf8_lossy(body).trim().to_string(),
+        });
+    }
+    Ok(())
+}
+
+/// Run a step of the probe within the timeout
+///
+/// # Arguments
+///
+/// * `step` - the step of the probe
+///
+/// # Return
+///
+/// * Result of the step and its response time
+///
+async fn timed(
+    step: impl std::future::Future<Output = Result<&'static str, VarnishClientError>>,
+) -> (Result<&'static str, VarnishClientError>, Duration) {
+    let start = Instant::now();
+    let result = match tokio::time::timeout(TIMEOUT, step).await {
+        Ok(result) => result,
+        Err(_timeout_elapsed) => Err(VarnishClientError::from(_timeout_elapsed)),
+    };
+    (result, start.elapsed().min(TIMEOUT))
+}
+
+/// Create a client probing a varnish node
+///
+/// Http connections are kept alive by the http client, a new cli connection
+/// is opened on each probe
+///
+/// # Arguments
+///
+/// * `cluster_name` - name of the service of the node
+/// * `addr` - ip:port of the http listener of the node
+///
+pub fn connect(cluster_name: &str, addr: &str) -> Result<Client, VarnishClientError> {
+    let config = VARNISH_CONFIG.get().cloned().unwrap_or_default();
+    let admin_addr = match config.admin_port {
+        0 => None,
+        admin_port => {
+            let mut socket_addr: SocketAddr = addr
+                .parse()
+                .map_err(|_| VarnishClientError::InvalidSocket(addr.to_string()))?;
+            socket_addr.set_port(admin_port);
+            Some(socket_addr)
+        }
+    };
+    Ok(Client {
+        cluster_name: cluster_name.to_owned(),
+        addr: addr.to_owned(),
+        request_metrics: RequestMetrics::new(cluster_name, addr),
+        admin_addr,
+        config,
+        http_client: HttpClient::new(),
+    })
+}
+
+pub struct Client {
+    cluster_name: String,
+    addr: String,
+    // Series of the requests, resolved once per command type and status
+    request_metrics: RequestMetrics,
+    admin_addr: Option<SocketAddr>,
+    config: VarnishConfig,
+    http_client: HttpClient<HttpConnector>,
+}
+
+impl Client {
+    /// Probe action
+    /// * issue the test request and export whether it was a cache hit
+    /// * ping the cli if an admin port is set
+    #[instrument(
+        name = "probe",
+        skip(self),
+        fields(cluster_name = %self.cluster_name, socket = %self.addr)
+    )]
+    pub async fn probe(&mut self) -> Result<(), VarnishClientError> {
+        let request = timed(self.request()).await;
+        self.record("request", request)?;
+        if let Some(admin_addr) = self.admin_addr {
+            let cli_ping = timed(self.cli_ping(admin_addr)).await;
+            self.record("cli_ping", cli_ping)?;
+        }
+        Ok(())
+    }
+
+    /// Export the status and the response time of a step of the probe
+    ///
+    /// # Arguments
+    ///
+    /// * `cmd_type` - the string representation of the step
+    /// * `step` - result of the step, the cache or cli status on success, and its response time
+    ///
+    fn record(
+        &mut self,
+        cmd_type: &str,
+        (result, elapsed): (Result<&'static str, VarnishClientError>, Duration),
+    ) -> Result<(), VarnishClientError> {
+        let status = match &result {
+            Ok(status) => status.to_string(),
+            Err(VarnishClientError::Status(status)) => status.to_string(),
+            Err(VarnishClientError::CliStatus { status, .. }) => status.to_string(),
+            Err(VarnishClientError::Timeout { .. }) => "Timeout".to_string(),
+            Err(_) => "Error".to_string(),
+        };
+        self.request_metrics.count_request(&status, cmd_type);
+        self.request_metrics
+            .observe_response_time(cmd_type, elapsed);
+        result.map(|_| ())
+    }
+
+    /// Issue the test request
+    ///
+    /// # Return
+    ///
+    /// * Cache status of the response
+    ///
+    async fn request(&self) -> Result<&'static str, VarnishClientError> {
+        debug!("Request varnish: {}{}", self.addr, self.config.path);
+        let host = match self.config.host.as_str() {
+            "" => self.addr.as_str(),
+            host => host,
+        };
+        let request = Request::builder()
+            .method(Method::GET)
+            .uri(format!("http://{}{}", self.addr, self.config.path))
+            .header(HOST, host)
+            .body(Body::empty())?;
+        let response = self.http_client.request(request).await?;
+        let status = response.status();
+        let cache_status = cache_status(response.headers());
+        hyper::body::to_bytes(response.into_body()).await?;
+        if !status.is_success() {
+            return Err(VarnishClientError::Status(status.as_u16()));
+        }
+        Ok(cache_status)
+    }
+
+    /// Authenticate on the cli if required and issue one ping
+    async fn cli_ping(&self, admin_addr: SocketAddr) -> Result<&'static str, VarnishClientError> {
+        let mut stream = TcpStream::connect(admin_addr).await?;
+        let (status, body) = read_cli_response(&mut stream).await?;
+        if status == CLI_AUTH {
+            if body.len() < CLI_CHALLENGE_LEN {
+                return Err(VarnishClientError::InvalidResponse(
+                    "truncated challenge".to_string(),
+                ));
+            }
+            let response = auth_response(&body[..CLI_CHALLENGE_LEN], &self.config.secret);
+            stream
+                .write_all(format!("auth {response}\n").as_bytes())
+                .await?;
+            let (status, body) = read_cli_response(&mut stream).await?;
+            check_cli_status(status, &body)?;
+        } else {
+            check_cli_status(status, &body)?;
+        }
+
+        stream.write_all(b"ping\n").await?;
+        let (status, body) = read_cli_response(&mut stream).await?;
+        check_cli_status(status, &body)?;
+        if !body.starts_with(b"PONG") {
+            return Err(VarnishClientError::InvalidResponse(
+                String::from_utf8_lossy(&body).to_string(),
+            ));
+        }
+        Ok("200")
+    }
+}
+
+impl ProbeConnection for Client {
+    fn probe(&mut self) -> ProbeFuture<'_> {
+        Box::pin(async move { Client::probe(self).await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use hyper::HeaderMap;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use wiremock::matchers::{method, path};
+    use wiremock::{Mock, MockServer, ResponseTemplate};
+
+    use crate::probes::prometheus::NUMBER_OF_REQUESTS;
+    use crate::varnish::{auth_response, cache_status, connect, VarnishClientError};
+
+    fn cli_response(status: u16, body: &str) -> Vec<u8> {
+        format!("{:3} {:<8}\n{}\n", status, body.len(), body).into_bytes()
+    }
+
+    #[test]
+    fn cache() {
+        let mut headers = HeaderMap::new();
+        assert_eq!("unknown", cache_status(&headers));
+        headers.insert("x-varnish", "32770".parse().unwrap());
+        assert_eq!("miss", cache_status(&headers));
+        headers.insert("x-varnish", "32770 3".parse().unwrap());
+        assert_eq!("hit", cache_status(&headers));
+        headers.insert("x-cache", "MISS".parse().unwrap());
+        assert_eq!("miss", cache_status(&headers));
+    }
+
+    #[test]
+    fn auth() {
+        assert_eq!(
+            "4612dbda0cbd8dcf32665ded74ada5fb344bbaea6f2e41ad9a99688eab0784f4",
+            auth_response(b"abcdefghijklmnopqrstuvwxyzabcdef", b"secret\n")
+        );
+    }
+
+    #[tokio::test]
+    async fn probe() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/"))
+            .respond_with(ResponseTemplate::new(200).insert_header("X-Varnish", "32770 3"))
+            .mount(&mock_server)
+            .await;
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let admin_port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            socket
+                .write_all(&cli_response(200, "Varnish Cache CLI 1.0"))
+                .await
+                .unwrap();
+            let mut buffer = [0; 64];
+            socket.read(&mut buffer).await.unwrap();
+            socket
+                .write_all(&cli_response(200, "PONG 1700000000 1.0"))
+                .await
+                .unwrap();
+        });
+
+        let socket = mock_server.address().to_string();
+        let mut client = connect("varnish_probe", &socket).unwrap();
+        client.admin_addr = Some(format!("127.0.0.1:{admin_port}").parse().unwrap());
+        client.probe().await.unwrap();
+        for (status, cmd_type) in [("hit", "request"), ("200", "cli_ping")] {
+            assert_eq!(
+                1,
+                NUMBER_OF_REQUESTS
+                    .get_metric_with_label_values(&["varnish_probe", &socket, status, cmd_type])
+                    .unwrap()
+                    .get()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn probe_status() {
+        let mock_server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&mock_server)
+            .await;
+
+        let socket = mock_server.address().to_string();
+        let mut client = connect("varnish_probe_status", &socket).unwrap();
+        assert!(matches!(
+            client.probe().await,
+            Err(VarnishClientError::Status(503))
+        ));
+    }
+}